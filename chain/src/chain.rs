@@ -1,25 +1,29 @@
-use ckb_chain_spec::consensus::Consensus;
 use ckb_core::block::Block;
 use ckb_core::cell::{
     resolve_transaction, BlockCellProvider, OverlayCellProvider, ResolvedTransaction,
 };
-use ckb_core::extras::BlockExt;
+use ckb_core::extras::{BlockExt, LockCell, ReorgRecord};
 use ckb_core::service::{Request, DEFAULT_CHANNEL_SIZE, SIGNAL_CHANNEL_SIZE};
-use ckb_core::transaction::{CellOutput, ProposalShortId};
-use ckb_core::{header::Header, BlockNumber};
-use ckb_notify::NotifyController;
-use ckb_shared::cell_set::CellSetDiff;
-use ckb_shared::chain_state::ChainState;
+use ckb_core::transaction::{CellOutput, OutPoint, ProposalShortId, Transaction};
+use ckb_core::transaction_meta::TransactionMeta;
+use ckb_core::{BlockNumber, Cycle};
+use ckb_notify::{ChainEvent, NotifyController};
+use ckb_shared::block_stage::BlockStage;
+use ckb_shared::cell_set::{CellSetChanges, CellSetDiff};
+use ckb_shared::chain_state::{transactions_fee, ChainState};
 use ckb_shared::error::SharedError;
 use ckb_shared::shared::Shared;
 use ckb_shared::store::{ChainStore, StoreBatch};
-use ckb_traits::{BlockMedianTimeContext, ChainProvider};
-use ckb_verification::{BlockVerifier, TransactionsVerifier, Verifier};
+use ckb_traits::{skip_number, ChainProvider};
+use ckb_verification::{
+    BlockVerifier, Error as VerificationError, HeaderResolver, HeaderResolverWrapper,
+    HeaderVerifier, TransactionError, TransactionsVerifier, Verifier,
+};
 use crossbeam_channel::{self, select, Receiver, Sender};
 use failure::Error as FailureError;
 use faketime::unix_time_as_millis;
 use fnv::{FnvHashMap, FnvHashSet};
-use log::{self, debug, error, log_enabled};
+use log::{self, debug, error, info, log_enabled, trace};
 use numext_fixed_hash::H256;
 use numext_fixed_uint::U256;
 use serde_derive::{Deserialize, Serialize};
@@ -29,6 +33,8 @@ use std::sync::Arc;
 use std::thread;
 use stop_handler::{SignalSender, StopHandler};
 
+const MAX_PRUNED_BLOCKS: BlockNumber = 1000;
+
 #[derive(Clone)]
 pub struct ChainController {
     process_block_sender: Sender<Request<Arc<Block>, Result<(), FailureError>>>,
@@ -75,6 +81,25 @@ impl ForkChanges {
     pub fn detached_proposal_id(&self) -> &FnvHashSet<ProposalShortId> {
         &self.detached_proposal_id
     }
+
+    // both lists are collected from the tips down
+    fn into_reorg_event(self) -> ChainEvent {
+        let ForkChanges {
+            mut attached_blocks,
+            detached_blocks,
+            ..
+        } = self;
+        attached_blocks.reverse();
+        let common_ancestor = attached_blocks
+            .first()
+            .map(|block| block.header().parent_hash().clone())
+            .unwrap_or_else(H256::zero);
+        ChainEvent::Reorg {
+            attached: attached_blocks,
+            detached: detached_blocks,
+            common_ancestor,
+        }
+    }
 }
 
 pub(crate) struct GlobalIndex {
@@ -98,39 +123,6 @@ impl GlobalIndex {
     }
 }
 
-// Verification context for fork
-struct ForkContext<'a, CS> {
-    pub fork_blocks: &'a [Block],
-    pub store: Arc<CS>,
-    pub consensus: &'a Consensus,
-}
-
-impl<'a, CS: ChainStore> ForkContext<'a, CS> {
-    fn get_header(&self, number: BlockNumber) -> Option<Header> {
-        match self
-            .fork_blocks
-            .iter()
-            .find(|b| b.header().number() == number)
-        {
-            Some(block) => Some(block.header().to_owned()),
-            None => self
-                .store
-                .get_block_hash(number)
-                .and_then(|hash| self.store.get_header(&hash)),
-        }
-    }
-}
-
-impl<'a, CS: ChainStore> BlockMedianTimeContext for ForkContext<'a, CS> {
-    fn median_block_count(&self) -> u64 {
-        self.consensus.median_time_block_count() as u64
-    }
-
-    fn timestamp(&self, number: BlockNumber) -> Option<u64> {
-        self.get_header(number).map(|header| header.timestamp())
-    }
-}
-
 pub struct ChainService<CS> {
     shared: Shared<CS>,
     notify: NotifyController,
@@ -191,16 +183,61 @@ impl<CS: ChainStore + 'static> ChainService<CS> {
         }
     }
 
+    /// Re-verify the stored tip and `depth` blocks below it against the loaded consensus
+    /// rules. A failure means the database is corrupted or the chain spec was changed, the
+    /// node must not serve or mine on top of such a chain.
+    pub fn verify_tip(&self, depth: BlockNumber) -> Result<(), FailureError> {
+        let mut hash = self.shared.chain_state().tip_hash();
+        let pow_engine = self.shared.consensus().pow_engine();
+        let block_verifier = BlockVerifier::new(self.shared.clone());
+        for _ in 0..=depth {
+            let block = self
+                .shared
+                .block(&hash)
+                .ok_or_else(|| SharedError::InvalidData(format!("missing block {:x}", hash)))?;
+            if block.header().number() == 0 {
+                break;
+            }
+            info!(
+                target: "chain",
+                "verify stored block {} {:x}",
+                block.header().number(),
+                hash
+            );
+            let resolver = HeaderResolverWrapper::new(block.header(), self.shared.clone());
+            let parent = resolver.parent().ok_or_else(|| {
+                SharedError::InvalidData(format!("missing parent of block {:x}", hash))
+            })?;
+            let median_time_context = self.shared.branch_median_time_context(parent);
+            HeaderVerifier::new(median_time_context, Arc::clone(&pow_engine))
+                .dao_activation_number(self.shared.consensus().dao_activation_number())
+                .verify(&resolver)?;
+            self.shared
+                .verification_pool()
+                .install(|| block_verifier.verify(&block))?;
+            hash = block.header().parent_hash().clone();
+        }
+        Ok(())
+    }
+
     // process_block will do block verify
     // but invoker should guarantee block header be verified
     pub(crate) fn process_block(&mut self, block: Arc<Block>) -> Result<(), FailureError> {
         debug!(target: "chain", "begin processing block: {}", block.header().hash());
         if self.verification {
+            let _span = self
+                .shared
+                .block_stage_metrics()
+                .span(BlockStage::BlockVerify, &block.header().hash());
             let block_verifier = BlockVerifier::new(self.shared.clone());
-            block_verifier.verify(&block).map_err(|e| {
-                debug!(target: "chain", "[process_block] verification error {:?}", e);
-                e
-            })?
+            let verification_pool = self.shared.verification_pool();
+            trace!(target: "chain", "verification pool {:?}", verification_pool.metrics());
+            verification_pool
+                .install(|| block_verifier.verify(&block))
+                .map_err(|e| {
+                    debug!(target: "chain", "[process_block] verification error {:?}", e);
+                    e
+                })?
         }
         self.insert_block(block)?;
         debug!(target: "chain", "finish processing block");
@@ -211,9 +248,13 @@ impl<CS: ChainStore + 'static> ChainService<CS> {
         let mut new_best_block = false;
         let mut total_difficulty = U256::zero();
 
-        let mut cell_set_diff = CellSetDiff::default();
+        let mut cell_set_changes = CellSetChanges::default();
         let mut fork = ForkChanges::default();
-        let mut chain_state = self.shared.chain_state().lock();
+        // the chain service is the only writer, it updates a copy of the snapshot and
+        // publishes it once the block is committed
+        let mut chain_state = (*self.shared.chain_state()).clone();
+        let block_stage_metrics = self.shared.block_stage_metrics();
+        let commit_span = block_stage_metrics.span(BlockStage::Commit, &block.header().hash());
         let tip_number = chain_state.tip_number();
         let tip_hash = chain_state.tip_hash();
         let parent_ext = self
@@ -244,6 +285,12 @@ impl<CS: ChainStore + 'static> ChainService<CS> {
 
         let mut batch = self.shared.store().new_batch()?;
         batch.insert_block(&block)?;
+        if let Some(skip) = self.shared.store().get_ancestor(
+            block.header().parent_hash(),
+            skip_number(block.header().number()),
+        ) {
+            batch.insert_skip_hash(&block.header().hash(), &skip.hash())?;
+        }
         if (cannon_total_difficulty > current_total_difficulty)
             || ((current_total_difficulty == cannon_total_difficulty)
                 && (block.header().hash() < tip_hash))
@@ -255,12 +302,45 @@ impl<CS: ChainStore + 'static> ChainService<CS> {
                 &cannon_total_difficulty - &current_total_difficulty
             );
 
-            self.find_fork(&mut fork, tip_number, &block, ext);
+            let switch_started_at = unix_time_as_millis();
+            self.find_fork(&mut fork, tip_number, &block, ext)?;
             self.update_index(&mut batch, &fork.detached_blocks, &fork.attached_blocks)?;
             // MUST update index before reconcile_main_chain
-            cell_set_diff = self.reconcile_main_chain(&mut batch, &mut fork, &mut chain_state)?;
+            let cell_set_diff = self.reconcile_main_chain(&mut batch, &mut fork, &chain_state)?;
+            self.update_tx_index_retention(
+                &mut batch,
+                &chain_state,
+                &fork,
+                &cell_set_diff,
+                tip_number,
+                block.header().number(),
+            )?;
+            self.prune_blocks(
+                &mut batch,
+                &chain_state,
+                &fork,
+                &cell_set_diff,
+                block.header().number(),
+            )?;
+            self.update_lock_index(&mut batch, &chain_state, &fork, &cell_set_diff)?;
+            cell_set_changes = chain_state.cell_set().changes(&cell_set_diff);
+            batch.update_cell_set(&cell_set_changes, &block.header().hash())?;
             self.update_proposal_ids(&mut chain_state, &fork);
             batch.insert_tip_header(&block.header())?;
+            if !fork.detached_blocks.is_empty() {
+                let now = unix_time_as_millis();
+                let record = ReorgRecord {
+                    timestamp: now,
+                    depth: fork.detached_blocks.len() as u64,
+                    attached: fork.attached_blocks.len() as u64,
+                    fork_number: block.header().number() - fork.attached_blocks.len() as u64,
+                    old_tip_hash: tip_hash,
+                    new_tip_hash: block.header().hash(),
+                    duration: now.saturating_sub(switch_started_at),
+                };
+                info!(target: "chain", "reorg {:?}", record);
+                batch.insert_reorg_record(self.shared.store().get_reorg_count(), &record)?;
+            }
             new_best_block = true;
 
             total_difficulty = cannon_total_difficulty;
@@ -268,29 +348,57 @@ impl<CS: ChainStore + 'static> ChainService<CS> {
             batch.insert_block_ext(&block.header().hash(), &ext)?;
         }
         batch.commit()?;
+        drop(commit_span);
 
         if new_best_block {
+            let _span = block_stage_metrics.span(BlockStage::TipUpdate, &block.header().hash());
             let tip_header = block.header().clone();
             // finalize proposal_id table change
             // then, update tx_pool
             let detached_proposal_id = chain_state.proposal_ids_finalize(tip_header.number());
             fork.detached_proposal_id = detached_proposal_id;
-            chain_state.update_tip(tip_header, total_difficulty, cell_set_diff);
+            chain_state.update_tip(tip_header, total_difficulty, cell_set_changes);
+            let chain_state = Arc::new(chain_state);
+            let mut tx_pool = self.shared.tx_pool().lock();
+            self.shared.set_chain_state(Arc::clone(&chain_state));
             chain_state.update_tx_pool_for_reorg(
+                &mut tx_pool,
                 fork.detached_blocks().iter(),
                 fork.attached_blocks().iter(),
                 fork.detached_proposal_id().iter(),
             );
+            // the subscribers may take the pool lock while handling the event
+            drop(tx_pool);
+            self.update_candidate_uncles(chain_state.tip_number(), &fork);
             if log_enabled!(target: "chain", log::Level::Debug) {
                 self.print_chain(&chain_state, 10);
             }
+            self.notify
+                .notify_chain_event(Arc::new(fork.into_reorg_event()));
         } else {
+            self.shared
+                .candidate_uncles()
+                .lock()
+                .insert(Arc::clone(&block));
             self.notify.notify_new_uncle(block);
         }
 
         Ok(())
     }
 
+    // the detached blocks are stale now, while the attached ones are on the main chain
+    fn update_candidate_uncles(&self, tip_number: BlockNumber, fork: &ForkChanges) {
+        let max_uncles_age = self.shared.consensus().max_uncles_age() as u64;
+        let mut candidate_uncles = self.shared.candidate_uncles().lock();
+        for block in fork.attached_blocks() {
+            candidate_uncles.remove(&block.header().hash());
+        }
+        for block in fork.detached_blocks() {
+            candidate_uncles.insert(Arc::new(block.clone()));
+        }
+        candidate_uncles.prune(tip_number, max_uncles_age);
+    }
+
     pub(crate) fn update_proposal_ids(&self, chain_state: &mut ChainState<CS>, fork: &ForkChanges) {
         for blk in fork.detached_blocks() {
             chain_state.remove_proposal_ids(&blk);
@@ -310,8 +418,260 @@ impl<CS: ChainStore + 'static> ChainService<CS> {
             batch.detach_block(block)?;
         }
 
+        let disable_tx_index = self.shared.store_config().disable_tx_index;
         for block in attached_blocks {
-            batch.attach_block(block)?;
+            if disable_tx_index {
+                batch.attach_header(block.header())?;
+            } else {
+                batch.attach_block(block)?;
+            }
+        }
+        Ok(())
+    }
+
+    // With a retention configured, drop the index entries of fully spent transactions below
+    // the cutoff, transactions with live outputs stay indexed so their cells can be resolved.
+    // Entries of pruned transactions revived by the detached blocks are restored.
+    pub(crate) fn update_tx_index_retention(
+        &self,
+        batch: &mut StoreBatch,
+        chain_state: &ChainState<CS>,
+        fork: &ForkChanges,
+        cell_set_diff: &CellSetDiff,
+        old_tip_number: BlockNumber,
+        new_tip_number: BlockNumber,
+    ) -> Result<(), FailureError> {
+        let store_config = self.shared.store_config();
+        let (old_cutoff, cutoff) = match (
+            store_config.tx_index_cutoff(old_tip_number),
+            store_config.tx_index_cutoff(new_tip_number),
+        ) {
+            (Some(old_cutoff), Some(cutoff)) => (old_cutoff, cutoff),
+            _ => return Ok(()),
+        };
+        let cell_set = chain_state.cell_set().new_overlay(cell_set_diff);
+        let is_spent = |tx_hash: &H256| {
+            cell_set
+                .get(tx_hash)
+                .map(TransactionMeta::is_all_dead)
+                .unwrap_or(true)
+        };
+
+        // blocks crossing the cutoff, below the fork point they are still on the main chain
+        let fork_number = new_tip_number - fork.attached_blocks().len() as BlockNumber;
+        for number in old_cutoff..cmp::min(cutoff, fork_number + 1) {
+            let hash = self
+                .shared
+                .block_hash(number)
+                .expect("main chain block should be indexed");
+            // the pruned blocks are fully spent, their entries are already dropped
+            let txs = match self.shared.block_body(&hash) {
+                Some(txs) => txs,
+                None => continue,
+            };
+            for tx in txs.iter().filter(|tx| is_spent(&tx.hash())) {
+                batch.delete_transaction_address(&tx.hash())?;
+            }
+        }
+        for block in fork.attached_blocks() {
+            if block.header().number() >= cutoff {
+                continue;
+            }
+            for tx in block
+                .transactions()
+                .iter()
+                .filter(|tx| is_spent(&tx.hash()))
+            {
+                batch.delete_transaction_address(&tx.hash())?;
+            }
+        }
+
+        // transactions below the cutoff spent by the attached blocks
+        for out_point in &cell_set_diff.new_inputs {
+            if let Some(meta) = cell_set.get(&out_point.tx_hash) {
+                if meta.block_number() < cutoff && meta.is_all_dead() {
+                    batch.delete_transaction_address(&out_point.tx_hash)?;
+                }
+            }
+        }
+
+        // transactions whose outputs the detached blocks revive
+        for out_point in &cell_set_diff.old_inputs {
+            let tx_hash = &out_point.tx_hash;
+            let meta = match cell_set.get(tx_hash) {
+                Some(meta) => meta,
+                None => continue,
+            };
+            if meta.is_all_dead()
+                || self
+                    .shared
+                    .store()
+                    .get_transaction_address(tx_hash)
+                    .is_some()
+            {
+                continue;
+            }
+            let address = self
+                .shared
+                .block_hash(meta.block_number())
+                .and_then(|hash| {
+                    self.shared
+                        .block_body(&hash)
+                        .and_then(|txs| txs.iter().position(|tx| &tx.hash() == tx_hash))
+                        .and_then(|index| {
+                            self.shared
+                                .store()
+                                .get_block_transaction_address(&hash, index)
+                        })
+                });
+            if let Some(address) = address {
+                batch.insert_transaction_address(tx_hash, &address)?;
+            }
+        }
+        Ok(())
+    }
+
+    // With a pruning depth configured, delete the bodies of the main chain blocks below the
+    // cutoff whose transactions are all fully spent, at most `MAX_PRUNED_BLOCKS` per block
+    // so that enabling it on a long chain catches up gradually. The blocks below the pruned
+    // height which were skipped while partly unspent are checked again when the fork spends
+    // their cells.
+    pub(crate) fn prune_blocks(
+        &self,
+        batch: &mut StoreBatch,
+        chain_state: &ChainState<CS>,
+        fork: &ForkChanges,
+        cell_set_diff: &CellSetDiff,
+        new_tip_number: BlockNumber,
+    ) -> Result<(), FailureError> {
+        let cutoff = match self
+            .shared
+            .store_config()
+            .block_pruning_cutoff(new_tip_number)
+        {
+            Some(cutoff) => cutoff,
+            None => return Ok(()),
+        };
+        let pruned_height = self.shared.store().get_pruned_height().unwrap_or(0);
+        // below the fork point the blocks are still on the main chain
+        let fork_number = new_tip_number - fork.attached_blocks().len() as BlockNumber;
+        let end = cmp::min(
+            cmp::min(cutoff, fork_number + 1),
+            pruned_height + MAX_PRUNED_BLOCKS,
+        );
+        let mut numbers: Vec<BlockNumber> = cell_set_diff
+            .new_inputs
+            .iter()
+            .filter_map(|out_point| chain_state.cell_set().get(&out_point.tx_hash))
+            .map(TransactionMeta::block_number)
+            .filter(|number| *number < pruned_height)
+            .collect::<FnvHashSet<_>>()
+            .into_iter()
+            .collect();
+        numbers.extend(pruned_height..end);
+        if numbers.is_empty() {
+            return Ok(());
+        }
+
+        let cell_set = chain_state.cell_set().new_overlay(cell_set_diff);
+        let is_spent = |tx: &Transaction| {
+            cell_set
+                .get(&tx.hash())
+                .map(TransactionMeta::is_all_dead)
+                .unwrap_or(true)
+        };
+        for number in numbers {
+            let hash = self
+                .shared
+                .block_hash(number)
+                .expect("main chain block should be indexed");
+            let txs = match self.shared.block_body(&hash) {
+                Some(txs) => txs,
+                None => continue,
+            };
+            if txs.iter().all(|tx| is_spent(tx)) {
+                for tx in &txs {
+                    batch.delete_transaction_address(&tx.hash())?;
+                    batch.delete_cell_outputs(&tx.hash())?;
+                }
+                batch.prune_block(&hash)?;
+            }
+        }
+        if end > pruned_height {
+            batch.insert_pruned_height(end)?;
+        }
+        Ok(())
+    }
+
+    // With the lock index enabled, index the cells made live by the fork and drop the ones
+    // it spends or detaches
+    pub(crate) fn update_lock_index(
+        &self,
+        batch: &mut StoreBatch,
+        chain_state: &ChainState<CS>,
+        fork: &ForkChanges,
+        cell_set_diff: &CellSetDiff,
+    ) -> Result<(), FailureError> {
+        if !self.shared.store_config().lock_index {
+            return Ok(());
+        }
+        let cell_set = chain_state.cell_set().new_overlay(cell_set_diff);
+
+        // the outputs of the fork blocks, the other ones are read from the store
+        let mut outputs: FnvHashMap<H256, &[CellOutput]> = FnvHashMap::default();
+        for block in fork.detached_blocks().iter().chain(fork.attached_blocks()) {
+            outputs.extend(
+                block
+                    .transactions()
+                    .iter()
+                    .map(|tx| (tx.hash(), tx.outputs())),
+            );
+        }
+        let mut touched: FnvHashSet<OutPoint> = cell_set_diff
+            .new_inputs
+            .union(&cell_set_diff.old_inputs)
+            .cloned()
+            .collect();
+        for (tx_hash, tx_outputs) in &outputs {
+            touched.extend(
+                (0..tx_outputs.len()).map(|index| OutPoint::new(tx_hash.clone(), index as u32)),
+            );
+        }
+
+        for out_point in touched {
+            let index = out_point.index as usize;
+            let output = match outputs.get(&out_point.tx_hash) {
+                Some(tx_outputs) => tx_outputs.get(index).cloned(),
+                None => cell_set
+                    .get(&out_point.tx_hash)
+                    .or_else(|| chain_state.cell_set().get(&out_point.tx_hash))
+                    .and_then(|meta| {
+                        self.shared.store().get_cell_output_in_block(
+                            &out_point.tx_hash,
+                            out_point.index,
+                            meta.block_number(),
+                        )
+                    }),
+            };
+            let output = match output {
+                Some(output) => output,
+                None => continue,
+            };
+            // the cells created and spent by the fork are dead as well
+            let live = cell_set.get(&out_point.tx_hash).filter(|meta| {
+                !meta.is_dead(index) && !cell_set_diff.new_inputs.contains(&out_point)
+            });
+            match live {
+                Some(meta) => {
+                    let cell = LockCell {
+                        capacity: output.capacity,
+                        lock: output.lock,
+                        block_number: meta.block_number(),
+                    };
+                    batch.insert_lock_cell(&out_point, &cell)?;
+                }
+                None => batch.delete_lock_cell(&output.lock.hash(), &out_point)?,
+            }
         }
         Ok(())
     }
@@ -322,17 +682,14 @@ impl<CS: ChainStore + 'static> ChainService<CS> {
         index: &mut GlobalIndex,
         new_tip_number: BlockNumber,
         current_tip_number: BlockNumber,
-    ) {
+    ) -> Result<(), FailureError> {
         if new_tip_number <= current_tip_number {
             for bn in new_tip_number..=current_tip_number {
                 let hash = self
                     .shared
                     .block_hash(bn)
                     .expect("block hash stored before alignment_fork");
-                let old_block = self
-                    .shared
-                    .block(&hash)
-                    .expect("block data stored before alignment_fork");
+                let old_block = self.detached_block(&hash)?;
                 fork.detached_blocks.push(old_block);
             }
         } else {
@@ -356,9 +713,14 @@ impl<CS: ChainStore + 'static> ChainService<CS> {
                 fork.attached_blocks.push(new_block);
             }
         }
+        Ok(())
     }
 
-    fn find_fork_until_latest_common(&self, fork: &mut ForkChanges, index: &mut GlobalIndex) {
+    fn find_fork_until_latest_common(
+        &self,
+        fork: &mut ForkChanges,
+        index: &mut GlobalIndex,
+    ) -> Result<(), FailureError> {
         loop {
             if index.number == 0 {
                 break;
@@ -370,10 +732,7 @@ impl<CS: ChainStore + 'static> ChainService<CS> {
             if detached_hash == index.hash {
                 break;
             }
-            let detached_blocks = self
-                .shared
-                .block(&detached_hash)
-                .expect("detached block stored before find_fork_until_latest_common");
+            let detached_blocks = self.detached_block(&detached_hash)?;
             fork.detached_blocks.push(detached_blocks);
 
             if index.unseen {
@@ -395,6 +754,19 @@ impl<CS: ChainStore + 'static> ChainService<CS> {
             index.forward(attached_block.header().parent_hash().clone());
             fork.attached_blocks.push(attached_block);
         }
+        Ok(())
+    }
+
+    // The body of a main chain block is missing only when it is pruned, the fork reaches
+    // below the pruned height then and can't be switched to.
+    fn detached_block(&self, hash: &H256) -> Result<Block, FailureError> {
+        self.shared.block(hash).ok_or_else(|| {
+            SharedError::InvalidData(format!(
+                "detached block {:x} is pruned, the fork is deeper than the pruning depth",
+                hash
+            ))
+            .into()
+        })
     }
 
     pub(crate) fn find_fork(
@@ -403,7 +775,7 @@ impl<CS: ChainStore + 'static> ChainService<CS> {
         current_tip_number: BlockNumber,
         new_tip_block: &Block,
         new_tip_ext: BlockExt,
-    ) {
+    ) -> Result<(), FailureError> {
         let new_tip_number = new_tip_block.header().number();
         fork.dirty_exts.push(new_tip_ext);
 
@@ -421,10 +793,10 @@ impl<CS: ChainStore + 'static> ChainService<CS> {
         // then detached_blocks.extend(chain[new_tip_number .. =current_tip_number])
         // if new_tip_number > current_tip_number
         // then attached_blocks.extend(forks[current_tip_number + 1 .. =new_tip_number])
-        self.alignment_fork(fork, &mut index, new_tip_number, current_tip_number);
+        self.alignment_fork(fork, &mut index, new_tip_number, current_tip_number)?;
 
         // find latest common ancestor
-        self.find_fork_until_latest_common(fork, &mut index);
+        self.find_fork_until_latest_common(fork, &mut index)
     }
 
     // we found new best_block total_difficulty > old_chain.total_difficulty
@@ -432,7 +804,7 @@ impl<CS: ChainStore + 'static> ChainService<CS> {
         &self,
         batch: &mut StoreBatch,
         fork: &mut ForkChanges,
-        chain_state: &mut ChainState<CS>,
+        chain_state: &ChainState<CS>,
     ) -> Result<CellSetDiff, FailureError> {
         let mut cell_set_diff = CellSetDiff::default();
         let mut outputs: FnvHashMap<H256, &[CellOutput]> = FnvHashMap::default();
@@ -456,7 +828,10 @@ impl<CS: ChainStore + 'static> ChainService<CS> {
         }
 
         // The verify function
-        let txs_verifier = TransactionsVerifier::new(self.shared.consensus().max_block_cycles());
+        let txs_verifier = TransactionsVerifier::with_script_hash_cache(
+            self.shared.consensus().max_block_cycles(),
+            Arc::clone(self.shared.script_hash_cache()),
+        );
 
         let mut found_error = None;
         // verify transaction
@@ -469,26 +844,65 @@ impl<CS: ChainStore + 'static> ChainService<CS> {
                     let block_cp = BlockCellProvider::new(b);
                     let cell_provider = OverlayCellProvider::new(&block_cp, &cell_set_overlay);
 
-                    let resolved: Vec<ResolvedTransaction> = b
+                    let resolved: Result<Vec<ResolvedTransaction>, VerificationError> = b
                         .transactions()
                         .iter()
-                        .map(|x| resolve_transaction(x, &mut seen_inputs, &cell_provider))
+                        .enumerate()
+                        .map(|(index, x)| {
+                            resolve_transaction(x, &mut seen_inputs, &cell_provider).map_err(
+                                |err| {
+                                    // indexed as `TransactionsVerifier` does, after the cellbase
+                                    VerificationError::Transactions((
+                                        index.saturating_sub(1),
+                                        TransactionError::Unresolvable(err),
+                                    ))
+                                },
+                            )
+                        })
                         .collect();
 
                     let cellbase_maturity = { self.shared.consensus().cellbase_maturity() };
+                    let fork_context = self
+                        .shared
+                        .branch_median_time_context(b.header())
+                        .with_blocks(&fork.attached_blocks);
+
+                    let txs_verify_cache: FnvHashMap<H256, Cycle> = {
+                        let mut cache = self.shared.txs_verify_cache().lock();
+                        b.transactions()
+                            .iter()
+                            .skip(1)
+                            .filter_map(|tx| {
+                                let tx_hash = tx.hash();
+                                cache.get_mut(&tx_hash).map(|cycles| (tx_hash, *cycles))
+                            })
+                            .collect()
+                    };
+
+                    let verify_ret = resolved.and_then(|resolved| {
+                        let fees = transactions_fee(&resolved)?;
+                        let cellbase_capacity =
+                            chain_state.expected_cellbase_capacity(b.header().number(), fees)?;
+                        let verified = self.shared.verification_pool().install(|| {
+                            txs_verifier.verify(
+                                &resolved,
+                                cellbase_capacity,
+                                fork_context,
+                                b.header().number(),
+                                cellbase_maturity,
+                                &txs_verify_cache,
+                            )
+                        })?;
+                        chain_state.insert_block_fees(b.header().hash(), fees);
+                        Ok(verified)
+                    });
+                    match verify_ret {
+                        Ok(verified) => {
+                            let mut cache = self.shared.txs_verify_cache().lock();
+                            for (tx_hash, cycles) in verified {
+                                cache.insert(tx_hash, cycles);
+                            }
 
-                    match txs_verifier.verify(
-                        &resolved,
-                        self.shared.block_reward(b.header().number()),
-                        ForkContext {
-                            fork_blocks: &fork.attached_blocks,
-                            store: Arc::clone(self.shared.store()),
-                            consensus: self.shared.consensus(),
-                        },
-                        b.header().number(),
-                        cellbase_maturity,
-                    ) {
-                        Ok(_) => {
                             cell_set_diff.push_new(b);
                             outputs.extend(
                                 b.transactions().iter().map(|tx| (tx.hash(), tx.outputs())),
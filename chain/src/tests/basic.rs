@@ -1,3 +1,4 @@
+use crate::chain::ChainBuilder;
 use crate::tests::util::{create_transaction, gen_block, start_chain};
 use ckb_chain_spec::consensus::Consensus;
 use ckb_core::block::Block;
@@ -7,10 +8,18 @@ use ckb_core::header::HeaderBuilder;
 use ckb_core::script::Script;
 use ckb_core::transaction::{CellInput, CellOutput, OutPoint, TransactionBuilder};
 use ckb_core::{capacity_bytes, Capacity};
+use ckb_db::memorydb::MemoryKeyValueDB;
+use ckb_notify::{ChainEvent, NotifyService};
 use ckb_shared::error::SharedError;
+use ckb_shared::shared::SharedBuilder;
+use ckb_shared::store::{ChainStore, StoreConfig, MIN_BLOCK_PRUNING_DEPTH};
+use ckb_shared::tx_pool::{PoolError, TxStatus};
 use ckb_traits::ChainProvider;
+use ckb_verification::TransactionError;
+use numext_fixed_hash::H256;
 use numext_fixed_uint::U256;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[test]
 fn test_genesis_transaction_spend() {
@@ -66,7 +75,6 @@ fn test_genesis_transaction_spend() {
     assert_eq!(
         shared
             .chain_state()
-            .lock()
             .get_cell_status(&OutPoint::new(genesis_tx_hash, 0)),
         CellStatus::Dead
     );
@@ -110,7 +118,6 @@ fn test_transaction_spend_in_same_block() {
         assert_eq!(
             shared
                 .chain_state()
-                .lock()
                 .get_cell_status(&OutPoint::new(hash.clone(), 0)),
             CellStatus::Unknown
         );
@@ -163,7 +170,6 @@ fn test_transaction_spend_in_same_block() {
         assert_eq!(
             shared
                 .chain_state()
-                .lock()
                 .get_cell_status(&OutPoint::new(hash.clone(), 0)),
             CellStatus::Dead
         );
@@ -172,7 +178,6 @@ fn test_transaction_spend_in_same_block() {
     assert_eq!(
         shared
             .chain_state()
-            .lock()
             .get_cell_status(&OutPoint::new(tx2_hash, 0)),
         CellStatus::live_output(tx2_output, Some(4), false)
     );
@@ -373,7 +378,7 @@ fn test_genesis_transaction_fetch() {
     let (_chain_controller, shared) = start_chain(Some(consensus), false);
 
     let out_point = OutPoint::new(root_hash, 0);
-    let state = shared.chain_state().lock().get_cell_status(&out_point);
+    let state = shared.chain_state().get_cell_status(&out_point);
     assert!(state.is_live());
 }
 
@@ -601,7 +606,7 @@ fn test_calculate_difficulty() {
         chain2.push(new_block.clone());
         parent = new_block.header().clone();
     }
-    let tip = shared.chain_state().lock().tip_header().clone();
+    let tip = shared.chain_state().tip_header().clone();
     let total_uncles_count = shared.block_ext(&tip.hash()).unwrap().total_uncles_count;
     assert_eq!(total_uncles_count, 25);
     let difficulty = shared.calculate_difficulty(&tip).unwrap();
@@ -631,7 +636,7 @@ fn test_calculate_difficulty() {
         chain2.push(new_block.clone());
         parent = new_block.header().clone();
     }
-    let tip = shared.chain_state().lock().tip_header().clone();
+    let tip = shared.chain_state().tip_header().clone();
     let total_uncles_count = shared.block_ext(&tip.hash()).unwrap().total_uncles_count;
     assert_eq!(total_uncles_count, 10);
     let difficulty = shared.calculate_difficulty(&tip).unwrap();
@@ -661,7 +666,7 @@ fn test_calculate_difficulty() {
         chain2.push(new_block.clone());
         parent = new_block.header().clone();
     }
-    let tip = shared.chain_state().lock().tip_header().clone();
+    let tip = shared.chain_state().tip_header().clone();
     let total_uncles_count = shared.block_ext(&tip.hash()).unwrap().total_uncles_count;
     assert_eq!(total_uncles_count, 150);
     let difficulty = shared.calculate_difficulty(&tip).unwrap();
@@ -669,3 +674,490 @@ fn test_calculate_difficulty() {
     // max[150 * 10 * 1000 / 200, 2 * 1000]
     assert_eq!(difficulty, U256::from(2000u64));
 }
+
+#[test]
+fn test_tx_index_retention() {
+    let tx = TransactionBuilder::default()
+        .input(CellInput::new(OutPoint::null(), 0, Default::default()))
+        .outputs(vec![
+            CellOutput::new(
+                capacity_bytes!(100_000_000),
+                vec![],
+                Script::default(),
+                None
+            );
+            100
+        ])
+        .build();
+    let genesis_tx_hash = tx.hash().clone();
+    let genesis_block = BlockBuilder::default()
+        .transaction(tx)
+        .with_header_builder(HeaderBuilder::default().difficulty(U256::from(1000u64)));
+    let consensus = Consensus::default().set_genesis_block(genesis_block);
+
+    let shared = SharedBuilder::<MemoryKeyValueDB>::new()
+        .consensus(consensus)
+        .store_config(StoreConfig {
+            tx_index_retention: Some(2),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let notify = NotifyService::default().start::<&str>(None);
+    let chain_controller = ChainBuilder::new(shared.clone(), notify)
+        .verification(false)
+        .build()
+        .start::<&str>(None);
+
+    // every transaction spends the only output of the previous one
+    let mut root_hash = genesis_tx_hash.clone();
+    let mut tx_hashes = vec![];
+    let mut blocks = vec![];
+    let mut parent = shared.block_header(&shared.block_hash(0).unwrap()).unwrap();
+    for i in 1..=10 {
+        let tx = create_transaction(root_hash, i as u8);
+        root_hash = tx.hash().clone();
+        tx_hashes.push(tx.hash().clone());
+        let block = gen_block(
+            &parent,
+            parent.difficulty().clone() + U256::from(1u64),
+            vec![tx],
+            vec![],
+            vec![],
+        );
+        parent = block.header().clone();
+        blocks.push(block);
+    }
+    for block in &blocks {
+        chain_controller
+            .process_block(Arc::new(block.clone()))
+            .expect("process block ok");
+    }
+
+    // tip 10, blocks from 9 are indexed
+    assert!(shared.get_transaction(&genesis_tx_hash).is_some());
+    assert!(shared
+        .get_transaction(&blocks[0].transactions()[0].hash())
+        .is_some());
+    for tx_hash in &tx_hashes[0..8] {
+        assert!(shared.get_transaction(tx_hash).is_none());
+    }
+    for tx_hash in &tx_hashes[8..10] {
+        assert!(shared.get_transaction(tx_hash).is_some());
+    }
+
+    // detaching blocks 9 and 10 revives the output of the transaction in block 8
+    let mut parent = blocks[7].header().clone();
+    for _ in 0..3 {
+        let block = gen_block(
+            &parent,
+            parent.difficulty().clone() + U256::from(1u64),
+            vec![],
+            vec![],
+            vec![],
+        );
+        parent = block.header().clone();
+        chain_controller
+            .process_block(Arc::new(block))
+            .expect("process block ok");
+    }
+    assert_eq!(shared.chain_state().tip_number(), 11);
+    assert!(shared.get_transaction(&tx_hashes[7]).is_some());
+    assert!(shared.get_transaction(&tx_hashes[6]).is_none());
+    assert!(shared.get_transaction(&tx_hashes[8]).is_none());
+}
+
+#[test]
+fn test_block_pruning() {
+    let depth = MIN_BLOCK_PRUNING_DEPTH;
+    let shared = SharedBuilder::<MemoryKeyValueDB>::new()
+        .store_config(StoreConfig {
+            block_pruning_depth: Some(depth),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let notify = NotifyService::default().start::<&str>(None);
+    let chain_controller = ChainBuilder::new(shared.clone(), notify)
+        .verification(false)
+        .build()
+        .start::<&str>(None);
+
+    // every block spends all the first outputs of the previous one, the second output of
+    // block 3 is spent by the last block only
+    let tip = depth + 7;
+    let mut blocks: Vec<Block> = vec![];
+    let mut parent = shared.block_header(&shared.block_hash(0).unwrap()).unwrap();
+    for i in 1..=tip {
+        let mut transactions = match blocks.last() {
+            Some(prev) => vec![TransactionBuilder::default()
+                .inputs(
+                    prev.transactions()
+                        .iter()
+                        .map(|tx| CellInput::new(OutPoint::new(tx.hash().clone(), 0), 0, vec![]))
+                        .collect(),
+                )
+                .output(CellOutput::new(
+                    capacity_bytes!(5000),
+                    vec![i as u8],
+                    Script::always_success(),
+                    None,
+                ))
+                .build()],
+            None => vec![create_transaction(H256::zero(), i as u8)],
+        };
+        if i == 3 {
+            transactions[0] = TransactionBuilder::default()
+                .transaction(transactions[0].clone())
+                .output(CellOutput::new(
+                    capacity_bytes!(5000),
+                    vec![],
+                    Script::always_success(),
+                    None,
+                ))
+                .build();
+        }
+        if i == tip {
+            // the tip is depth + 7, so the blocks below 8 are passed already
+            assert_eq!(shared.store().get_pruned_height(), Some(7));
+            assert!(shared.block_body(&blocks[2].header().hash()).is_some());
+            transactions[0] = TransactionBuilder::default()
+                .transaction(transactions[0].clone())
+                .input(CellInput::new(
+                    OutPoint::new(blocks[2].transactions()[1].hash().clone(), 1),
+                    0,
+                    vec![],
+                ))
+                .build();
+        }
+        let block = gen_block(
+            &parent,
+            parent.difficulty().clone() + U256::from(1u64),
+            transactions,
+            vec![],
+            vec![],
+        );
+        parent = block.header().clone();
+        chain_controller
+            .process_block(Arc::new(block.clone()))
+            .expect("process block ok");
+        blocks.push(block);
+    }
+
+    // the spent blocks below 8 are pruned, their headers are kept, block 3 is revisited once
+    // its last output is spent
+    assert_eq!(shared.store().get_pruned_height(), Some(8));
+    for block in &blocks[0..7] {
+        let hash = block.header().hash();
+        assert!(shared.block_body(&hash).is_none());
+        assert!(shared.block_header(&hash).is_some());
+        assert!(shared
+            .get_transaction(&block.transactions()[0].hash())
+            .is_none());
+    }
+    for block in &blocks[7..] {
+        assert!(shared.block(&block.header().hash()).is_some());
+    }
+    // the genesis block has no transaction at all
+    assert!(shared.block_body(&shared.block_hash(0).unwrap()).is_none());
+
+    // a fork detaching the pruned blocks is rejected instead of switched to
+    let fork = gen_block(
+        blocks[0].header(),
+        U256::from(u64::max_value()),
+        vec![],
+        vec![],
+        vec![],
+    );
+    assert!(chain_controller.process_block(Arc::new(fork)).is_err());
+    assert_eq!(shared.chain_state().tip_number(), tip);
+}
+
+#[test]
+fn test_lock_index() {
+    let shared = SharedBuilder::<MemoryKeyValueDB>::new()
+        .store_config(StoreConfig {
+            lock_index: true,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let notify = NotifyService::default().start::<&str>(None);
+    let chain_controller = ChainBuilder::new(shared.clone(), notify)
+        .verification(false)
+        .build()
+        .start::<&str>(None);
+    let lock_hash = Script::always_success().hash();
+    let lock_cells = || -> Vec<OutPoint> {
+        let mut out_points: Vec<OutPoint> = shared
+            .store()
+            .get_lock_cells(&lock_hash)
+            .into_iter()
+            .map(|(out_point, cell)| {
+                assert_eq!(cell.lock.hash(), lock_hash);
+                out_point
+            })
+            .collect();
+        out_points.sort_by_key(|out_point| out_point.tx_hash.clone());
+        out_points
+    };
+    let cellbase_out_points = |blocks: &[Block]| -> Vec<OutPoint> {
+        let mut out_points: Vec<OutPoint> = blocks
+            .iter()
+            .map(|block| OutPoint::new(block.transactions()[0].hash(), 0))
+            .collect();
+        out_points.sort_by_key(|out_point| out_point.tx_hash.clone());
+        out_points
+    };
+    let genesis = shared.block_header(&shared.block_hash(0).unwrap()).unwrap();
+
+    // the second block spends the cellbase of the first one
+    let block1 = gen_block(
+        &genesis,
+        genesis.difficulty().clone() + U256::from(1u64),
+        vec![],
+        vec![],
+        vec![],
+    );
+    let tx = create_transaction(block1.transactions()[0].hash(), 1);
+    let block2 = gen_block(
+        block1.header(),
+        block1.header().difficulty().clone() + U256::from(1u64),
+        vec![tx.clone()],
+        vec![],
+        vec![],
+    );
+    let block3 = gen_block(
+        block2.header(),
+        block2.header().difficulty().clone() + U256::from(1u64),
+        vec![],
+        vec![],
+        vec![],
+    );
+    for block in &[&block1, &block2, &block3] {
+        chain_controller
+            .process_block(Arc::new((*block).clone()))
+            .expect("process block ok");
+    }
+    let mut expected = cellbase_out_points(&[block2.clone(), block3.clone()]);
+    expected.push(OutPoint::new(tx.hash(), 0));
+    expected.sort_by_key(|out_point| out_point.tx_hash.clone());
+    assert_eq!(lock_cells(), expected);
+
+    // the fork drops the transaction and revives the cellbase it spent
+    let mut fork = vec![block1.clone()];
+    for _ in 0..3 {
+        let parent = fork.last().unwrap().header().clone();
+        let block = gen_block(
+            &parent,
+            parent.difficulty().clone() + U256::from(2u64),
+            vec![],
+            vec![],
+            vec![],
+        );
+        chain_controller
+            .process_block(Arc::new(block.clone()))
+            .expect("process block ok");
+        fork.push(block);
+    }
+    assert_eq!(shared.chain_state().tip_number(), 4);
+    assert_eq!(lock_cells(), cellbase_out_points(&fork));
+}
+
+#[test]
+fn test_verify_tip() {
+    let shared = SharedBuilder::<MemoryKeyValueDB>::new()
+        .consensus(Consensus::default().set_cellbase_maturity(0))
+        .build()
+        .unwrap();
+    let notify = NotifyService::default().start::<&str>(None);
+    let mut chain_service = ChainBuilder::new(shared.clone(), notify)
+        .verification(false)
+        .build();
+    assert!(chain_service.verify_tip(10).is_ok());
+
+    // stored without verification, the difficulty does not follow the consensus rules
+    let parent = shared.block_header(&shared.block_hash(0).unwrap()).unwrap();
+    let block = gen_block(
+        &parent,
+        parent.difficulty() + U256::from(100u64),
+        vec![],
+        vec![],
+        vec![],
+    );
+    chain_service
+        .process_block(Arc::new(block))
+        .expect("process block ok");
+    assert!(chain_service.verify_tip(0).is_err());
+}
+
+#[test]
+fn test_delay_immature_cellbase_spend() {
+    let consensus = Consensus::default().set_cellbase_maturity(3);
+    let (chain_controller, shared) = start_chain(Some(consensus), false);
+    let mut parent = shared.block_header(&shared.block_hash(0).unwrap()).unwrap();
+    let mut blocks = Vec::new();
+    for _ in 0..4 {
+        let block = gen_block(
+            &parent,
+            parent.difficulty() + U256::from(1u64),
+            vec![],
+            vec![],
+            vec![],
+        );
+        parent = block.header().clone();
+        blocks.push(block);
+    }
+    chain_controller
+        .process_block(Arc::new(blocks[0].clone()))
+        .expect("process block ok");
+
+    // the cellbase of block 1 is mature at block 4
+    let tx = create_transaction(blocks[0].transactions()[0].hash(), 1);
+    let ret = {
+        let mut tx_pool = shared.tx_pool().lock();
+        shared
+            .chain_state()
+            .add_tx_to_pool(&mut tx_pool, tx.clone())
+    };
+    assert_eq!(
+        ret,
+        Err(PoolError::InvalidTx(TransactionError::CellbaseImmaturity))
+    );
+    assert_eq!(shared.tx_status(&tx.hash()), Some(TxStatus::Pending));
+
+    let proposals = || {
+        shared
+            .tx_pool()
+            .lock()
+            .get_proposal_and_staging_txs(10, 10)
+            .0
+    };
+    for block in &blocks[1..3] {
+        chain_controller
+            .process_block(Arc::new(block.clone()))
+            .expect("process block ok");
+        assert!(proposals().is_empty());
+    }
+    chain_controller
+        .process_block(Arc::new(blocks[3].clone()))
+        .expect("process block ok");
+    assert_eq!(proposals(), vec![tx.proposal_short_id()]);
+}
+
+#[test]
+fn test_chain_reorg_events() {
+    let shared = SharedBuilder::<MemoryKeyValueDB>::new()
+        .consensus(Consensus::default().set_cellbase_maturity(0))
+        .build()
+        .unwrap();
+    let notify = NotifyService::default().start::<&str>(None);
+    let events = notify.subscribe_chain_event("test");
+    let chain_controller = ChainBuilder::new(shared.clone(), notify)
+        .verification(false)
+        .build()
+        .start::<&str>(None);
+
+    let genesis = shared.block_header(&shared.block_hash(0).unwrap()).unwrap();
+    let build_chain = |difficulty: u64| {
+        let mut blocks: Vec<Block> = Vec::new();
+        let mut parent = genesis.clone();
+        for _ in 0..2 {
+            let block = gen_block(
+                &parent,
+                parent.difficulty() + U256::from(difficulty),
+                vec![],
+                vec![],
+                vec![],
+            );
+            parent = block.header().clone();
+            blocks.push(block);
+        }
+        blocks
+    };
+    let chain1 = build_chain(100);
+    let chain2 = build_chain(150);
+
+    for block in chain1.iter().chain(chain2.iter()) {
+        chain_controller
+            .process_block(Arc::new(block.clone()))
+            .expect("process block ok");
+    }
+
+    let timeout = Duration::from_millis(500);
+    // the first block of chain2 is lighter than chain1 and doesn't move the tip
+    let extended = |block: &Block, common_ancestor: H256| ChainEvent::Reorg {
+        attached: vec![block.clone()],
+        detached: vec![],
+        common_ancestor,
+    };
+    assert_eq!(
+        events.recv_timeout(timeout).map(|event| (*event).clone()),
+        Ok(extended(&chain1[0], genesis.hash()))
+    );
+    assert_eq!(
+        events.recv_timeout(timeout).map(|event| (*event).clone()),
+        Ok(extended(&chain1[1], chain1[0].header().hash()))
+    );
+    assert_eq!(
+        events.recv_timeout(timeout).map(|event| (*event).clone()),
+        Ok(ChainEvent::Reorg {
+            attached: chain2.clone(),
+            detached: chain1.iter().rev().cloned().collect(),
+            common_ancestor: genesis.hash(),
+        })
+    );
+    assert!(events.recv_timeout(timeout).is_err());
+}
+
+#[test]
+fn test_reorg_keeps_tx_fee() {
+    let (chain_controller, shared) = start_chain(None, false);
+    let genesis = shared.block_header(&shared.block_hash(0).unwrap()).unwrap();
+    let block1 = gen_block(
+        &genesis,
+        genesis.difficulty() + U256::from(100u64),
+        vec![],
+        vec![],
+        vec![],
+    );
+    let tx = TransactionBuilder::default()
+        .input(CellInput::new(
+            OutPoint::new(block1.transactions()[0].hash(), 0),
+            0,
+            vec![],
+        ))
+        .output(CellOutput::new(
+            capacity_bytes!(4000),
+            vec![],
+            Script::always_success(),
+            None,
+        ))
+        .build();
+    let block2 = gen_block(
+        block1.header(),
+        block1.header().difficulty() + U256::from(100u64),
+        vec![tx.clone()],
+        vec![],
+        vec![],
+    );
+    let fork_block2 = gen_block(
+        block1.header(),
+        block1.header().difficulty() + U256::from(200u64),
+        vec![],
+        vec![],
+        vec![],
+    );
+    for block in vec![block1, block2, fork_block2] {
+        chain_controller
+            .process_block(Arc::new(block))
+            .expect("process block ok");
+    }
+
+    // the transaction detached by the fork goes back to the pending queue with its fee
+    let tx_pool = shared.tx_pool().lock();
+    let entry = tx_pool
+        .get_pending_entry(&tx.proposal_short_id())
+        .expect("pending entry");
+    assert_eq!(entry.fee, capacity_bytes!(1000));
+}
@@ -55,7 +55,7 @@ fn test_find_fork_case1() {
         chain_service.process_block(Arc::new(blk.clone())).unwrap();
     }
 
-    let tip_number = { shared.chain_state().lock().tip_number() };
+    let tip_number = { shared.chain_state().tip_number() };
 
     // fork2 total_difficulty 470
     let new_block = gen_block(&parent, U256::from(200u64), vec![], vec![], vec![]);
@@ -71,7 +71,9 @@ fn test_find_fork_case1() {
 
     let mut fork = ForkChanges::default();
 
-    chain_service.find_fork(&mut fork, tip_number, &new_block, ext);
+    chain_service
+        .find_fork(&mut fork, tip_number, &new_block, ext)
+        .unwrap();
 
     let detached_blocks: HashSet<Block> = HashSet::from_iter(fork1.into_iter());
     let attached_blocks: HashSet<Block> = HashSet::from_iter(fork2.into_iter());
@@ -127,7 +129,7 @@ fn test_find_fork_case2() {
         chain_service.process_block(Arc::new(blk.clone())).unwrap();
     }
 
-    let tip_number = { shared.chain_state().lock().tip_number() };
+    let tip_number = { shared.chain_state().tip_number() };
 
     let difficulty = parent.difficulty().clone();
     let new_block = gen_block(
@@ -149,7 +151,9 @@ fn test_find_fork_case2() {
 
     let mut fork = ForkChanges::default();
 
-    chain_service.find_fork(&mut fork, tip_number, &new_block, ext);
+    chain_service
+        .find_fork(&mut fork, tip_number, &new_block, ext)
+        .unwrap();
 
     let detached_blocks: HashSet<Block> = HashSet::from_iter(fork1[1..].iter().cloned());
     let attached_blocks: HashSet<Block> = HashSet::from_iter(fork2.into_iter());
@@ -205,7 +209,7 @@ fn test_find_fork_case3() {
         chain_service.process_block(Arc::new(blk.clone())).unwrap();
     }
 
-    let tip_number = { shared.chain_state().lock().tip_number() };
+    let tip_number = { shared.chain_state().tip_number() };
 
     println!("case3 tip{}", tip_number);
 
@@ -221,7 +225,9 @@ fn test_find_fork_case3() {
     };
     let mut fork = ForkChanges::default();
 
-    chain_service.find_fork(&mut fork, tip_number, &new_block, ext);
+    chain_service
+        .find_fork(&mut fork, tip_number, &new_block, ext)
+        .unwrap();
 
     let detached_blocks: HashSet<Block> = HashSet::from_iter(fork1.into_iter());
     let attached_blocks: HashSet<Block> = HashSet::from_iter(fork2.into_iter());
@@ -277,7 +283,7 @@ fn test_find_fork_case4() {
         chain_service.process_block(Arc::new(blk.clone())).unwrap();
     }
 
-    let tip_number = { shared.chain_state().lock().tip_number() };
+    let tip_number = { shared.chain_state().tip_number() };
 
     println!("case3 tip{}", tip_number);
 
@@ -294,7 +300,9 @@ fn test_find_fork_case4() {
 
     let mut fork = ForkChanges::default();
 
-    chain_service.find_fork(&mut fork, tip_number, &new_block, ext);
+    chain_service
+        .find_fork(&mut fork, tip_number, &new_block, ext)
+        .unwrap();
 
     let detached_blocks: HashSet<Block> = HashSet::from_iter(fork1.into_iter());
     let attached_blocks: HashSet<Block> = HashSet::from_iter(fork2.into_iter());
@@ -1,6 +1,8 @@
+use crate::error::Error;
 use crate::header::{Header, HeaderBuilder};
 use crate::transaction::{ProposalShortId, Transaction};
 use crate::uncle::{uncles_hash, UncleBlock};
+use bincode::serialized_size;
 use ckb_merkle_tree::merkle_root;
 use fnv::FnvHashSet;
 use numext_fixed_hash::H256;
@@ -49,6 +51,11 @@ impl Block {
         &self.uncles
     }
 
+    /// Serialized size of the block, witnesses included
+    pub fn serialized_size(&self) -> usize {
+        serialized_size(&self).expect("Block serialize should not fail") as usize
+    }
+
     pub fn cal_uncles_hash(&self) -> H256 {
         uncles_hash(&self.uncles)
     }
@@ -164,6 +171,48 @@ impl BlockBuilder {
         self.inner
     }
 
+    /// Same as `build`, but check that the commitments in the header match the body
+    pub fn build_checked(self) -> Result<Block, Error> {
+        let block = self.inner;
+        let header = block.header();
+        let transactions_root = block.cal_transactions_root();
+        if &transactions_root != header.transactions_root() {
+            return Err(Error::InvalidTransactionsRoot(
+                transactions_root,
+                header.transactions_root().to_owned(),
+            ));
+        }
+        let proposals_root = block.cal_proposals_root();
+        if &proposals_root != header.proposals_root() {
+            return Err(Error::InvalidProposalsRoot(
+                proposals_root,
+                header.proposals_root().to_owned(),
+            ));
+        }
+        let witnesses_root = block.cal_witnesses_root();
+        if &witnesses_root != header.witnesses_root() {
+            return Err(Error::InvalidWitnessesRoot(
+                witnesses_root,
+                header.witnesses_root().to_owned(),
+            ));
+        }
+        let uncles_hash = block.cal_uncles_hash();
+        if &uncles_hash != header.uncles_hash() {
+            return Err(Error::InvalidUnclesHash(
+                uncles_hash,
+                header.uncles_hash().to_owned(),
+            ));
+        }
+        let uncles_count = block.uncles().len() as u32;
+        if uncles_count != header.uncles_count() {
+            return Err(Error::InvalidUnclesCount(
+                uncles_count,
+                header.uncles_count(),
+            ));
+        }
+        Ok(block)
+    }
+
     pub fn with_header_builder(mut self, header_builder: HeaderBuilder) -> Block {
         let transactions_root = self.inner.cal_transactions_root();
         let witnesses_root = self.inner.cal_witnesses_root();
@@ -180,3 +229,42 @@ impl BlockBuilder {
         self.inner
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::TransactionBuilder;
+
+    #[test]
+    fn build_checked_block() {
+        let transaction = TransactionBuilder::default().build();
+        let block = BlockBuilder::default()
+            .transaction(transaction.clone())
+            .proposal(transaction.proposal_short_id())
+            .with_header_builder(HeaderBuilder::default());
+        assert_eq!(
+            BlockBuilder::default().block(block.clone()).build_checked(),
+            Ok(block.clone())
+        );
+
+        let ret = BlockBuilder::default()
+            .block(block.clone())
+            .transaction(TransactionBuilder::default().version(1).build())
+            .build_checked();
+        match ret {
+            Err(Error::InvalidTransactionsRoot(_, header_root)) => {
+                assert_eq!(&header_root, block.header().transactions_root())
+            }
+            _ => panic!("unexpected result {:?}", ret),
+        }
+
+        let ret = BlockBuilder::default()
+            .block(block.clone())
+            .proposal(ProposalShortId::zero())
+            .build_checked();
+        match ret {
+            Err(Error::InvalidProposalsRoot(..)) => {}
+            _ => panic!("unexpected result {:?}", ret),
+        }
+    }
+}
@@ -5,6 +5,7 @@ use fnv::{FnvHashMap, FnvHashSet};
 use numext_fixed_hash::H256;
 use std::iter::Chain;
 use std::slice;
+use std::sync::Arc;
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub enum LiveCell {
@@ -14,7 +15,9 @@ pub enum LiveCell {
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct CellMeta {
-    pub cell_output: CellOutput,
+    /// Shared with the other resolved transactions when it holds a script binary, see
+    /// `ScriptHashCache`
+    pub cell_output: Arc<CellOutput>,
     pub block_number: Option<u64>,
     pub cellbase: bool,
 }
@@ -44,13 +47,13 @@ impl CellStatus {
         CellStatus::Live(LiveCell::Null)
     }
 
-    pub fn live_output(
-        cell_output: CellOutput,
+    pub fn live_output<O: Into<Arc<CellOutput>>>(
+        cell_output: O,
         block_number: Option<u64>,
         cellbase: bool,
     ) -> CellStatus {
         CellStatus::Live(LiveCell::Output(CellMeta {
-            cell_output,
+            cell_output: cell_output.into(),
             block_number,
             cellbase,
         }))
@@ -168,10 +171,39 @@ impl<'a> CellProvider for BlockCellProvider<'a> {
     }
 }
 
+/// An input or dep of a resolved transaction which is not a live cell
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnresolvableError {
+    /// The input is spent in the chain or the pool, or by another input of the transaction
+    DeadInput(OutPoint),
+    /// The dep is spent in the chain or the pool, or by an input of the transaction
+    DeadDep(OutPoint),
+    /// The inputs and deps which are not found, their transactions may not have arrived yet
+    Unknown {
+        inputs: Vec<OutPoint>,
+        deps: Vec<OutPoint>,
+    },
+}
+
+/// Resolve the inputs and deps of `transaction` as live cells, the first dead input or dep, or
+/// else all the unknown ones, are an error. The inputs are added to `seen_inputs`, an input
+/// already seen is dead.
 pub fn resolve_transaction<'a, CP: CellProvider>(
     transaction: &'a Transaction,
     seen_inputs: &mut FnvHashSet<OutPoint>,
     cell_provider: &CP,
+) -> Result<ResolvedTransaction<'a>, UnresolvableError> {
+    let rtx = resolve_cells(transaction, seen_inputs, cell_provider);
+    rtx.check_resolvable()?;
+    Ok(rtx)
+}
+
+/// Same as `resolve_transaction`, but the dead and unknown inputs and deps are kept in the
+/// resolved transaction, e.g. to explain them or to keep the transaction as an orphan.
+pub fn resolve_cells<'a, CP: CellProvider>(
+    transaction: &'a Transaction,
+    seen_inputs: &mut FnvHashSet<OutPoint>,
+    cell_provider: &CP,
 ) -> ResolvedTransaction<'a> {
     let input_cells = transaction
         .input_pts()
@@ -227,6 +259,43 @@ impl<'a> ResolvedTransaction<'a> {
         self.cells_iter().all(CellStatus::is_live)
     }
 
+    /// Name the first dead input or dep, or else all the unknown ones. Dead cells come first
+    /// since the transaction can't be valid whatever arrives.
+    pub fn check_resolvable(&self) -> Result<(), UnresolvableError> {
+        let inputs = self
+            .input_cells
+            .iter()
+            .zip(self.transaction.input_pts().into_iter());
+        let deps = self
+            .dep_cells
+            .iter()
+            .zip(self.transaction.dep_pts().into_iter());
+        let mut unknown_inputs = Vec::new();
+        let mut unknown_deps = Vec::new();
+        for (cell_status, out_point) in inputs {
+            match cell_status {
+                CellStatus::Dead => return Err(UnresolvableError::DeadInput(out_point)),
+                CellStatus::Unknown => unknown_inputs.push(out_point),
+                CellStatus::Live(_) => {}
+            }
+        }
+        for (cell_status, out_point) in deps {
+            match cell_status {
+                CellStatus::Dead => return Err(UnresolvableError::DeadDep(out_point)),
+                CellStatus::Unknown => unknown_deps.push(out_point),
+                CellStatus::Live(_) => {}
+            }
+        }
+        if unknown_inputs.is_empty() && unknown_deps.is_empty() {
+            Ok(())
+        } else {
+            Err(UnresolvableError::Unknown {
+                inputs: unknown_inputs,
+                deps: unknown_deps,
+            })
+        }
+    }
+
     pub fn fee(&self) -> ::occupied_capacity::Result<Capacity> {
         self.inputs_capacity().and_then(|x| {
             self.transaction.outputs_capacity().and_then(|y| {
@@ -257,6 +326,7 @@ impl<'a> ResolvedTransaction<'a> {
 mod tests {
     use super::super::script::Script;
     use super::*;
+    use crate::transaction::{CellInput, TransactionBuilder};
     use crate::{capacity_bytes, Capacity};
     use numext_fixed_hash::H256;
     use std::collections::HashMap;
@@ -294,12 +364,12 @@ mod tests {
         };
         let o = CellMeta {
             block_number: Some(1),
-            cell_output: CellOutput {
+            cell_output: Arc::new(CellOutput {
                 capacity: capacity_bytes!(2),
                 data: vec![],
                 lock: Script::default(),
                 type_: None,
-            },
+            }),
             cellbase: false,
         };
 
@@ -313,4 +383,96 @@ mod tests {
         assert_eq!(CellStatus::Dead, db.get_cell_status(&p2));
         assert_eq!(CellStatus::Unknown, db.get_cell_status(&p3));
     }
+
+    #[test]
+    fn check_resolvable_names_out_points() {
+        let out_point = |index| OutPoint {
+            tx_hash: H256::zero(),
+            index,
+        };
+        let live = CellStatus::live_null();
+        let transaction = TransactionBuilder::default()
+            .input(CellInput::new(out_point(1), 0, vec![]))
+            .input(CellInput::new(out_point(2), 0, vec![]))
+            .dep(out_point(3))
+            .build();
+        let resolved = |input_cells, dep_cells| ResolvedTransaction {
+            transaction: &transaction,
+            input_cells,
+            dep_cells,
+        };
+
+        let rtx = resolved(vec![live.clone(), live.clone()], vec![live.clone()]);
+        assert_eq!(rtx.check_resolvable(), Ok(()));
+        let rtx = resolved(
+            vec![CellStatus::Unknown, live.clone()],
+            vec![CellStatus::Dead],
+        );
+        assert_eq!(
+            rtx.check_resolvable(),
+            Err(UnresolvableError::DeadDep(out_point(3)))
+        );
+        let rtx = resolved(
+            vec![CellStatus::Unknown, live.clone()],
+            vec![CellStatus::Unknown],
+        );
+        assert_eq!(
+            rtx.check_resolvable(),
+            Err(UnresolvableError::Unknown {
+                inputs: vec![out_point(1)],
+                deps: vec![out_point(3)],
+            })
+        );
+    }
+
+    #[test]
+    fn resolve_transaction_names_out_points() {
+        let out_point = |index| OutPoint {
+            tx_hash: H256::zero(),
+            index,
+        };
+        let cell_meta = CellMeta {
+            block_number: Some(1),
+            cell_output: Arc::new(CellOutput {
+                capacity: capacity_bytes!(2),
+                data: vec![],
+                lock: Script::default(),
+                type_: None,
+            }),
+            cellbase: false,
+        };
+        let mut db = CellMemoryDb {
+            cells: HashMap::new(),
+        };
+        db.cells.insert(out_point(1), Some(cell_meta));
+        db.cells.insert(out_point(2), None);
+        let spend = |input| {
+            TransactionBuilder::default()
+                .input(CellInput::new(out_point(input), 0, vec![]))
+                .build()
+        };
+
+        let mut seen_inputs = FnvHashSet::default();
+        let tx = spend(2);
+        assert_eq!(
+            resolve_transaction(&tx, &mut seen_inputs, &db).err(),
+            Some(UnresolvableError::DeadInput(out_point(2)))
+        );
+        let tx = spend(3);
+        assert_eq!(
+            resolve_transaction(&tx, &mut seen_inputs, &db).err(),
+            Some(UnresolvableError::Unknown {
+                inputs: vec![out_point(3)],
+                deps: vec![],
+            })
+        );
+        let tx = spend(1);
+        assert!(resolve_transaction(&tx, &mut seen_inputs, &db).is_ok());
+        // spent by an earlier transaction
+        let tx = TransactionBuilder::default().dep(out_point(1)).build();
+        assert_eq!(
+            resolve_transaction(&tx, &mut seen_inputs, &db).err(),
+            Some(UnresolvableError::DeadDep(out_point(1)))
+        );
+    }
 }
@@ -5,7 +5,12 @@ use numext_fixed_uint::U256;
 #[derive(Debug, PartialEq, Eq)]
 pub enum Error {
     InvalidTimestamp(u64, u64),
+    /// Commitments of a block, the value computed from the body then the value in the header
     InvalidTransactionsRoot(H256, H256),
+    InvalidProposalsRoot(H256, H256),
+    InvalidWitnessesRoot(H256, H256),
+    InvalidUnclesHash(H256, H256),
+    InvalidUnclesCount(u32, u32),
     InvalidPublicKey(H512),
     InvalidProof,
     InvalidDifficulty(U256, U256),
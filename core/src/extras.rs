@@ -1,3 +1,5 @@
+use crate::script::Script;
+use crate::{BlockNumber, Capacity};
 use numext_fixed_hash::H256;
 use numext_fixed_uint::U256;
 use serde_derive::{Deserialize, Serialize};
@@ -18,3 +20,29 @@ pub struct TransactionAddress {
     pub offset: usize,
     pub length: usize,
 }
+
+/// A live cell recorded by the lock index, which is keyed by the lock hash and the out point
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+pub struct LockCell {
+    pub capacity: Capacity,
+    pub lock: Script,
+    // Number of the block which created the cell
+    pub block_number: BlockNumber,
+}
+
+/// A switch of the main chain to a fork with more difficulty
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
+pub struct ReorgRecord {
+    // Unix time in milliseconds when the switch happened
+    pub timestamp: u64,
+    // Number of blocks detached from the main chain
+    pub depth: u64,
+    // Number of blocks attached to the main chain
+    pub attached: u64,
+    // Number of the last block shared by the old and new main chain
+    pub fork_number: BlockNumber,
+    pub old_tip_hash: H256,
+    pub new_tip_hash: H256,
+    // Milliseconds spent to switch the main chain
+    pub duration: u64,
+}
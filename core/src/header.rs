@@ -9,6 +9,8 @@ use std::fmt;
 pub use crate::{BlockNumber, Version};
 
 pub const HEADER_VERSION: Version = 0;
+/// The maximum size of the dao field once it is activated.
+pub const MAX_DAO_BYTES: usize = 32;
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
 pub struct Seal {
@@ -61,11 +63,37 @@ pub struct RawHeader {
     uncles_hash: H256,
     /// Number of the uncles
     uncles_count: u32,
+    /// Economic state of the chain, e.g. the accumulated issuance, empty before the
+    /// activation number of the consensus
+    #[serde(with = "serde_bytes")]
+    dao: Vec<u8>,
 }
 
 impl RawHeader {
     pub fn pow_hash(&self) -> H256 {
-        blake2b_256(serialize(self).expect("RawHeader serialize should not fail")).into()
+        blake2b_256(self.hashing_bytes()).into()
+    }
+
+    // Headers without dao are hashed in the layout predating the field, so the
+    // hashes of the existing chains, genesis included, are kept.
+    fn hashing_bytes(&self) -> Vec<u8> {
+        if self.dao.is_empty() {
+            serialize(&(
+                self.version,
+                &self.parent_hash,
+                self.timestamp,
+                self.number,
+                &self.transactions_root,
+                &self.proposals_root,
+                &self.witnesses_root,
+                &self.difficulty,
+                &self.uncles_hash,
+                self.uncles_count,
+            ))
+        } else {
+            serialize(self)
+        }
+        .expect("RawHeader serialize should not fail")
     }
 
     pub fn with_seal(self, seal: Seal) -> Header {
@@ -122,6 +150,10 @@ impl fmt::Debug for Header {
             .field("difficulty", &format_args!("{:#x}", self.raw.difficulty))
             .field("uncles_hash", &format_args!("{:#x}", self.raw.uncles_hash))
             .field("uncles_count", &self.raw.uncles_count)
+            .field(
+                "dao",
+                &format_args!("0x{}", &hex_string(&self.raw.dao).expect("hex dao")),
+            )
             .field("seal", &self.seal)
             .finish()
     }
@@ -157,7 +189,9 @@ impl Header {
     }
 
     pub fn hash(&self) -> H256 {
-        blake2b_256(serialize(&self).expect("Header serialize should not fail")).into()
+        let mut bytes = self.raw.hashing_bytes();
+        bytes.extend(serialize(&self.seal).expect("Header serialize should not fail"));
+        blake2b_256(bytes).into()
     }
 
     pub fn pow_hash(&self) -> H256 {
@@ -199,6 +233,10 @@ impl Header {
     pub fn uncles_count(&self) -> u32 {
         self.raw.uncles_count
     }
+
+    pub fn dao(&self) -> &[u8] {
+        &self.raw.dao
+    }
 }
 
 impl PartialEq for Header {
@@ -289,6 +327,11 @@ impl HeaderBuilder {
         self
     }
 
+    pub fn dao(mut self, dao: Vec<u8>) -> Self {
+        self.inner.raw.dao = dao;
+        self
+    }
+
     pub fn build(self) -> Header {
         self.inner
     }
@@ -0,0 +1,298 @@
+//! Transactions whose inputs are selected from candidate cells checked against a
+//! `CellProvider`, the capacity left over goes to a change output.
+
+use crate::cell::CellProvider;
+use crate::script::Script;
+use crate::transaction::{CellInput, CellOutput, OutPoint, Transaction, TransactionBuilder};
+use crate::{Capacity, Version};
+use fnv::FnvHashSet;
+
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum InputSelectionError {
+    /// Overflow when summing up capacities
+    CapacityOverflow,
+    /// The live candidates have `available` while the outputs and the fee need `required`
+    InsufficientCapacity {
+        available: Capacity,
+        required: Capacity,
+    },
+}
+
+impl From<occupied_capacity::Error> for InputSelectionError {
+    fn from(error: occupied_capacity::Error) -> Self {
+        match error {
+            occupied_capacity::Error::Overflow => InputSelectionError::CapacityOverflow,
+        }
+    }
+}
+
+/// Created by `TransactionBuilder::from_provider`.
+///
+/// The candidates are tried in order, the ones which are not live or already selected are
+/// skipped. Cells are selected until they cover the outputs and the fee, and either nothing is
+/// left or the rest is enough for the change cell. When the candidates run out, a rest too
+/// small for the change cell is paid as fee.
+pub struct ProviderTransactionBuilder<'a, CP: ?Sized> {
+    provider: &'a CP,
+    inner: TransactionBuilder,
+    candidates: Vec<OutPoint>,
+    outputs: Vec<CellOutput>,
+    fee: Capacity,
+    change_lock: Option<Script>,
+}
+
+impl<'a, CP: CellProvider + ?Sized> ProviderTransactionBuilder<'a, CP> {
+    pub fn new(provider: &'a CP) -> Self {
+        ProviderTransactionBuilder {
+            provider,
+            inner: TransactionBuilder::default(),
+            candidates: Vec::new(),
+            outputs: Vec::new(),
+            fee: Capacity::zero(),
+            change_lock: None,
+        }
+    }
+
+    pub fn version(mut self, version: Version) -> Self {
+        self.inner = self.inner.version(version);
+        self
+    }
+
+    pub fn dep(mut self, dep: OutPoint) -> Self {
+        self.inner = self.inner.dep(dep);
+        self
+    }
+
+    pub fn deps(mut self, deps: Vec<OutPoint>) -> Self {
+        self.inner = self.inner.deps(deps);
+        self
+    }
+
+    pub fn candidate(mut self, out_point: OutPoint) -> Self {
+        self.candidates.push(out_point);
+        self
+    }
+
+    pub fn candidates(mut self, out_points: Vec<OutPoint>) -> Self {
+        self.candidates.extend(out_points);
+        self
+    }
+
+    pub fn output(mut self, output: CellOutput) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
+    pub fn outputs(mut self, outputs: Vec<CellOutput>) -> Self {
+        self.outputs.extend(outputs);
+        self
+    }
+
+    pub fn fee(mut self, fee: Capacity) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    /// Lock of the change cell, the lock of the first selected cell by default
+    pub fn change_lock(mut self, lock: Script) -> Self {
+        self.change_lock = Some(lock);
+        self
+    }
+
+    /// Select the inputs and build the transaction, which has an empty witness for each input
+    pub fn build(self) -> Result<Transaction, InputSelectionError> {
+        let ProviderTransactionBuilder {
+            provider,
+            inner,
+            candidates,
+            outputs,
+            fee,
+            mut change_lock,
+        } = self;
+        let required = outputs
+            .iter()
+            .map(|output| output.capacity)
+            .try_fold(fee, Capacity::safe_add)?;
+
+        let mut inputs = Vec::new();
+        let mut available = Capacity::zero();
+        let mut change = None;
+        let mut selected = FnvHashSet::default();
+        for out_point in candidates {
+            if selected.contains(&out_point) {
+                continue;
+            }
+            let cell = match provider.cell(&out_point).take_live_output() {
+                Some(cell) => cell,
+                None => continue,
+            };
+            available = available.safe_add(cell.capacity())?;
+            let lock = change_lock
+                .get_or_insert_with(|| cell.cell_output.lock.clone())
+                .clone();
+            selected.insert(out_point.clone());
+            inputs.push(CellInput::new(out_point, 0, Vec::new()));
+            if available < required {
+                continue;
+            }
+            let rest = available.safe_sub(required)?;
+            if rest == Capacity::zero() {
+                break;
+            }
+            let output = CellOutput::new(rest, Vec::new(), lock, None);
+            if output.occupied_capacity()? <= rest {
+                change = Some(output);
+                break;
+            }
+        }
+        if available < required {
+            return Err(InputSelectionError::InsufficientCapacity {
+                available,
+                required,
+            });
+        }
+
+        let witnesses = vec![Vec::new(); inputs.len()];
+        Ok(inner
+            .inputs(inputs)
+            .outputs(outputs)
+            .outputs(change.into_iter().collect())
+            .witnesses(witnesses)
+            .build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capacity_bytes;
+    use crate::cell::{CellMeta, CellStatus, LiveCell};
+    use numext_fixed_hash::{h256, H256};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    struct CellMemoryDb {
+        cells: HashMap<OutPoint, Option<CellMeta>>,
+    }
+
+    impl CellProvider for CellMemoryDb {
+        fn cell(&self, o: &OutPoint) -> CellStatus {
+            match self.cells.get(o) {
+                Some(&Some(ref cell_meta)) => CellStatus::Live(LiveCell::Output(cell_meta.clone())),
+                Some(&None) => CellStatus::Dead,
+                None => CellStatus::Unknown,
+            }
+        }
+    }
+
+    fn lock() -> Script {
+        Script::new(Vec::new(), h256!("0x1"))
+    }
+
+    fn out_point(index: u32) -> OutPoint {
+        OutPoint::new(H256::zero(), index)
+    }
+
+    fn build_db(capacities: &[Option<Capacity>]) -> CellMemoryDb {
+        let cells = capacities
+            .iter()
+            .enumerate()
+            .map(|(index, capacity)| {
+                let meta = capacity.map(|capacity| CellMeta {
+                    cell_output: Arc::new(CellOutput::new(capacity, Vec::new(), lock(), None)),
+                    block_number: Some(1),
+                    cellbase: false,
+                });
+                (out_point(index as u32), meta)
+            })
+            .collect();
+        CellMemoryDb { cells }
+    }
+
+    fn payment() -> CellOutput {
+        CellOutput::new(capacity_bytes!(100), Vec::new(), Script::default(), None)
+    }
+
+    #[test]
+    fn select_inputs_with_change() {
+        let db = build_db(&[
+            Some(capacity_bytes!(60)),
+            None,
+            Some(capacity_bytes!(60)),
+            Some(capacity_bytes!(60)),
+        ]);
+        let tx = TransactionBuilder::from_provider(&db)
+            .candidates((0..5).map(out_point).collect())
+            .output(payment())
+            .fee(capacity_bytes!(1))
+            .build()
+            .unwrap();
+
+        // the rest of 19 is too small for the change cell
+        assert_eq!(
+            tx.input_pts(),
+            vec![out_point(0), out_point(2), out_point(3)]
+        );
+        assert_eq!(tx.witnesses().len(), 3);
+        assert_eq!(tx.outputs().len(), 2);
+        assert_eq!(tx.outputs()[1].capacity, capacity_bytes!(79));
+        assert_eq!(tx.outputs()[1].lock, lock());
+        assert_eq!(tx.check_capacity(capacity_bytes!(180)), Ok(()));
+    }
+
+    #[test]
+    fn pay_small_rest_as_fee() {
+        let db = build_db(&[Some(capacity_bytes!(60)), Some(capacity_bytes!(60))]);
+        let tx = TransactionBuilder::from_provider(&db)
+            .candidates(vec![out_point(0), out_point(1)])
+            .output(payment())
+            .fee(capacity_bytes!(1))
+            .build()
+            .unwrap();
+        assert_eq!(tx.inputs().len(), 2);
+        assert_eq!(tx.outputs(), &[payment()][..]);
+    }
+
+    #[test]
+    fn skip_duplicate_candidates() {
+        let db = build_db(&[Some(capacity_bytes!(60)), Some(capacity_bytes!(60))]);
+        let tx = TransactionBuilder::from_provider(&db)
+            .candidate(out_point(0))
+            .candidates(vec![out_point(0), out_point(1), out_point(0)])
+            .output(payment())
+            .fee(capacity_bytes!(1))
+            .build()
+            .unwrap();
+        assert_eq!(tx.input_pts(), vec![out_point(0), out_point(1)]);
+        assert_eq!(tx.witnesses().len(), 2);
+
+        // a cell counts once
+        let ret = TransactionBuilder::from_provider(&db)
+            .candidates(vec![out_point(0), out_point(0)])
+            .output(payment())
+            .build();
+        assert_eq!(
+            ret,
+            Err(InputSelectionError::InsufficientCapacity {
+                available: capacity_bytes!(60),
+                required: capacity_bytes!(100),
+            })
+        );
+    }
+
+    #[test]
+    fn insufficient_capacity() {
+        let db = build_db(&[Some(capacity_bytes!(60)), None]);
+        let ret = TransactionBuilder::from_provider(&db)
+            .candidates(vec![out_point(0), out_point(1)])
+            .output(payment())
+            .build();
+        assert_eq!(
+            ret,
+            Err(InputSelectionError::InsufficientCapacity {
+                available: capacity_bytes!(60),
+                required: capacity_bytes!(100),
+            })
+        );
+    }
+}
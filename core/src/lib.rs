@@ -8,10 +8,13 @@ pub mod difficulty;
 pub mod error;
 pub mod extras;
 pub mod header;
+pub mod input_selection;
 pub mod script;
+pub mod script_cache;
 pub mod service;
 pub mod transaction;
 pub mod transaction_meta;
+pub mod transaction_proof;
 pub mod uncle;
 
 pub use crate::error::Error;
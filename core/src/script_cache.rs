@@ -0,0 +1,105 @@
+use crate::transaction::{CellOutput, OutPoint};
+use ckb_util::Mutex;
+use lru_cache::LruCache;
+use numext_fixed_hash::H256;
+use std::sync::Arc;
+
+/// Cells holding the script binaries and their data hashes, which are the `code_hash` of the
+/// scripts they hold.
+///
+/// The cell at an out point never changes. The `CellMeta`s of the transactions depending on a
+/// popular script share the cached cell instead of loading another copy of the binary, and the
+/// script verifications look up its hash instead of hashing the binary again.
+#[derive(Debug)]
+pub struct ScriptHashCache {
+    cells: Mutex<LruCache<OutPoint, (Arc<CellOutput>, H256)>>,
+}
+
+impl ScriptHashCache {
+    pub fn new(capacity: usize) -> Self {
+        ScriptHashCache {
+            cells: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// The cell at `out_point`, loaded by `load` if it isn't cached. Only the cells holding
+    /// data are kept, the others can't be the deps of a script.
+    pub fn cell<F>(&self, out_point: &OutPoint, load: F) -> Option<Arc<CellOutput>>
+    where
+        F: FnOnce() -> Option<CellOutput>,
+    {
+        if let Some((cell, _)) = self.cells.lock().get_mut(out_point) {
+            return Some(Arc::clone(cell));
+        }
+        let cell = Arc::new(load()?);
+        if !cell.data.is_empty() {
+            let data_hash = cell.data_hash();
+            self.cells
+                .lock()
+                .insert(out_point.clone(), (Arc::clone(&cell), data_hash));
+        }
+        Some(cell)
+    }
+
+    /// The data hash of `cell`, which is the cell at `out_point`
+    pub fn data_hash(&self, out_point: &OutPoint, cell: &CellOutput) -> H256 {
+        let cached = self
+            .cells
+            .lock()
+            .get_mut(out_point)
+            .map(|(_, data_hash)| data_hash.clone());
+        match cached {
+            Some(data_hash) => data_hash,
+            None => {
+                let data_hash = cell.data_hash();
+                self.cells.lock().insert(
+                    out_point.clone(),
+                    (Arc::new(cell.clone()), data_hash.clone()),
+                );
+                data_hash
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::Script;
+    use crate::Capacity;
+
+    #[test]
+    fn cache_data_hash_by_out_point() {
+        let cache = ScriptHashCache::new(2);
+        let out_point = OutPoint::new(H256::zero(), 0);
+        let cell = CellOutput::new(Capacity::zero(), vec![1, 2, 3], Script::default(), None);
+        assert_eq!(cache.data_hash(&out_point, &cell), cell.data_hash());
+
+        // the cell at an out point never changes, the data isn't hashed again
+        let other_cell = CellOutput::new(Capacity::zero(), vec![4], Script::default(), None);
+        assert_eq!(cache.data_hash(&out_point, &other_cell), cell.data_hash());
+        assert_eq!(
+            cache.data_hash(&OutPoint::new(H256::zero(), 1), &other_cell),
+            other_cell.data_hash()
+        );
+    }
+
+    #[test]
+    fn share_cells_holding_data() {
+        let cache = ScriptHashCache::new(2);
+        let out_point = OutPoint::new(H256::zero(), 0);
+        let cell = CellOutput::new(Capacity::zero(), vec![1, 2, 3], Script::default(), None);
+        let first = cache.cell(&out_point, || Some(cell.clone())).unwrap();
+        let second = cache.cell(&out_point, || panic!("loaded again")).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(cache.data_hash(&out_point, &cell), cell.data_hash());
+
+        // the cells without data are loaded every time
+        let empty_out_point = OutPoint::new(H256::zero(), 1);
+        let empty_cell = CellOutput::new(Capacity::zero(), vec![], Script::default(), None);
+        let first = cache.cell(&empty_out_point, || Some(empty_cell.clone()));
+        let second = cache.cell(&empty_out_point, || Some(empty_cell.clone()));
+        assert!(!Arc::ptr_eq(&first.unwrap(), &second.unwrap()));
+        assert_eq!(cache.cell(&OutPoint::new(H256::zero(), 2), || None), None);
+    }
+}
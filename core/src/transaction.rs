@@ -1,9 +1,11 @@
 //! Transaction using Cell.
 //! It is similar to Bitcoin Tx <https://en.bitcoin.it/wiki/Protocol_documentation#tx/>
+use crate::cell::CellProvider;
+use crate::input_selection::ProviderTransactionBuilder;
 use crate::script::Script;
 pub use crate::Capacity;
 use crate::{BlockNumber, Version};
-use bincode::{deserialize, serialize};
+use bincode::{deserialize, serialize, serialized_size};
 use faster_hex::hex_string;
 use hash::blake2b_256;
 use numext_fixed_hash::H256;
@@ -136,6 +138,11 @@ impl CellOutput {
         blake2b_256(&self.data).into()
     }
 
+    /// Capacity occupied by the cell itself, its declared capacity must cover it
+    pub fn occupied_capacity(&self) -> ::occupied_capacity::Result<Capacity> {
+        ::occupied_capacity::OccupiedCapacity::occupied_capacity(self)
+    }
+
     pub fn destruct(self) -> (Capacity, Vec<u8>, Script, Option<Script>) {
         let CellOutput {
             capacity,
@@ -149,6 +156,16 @@ impl CellOutput {
 
 pub type Witness = Vec<Vec<u8>>;
 
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum CapacityError {
+    /// Overflow when summing up the outputs capacity
+    Overflow,
+    /// The outputs capacity exceeds the inputs capacity
+    OutputsSumOverflow { inputs: Capacity, outputs: Capacity },
+    /// The capacity of the output at `index` doesn't cover its occupied capacity
+    InsufficientCellCapacity { index: usize, capacity: Capacity },
+}
+
 #[derive(Clone, Serialize, Deserialize, Eq, Debug, Default, HasOccupiedCapacity)]
 pub struct Transaction {
     version: Version,
@@ -218,6 +235,11 @@ impl Transaction {
         blake2b_256(serialize(&self).expect("Transaction serialize should not fail")).into()
     }
 
+    /// Serialized size of the transaction, witnesses included
+    pub fn serialized_size(&self) -> usize {
+        serialized_size(&self).expect("Transaction serialize should not fail") as usize
+    }
+
     pub fn out_points_iter(&self) -> impl Iterator<Item = &OutPoint> {
         self.deps.iter().chain(
             self.inputs
@@ -263,6 +285,43 @@ impl Transaction {
             .map(|output| output.capacity)
             .try_fold(Capacity::zero(), Capacity::safe_add)
     }
+
+    /// Check that the outputs don't exceed `inputs_capacity` and that each output covers its
+    /// occupied capacity. The error about the outputs sum comes first, then the errors of the
+    /// outputs by index.
+    pub fn check_capacity(&self, inputs_capacity: Capacity) -> Result<(), Vec<CapacityError>> {
+        let mut errors = Vec::new();
+        match self.outputs_capacity() {
+            Ok(outputs_capacity) if outputs_capacity > inputs_capacity => {
+                errors.push(CapacityError::OutputsSumOverflow {
+                    inputs: inputs_capacity,
+                    outputs: outputs_capacity,
+                })
+            }
+            Ok(_) => {}
+            Err(_) => errors.push(CapacityError::Overflow),
+        }
+        errors.extend(
+            self.outputs
+                .iter()
+                .enumerate()
+                .filter(|(_, output)| {
+                    output
+                        .occupied_capacity()
+                        .map(|occupied| occupied > output.capacity)
+                        .unwrap_or(true)
+                })
+                .map(|(index, output)| CapacityError::InsufficientCellCapacity {
+                    index,
+                    capacity: output.capacity,
+                }),
+        );
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
 }
 
 #[derive(Default)]
@@ -271,6 +330,14 @@ pub struct TransactionBuilder {
 }
 
 impl TransactionBuilder {
+    /// Select the inputs from candidate cells checked against `provider`, see
+    /// `ProviderTransactionBuilder`
+    pub fn from_provider<CP: CellProvider + ?Sized>(
+        provider: &CP,
+    ) -> ProviderTransactionBuilder<CP> {
+        ProviderTransactionBuilder::new(provider)
+    }
+
     pub fn new(bytes: &[u8]) -> Self {
         TransactionBuilder {
             inner: deserialize(bytes).expect("transaction deserializing should be ok"),
@@ -447,4 +514,50 @@ mod test {
             "4bb6ed9e544f5609749cfaa91f315adc7facecbe18b0d507330ed070fb2a4247"
         );
     }
+
+    #[test]
+    fn test_check_capacity() {
+        let output = CellOutput::new(capacity_bytes!(60), vec![1; 10], Script::default(), None);
+        let occupied = output.occupied_capacity().unwrap();
+        let tx = TransactionBuilder::default()
+            .output(output.clone())
+            .output(CellOutput::new(
+                occupied,
+                vec![1; 11],
+                Script::default(),
+                None,
+            ))
+            .output(CellOutput::new(
+                occupied,
+                vec![1; 10],
+                Script::default(),
+                None,
+            ))
+            .build();
+        let outputs_capacity = tx.outputs_capacity().unwrap();
+
+        assert_eq!(
+            tx.check_capacity(outputs_capacity),
+            Err(vec![CapacityError::InsufficientCellCapacity {
+                index: 1,
+                capacity: occupied,
+            }])
+        );
+        assert_eq!(
+            tx.check_capacity(capacity_bytes!(60)),
+            Err(vec![
+                CapacityError::OutputsSumOverflow {
+                    inputs: capacity_bytes!(60),
+                    outputs: outputs_capacity,
+                },
+                CapacityError::InsufficientCellCapacity {
+                    index: 1,
+                    capacity: occupied,
+                },
+            ])
+        );
+
+        let tx = TransactionBuilder::default().output(output).build();
+        assert_eq!(tx.check_capacity(capacity_bytes!(60)), Ok(()));
+    }
 }
@@ -6,11 +6,16 @@ use serde_derive::{Deserialize, Serialize};
 struct BitVecSerde {
     #[serde(getter = "BitVec::to_bytes")]
     bits: Vec<u8>,
+    /// the bytes are padded, the length is kept to drop the padding bits
+    #[serde(getter = "BitVec::len")]
+    len: usize,
 }
 
 impl From<BitVecSerde> for BitVec {
     fn from(bv: BitVecSerde) -> BitVec {
-        BitVec::from_bytes(&bv.bits)
+        let mut bits = BitVec::from_bytes(&bv.bits);
+        bits.truncate(bv.len);
+        bits
     }
 }
 
@@ -59,8 +64,9 @@ impl TransactionMeta {
         self.dead_cell.none()
     }
 
+    /// Returns true if all the outputs are dead, the cellbase bit is not an output
     pub fn is_all_dead(&self) -> bool {
-        self.dead_cell.all()
+        self.dead_cell.iter().skip(1).all(|dead| dead)
     }
 
     pub fn is_dead(&self, index: usize) -> bool {
@@ -94,5 +100,20 @@ mod tests {
         assert!(decoded.is_dead(1));
         assert!(!decoded.is_dead(2));
         assert!(decoded.is_dead(3));
+        assert_eq!(decoded, original);
+    }
+
+    #[test]
+    fn transaction_meta_all_dead() {
+        let mut meta = TransactionMeta::new(0, 2);
+        meta.set_dead(0);
+        assert!(!meta.is_all_dead());
+        meta.set_dead(1);
+        assert!(meta.is_all_dead());
+
+        let mut cellbase = TransactionMeta::new_cellbase(0, 1);
+        assert!(!cellbase.is_all_dead());
+        cellbase.set_dead(0);
+        assert!(cellbase.is_all_dead());
     }
 }
@@ -0,0 +1,100 @@
+//! Merkle inclusion proofs of transactions against the `transactions_root` of their block,
+//! which let a client holding only the headers check that a transaction is committed.
+
+use crate::block::Block;
+use crate::header::Header;
+use crate::transaction::Transaction;
+use ckb_merkle_tree::{build_merkle_proof, MerkleProof};
+use numext_fixed_hash::H256;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct TransactionProof {
+    /// Hash of the block the transaction is committed in
+    pub block_hash: H256,
+    /// Index of the transaction leaf in the merkle tree
+    pub indices: Vec<u32>,
+    /// Hashes of the siblings on the path from the leaf to the root
+    pub lemmas: Vec<H256>,
+}
+
+impl TransactionProof {
+    /// Build the proof of the transaction `tx_hash` of `block`, `None` if the block doesn't
+    /// have it
+    pub fn new(block: &Block, tx_hash: &H256) -> Option<Self> {
+        let tx_hashes: Vec<H256> = block.transactions().iter().map(Transaction::hash).collect();
+        let index = tx_hashes.iter().position(|hash| hash == tx_hash)?;
+        build_merkle_proof(&tx_hashes, &[index]).map(|proof| TransactionProof {
+            block_hash: block.header().hash(),
+            indices: proof.indices().to_vec(),
+            lemmas: proof.lemmas().to_vec(),
+        })
+    }
+
+    /// Check that the transaction `tx_hash` is committed in the block of `header`
+    pub fn verify(&self, header: &Header, tx_hash: &H256) -> bool {
+        if header.hash() != self.block_hash || self.indices.len() != 1 {
+            return false;
+        }
+        let proof = MerkleProof::new(self.indices.clone(), self.lemmas.clone());
+        proof.root(&[tx_hash.to_owned()]).as_ref() == Some(header.transactions_root())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockBuilder;
+    use crate::header::HeaderBuilder;
+    use crate::transaction::TransactionBuilder;
+    use bincode::{deserialize, serialize};
+
+    fn build_block(txs_count: u32) -> Block {
+        BlockBuilder::default()
+            .transactions(
+                (0..txs_count)
+                    .map(|version| TransactionBuilder::default().version(version).build())
+                    .collect(),
+            )
+            .with_header_builder(HeaderBuilder::default())
+    }
+
+    #[test]
+    fn verify_transaction_proof() {
+        for txs_count in 1..8 {
+            let block = build_block(txs_count);
+            for tx in block.transactions() {
+                let proof = TransactionProof::new(&block, &tx.hash()).expect("tx in block");
+                assert!(proof.verify(block.header(), &tx.hash()));
+            }
+        }
+    }
+
+    #[test]
+    fn reject_invalid_transaction_proof() {
+        let block = build_block(5);
+        let other_block = build_block(6);
+        let tx_hash = block.transactions()[3].hash();
+        let other_tx_hash = block.transactions()[2].hash();
+        let proof = TransactionProof::new(&block, &tx_hash).expect("tx in block");
+
+        assert!(!proof.verify(block.header(), &other_tx_hash));
+        assert!(!proof.verify(other_block.header(), &tx_hash));
+        assert_eq!(
+            TransactionProof::new(&block, &other_block.transactions()[5].hash()),
+            None
+        );
+
+        let mut tampered = proof.clone();
+        tampered.lemmas[0] = H256::zero();
+        assert!(!tampered.verify(block.header(), &tx_hash));
+    }
+
+    #[test]
+    fn serialize_transaction_proof() {
+        let block = build_block(3);
+        let proof = TransactionProof::new(&block, &block.transactions()[1].hash()).unwrap();
+        let decoded: TransactionProof = deserialize(&serialize(&proof).unwrap()).unwrap();
+        assert_eq!(decoded, proof);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::config::DBBackend;
+use crate::memorydb::{MemoryDbBatch, MemoryKeyValueDB};
+use crate::rocksdb::{RocksDB, RocksdbBatch};
+use crate::{Col, DBConfig, DbBatch, KeyValueDB, Result};
+use std::ops::Range;
+
+/// The database selected by `DBConfig::backend`
+pub enum BackendDB {
+    RocksDB(RocksDB),
+    Memory(MemoryKeyValueDB),
+}
+
+impl BackendDB {
+    pub fn open(config: &DBConfig, columns: u32) -> Self {
+        match config.backend {
+            DBBackend::RocksDB => BackendDB::RocksDB(RocksDB::open(config, columns)),
+            DBBackend::Memory => BackendDB::Memory(MemoryKeyValueDB::open(columns as usize)),
+        }
+    }
+}
+
+impl KeyValueDB for BackendDB {
+    type Batch = BackendBatch;
+
+    fn read(&self, col: Col, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self {
+            BackendDB::RocksDB(db) => db.read(col, key),
+            BackendDB::Memory(db) => db.read(col, key),
+        }
+    }
+
+    fn partial_read(&self, col: Col, key: &[u8], range: &Range<usize>) -> Result<Option<Vec<u8>>> {
+        match self {
+            BackendDB::RocksDB(db) => db.partial_read(col, key, range),
+            BackendDB::Memory(db) => db.partial_read(col, key, range),
+        }
+    }
+
+    fn traverse<F>(&self, col: Col, callback: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>,
+    {
+        match self {
+            BackendDB::RocksDB(db) => db.traverse(col, callback),
+            BackendDB::Memory(db) => db.traverse(col, callback),
+        }
+    }
+
+    fn traverse_prefix<F>(&self, col: Col, prefix: &[u8], callback: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>,
+    {
+        match self {
+            BackendDB::RocksDB(db) => db.traverse_prefix(col, prefix, callback),
+            BackendDB::Memory(db) => db.traverse_prefix(col, prefix, callback),
+        }
+    }
+
+    fn batch(&self) -> Result<Self::Batch> {
+        match self {
+            BackendDB::RocksDB(db) => db.batch().map(BackendBatch::RocksDB),
+            BackendDB::Memory(db) => db.batch().map(BackendBatch::Memory),
+        }
+    }
+}
+
+pub enum BackendBatch {
+    RocksDB(RocksdbBatch),
+    Memory(MemoryDbBatch),
+}
+
+impl DbBatch for BackendBatch {
+    fn insert(&mut self, col: Col, key: &[u8], value: &[u8]) -> Result<()> {
+        match self {
+            BackendBatch::RocksDB(batch) => batch.insert(col, key, value),
+            BackendBatch::Memory(batch) => batch.insert(col, key, value),
+        }
+    }
+
+    fn delete(&mut self, col: Col, key: &[u8]) -> Result<()> {
+        match self {
+            BackendBatch::RocksDB(batch) => batch.delete(col, key),
+            BackendBatch::Memory(batch) => batch.delete(col, key),
+        }
+    }
+
+    fn commit(self) -> Result<()> {
+        match self {
+            BackendBatch::RocksDB(batch) => batch.commit(),
+            BackendBatch::Memory(batch) => batch.commit(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DBBackend;
+
+    #[test]
+    fn open_memory_backend() {
+        let config = DBConfig {
+            backend: DBBackend::Memory,
+            ..Default::default()
+        };
+        let db = BackendDB::open(&config, 2);
+        let mut batch = db.batch().unwrap();
+        batch.insert(1, &[1], &[1, 2]).unwrap();
+        batch.commit().unwrap();
+        assert_eq!(db.read(1, &[1]).unwrap(), Some(vec![1, 2]));
+        assert_eq!(db.read(0, &[1]).unwrap(), None);
+        assert!(config.path.as_os_str().is_empty());
+    }
+}
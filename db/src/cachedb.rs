@@ -57,6 +57,20 @@ where
         self.db.partial_read(col, key, range)
     }
 
+    fn traverse<F>(&self, col: Col, callback: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>,
+    {
+        self.db.traverse(col, callback)
+    }
+
+    fn traverse_prefix<F>(&self, col: Col, prefix: &[u8], callback: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>,
+    {
+        self.db.traverse_prefix(col, prefix, callback)
+    }
+
     fn batch(&self) -> Result<Self::Batch> {
         self.db.batch()
     }
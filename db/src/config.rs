@@ -1,10 +1,44 @@
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
 
-#[derive(Clone, Debug, Deserialize, Default)]
+// The tables must come last to be serializable by toml, keep `options` at the tail.
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct DBConfig {
     #[serde(default)]
     pub path: PathBuf,
+    #[serde(default)]
+    pub backend: DBBackend,
+    /// Size in bytes of the rocksdb LRU cache of uncompressed blocks, each column family has
+    /// its own
+    pub block_cache_size: Option<usize>,
+    /// Size in bytes of the rocksdb memtable before it is flushed to disk
+    pub write_buffer_size: Option<usize>,
+    /// Number of files rocksdb keeps open, -1 keeps all of them open
+    pub max_open_files: Option<i32>,
+    pub compaction_style: Option<CompactionStyle>,
+    /// Other rocksdb options, set after the database is opened
     pub options: Option<HashMap<String, String>>,
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DBBackend {
+    RocksDB,
+    /// Everything is kept in memory and lost on exit, for tests and ephemeral dev chains
+    Memory,
+}
+
+impl Default for DBBackend {
+    fn default() -> Self {
+        DBBackend::RocksDB
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompactionStyle {
+    Level,
+    Universal,
+    Fifo,
+}
@@ -7,13 +7,15 @@ use failure::Fail;
 use std::ops::Range;
 use std::result;
 
+pub mod backend;
 pub mod cachedb;
 pub mod config;
 pub mod memorydb;
 pub mod rocksdb;
 
+pub use crate::backend::BackendDB;
 pub use crate::cachedb::CacheDB;
-pub use crate::config::DBConfig;
+pub use crate::config::{CompactionStyle, DBBackend, DBConfig};
 pub use crate::memorydb::MemoryKeyValueDB;
 pub use crate::rocksdb::RocksDB;
 
@@ -30,6 +32,15 @@ pub trait KeyValueDB: Sync + Send {
     type Batch: DbBatch;
     fn read(&self, col: Col, key: &[u8]) -> Result<Option<Vec<u8>>>;
     fn partial_read(&self, col: Col, key: &[u8], range: &Range<usize>) -> Result<Option<Vec<u8>>>;
+    /// Call `callback` with every key and value of `col`, stop at the first error
+    fn traverse<F>(&self, col: Col, callback: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>;
+    /// Call `callback` with the keys starting with `prefix` and their values in ascending
+    /// order of the keys, stop at the first error
+    fn traverse_prefix<F>(&self, col: Col, prefix: &[u8], callback: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>;
     fn batch(&self) -> Result<Self::Batch>;
 }
 
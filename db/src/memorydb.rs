@@ -50,6 +50,39 @@ impl KeyValueDB for MemoryKeyValueDB {
         }
     }
 
+    fn traverse<F>(&self, col: Col, mut callback: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>,
+    {
+        let db = self.db.read();
+
+        match db.get(&col) {
+            None => Err(Error::DBError(format!("column {} not found ", col))),
+            Some(map) => map.iter().try_for_each(|(key, value)| callback(key, value)),
+        }
+    }
+
+    fn traverse_prefix<F>(&self, col: Col, prefix: &[u8], mut callback: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>,
+    {
+        let db = self.db.read();
+
+        match db.get(&col) {
+            None => Err(Error::DBError(format!("column {} not found ", col))),
+            Some(map) => {
+                let mut entries: Vec<_> = map
+                    .iter()
+                    .filter(|(key, _)| key.starts_with(prefix))
+                    .collect();
+                entries.sort();
+                entries
+                    .into_iter()
+                    .try_for_each(|(key, value)| callback(key, value))
+            }
+        }
+    }
+
     fn batch(&self) -> Result<Self::Batch> {
         Ok(Self::Batch {
             operations: Vec::new(),
@@ -153,4 +186,44 @@ mod tests {
             db.partial_read(0, &[0, 0], &(1..4)).unwrap()
         );
     }
+
+    #[test]
+    fn write_and_traverse() {
+        let db = MemoryKeyValueDB::open(2);
+        let mut batch = db.batch().unwrap();
+        batch.insert(0, &[0, 0], &[0, 0, 0]).unwrap();
+        batch.insert(1, &[1, 1], &[1, 1, 1]).unwrap();
+        batch.insert(1, &[2, 2], &[2, 2, 2]).unwrap();
+        batch.commit().unwrap();
+
+        let mut entries = Vec::new();
+        db.traverse(1, |key, value| {
+            entries.push((key.to_vec(), value.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![(vec![1, 1], vec![1, 1, 1]), (vec![2, 2], vec![2, 2, 2])]
+        );
+    }
+
+    #[test]
+    fn write_and_traverse_prefix() {
+        let db = MemoryKeyValueDB::open(1);
+        let mut batch = db.batch().unwrap();
+        batch.insert(0, &[1, 2], &[0]).unwrap();
+        batch.insert(0, &[1, 1], &[1]).unwrap();
+        batch.insert(0, &[2, 1], &[2]).unwrap();
+        batch.commit().unwrap();
+
+        let mut keys = Vec::new();
+        db.traverse_prefix(0, &[1], |key, _| {
+            keys.push(key.to_vec());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(keys, vec![vec![1, 1], vec![1, 2]]);
+    }
 }
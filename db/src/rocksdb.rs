@@ -1,6 +1,10 @@
+use crate::config::CompactionStyle;
 use crate::{Col, DBConfig, DbBatch, Error, KeyValueDB, Result};
 use log::{info, warn};
-use rocksdb::{ColumnFamily, Error as RdbError, Options, WriteBatch, DB};
+use rocksdb::{
+    BlockBasedOptions, ColumnFamily, ColumnFamilyDescriptor, DBCompactionStyle, Direction,
+    Error as RdbError, IteratorMode, Options, WriteBatch, DB,
+};
 use std::ops::Range;
 use std::sync::Arc;
 
@@ -9,7 +13,7 @@ use std::sync::Arc;
 //      - If the data can be migrated manually: update "x.y1.z" to "x.y2.0".
 //      - If the data can not be migrated: update "x1.y.z" to "x2.0.0".
 pub(crate) const VERSION_KEY: &str = "db-version";
-pub(crate) const VERSION_VALUE: &str = "0.1.0";
+pub(crate) const VERSION_VALUE: &str = "0.2.0";
 
 pub struct RocksDB {
     inner: Arc<DB>,
@@ -22,23 +26,28 @@ impl RocksDB {
         ver_key: &str,
         ver_val: &str,
     ) -> Result<Self> {
-        let mut opts = Options::default();
+        let mut opts = tuned_options(config);
         opts.create_if_missing(false);
         opts.create_missing_column_families(true);
 
         let cfnames: Vec<_> = (0..columns).map(|c| c.to_string()).collect();
-        let cf_options: Vec<&str> = cfnames.iter().map(|n| n as &str).collect();
+        let cf_descriptors = || {
+            cfnames
+                .iter()
+                .map(|name| ColumnFamilyDescriptor::new(name.as_str(), tuned_options(config)))
+                .collect::<Vec<_>>()
+        };
 
-        let db = DB::open_cf(&opts, &config.path, &cf_options).or_else(|err| {
+        let db = DB::open_cf_descriptors(&opts, &config.path, cf_descriptors()).or_else(|err| {
             let err_str = err.as_ref();
             if err_str.starts_with("Invalid argument:")
                 && err_str.ends_with("does not exist (create_if_missing is false)")
             {
                 info!("Initialize a new database");
                 opts.create_if_missing(true);
-                let db = DB::open_cf(&opts, &config.path, &cf_options).map_err(|err| {
-                    Error::DBError(format!("failed to open a new created database: {}", err))
-                })?;
+                let db = DB::open_cf_descriptors(&opts, &config.path, cf_descriptors()).map_err(
+                    |err| Error::DBError(format!("failed to open a new created database: {}", err)),
+                )?;
                 db.put(ver_key, ver_val).map_err(|err| {
                     Error::DBError(format!("failed to initiate the database: {}", err))
                 })?;
@@ -52,7 +61,7 @@ impl RocksDB {
                     Error::DBError(format!("failed to repair the database: {}", err))
                 })?;
                 warn!("Opening the repaired rocksdb ...");
-                DB::open_cf(&opts, &config.path, &cf_options).map_err(|err| {
+                DB::open_cf_descriptors(&opts, &config.path, cf_descriptors()).map_err(|err| {
                     Error::DBError(format!("failed to open the repaired database: {}", err))
                 })
             } else {
@@ -115,6 +124,30 @@ impl RocksDB {
     }
 }
 
+// The options of the database and of each column family, tuned by the config
+fn tuned_options(config: &DBConfig) -> Options {
+    let mut opts = Options::default();
+    if let Some(size) = config.block_cache_size {
+        let mut block_opts = BlockBasedOptions::default();
+        block_opts.set_lru_cache(size);
+        opts.set_block_based_table_factory(&block_opts);
+    }
+    if let Some(size) = config.write_buffer_size {
+        opts.set_write_buffer_size(size);
+    }
+    if let Some(max_open_files) = config.max_open_files {
+        opts.set_max_open_files(max_open_files);
+    }
+    if let Some(style) = config.compaction_style {
+        opts.set_compaction_style(match style {
+            CompactionStyle::Level => DBCompactionStyle::Level,
+            CompactionStyle::Universal => DBCompactionStyle::Universal,
+            CompactionStyle::Fifo => DBCompactionStyle::Fifo,
+        });
+    }
+    opts
+}
+
 fn cf_handle(db: &DB, col: Col) -> Result<ColumnFamily> {
     db.cf_handle(&col.to_string())
         .ok_or_else(|| Error::DBError(format!("column {} not found", col)))
@@ -139,6 +172,32 @@ impl KeyValueDB for RocksDB {
             .map_err(Into::into)
     }
 
+    fn traverse<F>(&self, col: Col, mut callback: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>,
+    {
+        let cf = cf_handle(&self.inner, col)?;
+        for (key, value) in self.inner.iterator_cf(cf, IteratorMode::Start)? {
+            callback(&key, &value)?;
+        }
+        Ok(())
+    }
+
+    fn traverse_prefix<F>(&self, col: Col, prefix: &[u8], mut callback: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()>,
+    {
+        let cf = cf_handle(&self.inner, col)?;
+        let mode = IteratorMode::From(prefix, Direction::Forward);
+        for (key, value) in self.inner.iterator_cf(cf, mode)? {
+            if !key.starts_with(prefix) {
+                break;
+            }
+            callback(&key, &value)?;
+        }
+        Ok(())
+    }
+
     fn batch(&self) -> Result<Self::Batch> {
         Ok(Self::Batch {
             db: Arc::clone(&self.inner),
@@ -215,10 +274,32 @@ mod tests {
                 opts.insert("disable_auto_compactions".to_owned(), "true".to_owned());
                 opts
             }),
+            ..Default::default()
         };
         RocksDB::open(&config, 2); // no panic
     }
 
+    #[test]
+    fn test_tuned_rocksdb_options() {
+        let tmp_dir = tempfile::Builder::new()
+            .prefix("test_tuned_rocksdb_options")
+            .tempdir()
+            .unwrap();
+        let config = DBConfig {
+            path: tmp_dir.as_ref().to_path_buf(),
+            block_cache_size: Some(1 << 20),
+            write_buffer_size: Some(1 << 20),
+            max_open_files: Some(64),
+            compaction_style: Some(CompactionStyle::Universal),
+            ..Default::default()
+        };
+        let db = RocksDB::open(&config, 2);
+        let mut batch = db.batch().unwrap();
+        batch.insert(1, &[1], &[1]).unwrap();
+        batch.commit().unwrap();
+        assert_eq!(db.read(1, &[1]).unwrap(), Some(vec![1]));
+    }
+
     #[test]
     #[should_panic]
     fn test_panic_on_invalid_rocksdb_options() {
@@ -233,6 +314,7 @@ mod tests {
                 opts.insert("letsrock".to_owned(), "true".to_owned());
                 opts
             }),
+            ..Default::default()
         };
         RocksDB::open(&config, 2); // panic
     }
@@ -278,6 +360,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn write_and_traverse() {
+        let db = setup_db("write_and_traverse", 2);
+        let mut batch = db.batch().unwrap();
+        batch.insert(0, &[0, 0], &[0, 0, 0]).unwrap();
+        batch.insert(1, &[1, 1], &[1, 1, 1]).unwrap();
+        batch.insert(1, &[2, 2], &[2, 2, 2]).unwrap();
+        batch.commit().unwrap();
+
+        let mut entries = Vec::new();
+        db.traverse(1, |key, value| {
+            entries.push((key.to_vec(), value.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![(vec![1, 1], vec![1, 1, 1]), (vec![2, 2], vec![2, 2, 2])]
+        );
+    }
+
+    #[test]
+    fn write_and_traverse_prefix() {
+        let db = setup_db("write_and_traverse_prefix", 1);
+        let mut batch = db.batch().unwrap();
+        batch.insert(0, &[1, 2], &[0]).unwrap();
+        batch.insert(0, &[1, 1], &[1]).unwrap();
+        batch.insert(0, &[2, 1], &[2]).unwrap();
+        batch.insert(0, &[0, 1], &[3]).unwrap();
+        batch.commit().unwrap();
+
+        let mut keys = Vec::new();
+        db.traverse_prefix(0, &[1], |key, _| {
+            keys.push(key.to_vec());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(keys, vec![vec![1, 1], vec![1, 2]]);
+    }
+
     #[test]
     #[should_panic]
     fn test_version_is_not_matched() {
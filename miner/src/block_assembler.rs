@@ -1,16 +1,18 @@
 use crate::config::BlockAssemblerConfig;
 use crate::error::Error;
-use ckb_core::block::Block;
-use ckb_core::header::Header;
+use ckb_core::block::BlockBuilder;
+use ckb_core::header::{Header, HeaderBuilder};
 use ckb_core::script::Script;
 use ckb_core::service::{Request, DEFAULT_CHANNEL_SIZE, SIGNAL_CHANNEL_SIZE};
 use ckb_core::transaction::{
     Capacity, CellInput, CellOutput, OutPoint, Transaction, TransactionBuilder,
 };
 use ckb_core::uncle::UncleBlock;
-use ckb_core::{Cycle, Version};
+use ckb_core::{BlockNumber, Cycle, Version};
 use ckb_notify::NotifyController;
-use ckb_shared::{shared::Shared, store::ChainStore, tx_pool::PoolEntry};
+use ckb_shared::shared::Shared;
+use ckb_shared::store::ChainStore;
+use ckb_shared::tx_pool::{PoolEntry, TxPoolEvent};
 use ckb_traits::ChainProvider;
 use ckb_util::Mutex;
 use crossbeam_channel::{self, select, Receiver, Sender};
@@ -22,18 +24,26 @@ use jsonrpc_types::{BlockTemplate, Bytes, CellbaseTemplate, TransactionTemplate,
 use log::error;
 use lru_cache::LruCache;
 use numext_fixed_hash::H256;
-use numext_fixed_uint::U256;
-use std::cmp;
+use std::cmp::{self, Reverse};
+use std::collections::BinaryHeap;
 use std::sync::{atomic::AtomicU64, atomic::AtomicUsize, atomic::Ordering, Arc};
 use std::thread;
+use std::time::Duration;
 use stop_handler::{SignalSender, StopHandler};
 
-const MAX_CANDIDATE_UNCLES: usize = 42;
-type BlockTemplateParams = (Option<Cycle>, Option<u64>, Option<Version>);
+type BlockTemplateParams = (Option<Cycle>, Option<u64>, Option<Version>, Option<u64>);
 type BlockTemplateResult = Result<BlockTemplate, FailureError>;
+// the parent hash and number of the issued works by work_id
+type Works = FnvHashMap<String, (H256, BlockNumber)>;
+// cycles limit, bytes limit, version and proposals limit
+type TemplateKey = (Cycle, u64, Version, u64);
 const BLOCK_ASSEMBLER_SUBSCRIBER: &str = "block_assembler";
 const BLOCK_TEMPLATE_TIMEOUT: u64 = 3000;
+// the pool changes are picked up at most once per interval
+const TEMPLATE_REFRESH_INTERVAL: u64 = 500;
 const TEMPLATE_CACHE_SIZE: usize = 10;
+const DEFAULT_PROPOSALS_LIMIT: u64 = 10000;
+const MAX_TEMPLATE_TXS: usize = 10000;
 
 struct TemplateCache {
     pub time: u64,
@@ -57,6 +67,8 @@ impl TemplateCache {
     }
 }
 
+/// Resolves input capacities through the transactions placed earlier in the
+/// template, falling back to the chain for cells committed already
 struct FeeCalculator<'a> {
     txs: &'a [PoolEntry],
     provider: &'a dyn ChainProvider,
@@ -76,34 +88,38 @@ impl<'a> FeeCalculator<'a> {
         }
     }
 
-    fn get_capacity(&self, out_point: &OutPoint) -> Option<Capacity> {
-        self.txs_map.get(&out_point.tx_hash).map_or_else(
-            || {
-                self.provider
-                    .get_transaction(&out_point.tx_hash)
-                    .and_then(|(tx, _block_hash)| {
-                        tx.outputs()
-                            .get(out_point.index as usize)
-                            .map(|output| output.capacity)
-                    })
-            },
-            |index| {
-                self.txs[*index]
-                    .transaction
-                    .outputs()
-                    .get(out_point.index as usize)
-                    .map(|output| output.capacity)
-            },
-        )
+    fn get_capacity(&self, position: usize, out_point: &OutPoint) -> Option<Capacity> {
+        self.txs_map
+            .get(&out_point.tx_hash)
+            .filter(|index| **index < position)
+            .map_or_else(
+                || {
+                    self.provider.get_transaction(&out_point.tx_hash).and_then(
+                        |(tx, _block_hash)| {
+                            tx.outputs()
+                                .get(out_point.index as usize)
+                                .map(|output| output.capacity)
+                        },
+                    )
+                },
+                |index| {
+                    self.txs[*index]
+                        .transaction
+                        .outputs()
+                        .get(out_point.index as usize)
+                        .map(|output| output.capacity)
+                },
+            )
     }
 
     fn calculate_transaction_fee(
         &self,
+        position: usize,
         transaction: &Transaction,
     ) -> Result<Capacity, FailureError> {
         let mut fee = Capacity::zero();
         for input in transaction.inputs() {
-            if let Some(capacity) = self.get_capacity(&input.previous_output) {
+            if let Some(capacity) = self.get_capacity(position, &input.previous_output) {
                 fee = fee.safe_add(capacity)?;
             } else {
                 Err(Error::InvalidInput)?;
@@ -122,9 +138,92 @@ impl<'a> FeeCalculator<'a> {
     }
 }
 
+/// Order the transactions so that every transaction comes after the transactions
+/// in the same template whose outputs it spends or depends on, keeping the original
+/// order otherwise.
+fn sort_in_topology(entries: Vec<PoolEntry>) -> Vec<PoolEntry> {
+    let indexes: FnvHashMap<H256, usize> = entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| (entry.transaction.hash(), index))
+        .collect();
+    let mut parents_count = vec![0usize; entries.len()];
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); entries.len()];
+    for (index, entry) in entries.iter().enumerate() {
+        let parents: FnvHashSet<usize> = entry
+            .transaction
+            .input_pts()
+            .iter()
+            .chain(entry.transaction.dep_pts().iter())
+            .filter_map(|out_point| indexes.get(&out_point.tx_hash).cloned())
+            .filter(|parent| *parent != index)
+            .collect();
+        parents_count[index] = parents.len();
+        for parent in parents {
+            children[parent].push(index);
+        }
+    }
+
+    let mut ready: BinaryHeap<Reverse<usize>> = parents_count
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count == 0)
+        .map(|(index, _)| Reverse(index))
+        .collect();
+    let mut order = Vec::with_capacity(entries.len());
+    while let Some(Reverse(index)) = ready.pop() {
+        order.push(index);
+        for child in &children[index] {
+            parents_count[*child] -= 1;
+            if parents_count[*child] == 0 {
+                ready.push(Reverse(*child));
+            }
+        }
+    }
+
+    let mut entries: Vec<Option<PoolEntry>> = entries.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .filter_map(|index| entries[index].take())
+        .collect()
+}
+
+/// Take the entries in the given order while the cycles and bytes last. An entry that doesn't
+/// fit is skipped together with the entries spending or depending on it, so the entries must
+/// already be sorted in topology.
+fn select_within_limits(
+    entries: Vec<PoolEntry>,
+    cycles_limit: Cycle,
+    bytes_limit: u64,
+) -> Vec<PoolEntry> {
+    let mut skipped = FnvHashSet::default();
+    let mut cycles: Cycle = 0;
+    let mut bytes: u64 = 0;
+    let mut selected = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let entry_cycles = cycles.saturating_add(entry.cycles.unwrap_or(0));
+        let entry_bytes = bytes.saturating_add(entry.size as u64);
+        let parent_skipped = entry
+            .transaction
+            .input_pts()
+            .iter()
+            .chain(entry.transaction.dep_pts().iter())
+            .any(|out_point| skipped.contains(&out_point.tx_hash));
+        if parent_skipped || entry_cycles > cycles_limit || entry_bytes > bytes_limit {
+            skipped.insert(entry.transaction.hash());
+            continue;
+        }
+        cycles = entry_cycles;
+        bytes = entry_bytes;
+        selected.push(entry);
+    }
+    selected
+}
+
 #[derive(Clone)]
 pub struct BlockAssemblerController {
     get_block_template_sender: Sender<Request<BlockTemplateParams, BlockTemplateResult>>,
+    works: Arc<Mutex<Works>>,
     stop: StopHandler<()>,
 }
 
@@ -144,22 +243,35 @@ impl BlockAssemblerController {
         cycles_limit: Option<Cycle>,
         bytes_limit: Option<u64>,
         max_version: Option<Version>,
+        proposals_limit: Option<u64>,
     ) -> BlockTemplateResult {
         Request::call(
             &self.get_block_template_sender,
-            (cycles_limit, bytes_limit, max_version),
+            (cycles_limit, bytes_limit, max_version, proposals_limit),
         )
         .expect("get_block_template() failed")
     }
+
+    /// The parent hash of the template issued with `work_id`, `None` if the work is unknown or
+    /// the tip has moved past its parent
+    pub fn get_work_parent_hash(&self, work_id: &str) -> Option<H256> {
+        self.works
+            .lock()
+            .get(work_id)
+            .map(|(parent_hash, _)| parent_hash.clone())
+    }
 }
 
+/// Assembles the block templates for the miners. The templates are cached by their limits and
+/// rebuilt in the background whenever the tip or the pool changes, the requests are answered
+/// from the cache unless it is outdated.
 pub struct BlockAssembler<CS> {
     shared: Shared<CS>,
-    candidate_uncles: LruCache<H256, Arc<Block>>,
     config: BlockAssemblerConfig,
     work_id: AtomicUsize,
     last_uncles_updated_at: AtomicU64,
-    template_caches: Mutex<LruCache<(Cycle, u64, Version), TemplateCache>>,
+    template_caches: Mutex<LruCache<TemplateKey, TemplateCache>>,
+    works: Arc<Mutex<Works>>,
 }
 
 impl<CS: ChainStore + 'static> BlockAssembler<CS> {
@@ -167,15 +279,15 @@ impl<CS: ChainStore + 'static> BlockAssembler<CS> {
         Self {
             shared,
             config,
-            candidate_uncles: LruCache::new(MAX_CANDIDATE_UNCLES),
             work_id: AtomicUsize::new(0),
             last_uncles_updated_at: AtomicU64::new(0),
             template_caches: Mutex::new(LruCache::new(TEMPLATE_CACHE_SIZE)),
+            works: Arc::new(Mutex::new(Works::default())),
         }
     }
 
     pub fn start<S: ToString>(
-        mut self,
+        self,
         thread_name: Option<S>,
         notify: &NotifyController,
     ) -> BlockAssemblerController {
@@ -194,17 +306,51 @@ impl<CS: ChainStore + 'static> BlockAssembler<CS> {
             get_block_template_receiver,
         };
 
+        let works = Arc::clone(&self.works);
         let new_uncle_receiver = notify.subscribe_new_uncle(BLOCK_ASSEMBLER_SUBSCRIBER);
+        let chain_event_receiver = notify.subscribe_chain_event(BLOCK_ASSEMBLER_SUBSCRIBER);
+        let tx_pool_event_receiver = self
+            .shared
+            .tx_pool()
+            .lock()
+            .subscribe_events(BLOCK_ASSEMBLER_SUBSCRIBER);
+        let refresh_ticker =
+            crossbeam_channel::tick(Duration::from_millis(TEMPLATE_REFRESH_INTERVAL));
+        let mut pool_changed = false;
         let thread = thread_builder
             .spawn(move || loop {
                 select! {
                     recv(signal_receiver) -> _ => {
                         break;
                     }
+                    recv(chain_event_receiver) -> msg => match msg {
+                        Ok(_chain_event) => {
+                            // a burst of events only needs one refresh
+                            chain_event_receiver.try_iter().count();
+                            pool_changed = false;
+                            self.refresh_default_template(true);
+                        }
+                        _ => {
+                            error!(target: "miner", "chain_event_receiver closed");
+                            break;
+                        }
+                    },
+                    recv(tx_pool_event_receiver) -> msg => match msg {
+                        // the rejected transactions never made it into the pool
+                        Ok(TxPoolEvent::Rejected { .. }) => {}
+                        Ok(_tx_pool_event) => pool_changed = true,
+                        _ => {
+                            error!(target: "miner", "tx_pool_event_receiver closed");
+                            break;
+                        }
+                    },
+                    recv(refresh_ticker) -> _ => {
+                        self.refresh_default_template(pool_changed);
+                        pool_changed = false;
+                    }
                     recv(new_uncle_receiver) -> msg => match msg {
-                        Ok(uncle_block) => {
-                            let hash = uncle_block.header().hash().clone();
-                            self.candidate_uncles.insert(hash, uncle_block);
+                        // the chain service has recorded it in the candidate uncles
+                        Ok(_uncle_block) => {
                             self.last_uncles_updated_at
                                 .store(unix_time_as_millis(), Ordering::SeqCst);
                         }
@@ -214,8 +360,8 @@ impl<CS: ChainStore + 'static> BlockAssembler<CS> {
                         }
                     },
                     recv(receivers.get_block_template_receiver) -> msg => match msg {
-                        Ok(Request { responder, arguments: (cycles_limit, bytes_limit, max_version) }) => {
-                            let _ = responder.send(self.get_block_template(cycles_limit, bytes_limit, max_version));
+                        Ok(Request { responder, arguments: (cycles_limit, bytes_limit, max_version, proposals_limit) }) => {
+                            let _ = responder.send(self.get_block_template(cycles_limit, bytes_limit, max_version, proposals_limit));
                         },
                         _ => {
                             error!(target: "miner", "get_block_template_receiver closed");
@@ -228,6 +374,7 @@ impl<CS: ChainStore + 'static> BlockAssembler<CS> {
 
         BlockAssemblerController {
             get_block_template_sender,
+            works,
             stop,
         }
     }
@@ -237,7 +384,8 @@ impl<CS: ChainStore + 'static> BlockAssembler<CS> {
         cycles_limit: Option<Cycle>,
         bytes_limit: Option<u64>,
         max_version: Option<Version>,
-    ) -> (Cycle, u64, Version) {
+        proposals_limit: Option<u64>,
+    ) -> TemplateKey {
         let consensus = self.shared.consensus();
         let cycles_limit = cycles_limit
             .min(Some(consensus.max_block_cycles()))
@@ -248,8 +396,11 @@ impl<CS: ChainStore + 'static> BlockAssembler<CS> {
         let version = max_version
             .min(Some(consensus.block_version()))
             .unwrap_or_else(|| consensus.block_version());
+        let proposals_limit = proposals_limit
+            .min(Some(DEFAULT_PROPOSALS_LIMIT))
+            .unwrap_or(DEFAULT_PROPOSALS_LIMIT);
 
-        (cycles_limit, bytes_limit, version)
+        (cycles_limit, bytes_limit, version, proposals_limit)
     }
 
     fn transform_uncle(uncle: UncleBlock) -> UncleTemplate {
@@ -286,51 +437,79 @@ impl<CS: ChainStore + 'static> BlockAssembler<CS> {
     }
 
     fn get_block_template(
-        &mut self,
+        &self,
         cycles_limit: Option<Cycle>,
         bytes_limit: Option<u64>,
         max_version: Option<Version>,
+        proposals_limit: Option<u64>,
     ) -> Result<BlockTemplate, FailureError> {
-        let (cycles_limit, bytes_limit, version) =
-            self.transform_params(cycles_limit, bytes_limit, max_version);
-        let uncles_count_limit = self.shared.consensus().max_uncles_num() as u32;
+        let key = self.transform_params(cycles_limit, bytes_limit, max_version, proposals_limit);
+        match self.cached_template(&key) {
+            Some(template) => Ok(template),
+            None => self.build_template(key),
+        }
+    }
 
+    fn cached_template(&self, key: &TemplateKey) -> Option<BlockTemplate> {
         let last_uncles_updated_at = self.last_uncles_updated_at.load(Ordering::SeqCst);
-        let chain_state = self.shared.chain_state().lock();
-        let last_txs_updated_at = chain_state.get_last_txs_updated_at();
+        let last_txs_updated_at = self.shared.tx_pool().lock().get_last_txs_updated_at();
+        let (tip_number, tip_timestamp) = {
+            let chain_state = self.shared.chain_state();
+            (
+                chain_state.tip_number(),
+                chain_state.tip_header().timestamp(),
+            )
+        };
+        let current_time = cmp::max(unix_time_as_millis(), tip_timestamp + 1);
+
+        self.template_caches
+            .lock()
+            .get(key)
+            .filter(|template_cache| {
+                !template_cache.is_outdate(
+                    last_uncles_updated_at,
+                    last_txs_updated_at,
+                    current_time,
+                    (tip_number + 1).to_string(),
+                )
+            })
+            .map(|template_cache| template_cache.template.clone())
+    }
 
-        let header = chain_state.tip_header().clone();
-        let number = chain_state.tip_number() + 1;
-        let current_time = cmp::max(unix_time_as_millis(), header.timestamp() + 1);
+    /// Rebuild the template with the default limits when it is outdated, or anyway if `force`d,
+    /// so the miners asking after the tip or the pool changed don't wait for the assembly.
+    /// Returns whether it was rebuilt.
+    fn refresh_default_template(&self, force: bool) -> bool {
+        let key = self.transform_params(None, None, None, None);
+        if !force && self.cached_template(&key).is_some() {
+            return false;
+        }
+        if let Err(err) = self.build_template(key) {
+            error!(target: "miner", "refresh block template {:?} failed: {:?}", key, err);
+            return false;
+        }
+        true
+    }
 
-        let mut template_caches = self.template_caches.lock();
+    fn build_template(&self, key: TemplateKey) -> Result<BlockTemplate, FailureError> {
+        let (cycles_limit, bytes_limit, version, proposals_limit) = key;
+        let uncles_count_limit = self.shared.consensus().max_uncles_num() as u32;
 
-        if let Some(template_cache) = template_caches.get(&(cycles_limit, bytes_limit, version)) {
-            if !template_cache.is_outdate(
-                last_uncles_updated_at,
-                last_txs_updated_at,
-                current_time,
-                number.to_string(),
-            ) {
-                return Ok(template_cache.template.clone());
-            }
-        }
+        let last_uncles_updated_at = self.last_uncles_updated_at.load(Ordering::SeqCst);
+        let tx_pool = self.shared.tx_pool().lock();
+        let last_txs_updated_at = tx_pool.get_last_txs_updated_at();
+        let header = self.shared.chain_state().tip_header().clone();
+        let number = header.number() + 1;
 
         let difficulty = self
             .shared
             .calculate_difficulty(&header)
             .expect("get difficulty");
 
-        let (proposals, transactions) = chain_state.get_proposal_and_staging_txs(10000, 10000);
+        let (proposals, transactions) =
+            tx_pool.get_proposal_and_staging_txs(proposals_limit as usize, MAX_TEMPLATE_TXS);
         // Release the lock as soon as possible, let other services do their work
-        drop(chain_state);
-
-        let (uncles, bad_uncles) = self.prepare_uncles(&header, &difficulty);
-        if !bad_uncles.is_empty() {
-            for bad in bad_uncles {
-                self.candidate_uncles.remove(&bad);
-            }
-        }
+        drop(tx_pool);
 
         let args = self
             .config
@@ -339,14 +518,54 @@ impl<CS: ChainStore + 'static> BlockAssembler<CS> {
             .cloned()
             .map(Bytes::into_vec)
             .collect();
+        let cellbase_lock = Script::new(args, self.config.code_hash.clone());
+
+        let uncles =
+            self.shared
+                .candidate_uncles()
+                .lock()
+                .select(&self.shared, &header, &difficulty);
+
+        // The header, uncles, proposals and cellbase take their bytes from the limit too. The
+        // cellbase capacity doesn't change the size, so a placeholder stands for the cellbase.
+        let reserved_bytes = BlockBuilder::default()
+            .header(
+                HeaderBuilder::default()
+                    .version(version)
+                    .parent_hash(header.hash())
+                    .number(number)
+                    .difficulty(difficulty.clone())
+                    .build(),
+            )
+            .uncles(uncles.clone())
+            .proposals(proposals.clone())
+            .transaction(
+                TransactionBuilder::default()
+                    .input(CellInput::new_cellbase_input(number))
+                    .output(CellOutput::new(
+                        Capacity::zero(),
+                        Vec::new(),
+                        cellbase_lock.clone(),
+                        None,
+                    ))
+                    .build(),
+            )
+            .build()
+            .serialized_size() as u64;
+
+        // Chained transactions can be committed in one block, as long as parents come first
+        let transactions = select_within_limits(
+            sort_in_topology(transactions),
+            cycles_limit,
+            bytes_limit.saturating_sub(reserved_bytes),
+        );
 
         // dummy cellbase
-        let cellbase_lock = Script::new(args, self.config.code_hash.clone());
         let cellbase = self.create_cellbase_transaction(&header, &transactions, cellbase_lock)?;
 
         // Should recalculate current time after create cellbase (create cellbase may spend a lot of time)
         let current_time = cmp::max(unix_time_as_millis(), header.timestamp() + 1);
-        let template = BlockTemplate {
+        let mut template = BlockTemplate {
             version,
             difficulty,
             current_time: current_time.to_string(),
@@ -362,11 +581,12 @@ impl<CS: ChainStore + 'static> BlockAssembler<CS> {
                 .collect(),
             proposals: proposals.into_iter().map(Into::into).collect(),
             cellbase: Self::transform_cellbase(&cellbase, None),
-            work_id: format!("{}", self.work_id.fetch_add(1, Ordering::SeqCst)),
+            work_id: String::new(),
         };
+        template.work_id = self.issue_work(&key, &template, &header);
 
-        template_caches.insert(
-            (cycles_limit, bytes_limit, version),
+        self.template_caches.lock().insert(
+            key,
             TemplateCache {
                 time: current_time,
                 uncles_updated_at: last_uncles_updated_at,
@@ -378,6 +598,34 @@ impl<CS: ChainStore + 'static> BlockAssembler<CS> {
         Ok(template)
     }
 
+    // The work of the cached template is issued again when only the time has changed since, the
+    // works on the parents the tip has moved past are dropped
+    fn issue_work(&self, key: &TemplateKey, template: &BlockTemplate, parent: &Header) -> String {
+        let previous_work_id = self.template_caches.lock().get(key).and_then(|cache| {
+            let unchanged = BlockTemplate {
+                current_time: cache.template.current_time.clone(),
+                work_id: cache.template.work_id.clone(),
+                ..template.clone()
+            } == cache.template;
+            if unchanged {
+                Some(cache.template.work_id.clone())
+            } else {
+                None
+            }
+        });
+
+        let mut works = self.works.lock();
+        works.retain(|_, (_, parent_number)| *parent_number >= parent.number());
+        match previous_work_id {
+            Some(ref work_id) if works.contains_key(work_id) => work_id.clone(),
+            _ => {
+                let work_id = format!("{}", self.work_id.fetch_add(1, Ordering::SeqCst));
+                works.insert(work_id.clone(), (parent.hash(), parent.number()));
+                work_id
+            }
+        }
+    }
+
     fn create_cellbase_transaction(
         &self,
         header: &Header,
@@ -390,102 +638,31 @@ impl<CS: ChainStore + 'static> BlockAssembler<CS> {
         // but the truth is we will modify this after we designed lock script anyway, so let's
         // stick to the simpler way and just convert everything to a single string, then to UTF8
         // bytes, they really serve the same purpose at the moment
-        let block_reward = self.shared.block_reward(header.number() + 1);
         let mut fee = Capacity::zero();
         // depends cells may produced from previous tx
         let fee_calculator = FeeCalculator::new(&pes, &self.shared);
-        for pe in pes {
-            fee = fee.safe_add(fee_calculator.calculate_transaction_fee(&pe.transaction)?)?;
+        for (position, pe) in pes.iter().enumerate() {
+            fee =
+                fee.safe_add(fee_calculator.calculate_transaction_fee(position, &pe.transaction)?)?;
         }
 
-        let output = CellOutput::new(block_reward.safe_add(fee)?, Vec::new(), lock, None);
+        let capacity = self
+            .shared
+            .chain_state()
+            .expected_cellbase_capacity(header.number() + 1, fee)?;
+        let output = CellOutput::new(capacity, Vec::new(), lock, None);
 
         Ok(TransactionBuilder::default()
             .input(input)
             .output(output)
             .build())
     }
-
-    fn prepare_uncles(
-        &self,
-        tip: &Header,
-        current_difficulty: &U256,
-    ) -> (Vec<UncleBlock>, Vec<H256>) {
-        let max_uncles_age = self.shared.consensus().max_uncles_age();
-        let mut excluded = FnvHashSet::default();
-
-        // cB
-        // tip      1 depth, valid uncle
-        // tip.p^0  ---/  2
-        // tip.p^1  -----/  3
-        // tip.p^2  -------/  4
-        // tip.p^3  ---------/  5
-        // tip.p^4  -----------/  6
-        // tip.p^5  -------------/
-        // tip.p^6
-        let mut block_hash = tip.hash().clone();
-        excluded.insert(block_hash.clone());
-        for _depth in 0..max_uncles_age {
-            if let Some(block) = self.shared.block(&block_hash) {
-                excluded.insert(block.header().parent_hash().clone());
-                for uncle in block.uncles() {
-                    excluded.insert(uncle.header.hash().clone());
-                }
-
-                block_hash = block.header().parent_hash().clone();
-            } else {
-                break;
-            }
-        }
-
-        let current_number = tip.number() + 1;
-        let current_difficulty_epoch =
-            current_number / self.shared.consensus().difficulty_adjustment_interval();
-
-        let max_uncles_num = self.shared.consensus().max_uncles_num();
-        let mut included = FnvHashSet::default();
-        let mut uncles = Vec::with_capacity(max_uncles_num);
-        let mut bad_uncles = Vec::new();
-
-        for (hash, block) in self.candidate_uncles.iter() {
-            if uncles.len() == max_uncles_num {
-                break;
-            }
-
-            let block_difficulty_epoch =
-                block.header().number() / self.shared.consensus().difficulty_adjustment_interval();
-
-            // uncle must be same difficulty epoch with candidate
-            if block.header().difficulty() != current_difficulty
-                || block_difficulty_epoch != current_difficulty_epoch
-            {
-                bad_uncles.push(hash.clone());
-                continue;
-            }
-
-            let depth = current_number.saturating_sub(block.header().number());
-            if depth > max_uncles_age as u64
-                || depth < 1
-                || included.contains(hash)
-                || excluded.contains(hash)
-            {
-                bad_uncles.push(hash.clone());
-            } else {
-                let uncle = UncleBlock {
-                    header: block.header().clone(),
-                    proposals: block.proposals().to_vec(),
-                };
-                uncles.push(uncle);
-                included.insert(hash.clone());
-            }
-        }
-        (uncles, bad_uncles)
-    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{block_assembler::BlockAssembler, config::BlockAssemblerConfig};
+    use crate::block_assembler::{select_within_limits, sort_in_topology, BlockAssembler};
+    use crate::config::BlockAssemblerConfig;
     use ckb_chain::chain::ChainBuilder;
     use ckb_chain::chain::ChainController;
     use ckb_chain_spec::consensus::Consensus;
@@ -494,7 +671,7 @@ mod tests {
     use ckb_core::header::{Header, HeaderBuilder};
     use ckb_core::script::Script;
     use ckb_core::transaction::{
-        CellInput, CellOutput, ProposalShortId, Transaction, TransactionBuilder,
+        CellInput, CellOutput, OutPoint, ProposalShortId, Transaction, TransactionBuilder,
     };
     use ckb_core::{BlockNumber, Capacity};
     use ckb_db::memorydb::MemoryKeyValueDB;
@@ -503,9 +680,10 @@ mod tests {
     use ckb_shared::shared::Shared;
     use ckb_shared::shared::SharedBuilder;
     use ckb_shared::store::{ChainKVStore, ChainStore};
+    use ckb_shared::tx_pool::PoolEntry;
     use ckb_traits::ChainProvider;
     use ckb_verification::{BlockVerifier, HeaderResolverWrapper, HeaderVerifier, Verifier};
-    use jsonrpc_types::{BlockTemplate, CellbaseTemplate};
+    use jsonrpc_types::{BlockTemplate, CellbaseTemplate, ProposalShortId as JsonProposalShortId};
     use numext_fixed_hash::H256;
     use numext_fixed_uint::U256;
     use std::convert::TryInto;
@@ -547,10 +725,10 @@ mod tests {
             code_hash: H256::zero(),
             args: vec![],
         };
-        let mut block_assembler = setup_block_assembler(shared.clone(), config);
+        let block_assembler = setup_block_assembler(shared.clone(), config);
 
         let block_template = block_assembler
-            .get_block_template(None, None, None)
+            .get_block_template(None, None, None, None)
             .unwrap();
 
         let BlockTemplate {
@@ -608,7 +786,7 @@ mod tests {
 
         let resolver = HeaderResolverWrapper::new(block.header(), shared.clone());
         let header_verify_result = {
-            let chain_state = shared.chain_state().lock();
+            let chain_state = shared.chain_state();
             let header_verifier =
                 HeaderVerifier::new(&*chain_state, Pow::Dummy(Default::default()).engine());
             header_verifier.verify(&resolver)
@@ -686,9 +864,17 @@ mod tests {
         // block number 3, epoch 0
         let _ = new_uncle_receiver.recv();
         let block_template = block_assembler_controller
-            .get_block_template(None, None, None)
+            .get_block_template(None, None, None, None)
             .unwrap();
         assert_eq!(block_template.uncles[0].hash, block0_0.header().hash());
+        assert_eq!(
+            block_assembler_controller.get_work_parent_hash(&block_template.work_id),
+            Some(block_template.parent_hash.clone())
+        );
+        assert_eq!(
+            block_assembler_controller.get_work_parent_hash("unknown"),
+            None
+        );
 
         let block2_1 = gen_block(
             block1_1.header(),
@@ -700,9 +886,150 @@ mod tests {
             .unwrap();
 
         let block_template = block_assembler_controller
-            .get_block_template(None, None, None)
+            .get_block_template(None, None, None, None)
             .unwrap();
         // block number 4, epoch 1, block_template should not include last epoch uncles
         assert!(block_template.uncles.is_empty());
     }
+
+    #[test]
+    fn test_refresh_default_template() {
+        let (chain_controller, shared, _notify) = start_chain(None, None);
+        let config = BlockAssemblerConfig {
+            code_hash: H256::zero(),
+            args: vec![],
+        };
+        let block_assembler = setup_block_assembler(shared.clone(), config);
+        let default_key = block_assembler.transform_params(None, None, None, None);
+
+        // built by the first refresh, then fresh until something changes
+        assert!(block_assembler.refresh_default_template(false));
+        assert!(!block_assembler.refresh_default_template(false));
+
+        let genesis = shared.block_header(&shared.block_hash(0).unwrap()).unwrap();
+        let block = gen_block(&genesis, 10, genesis.difficulty().clone());
+        chain_controller
+            .process_block(Arc::new(block.clone()))
+            .unwrap();
+        assert!(block_assembler.refresh_default_template(false));
+        let template = block_assembler.cached_template(&default_key).unwrap();
+        assert_eq!(template.parent_hash, block.header().hash());
+
+        // the pool events force a refresh
+        let tx = create_cellbase(10);
+        shared
+            .tx_pool()
+            .lock()
+            .enqueue_tx(None, Capacity::zero(), tx.clone());
+        assert!(block_assembler.refresh_default_template(true));
+        let template = block_assembler.cached_template(&default_key).unwrap();
+        let proposal_id: JsonProposalShortId = tx.proposal_short_id().into();
+        assert!(template.proposals.contains(&proposal_id));
+    }
+
+    #[test]
+    fn test_works_kept_until_tip_moves() {
+        let (chain_controller, shared, _notify) = start_chain(None, None);
+        let config = BlockAssemblerConfig {
+            code_hash: H256::zero(),
+            args: vec![],
+        };
+        let block_assembler = setup_block_assembler(shared.clone(), config);
+        let key = block_assembler.transform_params(None, None, None, None);
+
+        let template = block_assembler.build_template(key).unwrap();
+        // only the time has changed
+        assert_eq!(
+            block_assembler.build_template(key).unwrap().work_id,
+            template.work_id
+        );
+
+        // more works than the miners could have in flight
+        for number in 0..1100 {
+            shared.tx_pool().lock().enqueue_tx(
+                None,
+                Capacity::zero(),
+                create_cellbase(100 + number),
+            );
+            let newer = block_assembler.build_template(key).unwrap();
+            assert_ne!(newer.work_id, template.work_id);
+        }
+        assert!(block_assembler.works.lock().contains_key(&template.work_id));
+
+        let genesis = shared.block_header(&shared.block_hash(0).unwrap()).unwrap();
+        let block = gen_block(&genesis, 10, genesis.difficulty().clone());
+        chain_controller.process_block(Arc::new(block)).unwrap();
+        block_assembler.build_template(key).unwrap();
+        assert!(!block_assembler.works.lock().contains_key(&template.work_id));
+    }
+
+    fn create_chained_tx(parent: &Transaction) -> Transaction {
+        TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(parent.hash(), 0), 0, vec![]))
+            .output(CellOutput::new(
+                Capacity::zero(),
+                vec![],
+                Script::default(),
+                None,
+            ))
+            .build()
+    }
+
+    #[test]
+    fn test_sort_in_topology() {
+        let root = create_cellbase(1);
+        let child = create_chained_tx(&root);
+        let grandchild = create_chained_tx(&child);
+        let other = create_cellbase(2);
+
+        let entries = vec![
+            PoolEntry::new(grandchild.clone(), 0, None, Capacity::zero()),
+            PoolEntry::new(other.clone(), 0, None, Capacity::zero()),
+            PoolEntry::new(child.clone(), 0, None, Capacity::zero()),
+            PoolEntry::new(root.clone(), 0, None, Capacity::zero()),
+        ];
+        let hashes: Vec<_> = sort_in_topology(entries)
+            .into_iter()
+            .map(|entry| entry.transaction.hash())
+            .collect();
+        assert_eq!(
+            hashes,
+            vec![other.hash(), root.hash(), child.hash(), grandchild.hash()]
+        );
+    }
+
+    #[test]
+    fn test_select_within_limits() {
+        let root = create_cellbase(1);
+        let child = create_chained_tx(&root);
+        let other = create_cellbase(2);
+        let entries = || {
+            vec![
+                PoolEntry::new(root.clone(), 0, Some(10), Capacity::zero()),
+                PoolEntry::new(child.clone(), 0, Some(10), Capacity::zero()),
+                PoolEntry::new(other.clone(), 0, Some(5), Capacity::zero()),
+            ]
+        };
+        let hashes = |entries: Vec<PoolEntry>| -> Vec<H256> {
+            entries
+                .into_iter()
+                .map(|entry| entry.transaction.hash())
+                .collect()
+        };
+
+        assert_eq!(
+            hashes(select_within_limits(entries(), 15, u64::max_value())),
+            vec![root.hash(), other.hash()]
+        );
+        // the child can't be committed without its parent
+        assert_eq!(
+            hashes(select_within_limits(entries(), 8, u64::max_value())),
+            vec![other.hash()]
+        );
+        let bytes_limit = (root.serialized_size() + child.serialized_size()) as u64;
+        assert_eq!(
+            hashes(select_within_limits(entries(), 100, bytes_limit)),
+            vec![root.hash(), child.hash()]
+        );
+    }
 }
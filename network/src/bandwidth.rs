@@ -0,0 +1,128 @@
+use crate::ProtocolId;
+use fnv::FnvHashMap;
+use p2p::SessionId;
+use std::time::{Duration, Instant};
+
+/// Rates are calculated over windows of this length
+pub const BANDWIDTH_RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Traffic of a session, or of a protocol in a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Bandwidth {
+    pub total_sent: u64,
+    pub total_received: u64,
+    /// Bytes per second sent in the last complete window
+    pub sent_rate: u64,
+    /// Bytes per second received in the last complete window
+    pub received_rate: u64,
+}
+
+impl Bandwidth {
+    fn add(&mut self, other: &Bandwidth) {
+        self.total_sent += other.total_sent;
+        self.total_received += other.total_received;
+        self.sent_rate += other.sent_rate;
+        self.received_rate += other.received_rate;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PeerBandwidth {
+    pub total: Bandwidth,
+    pub protocols: FnvHashMap<ProtocolId, Bandwidth>,
+}
+
+#[derive(Debug, Default)]
+struct Traffic {
+    bandwidth: Bandwidth,
+    window_sent: u64,
+    window_received: u64,
+}
+
+pub struct BandwidthMonitor {
+    sessions: FnvHashMap<SessionId, FnvHashMap<ProtocolId, Traffic>>,
+    window_start: Instant,
+}
+
+impl BandwidthMonitor {
+    pub fn new(now: Instant) -> Self {
+        BandwidthMonitor {
+            sessions: FnvHashMap::default(),
+            window_start: now,
+        }
+    }
+
+    pub fn record_sent(
+        &mut self,
+        now: Instant,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        len: usize,
+    ) {
+        self.roll_window(now);
+        let traffic = self.traffic_mut(session_id, proto_id);
+        traffic.bandwidth.total_sent += len as u64;
+        traffic.window_sent += len as u64;
+    }
+
+    pub fn record_received(
+        &mut self,
+        now: Instant,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        len: usize,
+    ) {
+        self.roll_window(now);
+        let traffic = self.traffic_mut(session_id, proto_id);
+        traffic.bandwidth.total_received += len as u64;
+        traffic.window_received += len as u64;
+    }
+
+    pub fn remove_session(&mut self, session_id: SessionId) {
+        self.sessions.remove(&session_id);
+    }
+
+    pub fn peer_bandwidth(&mut self, now: Instant) -> FnvHashMap<SessionId, PeerBandwidth> {
+        self.roll_window(now);
+        self.sessions
+            .iter()
+            .map(|(session_id, protocols)| {
+                let mut peer_bandwidth = PeerBandwidth::default();
+                for (proto_id, traffic) in protocols {
+                    peer_bandwidth.total.add(&traffic.bandwidth);
+                    peer_bandwidth
+                        .protocols
+                        .insert(*proto_id, traffic.bandwidth);
+                }
+                (*session_id, peer_bandwidth)
+            })
+            .collect()
+    }
+
+    fn traffic_mut(&mut self, session_id: SessionId, proto_id: ProtocolId) -> &mut Traffic {
+        self.sessions
+            .entry(session_id)
+            .or_insert_with(FnvHashMap::default)
+            .entry(proto_id)
+            .or_insert_with(Traffic::default)
+    }
+
+    fn roll_window(&mut self, now: Instant) {
+        if now < self.window_start + BANDWIDTH_RATE_WINDOW {
+            return;
+        }
+        // traffic is counted until the first record after the window ends
+        let secs = (now - self.window_start).as_secs();
+        for traffic in self
+            .sessions
+            .values_mut()
+            .flat_map(|protocols| protocols.values_mut())
+        {
+            traffic.bandwidth.sent_rate = traffic.window_sent / secs;
+            traffic.bandwidth.received_rate = traffic.window_received / secs;
+            traffic.window_sent = 0;
+            traffic.window_received = 0;
+        }
+        self.window_start = now;
+    }
+}
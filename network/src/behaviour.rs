@@ -4,9 +4,13 @@ use crate::Score;
 /// we maintain a score to each peer
 /// report peer bahaviour will affects peer's score
 ///
-/// Currently this feature is disabled, maybe someday we will add it back or totally remove it.
+/// Currently exceeding the message rate limits and quick feeler handshakes are reported.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Behaviour {
+    /// Kept sending messages over the rate limit of a protocol
+    ExceedRateLimit,
+    /// Answered a feeler connection within `FAST_FEELER_HANDSHAKE`
+    FastFeelerHandshake,
     #[cfg(test)]
     TestGood,
     #[cfg(test)]
@@ -17,6 +21,8 @@ impl Behaviour {
     pub fn score(self) -> Score {
         #[allow(unreachable_patterns)]
         match self {
+            Behaviour::ExceedRateLimit => -20,
+            Behaviour::FastFeelerHandshake => 2,
             #[cfg(test)]
             Behaviour::TestGood => 10,
             #[cfg(test)]
@@ -4,7 +4,7 @@ extern crate ckb_network;
 extern crate ckb_util;
 
 use ckb_network::{
-    multiaddr::ToMultiaddr,
+    multiaddr::{Multiaddr, ToMultiaddr},
     peer_store::{PeerStore, SqlitePeerStore},
     PeerId, SessionType,
 };
@@ -51,15 +51,22 @@ fn insert_peer_info_benchmark(c: &mut Criterion) {
     });
 }
 
+// Addresses in different network groups, addresses in the same group share a few buckets
+fn distinct_addr(i: usize) -> Multiaddr {
+    format!("/ip4/{}.{}.1.1", i / 256 + 1, i % 256)
+        .to_multiaddr()
+        .unwrap()
+}
+
 fn random_order_benchmark(c: &mut Criterion) {
     {
         let mut peer_store = SqlitePeerStore::memory().expect("temp");
-        let addr = "/ip4/127.0.0.1".to_multiaddr().unwrap();
         {
-            for _ in 0..8000 {
+            for i in 0..8000 {
                 let peer_id = PeerId::random();
+                let addr = distinct_addr(i);
                 peer_store.add_connected_peer(&peer_id, addr.clone(), SessionType::Outbound);
-                let _ = peer_store.add_discovered_addr(&peer_id, addr.clone());
+                let _ = peer_store.add_discovered_addr(&peer_id, addr);
             }
         }
         c.bench_function("random order 1000 / 8000 peer_info", {
@@ -72,12 +79,12 @@ fn random_order_benchmark(c: &mut Criterion) {
         });
 
         let mut peer_store = SqlitePeerStore::memory().expect("temp");
-        let addr = "/ip4/127.0.0.1".to_multiaddr().unwrap();
         {
-            for _ in 0..8000 {
+            for i in 0..8000 {
                 let peer_id = PeerId::random();
+                let addr = distinct_addr(i);
                 peer_store.add_connected_peer(&peer_id, addr.clone(), SessionType::Outbound);
-                let _ = peer_store.add_discovered_addr(&peer_id, addr.clone());
+                let _ = peer_store.add_discovered_addr(&peer_id, addr);
             }
         }
         c.bench_function("random order 2000 / 8000 peer_info", {
@@ -96,11 +103,11 @@ fn random_order_benchmark(c: &mut Criterion) {
         move |b| {
             b.iter({
                 let mut peer_store = SqlitePeerStore::temp().expect("temp");
-                let addr = "/ip4/127.0.0.1".to_multiaddr().unwrap();
-                for _ in 0..8000 {
+                for i in 0..8000 {
                     let peer_id = PeerId::random();
+                    let addr = distinct_addr(i);
                     peer_store.add_connected_peer(&peer_id, addr.clone(), SessionType::Outbound);
-                    let _ = peer_store.add_discovered_addr(&peer_id, addr.clone());
+                    let _ = peer_store.add_discovered_addr(&peer_id, addr);
                 }
                 move || {
                     let count = 1000;
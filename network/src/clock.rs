@@ -0,0 +1,100 @@
+use ckb_util::Mutex;
+use futures::{task, Async, Poll, Stream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::timer::Interval;
+
+pub type IntervalStream = Box<dyn Stream<Item = Instant, Error = ()> + Send>;
+
+/// Source of time for time dependent network services, tests replace it with
+/// `MockClock` to move time forward without real sleeps
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    /// A stream yields every `period`, the first tick is one `period` from now
+    fn interval(&self, period: Duration) -> IntervalStream;
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn interval(&self, period: Duration) -> IntervalStream {
+        Box::new(Interval::new(Instant::now() + period, period).map_err(|_| ()))
+    }
+}
+
+#[derive(Default)]
+struct MockClockInner {
+    elapsed: Duration,
+    // intervals waiting for the time to move forward
+    waiting: Vec<task::Task>,
+}
+
+/// A clock only moves forward by `advance`
+#[derive(Clone)]
+pub struct MockClock {
+    start: Instant,
+    inner: Arc<Mutex<MockClockInner>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock {
+            start: Instant::now(),
+            inner: Default::default(),
+        }
+    }
+}
+
+impl MockClock {
+    pub fn advance(&self, duration: Duration) {
+        let waiting = {
+            let mut inner = self.inner.lock();
+            inner.elapsed += duration;
+            ::std::mem::replace(&mut inner.waiting, Vec::new())
+        };
+        for task in waiting {
+            task.notify();
+        }
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.inner.lock().elapsed
+    }
+
+    fn interval(&self, period: Duration) -> IntervalStream {
+        Box::new(MockInterval {
+            next: self.now() + period,
+            period,
+            clock: self.clone(),
+        })
+    }
+}
+
+struct MockInterval {
+    next: Instant,
+    period: Duration,
+    clock: MockClock,
+}
+
+impl Stream for MockInterval {
+    type Item = Instant;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut inner = self.clock.inner.lock();
+        if self.clock.start + inner.elapsed < self.next {
+            inner.waiting.push(task::current());
+            return Ok(Async::NotReady);
+        }
+        let tick = self.next;
+        self.next += self.period;
+        Ok(Async::Ready(Some(tick)))
+    }
+}
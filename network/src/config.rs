@@ -1,6 +1,10 @@
+use crate::dns_seeding::{decode_hex, SEEDER_PUBKEY_SIZE};
 use crate::errors::{ConfigError, Error};
-use crate::PeerId;
-use log::info;
+use crate::protocols::is_valid_version;
+use crate::rate_limiter::RateLimitConfig;
+use crate::{PeerId, ProtocolId, ProtocolVersion};
+use fnv::FnvHashMap;
+use log::{info, warn};
 use p2p::multiaddr::{Multiaddr, Protocol, ToMultiaddr};
 use rand;
 use rand::Rng;
@@ -16,15 +20,65 @@ pub struct NetworkConfig {
     pub reserved_only: bool,
     pub max_peers: u32,
     pub max_outbound_peers: u32,
+    /// Extra outbound slots for peers which only relay blocks, picked from network groups
+    /// different from the other outbound peers, so blocks still propagate when the peers
+    /// relaying transactions are eclipsed or banned
+    #[serde(default)]
+    pub block_relay_only_peers: u32,
     #[serde(default)]
     pub path: PathBuf,
+    /// Addresses kept in the peer store, the lowest quality ones are dropped periodically
+    /// when there are more. Addresses of bootnodes and reserved peers are always kept
+    #[serde(default = "default_max_peer_store_addrs")]
+    pub max_peer_store_addrs: u32,
     pub ping_interval_secs: u64,
     pub ping_timeout_secs: u64,
     pub connect_outbound_interval_secs: u64,
+    /// Sessions without traffic on any protocol for this long are disconnected, 0 disables it
+    #[serde(default = "default_idle_timeout_secs")]
+    pub idle_timeout_secs: u64,
+    /// Only TCP addresses, the p2p library has no WebSocket transport
     pub listen_addresses: Vec<Multiaddr>,
     pub public_addresses: Vec<Multiaddr>,
     pub bootnodes: Vec<Multiaddr>,
+    #[serde(default)]
+    pub dns_seeds: Vec<String>,
+    /// Hex encoded compressed secp256k1 public keys of trusted dns seeders, a seed record
+    /// signed by any of them is accepted
+    #[serde(default)]
+    pub dns_seed_pubkeys: Vec<String>,
+    /// Identifies the chain in dns seed records, derived from the genesis hash on start
+    #[serde(skip)]
+    pub network_magic: u32,
+    /// Use only the bootnodes and dns seeds listed in this config, ignore the ones in chain spec
+    #[serde(default)]
+    pub ignore_spec_seeds: bool,
     pub reserved_peers: Vec<Multiaddr>,
+    /// Allow several outbound peers in the same network group (/16 IPv4 or /32 IPv6 subnet, the
+    /// IPv6 tunnels are grouped by their IPv4 address), by default each outbound slot is filled
+    /// with a peer from a different group
+    #[serde(default)]
+    pub allow_outbound_same_group: bool,
+    /// Minimum protocol versions by protocol id, taken from the chain spec on start.
+    /// Peers opening a protocol with a lower version are disconnected
+    #[serde(skip)]
+    pub min_protocol_versions: FnvHashMap<ProtocolId, ProtocolVersion>,
+    /// Store and advertise unroutable addresses (loopback, private, link local) learned
+    /// through discovery, for local test networks
+    #[serde(default)]
+    pub discovery_local_address: bool,
+    /// Per peer message budgets of protocols, protocols not listed are unlimited
+    #[serde(default)]
+    pub rate_limits: Vec<RateLimitConfig>,
+}
+
+fn default_max_peer_store_addrs() -> u32 {
+    16384
+}
+
+fn default_idle_timeout_secs() -> u64 {
+    // 30 minutes
+    1800
 }
 
 fn generate_random_key() -> [u8; 32] {
@@ -65,6 +119,20 @@ impl NetworkConfig {
         self.max_outbound_peers
     }
 
+    /// Check the settings which can't be checked by the deserialization
+    pub fn validate(&self) -> Result<(), String> {
+        for rate_limit in &self.rate_limits {
+            rate_limit.validate()?;
+        }
+        Ok(())
+    }
+
+    pub fn min_protocol_version(&self, proto_id: ProtocolId) -> Option<&str> {
+        self.min_protocol_versions
+            .get(&proto_id)
+            .map(String::as_str)
+    }
+
     fn read_secret_key(&self) -> Result<Option<secio::SecioKeyPair>, Error> {
         let path = self.secret_key_path();
         let mut file = match fs::File::open(path) {
@@ -118,6 +186,67 @@ impl NetworkConfig {
         Ok(peers)
     }
 
+    /// Take the minimum protocol versions of the chain spec, a malformed version is an error
+    /// since it couldn't be enforced.
+    pub fn set_min_protocol_versions(
+        &mut self,
+        versions: Vec<(ProtocolId, ProtocolVersion)>,
+    ) -> Result<(), Error> {
+        for (proto_id, version) in &versions {
+            if !is_valid_version(version) {
+                return Err(ConfigError::BadProtocolVersion(*proto_id, version.clone()).into());
+            }
+        }
+        self.min_protocol_versions = versions.into_iter().collect();
+        Ok(())
+    }
+
+    /// Put the bootnodes, dns seeds and seeder keys defined by the chain spec before the
+    /// configured ones, unless `ignore_spec_seeds` is set.
+    pub fn merge_spec_seeds(
+        &mut self,
+        bootnodes: &[String],
+        dns_seeds: &[String],
+        dns_seed_pubkeys: &[String],
+    ) -> Result<(), Error> {
+        if self.ignore_spec_seeds {
+            return Ok(());
+        }
+        let mut merged_bootnodes = Vec::with_capacity(bootnodes.len() + self.bootnodes.len());
+        for addr_str in bootnodes {
+            let addr = addr_str
+                .to_multiaddr()
+                .map_err(|_| ConfigError::BadAddress)?;
+            if !merged_bootnodes.contains(&addr) {
+                merged_bootnodes.push(addr);
+            }
+        }
+        for addr in self.bootnodes.drain(..) {
+            if !merged_bootnodes.contains(&addr) {
+                merged_bootnodes.push(addr);
+            }
+        }
+        self.bootnodes = merged_bootnodes;
+
+        self.dns_seeds = merge_strings(dns_seeds, &mut self.dns_seeds);
+        self.dns_seed_pubkeys = merge_strings(dns_seed_pubkeys, &mut self.dns_seed_pubkeys);
+        Ok(())
+    }
+
+    /// Decoded `dns_seed_pubkeys`, invalid keys are skipped
+    pub fn dns_seed_pubkeys(&self) -> Vec<Vec<u8>> {
+        self.dns_seed_pubkeys
+            .iter()
+            .filter_map(|pubkey| {
+                let decoded = decode_hex(pubkey, SEEDER_PUBKEY_SIZE);
+                if decoded.is_none() {
+                    warn!(target: "network", "invalid dns seed pubkey {}", pubkey);
+                }
+                decoded
+            })
+            .collect()
+    }
+
     pub fn bootnodes(&self) -> Result<Vec<(PeerId, Multiaddr)>, Error> {
         let mut peers = Vec::with_capacity(self.bootnodes.len());
         for addr_str in &self.bootnodes {
@@ -135,3 +264,14 @@ impl NetworkConfig {
         Ok(peers)
     }
 }
+
+// Keep the order, entries in `first` go first
+fn merge_strings(first: &[String], second: &mut Vec<String>) -> Vec<String> {
+    let mut merged: Vec<String> = Vec::with_capacity(first.len() + second.len());
+    for item in first.iter().cloned().chain(second.drain(..)) {
+        if !merged.contains(&item) {
+            merged.push(item);
+        }
+    }
+    merged
+}
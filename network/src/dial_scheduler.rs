@@ -0,0 +1,187 @@
+use crate::dump::DialDump;
+use crate::PeerId;
+use fnv::FnvHashMap;
+use lru_cache::LruCache;
+use p2p::multiaddr::{Multiaddr, Protocol};
+use rand::{thread_rng, Rng};
+use std::cmp::min;
+use std::time::{Duration, Instant};
+
+/// Outbound dials running at the same time
+pub const MAX_CONCURRENT_DIALS: usize = 8;
+/// A dial neither connected nor failed after this long is counted as failed
+pub const DIAL_TIMEOUT: Duration = Duration::from_secs(30);
+/// Addresses failed this many times in a row are removed from the peer store
+pub const MAX_DIAL_FAILURES: u32 = 8;
+const BASE_BACKOFF: Duration = Duration::from_secs(60);
+const MAX_BACKOFF: Duration = Duration::from_secs(4 * 60 * 60);
+const DIAL_RECORDS_CACHE_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct DialRecord {
+    failures: u32,
+    retry_at: Instant,
+}
+
+/// Result of a failed dial
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DialFailure {
+    /// The address may be dialed again after a backoff
+    Retry,
+    /// The address failed too many times, remove it from the peer store
+    Prune,
+}
+
+/// Schedules outbound dials, addresses which failed are retried after an exponential
+/// backoff with jitter, and at most `max_concurrent` dials run at the same time.
+///
+/// Addresses are keyed without the `/p2p/` suffix.
+pub struct DialScheduler {
+    records: LruCache<Multiaddr, DialRecord>,
+    in_flight: FnvHashMap<Multiaddr, (PeerId, Instant)>,
+    max_concurrent: usize,
+}
+
+impl Default for DialScheduler {
+    fn default() -> Self {
+        DialScheduler::new(MAX_CONCURRENT_DIALS)
+    }
+}
+
+impl DialScheduler {
+    pub fn new(max_concurrent: usize) -> Self {
+        DialScheduler {
+            records: LruCache::new(DIAL_RECORDS_CACHE_SIZE),
+            in_flight: FnvHashMap::default(),
+            max_concurrent,
+        }
+    }
+
+    /// Addresses with recorded failures
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Dials can be started without exceeding the cap
+    pub fn available_slots(&self) -> usize {
+        self.max_concurrent.saturating_sub(self.in_flight.len())
+    }
+
+    /// Whether `addr` is neither being dialed nor backing off
+    pub fn can_dial(&mut self, addr: &Multiaddr, now: Instant) -> bool {
+        let addr = strip_peer_id(addr);
+        !self.in_flight.contains_key(&addr)
+            && self
+                .records
+                .get_mut(&addr)
+                .map(|record| now >= record.retry_at)
+                .unwrap_or(true)
+    }
+
+    pub fn dial_started(&mut self, peer_id: &PeerId, addr: &Multiaddr, now: Instant) {
+        self.in_flight
+            .insert(strip_peer_id(addr), (peer_id.to_owned(), now));
+    }
+
+    /// Returns the time from starting the dial to the opened session
+    pub fn dial_succeeded(&mut self, addr: &Multiaddr, now: Instant) -> Option<Duration> {
+        let addr = strip_peer_id(addr);
+        self.records.remove(&addr);
+        self.in_flight
+            .remove(&addr)
+            .map(|(_, started_at)| now.duration_since(started_at))
+    }
+
+    pub fn dial_failed(&mut self, addr: &Multiaddr, now: Instant) -> DialFailure {
+        let addr = strip_peer_id(addr);
+        self.in_flight.remove(&addr);
+        let failures = self
+            .records
+            .get_mut(&addr)
+            .map(|record| record.failures)
+            .unwrap_or(0)
+            + 1;
+        let retry_at = now + jitter(backoff(failures));
+        self.records.insert(addr, DialRecord { failures, retry_at });
+        if failures >= MAX_DIAL_FAILURES {
+            DialFailure::Prune
+        } else {
+            DialFailure::Retry
+        }
+    }
+
+    /// Running dials and addresses backing off, for `NetworkController::dump_state`
+    pub fn dump(&self, now: Instant) -> Vec<DialDump> {
+        let mut dials: Vec<DialDump> = self
+            .records
+            .iter()
+            .map(|(addr, record)| DialDump {
+                address: addr.to_string(),
+                peer_id: self
+                    .in_flight
+                    .get(addr)
+                    .map(|(peer_id, _)| peer_id.to_base58()),
+                failures: record.failures,
+                retry_in_secs: if record.retry_at > now {
+                    (record.retry_at - now).as_secs()
+                } else {
+                    0
+                },
+            })
+            .collect();
+        for (addr, (peer_id, _)) in &self.in_flight {
+            if !self.records.contains_key(addr) {
+                dials.push(DialDump {
+                    address: addr.to_string(),
+                    peer_id: Some(peer_id.to_base58()),
+                    failures: 0,
+                    retry_in_secs: 0,
+                });
+            }
+        }
+        dials
+    }
+
+    /// Count dials started `DIAL_TIMEOUT` ago as failed, returns the addresses to prune
+    pub fn expire_dials(&mut self, now: Instant) -> Vec<(PeerId, Multiaddr)> {
+        let expired: Vec<(Multiaddr, PeerId)> = self
+            .in_flight
+            .iter()
+            .filter(|(_, (_, started_at))| now >= *started_at + DIAL_TIMEOUT)
+            .map(|(addr, (peer_id, _))| (addr.to_owned(), peer_id.to_owned()))
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|(addr, peer_id)| match self.dial_failed(&addr, now) {
+                DialFailure::Prune => Some((peer_id, addr)),
+                DialFailure::Retry => None,
+            })
+            .collect()
+    }
+}
+
+// BASE_BACKOFF doubled for each failure in a row, up to MAX_BACKOFF
+fn backoff(failures: u32) -> Duration {
+    let exp = min(failures.saturating_sub(1), 16);
+    min(BASE_BACKOFF * (1u32 << exp), MAX_BACKOFF)
+}
+
+// A random delay in [backoff / 2, backoff], so addresses failed together are not retried together
+fn jitter(backoff: Duration) -> Duration {
+    let half = backoff / 2;
+    let millis = half.as_secs() * 1000 + u64::from(half.subsec_millis());
+    half + Duration::from_millis(thread_rng().gen_range(0, millis + 1))
+}
+
+pub(crate) fn strip_peer_id(addr: &Multiaddr) -> Multiaddr {
+    addr.iter()
+        .filter(|proto| match proto {
+            Protocol::P2p(_) => false,
+            _ => true,
+        })
+        .collect()
+}
@@ -0,0 +1,198 @@
+mod seed_record;
+
+pub(crate) use self::seed_record::decode_hex;
+pub use self::seed_record::{SeedRecord, SeedRecordError};
+
+use crate::peer_store::PeerStore;
+use crate::NetworkState;
+use crypto::secp::Privkey;
+use faketime::unix_time_as_millis;
+use futures::future::{self, Loop};
+use futures::{Future, Stream};
+use log::{debug, info, trace, warn};
+use std::cmp::min;
+use std::sync::Arc;
+use std::time::Duration;
+use trust_dns_resolver::proto::rr::rdata::TXT;
+use trust_dns_resolver::AsyncResolver;
+
+/// Seeding stops once the peer store knows this many addresses
+pub(crate) const SEEDING_MIN_PEERS: u32 = 10;
+pub(crate) const INITIAL_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(30 * 60);
+/// Size of a compressed secp256k1 public key
+pub const SEEDER_PUBKEY_SIZE: usize = 33;
+
+/// Fetch peer addresses from the TXT records of the dns seeds, see `SeedRecord` for the format.
+///
+/// The seeds are resolved with the nameservers of the system, a truncated UDP response is
+/// queried again over TCP.
+pub struct DnsSeedingService {
+    network_state: Arc<NetworkState>,
+    seeds: Vec<String>,
+    trusted_pubkeys: Vec<Vec<u8>>,
+    network_magic: u32,
+}
+
+impl DnsSeedingService {
+    pub fn new(
+        network_state: Arc<NetworkState>,
+        seeds: Vec<String>,
+        trusted_pubkeys: Vec<Vec<u8>>,
+        network_magic: u32,
+    ) -> Self {
+        DnsSeedingService {
+            network_state,
+            seeds,
+            trusted_pubkeys,
+            network_magic,
+        }
+    }
+
+    /// Query all seeds concurrently, retry with an exponential backoff until the peer store
+    /// has enough addresses.
+    pub fn seeding(self) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        if self.trusted_pubkeys.is_empty() {
+            warn!(target: "network", "dns seeding is disabled: no dns seed pubkey configured");
+            return Box::new(future::ok(()));
+        }
+        let (resolver, background) = match AsyncResolver::from_system_conf() {
+            Ok(resolver) => resolver,
+            Err(err) => {
+                warn!(target: "network", "dns seeding is disabled: {}", err);
+                return Box::new(future::ok(()));
+            }
+        };
+        // the background task resolves the queries until the resolver is dropped with the
+        // seeding loop
+        let seeding = Self::seeding_loop(Arc::new(self), move |service| {
+            service.seeding_round(&resolver)
+        });
+        Box::new(background.join(seeding).map(|_| ()))
+    }
+
+    /// Run `round` until the peer store has enough addresses, the interval between the rounds
+    /// doubles up to `MAX_RETRY_INTERVAL`
+    pub(crate) fn seeding_loop<R, F>(
+        service: Arc<Self>,
+        round: R,
+    ) -> impl Future<Item = (), Error = ()>
+    where
+        R: Fn(&DnsSeedingService) -> F,
+        F: Future<Item = (), Error = ()>,
+    {
+        future::loop_fn(INITIAL_RETRY_INTERVAL, move |retry_interval| {
+            let service = Arc::clone(&service);
+            if service.has_enough_peers() {
+                return future::Either::A(future::ok(Loop::Break(())));
+            }
+            future::Either::B(round(&service).and_then(move |_| {
+                if service.has_enough_peers() {
+                    info!(target: "network", "dns seeding finished");
+                    return future::Either::A(future::ok(Loop::Break(())));
+                }
+                debug!(
+                    target: "network",
+                    "dns seeding got too few peers, retry after {:?}",
+                    retry_interval
+                );
+                let next_interval = min(retry_interval * 2, MAX_RETRY_INTERVAL);
+                future::Either::B(
+                    service
+                        .network_state
+                        .clock()
+                        .interval(retry_interval)
+                        .into_future()
+                        .map(move |_| Loop::Continue(next_interval))
+                        .map_err(|_| ()),
+                )
+            }))
+        })
+    }
+
+    fn has_enough_peers(&self) -> bool {
+        self.network_state.with_peer_store(|peer_store| {
+            peer_store.random_peers(SEEDING_MIN_PEERS).len() >= SEEDING_MIN_PEERS as usize
+        })
+    }
+
+    fn seeding_round(&self, resolver: &AsyncResolver) -> impl Future<Item = (), Error = ()> {
+        let queries = self
+            .seeds
+            .iter()
+            .map(|seed| {
+                let seed = seed.to_owned();
+                resolver
+                    .txt_lookup(seed.as_str())
+                    .then(move |result| match result {
+                        Ok(lookup) => Ok(lookup.iter().filter_map(txt_record_text).collect()),
+                        Err(err) => {
+                            debug!(target: "network", "query dns seed {} error: {}", seed, err);
+                            Ok(Vec::new())
+                        }
+                    })
+            })
+            .collect::<Vec<_>>();
+        let network_state = Arc::clone(&self.network_state);
+        let trusted_pubkeys = self.trusted_pubkeys.clone();
+        let network_magic = self.network_magic;
+        future::join_all(queries).map(move |results| {
+            let local_peer_id = network_state.local_peer_id().clone();
+            let now = unix_time_as_millis() / 1000;
+            let peers = results
+                .into_iter()
+                .flatten()
+                .filter_map(|record| {
+                    match SeedRecord::decode_and_verify(
+                        &record,
+                        &trusted_pubkeys,
+                        network_magic,
+                        now,
+                    ) {
+                        Ok(seed_record) => Some(seed_record),
+                        Err(err) => {
+                            trace!(target: "network", "invalid seed record {}: {:?}", record, err);
+                            None
+                        }
+                    }
+                })
+                .filter(|seed_record| seed_record.peer_id != local_peer_id)
+                .collect::<Vec<_>>();
+            debug!(target: "network", "dns seeding got {} peers", peers.len());
+            network_state.with_peer_store_mut(|peer_store| {
+                for seed_record in peers {
+                    peer_store.add_discovered_addr(&seed_record.peer_id, seed_record.address);
+                }
+            });
+        })
+    }
+}
+
+/// The character strings of a TXT record joined, `None` if the text isn't UTF-8
+pub(crate) fn txt_record_text(txt: &TXT) -> Option<String> {
+    String::from_utf8(txt.iter().flat_map(|data| data.iter().cloned()).collect()).ok()
+}
+
+/// Sign the best addresses in the peer store as seed records, a seeder publishes them as TXT
+/// records for the network identified by `network_magic`.
+pub fn export_seed_records(
+    peer_store: &dyn PeerStore,
+    privkey: &Privkey,
+    network_magic: u32,
+    valid_until: u64,
+    count: u32,
+) -> Result<Vec<String>, SeedRecordError> {
+    peer_store
+        .best_peers(count)
+        .into_iter()
+        .map(|(peer_id, address)| {
+            SeedRecord {
+                address,
+                peer_id,
+                valid_until,
+                network_magic,
+            }
+            .encode(privkey)
+        })
+        .collect()
+}
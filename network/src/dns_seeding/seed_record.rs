@@ -0,0 +1,132 @@
+use crate::PeerId;
+use crypto::secp::{Privkey, Signature};
+use faster_hex::{hex_decode, hex_string};
+use hash::blake2b_256;
+use numext_fixed_hash::H256;
+use p2p::multiaddr::{Multiaddr, Protocol, ToMultiaddr};
+
+const SEPARATOR: char = ';';
+const SIGNATURE_SIZE: usize = 65;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeedRecordError {
+    /// The record can not be parsed
+    InvalidRecord,
+    InvalidSignature,
+    /// The record is not signed by any of the trusted seeder keys
+    UntrustedSigner,
+    /// The record is for another chain
+    NetworkMismatch,
+    Expired,
+}
+
+/// A peer address published by a dns seeder in a TXT record:
+/// `<multiaddr>/p2p/<peer_id>;<valid_until>;<network_magic>;<signature>`,
+/// `valid_until` is unix time in seconds, the magic and signature are hex encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeedRecord {
+    pub address: Multiaddr,
+    pub peer_id: PeerId,
+    pub valid_until: u64,
+    pub network_magic: u32,
+}
+
+impl SeedRecord {
+    /// Decode a TXT record and check it is signed by one of `trusted_pubkeys` (compressed
+    /// secp256k1 public keys) for the chain identified by `network_magic`.
+    pub fn decode_and_verify(
+        record: &str,
+        trusted_pubkeys: &[Vec<u8>],
+        network_magic: u32,
+        now: u64,
+    ) -> Result<SeedRecord, SeedRecordError> {
+        let mut parts = record.trim().split(SEPARATOR);
+        let (address, valid_until, magic, signature) = match (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) {
+            (Some(address), Some(valid_until), Some(magic), Some(signature), None) => {
+                (address, valid_until, magic, signature)
+            }
+            _ => return Err(SeedRecordError::InvalidRecord),
+        };
+
+        let mut address = address
+            .to_multiaddr()
+            .map_err(|_| SeedRecordError::InvalidRecord)?;
+        let peer_id = match address.pop() {
+            Some(Protocol::P2p(key)) => {
+                PeerId::from_bytes(key.into_bytes()).map_err(|_| SeedRecordError::InvalidRecord)?
+            }
+            _ => return Err(SeedRecordError::InvalidRecord),
+        };
+        let valid_until = valid_until
+            .parse::<u64>()
+            .map_err(|_| SeedRecordError::InvalidRecord)?;
+        let magic = u32::from_str_radix(magic, 16).map_err(|_| SeedRecordError::InvalidRecord)?;
+        let signature = decode_hex(signature, SIGNATURE_SIZE)
+            .map(Signature::from)
+            .ok_or(SeedRecordError::InvalidRecord)?;
+
+        let seed_record = SeedRecord {
+            address,
+            peer_id,
+            valid_until,
+            network_magic: magic,
+        };
+        let signer = signature
+            .recover(&seed_record.signing_message())
+            .map_err(|_| SeedRecordError::InvalidSignature)?
+            .serialize();
+        if !trusted_pubkeys.contains(&signer) {
+            return Err(SeedRecordError::UntrustedSigner);
+        }
+        if seed_record.network_magic != network_magic {
+            return Err(SeedRecordError::NetworkMismatch);
+        }
+        if seed_record.valid_until < now {
+            return Err(SeedRecordError::Expired);
+        }
+        Ok(seed_record)
+    }
+
+    /// Sign the record with the seeder key and serialize it to the TXT record format
+    pub fn encode(&self, privkey: &Privkey) -> Result<String, SeedRecordError> {
+        let signature = privkey
+            .sign_recoverable(&self.signing_message())
+            .map_err(|_| SeedRecordError::InvalidSignature)?;
+        let signature =
+            hex_string(&signature.serialize()).map_err(|_| SeedRecordError::InvalidSignature)?;
+        Ok(format!(
+            "{}/p2p/{}{sep}{}{sep}{:x}{sep}{}",
+            self.address,
+            self.peer_id.to_base58(),
+            self.valid_until,
+            self.network_magic,
+            signature,
+            sep = SEPARATOR,
+        ))
+    }
+
+    pub(crate) fn signing_message(&self) -> H256 {
+        let mut data = self.address.to_bytes();
+        data.extend_from_slice(self.peer_id.as_bytes());
+        data.extend_from_slice(&self.valid_until.to_le_bytes());
+        data.extend_from_slice(&self.network_magic.to_le_bytes());
+        blake2b_256(data).into()
+    }
+}
+
+/// Decode a hex string, an optional `0x` prefix is allowed
+pub(crate) fn decode_hex(hex: &str, size: usize) -> Option<Vec<u8>> {
+    let hex = hex.trim_start_matches("0x");
+    if hex.len() != size * 2 {
+        return None;
+    }
+    let mut bytes = vec![0u8; size];
+    hex_decode(hex.as_bytes(), &mut bytes).ok()?;
+    Some(bytes)
+}
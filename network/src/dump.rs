@@ -0,0 +1,53 @@
+//! Serializable snapshot of the network state, see `NetworkController::dump_state`
+use crate::peer_store::Score;
+use crate::ProtocolVersion;
+use serde_derive::Serialize;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct NetworkStateDump {
+    pub local_peer_id: String,
+    /// Listen and public addresses from the config
+    pub configured_addresses: Vec<String>,
+    /// Addresses of ourselves learned at runtime, with their scores
+    pub observed_addresses: Vec<(String, u8)>,
+    pub sessions: Vec<SessionDump>,
+    pub dial_queue: Vec<DialDump>,
+    pub banned_addrs: Vec<BannedAddrDump>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionDump {
+    pub session_id: usize,
+    pub peer_id: String,
+    pub address: String,
+    pub is_outbound: bool,
+    pub is_reserved: bool,
+    pub is_feeler: bool,
+    pub is_block_relay_only: bool,
+    pub connected_secs: u64,
+    pub rtt_millis: Option<u64>,
+    pub client_version: Option<String>,
+    /// Opened protocols and their negotiated versions
+    pub protocols: Vec<(usize, ProtocolVersion)>,
+    pub score: Option<Score>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DialDump {
+    /// Address without the `/p2p/` suffix
+    pub address: String,
+    /// Set while the dial is running
+    pub peer_id: Option<String>,
+    /// Failed dials in a row
+    pub failures: u32,
+    /// Seconds until the address may be dialed again
+    pub retry_in_secs: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BannedAddrDump {
+    pub ip: String,
+    /// Unix time in seconds when the ban expires
+    pub ban_until: u64,
+    pub reason: String,
+}
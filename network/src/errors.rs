@@ -1,4 +1,4 @@
-use crate::{peer_store::sqlite::DBError, ProtocolId};
+use crate::{peer_store::sqlite::DBError, ProtocolId, ProtocolVersion};
 use p2p::{secio::PeerId, SessionId};
 use std::fmt;
 use std::fmt::Display;
@@ -19,6 +19,7 @@ pub enum Error {
 pub enum ConfigError {
     BadAddress,
     InvalidKey,
+    BadProtocolVersion(ProtocolId, ProtocolVersion),
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -1,5 +1,10 @@
+pub mod bandwidth;
 mod behaviour;
+pub mod clock;
 mod config;
+mod dial_scheduler;
+pub mod dns_seeding;
+pub mod dump;
 pub mod errors;
 pub mod network;
 mod network_group;
@@ -7,19 +12,26 @@ mod peer;
 pub mod peer_registry;
 pub mod peer_store;
 mod protocols;
+mod rate_limiter;
 
 #[cfg(test)]
 mod tests;
 
 pub use crate::{
+    bandwidth::{Bandwidth, PeerBandwidth},
     behaviour::Behaviour,
     config::NetworkConfig,
+    dump::NetworkStateDump,
     errors::Error,
-    network::{NetworkController, NetworkService, NetworkState},
-    peer::{Peer, PeerIdentifyInfo},
+    network::{NetworkController, NetworkService, NetworkState, NetworkStateMetrics},
+    peer::{Peer, PeerIdentifyInfo, PeerInfo},
     peer_registry::PeerRegistry,
-    peer_store::Score,
-    protocols::{CKBProtocol, CKBProtocolContext, CKBProtocolHandler, PeerIndex},
+    peer_store::{BannedAddr, Score},
+    protocols::{
+        is_valid_version, meets_min_version, select_version, CKBProtocol, CKBProtocolContext,
+        CKBProtocolHandler, DisconnectReason, PeerIndex,
+    },
+    rate_limiter::{RateLimitConfig, RateLimitVerdict},
 };
 pub use p2p::{
     multiaddr,
@@ -32,3 +44,6 @@ pub use p2p::{
 // Max message frame length: 20MB
 pub const MAX_FRAME_LENGTH: usize = 20 * 1024 * 1024;
 pub type ProtocolVersion = String;
+/// Bit set of optional features implied by a protocol version, the meaning of the bits is
+/// defined by each protocol. They follow the negotiated version, see `CKBProtocol::with_features`
+pub type ProtocolFeatures = u64;
@@ -1,6 +1,12 @@
+use crate::bandwidth::{BandwidthMonitor, PeerBandwidth};
+use crate::clock::{Clock, SystemClock};
+use crate::dial_scheduler::{strip_peer_id, DialFailure, DialScheduler};
+use crate::dns_seeding::{export_seed_records, DnsSeedingService, SeedRecordError};
+use crate::dump::{BannedAddrDump, NetworkStateDump, SessionDump};
 use crate::errors::Error;
+use crate::network_group::MultiaddrExt;
 use crate::peer_registry::{ConnectionStatus, PeerRegistry};
-use crate::peer_store::{sqlite::SqlitePeerStore, PeerStore, Status};
+use crate::peer_store::{sqlite::SqlitePeerStore, BannedAddr, PeerStore, Status};
 use crate::protocols::feeler::Feeler;
 use crate::protocols::{
     discovery::{DiscoveryProtocol, DiscoveryService},
@@ -8,12 +14,14 @@ use crate::protocols::{
     outbound_peer::OutboundPeerService,
     ping::PingService,
 };
+use crate::rate_limiter::{RateLimitVerdict, RateLimiter};
 use crate::Peer;
 use crate::{
     Behaviour, CKBProtocol, NetworkConfig, ProtocolId, ProtocolVersion, PublicKey, ServiceControl,
 };
 use ckb_util::{Mutex, RwLock};
-use fnv::{FnvHashMap, FnvHashSet};
+use crypto::secp::Privkey;
+use fnv::FnvHashSet;
 use futures::sync::mpsc::channel;
 use futures::sync::{mpsc, oneshot};
 use futures::Future;
@@ -32,7 +40,7 @@ use p2p::{
         TargetSession,
     },
     traits::ServiceHandle,
-    utils::extract_peer_id,
+    utils::{extract_peer_id, is_reachable, multiaddr_to_socketaddr},
     SessionId,
 };
 use p2p_identify::IdentifyProtocol;
@@ -40,6 +48,7 @@ use p2p_ping::PingHandler;
 use secio;
 use std::boxed::Box;
 use std::cmp::max;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
@@ -53,10 +62,21 @@ const IDENTIFY_PROTOCOL_ID: usize = 2;
 const FEELER_PROTOCOL_ID: usize = 3;
 
 const ADDR_LIMIT: u32 = 3;
-const FAILED_DIAL_CACHE_SIZE: usize = 100;
+const IDLE_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+// Time to flush the queued messages and close the sessions on shutdown
+pub(crate) const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+// Addresses of ourselves learned at runtime, configured addresses are not counted
+const LISTENED_ADDRESSES_CACHE_SIZE: usize = 64;
 
 type MultiaddrList = Vec<(Multiaddr, u8)>;
 
+/// Sizes of the bounded caches in `NetworkState`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NetworkStateMetrics {
+    pub failed_dials: usize,
+    pub listened_addresses: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct SessionInfo {
     pub peer: Peer,
@@ -67,29 +87,39 @@ pub struct NetworkState {
     peer_registry: RwLock<PeerRegistry>,
     peer_store: Mutex<Box<dyn PeerStore>>,
     pub(crate) original_listened_addresses: RwLock<Vec<Multiaddr>>,
-    // For avoid repeat failed dial
-    pub(crate) failed_dials: RwLock<LruCache<PeerId, Instant>>,
+    // Backoff of failed dials and the running ones
+    pub(crate) dial_scheduler: Mutex<DialScheduler>,
 
     protocol_ids: RwLock<FnvHashSet<ProtocolId>>,
-    listened_addresses: RwLock<FnvHashMap<Multiaddr, u8>>,
+    // Listen and public addresses from config, they always have the max score
+    configured_addresses: Vec<Multiaddr>,
+    listened_addresses: RwLock<LruCache<Multiaddr, u8>>,
     // Send disconnect message but not disconnected yet
     disconnecting_sessions: RwLock<FnvHashSet<SessionId>>,
+    // New sessions are refused once the shutdown starts
+    shutting_down: AtomicBool,
     local_private_key: secio::SecioKeyPair,
     local_peer_id: PeerId,
-    config: NetworkConfig,
+    pub(crate) config: NetworkConfig,
+    clock: Arc<dyn Clock>,
+    bandwidth: Mutex<BandwidthMonitor>,
+    rate_limiter: Mutex<RateLimiter>,
 }
 
 impl NetworkState {
     pub fn from_config(config: NetworkConfig) -> Result<NetworkState, Error> {
         config.create_dir_if_not_exists()?;
         let local_private_key = config.fetch_private_key()?;
-        // set max score to public addresses
-        let listened_addresses: FnvHashMap<Multiaddr, u8> = config
+        let mut configured_addresses: Vec<Multiaddr> = Vec::new();
+        for addr in config
             .listen_addresses
             .iter()
             .chain(config.public_addresses.iter())
-            .map(|addr| (addr.to_owned(), std::u8::MAX))
-            .collect();
+        {
+            if !configured_addresses.contains(addr) {
+                configured_addresses.push(addr.to_owned());
+            }
+        }
         let peer_store: Mutex<Box<dyn PeerStore>> = {
             let mut peer_store =
                 SqlitePeerStore::file(config.peer_store_path().to_string_lossy().to_string())?;
@@ -110,22 +140,76 @@ impl NetworkState {
             config.max_outbound_peers(),
             config.reserved_only,
             reserved_peers,
-        );
+        )
+        .with_max_block_relay_only(config.block_relay_only_peers);
+
+        let rate_limiter = Mutex::new(RateLimiter::new(&config.rate_limits));
 
         Ok(NetworkState {
             peer_store,
             config,
             peer_registry: RwLock::new(peer_registry),
-            failed_dials: RwLock::new(LruCache::new(FAILED_DIAL_CACHE_SIZE)),
-            listened_addresses: RwLock::new(listened_addresses),
+            dial_scheduler: Mutex::new(DialScheduler::default()),
+            configured_addresses,
+            listened_addresses: RwLock::new(LruCache::new(LISTENED_ADDRESSES_CACHE_SIZE)),
             original_listened_addresses: RwLock::new(Vec::new()),
             disconnecting_sessions: RwLock::new(FnvHashSet::default()),
+            shutting_down: AtomicBool::new(false),
             local_private_key: local_private_key.clone(),
             local_peer_id: local_private_key.to_public_key().peer_id(),
             protocol_ids: RwLock::new(FnvHashSet::default()),
+            clock: Arc::new(SystemClock),
+            bandwidth: Mutex::new(BandwidthMonitor::new(Instant::now())),
+            rate_limiter,
         })
     }
 
+    /// Replace the system clock, mainly for tests
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.bandwidth = Mutex::new(BandwidthMonitor::new(clock.now()));
+        self.clock = clock;
+        self
+    }
+
+    pub(crate) fn record_sent(&self, target: &TargetSession, proto_id: ProtocolId, len: usize) {
+        let sessions = match target {
+            TargetSession::Single(session_id) => vec![*session_id],
+            TargetSession::Multi(session_ids) => session_ids.clone(),
+            TargetSession::All => self.with_peer_registry(PeerRegistry::connected_peers),
+        };
+        let now = self.clock.now();
+        let mut bandwidth = self.bandwidth.lock();
+        for session_id in sessions {
+            bandwidth.record_sent(now, session_id, proto_id, len);
+        }
+    }
+
+    pub(crate) fn record_received(&self, session_id: SessionId, proto_id: ProtocolId, len: usize) {
+        let now = self.clock.now();
+        self.bandwidth
+            .lock()
+            .record_received(now, session_id, proto_id, len);
+    }
+
+    /// Take a token from the message budget of `proto_id` for `session_id`
+    pub(crate) fn check_rate_limit(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+    ) -> RateLimitVerdict {
+        let now = self.clock.now();
+        self.rate_limiter.lock().check(now, session_id, proto_id)
+    }
+
+    pub fn peer_bandwidth(&self) -> FnvHashMap<SessionId, PeerBandwidth> {
+        let now = self.clock.now();
+        self.bandwidth.lock().peer_bandwidth(now)
+    }
+
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
     pub(crate) fn report_session(
         &self,
         p2p_control: &ServiceControl,
@@ -171,11 +255,12 @@ impl NetworkState {
         p2p_control: &ServiceControl,
         session_id: SessionId,
         timeout: Duration,
+        reason: String,
     ) {
         if let Some(peer_id) =
             self.with_peer_registry(|reg| reg.get_peer(session_id).map(|peer| peer.peer_id.clone()))
         {
-            self.ban_peer(p2p_control, &peer_id, timeout);
+            self.ban_peer(p2p_control, &peer_id, timeout, reason);
         } else {
             debug!(target: "network", "Ban session({}) failed: not in peer registry", session_id);
         }
@@ -186,9 +271,10 @@ impl NetworkState {
         p2p_control: &ServiceControl,
         peer_id: &PeerId,
         timeout: Duration,
+        reason: String,
     ) {
-        info!(target: "network", "ban peer {:?} with {:?}", peer_id, timeout);
-        self.peer_store.lock().ban_peer(peer_id, timeout);
+        info!(target: "network", "ban peer {:?} with {:?}, reason: {}", peer_id, timeout, reason);
+        self.peer_store.lock().ban_peer(peer_id, timeout, reason);
         self.with_peer_registry_mut(|reg| {
             if let Some(session_id) = reg.get_key_by_peer_id(peer_id) {
                 reg.remove_peer(session_id);
@@ -199,6 +285,35 @@ impl NetworkState {
         });
     }
 
+    pub(crate) fn ban_addr(
+        &self,
+        p2p_control: &ServiceControl,
+        addr: &Multiaddr,
+        timeout: Duration,
+        reason: String,
+    ) {
+        info!(target: "network", "ban addr {} with {:?}, reason: {}", addr, timeout, reason);
+        let ip = match addr.extract_ip_addr() {
+            Some(ip) => ip,
+            None => return,
+        };
+        self.peer_store.lock().ban_addr(addr, timeout, reason);
+        self.with_peer_registry_mut(|reg| {
+            let banned_sessions = reg
+                .peers()
+                .values()
+                .filter(|peer| peer.address.extract_ip_addr() == Some(ip))
+                .map(|peer| peer.session_id)
+                .collect::<Vec<_>>();
+            for session_id in banned_sessions {
+                reg.remove_peer(session_id);
+                if let Err(err) = p2p_control.disconnect(session_id) {
+                    error!(target: "network", "send message to p2p service error: {:?}", err);
+                }
+            }
+        });
+    }
+
     pub(crate) fn query_session_id(&self, peer_id: &PeerId) -> Option<SessionId> {
         let mut target_session_id = None;
         // Create a scope for avoid dead lock
@@ -285,15 +400,143 @@ impl NetworkState {
         self.local_private_key().to_peer_id().to_base58()
     }
 
+    /// New sessions are refused once the shutdown starts
+    pub(crate) fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
     pub(crate) fn listened_addresses(&self, count: usize) -> Vec<(Multiaddr, u8)> {
-        self.listened_addresses
-            .read()
+        let listened_addresses = self.listened_addresses.read();
+        self.configured_addresses
             .iter()
+            .map(|addr| (addr.to_owned(), std::u8::MAX))
+            .chain(
+                listened_addresses
+                    .iter()
+                    .filter(|(addr, _)| !self.configured_addresses.contains(addr))
+                    .map(|(addr, score)| (addr.to_owned(), *score)),
+            )
             .take(count)
-            .map(|(addr, score)| (addr.to_owned(), *score))
             .collect()
     }
 
+    /// Whether an address is worth storing and gossiping, unroutable addresses (loopback,
+    /// private, link local, ...) are only allowed with `discovery_local_address`
+    pub(crate) fn is_advertisable(&self, addr: &Multiaddr) -> bool {
+        if self.config.discovery_local_address {
+            return true;
+        }
+        multiaddr_to_socketaddr(addr)
+            .map(|socket_addr| is_reachable(socket_addr.ip()))
+            .unwrap_or(false)
+    }
+
+    /// Our external address confirmed by the config or by dialing ourselves, advertised to
+    /// peers through discovery
+    pub(crate) fn self_advertised_addr(&self) -> Option<Multiaddr> {
+        self.listened_addresses(LISTENED_ADDRESSES_CACHE_SIZE)
+            .into_iter()
+            .filter(|(addr, _)| self.is_advertisable(addr))
+            .max_by_key(|(_, score)| *score)
+            .map(|(addr, _)| addr)
+    }
+
+    fn is_listened_address(&self, addr: &Multiaddr) -> bool {
+        self.configured_addresses.contains(addr)
+            || self.listened_addresses.read().contains_key(addr)
+    }
+
+    /// Drop the lowest quality addresses over `max_peer_store_addrs` from the peer store
+    pub(crate) fn prune_peer_store(&self) {
+        let protected = self.with_peer_registry(PeerRegistry::reserved_peers);
+        let max_addrs = self.config.max_peer_store_addrs;
+        let pruned =
+            self.with_peer_store_mut(|peer_store| peer_store.prune_addrs(max_addrs, &protected));
+        if pruned > 0 {
+            debug!(target: "network", "peer store is full, drop {} addrs", pruned);
+        }
+    }
+
+    /// Forget an address which failed to dial too many times
+    pub(crate) fn prune_addr(&self, peer_id: &PeerId, addr: &Multiaddr) {
+        debug!(target: "network", "prune addr {} of {:?}, too many failed dials", addr, peer_id);
+        let addr = strip_peer_id(addr);
+        self.with_peer_store_mut(|peer_store| peer_store.remove_addr(peer_id, &addr));
+    }
+
+    pub fn metrics(&self) -> NetworkStateMetrics {
+        NetworkStateMetrics {
+            failed_dials: self.dial_scheduler.lock().len(),
+            listened_addresses: self.listened_addresses.read().len(),
+        }
+    }
+
+    pub fn dump_state(&self) -> NetworkStateDump {
+        let now = Instant::now();
+        let peers =
+            self.with_peer_registry(|reg| reg.peers().values().cloned().collect::<Vec<_>>());
+        let (sessions, banned_addrs) = self.with_peer_store(|peer_store| {
+            let mut sessions = peers
+                .into_iter()
+                .map(|peer| {
+                    let mut protocols: Vec<(usize, ProtocolVersion)> = peer
+                        .protocols
+                        .iter()
+                        .map(|(proto_id, version)| (proto_id.value(), version.to_owned()))
+                        .collect();
+                    protocols.sort();
+                    SessionDump {
+                        session_id: peer.session_id.value(),
+                        peer_id: peer.peer_id.to_base58(),
+                        address: peer.address.to_string(),
+                        is_outbound: peer.is_outbound(),
+                        is_reserved: peer.is_reserved,
+                        is_feeler: peer.is_feeler,
+                        is_block_relay_only: peer.is_block_relay_only,
+                        connected_secs: (now - peer.connected_time).as_secs(),
+                        rtt_millis: peer
+                            .rtt
+                            .map(|rtt| rtt.as_secs() * 1000 + u64::from(rtt.subsec_millis())),
+                        client_version: peer
+                            .identify_info
+                            .as_ref()
+                            .map(|info| info.client_version.to_owned()),
+                        protocols,
+                        score: peer_store.peer_score(&peer.peer_id),
+                    }
+                })
+                .collect::<Vec<_>>();
+            sessions.sort_by_key(|session| session.session_id);
+            let banned_addrs = peer_store
+                .banned_addrs()
+                .into_iter()
+                .map(|banned| BannedAddrDump {
+                    ip: banned.ip.to_string(),
+                    ban_until: banned.ban_until.as_secs(),
+                    reason: banned.reason,
+                })
+                .collect();
+            (sessions, banned_addrs)
+        });
+        NetworkStateDump {
+            local_peer_id: self.local_peer_id.to_base58(),
+            configured_addresses: self
+                .configured_addresses
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            observed_addresses: self
+                .listened_addresses
+                .read()
+                .iter()
+                .map(|(addr, score)| (addr.to_string(), *score))
+                .collect(),
+            sessions,
+            dial_queue: self.dial_scheduler.lock().dump(now),
+            banned_addrs,
+        }
+    }
+
     pub(crate) fn connection_status(&self) -> ConnectionStatus {
         self.peer_registry.read().connection_status()
     }
@@ -325,6 +568,60 @@ impl NetworkState {
         }
     }
 
+    pub(crate) fn add_reserved_peer(
+        &self,
+        p2p_control: &ServiceControl,
+        peer_id: &PeerId,
+        address: Multiaddr,
+    ) {
+        info!(target: "network", "add reserved peer {:?} {}", peer_id, address);
+        let connected = self.with_peer_registry_mut(|reg| {
+            reg.add_reserved_peer(peer_id.to_owned());
+            reg.get_key_by_peer_id(peer_id).is_some()
+        });
+        if !connected {
+            self.add_node(p2p_control, peer_id, address);
+        }
+    }
+
+    pub(crate) fn remove_reserved_peer(&self, p2p_control: &ServiceControl, peer_id: &PeerId) {
+        info!(target: "network", "remove reserved peer {:?}", peer_id);
+        if let Some(session_id) =
+            self.with_peer_registry_mut(|reg| reg.remove_reserved_peer(peer_id))
+        {
+            debug!(target: "network", "disconnect {} which is no longer reserved", session_id);
+            if let Err(err) = p2p_control.disconnect(session_id) {
+                error!(target: "network", "send message to p2p service error: {:?}", err);
+            }
+        }
+    }
+
+    pub(crate) fn set_max_outbound(&self, p2p_control: &ServiceControl, max_outbound: u32) {
+        info!(target: "network", "set max outbound peers to {}", max_outbound);
+        for session_id in self.with_peer_registry_mut(|reg| reg.set_max_outbound(max_outbound)) {
+            debug!(target: "network", "disconnect {} over the outbound limit", session_id);
+            if let Err(err) = p2p_control.disconnect(session_id) {
+                error!(target: "network", "send message to p2p service error: {:?}", err);
+            }
+        }
+    }
+
+    /// Disconnect the sessions without traffic on any protocol for `idle_timeout_secs`, e.g.
+    /// the ones which never opened the ping protocol and went silent
+    pub(crate) fn disconnect_idle_sessions(&self, p2p_control: &ServiceControl) {
+        let idle_timeout = Duration::from_secs(self.config.idle_timeout_secs);
+        let now = self.clock.now();
+        for session_id in self.with_peer_registry(|reg| reg.idle_sessions(now, idle_timeout)) {
+            if !self.disconnecting_sessions.write().insert(session_id) {
+                continue;
+            }
+            debug!(target: "network", "disconnect {} idle for {:?}", session_id, idle_timeout);
+            if let Err(err) = p2p_control.disconnect(session_id) {
+                error!(target: "network", "send message to p2p service error: {:?}", err);
+            }
+        }
+    }
+
     fn to_external_url(&self, addr: &Multiaddr) -> String {
         format!("{}/p2p/{}", addr, self.node_id())
     }
@@ -345,7 +642,7 @@ impl NetworkState {
         mut addr: Multiaddr,
         target: DialProtocol,
     ) {
-        if !self.listened_addresses.read().contains_key(&addr) {
+        if !self.is_listened_address(&addr) {
             match Multihash::from_bytes(peer_id.as_bytes().to_vec()) {
                 Ok(peer_id_hash) => {
                     addr.append(multiaddr::Protocol::P2p(peer_id_hash));
@@ -407,11 +704,17 @@ impl ServiceHandle for EventHandler {
                 if let Some(peer_id) = extract_peer_id(address) {
                     self.network_state.with_peer_registry_mut(|reg| {
                         reg.remove_feeler(&peer_id);
+                        reg.remove_block_relay_only_dial(&peer_id);
                     });
-                    self.network_state
-                        .failed_dials
-                        .write()
-                        .insert(peer_id, Instant::now());
+                    let now = self.network_state.clock.now();
+                    let failure = self
+                        .network_state
+                        .dial_scheduler
+                        .lock()
+                        .dial_failed(address, now);
+                    if failure == DialFailure::Prune {
+                        self.network_state.prune_addr(&peer_id, address);
+                    }
                 }
             }
             ServiceError::ProtocolError {
@@ -459,6 +762,24 @@ impl ServiceHandle for EventHandler {
                     session_context.id,
                     session_context.address,
                 );
+                if self.network_state.is_shutting_down() {
+                    debug!(
+                        target: "network",
+                        "shutting down, disconnect {}",
+                        session_context.id
+                    );
+                    context.disconnect(session_context.id);
+                    return;
+                }
+                let handshake = if session_context.ty.is_outbound() {
+                    let now = self.network_state.clock.now();
+                    self.network_state
+                        .dial_scheduler
+                        .lock()
+                        .dial_succeeded(&session_context.address, now)
+                } else {
+                    None
+                };
                 let peer_id = session_context
                     .remote_pubkey
                     .as_ref()
@@ -470,10 +791,16 @@ impl ServiceHandle for EventHandler {
                 {
                     debug!(
                         target: "network",
-                        "feeler connected {} => {}",
+                        "feeler connected {} => {} in {:?}",
                         session_context.id,
                         session_context.address,
+                        handshake,
                     );
+                    if let Some(handshake) = handshake {
+                        self.network_state.with_peer_store_mut(|peer_store| {
+                            peer_store.record_feeler(&peer_id, handshake)
+                        });
+                    }
                 } else {
                     match self.network_state.accept_peer(&session_context) {
                         Ok(Some(evicted_peer)) => {
@@ -515,6 +842,14 @@ impl ServiceHandle for EventHandler {
                     .disconnecting_sessions
                     .write()
                     .remove(&session_context.id);
+                self.network_state
+                    .bandwidth
+                    .lock()
+                    .remove_session(session_context.id);
+                self.network_state
+                    .rate_limiter
+                    .lock()
+                    .remove_session(session_context.id);
                 let peer_exists = self
                     .network_state
                     .peer_registry
@@ -569,12 +904,15 @@ impl ServiceHandle for EventHandler {
                         .network_state
                         .with_peer_registry(|reg| reg.is_feeler(&peer_id))
                     {
+                        // Sessions refused by `accept_peer` (e.g. banned ones) must not
+                        // keep using any protocol
                         warn!(
                             target: "network",
-                            "Invalid session {}, protocol id {}",
+                            "Invalid session {}, protocol id {}, disconnect it",
                             session_context.id,
                             proto_id,
                         );
+                        context.disconnect(session_context.id);
                     }
                 }
             }
@@ -582,13 +920,18 @@ impl ServiceHandle for EventHandler {
                 // Do nothing
             }
             ProtocolEvent::Received {
-                session_context, ..
+                session_context,
+                proto_id,
+                data,
             } => {
                 let session_id = session_context.id;
+                self.network_state
+                    .record_received(session_id, proto_id, data.len());
+                let now = self.network_state.clock.now();
                 let peer_not_exists = self.network_state.with_peer_registry_mut(|reg| {
                     reg.get_peer_mut(session_id)
                         .map(|peer| {
-                            peer.last_message_time = Some(Instant::now());
+                            peer.last_message_time = Some(now);
                         })
                         .is_none()
                 });
@@ -629,7 +972,8 @@ impl NetworkService {
 
         // == Build special protocols
 
-        // TODO: how to deny banned node to open those protocols?
+        // Banned nodes are refused in `accept_peer`, the protocols they open anyway are
+        // closed in `EventHandler::handle_proto`
         // Ping protocol
         let (ping_sender, ping_receiver) = channel(std::u8::MAX as usize);
         let ping_interval = Duration::from_secs(config.ping_interval_secs);
@@ -710,11 +1054,32 @@ impl NetworkService {
             p2p_service.control().clone(),
             Duration::from_secs(config.connect_outbound_interval_secs),
         );
-        let bg_services = vec![
+        let mut bg_services = vec![
             Box::new(ping_service.for_each(|_| Ok(()))) as Box<_>,
             Box::new(disc_service.for_each(|_| Ok(()))) as Box<_>,
             Box::new(outbound_peer_service.for_each(|_| Ok(()))) as Box<_>,
         ];
+        if !config.dns_seeds.is_empty() {
+            let dns_seeding_service = DnsSeedingService::new(
+                Arc::clone(&network_state),
+                config.dns_seeds.clone(),
+                config.dns_seed_pubkeys(),
+                config.network_magic,
+            );
+            bg_services.push(dns_seeding_service.seeding());
+        }
+        if config.idle_timeout_secs > 0 {
+            let network_state = Arc::clone(&network_state);
+            let p2p_control = p2p_service.control().clone();
+            let idle_sweeper = network_state
+                .clock()
+                .interval(IDLE_SWEEP_INTERVAL)
+                .for_each(move |_| {
+                    network_state.disconnect_idle_sessions(&p2p_control);
+                    Ok(())
+                });
+            bg_services.push(Box::new(idle_sweeper));
+        }
 
         NetworkService {
             p2p_service,
@@ -783,7 +1148,11 @@ impl NetworkService {
             .spawn(move || {
                 let inner_p2p_control = self.p2p_service.control().clone();
                 let mut runtime = Runtime::new().expect("Network tokio runtime init failed");
-                runtime.spawn(self.p2p_service.for_each(|_| Ok(())));
+                let (p2p_stopped_sender, p2p_stopped_receiver) = crossbeam_channel::bounded(1);
+                runtime.spawn(self.p2p_service.for_each(|_| Ok(())).then(move |_| {
+                    let _ = p2p_stopped_sender.send(());
+                    Ok(())
+                }));
 
                 // NOTE: for ensure background task finished
                 let bg_signals = self
@@ -804,20 +1173,65 @@ impl NetworkService {
 
                 // Recevied stop signal, doing cleanup
                 let _ = receiver.recv();
+                let deadline = Instant::now() + SHUTDOWN_FLUSH_TIMEOUT;
+                // Refuse new sessions, and drop senders to stop all corresponding background
+                // task, so no more dials and gossips
+                self.network_state
+                    .shutting_down
+                    .store(true, Ordering::SeqCst);
+                drop(bg_signals);
+
+                // Disconnect after the queued messages are sent, the closed sessions update
+                // the peer store in `SessionClose`
                 for peer in self.network_state.peer_registry.read().peers().values() {
                     info!(target: "network", "disconnect peer {}", peer.address);
                     if let Err(err) = inner_p2p_control.disconnect(peer.session_id) {
                         warn!(target: "network", "send disconnect message to p2p error: {:?}", err);
                     }
                 }
-                // Drop senders to stop all corresponding background task
-                drop(bg_signals);
+                while Instant::now() < deadline
+                    && !self.network_state.peer_registry.read().peers().is_empty()
+                {
+                    thread::sleep(Duration::from_millis(50));
+                }
+                let unclosed: Vec<PeerId> = self.network_state.with_peer_registry(|reg| {
+                    reg.peers()
+                        .values()
+                        .map(|peer| peer.peer_id.clone())
+                        .collect()
+                });
+                if !unclosed.is_empty() {
+                    warn!(
+                        target: "network",
+                        "{} sessions are not closed in {:?}",
+                        unclosed.len(),
+                        SHUTDOWN_FLUSH_TIMEOUT
+                    );
+                    self.network_state.with_peer_store_mut(|peer_store| {
+                        for peer_id in &unclosed {
+                            peer_store.update_status(peer_id, Status::Disconnected);
+                        }
+                    });
+                }
+
                 if let Err(err) = inner_p2p_control.shutdown() {
                     warn!(target: "network", "send shutdown message to p2p error: {:?}", err);
                 }
-
-                debug!(target: "network", "Waiting tokio runtime to finish ...");
-                runtime.shutdown_on_idle().wait().unwrap();
+                debug!(target: "network", "Waiting p2p service to finish ...");
+                // leave the p2p service a moment to close even if the sessions took all time
+                let now = Instant::now();
+                let remaining = if deadline > now {
+                    deadline - now
+                } else {
+                    Duration::from_millis(0)
+                };
+                if p2p_stopped_receiver
+                    .recv_timeout(max(remaining, Duration::from_secs(1)))
+                    .is_err()
+                {
+                    warn!(target: "network", "p2p service does not stop in time, abort it");
+                }
+                runtime.shutdown_now().wait().unwrap();
                 debug!(target: "network", "Shutdown network service finished!");
             })
             .expect("Start NetworkService fialed");
@@ -851,6 +1265,24 @@ impl NetworkController {
             .add_node(&self.p2p_control, peer_id, address)
     }
 
+    /// Reserve slots for a peer and dial it if not connected, until restart
+    pub fn add_reserved_peer(&self, peer_id: &PeerId, address: Multiaddr) {
+        self.network_state
+            .add_reserved_peer(&self.p2p_control, peer_id, address)
+    }
+
+    /// Release the slots of a reserved peer, it is disconnected in reserved only mode
+    pub fn remove_reserved_peer(&self, peer_id: &PeerId) {
+        self.network_state
+            .remove_reserved_peer(&self.p2p_control, peer_id)
+    }
+
+    /// Change the outbound peers limit, unreserved outbound peers over it are disconnected
+    pub fn set_max_outbound(&self, max_outbound: u32) {
+        self.network_state
+            .set_max_outbound(&self.p2p_control, max_outbound)
+    }
+
     pub fn connected_peers(&self) -> Vec<(PeerId, Peer, MultiaddrList)> {
         let peers = self
             .network_state
@@ -875,21 +1307,89 @@ impl NetworkController {
         })
     }
 
+    pub fn ban_peer(&self, peer_id: &PeerId, timeout: Duration, reason: String) {
+        self.network_state
+            .ban_peer(&self.p2p_control, peer_id, timeout, reason)
+    }
+
+    pub fn ban_addr(&self, addr: &Multiaddr, timeout: Duration, reason: String) {
+        self.network_state
+            .ban_addr(&self.p2p_control, addr, timeout, reason)
+    }
+
+    pub fn list_banned(&self) -> Vec<BannedAddr> {
+        self.network_state
+            .with_peer_store(|peer_store| peer_store.banned_addrs())
+    }
+
+    pub fn metrics(&self) -> NetworkStateMetrics {
+        self.network_state.metrics()
+    }
+
+    /// Snapshot of sessions, dials, addresses and bans for debugging connectivity
+    pub fn dump_state(&self) -> NetworkStateDump {
+        self.network_state.dump_state()
+    }
+
+    /// Signed seed records of the best `count` known peers, for running a dns seeder
+    pub fn seed_records(
+        &self,
+        privkey: &Privkey,
+        valid_until: u64,
+        count: u32,
+    ) -> Result<Vec<String>, SeedRecordError> {
+        let network_magic = self.network_state.config.network_magic;
+        self.network_state.with_peer_store(|peer_store| {
+            export_seed_records(peer_store, privkey, network_magic, valid_until, count)
+        })
+    }
+
+    /// Traffic of connected sessions, in total and per protocol
+    pub fn peer_bandwidth(&self) -> FnvHashMap<SessionId, PeerBandwidth> {
+        self.network_state.peer_bandwidth()
+    }
+
     pub fn broadcast(&self, proto_id: ProtocolId, data: Bytes) {
-        let session_ids = self.network_state.peer_registry.read().connected_peers();
-        if let Err(err) =
-            self.p2p_control
-                .filter_broadcast(TargetSession::Multi(session_ids), proto_id, data)
-        {
+        if let Err(err) = self.try_broadcast(proto_id, data) {
             warn!(target: "network", "broadcast message to {} failed: {:?}", proto_id, err);
         }
     }
 
+    /// Broadcast to all connected peers, return the error instead of logging it so the
+    /// caller can back off when the p2p service is busy
+    pub fn try_broadcast(&self, proto_id: ProtocolId, data: Bytes) -> Result<(), Error> {
+        let session_ids = self.network_state.peer_registry.read().connected_peers();
+        let len = data.len();
+        self.p2p_control
+            .filter_broadcast(TargetSession::Multi(session_ids.clone()), proto_id, data)
+            .map_err(|err| Error::P2P(format!("{:?}", err)))?;
+        self.network_state
+            .record_sent(&TargetSession::Multi(session_ids), proto_id, len);
+        Ok(())
+    }
+
     pub fn send_message_to(&self, session_id: SessionId, proto_id: ProtocolId, data: Bytes) {
-        if let Err(err) = self.p2p_control.send_message_to(session_id, proto_id, data) {
+        if let Err(err) = self.try_send_message_to(session_id, proto_id, data) {
             warn!(target: "network", "send message to {} {} failed: {:?}", session_id, proto_id, err);
         }
     }
+
+    /// Send to a single session, return the error instead of logging it so the caller
+    /// can back off when the p2p service is busy
+    pub fn try_send_message_to(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        data: Bytes,
+    ) -> Result<(), Error> {
+        let len = data.len();
+        self.p2p_control
+            .send_message_to(session_id, proto_id, data)
+            .map_err(|err| Error::P2P(format!("{:?}", err)))?;
+        self.network_state
+            .record_sent(&TargetSession::Single(session_id), proto_id, len);
+        Ok(())
+    }
 }
 
 impl Drop for NetworkController {
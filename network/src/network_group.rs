@@ -53,13 +53,22 @@ impl NetworkGroup for Multiaddr {
                 let bits = ipv4.octets();
                 return Group::IP4([bits[0], bits[1]]);
             }
-            // IPv6 NetworkGroup
+            // IPv6 NetworkGroup, the tunnels embedding an IPv4 address are grouped by it, so
+            // that a single IPv4 subnet can't spread over many IPv6 groups
             if let IpAddr::V6(ipv6) = ip_addr {
                 if let Some(ipv4) = ipv6.to_ipv4() {
                     let bits = ipv4.octets();
                     return Group::IP4([bits[0], bits[1]]);
                 }
                 let bits = ipv6.octets();
+                // 6to4, 2002:AABB:CCDD::/48 embeds AA.BB.CC.DD
+                if bits[0] == 0x20 && bits[1] == 0x02 {
+                    return Group::IP4([bits[2], bits[3]]);
+                }
+                // Teredo, 2001:0000::/32 embeds the client address inverted in the last 32 bits
+                if bits[0] == 0x20 && bits[1] == 0x01 && bits[2] == 0 && bits[3] == 0 {
+                    return Group::IP4([!bits[12], !bits[13]]);
+                }
                 return Group::IP6([bits[0], bits[1], bits[2], bits[3]]);
             }
         }
@@ -1,5 +1,6 @@
 use crate::network_group::{Group, NetworkGroup};
-use crate::{multiaddr::Multiaddr, ProtocolId, ProtocolVersion, SessionType};
+use crate::peer_store::Score;
+use crate::{multiaddr::Multiaddr, ProtocolFeatures, ProtocolId, ProtocolVersion, SessionType};
 use fnv::FnvHashMap;
 use p2p::{secio::PeerId, SessionId};
 use std::time::{Duration, Instant};
@@ -21,12 +22,18 @@ pub struct Peer {
     pub last_ping_time: Option<Instant>,
     pub last_message_time: Option<Instant>,
     pub ping: Option<Duration>,
+    /// Smoothed ping round trip time
+    pub rtt: Option<Duration>,
     pub is_feeler: bool,
     pub connected_time: Instant,
     pub session_id: SessionId,
     pub session_type: SessionType,
     pub protocols: FnvHashMap<ProtocolId, ProtocolVersion>,
+    /// Features implied by the negotiated protocol versions
+    pub protocol_features: FnvHashMap<ProtocolId, ProtocolFeatures>,
     pub is_reserved: bool,
+    /// Connected in an outbound slot for block relay only, transactions are not relayed with it
+    pub is_block_relay_only: bool,
 }
 
 impl Peer {
@@ -41,6 +48,7 @@ impl Peer {
             address,
             identify_info: None,
             ping: None,
+            rtt: None,
             last_ping_time: None,
             last_message_time: None,
             connected_time: Instant::now(),
@@ -49,7 +57,9 @@ impl Peer {
             session_id,
             session_type,
             protocols: FnvHashMap::with_capacity_and_hasher(1, Default::default()),
+            protocol_features: FnvHashMap::default(),
             is_reserved,
+            is_block_relay_only: false,
         }
     }
 
@@ -61,6 +71,15 @@ impl Peer {
         self.session_type.is_inbound()
     }
 
+    /// Record a ping sample, `rtt` moves 1/8 of the way towards it like TCP's SRTT
+    pub fn update_rtt(&mut self, sample: Duration) {
+        self.ping = Some(sample);
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => (rtt * 7 + sample) / 8,
+            None => sample,
+        });
+    }
+
     pub fn network_group(&self) -> Group {
         self.address.network_group()
     }
@@ -68,4 +87,67 @@ impl Peer {
     pub fn protocol_version(&self, protocol_id: ProtocolId) -> Option<ProtocolVersion> {
         self.protocols.get(&protocol_id).cloned()
     }
+
+    pub fn has_protocol_feature(&self, protocol_id: ProtocolId, feature: ProtocolFeatures) -> bool {
+        self.protocol_features
+            .get(&protocol_id)
+            .map(|features| features & feature == feature)
+            .unwrap_or(false)
+    }
+}
+
+/// Read only snapshot of a connected peer's metadata, handed to protocol handlers
+#[derive(Clone, Debug)]
+pub struct PeerInfo {
+    pub peer_id: PeerId,
+    pub session_type: SessionType,
+    /// Negotiated protocol versions
+    pub protocols: FnvHashMap<ProtocolId, ProtocolVersion>,
+    /// Features implied by the negotiated protocol versions
+    pub protocol_features: FnvHashMap<ProtocolId, ProtocolFeatures>,
+    /// Latest ping round trip time
+    pub ping: Option<Duration>,
+    /// Smoothed ping round trip time, steadier than `ping` for ranking peers
+    pub rtt: Option<Duration>,
+    /// Misbehavior score recorded in the peer store, lower is worse
+    pub score: Option<Score>,
+    /// Peer is one of the configured reserved peers
+    pub is_whitelisted: bool,
+    /// Transactions are not relayed with the peer
+    pub is_block_relay_only: bool,
+}
+
+impl PeerInfo {
+    pub fn new(peer: &Peer, score: Option<Score>) -> Self {
+        PeerInfo {
+            peer_id: peer.peer_id.clone(),
+            session_type: peer.session_type,
+            protocols: peer.protocols.clone(),
+            protocol_features: peer.protocol_features.clone(),
+            ping: peer.ping,
+            rtt: peer.rtt,
+            score,
+            is_whitelisted: peer.is_reserved,
+            is_block_relay_only: peer.is_block_relay_only,
+        }
+    }
+
+    pub fn is_outbound(&self) -> bool {
+        self.session_type.is_outbound()
+    }
+
+    pub fn is_inbound(&self) -> bool {
+        self.session_type.is_inbound()
+    }
+
+    pub fn protocol_version(&self, protocol_id: ProtocolId) -> Option<ProtocolVersion> {
+        self.protocols.get(&protocol_id).cloned()
+    }
+
+    pub fn has_protocol_feature(&self, protocol_id: ProtocolId, feature: ProtocolFeatures) -> bool {
+        self.protocol_features
+            .get(&protocol_id)
+            .map(|features| features & feature == feature)
+            .unwrap_or(false)
+    }
 }
@@ -1,3 +1,4 @@
+use crate::network_group::Group;
 use crate::peer_store::PeerStore;
 use crate::{errors::PeerError, Peer, PeerId, SessionType};
 use fnv::{FnvHashMap, FnvHashSet};
@@ -5,6 +6,7 @@ use log::debug;
 use p2p::{multiaddr::Multiaddr, SessionId};
 use rand::seq::SliceRandom;
 use rand::thread_rng;
+use std::time::{Duration, Instant};
 
 pub(crate) const EVICTION_PROTECT_PEERS: usize = 8;
 
@@ -14,10 +16,14 @@ pub struct PeerRegistry {
     max_inbound: u32,
     // max outbound limitation
     max_outbound: u32,
+    // outbound slots of block relay only peers, not counted in `max_outbound`
+    max_block_relay_only: u32,
     // Only reserved peers or allow all peers.
     reserved_only: bool,
     reserved_peers: FnvHashSet<PeerId>,
     feeler_peers: FnvHashSet<PeerId>,
+    // peers dialed for the block relay only slots
+    block_relay_only_dials: FnvHashSet<PeerId>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -25,8 +31,10 @@ pub struct ConnectionStatus {
     pub total: u32,
     pub unreserved_inbound: u32,
     pub unreserved_outbound: u32,
+    pub block_relay_only_outbound: u32,
     pub max_inbound: u32,
     pub max_outbound: u32,
+    pub max_block_relay_only: u32,
 }
 
 fn sort_then_drop<T, F>(list: &mut Vec<T>, n: usize, compare: F)
@@ -55,12 +63,19 @@ impl PeerRegistry {
             peers: FnvHashMap::with_capacity_and_hasher(20, Default::default()),
             reserved_peers: reserved_peers_set,
             feeler_peers: FnvHashSet::default(),
+            block_relay_only_dials: FnvHashSet::default(),
             max_inbound,
             max_outbound,
+            max_block_relay_only: 0,
             reserved_only,
         }
     }
 
+    pub fn with_max_block_relay_only(mut self, max_block_relay_only: u32) -> Self {
+        self.max_block_relay_only = max_block_relay_only;
+        self
+    }
+
     pub(crate) fn accept_peer(
         &mut self,
         peer_id: PeerId,
@@ -77,6 +92,9 @@ impl PeerRegistry {
         }
 
         let is_reserved = self.reserved_peers.contains(&peer_id);
+        let is_block_relay_only = self.block_relay_only_dials.remove(&peer_id)
+            && session_type.is_outbound()
+            && !is_reserved;
         let mut evicted_peer: Option<Peer> = None;
 
         if !is_reserved {
@@ -84,7 +102,7 @@ impl PeerRegistry {
                 return Err(PeerError::NonReserved);
             }
             // ban_list lock acquired
-            if peer_store.is_banned(&peer_id) {
+            if peer_store.is_banned(&peer_id) || peer_store.is_addr_banned(&remote_addr) {
                 return Err(PeerError::Banned);
             }
 
@@ -98,18 +116,24 @@ impl PeerRegistry {
                         return Err(PeerError::ReachMaxInboundLimit);
                     }
                 }
+            } else if is_block_relay_only {
+                if connection_status.block_relay_only_outbound >= self.max_block_relay_only {
+                    return Err(PeerError::ReachMaxOutboundLimit);
+                }
             } else if connection_status.unreserved_outbound >= self.max_outbound {
                 return Err(PeerError::ReachMaxOutboundLimit);
             }
         }
         peer_store.add_connected_peer(&peer_id, remote_addr.clone(), session_type);
-        let peer = Peer::new(session_id, session_type, peer_id, remote_addr, is_reserved);
+        let mut peer = Peer::new(session_id, session_type, peer_id, remote_addr, is_reserved);
+        peer.is_block_relay_only = is_block_relay_only;
         self.peers.insert(session_id, peer);
         Ok(evicted_peer)
     }
 
-    // When have inbound connection, we try evict a inbound peer
-    // TODO: revisit this after find out what's bitcoin way
+    // When have inbound connection, we try evict a inbound peer, like bitcoin, a set of
+    // peers is protected first and the victim is chosen from the largest network group of
+    // the rest
     fn try_evict_inbound_peer(&self, peer_store: &PeerStore) -> Option<SessionId> {
         let mut candidate_peers = {
             self.peers
@@ -118,6 +142,24 @@ impl PeerRegistry {
                 .collect::<Vec<_>>()
         };
         // Protect peers based on characteristics that an attacker hard to simulate or manipulate
+        // Protect peers which are the only one from their network group, they keep our
+        // connections diverse
+        if candidate_peers.len() > EVICTION_PROTECT_PEERS {
+            let mut group_sizes = FnvHashMap::default();
+            for peer in &candidate_peers {
+                *group_sizes.entry(peer.network_group()).or_insert(0usize) += 1;
+            }
+            let mut protected = 0;
+            candidate_peers.retain(|peer| {
+                if protected < EVICTION_PROTECT_PEERS && group_sizes[&peer.network_group()] == 1 {
+                    protected += 1;
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+
         // Protect peers which has the highest score
         sort_then_drop(
             &mut candidate_peers,
@@ -134,14 +176,15 @@ impl PeerRegistry {
             &mut candidate_peers,
             EVICTION_PROTECT_PEERS,
             |peer1, peer2| {
+                // most pings are below one second, compare them in milliseconds
                 let peer1_ping = peer1
                     .ping
-                    .map(|p| p.as_secs())
-                    .unwrap_or_else(|| std::u64::MAX);
+                    .map(|p| p.as_millis())
+                    .unwrap_or_else(|| std::u128::MAX);
                 let peer2_ping = peer2
                     .ping
-                    .map(|p| p.as_secs())
-                    .unwrap_or_else(|| std::u64::MAX);
+                    .map(|p| p.as_millis())
+                    .unwrap_or_else(|| std::u128::MAX);
                 peer2_ping.cmp(&peer1_ping)
             },
         );
@@ -208,6 +251,15 @@ impl PeerRegistry {
         self.feeler_peers.contains(peer_id)
     }
 
+    /// The next outbound session of `peer_id` takes a block relay only slot
+    pub fn add_block_relay_only_dial(&mut self, peer_id: PeerId) {
+        self.block_relay_only_dials.insert(peer_id);
+    }
+
+    pub fn remove_block_relay_only_dial(&mut self, peer_id: &PeerId) {
+        self.block_relay_only_dials.remove(peer_id);
+    }
+
     pub fn get_peer(&self, session_id: SessionId) -> Option<&Peer> {
         self.peers.get(&session_id)
     }
@@ -235,6 +287,62 @@ impl PeerRegistry {
             .and_then(|session_id| self.peers.remove(&session_id))
     }
 
+    /// Reserve slots for `peer_id`, a connected session of it is no longer counted in the limits
+    pub(crate) fn add_reserved_peer(&mut self, peer_id: PeerId) {
+        if let Some(peer) = self.peers.values_mut().find(|peer| peer.peer_id == peer_id) {
+            peer.is_reserved = true;
+        }
+        self.reserved_peers.insert(peer_id);
+    }
+
+    /// Returns the session of `peer_id` which must be disconnected because only reserved peers
+    /// are allowed
+    pub(crate) fn remove_reserved_peer(&mut self, peer_id: &PeerId) -> Option<SessionId> {
+        if !self.reserved_peers.remove(peer_id) {
+            return None;
+        }
+        let peer = self
+            .peers
+            .values_mut()
+            .find(|peer| &peer.peer_id == peer_id)?;
+        peer.is_reserved = false;
+        if self.reserved_only {
+            Some(peer.session_id)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_reserved(&self, peer_id: &PeerId) -> bool {
+        self.reserved_peers.contains(peer_id)
+    }
+
+    pub fn reserved_peers(&self) -> Vec<PeerId> {
+        self.reserved_peers.iter().cloned().collect()
+    }
+
+    /// Returns the unreserved outbound sessions over the new limit, the latest connected ones
+    /// are dropped first
+    pub(crate) fn set_max_outbound(&mut self, max_outbound: u32) -> Vec<SessionId> {
+        self.max_outbound = max_outbound;
+        let mut outbound_peers: Vec<&Peer> = self
+            .peers
+            .values()
+            .filter(|peer| {
+                peer.is_outbound()
+                    && !peer.is_reserved
+                    && !peer.is_feeler
+                    && !peer.is_block_relay_only
+            })
+            .collect();
+        outbound_peers.sort_by_key(|peer| peer.connected_time);
+        outbound_peers
+            .into_iter()
+            .skip(max_outbound as usize)
+            .map(|peer| peer.session_id)
+            .collect()
+    }
+
     pub fn peers(&self) -> &FnvHashMap<SessionId, Peer> {
         &self.peers
     }
@@ -243,12 +351,37 @@ impl PeerRegistry {
         self.peers.keys().cloned().collect()
     }
 
+    /// Sessions received nothing on any protocol for `idle_timeout`, sessions never received
+    /// anything are counted from the connected time
+    pub fn idle_sessions(&self, now: Instant, idle_timeout: Duration) -> Vec<SessionId> {
+        self.peers
+            .values()
+            .filter(|peer| {
+                let last_active = peer.last_message_time.unwrap_or(peer.connected_time);
+                now >= last_active + idle_timeout
+            })
+            .map(|peer| peer.session_id)
+            .collect()
+    }
+
+    /// Network groups of the peers in unreserved outbound slots, block relay only ones included
+    pub fn outbound_groups(&self) -> FnvHashSet<Group> {
+        self.peers
+            .values()
+            .filter(|peer| peer.is_outbound() && !peer.is_reserved)
+            .map(Peer::network_group)
+            .collect()
+    }
+
     pub(crate) fn connection_status(&self) -> ConnectionStatus {
         let total = self.peers.len() as u32;
         let mut unreserved_inbound: u32 = 0;
         let mut unreserved_outbound: u32 = 0;
+        let mut block_relay_only_outbound: u32 = 0;
         for peer in self.peers.values().filter(|peer| !peer.is_reserved) {
-            if peer.is_outbound() {
+            if peer.is_block_relay_only {
+                block_relay_only_outbound += 1;
+            } else if peer.is_outbound() {
                 unreserved_outbound += 1;
             } else {
                 unreserved_inbound += 1;
@@ -258,8 +391,10 @@ impl PeerRegistry {
             total,
             unreserved_inbound,
             unreserved_outbound,
+            block_relay_only_outbound,
             max_inbound: self.max_inbound,
             max_outbound: self.max_outbound,
+            max_block_relay_only: self.max_block_relay_only,
         }
     }
 }
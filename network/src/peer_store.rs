@@ -3,6 +3,8 @@ pub mod sqlite;
 pub use crate::{peer_store::sqlite::SqlitePeerStore, SessionType};
 pub(crate) use crate::{Behaviour, PeerId};
 use p2p::multiaddr::Multiaddr;
+use serde_derive::{Deserialize, Serialize};
+use std::net::IpAddr;
 use std::time::Duration;
 
 pub type Score = i32;
@@ -34,6 +36,14 @@ pub trait PeerStore: Send {
     /// Add discovered peer addresses
     /// this method will assume peer and addr is untrust since we have not connected to it.
     fn add_discovered_addr(&mut self, peer_id: &PeerId, address: Multiaddr) -> bool;
+    /// Add peer addresses told by the peer connected from `source`, addresses from the same
+    /// network group of sources are limited to a few buckets.
+    fn add_discovered_addr_from(
+        &mut self,
+        peer_id: &PeerId,
+        address: Multiaddr,
+        source: &Multiaddr,
+    ) -> bool;
     /// Report peer behaviours
     fn report(&mut self, peer_id: &PeerId, behaviour: Behaviour) -> ReportResult;
     /// Update peer status
@@ -44,23 +54,66 @@ pub trait PeerStore: Send {
     fn add_bootnode(&mut self, peer_id: PeerId, addr: Multiaddr);
     /// This method randomly return peers, it return bootnodes if no other peers in PeerStore.
     fn bootnodes(&self, count: u32) -> Vec<(PeerId, Multiaddr)>;
+    /// Remove an addr of a peer, e.g. one failed to dial too many times
+    fn remove_addr(&mut self, peer_id: &PeerId, addr: &Multiaddr);
     /// Get addrs of a peer, note a peer may have multiple addrs
     fn peer_addrs(&self, peer_id: &PeerId, count: u32) -> Option<Vec<Multiaddr>>;
-    /// Get peers for outbound connection, this method randomly return non-connected peer addrs
+    /// Get peers for outbound connection, this method randomly return non-connected peer addrs,
+    /// sampled across the buckets of tried and new addrs
     fn peers_to_attempt(&self, count: u32) -> Vec<(PeerId, Multiaddr)>;
     /// Get peers for feeler connection, this method randomly return peer addrs that we never
-    /// connected to.
+    /// connected to, sampled across the buckets of new addrs.
     fn peers_to_feeler(&self, count: u32) -> Vec<(PeerId, Multiaddr)>;
     /// Randomly get peers
     fn random_peers(&self, count: u32) -> Vec<(PeerId, Multiaddr)>;
-    /// Ban a peer
-    fn ban_peer(&mut self, peer_id: &PeerId, timeout: Duration);
+    /// Get peers with the highest score, only addrs we successfully connected to recently are
+    /// returned so they are known to be reachable.
+    fn best_peers(&self, count: u32) -> Vec<(PeerId, Multiaddr)>;
+    /// Ban a peer, the connected addr of the peer is banned actually
+    fn ban_peer(&mut self, peer_id: &PeerId, timeout: Duration, reason: String);
+    /// Ban the ip of an addr
+    fn ban_addr(&mut self, addr: &Multiaddr, timeout: Duration, reason: String);
     /// Check peer ban status
     fn is_banned(&self, peer_id: &PeerId) -> bool;
+    /// Check addr ban status
+    fn is_addr_banned(&self, addr: &Multiaddr) -> bool;
+    /// Get addresses which are still banned
+    fn banned_addrs(&self) -> Vec<BannedAddr>;
+    /// Record the handshake time of a feeler connection to the peer, a fast handshake
+    /// improves the score of the peer
+    fn record_feeler(&mut self, peer_id: &PeerId, handshake: Duration);
+    /// Drop the lowest quality addrs until at most `max_addrs` are left, the addrs of
+    /// bootnodes, `protected` peers and connected peers are never dropped. Returns the
+    /// number of dropped addrs
+    fn prune_addrs(&mut self, max_addrs: u32, protected: &[PeerId]) -> usize;
     /// peer score config
     fn peer_score_config(&self) -> PeerScoreConfig;
 }
 
+/// A banned ip, connections from or to it are refused until `ban_until`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BannedAddr {
+    pub ip: IpAddr,
+    /// Unix time when the ban expires
+    pub ban_until: Duration,
+    /// Unix time when the ban is created
+    pub created_at: Duration,
+    pub reason: String,
+}
+
+/// A line of the exported address book, `score` and `last_connected_at` are informational,
+/// imported addresses are always treated as discovered ones.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressRecord {
+    /// Address with the peer id, e.g. `/ip4/1.1.1.1/tcp/8115/p2p/<peer_id>`
+    pub address: String,
+    #[serde(default)]
+    pub score: Option<Score>,
+    /// Unix time in seconds when we connected to the address last time, 0 means never
+    #[serde(default)]
+    pub last_connected_at: u64,
+}
+
 /// Peer Status
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Status {
@@ -1,3 +1,4 @@
+pub(crate) mod addrman;
 pub(crate) mod db;
 #[cfg(db_trace)]
 pub mod db_trace;
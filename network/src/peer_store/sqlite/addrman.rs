@@ -0,0 +1,141 @@
+//! Bucketing of peer addresses, in the style of the bitcoin address manager.
+//!
+//! Addresses we never connected to are kept in the new table and moved to the tried table
+//! after we successfully connected to them. Both tables are split into buckets of limited size,
+//! the bucket of an address is derived from a secret key, the network group of the address and,
+//! in the new table, the network group of the peer which told us the address. An attacker
+//! controlling a few network groups can only fill a few buckets, and peers are sampled across
+//! buckets, so flooding addresses does not take over the outbound selection.
+
+use crate::network_group::NetworkGroup;
+use crate::peer_store::sqlite::db::network_group_to_bytes;
+use crate::peer_store::{Multiaddr, PeerId, Score};
+use fnv::{FnvHashMap, FnvHashSet};
+use hash::blake2b_256;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use std::cmp::Reverse;
+
+pub(crate) const NEW_BUCKET_COUNT: u64 = 1024;
+pub(crate) const TRIED_BUCKET_COUNT: u64 = 256;
+/// Max addresses in a bucket
+pub(crate) const BUCKET_SIZE: u32 = 64;
+/// Number of new buckets the addresses from one source group can be put into
+const NEW_BUCKETS_PER_SOURCE_GROUP: u64 = 64;
+/// Number of tried buckets the addresses in one network group can be put into
+const TRIED_BUCKETS_PER_GROUP: u64 = 8;
+pub(crate) const KEY_SIZE: usize = 32;
+
+/// An address in the new or the tried table
+#[derive(Debug, Clone)]
+pub(crate) struct BucketEntry {
+    pub peer_id: PeerId,
+    pub addr: Multiaddr,
+    pub tried: bool,
+    pub bucket: u32,
+    pub score: Score,
+    /// Handshake time of the last feeler connection, 0 if never measured
+    pub handshake_millis: u32,
+}
+
+impl BucketEntry {
+    // Higher score first, then faster handshake, unmeasured ones last
+    fn rank(&self) -> (Score, Reverse<u32>) {
+        let handshake_millis = if self.handshake_millis == 0 {
+            u32::max_value()
+        } else {
+            self.handshake_millis
+        };
+        (self.score, Reverse(handshake_millis))
+    }
+}
+
+pub(crate) fn group_bytes(addr: &Multiaddr) -> Vec<u8> {
+    network_group_to_bytes(&addr.network_group())
+}
+
+fn keyed_hash(key: &[u8], parts: &[&[u8]]) -> u64 {
+    let mut data = key.to_vec();
+    for part in parts {
+        // length prefix keeps the concatenation unambiguous
+        data.extend_from_slice(&(part.len() as u32).to_le_bytes());
+        data.extend_from_slice(part);
+    }
+    let hash = blake2b_256(data);
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Bucket in the new table of `addr` which is told by a peer in `source_group`
+pub(crate) fn new_bucket(key: &[u8], addr: &Multiaddr, source_group: &[u8]) -> u32 {
+    let slot = keyed_hash(key, &[&group_bytes(addr), source_group]) % NEW_BUCKETS_PER_SOURCE_GROUP;
+    (keyed_hash(key, &[source_group, &slot.to_le_bytes()]) % NEW_BUCKET_COUNT) as u32
+}
+
+/// Bucket in the tried table of `addr`
+pub(crate) fn tried_bucket(key: &[u8], addr: &Multiaddr) -> u32 {
+    let slot = keyed_hash(key, &[&addr.to_bytes()]) % TRIED_BUCKETS_PER_GROUP;
+    (keyed_hash(key, &[&group_bytes(addr), &slot.to_le_bytes()]) % TRIED_BUCKET_COUNT) as u32
+}
+
+/// Pick at most `count` addresses of distinct peers. Buckets are visited in a random order,
+/// alternating between the tried and the new table, one random address is taken from each
+/// bucket per round, so a crowded bucket is not more likely to be picked than others.
+/// Within a bucket, addresses of peers with better scores and faster feeler handshakes are
+/// taken first, ties are broken randomly.
+pub(crate) fn sample(entries: Vec<BucketEntry>, count: usize) -> Vec<(PeerId, Multiaddr)> {
+    let mut rng = thread_rng();
+    let mut buckets: FnvHashMap<(bool, u32), Vec<BucketEntry>> = FnvHashMap::default();
+    for entry in entries {
+        buckets
+            .entry((entry.tried, entry.bucket))
+            .or_insert_with(Vec::new)
+            .push(entry);
+    }
+    let (mut tried, mut new): (Vec<_>, Vec<_>) = buckets
+        .into_iter()
+        .map(|((tried, _bucket), mut entries)| {
+            entries.shuffle(&mut rng);
+            // addresses are popped from the back, the best ones go last
+            entries.sort_by_key(BucketEntry::rank);
+            let addrs: Vec<_> = entries
+                .into_iter()
+                .map(|entry| (entry.peer_id, entry.addr))
+                .collect();
+            (tried, addrs)
+        })
+        .partition(|(tried, _addrs)| *tried);
+    tried.shuffle(&mut rng);
+    new.shuffle(&mut rng);
+
+    let mut queues = Vec::with_capacity(tried.len() + new.len());
+    let mut tried = tried.into_iter();
+    let mut new = new.into_iter();
+    loop {
+        match (tried.next(), new.next()) {
+            (None, None) => break,
+            (tried_bucket, new_bucket) => {
+                queues.extend(tried_bucket.map(|(_, addrs)| addrs));
+                queues.extend(new_bucket.map(|(_, addrs)| addrs));
+            }
+        }
+    }
+
+    let mut selected = Vec::with_capacity(count);
+    let mut selected_peers = FnvHashSet::default();
+    while selected.len() < count && !queues.is_empty() {
+        for queue in queues.iter_mut() {
+            if selected.len() >= count {
+                break;
+            }
+            if let Some((peer_id, addr)) = queue.pop() {
+                if selected_peers.insert(peer_id.clone()) {
+                    selected.push((peer_id, addr));
+                }
+            }
+        }
+        queues.retain(|queue| !queue.is_empty());
+    }
+    selected
+}
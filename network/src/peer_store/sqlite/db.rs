@@ -1,10 +1,12 @@
 use crate::network_group::{Group, NetworkGroup};
+use crate::peer_store::sqlite::addrman::{BucketEntry, BUCKET_SIZE, KEY_SIZE};
 use crate::peer_store::sqlite::DBError;
 use crate::peer_store::{Multiaddr, PeerId, Score, Status};
 use crate::SessionType;
 use rusqlite::types::ToSql;
 use rusqlite::OptionalExtension;
-use rusqlite::{Connection, NO_PARAMS};
+use rusqlite::{Connection, Row, NO_PARAMS};
+use std::cmp;
 use std::iter::FromIterator;
 use std::time::Duration;
 
@@ -21,7 +23,8 @@ pub fn create_tables(conn: &Connection) -> DBResult<()> {
     status INTEGER NOT NULL,
     endpoint INTEGER NOT NULL,
     ban_time INTEGER NOT NULL,
-    last_connected_at INTEGER NOT NULL
+    last_connected_at INTEGER NOT NULL,
+    handshake_millis INTEGER NOT NULL DEFAULT 0
     );
     "#;
     conn.execute_batch(sql)?;
@@ -30,19 +33,102 @@ pub fn create_tables(conn: &Connection) -> DBResult<()> {
     id INTEGER PRIMARY KEY NOT NULL,
     peer_info_id INTEGER NOT NULL,
     addr BINARY NOT NULL,
-    last_connected_at INTEGER NOT NULL
+    last_connected_at INTEGER NOT NULL,
+    source_group BINARY NOT NULL DEFAULT x'',
+    tried INTEGER NOT NULL DEFAULT 0,
+    bucket INTEGER NOT NULL DEFAULT 0
     );
     CREATE UNIQUE INDEX IF NOT EXISTS idx_peer_info_id_addr_on_peer_addr ON peer_addr (peer_info_id, addr);
     "#;
     conn.execute_batch(sql)?;
     let sql = r#"
+    CREATE TABLE IF NOT EXISTS addrman (
+    id INTEGER PRIMARY KEY NOT NULL,
+    key BINARY NOT NULL
+    );
+    "#;
+    conn.execute_batch(sql)?;
+    let sql = r#"
     CREATE TABLE IF NOT EXISTS ban_list (
     id INTEGER PRIMARY KEY NOT NULL,
     ip BINARY UNIQUE NOT NULL,
-    ban_time INTEGER NOT NULL
+    ban_time INTEGER NOT NULL,
+    reason TEXT NOT NULL DEFAULT '',
+    created_at INTEGER NOT NULL DEFAULT 0
     );
     "#;
-    conn.execute_batch(sql).map_err(Into::into)
+    conn.execute_batch(sql)?;
+    migrate_ban_list(conn)?;
+    migrate_peer_info(conn)
+}
+
+// ban_list created by older versions has no reason and created_at columns
+fn migrate_ban_list(conn: &Connection) -> DBResult<()> {
+    let mut stmt = conn.prepare("PRAGMA table_info(ban_list)")?;
+    let rows = stmt.query_map(NO_PARAMS, |row| row.get::<_, String>(1))?;
+    let columns: Vec<String> = Result::from_iter(rows)?;
+    if !columns.iter().any(|column| column == "reason") {
+        let sql = r#"
+        ALTER TABLE ban_list ADD COLUMN reason TEXT NOT NULL DEFAULT '';
+        ALTER TABLE ban_list ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+        "#;
+        conn.execute_batch(sql)?;
+    }
+    Ok(())
+}
+
+// peer_info created by older versions has no handshake_millis column
+fn migrate_peer_info(conn: &Connection) -> DBResult<()> {
+    let mut stmt = conn.prepare("PRAGMA table_info(peer_info)")?;
+    let rows = stmt.query_map(NO_PARAMS, |row| row.get::<_, String>(1))?;
+    let columns: Vec<String> = Result::from_iter(rows)?;
+    if !columns.iter().any(|column| column == "handshake_millis") {
+        conn.execute_batch(
+            "ALTER TABLE peer_info ADD COLUMN handshake_millis INTEGER NOT NULL DEFAULT 0;",
+        )?;
+    }
+    Ok(())
+}
+
+/// Add the bucket columns to peer_addr created by older versions, returns true if the
+/// addresses need to be put into buckets.
+pub fn migrate_peer_addr(conn: &Connection) -> DBResult<bool> {
+    let mut stmt = conn.prepare("PRAGMA table_info(peer_addr)")?;
+    let rows = stmt.query_map(NO_PARAMS, |row| row.get::<_, String>(1))?;
+    let columns: Vec<String> = Result::from_iter(rows)?;
+    let migrated = !columns.iter().any(|column| column == "bucket");
+    if migrated {
+        let sql = r#"
+        ALTER TABLE peer_addr ADD COLUMN source_group BINARY NOT NULL DEFAULT x'';
+        ALTER TABLE peer_addr ADD COLUMN tried INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE peer_addr ADD COLUMN bucket INTEGER NOT NULL DEFAULT 0;
+        "#;
+        conn.execute_batch(sql)?;
+    }
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_tried_bucket_on_peer_addr ON peer_addr (tried, bucket);",
+    )?;
+    Ok(migrated)
+}
+
+/// The secret key to compute address buckets, it is generated when the peer store is created.
+pub fn get_or_insert_addrman_key(conn: &Connection) -> DBResult<Vec<u8>> {
+    let key = conn
+        .query_row("SELECT key FROM addrman WHERE id=0", NO_PARAMS, |row| {
+            row.get::<_, Vec<u8>>(0)
+        })
+        .optional()?;
+    match key {
+        Some(key) => Ok(key),
+        None => {
+            let key: [u8; KEY_SIZE] = rand::random();
+            conn.execute(
+                "INSERT INTO addrman (id, key) VALUES(0, ?1)",
+                &[key.to_vec()],
+            )?;
+            Ok(key.to_vec())
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -143,6 +229,14 @@ impl PeerInfo {
             .map_err(Into::into)
     }
 
+    /// Time to connect and finish the handshake in the last feeler connection
+    pub fn update_handshake(conn: &Connection, id: u32, handshake_millis: u32) -> DBResult<usize> {
+        let mut stmt =
+            conn.prepare("UPDATE peer_info SET handshake_millis=:handshake_millis WHERE id=:id")?;
+        stmt.execute_named(&[(":handshake_millis", &handshake_millis), (":id", &id)])
+            .map_err(Into::into)
+    }
+
     pub fn update_status(conn: &Connection, id: u32, status: Status) -> DBResult<usize> {
         let mut stmt = conn.prepare("UPDATE peer_info SET status=:status WHERE id=:id")?;
         stmt.execute_named(&[(":status", &status_to_u8(status)), (":id", &id)])
@@ -189,37 +283,122 @@ impl PeerAddr {
         peer_info_id: u32,
         addr: &Multiaddr,
         last_connected_at: Duration,
+        source_group: &[u8],
+        tried: bool,
+        bucket: u32,
     ) -> DBResult<usize> {
         let mut stmt = conn.prepare(
-            "INSERT OR IGNORE INTO peer_addr (peer_info_id, addr, last_connected_at)
-                     VALUES(:peer_info_id, :addr, :last_connected_at)",
+            "INSERT OR IGNORE INTO peer_addr (peer_info_id, addr, last_connected_at, source_group, tried, bucket)
+                     VALUES(:peer_info_id, :addr, :last_connected_at, :source_group, :tried, :bucket)",
         )?;
         stmt.execute_named(&[
             (":peer_info_id", &peer_info_id),
             (":addr", &addr.to_bytes()),
             (":last_connected_at", &duration_to_secs(last_connected_at)),
+            (":source_group", &source_group),
+            (":tried", &tried),
+            (":bucket", &bucket),
         ])
         .map_err(Into::into)
     }
 
-    pub fn update_connected_at(
+    /// Returns whether the addr is in the tried table, None if the addr is unknown
+    pub fn get_tried(
+        conn: &Connection,
+        peer_info_id: u32,
+        addr: &Multiaddr,
+    ) -> DBResult<Option<bool>> {
+        conn.query_row_named(
+            "SELECT tried FROM peer_addr WHERE peer_info_id=:peer_info_id AND addr=:addr",
+            &[
+                (":peer_info_id", &peer_info_id),
+                (":addr", &addr.to_bytes()),
+            ],
+            |row| row.get::<_, bool>(0),
+        )
+        .optional()
+        .map_err(Into::into)
+    }
+
+    /// Move the addr to the tried table
+    pub fn mark_tried(
         conn: &Connection,
         peer_info_id: u32,
-        addr: Multiaddr,
+        addr: &Multiaddr,
+        bucket: u32,
         last_connected_at: Duration,
     ) -> DBResult<usize> {
         let mut stmt = conn.prepare(
-            "UPDATE peer_addr SET last_connected_at=:last_connected_at
+            "UPDATE peer_addr SET last_connected_at=:last_connected_at, tried=1, bucket=:bucket
                     WHERE peer_info_id=:peer_info_id AND addr=:addr",
         )?;
         stmt.execute_named(&[
             (":peer_info_id", &peer_info_id),
             (":addr", &addr.to_bytes()),
+            (":bucket", &bucket),
             (":last_connected_at", &duration_to_secs(last_connected_at)),
         ])
         .map_err(Into::into)
     }
 
+    pub fn count_in_bucket(conn: &Connection, tried: bool, bucket: u32) -> DBResult<u32> {
+        conn.query_row_named(
+            "SELECT COUNT(*) FROM peer_addr WHERE tried=:tried AND bucket=:bucket",
+            &[(":tried", &tried), (":bucket", &bucket)],
+            |row| row.get(0),
+        )
+        .map_err(Into::into)
+    }
+
+    /// The addr connected least recently in the bucket, returns its id, addr and source group
+    pub fn oldest_in_bucket(
+        conn: &Connection,
+        tried: bool,
+        bucket: u32,
+    ) -> DBResult<Option<(u32, Multiaddr, Vec<u8>)>> {
+        conn.query_row_named(
+            "SELECT id, addr, source_group FROM peer_addr WHERE tried=:tried AND bucket=:bucket
+                    ORDER BY last_connected_at, id LIMIT 1",
+            &[(":tried", &tried), (":bucket", &bucket)],
+            |row| {
+                (
+                    row.get(0),
+                    Multiaddr::from_bytes(row.get(1)).expect("parse multiaddr"),
+                    row.get(2),
+                )
+            },
+        )
+        .optional()
+        .map_err(Into::into)
+    }
+
+    pub fn update_bucket(conn: &Connection, id: u32, tried: bool, bucket: u32) -> DBResult<usize> {
+        let mut stmt =
+            conn.prepare("UPDATE peer_addr SET tried=:tried, bucket=:bucket WHERE id=:id")?;
+        stmt.execute_named(&[(":tried", &tried), (":bucket", &bucket), (":id", &id)])
+            .map_err(Into::into)
+    }
+
+    pub fn delete(conn: &Connection, id: u32) -> DBResult<usize> {
+        conn.execute("DELETE FROM peer_addr WHERE id=?1", &[id])
+            .map_err(Into::into)
+    }
+
+    /// All addrs with id, source group and last connected time
+    pub fn get_all(conn: &Connection) -> DBResult<Vec<(u32, Multiaddr, Vec<u8>, Duration)>> {
+        let mut stmt =
+            conn.prepare("SELECT id, addr, source_group, last_connected_at FROM peer_addr")?;
+        let rows = stmt.query_map(NO_PARAMS, |row| {
+            (
+                row.get(0),
+                Multiaddr::from_bytes(row.get(1)).expect("parse multiaddr"),
+                row.get(2),
+                secs_to_duration(row.get(3)),
+            )
+        })?;
+        Result::from_iter(rows).map_err(Into::into)
+    }
+
     pub fn get_addrs(conn: &Connection, id: u32, count: u32) -> DBResult<Vec<Multiaddr>> {
         let mut stmt = conn.prepare(
             "SELECT addr FROM peer_addr WHERE peer_info_id == :id 
@@ -231,10 +410,51 @@ impl PeerAddr {
         Result::from_iter(rows).map_err(Into::into)
     }
 
+    pub fn delete_addr(conn: &Connection, peer_info_id: u32, addr: &Multiaddr) -> DBResult<usize> {
+        let mut stmt =
+            conn.prepare("DELETE FROM peer_addr WHERE peer_info_id=:peer_info_id AND addr=:addr")?;
+        stmt.execute_named(&[
+            (":peer_info_id", &peer_info_id),
+            (":addr", &addr.to_bytes()),
+        ])
+        .map_err(Into::into)
+    }
+
     pub fn delete_by_peer_id(conn: &Connection, id: u32) -> DBResult<usize> {
         conn.execute("DELETE FROM peer_addr WHERE peer_info_id=?1", &[id])
             .map_err(Into::into)
     }
+
+    pub fn count(conn: &Connection) -> DBResult<u32> {
+        conn.query_row::<u32, _, _>("SELECT COUNT(*) FROM peer_addr", NO_PARAMS, |r| r.get(0))
+            .map_err(Into::into)
+    }
+
+    /// Ids of the `limit` worst addrs of peers not connected and not in `excluded`: addrs
+    /// never connected to go first, then the ones of peers with lower scores, then the ones
+    /// connected least recently
+    pub fn worst_addrs(conn: &Connection, excluded: &[PeerId], limit: u32) -> DBResult<Vec<u32>> {
+        let placeholders = vec!["?"; excluded.len()].join(", ");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT peer_addr.id FROM peer_addr
+                    INNER JOIN peer_info ON peer_addr.peer_info_id = peer_info.id
+                    WHERE peer_info.status != ?
+                    AND peer_info.peer_id NOT IN ({})
+                    ORDER BY peer_addr.tried, peer_info.score, peer_addr.last_connected_at, peer_addr.id
+                    LIMIT ?",
+            placeholders
+        ))?;
+        let connected = status_to_u8(Status::Connected);
+        let excluded = excluded
+            .iter()
+            .map(|peer_id| peer_id.as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let mut params: Vec<&ToSql> = vec![&connected];
+        params.extend(excluded.iter().map(|peer_id| peer_id as &ToSql));
+        params.push(&limit);
+        let rows = stmt.query_map(&params, |row| row.get(0))?;
+        Result::from_iter(rows).map_err(Into::into)
+    }
 }
 
 pub fn get_random_peers(
@@ -270,76 +490,178 @@ pub fn get_random_peers(
     Ok(peers)
 }
 
-pub fn get_peers_to_attempt(conn: &Connection, count: u32) -> DBResult<Vec<(u32, PeerId)>> {
-    // random select peers
+/// All addresses of peers which are not banned, with the peer score and the time the address
+/// connected last time.
+pub fn get_all_addrs(conn: &Connection) -> DBResult<Vec<(PeerId, Multiaddr, Score, Duration)>> {
     let mut stmt = conn.prepare(
-        "SELECT id, peer_id FROM peer_info 
-                                WHERE status != :connected_status 
-                                AND ban_time < strftime('%s','now') 
-                                ORDER BY RANDOM() LIMIT :count",
+        "SELECT peer_info.peer_id, peer_addr.addr, peer_info.score, peer_addr.last_connected_at
+                                FROM peer_addr
+                                INNER JOIN peer_info ON peer_addr.peer_info_id = peer_info.id
+                                WHERE peer_info.ban_time < strftime('%s','now')
+                                ORDER BY peer_info.score DESC, peer_addr.last_connected_at DESC",
+    )?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        (
+            PeerId::from_bytes(row.get(0)).expect("parse peer_id"),
+            Multiaddr::from_bytes(row.get(1)).expect("parse multiaddr"),
+            row.get::<_, Score>(2),
+            secs_to_duration(row.get(3)),
+        )
+    })?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(Into::into)
+}
+
+pub fn get_best_peers(
+    conn: &Connection,
+    count: u32,
+    expired_at: Duration,
+) -> DBResult<Vec<(PeerId, Multiaddr)>> {
+    // select high score peers and their most recently connected addr
+    let mut stmt = conn.prepare(
+        "SELECT peer_info.peer_id, peer_addr.addr, MAX(peer_addr.last_connected_at) FROM peer_info
+                                INNER JOIN peer_addr ON peer_addr.peer_info_id = peer_info.id
+                                WHERE peer_info.ban_time < strftime('%s','now')
+                                AND peer_addr.last_connected_at > :time
+                                GROUP BY peer_info.id
+                                ORDER BY peer_info.score DESC LIMIT :count",
     )?;
     let rows = stmt.query_map_named(
-        &[
-            (
-                ":connected_status",
-                &status_to_u8(Status::Connected) as &ToSql,
-            ),
-            (":count", &count),
-        ],
+        &[(":count", &count), (":time", &duration_to_secs(expired_at))],
         |row| {
             (
-                row.get::<_, u32>(0),
-                PeerId::from_bytes(row.get(1)).expect("parse peer_id"),
+                PeerId::from_bytes(row.get(0)).expect("parse peer_id"),
+                Multiaddr::from_bytes(row.get(1)).expect("parse multiaddr"),
             )
         },
     )?;
-
     rows.collect::<Result<Vec<_>, _>>().map_err(Into::into)
 }
 
-pub fn get_peers_to_feeler(
+/// Addrs of peers which are not connected and not banned, from at most `count` random buckets
+pub fn get_addrs_to_attempt(conn: &Connection, count: u32) -> DBResult<Vec<BucketEntry>> {
+    get_bucket_entries(
+        conn,
+        "peer_info.status != :connected_status
+        AND peer_info.ban_time < strftime('%s','now')",
+        &[(
+            ":connected_status",
+            &status_to_u8(Status::Connected) as &ToSql,
+        )],
+        count,
+    )
+}
+
+/// Addrs in the new table of peers which are not connected and not banned, and we have not
+/// connected to since `expired_at`, from at most `count` random buckets.
+pub fn get_addrs_to_feeler(
     conn: &Connection,
-    count: u32,
     expired_at: Duration,
-) -> DBResult<Vec<(u32, PeerId)>> {
-    // random select peers
-    let mut stmt = conn.prepare(
-        "SELECT id, peer_id FROM peer_info 
-                                WHERE status != :connected_status 
-                                AND last_connected_at < :time 
-                                AND ban_time < strftime('%s','now') 
-                                ORDER BY RANDOM() LIMIT :count",
-    )?;
-    let rows = stmt.query_map_named(
+    count: u32,
+) -> DBResult<Vec<BucketEntry>> {
+    get_bucket_entries(
+        conn,
+        "peer_addr.tried = 0
+        AND peer_info.status != :connected_status
+        AND peer_info.last_connected_at < :time
+        AND peer_info.ban_time < strftime('%s','now')",
         &[
             (
                 ":connected_status",
                 &status_to_u8(Status::Connected) as &ToSql,
             ),
             (":time", &duration_to_secs(expired_at)),
-            (":count", &count),
         ],
-        |row| {
-            (
-                row.get::<_, u32>(0),
-                PeerId::from_bytes(row.get(1)).expect("parse peer_id"),
-            )
-        },
-    )?;
-    rows.collect::<Result<Vec<_>, _>>().map_err(Into::into)
+        count,
+    )
 }
 
-pub fn insert_ban_record(conn: &Connection, ip: &[u8], ban_time: Duration) -> DBResult<usize> {
-    let mut stmt =
-        conn.prepare("INSERT OR REPLACE INTO ban_list (ip, ban_time) VALUES(:ip, :ban_time);")?;
-    stmt.execute_named(&[(":ip", &ip), (":ban_time", &duration_to_secs(ban_time))])
-        .map_err(Into::into)
+// Pick `count` random buckets having addrs matching `filter`, then take at most `count` addrs
+// from each of them, better scored peers and faster feeler handshakes first.
+fn get_bucket_entries(
+    conn: &Connection,
+    filter: &str,
+    params: &[(&str, &ToSql)],
+    count: u32,
+) -> DBResult<Vec<BucketEntry>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT DISTINCT peer_addr.tried, peer_addr.bucket FROM peer_addr
+                                INNER JOIN peer_info ON peer_addr.peer_info_id = peer_info.id
+                                WHERE {}
+                                ORDER BY RANDOM() LIMIT :count",
+        filter
+    ))?;
+    let mut bucket_params = params.to_vec();
+    bucket_params.push((":count", &count as &ToSql));
+    let buckets = stmt
+        .query_map_named(&bucket_params, |row| {
+            (row.get::<_, bool>(0), row.get::<_, u32>(1))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT peer_info.peer_id, peer_addr.addr, peer_addr.tried, peer_addr.bucket, peer_info.score, peer_info.handshake_millis FROM peer_addr
+                                INNER JOIN peer_info ON peer_addr.peer_info_id = peer_info.id
+                                WHERE {}
+                                AND peer_addr.tried = :tried AND peer_addr.bucket = :bucket
+                                ORDER BY peer_info.score DESC, peer_info.handshake_millis = 0,
+                                peer_info.handshake_millis, RANDOM() LIMIT :limit",
+        filter
+    ))?;
+    let limit = cmp::min(count, BUCKET_SIZE);
+    let mut entries = Vec::new();
+    for (tried, bucket) in buckets {
+        let mut entry_params = params.to_vec();
+        entry_params.push((":tried", &tried as &ToSql));
+        entry_params.push((":bucket", &bucket as &ToSql));
+        entry_params.push((":limit", &limit as &ToSql));
+        for entry in stmt.query_map_named(&entry_params, bucket_entry_from_row)? {
+            entries.push(entry?);
+        }
+    }
+    Ok(entries)
 }
 
-pub fn get_ban_records(conn: &Connection, now: Duration) -> DBResult<Vec<(Vec<u8>, Duration)>> {
-    let mut stmt = conn.prepare("SELECT ip, ban_time FROM ban_list WHERE ban_time > :now")?;
-    let rows = stmt.query_map_named(&[(":now", &duration_to_secs(now))], |row| {
-        (row.get::<_, Vec<u8>>(0), secs_to_duration(row.get(1)))
+fn bucket_entry_from_row(row: &Row) -> BucketEntry {
+    BucketEntry {
+        peer_id: PeerId::from_bytes(row.get(0)).expect("parse peer_id"),
+        addr: Multiaddr::from_bytes(row.get(1)).expect("parse multiaddr"),
+        tried: row.get(2),
+        bucket: row.get(3),
+        score: row.get(4),
+        handshake_millis: row.get(5),
+    }
+}
+
+#[derive(Debug)]
+pub struct BanRecord {
+    pub ip: Vec<u8>,
+    pub ban_time: Duration,
+    pub reason: String,
+    pub created_at: Duration,
+}
+
+pub fn insert_ban_record(conn: &Connection, record: &BanRecord) -> DBResult<usize> {
+    let mut stmt = conn.prepare(
+        "INSERT OR REPLACE INTO ban_list (ip, ban_time, reason, created_at) VALUES(:ip, :ban_time, :reason, :created_at);",
+    )?;
+    stmt.execute_named(&[
+        (":ip", &record.ip),
+        (":ban_time", &duration_to_secs(record.ban_time)),
+        (":reason", &record.reason),
+        (":created_at", &duration_to_secs(record.created_at)),
+    ])
+    .map_err(Into::into)
+}
+
+pub fn get_ban_records(conn: &Connection, now: Duration) -> DBResult<Vec<BanRecord>> {
+    let mut stmt = conn.prepare(
+        "SELECT ip, ban_time, reason, created_at FROM ban_list WHERE ban_time > :now ORDER BY created_at",
+    )?;
+    let rows = stmt.query_map_named(&[(":now", &duration_to_secs(now))], |row| BanRecord {
+        ip: row.get(0),
+        ban_time: secs_to_duration(row.get(1)),
+        reason: row.get(2),
+        created_at: secs_to_duration(row.get(3)),
     })?;
     Result::from_iter(rows).map_err(Into::into)
 }
@@ -382,6 +704,6 @@ fn bool_to_endpoint(is_inbound: bool) -> SessionType {
     }
 }
 
-fn network_group_to_bytes(network_group: &Group) -> Vec<u8> {
+pub fn network_group_to_bytes(network_group: &Group) -> Vec<u8> {
     format!("{:?}", network_group).into_bytes()
 }
@@ -1,4 +1,5 @@
 use crate::network_group::MultiaddrExt;
+use crate::peer_store::sqlite::addrman::{self, BucketEntry};
 use crate::peer_store::sqlite::{db, DBError};
 /// SqlitePeerStore
 /// Principles:
@@ -12,26 +13,36 @@ use crate::peer_store::sqlite::{db, DBError};
 ///    score.
 /// 4. Good peers can get higher score than bad peers.
 use crate::peer_store::{
-    Behaviour, Multiaddr, PeerId, PeerScoreConfig, PeerStore, ReportResult, Score, Status,
+    AddressRecord, BannedAddr, Behaviour, Multiaddr, PeerId, PeerScoreConfig, PeerStore,
+    ReportResult, Score, Status,
 };
 use crate::SessionType;
 use faketime::unix_time;
 use fnv::FnvHashMap;
+use log::error;
+use p2p::multiaddr::{Protocol, ToMultiaddr};
 use rusqlite::Connection;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error as IoError, ErrorKind, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
 use std::time::Duration;
 
 /// After this limitation, peer store will try to eviction peers
 pub(crate) const PEER_STORE_LIMIT: u32 = 8192;
 /// Consider we never seen a peer if peer's last_connected_at beyond this timeout
 pub(crate) const LAST_CONNECTED_TIMEOUT_SECS: u64 = 14 * 24 * 3600;
+/// Feeler handshakes within this time are rewarded
+pub(crate) const FAST_FEELER_HANDSHAKE: Duration = Duration::from_secs(2);
 /// Clear banned list if the list reach this size
 const BAN_LIST_CLEAR_EXPIRES_SIZE: usize = 1024;
-const DEFAULT_ADDRS: u32 = 3;
 
 pub struct SqlitePeerStore {
     bootnodes: Vec<(PeerId, Multiaddr)>,
     peer_score_config: PeerScoreConfig,
     ban_list: FnvHashMap<Vec<u8>, Duration>,
+    // secret key to compute the buckets of addresses
+    addrman_key: Vec<u8>,
     pub(crate) conn: Connection,
 }
 
@@ -40,6 +51,7 @@ impl SqlitePeerStore {
         let mut peer_store = SqlitePeerStore {
             bootnodes: Vec::new(),
             ban_list: Default::default(),
+            addrman_key: Vec::new(),
             conn,
             peer_score_config,
         };
@@ -62,12 +74,171 @@ impl SqlitePeerStore {
         Self::file("".into())
     }
 
+    /// Write addresses of peers which are not banned to `path` as JSON lines of
+    /// `AddressRecord`, returns the number of exported addresses.
+    pub fn export_addresses<P: AsRef<Path>>(&self, path: P) -> Result<usize, IoError> {
+        let addrs = db::get_all_addrs(&self.conn)
+            .map_err(|err| IoError::new(ErrorKind::Other, format!("{:?}", err)))?;
+        let mut writer = BufWriter::new(File::create(path)?);
+        let mut count = 0;
+        for (peer_id, addr, score, last_connected_at) in addrs {
+            if self.is_addr_banned(&addr) {
+                continue;
+            }
+            let record = AddressRecord {
+                address: format!("{}/p2p/{}", addr, peer_id.to_base58()),
+                score: Some(score),
+                last_connected_at: last_connected_at.as_secs(),
+            };
+            serde_json::to_writer(&mut writer, &record)?;
+            writer.write_all(b"\n")?;
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Add addresses exported by `export_addresses` as discovered addresses, empty lines are
+    /// ignored. Returns the number of newly added addresses.
+    pub fn import_addresses<P: AsRef<Path>>(&mut self, path: P) -> Result<usize, IoError> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut records = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = |reason: String| {
+                IoError::new(
+                    ErrorKind::InvalidData,
+                    format!("line {}: {}", i + 1, reason),
+                )
+            };
+            let record: AddressRecord =
+                serde_json::from_str(&line).map_err(|err| invalid(err.to_string()))?;
+            let mut addr = record
+                .address
+                .to_multiaddr()
+                .map_err(|_| invalid(format!("invalid address {}", record.address)))?;
+            let peer_id = match addr.pop() {
+                Some(Protocol::P2p(key)) => PeerId::from_bytes(key.into_bytes())
+                    .map_err(|_| invalid(format!("invalid peer id in {}", record.address)))?,
+                _ => return Err(invalid(format!("no peer id in {}", record.address))),
+            };
+            records.push((peer_id, addr));
+        }
+        // validate the whole file before changing the peer store
+        let mut added = 0;
+        for (peer_id, addr) in records {
+            if self.add_discovered_addr(&peer_id, addr) {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    // Delete the worst addrs exceeding `max_addrs`, addrs of bootnodes and `protected` peers
+    // are kept
+    fn delete_worst_addrs(
+        &mut self,
+        max_addrs: u32,
+        protected: &[PeerId],
+    ) -> Result<usize, DBError> {
+        let count = db::PeerAddr::count(&self.conn)?;
+        if count <= max_addrs {
+            return Ok(0);
+        }
+        let mut excluded = protected.to_vec();
+        excluded.extend(
+            self.bootnodes
+                .iter()
+                .map(|(bootnode_id, _)| bootnode_id.clone()),
+        );
+        let ids = db::PeerAddr::worst_addrs(&self.conn, &excluded, count - max_addrs)?;
+        let tx = self.conn.transaction()?;
+        for id in &ids {
+            db::PeerAddr::delete(&tx, *id)?;
+        }
+        tx.commit()?;
+        Ok(ids.len())
+    }
+
+    fn sample_addrs(&self, addrs: Vec<BucketEntry>, count: u32) -> Vec<(PeerId, Multiaddr)> {
+        let addrs = addrs
+            .into_iter()
+            .filter(|entry| !self.is_addr_banned(&entry.addr))
+            .collect();
+        addrman::sample(addrs, count as usize)
+    }
+
     fn prepare(&mut self) -> Result<(), DBError> {
         self.create_tables()?;
+        self.addrman_key = db::get_or_insert_addrman_key(&self.conn)?;
+        if db::migrate_peer_addr(&self.conn)? {
+            self.rebucket_addrs()?;
+        }
         self.reset_status()?;
         self.load_banlist()
     }
 
+    // Put addrs stored by older versions into buckets, addrs we have connected to go to the
+    // tried table. The bucket size limit is not enforced here.
+    fn rebucket_addrs(&self) -> Result<(), DBError> {
+        for (id, addr, _source_group, last_connected_at) in db::PeerAddr::get_all(&self.conn)? {
+            let tried = last_connected_at.as_secs() > 0;
+            let bucket = if tried {
+                addrman::tried_bucket(&self.addrman_key, &addr)
+            } else {
+                addrman::new_bucket(&self.addrman_key, &addr, &addrman::group_bytes(&addr))
+            };
+            db::PeerAddr::update_bucket(&self.conn, id, tried, bucket)?;
+        }
+        Ok(())
+    }
+
+    // Evict the least recently connected addr if the new bucket is full
+    fn make_room_in_new_bucket(&self, bucket: u32) -> Result<(), DBError> {
+        if db::PeerAddr::count_in_bucket(&self.conn, false, bucket)? >= addrman::BUCKET_SIZE {
+            if let Some((id, _addr, _source_group)) =
+                db::PeerAddr::oldest_in_bucket(&self.conn, false, bucket)?
+            {
+                db::PeerAddr::delete(&self.conn, id)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Move the least recently connected addr back to the new table if the tried bucket is full
+    fn make_room_in_tried_bucket(&self, bucket: u32) -> Result<(), DBError> {
+        if db::PeerAddr::count_in_bucket(&self.conn, true, bucket)? >= addrman::BUCKET_SIZE {
+            if let Some((id, addr, source_group)) =
+                db::PeerAddr::oldest_in_bucket(&self.conn, true, bucket)?
+            {
+                let new_bucket = addrman::new_bucket(&self.addrman_key, &addr, &source_group);
+                self.make_room_in_new_bucket(new_bucket)?;
+                db::PeerAddr::update_bucket(&self.conn, id, false, new_bucket)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Move the addr to the tried table, unknown addrs are ignored
+    fn mark_tried(
+        &self,
+        peer_info_id: u32,
+        addr: &Multiaddr,
+        last_connected_at: Duration,
+    ) -> Result<(), DBError> {
+        let bucket = addrman::tried_bucket(&self.addrman_key, addr);
+        match db::PeerAddr::get_tried(&self.conn, peer_info_id, addr)? {
+            Some(true) => {}
+            Some(false) => self.make_room_in_tried_bucket(bucket)?,
+            None => return Ok(()),
+        }
+        db::PeerAddr::mark_tried(&self.conn, peer_info_id, addr, bucket, last_connected_at)?;
+        Ok(())
+    }
+
     fn create_tables(&self) -> Result<(), DBError> {
         db::create_tables(&self.conn)
     }
@@ -80,39 +251,12 @@ impl SqlitePeerStore {
         self.clear_expires_banned_ip()?;
         let now = unix_time();
         let ban_records = db::get_ban_records(&self.conn, now)?;
-        for (ip, ban_time) in ban_records {
-            self.ban_list.insert(ip, ban_time);
+        for record in ban_records {
+            self.ban_list.insert(record.ip, record.ban_time);
         }
         Ok(())
     }
 
-    fn ban_ip(&mut self, addr: &Multiaddr, timeout: Duration) {
-        let ip = {
-            match addr.extract_ip_addr_binary() {
-                Some(binary) => binary,
-                None => return,
-            }
-        };
-        let ban_time = unix_time() + timeout;
-        db::insert_ban_record(&self.conn, &ip, ban_time).expect("ban ip");
-        self.ban_list.insert(ip, ban_time);
-        if self.ban_list.len() > BAN_LIST_CLEAR_EXPIRES_SIZE {
-            self.clear_expires_banned_ip().expect("clear ban list");
-        }
-    }
-
-    fn is_addr_banned(&self, addr: &Multiaddr) -> bool {
-        let ip = match addr.extract_ip_addr_binary() {
-            Some(ip) => ip,
-            None => return false,
-        };
-        let now = unix_time();
-        match self.ban_list.get(&ip) {
-            Some(ban_time) => *ban_time > now,
-            None => false,
-        }
-    }
-
     fn clear_expires_banned_ip(&mut self) -> Result<(), DBError> {
         let now = unix_time();
         let ips = db::clear_expires_banned_ip(&self.conn, now)?;
@@ -170,21 +314,6 @@ impl SqlitePeerStore {
     fn get_peer_info(&self, peer_id: &PeerId) -> Option<db::PeerInfo> {
         db::PeerInfo::get_by_peer_id(&self.conn, peer_id).expect("get peer info")
     }
-
-    fn find_addrs_for_peers(
-        &self,
-        conn: &rusqlite::Connection,
-        peers: Vec<(u32, PeerId)>,
-    ) -> Result<Vec<(PeerId, Multiaddr)>, DBError> {
-        let mut peer_addrs = Vec::with_capacity(peers.len());
-        for (id, peer_id) in peers {
-            let addrs = db::PeerAddr::get_addrs(conn, id, DEFAULT_ADDRS)?;
-            if let Some(addr) = addrs.into_iter().find(|addr| !self.is_addr_banned(&addr)) {
-                peer_addrs.push((peer_id, addr));
-            }
-        }
-        Ok(peer_addrs)
-    }
 }
 
 impl PeerStore for SqlitePeerStore {
@@ -217,20 +346,47 @@ impl PeerStore for SqlitePeerStore {
             let peer = db::PeerInfo::get_by_peer_id(&self.conn, peer_id)
                 .expect("get_by_peer_id failed")
                 .expect("must have");
-            db::PeerAddr::update_connected_at(&self.conn, peer.id, addr, now)
-                .expect("update connected at");
+            self.mark_tried(peer.id, &addr, now)
+                .expect("mark addr tried");
         }
     }
 
     fn add_discovered_addr(&mut self, peer_id: &PeerId, addr: Multiaddr) -> bool {
+        let source = addr.clone();
+        self.add_discovered_addr_from(peer_id, addr, &source)
+    }
+
+    fn add_discovered_addr_from(
+        &mut self,
+        peer_id: &PeerId,
+        addr: Multiaddr,
+        source: &Multiaddr,
+    ) -> bool {
         // peer store is full
         if self.check_store_limit().is_err() {
             return false;
         }
         let peer_info = self.fetch_peer_info(peer_id);
-        let inserted =
-            db::PeerAddr::insert(&self.conn, peer_info.id, &addr, Duration::from_secs(0))
-                .expect("insert addr");
+        if db::PeerAddr::get_tried(&self.conn, peer_info.id, &addr)
+            .expect("get addr")
+            .is_some()
+        {
+            return false;
+        }
+        let source_group = addrman::group_bytes(source);
+        let bucket = addrman::new_bucket(&self.addrman_key, &addr, &source_group);
+        self.make_room_in_new_bucket(bucket)
+            .expect("make room in new bucket");
+        let inserted = db::PeerAddr::insert(
+            &self.conn,
+            peer_info.id,
+            &addr,
+            Duration::from_secs(0),
+            &source_group,
+            false,
+            bucket,
+        )
+        .expect("insert addr");
         inserted > 0
     }
 
@@ -241,13 +397,28 @@ impl PeerStore for SqlitePeerStore {
         let peer = self.fetch_peer_info(peer_id);
         let score = peer.score.saturating_add(behaviour.score());
         if score < self.peer_score_config.ban_score {
-            self.ban_peer(peer_id, self.peer_score_config.ban_timeout);
+            self.ban_peer(
+                peer_id,
+                self.peer_score_config.ban_timeout,
+                format!("score {} below ban score", score),
+            );
             return ReportResult::Banned;
         }
         db::PeerInfo::update_score(&self.conn, peer.id, score).expect("update peer score");
         ReportResult::Ok
     }
 
+    fn record_feeler(&mut self, peer_id: &PeerId, handshake: Duration) {
+        let peer = self.fetch_peer_info(peer_id);
+        let millis = handshake.as_secs() * 1000 + u64::from(handshake.subsec_millis());
+        // 0 means never measured
+        let millis = millis.max(1).min(u64::from(u32::max_value())) as u32;
+        db::PeerInfo::update_handshake(&self.conn, peer.id, millis).expect("update handshake");
+        if handshake <= FAST_FEELER_HANDSHAKE {
+            self.report(peer_id, Behaviour::FastFeelerHandshake);
+        }
+    }
+
     fn update_status(&self, peer_id: &PeerId, status: Status) {
         if let Some(peer) = self.get_peer_info(peer_id) {
             db::PeerInfo::update_status(&self.conn, peer.id, status).expect("update status");
@@ -280,6 +451,11 @@ impl PeerStore for SqlitePeerStore {
         }
         peers
     }
+    fn remove_addr(&mut self, peer_id: &PeerId, addr: &Multiaddr) {
+        if let Some(peer) = self.get_peer_info(peer_id) {
+            db::PeerAddr::delete_addr(&self.conn, peer.id, addr).expect("delete peer addr");
+        }
+    }
     fn peer_addrs<'a>(&'a self, peer_id: &'a PeerId, count: u32) -> Option<Vec<Multiaddr>> {
         self.get_peer_info(peer_id).map(|peer| {
             db::PeerAddr::get_addrs(&self.conn, peer.id, count).expect("get peer addrs")
@@ -287,20 +463,18 @@ impl PeerStore for SqlitePeerStore {
     }
 
     fn peers_to_attempt(&self, count: u32) -> Vec<(PeerId, Multiaddr)> {
-        let peers = db::get_peers_to_attempt(&self.conn, count).expect("get peers to attempt");
-        self.find_addrs_for_peers(&self.conn, peers)
-            .expect("find_addrs_for_peers failed")
+        let addrs = db::get_addrs_to_attempt(&self.conn, count).expect("get addrs to attempt");
+        self.sample_addrs(addrs, count)
     }
 
     fn peers_to_feeler(&self, count: u32) -> Vec<(PeerId, Multiaddr)> {
-        let peers = db::get_peers_to_feeler(
+        let addrs = db::get_addrs_to_feeler(
             &self.conn,
-            count,
             unix_time() - Duration::from_secs(LAST_CONNECTED_TIMEOUT_SECS),
+            count,
         )
-        .expect("get peers to feeler");
-        self.find_addrs_for_peers(&self.conn, peers)
-            .expect("find_addrs_for_peers failed")
+        .expect("get addrs to feeler");
+        self.sample_addrs(addrs, count)
     }
 
     fn random_peers(&self, count: u32) -> Vec<(PeerId, Multiaddr)> {
@@ -312,9 +486,42 @@ impl PeerStore for SqlitePeerStore {
         .expect("get random peers")
     }
 
-    fn ban_peer(&mut self, peer_id: &PeerId, timeout: Duration) {
+    fn best_peers(&self, count: u32) -> Vec<(PeerId, Multiaddr)> {
+        db::get_best_peers(
+            &self.conn,
+            count,
+            unix_time() - Duration::from_secs(LAST_CONNECTED_TIMEOUT_SECS),
+        )
+        .expect("get best peers")
+        .into_iter()
+        .filter(|(_peer_id, addr)| !self.is_addr_banned(addr))
+        .collect()
+    }
+
+    fn ban_peer(&mut self, peer_id: &PeerId, timeout: Duration, reason: String) {
         if let Some(peer) = self.get_peer_info(peer_id) {
-            self.ban_ip(&peer.connected_addr, timeout);
+            self.ban_addr(&peer.connected_addr, timeout, reason);
+        }
+    }
+
+    fn ban_addr(&mut self, addr: &Multiaddr, timeout: Duration, reason: String) {
+        let ip = {
+            match addr.extract_ip_addr_binary() {
+                Some(binary) => binary,
+                None => return,
+            }
+        };
+        let now = unix_time();
+        let record = db::BanRecord {
+            ip,
+            ban_time: now + timeout,
+            reason,
+            created_at: now,
+        };
+        db::insert_ban_record(&self.conn, &record).expect("ban ip");
+        self.ban_list.insert(record.ip, record.ban_time);
+        if self.ban_list.len() > BAN_LIST_CLEAR_EXPIRES_SIZE {
+            self.clear_expires_banned_ip().expect("clear ban list");
         }
     }
 
@@ -324,7 +531,61 @@ impl PeerStore for SqlitePeerStore {
         }
         false
     }
+
+    fn is_addr_banned(&self, addr: &Multiaddr) -> bool {
+        let ip = match addr.extract_ip_addr_binary() {
+            Some(ip) => ip,
+            None => return false,
+        };
+        let now = unix_time();
+        match self.ban_list.get(&ip) {
+            Some(ban_time) => *ban_time > now,
+            None => false,
+        }
+    }
+
+    fn banned_addrs(&self) -> Vec<BannedAddr> {
+        db::get_ban_records(&self.conn, unix_time())
+            .expect("get ban records")
+            .into_iter()
+            .filter_map(|record| {
+                binary_to_ip_addr(&record.ip).map(|ip| BannedAddr {
+                    ip,
+                    ban_until: record.ban_time,
+                    created_at: record.created_at,
+                    reason: record.reason,
+                })
+            })
+            .collect()
+    }
+
+    fn prune_addrs(&mut self, max_addrs: u32, protected: &[PeerId]) -> usize {
+        match self.delete_worst_addrs(max_addrs, protected) {
+            Ok(deleted) => deleted,
+            Err(err) => {
+                error!(target: "network", "failed to prune peer addrs: {:?}", err);
+                0
+            }
+        }
+    }
+
     fn peer_score_config(&self) -> PeerScoreConfig {
         self.peer_score_config
     }
 }
+
+fn binary_to_ip_addr(binary: &[u8]) -> Option<IpAddr> {
+    match binary.len() {
+        4 => {
+            let mut octets = [0u8; 4];
+            octets.copy_from_slice(binary);
+            Some(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(binary);
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
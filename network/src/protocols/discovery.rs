@@ -3,7 +3,11 @@ use crate::NetworkState;
 use fnv::FnvHashMap;
 use futures::{sync::mpsc, sync::oneshot, try_ready, Async, Future, Stream};
 use log::{debug, trace, warn};
-use std::{sync::Arc, time::Duration};
+use std::{
+    cmp::min,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use p2p::{
     context::{ProtocolContext, ProtocolContextMutRef},
@@ -145,10 +149,48 @@ pub enum DiscoveryEvent {
     },
 }
 
+// A session may send this many addresses at once, then one every 10 seconds, like the
+// addr rate limit of bitcoin
+const MAX_ADDR_TOKENS: u64 = 1000;
+const ADDR_TOKEN_INTERVAL_MILLIS: u64 = 10_000;
+
+/// Budget of addresses a session may send us, addresses over it are dropped so an addr
+/// storm can't flush the peer store
+pub(crate) struct AddrBudget {
+    // tokens in thousandths, refilled every millisecond
+    tokens: u64,
+    updated_at: Instant,
+}
+
+impl AddrBudget {
+    pub(crate) fn new(now: Instant) -> Self {
+        AddrBudget {
+            tokens: MAX_ADDR_TOKENS * ADDR_TOKEN_INTERVAL_MILLIS,
+            updated_at: now,
+        }
+    }
+
+    /// Take tokens for up to `count` addresses, return how many addresses are accepted
+    pub(crate) fn consume(&mut self, now: Instant, count: usize) -> usize {
+        if now > self.updated_at {
+            let elapsed = now - self.updated_at;
+            let elapsed_millis = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+            self.tokens = min(
+                MAX_ADDR_TOKENS * ADDR_TOKEN_INTERVAL_MILLIS,
+                self.tokens.saturating_add(elapsed_millis),
+            );
+            self.updated_at = now;
+        }
+        let accepted = min(count as u64, self.tokens / ADDR_TOKEN_INTERVAL_MILLIS);
+        self.tokens -= accepted * ADDR_TOKEN_INTERVAL_MILLIS;
+        accepted as usize
+    }
+}
+
 pub struct DiscoveryService {
     event_receiver: mpsc::UnboundedReceiver<DiscoveryEvent>,
     network_state: Arc<NetworkState>,
-    sessions: FnvHashMap<SessionId, PeerId>,
+    sessions: FnvHashMap<SessionId, (PeerId, AddrBudget)>,
 }
 
 impl DiscoveryService {
@@ -174,16 +216,35 @@ impl Stream for DiscoveryService {
                 peer_id,
             }) => {
                 if let Some(peer_id) = peer_id {
-                    self.sessions.insert(session_id, peer_id);
+                    let budget = AddrBudget::new(self.network_state.clock().now());
+                    self.sessions.insert(session_id, (peer_id, budget));
                 }
             }
             Some(DiscoveryEvent::Disconnected(session_id)) => {
                 self.sessions.remove(&session_id);
             }
             Some(DiscoveryEvent::AddNewAddrs { session_id, addrs }) => {
-                if let Some(_peer_id) = self.sessions.get(&session_id) {
-                    // TODO: wait for peer store update
-                    for addr in addrs.into_iter() {
+                if let Some((_peer_id, budget)) = self.sessions.get_mut(&session_id) {
+                    // addrs are bucketed by the network group of the peer which sent them
+                    let source = self.network_state.with_peer_registry(|peer_registry| {
+                        peer_registry
+                            .get_peer(session_id)
+                            .map(|peer| peer.address.clone())
+                    });
+                    let source = match source {
+                        Some(source) => source,
+                        None => return Ok(Async::Ready(Some(()))),
+                    };
+                    let accepted = budget.consume(self.network_state.clock().now(), addrs.len());
+                    if accepted < addrs.len() {
+                        debug!(
+                            target: "network",
+                            "session {} sent too many addrs, drop {} of them",
+                            session_id,
+                            addrs.len() - accepted
+                        );
+                    }
+                    for addr in addrs.into_iter().take(accepted) {
                         trace!(target: "network", "Add discovered address:{:?}", addr);
                         if let Some(peer_id) = extract_peer_id(&addr) {
                             let addr = addr
@@ -193,9 +254,13 @@ impl Stream for DiscoveryService {
                                     _ => true,
                                 })
                                 .collect::<Multiaddr>();
+                            if !self.network_state.is_advertisable(&addr) {
+                                trace!(target: "network", "ignore unroutable address {}", addr);
+                                continue;
+                            }
 
                             self.network_state.with_peer_store_mut(|peer_store| {
-                                if !peer_store.add_discovered_addr(&peer_id, addr) {
+                                if !peer_store.add_discovered_addr_from(&peer_id, addr, &source) {
                                     trace!(target: "network", "add_discovered_addr failed {:?}", peer_id);
                                 }
                             });
@@ -214,8 +279,17 @@ impl Stream for DiscoveryService {
                 let random_peers = self
                     .network_state
                     .with_peer_store(|peer_store| peer_store.random_peers(n as u32));
-                let addrs = random_peers
+                // the discovery announces these addrs periodically, take the chance to
+                // advertise our own confirmed external address
+                let self_addr = self
+                    .network_state
+                    .self_advertised_addr()
+                    .map(|addr| (self.network_state.local_peer_id().clone(), addr));
+                let addrs = self_addr
                     .into_iter()
+                    .chain(random_peers.into_iter())
+                    .filter(|(_peer_id, addr)| self.network_state.is_advertisable(addr))
+                    .take(n)
                     .filter_map(|(peer_id, mut addr)| {
                         Multihash::from_bytes(peer_id.into_bytes())
                             .ok()
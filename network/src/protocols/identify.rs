@@ -56,9 +56,19 @@ impl Callback for IdentifyCallback {
         );
         self.remote_listen_addrs
             .insert(peer_id.clone(), addrs.clone());
+        let source = self.network_state.with_peer_registry(|peer_registry| {
+            peer_registry
+                .get_key_by_peer_id(peer_id)
+                .and_then(|session_id| peer_registry.get_peer(session_id))
+                .map(|peer| peer.address.clone())
+        });
         self.network_state.with_peer_store_mut(|peer_store| {
             for addr in addrs {
-                if !peer_store.add_discovered_addr(&peer_id, addr) {
+                let added = match source {
+                    Some(ref source) => peer_store.add_discovered_addr_from(&peer_id, addr, source),
+                    None => peer_store.add_discovered_addr(&peer_id, addr),
+                };
+                if !added {
                     trace!(target: "network", "add_discovered_addr failed {:?}", peer_id);
                 }
             }
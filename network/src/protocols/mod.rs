@@ -4,7 +4,8 @@ pub(crate) mod identify;
 pub(crate) mod outbound_peer;
 pub(crate) mod ping;
 
-use log::{error, trace};
+use fnv::FnvHashMap;
+use log::{error, trace, warn};
 use p2p::{
     builder::MetaBuilder,
     bytes::Bytes,
@@ -19,7 +20,11 @@ use tokio::codec::length_delimited;
 
 pub type PeerIndex = SessionId;
 
-use crate::{Behaviour, NetworkState, Peer, PeerRegistry, ProtocolVersion, MAX_FRAME_LENGTH};
+use crate::rate_limiter::RateLimitVerdict;
+use crate::{
+    Behaviour, NetworkState, Peer, PeerInfo, PeerRegistry, ProtocolFeatures, ProtocolVersion,
+    MAX_FRAME_LENGTH,
+};
 
 pub trait CKBProtocolContext: Send {
     // Interact with underlying p2p service
@@ -31,6 +36,8 @@ pub trait CKBProtocolContext: Send {
     fn disconnect(&self, peer_index: PeerIndex);
     // Interact with NetworkState
     fn get_peer(&self, peer_index: PeerIndex) -> Option<Peer>;
+    // Negotiated versions, direction, latency, score and whitelist status of a connected peer
+    fn peer_info(&self, peer_index: PeerIndex) -> Option<PeerInfo>;
     fn connected_peers(&self) -> Vec<PeerIndex>;
     fn report_peer(&self, peer_index: PeerIndex, behaviour: Behaviour);
     fn ban_peer(&self, peer_index: PeerIndex, timeout: Duration);
@@ -70,6 +77,8 @@ pub struct CKBProtocol {
     protocol_name: String,
     // supported version, used to check protocol version
     supported_versions: Vec<ProtocolVersion>,
+    // features implied by the supported versions, versions not in it have no features
+    features: FnvHashMap<ProtocolVersion, ProtocolFeatures>,
     handler: Box<Fn() -> Box<dyn CKBProtocolHandler + Send + 'static> + Send + 'static>,
     network_state: Arc<NetworkState>,
 }
@@ -89,12 +98,26 @@ impl CKBProtocol {
             protocol_name: format!("/ckb/{}/", protocol_name).to_string(),
             supported_versions: {
                 let mut versions: Vec<_> = versions.to_vec();
-                versions.sort_by(|a, b| b.cmp(a));
+                versions.sort_by(|a, b| version_key(b).cmp(&version_key(a)));
                 versions.to_vec()
             },
+            features: FnvHashMap::default(),
         }
     }
 
+    /// Set the optional features implied by `version`. The features are not exchanged in the
+    /// handshake, a peer negotiating `version` is assumed to support them, so new features
+    /// must come with a new version and the features of a released version never change.
+    pub fn with_features(mut self, version: &str, features: ProtocolFeatures) -> Self {
+        assert!(
+            self.supported_versions.iter().any(|v| v == version),
+            "features of unsupported version {}",
+            version
+        );
+        self.features.insert(version.to_string(), features);
+        self
+    }
+
     pub fn id(&self) -> ProtocolId {
         self.id
     }
@@ -107,6 +130,10 @@ impl CKBProtocol {
         self.supported_versions.contains(&version)
     }
 
+    pub fn features(&self, version: &str) -> ProtocolFeatures {
+        self.features.get(version).cloned().unwrap_or(0)
+    }
+
     pub fn build(self) -> ProtocolMeta {
         let protocol_name = self.protocol_name();
         let supported_versions = self
@@ -125,10 +152,13 @@ impl CKBProtocol {
                 )
             })
             .support_versions(supported_versions)
+            .select_version(Box::new(select_version))
             .service_handle(move || {
                 ProtocolHandle::Both(Box::new(CKBHandler {
                     proto_id: self.id,
                     network_state: Arc::clone(&self.network_state),
+                    supported_versions: self.supported_versions.clone(),
+                    features: self.features.clone(),
                     handler: (self.handler)(),
                 }))
             })
@@ -136,9 +166,64 @@ impl CKBProtocol {
     }
 }
 
+// Versions are dot separated numbers, compared numerically so "10" is above "9".
+// Return None for malformed versions.
+fn version_key(version: &str) -> Option<Vec<u32>> {
+    version
+        .split('.')
+        .map(|part| part.parse::<u32>().ok())
+        .collect()
+}
+
+/// Select the highest version supported by both sides, malformed versions are ignored
+pub fn select_version(
+    local: &[ProtocolVersion],
+    remote: &[ProtocolVersion],
+) -> Option<ProtocolVersion> {
+    local
+        .iter()
+        .filter(|version| remote.contains(version))
+        .filter_map(|version| version_key(version).map(|key| (key, version)))
+        .max_by(|a, b| a.0.cmp(&b.0))
+        .map(|(_, version)| version.clone())
+}
+
+/// Whether `version` is made of dot separated numbers
+pub fn is_valid_version(version: &str) -> bool {
+    version_key(version).is_some()
+}
+
+/// Whether `version` is at or above `min_version`, both compared numerically
+pub fn meets_min_version(version: &str, min_version: &str) -> bool {
+    match (version_key(version), version_key(min_version)) {
+        (Some(version), Some(min_version)) => version >= min_version,
+        // malformed floors are rejected when the config is loaded, a malformed version can't
+        // meet a floor
+        (version, None) => version.is_some(),
+        (None, Some(_)) => false,
+    }
+}
+
+/// Why the local node closes a session, the code is logged to tell the reasons apart,
+/// e.g. to follow a coordinated protocol upgrade
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisconnectReason {
+    UnsupportedProtocolVersion = 1,
+    ProtocolVersionTooLow = 2,
+    ExceedRateLimit = 3,
+}
+
+impl DisconnectReason {
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+}
+
 struct CKBHandler {
     proto_id: ProtocolId,
     network_state: Arc<NetworkState>,
+    supported_versions: Vec<ProtocolVersion>,
+    features: FnvHashMap<ProtocolVersion, ProtocolFeatures>,
     handler: Box<dyn CKBProtocolHandler>,
 }
 
@@ -155,12 +240,54 @@ impl ServiceProtocol for CKBHandler {
     }
 
     fn connected(&mut self, context: ProtocolContextMutRef, version: &str) {
+        let peer_index = context.session.id;
+        // the remote may open the protocol with any version, don't hand unknown versions
+        // to the handler
+        if !self.supported_versions.iter().any(|v| v == version) {
+            warn!(
+                target: "network",
+                "peer {} opened protocol {} with unsupported version {:?}, disconnect it, reason {:?}({})",
+                peer_index,
+                self.proto_id,
+                version,
+                DisconnectReason::UnsupportedProtocolVersion,
+                DisconnectReason::UnsupportedProtocolVersion.code(),
+            );
+            context.disconnect(peer_index);
+            return;
+        }
+        if let Some(min_version) = self
+            .network_state
+            .config
+            .min_protocol_version(self.proto_id)
+        {
+            if !meets_min_version(version, min_version) {
+                warn!(
+                    target: "network",
+                    "peer {} opened protocol {} with version {:?} below minimum {:?}, disconnect it, reason {:?}({})",
+                    peer_index,
+                    self.proto_id,
+                    version,
+                    min_version,
+                    DisconnectReason::ProtocolVersionTooLow,
+                    DisconnectReason::ProtocolVersionTooLow.code(),
+                );
+                context.disconnect(peer_index);
+                return;
+            }
+        }
+        let features = self.features.get(version).cloned().unwrap_or(0);
+        self.network_state.with_peer_registry_mut(|reg| {
+            if let Some(peer) = reg.get_peer_mut(peer_index) {
+                peer.protocol_features.insert(self.proto_id, features);
+            }
+        });
+
         let nc = DefaultCKBProtocolContext {
             proto_id: self.proto_id,
             network_state: Arc::clone(&self.network_state),
             p2p_control: context.control().clone(),
         };
-        let peer_index = context.session.id;
         self.handler.connected(Box::new(nc), peer_index, version);
     }
 
@@ -176,12 +303,39 @@ impl ServiceProtocol for CKBHandler {
 
     fn received(&mut self, context: ProtocolContextMutRef, data: bytes::Bytes) {
         trace!(target: "network", "[received message]: {}, {}, length={}", self.proto_id, context.session.id, data.len());
+        let peer_index = context.session.id;
+        match self
+            .network_state
+            .check_rate_limit(peer_index, self.proto_id)
+        {
+            RateLimitVerdict::Allow => {}
+            RateLimitVerdict::Throttle => {
+                trace!(target: "network", "peer {} exceeds rate limit of protocol {}, drop message", peer_index, self.proto_id);
+                return;
+            }
+            RateLimitVerdict::Disconnect => {
+                warn!(
+                    target: "network",
+                    "peer {} keeps exceeding rate limit of protocol {}, disconnect it, reason {:?}({})",
+                    peer_index,
+                    self.proto_id,
+                    DisconnectReason::ExceedRateLimit,
+                    DisconnectReason::ExceedRateLimit.code(),
+                );
+                self.network_state.report_session(
+                    context.control(),
+                    peer_index,
+                    Behaviour::ExceedRateLimit,
+                );
+                context.disconnect(peer_index);
+                return;
+            }
+        }
         let nc = DefaultCKBProtocolContext {
             proto_id: self.proto_id,
             network_state: Arc::clone(&self.network_state),
             p2p_control: context.control().clone(),
         };
-        let peer_index = context.session.id;
         self.handler.received(Box::new(nc), peer_index, data);
     }
 
@@ -225,12 +379,19 @@ impl CKBProtocolContext for DefaultCKBProtocolContext {
     }
     fn send_message(&self, proto_id: ProtocolId, peer_index: PeerIndex, data: Bytes) {
         trace!(target: "network", "[send message]: {}, to={}, length={}", proto_id, peer_index, data.len());
+        self.network_state
+            .record_sent(&TargetSession::Single(peer_index), proto_id, data.len());
         if let Err(err) = self.p2p_control.send_message_to(peer_index, proto_id, data) {
             error!(target: "network", "send message to p2p service error: {:?}", err);
         }
     }
     fn send_message_to(&self, peer_index: PeerIndex, data: Bytes) {
         trace!(target: "network", "[send message to]: {}, to={}, length={}", self.proto_id, peer_index, data.len());
+        self.network_state.record_sent(
+            &TargetSession::Single(peer_index),
+            self.proto_id,
+            data.len(),
+        );
         if let Err(err) = self
             .p2p_control
             .send_message_to(peer_index, self.proto_id, data)
@@ -239,6 +400,8 @@ impl CKBProtocolContext for DefaultCKBProtocolContext {
         }
     }
     fn filter_broadcast(&self, target: TargetSession, data: Bytes) {
+        self.network_state
+            .record_sent(&target, self.proto_id, data.len());
         if let Err(err) = self
             .p2p_control
             .filter_broadcast(target, self.proto_id, data)
@@ -256,6 +419,13 @@ impl CKBProtocolContext for DefaultCKBProtocolContext {
         self.network_state
             .with_peer_registry(|reg| reg.get_peer(peer_index).cloned())
     }
+    fn peer_info(&self, peer_index: PeerIndex) -> Option<PeerInfo> {
+        let peer = self.get_peer(peer_index)?;
+        let score = self
+            .network_state
+            .with_peer_store(|peer_store| peer_store.peer_score(&peer.peer_id));
+        Some(PeerInfo::new(&peer, score))
+    }
     fn connected_peers(&self) -> Vec<PeerIndex> {
         self.network_state
             .with_peer_registry(PeerRegistry::connected_peers)
@@ -265,8 +435,12 @@ impl CKBProtocolContext for DefaultCKBProtocolContext {
             .report_session(&self.p2p_control, peer_index, behaviour);
     }
     fn ban_peer(&self, peer_index: PeerIndex, timeout: Duration) {
-        self.network_state
-            .ban_session(&self.p2p_control, peer_index, timeout);
+        self.network_state.ban_session(
+            &self.p2p_control,
+            peer_index,
+            timeout,
+            format!("misbehave in protocol {}", self.proto_id),
+        );
     }
 
     fn protocol_id(&self) -> ProtocolId {
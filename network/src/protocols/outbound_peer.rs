@@ -1,18 +1,33 @@
+use crate::clock::IntervalStream;
+use crate::network_group::{Group, NetworkGroup};
 use crate::NetworkState;
+use fnv::FnvHashSet;
 use futures::{try_ready, Async, Stream};
 use log::{debug, trace, warn};
-use p2p::service::ServiceControl;
+use p2p::{multiaddr::Multiaddr, service::ServiceControl};
+use std::cmp::min;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::usize;
-use tokio::timer::Interval;
 
 const FEELER_CONNECTION_COUNT: u32 = 5;
+// 10 minutes
+const PEER_STORE_GC_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Whether `addr` is outside the occupied network `groups`, its group is occupied from then
+/// on. Local and unknown addresses are exempt, e.g. nodes of a local testnet.
+pub(crate) fn occupy_network_group(groups: &mut FnvHashSet<Group>, addr: &Multiaddr) -> bool {
+    match addr.network_group() {
+        group @ Group::IP4(_) | group @ Group::IP6(_) => groups.insert(group),
+        Group::LocalNetwork | Group::NoGroup => true,
+    }
+}
 
 pub struct OutboundPeerService {
-    pub stream_interval: Interval,
+    pub stream_interval: IntervalStream,
     pub network_state: Arc<NetworkState>,
     pub p2p_control: ServiceControl,
+    last_gc_at: Instant,
 }
 
 impl OutboundPeerService {
@@ -21,40 +36,74 @@ impl OutboundPeerService {
         p2p_control: ServiceControl,
         try_connect_interval: Duration,
     ) -> Self {
+        let stream_interval = network_state.clock().interval(try_connect_interval);
+        let last_gc_at = network_state.clock().now();
         OutboundPeerService {
             network_state,
             p2p_control,
-            stream_interval: Interval::new_interval(try_connect_interval),
+            stream_interval,
+            last_gc_at,
         }
     }
 
-    fn attempt_dial_peers(&mut self, count: u32) {
+    // Block relay only peers are always picked from unoccupied network groups
+    fn attempt_dial_peers(&mut self, count: u32, block_relay_only: bool) {
+        let now = self.network_state.clock().now();
+        let pruned = self.network_state.dial_scheduler.lock().expire_dials(now);
+        for (peer_id, addr) in pruned {
+            self.network_state.prune_addr(&peer_id, &addr);
+        }
+        let count = min(
+            count,
+            self.network_state.dial_scheduler.lock().available_slots() as u32,
+        );
+        if count == 0 {
+            trace!(target: "network", "no dial slot available");
+            return;
+        }
+        let mut occupied_groups =
+            if self.network_state.config.allow_outbound_same_group && !block_relay_only {
+                None
+            } else {
+                Some(
+                    self.network_state
+                        .with_peer_registry(|reg| reg.outbound_groups()),
+                )
+            };
+        // fetch more candidates, the ones in occupied groups are skipped
+        let extra = occupied_groups
+            .as_ref()
+            .map(|groups| groups.len())
+            .unwrap_or(0) as u32;
         let attempt_peers = self
             .network_state
-            .with_peer_store(|peer_store| peer_store.peers_to_attempt(count + 5));
+            .with_peer_store(|peer_store| peer_store.peers_to_attempt(count + extra + 5));
         let p2p_control = self.p2p_control.clone();
         trace!(target: "network", "count={}, attempt_peers: {:?}", count, attempt_peers);
-        for (peer_id, addr) in attempt_peers
+        let mut dial_scheduler = self.network_state.dial_scheduler.lock();
+        let dial_peers: Vec<_> = attempt_peers
             .into_iter()
-            .filter(|(peer_id, _addr)| {
+            .filter(|(peer_id, addr)| {
                 self.network_state.local_peer_id() != peer_id
                     && !self
                         .network_state
                         .with_peer_registry(|reg| reg.is_feeler(peer_id))
-                    && self
-                        .network_state
-                        .failed_dials
-                        .read()
-                        .get(peer_id)
-                        .map(|last_dial| {
-                            // Dial after 5 minutes when last failed
-                            Instant::now() - *last_dial > Duration::from_secs(300)
-                        })
-                        .unwrap_or(true)
+                    && dial_scheduler.can_dial(addr, now)
+            })
+            .filter(|(_peer_id, addr)| match occupied_groups {
+                Some(ref mut groups) => occupy_network_group(groups, addr),
+                None => true,
             })
             .take(count as usize)
-        {
-            debug!(target: "network", "dial attempt peer: {:?}", addr);
+            .collect();
+        for (peer_id, addr) in dial_peers {
+            debug!(target: "network", "dial attempt peer: {:?}, block relay only: {}", addr, block_relay_only);
+            if block_relay_only {
+                self.network_state.with_peer_registry_mut(|reg| {
+                    reg.add_block_relay_only_dial(peer_id.clone());
+                });
+            }
+            dial_scheduler.dial_started(&peer_id, &addr, now);
             self.network_state.dial_all(&p2p_control, &peer_id, addr);
         }
     }
@@ -84,11 +133,27 @@ impl Stream for OutboundPeerService {
     fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Self::Error> {
         match try_ready!(self.stream_interval.poll().map_err(|_| ())) {
             Some(_tick) => {
+                trace!(target: "network", "network state metrics: {:?}", self.network_state.metrics());
+                let now = self.network_state.clock().now();
+                if now.duration_since(self.last_gc_at) >= PEER_STORE_GC_INTERVAL {
+                    self.network_state.prune_peer_store();
+                    self.last_gc_at = now;
+                }
                 let status = self.network_state.connection_status();
-                let new_outbound = status.max_outbound - status.unreserved_outbound;
-                if new_outbound > 0 {
+                let new_outbound = status
+                    .max_outbound
+                    .saturating_sub(status.unreserved_outbound);
+                let new_block_relay_only = status
+                    .max_block_relay_only
+                    .saturating_sub(status.block_relay_only_outbound);
+                if new_outbound > 0 || new_block_relay_only > 0 {
                     // dial peers
-                    self.attempt_dial_peers(new_outbound as u32);
+                    if new_outbound > 0 {
+                        self.attempt_dial_peers(new_outbound, false);
+                    }
+                    if new_block_relay_only > 0 {
+                        self.attempt_dial_peers(new_block_relay_only, true);
+                    }
                 } else {
                     // feeler peers
                     self.feeler_peers(FEELER_CONNECTION_COUNT);
@@ -4,7 +4,6 @@ use log::{debug, trace, warn};
 use p2p::service::ServiceControl;
 use p2p_ping::Event;
 use std::sync::Arc;
-use std::time::Instant;
 
 pub struct PingService {
     network_state: Arc<NetworkState>,
@@ -39,10 +38,11 @@ impl Stream for PingService {
             Some(Pong(peer_id, duration)) => {
                 trace!(target: "network", "receive pong from {:?} duration {:?}", peer_id, duration);
                 if let Some(session_id) = self.network_state.query_session_id(&peer_id) {
+                    let now = self.network_state.clock().now();
                     self.network_state.with_peer_registry_mut(|reg| {
                         if let Some(mut peer) = reg.get_peer_mut(session_id) {
-                            peer.ping = Some(duration);
-                            peer.last_ping_time = Some(Instant::now());
+                            peer.update_rtt(duration);
+                            peer.last_ping_time = Some(now);
                         }
                     })
                 }
@@ -0,0 +1,126 @@
+use crate::ProtocolId;
+use fnv::FnvHashMap;
+use p2p::SessionId;
+use serde_derive::{Deserialize, Serialize};
+use std::cmp::min;
+use std::time::{Duration, Instant};
+
+// tokens are counted in millionths so the bucket refills every microsecond
+const TOKEN: u64 = 1_000_000;
+
+/// Message budget of a protocol, every peer gets its own token bucket
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    pub protocol_id: usize,
+    /// Messages a peer may send per second in the long run, 0 means unlimited
+    pub messages_per_second: u32,
+    /// Messages a peer may send at once after staying quiet, at least 1
+    pub burst: u32,
+}
+
+impl RateLimitConfig {
+    /// A limited protocol with no burst would drop every message and disconnect the peer
+    pub fn validate(&self) -> Result<(), String> {
+        if self.messages_per_second > 0 && self.burst == 0 {
+            return Err(format!(
+                "network.rate_limits of protocol {} sets burst to 0",
+                self.protocol_id
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitVerdict {
+    /// Handle the message
+    Allow,
+    /// Over the budget, drop the message
+    Throttle,
+    /// The peer kept exceeding the budget, report and disconnect it
+    Disconnect,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: u64,
+    updated_at: Instant,
+    // messages dropped since the bucket was last full
+    throttled: u32,
+}
+
+/// Per peer per protocol token buckets of received messages.
+///
+/// Messages over the budget are dropped, a peer which had as many messages dropped as
+/// the burst size without backing off until its bucket is full again is disconnected.
+pub struct RateLimiter {
+    limits: FnvHashMap<ProtocolId, RateLimitConfig>,
+    buckets: FnvHashMap<(SessionId, ProtocolId), Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: &[RateLimitConfig]) -> Self {
+        RateLimiter {
+            limits: limits
+                .iter()
+                .filter(|limit| limit.messages_per_second > 0)
+                .map(|limit| (limit.protocol_id.into(), limit.clone()))
+                .collect(),
+            buckets: FnvHashMap::default(),
+        }
+    }
+
+    /// Take a token for a message `session_id` sent on `proto_id`
+    pub fn check(
+        &mut self,
+        now: Instant,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+    ) -> RateLimitVerdict {
+        let limit = match self.limits.get(&proto_id) {
+            Some(limit) => limit,
+            None => return RateLimitVerdict::Allow,
+        };
+        let capacity = u64::from(limit.burst) * TOKEN;
+        let bucket = self
+            .buckets
+            .entry((session_id, proto_id))
+            .or_insert_with(|| Bucket {
+                tokens: capacity,
+                updated_at: now,
+                throttled: 0,
+            });
+
+        if now > bucket.updated_at {
+            let elapsed = now - bucket.updated_at;
+            let elapsed_micros = elapsed
+                .as_secs()
+                .saturating_mul(1_000_000)
+                .saturating_add(u64::from(elapsed.subsec_micros()));
+            // a token per 1_000_000 / messages_per_second microseconds
+            let refill = elapsed_micros.saturating_mul(u64::from(limit.messages_per_second));
+            bucket.tokens = min(capacity, bucket.tokens.saturating_add(refill));
+            // the nanoseconds not counted yet are kept for the next refill
+            bucket.updated_at += Duration::from_micros(elapsed_micros);
+        }
+        if bucket.tokens == capacity {
+            bucket.throttled = 0;
+        }
+
+        if bucket.tokens >= TOKEN {
+            bucket.tokens -= TOKEN;
+            RateLimitVerdict::Allow
+        } else if bucket.throttled < limit.burst {
+            bucket.throttled += 1;
+            RateLimitVerdict::Throttle
+        } else {
+            self.buckets.remove(&(session_id, proto_id));
+            RateLimitVerdict::Disconnect
+        }
+    }
+
+    pub fn remove_session(&mut self, session_id: SessionId) {
+        self.buckets
+            .retain(|(bucket_session_id, _), _| *bucket_session_id != session_id);
+    }
+}
@@ -0,0 +1,34 @@
+use crate::bandwidth::{BandwidthMonitor, BANDWIDTH_RATE_WINDOW};
+use crate::ProtocolId;
+use p2p::SessionId;
+use std::time::Instant;
+
+#[test]
+fn test_bandwidth_monitor() {
+    let start = Instant::now();
+    let session1: SessionId = 1.into();
+    let session2: SessionId = 2.into();
+    let proto0: ProtocolId = 0.into();
+    let proto1: ProtocolId = 1.into();
+    let mut monitor = BandwidthMonitor::new(start);
+    monitor.record_sent(start, session1, proto0, 100);
+    monitor.record_received(start, session1, proto1, 300);
+    monitor.record_received(start, session2, proto1, 50);
+
+    let bandwidth = monitor.peer_bandwidth(start);
+    assert_eq!(bandwidth.len(), 2);
+    let peer = &bandwidth[&session1];
+    assert_eq!(peer.total.total_sent, 100);
+    assert_eq!(peer.total.total_received, 300);
+    assert_eq!(peer.protocols[&proto1].total_received, 300);
+    assert_eq!(peer.total.received_rate, 0);
+
+    // rates are available after the window ends
+    let bandwidth = monitor.peer_bandwidth(start + BANDWIDTH_RATE_WINDOW);
+    let secs = BANDWIDTH_RATE_WINDOW.as_secs();
+    assert_eq!(bandwidth[&session1].total.sent_rate, 100 / secs);
+    assert_eq!(bandwidth[&session1].total.received_rate, 300 / secs);
+
+    monitor.remove_session(session1);
+    assert_eq!(monitor.peer_bandwidth(start).len(), 1);
+}
@@ -0,0 +1,14 @@
+use crate::clock::{Clock, MockClock};
+use futures::Stream;
+use std::time::Duration;
+
+#[test]
+fn test_mock_clock_interval() {
+    let clock = MockClock::default();
+    let start = clock.now();
+    let mut interval = clock.interval(Duration::from_secs(10)).wait();
+    clock.advance(Duration::from_secs(25));
+    assert_eq!(clock.now() - start, Duration::from_secs(25));
+    assert_eq!(interval.next(), Some(Ok(start + Duration::from_secs(10))));
+    assert_eq!(interval.next(), Some(Ok(start + Duration::from_secs(20))));
+}
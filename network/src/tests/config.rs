@@ -0,0 +1,86 @@
+use super::network_config;
+use crate::multiaddr::{Multiaddr, ToMultiaddr};
+
+const BOOTNODE_A: &str =
+    "/ip4/127.0.0.1/tcp/8114/p2p/QmTRHCdrRtgUzYLNCin69zEvPvLYdxUZLLfLYyHVY3DZAS";
+const BOOTNODE_B: &str =
+    "/ip4/127.0.0.2/tcp/8114/p2p/QmTRHCdrRtgUzYLNCin69zEvPvLYdxUZLLfLYyHVY3DZAS";
+const BOOTNODE_C: &str =
+    "/ip4/127.0.0.3/tcp/8114/p2p/QmTRHCdrRtgUzYLNCin69zEvPvLYdxUZLLfLYyHVY3DZAS";
+
+fn addr(addr: &str) -> Multiaddr {
+    addr.to_multiaddr().expect("parse multiaddr")
+}
+
+fn strings(items: &[&str]) -> Vec<String> {
+    items.iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn test_merge_spec_seeds() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut config = network_config(dir.path(), 0);
+    config.bootnodes = vec![addr(BOOTNODE_B), addr(BOOTNODE_C)];
+    config.dns_seeds = strings(&["seed-b", "seed-c"]);
+    config.dns_seed_pubkeys = strings(&["key-b"]);
+
+    // the spec seeds come first, the duplicates are kept at their first position
+    config
+        .merge_spec_seeds(
+            &strings(&[BOOTNODE_A, BOOTNODE_B, BOOTNODE_A]),
+            &strings(&["seed-a", "seed-c"]),
+            &strings(&["key-a", "key-b", "key-a"]),
+        )
+        .expect("merge");
+    assert_eq!(
+        config.bootnodes,
+        vec![addr(BOOTNODE_A), addr(BOOTNODE_B), addr(BOOTNODE_C)]
+    );
+    assert_eq!(config.dns_seeds, strings(&["seed-a", "seed-c", "seed-b"]));
+    assert_eq!(config.dns_seed_pubkeys, strings(&["key-a", "key-b"]));
+    assert_eq!(config.bootnodes().expect("bootnodes").len(), 3);
+
+    // merging again changes nothing
+    let (bootnodes, dns_seeds) = (config.bootnodes.clone(), config.dns_seeds.clone());
+    config
+        .merge_spec_seeds(
+            &strings(&[BOOTNODE_A]),
+            &strings(&["seed-a"]),
+            &strings(&["key-a"]),
+        )
+        .expect("merge");
+    assert_eq!(config.bootnodes, bootnodes);
+    assert_eq!(config.dns_seeds, dns_seeds);
+}
+
+#[test]
+fn test_merge_spec_seeds_ignored() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut config = network_config(dir.path(), 0);
+    config.bootnodes = vec![addr(BOOTNODE_B)];
+    config.ignore_spec_seeds = true;
+
+    config
+        .merge_spec_seeds(
+            &strings(&[BOOTNODE_A]),
+            &strings(&["seed-a"]),
+            &strings(&["key-a"]),
+        )
+        .expect("merge");
+    assert_eq!(config.bootnodes, vec![addr(BOOTNODE_B)]);
+    assert!(config.dns_seeds.is_empty());
+    assert!(config.dns_seed_pubkeys.is_empty());
+}
+
+#[test]
+fn test_merge_malformed_spec_bootnode() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut config = network_config(dir.path(), 0);
+    config.bootnodes = vec![addr(BOOTNODE_B)];
+
+    assert!(config
+        .merge_spec_seeds(&strings(&["not an address"]), &[], &[])
+        .is_err());
+    // the configured seeds are left untouched
+    assert_eq!(config.bootnodes, vec![addr(BOOTNODE_B)]);
+}
@@ -0,0 +1,89 @@
+use crate::dial_scheduler::{DialFailure, DialScheduler, DIAL_TIMEOUT, MAX_DIAL_FAILURES};
+use crate::{multiaddr::ToMultiaddr, PeerId};
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_backoff_after_failure() {
+    let mut scheduler = DialScheduler::default();
+    let addr = "/ip4/1.1.1.1/tcp/8115".to_multiaddr().unwrap();
+    let now = Instant::now();
+    assert!(scheduler.can_dial(&addr, now));
+
+    assert_eq!(scheduler.dial_failed(&addr, now), DialFailure::Retry);
+    assert!(!scheduler.can_dial(&addr, now));
+    // the first backoff is 30 to 60 seconds
+    assert!(!scheduler.can_dial(&addr, now + Duration::from_secs(29)));
+    assert!(scheduler.can_dial(&addr, now + Duration::from_secs(60)));
+
+    // the backoff doubles
+    scheduler.dial_failed(&addr, now);
+    assert!(!scheduler.can_dial(&addr, now + Duration::from_secs(59)));
+    assert!(scheduler.can_dial(&addr, now + Duration::from_secs(120)));
+
+    assert_eq!(scheduler.dial_succeeded(&addr, now), None);
+    assert!(scheduler.can_dial(&addr, now));
+    assert!(scheduler.is_empty());
+}
+
+#[test]
+fn test_prune_after_max_failures() {
+    let mut scheduler = DialScheduler::default();
+    let addr = "/ip4/1.1.1.1/tcp/8115".to_multiaddr().unwrap();
+    let now = Instant::now();
+    for _ in 1..MAX_DIAL_FAILURES {
+        assert_eq!(scheduler.dial_failed(&addr, now), DialFailure::Retry);
+    }
+    assert_eq!(scheduler.dial_failed(&addr, now), DialFailure::Prune);
+}
+
+#[test]
+fn test_concurrent_dials_cap_and_timeout() {
+    let mut scheduler = DialScheduler::new(2);
+    let peer_id = PeerId::random();
+    let addr1 = "/ip4/1.1.1.1/tcp/8115".to_multiaddr().unwrap();
+    let addr2 = "/ip4/2.2.2.2/tcp/8115".to_multiaddr().unwrap();
+    let now = Instant::now();
+
+    scheduler.dial_started(&peer_id, &addr1, now);
+    assert!(!scheduler.can_dial(&addr1, now));
+    assert_eq!(scheduler.available_slots(), 1);
+    scheduler.dial_started(&peer_id, &addr2, now);
+    assert_eq!(scheduler.available_slots(), 0);
+
+    assert_eq!(
+        scheduler.dial_succeeded(&addr2, now + Duration::from_secs(1)),
+        Some(Duration::from_secs(1))
+    );
+    assert_eq!(scheduler.available_slots(), 1);
+
+    assert!(scheduler.expire_dials(now).is_empty());
+    assert_eq!(scheduler.available_slots(), 1);
+    // a dial without answer is counted as failed
+    assert!(scheduler.expire_dials(now + DIAL_TIMEOUT).is_empty());
+    assert_eq!(scheduler.available_slots(), 2);
+    assert_eq!(scheduler.len(), 1);
+    assert!(!scheduler.can_dial(&addr1, now + DIAL_TIMEOUT));
+}
+
+#[test]
+fn test_dump_dial_queue() {
+    let mut scheduler = DialScheduler::default();
+    let peer_id = PeerId::random();
+    let addr1 = "/ip4/1.1.1.1/tcp/8115".to_multiaddr().unwrap();
+    let addr2 = "/ip4/2.2.2.2/tcp/8115".to_multiaddr().unwrap();
+    let now = Instant::now();
+
+    scheduler.dial_failed(&addr1, now);
+    scheduler.dial_started(&peer_id, &addr2, now);
+
+    let mut dials = scheduler.dump(now);
+    dials.sort_by_key(|dial| dial.address.clone());
+    assert_eq!(dials.len(), 2);
+    assert_eq!(dials[0].address, addr1.to_string());
+    assert_eq!(dials[0].failures, 1);
+    assert_eq!(dials[0].peer_id, None);
+    assert!(dials[0].retry_in_secs >= 29);
+    assert_eq!(dials[1].address, addr2.to_string());
+    assert_eq!(dials[1].peer_id, Some(peer_id.to_base58()));
+    assert_eq!(dials[1].retry_in_secs, 0);
+}
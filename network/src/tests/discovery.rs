@@ -0,0 +1,19 @@
+use crate::protocols::discovery::AddrBudget;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_addr_budget() {
+    let now = Instant::now();
+    let mut budget = AddrBudget::new(now);
+    assert_eq!(budget.consume(now, 600), 600);
+    assert_eq!(budget.consume(now, 600), 400);
+    assert_eq!(budget.consume(now, 1), 0);
+
+    // one address every 10 seconds
+    assert_eq!(budget.consume(now + Duration::from_secs(9), 1), 0);
+    assert_eq!(budget.consume(now + Duration::from_secs(25), 10), 2);
+
+    // the budget is capped after a long silence
+    let later = now + Duration::from_secs(100_000);
+    assert_eq!(budget.consume(later, 2000), 1000);
+}
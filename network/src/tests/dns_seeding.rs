@@ -0,0 +1,185 @@
+use super::network_config;
+use crate::clock::MockClock;
+use crate::dns_seeding::{
+    export_seed_records, txt_record_text, DnsSeedingService, SeedRecord, SeedRecordError,
+    INITIAL_RETRY_INTERVAL, SEEDING_MIN_PEERS,
+};
+use crate::multiaddr::Multiaddr;
+use crate::peer_store::{PeerStore, SqlitePeerStore};
+use crate::{NetworkState, PeerId, SessionType};
+use crypto::secp::Generator;
+use futures::executor::{self, Notify, NotifyHandle};
+use futures::{future, Future};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use trust_dns_resolver::proto::rr::rdata::TXT;
+
+struct NoopNotify;
+
+impl Notify for NoopNotify {
+    fn notify(&self, _id: usize) {}
+}
+
+fn add_connected_peers(network_state: &NetworkState, ports: Range<u32>) {
+    network_state.with_peer_store_mut(|peer_store| {
+        for port in ports {
+            let peer_id = PeerId::random();
+            let addr: Multiaddr = format!("/ip4/1.1.1.1/tcp/{}", port).parse().unwrap();
+            peer_store.add_discovered_addr(&peer_id, addr.clone());
+            peer_store.add_connected_peer(&peer_id, addr, SessionType::Outbound);
+        }
+    });
+}
+
+#[test]
+fn test_seeding_retries_until_enough_peers() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let clock = MockClock::default();
+    let network_state = Arc::new(
+        NetworkState::from_config(network_config(dir.path(), 0))
+            .unwrap()
+            .with_clock(Arc::new(clock.clone())),
+    );
+    let service = DnsSeedingService::new(Arc::clone(&network_state), Vec::new(), Vec::new(), 0);
+    let rounds = Arc::new(AtomicUsize::new(0));
+    let seeding = {
+        let rounds = Arc::clone(&rounds);
+        let network_state = Arc::clone(&network_state);
+        DnsSeedingService::seeding_loop(Arc::new(service), move |_| {
+            // a few peers are found in each round
+            let round = rounds.fetch_add(1, Ordering::SeqCst) as u32;
+            add_connected_peers(&network_state, round * 4..(round + 1) * 4);
+            future::ok(())
+        })
+    };
+    let notify = NotifyHandle::from(Arc::new(NoopNotify));
+    let mut seeding = executor::spawn(seeding);
+
+    // the first round runs at once, then waits for the retry interval
+    assert!(seeding
+        .poll_future_notify(&notify, 0)
+        .unwrap()
+        .is_not_ready());
+    assert_eq!(rounds.load(Ordering::SeqCst), 1);
+    clock.advance(INITIAL_RETRY_INTERVAL - Duration::from_secs(1));
+    assert!(seeding
+        .poll_future_notify(&notify, 0)
+        .unwrap()
+        .is_not_ready());
+    assert_eq!(rounds.load(Ordering::SeqCst), 1);
+    clock.advance(Duration::from_secs(1));
+    assert!(seeding
+        .poll_future_notify(&notify, 0)
+        .unwrap()
+        .is_not_ready());
+    assert_eq!(rounds.load(Ordering::SeqCst), 2);
+
+    // the interval doubles
+    clock.advance(INITIAL_RETRY_INTERVAL);
+    assert!(seeding
+        .poll_future_notify(&notify, 0)
+        .unwrap()
+        .is_not_ready());
+    assert_eq!(rounds.load(Ordering::SeqCst), 2);
+    clock.advance(INITIAL_RETRY_INTERVAL);
+    // enough peers after the third round
+    assert!(seeding.poll_future_notify(&notify, 0).unwrap().is_ready());
+    assert_eq!(rounds.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_seeding_skipped_with_enough_peers() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let network_state = Arc::new(NetworkState::from_config(network_config(dir.path(), 0)).unwrap());
+    add_connected_peers(&network_state, 0..SEEDING_MIN_PEERS);
+    let service = DnsSeedingService::new(Arc::clone(&network_state), Vec::new(), Vec::new(), 0);
+    let rounds = Arc::new(AtomicUsize::new(0));
+    let seeding = {
+        let rounds = Arc::clone(&rounds);
+        DnsSeedingService::seeding_loop(Arc::new(service), move |_| {
+            rounds.fetch_add(1, Ordering::SeqCst);
+            future::ok(())
+        })
+    };
+    assert_eq!(seeding.wait(), Ok(()));
+    assert_eq!(rounds.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn test_txt_record_text() {
+    let txt = TXT::new(vec!["/ip4/1.1.1.1/tcp/".to_string(), "8115".to_string()]);
+    assert_eq!(
+        txt_record_text(&txt),
+        Some("/ip4/1.1.1.1/tcp/8115".to_string())
+    );
+}
+
+#[test]
+fn test_verify_seed_record() {
+    let (privkey, pubkey) = Generator::new().random_keypair().unwrap();
+    let (other_privkey, other_pubkey) = Generator::new().random_keypair().unwrap();
+    let seed_record = SeedRecord {
+        address: "/ip4/1.1.1.1/tcp/8115".parse().unwrap(),
+        peer_id: PeerId::random(),
+        valid_until: 1000,
+        network_magic: 42,
+    };
+    let record = seed_record.encode(&privkey).unwrap();
+
+    // any of the trusted keys is accepted
+    let trusted = vec![other_pubkey.serialize(), pubkey.serialize()];
+    assert_eq!(
+        SeedRecord::decode_and_verify(&record, &trusted, 42, 999),
+        Ok(seed_record.clone())
+    );
+    assert_eq!(
+        SeedRecord::decode_and_verify(&record, &trusted[..1], 42, 999),
+        Err(SeedRecordError::UntrustedSigner)
+    );
+    assert_eq!(
+        SeedRecord::decode_and_verify(&record, &trusted, 43, 999),
+        Err(SeedRecordError::NetworkMismatch)
+    );
+    assert_eq!(
+        SeedRecord::decode_and_verify(&record, &trusted, 42, 1001),
+        Err(SeedRecordError::Expired)
+    );
+
+    // signed by another key
+    let forged = seed_record.encode(&other_privkey).unwrap();
+    assert_eq!(
+        SeedRecord::decode_and_verify(&forged, &trusted[1..], 42, 999),
+        Err(SeedRecordError::UntrustedSigner)
+    );
+    // the signature does not cover the tampered port
+    let tampered = record.replace("8115", "8116");
+    assert!(SeedRecord::decode_and_verify(&tampered, &trusted[1..], 42, 999).is_err());
+    assert_eq!(
+        SeedRecord::decode_and_verify("/ip4/1.1.1.1/tcp/8115;1000;2a", &trusted, 42, 999),
+        Err(SeedRecordError::InvalidRecord)
+    );
+}
+
+#[test]
+fn test_export_seed_records() {
+    let (privkey, pubkey) = Generator::new().random_keypair().unwrap();
+    let mut peer_store = SqlitePeerStore::memory().unwrap();
+    let peer_id = PeerId::random();
+    let addr: Multiaddr = "/ip4/1.1.1.1/tcp/8115".parse().unwrap();
+    peer_store.add_discovered_addr(&peer_id, addr.clone());
+    peer_store.add_connected_peer(&peer_id, addr.clone(), SessionType::Outbound);
+
+    let records = export_seed_records(&peer_store, &privkey, 42, 1000, 10).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(
+        SeedRecord::decode_and_verify(&records[0], &[pubkey.serialize()], 42, 999),
+        Ok(SeedRecord {
+            address: addr,
+            peer_id,
+            valid_until: 1000,
+            network_magic: 42,
+        })
+    );
+}
@@ -1,3 +1,46 @@
+mod bandwidth;
+mod clock;
+mod config;
+mod dial_scheduler;
+mod discovery;
+mod dns_seeding;
+mod network_group;
 mod peer_registry;
+mod protocols;
+mod rate_limiter;
+mod shutdown;
 #[cfg(test)]
 mod sqlite_peer_store;
+
+use crate::NetworkConfig;
+use std::path::Path;
+
+/// A config listening on `port` of localhost, the network data is kept in `dir`
+fn network_config(dir: &Path, port: u16) -> NetworkConfig {
+    NetworkConfig {
+        listen_addresses: vec![format!("/ip4/127.0.0.1/tcp/{}", port)
+            .parse()
+            .expect("invalid address")],
+        public_addresses: vec![],
+        bootnodes: vec![],
+        dns_seeds: vec![],
+        dns_seed_pubkeys: vec![],
+        network_magic: 0,
+        ignore_spec_seeds: false,
+        reserved_peers: vec![],
+        allow_outbound_same_group: false,
+        min_protocol_versions: Default::default(),
+        block_relay_only_peers: 0,
+        discovery_local_address: true,
+        max_peer_store_addrs: 16384,
+        idle_timeout_secs: 1800,
+        rate_limits: Vec::new(),
+        reserved_only: false,
+        max_peers: 8,
+        max_outbound_peers: 4,
+        path: dir.to_path_buf(),
+        ping_interval_secs: 15,
+        ping_timeout_secs: 20,
+        connect_outbound_interval_secs: 1,
+    }
+}
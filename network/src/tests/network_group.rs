@@ -0,0 +1,66 @@
+use crate::{
+    multiaddr::ToMultiaddr,
+    network_group::{Group, NetworkGroup},
+    protocols::outbound_peer::occupy_network_group,
+};
+use fnv::FnvHashSet;
+
+fn group(addr: &str) -> Group {
+    addr.to_multiaddr().unwrap().network_group()
+}
+
+#[test]
+fn test_ipv4_group() {
+    assert_eq!(group("/ip4/1.2.3.4/tcp/8115"), Group::IP4([1, 2]));
+    assert_eq!(group("/ip4/1.2.200.200/tcp/8115"), Group::IP4([1, 2]));
+    assert_eq!(group("/ip4/127.0.0.1/tcp/8115"), Group::LocalNetwork);
+}
+
+#[test]
+fn test_ipv6_group() {
+    assert_eq!(
+        group("/ip6/2400:cb00:2048:1::6814:155a/tcp/8115"),
+        Group::IP6([0x24, 0x00, 0xcb, 0x00])
+    );
+    assert_eq!(
+        group("/ip6/2400:cb00:ffff::1/tcp/8115"),
+        Group::IP6([0x24, 0x00, 0xcb, 0x00])
+    );
+    assert_eq!(group("/ip6/::1/tcp/8115"), Group::LocalNetwork);
+}
+
+#[test]
+fn test_ipv6_embedding_ipv4_group() {
+    // IPv4 mapped
+    assert_eq!(group("/ip6/::ffff:1.2.3.4/tcp/8115"), Group::IP4([1, 2]));
+    // 6to4
+    assert_eq!(group("/ip6/2002:102:304::1/tcp/8115"), Group::IP4([1, 2]));
+    // Teredo
+    assert_eq!(
+        group("/ip6/2001:0:4136:e378:8000:63bf:fefd:fcfb/tcp/8115"),
+        Group::IP4([1, 2])
+    );
+}
+
+#[test]
+fn test_occupy_network_group() {
+    let mut groups = FnvHashSet::default();
+    groups.insert(Group::IP4([1, 1]));
+    let occupy = |groups: &mut FnvHashSet<Group>, addr: &str| {
+        occupy_network_group(groups, &addr.to_multiaddr().unwrap())
+    };
+
+    // the group of a connected peer
+    assert!(!occupy(&mut groups, "/ip4/1.1.2.2/tcp/8115"));
+    // only the first candidate of a group is dialed
+    assert!(occupy(&mut groups, "/ip4/2.2.2.2/tcp/8115"));
+    assert!(!occupy(&mut groups, "/ip4/2.2.3.3/tcp/8115"));
+    assert!(!occupy(&mut groups, "/ip6/2002:202:404::1/tcp/8115"));
+    assert!(occupy(&mut groups, "/ip6/2400:cb00::1/tcp/8115"));
+    assert!(!occupy(&mut groups, "/ip6/2400:cb00:1::1/tcp/8115"));
+    assert!(occupy(&mut groups, "/ip6/2400:cb01::1/tcp/8115"));
+    // local addresses are exempt
+    assert!(occupy(&mut groups, "/ip4/127.0.0.1/tcp/8115"));
+    assert!(occupy(&mut groups, "/ip4/127.0.0.1/tcp/8116"));
+    assert_eq!(groups.len(), 4);
+}
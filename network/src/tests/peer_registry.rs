@@ -1,9 +1,10 @@
 use crate::{
     errors::PeerError,
     multiaddr::ToMultiaddr,
+    network_group::Group,
     peer_registry::{PeerRegistry, EVICTION_PROTECT_PEERS},
     peer_store::{PeerStore, SqlitePeerStore},
-    Behaviour, PeerId, SessionType,
+    Behaviour, Peer, PeerId, SessionType,
 };
 use std::time::{Duration, Instant};
 
@@ -287,3 +288,259 @@ fn test_accept_inbound_peer_eviction() {
         .expect("accept");
     assert!(peers_registry.get_key_by_peer_id(&evict_target).is_none());
 }
+
+#[test]
+fn test_accept_inbound_peer_eviction_protect_unique_network_group() {
+    let mut peer_store = new_peer_store();
+    let addr = "/ip4/192.168.0.1".to_multiaddr().unwrap();
+    let unique_addr = "/ip4/10.0.0.1".to_multiaddr().unwrap();
+    let unique_peer = PeerId::random();
+    let mut peers_registry =
+        PeerRegistry::new((EVICTION_PROTECT_PEERS + 1) as u32, 3, false, Vec::new());
+    for session_id in 0..EVICTION_PROTECT_PEERS {
+        peers_registry
+            .accept_peer(
+                PeerId::random(),
+                addr.clone(),
+                session_id.into(),
+                SessionType::Inbound,
+                peer_store.as_mut(),
+            )
+            .expect("accept");
+    }
+    peers_registry
+        .accept_peer(
+            unique_peer.clone(),
+            unique_addr,
+            1000.into(),
+            SessionType::Inbound,
+            peer_store.as_mut(),
+        )
+        .expect("accept");
+    // the lowest scored peer is still protected by its network group
+    peer_store.report(&unique_peer, Behaviour::TestBad);
+
+    let evicted_peer = peers_registry
+        .accept_peer(
+            PeerId::random(),
+            addr.clone(),
+            2000.into(),
+            SessionType::Inbound,
+            peer_store.as_mut(),
+        )
+        .expect("accept")
+        .expect("evict a peer");
+    assert_ne!(evicted_peer.peer_id, unique_peer);
+    assert!(peers_registry.get_key_by_peer_id(&unique_peer).is_some());
+}
+
+#[test]
+fn test_outbound_groups() {
+    let mut peer_store = new_peer_store();
+    let mut peers = PeerRegistry::new(3, 3, false, vec![]);
+    for (session_id, (addr, session_type)) in [
+        ("/ip4/1.1.1.1", SessionType::Outbound),
+        ("/ip4/1.1.2.2", SessionType::Outbound),
+        ("/ip6/2400:cb00::1", SessionType::Outbound),
+        ("/ip4/2.2.2.2", SessionType::Inbound),
+    ]
+    .iter()
+    .enumerate()
+    {
+        peers
+            .accept_peer(
+                PeerId::random(),
+                addr.to_multiaddr().unwrap(),
+                session_id.into(),
+                *session_type,
+                peer_store.as_mut(),
+            )
+            .expect("accept");
+    }
+
+    let groups = peers.outbound_groups();
+    assert_eq!(groups.len(), 2);
+    assert!(groups.contains(&Group::IP4([1, 1])));
+    assert!(groups.contains(&Group::IP6([0x24, 0x00, 0xcb, 0x00])));
+}
+
+#[test]
+fn test_peer_rtt_smoothing() {
+    let mut peer = Peer::new(
+        0.into(),
+        SessionType::Outbound,
+        PeerId::random(),
+        "/ip4/127.0.0.1".to_multiaddr().unwrap(),
+        false,
+    );
+    peer.update_rtt(Duration::from_millis(80));
+    assert_eq!(peer.rtt, Some(Duration::from_millis(80)));
+    peer.update_rtt(Duration::from_millis(160));
+    assert_eq!(peer.ping, Some(Duration::from_millis(160)));
+    assert_eq!(peer.rtt, Some(Duration::from_millis(90)));
+}
+
+#[test]
+fn test_add_and_remove_reserved_peer() {
+    let mut peer_store = new_peer_store();
+    let addr = "/ip4/127.0.0.1".to_multiaddr().unwrap();
+    let peer_id = PeerId::random();
+    let mut peers = PeerRegistry::new(3, 3, true, vec![]);
+    assert!(peers
+        .accept_peer(
+            peer_id.clone(),
+            addr.clone(),
+            1.into(),
+            SessionType::Inbound,
+            peer_store.as_mut(),
+        )
+        .is_err());
+
+    peers.add_reserved_peer(peer_id.clone());
+    assert!(peers.is_reserved(&peer_id));
+    peers
+        .accept_peer(
+            peer_id.clone(),
+            addr.clone(),
+            1.into(),
+            SessionType::Inbound,
+            peer_store.as_mut(),
+        )
+        .expect("accept");
+    assert!(peers.get_peer(1.into()).unwrap().is_reserved);
+
+    // only reserved peers are allowed, the session must be closed
+    assert_eq!(peers.remove_reserved_peer(&peer_id), Some(1.into()));
+    assert!(!peers.is_reserved(&peer_id));
+    assert!(!peers.get_peer(1.into()).unwrap().is_reserved);
+    assert_eq!(peers.remove_reserved_peer(&peer_id), None);
+}
+
+#[test]
+fn test_set_max_outbound() {
+    let mut peer_store = new_peer_store();
+    let addr = "/ip4/127.0.0.1".to_multiaddr().unwrap();
+    let reserved_peer = PeerId::random();
+    let mut peers = PeerRegistry::new(3, 3, false, vec![reserved_peer.clone()]);
+    peers
+        .accept_peer(
+            reserved_peer,
+            addr.clone(),
+            1.into(),
+            SessionType::Outbound,
+            peer_store.as_mut(),
+        )
+        .expect("accept");
+    for session_id in 2..=4 {
+        peers
+            .accept_peer(
+                PeerId::random(),
+                addr.clone(),
+                session_id.into(),
+                SessionType::Outbound,
+                peer_store.as_mut(),
+            )
+            .expect("accept");
+        peers
+            .get_peer_mut(session_id.into())
+            .unwrap()
+            .connected_time = Instant::now() + Duration::from_secs(session_id as u64);
+    }
+
+    // reserved peers are not counted, the latest connected are dropped first
+    let dropped = peers.set_max_outbound(1);
+    assert_eq!(dropped.len(), 2);
+    assert!(dropped.contains(&3.into()));
+    assert!(dropped.contains(&4.into()));
+    assert_eq!(peers.connection_status().max_outbound, 1);
+    assert!(peers.set_max_outbound(5).is_empty());
+}
+
+#[test]
+fn test_block_relay_only_slots() {
+    let mut peer_store = new_peer_store();
+    let addr = "/ip4/127.0.0.1".to_multiaddr().unwrap();
+    let mut peers = PeerRegistry::new(3, 1, false, vec![]).with_max_block_relay_only(1);
+    peers
+        .accept_peer(
+            PeerId::random(),
+            addr.clone(),
+            1.into(),
+            SessionType::Outbound,
+            peer_store.as_mut(),
+        )
+        .expect("accept");
+    assert_eq!(
+        peers
+            .accept_peer(
+                PeerId::random(),
+                addr.clone(),
+                2.into(),
+                SessionType::Outbound,
+                peer_store.as_mut(),
+            )
+            .unwrap_err(),
+        PeerError::ReachMaxOutboundLimit,
+    );
+
+    // the outbound slots are full, the block relay only slot is not
+    let block_relay_peer = PeerId::random();
+    peers.add_block_relay_only_dial(block_relay_peer.clone());
+    peers
+        .accept_peer(
+            block_relay_peer,
+            addr.clone(),
+            2.into(),
+            SessionType::Outbound,
+            peer_store.as_mut(),
+        )
+        .expect("accept");
+    assert!(peers.get_peer(2.into()).unwrap().is_block_relay_only);
+    let status = peers.connection_status();
+    assert_eq!(status.unreserved_outbound, 1);
+    assert_eq!(status.block_relay_only_outbound, 1);
+
+    let another_peer = PeerId::random();
+    peers.add_block_relay_only_dial(another_peer.clone());
+    assert_eq!(
+        peers
+            .accept_peer(
+                another_peer,
+                addr.clone(),
+                3.into(),
+                SessionType::Outbound,
+                peer_store.as_mut(),
+            )
+            .unwrap_err(),
+        PeerError::ReachMaxOutboundLimit,
+    );
+}
+
+#[test]
+fn test_idle_sessions() {
+    let mut peer_store = new_peer_store();
+    let addr = "/ip4/127.0.0.1".to_multiaddr().unwrap();
+    let mut peers = PeerRegistry::new(3, 3, false, vec![]);
+    for session_id in 1..=3 {
+        peers
+            .accept_peer(
+                PeerId::random(),
+                addr.clone(),
+                session_id.into(),
+                SessionType::Inbound,
+                peer_store.as_mut(),
+            )
+            .expect("accept");
+    }
+    let idle_timeout = Duration::from_secs(60);
+    let now = Instant::now() + Duration::from_secs(120);
+    // session 1 received a message recently, session 2 long ago, session 3 never
+    peers.get_peer_mut(1.into()).unwrap().last_message_time = Some(now - Duration::from_secs(10));
+    peers.get_peer_mut(2.into()).unwrap().last_message_time = Some(now - Duration::from_secs(100));
+
+    let idle = peers.idle_sessions(now, idle_timeout);
+    assert_eq!(idle.len(), 2);
+    assert!(idle.contains(&2.into()));
+    assert!(idle.contains(&3.into()));
+    assert!(peers.idle_sessions(Instant::now(), idle_timeout).is_empty());
+}
@@ -0,0 +1,97 @@
+use super::network_config;
+use crate::protocols::{is_valid_version, meets_min_version, select_version, CKBProtocol};
+use crate::{CKBProtocolContext, CKBProtocolHandler, NetworkState};
+use std::sync::Arc;
+
+struct DummyHandler;
+
+impl CKBProtocolHandler for DummyHandler {
+    fn init(&mut self, _nc: Box<dyn CKBProtocolContext>) {}
+}
+
+fn new_protocol(versions: &[&str]) -> (CKBProtocol, tempfile::TempDir) {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let network_state = NetworkState::from_config(network_config(dir.path(), 0)).unwrap();
+    let protocol = CKBProtocol::new(
+        "test".to_string(),
+        1.into(),
+        &self::versions(versions),
+        || Box::new(DummyHandler),
+        Arc::new(network_state),
+    );
+    (protocol, dir)
+}
+
+fn versions(versions: &[&str]) -> Vec<String> {
+    versions.iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn test_select_highest_mutual_version() {
+    assert_eq!(
+        select_version(&versions(&["1", "2", "3"]), &versions(&["2", "3", "4"])),
+        Some("3".to_string())
+    );
+    assert_eq!(
+        select_version(&versions(&["1", "2"]), &versions(&["3"])),
+        None
+    );
+}
+
+#[test]
+fn test_select_version_compares_numerically() {
+    assert_eq!(
+        select_version(&versions(&["9", "10"]), &versions(&["10", "9"])),
+        Some("10".to_string())
+    );
+    assert_eq!(
+        select_version(&versions(&["1.2", "1.10"]), &versions(&["1.2", "1.10"])),
+        Some("1.10".to_string())
+    );
+}
+
+#[test]
+fn test_select_version_ignores_malformed() {
+    assert_eq!(
+        select_version(&versions(&["1", "x"]), &versions(&["1", "x"])),
+        Some("1".to_string())
+    );
+    assert_eq!(select_version(&versions(&["x"]), &versions(&["x"])), None);
+}
+
+#[test]
+fn test_is_valid_version() {
+    assert!(is_valid_version("1"));
+    assert!(is_valid_version("1.10"));
+    assert!(!is_valid_version(""));
+    assert!(!is_valid_version("1.x"));
+    assert!(!is_valid_version("v1"));
+}
+
+#[test]
+fn test_meets_min_version() {
+    assert!(meets_min_version("2", "2"));
+    assert!(meets_min_version("10", "9"));
+    assert!(meets_min_version("1.10", "1.2"));
+    assert!(!meets_min_version("1", "2"));
+    assert!(!meets_min_version("x", "1"));
+    // a malformed floor is not enforced
+    assert!(meets_min_version("1", "x"));
+}
+
+#[test]
+fn test_features_follow_the_version() {
+    let (protocol, _dir) = new_protocol(&["1", "2"]);
+    let protocol = protocol.with_features("2", 0b11);
+    assert_eq!(protocol.features("2"), 0b11);
+    // versions without features and unknown versions
+    assert_eq!(protocol.features("1"), 0);
+    assert_eq!(protocol.features("3"), 0);
+}
+
+#[test]
+#[should_panic]
+fn test_features_of_unsupported_version() {
+    let (protocol, _dir) = new_protocol(&["1"]);
+    protocol.with_features("2", 1);
+}
@@ -0,0 +1,133 @@
+use crate::rate_limiter::{RateLimitConfig, RateLimitVerdict, RateLimiter};
+use crate::ProtocolId;
+use p2p::SessionId;
+use std::time::{Duration, Instant};
+
+fn limiter() -> RateLimiter {
+    RateLimiter::new(&[RateLimitConfig {
+        protocol_id: 100,
+        messages_per_second: 10,
+        burst: 5,
+    }])
+}
+
+#[test]
+fn test_unlimited_protocol() {
+    let mut limiter = limiter();
+    let relay: ProtocolId = 101.into();
+    let now = Instant::now();
+    for _ in 0..100 {
+        assert_eq!(limiter.check(now, 1.into(), relay), RateLimitVerdict::Allow);
+    }
+}
+
+#[test]
+fn test_throttle_then_disconnect() {
+    let mut limiter = limiter();
+    let session_id: SessionId = 1.into();
+    let sync: ProtocolId = 100.into();
+    let now = Instant::now();
+    for _ in 0..5 {
+        assert_eq!(
+            limiter.check(now, session_id, sync),
+            RateLimitVerdict::Allow
+        );
+    }
+    // other peers have their own budgets
+    assert_eq!(limiter.check(now, 2.into(), sync), RateLimitVerdict::Allow);
+    for _ in 0..5 {
+        assert_eq!(
+            limiter.check(now, session_id, sync),
+            RateLimitVerdict::Throttle
+        );
+    }
+    // a token every 100ms
+    let now = now + Duration::from_millis(100);
+    assert_eq!(
+        limiter.check(now, session_id, sync),
+        RateLimitVerdict::Allow
+    );
+    assert_eq!(
+        limiter.check(now, session_id, sync),
+        RateLimitVerdict::Disconnect
+    );
+}
+
+#[test]
+fn test_backing_off_resets_throttled() {
+    let mut limiter = limiter();
+    let session_id: SessionId = 1.into();
+    let sync: ProtocolId = 100.into();
+    let now = Instant::now();
+    for _ in 0..5 {
+        limiter.check(now, session_id, sync);
+    }
+    for _ in 0..5 {
+        assert_eq!(
+            limiter.check(now, session_id, sync),
+            RateLimitVerdict::Throttle
+        );
+    }
+
+    // the bucket is full again after 500ms
+    let now = now + Duration::from_millis(500);
+    for _ in 0..5 {
+        assert_eq!(
+            limiter.check(now, session_id, sync),
+            RateLimitVerdict::Allow
+        );
+    }
+    assert_eq!(
+        limiter.check(now, session_id, sync),
+        RateLimitVerdict::Throttle
+    );
+
+    limiter.remove_session(session_id);
+    assert_eq!(
+        limiter.check(now, session_id, sync),
+        RateLimitVerdict::Allow
+    );
+}
+
+#[test]
+fn test_keep_fractional_tokens() {
+    let mut limiter = RateLimiter::new(&[RateLimitConfig {
+        protocol_id: 100,
+        messages_per_second: 3,
+        burst: 2,
+    }]);
+    let session_id: SessionId = 1.into();
+    let sync: ProtocolId = 100.into();
+    let mut now = Instant::now();
+    for _ in 0..2 {
+        assert_eq!(
+            limiter.check(now, session_id, sync),
+            RateLimitVerdict::Allow
+        );
+    }
+    // a token every 333.33ms, the parts refilled by the frequent checks add up
+    for _ in 0..2 {
+        now += Duration::from_micros(166_666);
+        assert_eq!(
+            limiter.check(now, session_id, sync),
+            RateLimitVerdict::Throttle
+        );
+    }
+    now += Duration::from_micros(2);
+    assert_eq!(
+        limiter.check(now, session_id, sync),
+        RateLimitVerdict::Allow
+    );
+}
+
+#[test]
+fn test_validate_burst() {
+    let config = |messages_per_second, burst| RateLimitConfig {
+        protocol_id: 100,
+        messages_per_second,
+        burst,
+    };
+    assert!(config(10, 1).validate().is_ok());
+    assert!(config(0, 0).validate().is_ok());
+    assert!(config(10, 0).validate().is_err());
+}
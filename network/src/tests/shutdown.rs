@@ -0,0 +1,149 @@
+use super::network_config;
+use crate::multiaddr::{Multiaddr, ToMultiaddr};
+use crate::network::SHUTDOWN_FLUSH_TIMEOUT;
+use crate::peer_store::Status;
+use crate::{NetworkController, NetworkService, NetworkState, PeerId, SessionType};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Node {
+    network_state: Arc<NetworkState>,
+    controller: Option<NetworkController>,
+    address: Multiaddr,
+    _dir: tempfile::TempDir,
+}
+
+impl Node {
+    fn start() -> Node {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let network_state =
+            Arc::new(NetworkState::from_config(network_config(dir.path(), 0)).unwrap());
+        let controller = NetworkService::new(Arc::clone(&network_state), Vec::new())
+            .start(Some("NetworkService"))
+            .expect("start network service");
+        let address = network_state.original_listened_addresses.read()[0].clone();
+        Node {
+            network_state,
+            controller: Some(controller),
+            address,
+            _dir: dir,
+        }
+    }
+
+    fn peer_id(&self) -> PeerId {
+        self.network_state.local_peer_id().clone()
+    }
+
+    fn controller(&self) -> &NetworkController {
+        self.controller.as_ref().expect("running")
+    }
+
+    fn connect(&self, other: &Node) {
+        self.controller()
+            .add_node(&other.peer_id(), other.address.clone());
+    }
+
+    fn is_connected(&self, peer_id: &PeerId) -> bool {
+        self.network_state
+            .with_peer_registry(|reg| reg.get_key_by_peer_id(peer_id).is_some())
+    }
+
+    fn peer_status(&self, peer_id: &PeerId) -> Status {
+        self.network_state
+            .with_peer_store(|peer_store| peer_store.peer_status(peer_id))
+    }
+
+    // Dropping the last controller sends the stop signal and waits the network thread
+    fn shutdown(&mut self) -> Duration {
+        let now = Instant::now();
+        self.controller.take();
+        now.elapsed()
+    }
+}
+
+fn wait_until<F: Fn() -> bool>(f: F) -> bool {
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while Instant::now() < deadline {
+        if f() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+#[test]
+fn test_shutdown_closes_sessions() {
+    let mut node1 = Node::start();
+    let node2 = Node::start();
+    node1.connect(&node2);
+    assert!(wait_until(
+        || node1.is_connected(&node2.peer_id()) && node2.is_connected(&node1.peer_id())
+    ));
+    assert_eq!(node1.peer_status(&node2.peer_id()), Status::Connected);
+
+    // the session is closed in time, no need to wait until the deadline
+    let elapsed = node1.shutdown();
+    assert!(elapsed < SHUTDOWN_FLUSH_TIMEOUT);
+    assert!(node1.network_state.is_shutting_down());
+    assert!(!node1.is_connected(&node2.peer_id()));
+    assert_eq!(node1.peer_status(&node2.peer_id()), Status::Disconnected);
+    assert!(wait_until(|| !node2.is_connected(&node1.peer_id())));
+
+    // the runtime is shut down, nothing listens any more
+    node2.connect(&node1);
+    thread::sleep(Duration::from_secs(1));
+    assert!(!node2.is_connected(&node1.peer_id()));
+}
+
+#[test]
+fn test_shutdown_marks_unclosed_sessions_at_deadline() {
+    let mut node1 = Node::start();
+    let node2 = Node::start();
+
+    // a peer without a session never closes, it blocks the shutdown until the deadline
+    let unclosed_peer = PeerId::random();
+    let addr = "/ip4/127.0.0.1/tcp/42".to_multiaddr().unwrap();
+    node1.network_state.with_peer_store_mut(|peer_store| {
+        node1.network_state.with_peer_registry_mut(|reg| {
+            reg.accept_peer(
+                unclosed_peer.clone(),
+                addr,
+                std::usize::MAX.into(),
+                SessionType::Inbound,
+                peer_store,
+            )
+            .expect("accept");
+        });
+        peer_store.update_status(&unclosed_peer, Status::Connected);
+    });
+    assert_eq!(node1.peer_status(&unclosed_peer), Status::Connected);
+
+    let network_state = Arc::clone(&node1.network_state);
+    let (node1_peer_id, node1_address) = (node1.peer_id(), node1.address.clone());
+    let shutdown = thread::spawn(move || node1.shutdown());
+    assert!(wait_until(|| network_state.is_shutting_down()));
+
+    // new sessions are opened and closed at once while draining, they are never registered
+    node2.controller().add_node(&node1_peer_id, node1_address);
+    assert!(wait_until(
+        || node2.peer_status(&node1_peer_id) == Status::Disconnected
+    ));
+
+    let elapsed = shutdown.join().expect("shutdown");
+    // the shutdown waits the unclosed session until the deadline, then leaves the p2p
+    // service at least a second before shutting down the runtime
+    assert!(elapsed >= SHUTDOWN_FLUSH_TIMEOUT);
+    assert!(elapsed < SHUTDOWN_FLUSH_TIMEOUT + Duration::from_secs(3));
+    assert_eq!(
+        network_state.with_peer_store(|peer_store| peer_store.peer_status(&node2.peer_id())),
+        Status::Unknown
+    );
+    assert_eq!(
+        network_state.with_peer_store(|peer_store| peer_store.peer_status(&unclosed_peer)),
+        Status::Disconnected
+    );
+}
@@ -1,8 +1,11 @@
 use crate::{
     multiaddr::ToMultiaddr,
     peer_store::{
+        sqlite::addrman::{self, BucketEntry, BUCKET_SIZE},
         sqlite::db,
-        sqlite::peer_store::{LAST_CONNECTED_TIMEOUT_SECS, PEER_STORE_LIMIT},
+        sqlite::peer_store::{
+            FAST_FEELER_HANDSHAKE, LAST_CONNECTED_TIMEOUT_SECS, PEER_STORE_LIMIT,
+        },
         PeerStore, SqlitePeerStore, Status,
     },
     Behaviour, PeerId, SessionType,
@@ -61,14 +64,27 @@ fn test_update_status() {
 fn test_ban_peer() {
     let mut peer_store: Box<dyn PeerStore> = Box::new(new_peer_store());
     let peer_id = PeerId::random();
-    peer_store.ban_peer(&peer_id, Duration::from_secs(10));
+    peer_store.ban_peer(&peer_id, Duration::from_secs(10), "test".to_string());
     assert!(!peer_store.is_banned(&peer_id));
     let addr = "/ip4/127.0.0.1".to_multiaddr().unwrap();
     peer_store.add_connected_peer(&peer_id, addr, SessionType::Inbound);
-    peer_store.ban_peer(&peer_id, Duration::from_secs(10));
+    peer_store.ban_peer(&peer_id, Duration::from_secs(10), "test".to_string());
     assert!(peer_store.is_banned(&peer_id));
 }
 
+#[test]
+fn test_ban_addr() {
+    let mut peer_store: Box<dyn PeerStore> = Box::new(new_peer_store());
+    let addr = "/ip4/127.0.0.1/tcp/42".to_multiaddr().unwrap();
+    peer_store.ban_addr(&addr, Duration::from_secs(10), "test".to_string());
+    assert!(peer_store.is_addr_banned(&"/ip4/127.0.0.1/tcp/43".to_multiaddr().unwrap()));
+    assert!(!peer_store.is_addr_banned(&"/ip4/127.0.0.2/tcp/42".to_multiaddr().unwrap()));
+    let banned_addrs = peer_store.banned_addrs();
+    assert_eq!(banned_addrs.len(), 1);
+    assert_eq!(banned_addrs[0].ip.to_string(), "127.0.0.1");
+    assert_eq!(banned_addrs[0].reason, "test");
+}
+
 #[test]
 fn test_attepmt_ban() {
     let mut peer_store: Box<dyn PeerStore> = Box::new(new_peer_store());
@@ -77,7 +93,7 @@ fn test_attepmt_ban() {
     peer_store.add_connected_peer(&peer_id, addr.clone(), SessionType::Inbound);
     peer_store.add_discovered_addr(&peer_id, addr.clone());
     assert_eq!(peer_store.peers_to_attempt(2).len(), 1);
-    peer_store.ban_peer(&peer_id, Duration::from_secs(10));
+    peer_store.ban_peer(&peer_id, Duration::from_secs(10), "test".to_string());
     assert_eq!(peer_store.peers_to_attempt(2).len(), 0);
 }
 
@@ -151,6 +167,27 @@ fn test_random_peers() {
     assert_eq!(peer_store.random_peers(1).len(), 1);
 }
 
+#[test]
+fn test_best_peers() {
+    let mut peer_store: Box<dyn PeerStore> = Box::new(new_peer_store());
+    let addr1 = "/ip4/127.0.0.1/tcp/42".to_multiaddr().unwrap();
+    let addr2 = "/ip4/192.163.1.1/tcp/42".to_multiaddr().unwrap();
+    let peer_id1 = PeerId::random();
+    let peer_id2 = PeerId::random();
+    peer_store.add_discovered_addr(&peer_id1, addr1.clone());
+    peer_store.add_discovered_addr(&peer_id2, addr2.clone());
+    // addrs we never connected to are not returned
+    assert!(peer_store.best_peers(2).is_empty());
+    peer_store.add_connected_peer(&peer_id1, addr1.clone(), SessionType::Outbound);
+    peer_store.add_connected_peer(&peer_id2, addr2.clone(), SessionType::Outbound);
+    peer_store.report(&peer_id1, Behaviour::TestBad);
+    assert_eq!(
+        peer_store.best_peers(2),
+        vec![(peer_id2.clone(), addr2), (peer_id1.clone(), addr1)]
+    );
+    assert_eq!(peer_store.best_peers(1).len(), 1);
+}
+
 #[test]
 fn test_delete_peer_info() {
     let mut peer_store = new_peer_store();
@@ -194,3 +231,223 @@ fn test_delete_peer_info() {
     // evict_target is evicted in previous step
     assert_eq!(peer_store.peer_score(&evict_target), None);
 }
+
+#[test]
+fn test_export_and_import_addresses() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("addresses.jsonl");
+    let mut peer_store = new_peer_store();
+    let peer_id = PeerId::random();
+    let addr1 = "/ip4/127.0.0.1/tcp/42".to_multiaddr().unwrap();
+    let addr2 = "/ip4/192.163.1.1/tcp/42".to_multiaddr().unwrap();
+    peer_store.add_discovered_addr(&peer_id, addr1.clone());
+    peer_store.add_discovered_addr(&peer_id, addr2.clone());
+    let banned_peer_id = PeerId::random();
+    let banned_addr = "/ip4/10.0.0.1/tcp/42".to_multiaddr().unwrap();
+    peer_store.add_discovered_addr(&banned_peer_id, banned_addr.clone());
+    peer_store.ban_addr(&banned_addr, Duration::from_secs(10), "test".to_string());
+    assert_eq!(peer_store.export_addresses(&path).unwrap(), 2);
+
+    let mut imported = new_peer_store();
+    assert_eq!(imported.import_addresses(&path).unwrap(), 2);
+    let mut addrs = imported.peer_addrs(&peer_id, 3).unwrap();
+    addrs.sort_by_key(|addr| addr.to_string());
+    assert_eq!(addrs, vec![addr1, addr2]);
+    assert!(imported.peer_addrs(&banned_peer_id, 1).is_none());
+    // addresses already known are not counted
+    assert_eq!(imported.import_addresses(&path).unwrap(), 0);
+}
+
+#[test]
+fn test_import_invalid_addresses() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("addresses.jsonl");
+    let peer_id = PeerId::random();
+    std::fs::write(
+        &path,
+        format!(
+            "{{\"address\":\"/ip4/127.0.0.1/tcp/42/p2p/{}\"}}\n\n{{\"address\":\"/ip4/127.0.0.1/tcp/43\"}}\n",
+            peer_id.to_base58()
+        ),
+    )
+    .unwrap();
+    let mut peer_store = new_peer_store();
+    let err = peer_store.import_addresses(&path).unwrap_err();
+    assert!(err.to_string().starts_with("line 3:"));
+    // nothing is imported if any line is invalid
+    assert!(peer_store.peer_addrs(&peer_id, 1).is_none());
+}
+
+#[test]
+fn test_addrs_from_one_source_share_a_bucket() {
+    let mut peer_store = new_peer_store();
+    let source = "/ip4/5.5.5.5/tcp/42".to_multiaddr().unwrap();
+    for i in 0..100 {
+        let addr = format!("/ip4/1.2.{}.1/tcp/42", i).to_multiaddr().unwrap();
+        peer_store.add_discovered_addr_from(&PeerId::random(), addr, &source);
+    }
+    let count: u32 = peer_store
+        .conn
+        .query_row(
+            "SELECT COUNT(*) FROM peer_addr",
+            rusqlite::NO_PARAMS,
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(count, BUCKET_SIZE);
+    assert_eq!(peer_store.peers_to_attempt(100).len(), BUCKET_SIZE as usize);
+}
+
+#[test]
+fn test_addrs_to_attempt_are_bounded_per_bucket() {
+    let mut peer_store = new_peer_store();
+    let source = "/ip4/5.5.5.5/tcp/42".to_multiaddr().unwrap();
+    let mut peers = Vec::new();
+    for i in 0..10 {
+        let peer_id = PeerId::random();
+        let addr = format!("/ip4/1.2.{}.1/tcp/42", i).to_multiaddr().unwrap();
+        peer_store.add_discovered_addr_from(&peer_id, addr, &source);
+        peers.push(peer_id);
+    }
+    let best = peers[7].clone();
+    assert!(peer_store.report(&best, Behaviour::TestGood).is_ok());
+
+    // all addrs are in one bucket, only the best ones of it are loaded
+    let entries = db::get_addrs_to_attempt(&peer_store.conn, 2).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].peer_id, best);
+    assert_eq!(
+        db::get_addrs_to_attempt(&peer_store.conn, 100)
+            .unwrap()
+            .len(),
+        10
+    );
+}
+
+#[test]
+fn test_connected_addr_moves_to_tried() {
+    let mut peer_store = new_peer_store();
+    let peer_id = PeerId::random();
+    let addr = "/ip4/1.2.3.4/tcp/42".to_multiaddr().unwrap();
+    peer_store.add_discovered_addr(&peer_id, addr.clone());
+    let peer_info_id = db::PeerInfo::get_by_peer_id(&peer_store.conn, &peer_id)
+        .unwrap()
+        .unwrap()
+        .id;
+    assert_eq!(
+        db::PeerAddr::get_tried(&peer_store.conn, peer_info_id, &addr).unwrap(),
+        Some(false)
+    );
+    peer_store.add_connected_peer(&peer_id, addr.clone(), SessionType::Outbound);
+    assert_eq!(
+        db::PeerAddr::get_tried(&peer_store.conn, peer_info_id, &addr).unwrap(),
+        Some(true)
+    );
+    // tried addrs are not feeler candidates
+    peer_store.update_status(&peer_id, Status::Disconnected);
+    assert!(peer_store.peers_to_feeler(1).is_empty());
+    assert_eq!(peer_store.peers_to_attempt(1), vec![(peer_id, addr)]);
+}
+
+#[test]
+fn test_sample_across_buckets() {
+    let addr = "/ip4/1.2.3.4/tcp/42".to_multiaddr().unwrap();
+    let entry = |tried, bucket| BucketEntry {
+        peer_id: PeerId::random(),
+        addr: addr.clone(),
+        tried,
+        bucket,
+        score: 100,
+        handshake_millis: 0,
+    };
+    let mut entries: Vec<_> = (0..100).map(|_| entry(false, 1)).collect();
+    let sparse = vec![entry(false, 2), entry(true, 1)];
+    entries.extend(sparse.clone());
+    let sampled = addrman::sample(entries, 3);
+    assert_eq!(sampled.len(), 3);
+    for entry in sparse {
+        assert!(sampled.contains(&(entry.peer_id, entry.addr)));
+    }
+}
+
+#[test]
+fn test_sample_prefers_better_peers() {
+    let addr = "/ip4/1.2.3.4/tcp/42".to_multiaddr().unwrap();
+    let entry = |score, handshake_millis| BucketEntry {
+        peer_id: PeerId::random(),
+        addr: addr.clone(),
+        tried: true,
+        bucket: 1,
+        score,
+        handshake_millis,
+    };
+    let best = entry(110, 0);
+    let fast = entry(100, 50);
+    let mut entries = vec![entry(100, 0), entry(100, 900), entry(90, 10)];
+    entries.push(best.clone());
+    entries.push(fast.clone());
+    let sampled = addrman::sample(entries, 2);
+    assert_eq!(
+        sampled,
+        vec![(best.peer_id, best.addr), (fast.peer_id, fast.addr)]
+    );
+}
+
+#[test]
+fn test_record_feeler() {
+    let mut peer_store = new_peer_store();
+    let default_score = peer_store.peer_score_config().default_score;
+    let fast = PeerId::random();
+    peer_store.add_discovered_addr(&fast, "/ip4/1.1.1.1/tcp/8115".to_multiaddr().unwrap());
+    let slow = PeerId::random();
+    peer_store.add_discovered_addr(&slow, "/ip4/2.2.2.2/tcp/8115".to_multiaddr().unwrap());
+
+    peer_store.record_feeler(&fast, Duration::from_millis(300));
+    peer_store.record_feeler(&slow, FAST_FEELER_HANDSHAKE * 2);
+    assert!(peer_store.peer_score(&fast).unwrap() > default_score);
+    assert_eq!(peer_store.peer_score(&slow), Some(default_score));
+
+    let entries = db::get_addrs_to_attempt(&peer_store.conn, 2).unwrap();
+    let handshake_of = |peer_id: &PeerId| {
+        entries
+            .iter()
+            .find(|entry| &entry.peer_id == peer_id)
+            .map(|entry| entry.handshake_millis)
+    };
+    assert_eq!(handshake_of(&fast), Some(300));
+    assert_eq!(handshake_of(&slow), Some(4000));
+}
+
+#[test]
+fn test_prune_addrs() {
+    let mut peer_store: Box<dyn PeerStore> = Box::new(new_peer_store());
+    let bootnode = PeerId::random();
+    let bootnode_addr = "/ip4/1.1.1.1/tcp/8115".to_multiaddr().unwrap();
+    peer_store.add_bootnode(bootnode.clone(), bootnode_addr.clone());
+    peer_store.add_discovered_addr(&bootnode, bootnode_addr);
+    let reserved = PeerId::random();
+    peer_store.add_discovered_addr(&reserved, "/ip4/2.2.2.2/tcp/8115".to_multiaddr().unwrap());
+    let tried = PeerId::random();
+    let tried_addr = "/ip4/3.3.3.3/tcp/8115".to_multiaddr().unwrap();
+    peer_store.add_discovered_addr(&tried, tried_addr.clone());
+    peer_store.add_connected_peer(&tried, tried_addr, SessionType::Outbound);
+    peer_store.update_status(&tried, Status::Disconnected);
+    let bad = PeerId::random();
+    peer_store.add_discovered_addr(&bad, "/ip4/4.4.4.4/tcp/8115".to_multiaddr().unwrap());
+    assert!(peer_store.report(&bad, Behaviour::TestBad).is_ok());
+    let normal = PeerId::random();
+    peer_store.add_discovered_addr(&normal, "/ip4/5.5.5.5/tcp/8115".to_multiaddr().unwrap());
+
+    let protected = vec![reserved.clone()];
+    assert_eq!(peer_store.prune_addrs(5, &protected), 0);
+    // the untried addr of the lowest scored peer goes first
+    assert_eq!(peer_store.prune_addrs(4, &protected), 1);
+    assert!(peer_store.peer_addrs(&bad, 1).unwrap().is_empty());
+    assert_eq!(peer_store.peer_addrs(&normal, 1).unwrap().len(), 1);
+    // then untried addrs before tried ones, bootnodes and protected peers are kept
+    assert_eq!(peer_store.prune_addrs(0, &protected), 2);
+    assert!(peer_store.peer_addrs(&normal, 1).unwrap().is_empty());
+    assert!(peer_store.peer_addrs(&tried, 1).unwrap().is_empty());
+    assert_eq!(peer_store.peer_addrs(&bootnode, 1).unwrap().len(), 1);
+    assert_eq!(peer_store.peer_addrs(&reserved, 1).unwrap().len(), 1);
+}
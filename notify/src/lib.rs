@@ -2,9 +2,11 @@
 
 use ckb_core::block::Block;
 use ckb_core::service::Request;
-use crossbeam_channel::{select, Receiver, Sender};
+use crossbeam_channel::{select, Receiver, Sender, TrySendError};
 use fnv::FnvHashMap;
 use log::{debug, trace, warn};
+use numext_fixed_hash::H256;
+use std::fmt;
 use std::sync::Arc;
 use std::thread;
 use stop_handler::{SignalSender, StopHandler};
@@ -13,34 +15,22 @@ pub const SIGNAL_CHANNEL_SIZE: usize = 1;
 pub const REGISTER_CHANNEL_SIZE: usize = 2;
 pub const NOTIFY_CHANNEL_SIZE: usize = 128;
 
-// #[derive(Clone, PartialEq, Debug, Default)]
-// pub struct ForkBlocks {
-//     olds: Vec<Block>,
-//     news: Vec<Block>,
-// }
-
-// impl ForkBlocks {
-//     pub fn new(olds: Vec<Block>, news: Vec<Block>) -> Self {
-//         ForkBlocks { olds, news }
-//     }
-
-//     pub fn old_blks(&self) -> &Vec<Block> {
-//         &self.olds
-//     }
-
-//     pub fn new_blks(&self) -> &Vec<Block> {
-//         &self.news
-//     }
-
-//     pub fn push_new(&mut self, b: Block) {
-//         self.news.push(b);
-//     }
-// }
+#[derive(Clone, PartialEq, Debug)]
+pub enum ChainEvent {
+    /// The tip moved, the main chain blocks above `common_ancestor` are replaced. `detached`
+    /// is ordered from the old tip down and `attached` from the common ancestor up to the new
+    /// tip, `detached` is empty when the tip is simply extended.
+    Reorg {
+        attached: Vec<Block>,
+        detached: Vec<Block>,
+        common_ancestor: H256,
+    },
+}
 
 pub type MsgNewTransaction = ();
 // pub type MsgNewTip = Arc<Block>;
 pub type MsgNewUncle = Arc<Block>;
-// pub type MsgSwitchFork = Arc<ForkBlocks>;
+pub type MsgChainEvent = Arc<ChainEvent>;
 pub type NotifyRegister<M> = Sender<Request<(String, usize), Receiver<M>>>;
 
 #[derive(Default)]
@@ -52,11 +42,11 @@ pub struct NotifyController {
     // new_transaction_register: NotifyRegister<MsgNewTransaction>,
     // new_tip_register: NotifyRegister<MsgNewTip>,
     new_uncle_register: NotifyRegister<MsgNewUncle>,
-    // switch_fork_register: NotifyRegister<MsgSwitchFork>,
+    chain_event_register: NotifyRegister<MsgChainEvent>,
     // new_transaction_notifier: Sender<MsgNewTransaction>,
     // new_tip_notifier: Sender<MsgNewTip>,
     new_uncle_notifier: Sender<MsgNewUncle>,
-    // switch_fork_notifier: Sender<MsgSwitchFork>,
+    chain_event_notifier: Sender<MsgChainEvent>,
 }
 
 impl Drop for NotifyController {
@@ -75,8 +65,8 @@ impl NotifyService {
         //     crossbeam_channel::bounded(REGISTER_CHANNEL_SIZE);
         let (new_uncle_register, new_uncle_register_receiver) =
             crossbeam_channel::bounded(REGISTER_CHANNEL_SIZE);
-        // let (switch_fork_register, switch_fork_register_receiver) =
-        //     crossbeam_channel::bounded(REGISTER_CHANNEL_SIZE);
+        let (chain_event_register, chain_event_register_receiver) =
+            crossbeam_channel::bounded(REGISTER_CHANNEL_SIZE);
 
         // let (new_transaction_sender, new_transaction_receiver) =
         //     crossbeam_channel::bounded::<MsgNewTransaction>(NOTIFY_CHANNEL_SIZE);
@@ -84,13 +74,13 @@ impl NotifyService {
         //     crossbeam_channel::bounded::<MsgNewTip>(NOTIFY_CHANNEL_SIZE);
         let (new_uncle_sender, new_uncle_receiver) =
             crossbeam_channel::bounded::<MsgNewUncle>(NOTIFY_CHANNEL_SIZE);
-        // let (switch_fork_sender, switch_fork_receiver) =
-        //     crossbeam_channel::bounded::<MsgSwitchFork>(NOTIFY_CHANNEL_SIZE);
+        let (chain_event_sender, chain_event_receiver) =
+            crossbeam_channel::bounded::<MsgChainEvent>(NOTIFY_CHANNEL_SIZE);
 
         // let mut new_transaction_subscribers = FnvHashMap::default();
         // let mut new_tip_subscribers = FnvHashMap::default();
         let mut new_uncle_subscribers = FnvHashMap::default();
-        // let mut switch_fork_subscribers = FnvHashMap::default();
+        let mut chain_event_subscribers = FnvHashMap::default();
 
         let mut thread_builder = thread::Builder::new();
         // Mainly for test: give a empty thread_name
@@ -113,9 +103,9 @@ impl NotifyService {
                     recv(new_uncle_register_receiver) -> msg => Self::handle_register_new_uncle(
                         &mut new_uncle_subscribers, msg
                     ),
-                    // recv(switch_fork_register_receiver) -> msg => Self::handle_register_switch_fork(
-                    //     &mut switch_fork_subscribers, msg
-                    // ),
+                    recv(chain_event_register_receiver) -> msg => Self::handle_register_chain_event(
+                        &mut chain_event_subscribers, msg
+                    ),
 
                     // recv(new_transaction_receiver) -> msg => Self::handle_notify_new_transaction(
                     //     &new_transaction_subscribers, msg
@@ -126,9 +116,9 @@ impl NotifyService {
                     recv(new_uncle_receiver) -> msg => Self::handle_notify_new_uncle(
                         &new_uncle_subscribers, msg
                     ),
-                    // recv(switch_fork_receiver) -> msg => Self::handle_notify_switch_fork(
-                    //     &switch_fork_subscribers, msg
-                    // )
+                    recv(chain_event_receiver) -> msg => Self::handle_notify_chain_event(
+                        &chain_event_subscribers, msg
+                    ),
                 }
             })
             .expect("Start notify service failed");
@@ -137,11 +127,11 @@ impl NotifyService {
             // new_transaction_register,
             // new_tip_register,
             new_uncle_register,
-            // switch_fork_register,
+            chain_event_register,
             // new_transaction_notifier: new_transaction_sender,
             // new_tip_notifier: new_tip_sender,
             new_uncle_notifier: new_uncle_sender,
-            // switch_fork_notifier: switch_fork_sender,
+            chain_event_notifier: chain_event_sender,
             stop: StopHandler::new(SignalSender::Crossbeam(signal_sender), join_handle),
         }
     }
@@ -203,26 +193,26 @@ impl NotifyService {
         }
     }
 
-    // fn handle_register_switch_fork(
-    //     subscribers: &mut FnvHashMap<String, Sender<MsgSwitchFork>>,
-    //     msg: Result<
-    //         Request<(String, usize), Receiver<MsgSwitchFork>>,
-    //         crossbeam_channel::RecvError,
-    //     >,
-    // ) {
-    //     match msg {
-    //         Ok(Request {
-    //             responder,
-    //             arguments: (name, capacity),
-    //         }) => {
-    //             debug!(target: "notify", "Register switch_fork {:?}", name);
-    //             let (sender, receiver) = crossbeam_channel::bounded::<MsgSwitchFork>(capacity);
-    //             subscribers.insert(name, sender);
-    //             let _ = responder.send(receiver);
-    //         }
-    //         _ => warn!(target: "notify", "Register switch_fork channel is closed"),
-    //     }
-    // }
+    fn handle_register_chain_event(
+        subscribers: &mut FnvHashMap<String, Sender<MsgChainEvent>>,
+        msg: Result<
+            Request<(String, usize), Receiver<MsgChainEvent>>,
+            crossbeam_channel::RecvError,
+        >,
+    ) {
+        match msg {
+            Ok(Request {
+                responder,
+                arguments: (name, capacity),
+            }) => {
+                debug!(target: "notify", "Register chain_event {:?}", name);
+                let (sender, receiver) = crossbeam_channel::bounded::<MsgChainEvent>(capacity);
+                subscribers.insert(name, sender);
+                let _ = responder.send(receiver);
+            }
+            _ => warn!(target: "notify", "Register chain_event channel is closed"),
+        }
+    }
 
     // fn handle_notify_new_transaction(
     //     subscribers: &FnvHashMap<String, Sender<MsgNewTransaction>>,
@@ -261,28 +251,34 @@ impl NotifyService {
         match msg {
             Ok(msg) => {
                 trace!(target: "notify", "event new uncle {:?}", msg);
-                for subscriber in subscribers.values() {
-                    let _ = subscriber.send(Arc::clone(&msg));
-                }
+                Self::broadcast(subscribers, &msg);
             }
             _ => warn!(target: "notify", "new uncle channel is closed"),
         }
     }
 
-    // fn handle_notify_switch_fork(
-    //     subscribers: &FnvHashMap<String, Sender<MsgSwitchFork>>,
-    //     msg: Result<MsgSwitchFork, crossbeam_channel::RecvError>,
-    // ) {
-    //     match msg {
-    //         Ok(msg) => {
-    //             trace!(target: "notify", "event switch fork {:?}", msg);
-    //             for subscriber in subscribers.values() {
-    //                 let _ = subscriber.send(Arc::clone(&msg));
-    //             }
-    //         }
-    //         _ => warn!(target: "notify", "event 3 channel is closed"),
-    //     }
-    // }
+    fn handle_notify_chain_event(
+        subscribers: &FnvHashMap<String, Sender<MsgChainEvent>>,
+        msg: Result<MsgChainEvent, crossbeam_channel::RecvError>,
+    ) {
+        match msg {
+            Ok(msg) => {
+                trace!(target: "notify", "event chain {:?}", msg);
+                Self::broadcast(subscribers, &msg);
+            }
+            _ => warn!(target: "notify", "chain event channel is closed"),
+        }
+    }
+
+    // A subscriber lagging behind misses the message instead of blocking the others, and the
+    // notifier along with them
+    fn broadcast<M: fmt::Debug>(subscribers: &FnvHashMap<String, Sender<Arc<M>>>, msg: &Arc<M>) {
+        for (name, subscriber) in subscribers {
+            if let Err(TrySendError::Full(_)) = subscriber.try_send(Arc::clone(msg)) {
+                warn!(target: "notify", "subscriber {} lags behind, drop {:?}", name, msg);
+            }
+        }
+    }
 }
 
 impl NotifyController {
@@ -298,10 +294,10 @@ impl NotifyController {
         Request::call(&self.new_uncle_register, (name.to_string(), 128))
             .expect("Subscribe new uncle failed")
     }
-    // pub fn subscribe_switch_fork<S: ToString>(&self, name: S) -> Receiver<MsgSwitchFork> {
-    //     Request::call(&self.switch_fork_register, (name.to_string(), 128))
-    //         .expect("Subscribe switch fork failed")
-    // }
+    pub fn subscribe_chain_event<S: ToString>(&self, name: S) -> Receiver<MsgChainEvent> {
+        Request::call(&self.chain_event_register, (name.to_string(), 128))
+            .expect("Subscribe chain event failed")
+    }
 
     // pub fn notify_new_transaction(&self) {
     //     let _ = self.new_transaction_notifier.send(());
@@ -312,14 +308,15 @@ impl NotifyController {
     pub fn notify_new_uncle(&self, block: MsgNewUncle) {
         let _ = self.new_uncle_notifier.send(block);
     }
-    // pub fn notify_switch_fork(&self, txs: MsgSwitchFork) {
-    //     let _ = self.switch_fork_notifier.send(txs);
-    // }
+    pub fn notify_chain_event(&self, event: MsgChainEvent) {
+        let _ = self.chain_event_notifier.send(event);
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
+    use std::time::Duration;
 
     // #[test]
     // fn test_new_transaction() {
@@ -342,14 +339,37 @@ mod tests {
     //     assert_eq!(receiver2.recv(), Ok(tip));
     // }
 
-    // #[test]
-    // fn test_switch_fork() {
-    //     let blks = Arc::new(ForkBlocks::default());
-    //     let notify = NotifyService::default().start::<&str>(None);
-    //     let receiver1 = notify.subscribe_switch_fork("miner1");
-    //     let receiver2 = notify.subscribe_switch_fork("miner2");
-    //     notify.notify_switch_fork(Arc::clone(&blks));
-    //     assert_eq!(receiver1.recv(), Ok(Arc::clone(&blks)));
-    //     assert_eq!(receiver2.recv(), Ok(blks));
-    // }
+    #[test]
+    fn test_chain_event() {
+        let event = Arc::new(ChainEvent::Reorg {
+            attached: vec![Block::default()],
+            detached: Vec::new(),
+            common_ancestor: H256::zero(),
+        });
+        let notify = NotifyService::default().start::<&str>(None);
+        let receiver1 = notify.subscribe_chain_event("miner1");
+        let receiver2 = notify.subscribe_chain_event("tx_pool");
+        notify.notify_chain_event(Arc::clone(&event));
+        assert_eq!(receiver1.recv(), Ok(Arc::clone(&event)));
+        assert_eq!(receiver2.recv(), Ok(event));
+    }
+
+    #[test]
+    fn test_lagging_subscriber() {
+        let event = Arc::new(ChainEvent::Reorg {
+            attached: vec![Block::default()],
+            detached: Vec::new(),
+            common_ancestor: H256::zero(),
+        });
+        let notify = NotifyService::default().start::<&str>(None);
+        let _lagging = notify.subscribe_chain_event("lagging");
+        let receiver = notify.subscribe_chain_event("miner");
+        for _ in 0..NOTIFY_CHANNEL_SIZE * 2 {
+            notify.notify_chain_event(Arc::clone(&event));
+            assert_eq!(
+                receiver.recv_timeout(Duration::from_secs(5)),
+                Ok(Arc::clone(&event))
+            );
+        }
+    }
 }
@@ -1,15 +1,16 @@
 use crate::protocol_generated::ckb::protocol::{
-    Block as FbsBlock, BlockBuilder, BlockProposalBuilder, BlockTransactionsBuilder,
-    Bytes as FbsBytes, BytesBuilder, CellInput as FbsCellInput, CellInputBuilder,
-    CellOutput as FbsCellOutput, CellOutputBuilder, CompactBlock, CompactBlockBuilder,
-    FilteredBlock, FilteredBlockBuilder, GetBlockProposalBuilder, GetBlockTransactionsBuilder,
-    GetBlocks as FbsGetBlocks, GetBlocksBuilder, GetHeaders as FbsGetHeaders, GetHeadersBuilder,
-    Header as FbsHeader, HeaderBuilder, Headers as FbsHeaders, HeadersBuilder,
-    IndexTransactionBuilder, MerkleProofBuilder, OutPoint as FbsOutPoint, OutPointBuilder,
-    ProposalShortId as FbsProposalShortId, RelayMessage, RelayMessageBuilder, RelayPayload,
-    RelayTransaction as FbsRelayTransaction, RelayTransactionBuilder, Script as FbsScript,
-    ScriptBuilder, SyncMessage, SyncMessageBuilder, SyncPayload, Time as FbsTime, TimeBuilder,
-    TimeMessage, TimeMessageBuilder, Transaction as FbsTransaction, TransactionBuilder,
+    Block as FbsBlock, BlockAnnouncementBuilder, BlockBuilder, BlockProposalBuilder,
+    BlockTransactionsBuilder, Bytes as FbsBytes, BytesBuilder, CellInput as FbsCellInput,
+    CellInputBuilder, CellOutput as FbsCellOutput, CellOutputBuilder, CompactBlock,
+    CompactBlockBuilder, FilteredBlock, FilteredBlockBuilder, GetBlockProposalBuilder,
+    GetBlockTransactionsBuilder, GetBlocks as FbsGetBlocks, GetBlocksBuilder,
+    GetHeaders as FbsGetHeaders, GetHeadersBuilder, Header as FbsHeader, HeaderBuilder,
+    Headers as FbsHeaders, HeadersBuilder, IndexTransactionBuilder, MerkleProofBuilder,
+    OutPoint as FbsOutPoint, OutPointBuilder, ProposalShortId as FbsProposalShortId, RelayMessage,
+    RelayMessageBuilder, RelayPayload, RelayTransaction as FbsRelayTransaction,
+    RelayTransactionBuilder, Script as FbsScript, ScriptBuilder, SendCompactBlockBuilder,
+    SyncMessage, SyncMessageBuilder, SyncPayload, Time as FbsTime, TimeBuilder, TimeMessage,
+    TimeMessageBuilder, Transaction as FbsTransaction, TransactionBuilder,
     UncleBlock as FbsUncleBlock, UncleBlockBuilder, Witness as FbsWitness, WitnessBuilder,
     H256 as FbsH256,
 };
@@ -52,6 +53,7 @@ impl<'a> FbsHeader<'a> {
         let difficulty = FbsBytes::build(fbb, &uint_to_bytes(header.difficulty()));
         let proof = FbsBytes::build(fbb, &header.proof());
         let uncles_hash = header.uncles_hash().into();
+        let dao = FbsBytes::build(fbb, header.dao());
         let mut builder = HeaderBuilder::new(fbb);
         builder.add_version(header.version());
         builder.add_parent_hash(&parent_hash);
@@ -65,6 +67,7 @@ impl<'a> FbsHeader<'a> {
         builder.add_proof(proof);
         builder.add_uncles_hash(&uncles_hash);
         builder.add_uncles_count(header.uncles_count());
+        builder.add_dao(dao);
         builder.finish()
     }
 }
@@ -130,11 +133,13 @@ impl<'a> FbsRelayTransaction<'a> {
         fbb: &mut FlatBufferBuilder<'b>,
         transaction: &Transaction,
         cycles: Cycle,
+        stem_hops: u8,
     ) -> WIPOffset<FbsRelayTransaction<'b>> {
         let tx = FbsTransaction::build(fbb, transaction);
         let mut builder = RelayTransactionBuilder::new(fbb);
         builder.add_transaction(tx);
         builder.add_cycles(cycles);
+        builder.add_stem_hops(stem_hops);
         builder.finish()
     }
 }
@@ -521,7 +526,18 @@ impl<'a> RelayMessage<'a> {
         transaction: &Transaction,
         cycles: Cycle,
     ) -> WIPOffset<RelayMessage<'b>> {
-        let fbs_transaction = FbsRelayTransaction::build(fbb, transaction, cycles);
+        Self::build_stem_transaction(fbb, transaction, cycles, 0)
+    }
+
+    /// Relay a transaction in the stem phase, the receiver forwards it to a single peer with
+    /// `stem_hops - 1`, a transaction with `stem_hops` 0 is broadcast.
+    pub fn build_stem_transaction<'b>(
+        fbb: &mut FlatBufferBuilder<'b>,
+        transaction: &Transaction,
+        cycles: Cycle,
+        stem_hops: u8,
+    ) -> WIPOffset<RelayMessage<'b>> {
+        let fbs_transaction = FbsRelayTransaction::build(fbb, transaction, cycles, stem_hops);
         let mut builder = RelayMessageBuilder::new(fbb);
         builder.add_payload_type(RelayPayload::RelayTransaction);
         builder.add_payload(fbs_transaction.as_union_value());
@@ -552,6 +568,17 @@ impl<'a> RelayMessage<'a> {
         fbb: &mut FlatBufferBuilder<'b>,
         block_hash: &H256,
         transactions: &[Transaction],
+    ) -> WIPOffset<RelayMessage<'b>> {
+        Self::build_block_transactions_chunk(fbb, block_hash, transactions, 0, 1)
+    }
+
+    /// Build the `sequence`th of `total` chunks of the transactions requested for a block
+    pub fn build_block_transactions_chunk<'b>(
+        fbb: &mut FlatBufferBuilder<'b>,
+        block_hash: &H256,
+        transactions: &[Transaction],
+        sequence: u32,
+        total: u32,
     ) -> WIPOffset<RelayMessage<'b>> {
         let block_transactions = {
             let fbs_block_hash = block_hash.into();
@@ -564,6 +591,8 @@ impl<'a> RelayMessage<'a> {
             let mut builder = BlockTransactionsBuilder::new(fbb);
             builder.add_block_hash(&fbs_block_hash);
             builder.add_transactions(transactions);
+            builder.add_sequence(sequence);
+            builder.add_total(total);
             builder.finish()
         };
 
@@ -617,6 +646,39 @@ impl<'a> RelayMessage<'a> {
         builder.add_payload(block_proposal.as_union_value());
         builder.finish()
     }
+
+    pub fn build_send_compact_block<'b>(
+        fbb: &mut FlatBufferBuilder<'b>,
+        high_bandwidth: bool,
+    ) -> WIPOffset<RelayMessage<'b>> {
+        let send_compact_block = {
+            let mut builder = SendCompactBlockBuilder::new(fbb);
+            builder.add_high_bandwidth(high_bandwidth);
+            builder.finish()
+        };
+
+        let mut builder = RelayMessageBuilder::new(fbb);
+        builder.add_payload_type(RelayPayload::SendCompactBlock);
+        builder.add_payload(send_compact_block.as_union_value());
+        builder.finish()
+    }
+
+    pub fn build_block_announcement<'b>(
+        fbb: &mut FlatBufferBuilder<'b>,
+        block_hash: &H256,
+    ) -> WIPOffset<RelayMessage<'b>> {
+        let block_announcement = {
+            let fbs_block_hash = block_hash.into();
+            let mut builder = BlockAnnouncementBuilder::new(fbb);
+            builder.add_block_hash(&fbs_block_hash);
+            builder.finish()
+        };
+
+        let mut builder = RelayMessageBuilder::new(fbb);
+        builder.add_payload_type(RelayPayload::BlockAnnouncement);
+        builder.add_payload(block_announcement.as_union_value());
+        builder.finish()
+    }
 }
 
 impl<'a> TimeMessage<'a> {
@@ -642,7 +704,7 @@ mod tests {
 
     #[test]
     fn build_and_convert_header() {
-        let header = HeaderBuilder::default().build();
+        let header = HeaderBuilder::default().dao(vec![1, 2, 3]).build();
         let builder = &mut FlatBufferBuilder::new();
         let b = FbsHeader::build(builder, &header);
         builder.finish(b, None);
@@ -651,6 +713,40 @@ mod tests {
         assert_eq!(header, fbs_header.try_into().unwrap());
     }
 
+    #[test]
+    fn convert_header_without_dao() {
+        let header = HeaderBuilder::default().number(1).build();
+        let builder = &mut FlatBufferBuilder::new();
+        let parent_hash = header.parent_hash().into();
+        let transactions_root = header.transactions_root().into();
+        let proposals_root = header.proposals_root().into();
+        let witnesses_root = header.witnesses_root().into();
+        let difficulty = FbsBytes::build(builder, &uint_to_bytes(header.difficulty()));
+        let proof = FbsBytes::build(builder, &header.proof());
+        let uncles_hash = header.uncles_hash().into();
+        let mut fbs_builder = super::HeaderBuilder::new(builder);
+        fbs_builder.add_version(header.version());
+        fbs_builder.add_parent_hash(&parent_hash);
+        fbs_builder.add_timestamp(header.timestamp());
+        fbs_builder.add_number(header.number());
+        fbs_builder.add_transactions_root(&transactions_root);
+        fbs_builder.add_proposals_root(&proposals_root);
+        fbs_builder.add_witnesses_root(&witnesses_root);
+        fbs_builder.add_difficulty(difficulty);
+        fbs_builder.add_nonce(header.nonce());
+        fbs_builder.add_proof(proof);
+        fbs_builder.add_uncles_hash(&uncles_hash);
+        fbs_builder.add_uncles_count(header.uncles_count());
+        let b = fbs_builder.finish();
+        builder.finish(b, None);
+
+        let fbs_header = get_root::<FbsHeader>(builder.finished_data());
+        assert!(fbs_header.dao().is_none());
+        let converted: Header = fbs_header.try_into().unwrap();
+        assert!(converted.dao().is_empty());
+        assert_eq!(header, converted);
+    }
+
     #[test]
     fn build_and_convert_block() {
         let block = BlockBuilder::default()
@@ -680,4 +776,25 @@ mod tests {
         let fbs_compact_block = get_root::<CompactBlock>(builder.finished_data());
         assert_eq!(1, fbs_compact_block.prefilled_transactions().unwrap().len());
     }
+
+    #[test]
+    fn build_stem_transaction() {
+        let tx = TransactionBuilder::default().build();
+        let builder = &mut FlatBufferBuilder::new();
+        let b = RelayMessage::build_stem_transaction(builder, &tx, 100, 3);
+        builder.finish(b, None);
+
+        let message = get_root::<RelayMessage>(builder.finished_data());
+        let relay_transaction = message.payload_as_relay_transaction().unwrap();
+        assert_eq!(3, relay_transaction.stem_hops());
+        assert_eq!((tx.clone(), 100), relay_transaction.try_into().unwrap());
+
+        let builder = &mut FlatBufferBuilder::new();
+        let b = RelayMessage::build_transaction(builder, &tx, 100);
+        builder.finish(b, None);
+
+        let message = get_root::<RelayMessage>(builder.finished_data());
+        let relay_transaction = message.payload_as_relay_transaction().unwrap();
+        assert_eq!(0, relay_transaction.stem_hops());
+    }
 }
@@ -149,6 +149,11 @@ impl<'a> TryFrom<ckb_protocol::Header<'a>> for ckb_core::header::Header {
         let proposals_root = cast!(header.proposals_root())?;
         let witnesses_root = cast!(header.witnesses_root())?;
         let uncles_hash = cast!(header.uncles_hash())?;
+        // headers from peers predating the dao field don't carry it
+        let dao = match header.dao() {
+            Some(dao) => cast!(dao.seq())?.to_vec(),
+            None => Vec::new(),
+        };
 
         Ok(ckb_core::header::HeaderBuilder::default()
             .version(header.version())
@@ -168,6 +173,7 @@ impl<'a> TryFrom<ckb_protocol::Header<'a>> for ckb_core::header::Header {
                 .and_then(|p| p.seq())
                 .map(|p| p.to_vec()))?)
             .uncles_count(header.uncles_count())
+            .dao(dao)
             .build())
     }
 }
@@ -117,11 +117,13 @@ pub enum RelayPayload {
   BlockTransactions = 4,
   GetBlockProposal = 5,
   BlockProposal = 6,
+  SendCompactBlock = 7,
+  BlockAnnouncement = 8,
 
 }
 
 const ENUM_MIN_RELAY_PAYLOAD: u8 = 0;
-const ENUM_MAX_RELAY_PAYLOAD: u8 = 6;
+const ENUM_MAX_RELAY_PAYLOAD: u8 = 8;
 
 impl<'a> flatbuffers::Follow<'a> for RelayPayload {
   type Inner = Self;
@@ -155,25 +157,29 @@ impl flatbuffers::Push for RelayPayload {
 }
 
 #[allow(non_camel_case_types)]
-const ENUM_VALUES_RELAY_PAYLOAD:[RelayPayload; 7] = [
+const ENUM_VALUES_RELAY_PAYLOAD:[RelayPayload; 9] = [
   RelayPayload::NONE,
   RelayPayload::CompactBlock,
   RelayPayload::RelayTransaction,
   RelayPayload::GetBlockTransactions,
   RelayPayload::BlockTransactions,
   RelayPayload::GetBlockProposal,
-  RelayPayload::BlockProposal
+  RelayPayload::BlockProposal,
+  RelayPayload::SendCompactBlock,
+  RelayPayload::BlockAnnouncement
 ];
 
 #[allow(non_camel_case_types)]
-const ENUM_NAMES_RELAY_PAYLOAD:[&'static str; 7] = [
+const ENUM_NAMES_RELAY_PAYLOAD:[&'static str; 9] = [
     "NONE",
     "CompactBlock",
     "RelayTransaction",
     "GetBlockTransactions",
     "BlockTransactions",
     "GetBlockProposal",
-    "BlockProposal"
+    "BlockProposal",
+    "SendCompactBlock",
+    "BlockAnnouncement"
 ];
 
 pub fn enum_name_relay_payload(e: RelayPayload) -> &'static str {
@@ -1022,6 +1028,7 @@ impl<'a> Header<'a> {
       builder.add_nonce(args.nonce);
       builder.add_number(args.number);
       builder.add_timestamp(args.timestamp);
+      if let Some(x) = args.dao { builder.add_dao(x); }
       builder.add_uncles_count(args.uncles_count);
       if let Some(x) = args.uncles_hash { builder.add_uncles_hash(x); }
       if let Some(x) = args.proof { builder.add_proof(x); }
@@ -1046,6 +1053,7 @@ impl<'a> Header<'a> {
     pub const VT_PROOF: flatbuffers::VOffsetT = 22;
     pub const VT_UNCLES_HASH: flatbuffers::VOffsetT = 24;
     pub const VT_UNCLES_COUNT: flatbuffers::VOffsetT = 26;
+    pub const VT_DAO: flatbuffers::VOffsetT = 28;
 
   #[inline]
   pub fn version(&self) -> u32 {
@@ -1095,6 +1103,10 @@ impl<'a> Header<'a> {
   pub fn uncles_count(&self) -> u32 {
     self._tab.get::<u32>(Header::VT_UNCLES_COUNT, Some(0)).unwrap()
   }
+  #[inline]
+  pub fn dao(&self) -> Option<Bytes<'a>> {
+    self._tab.get::<flatbuffers::ForwardsUOffset<Bytes<'a>>>(Header::VT_DAO, None)
+  }
 }
 
 pub struct HeaderArgs<'a> {
@@ -1110,6 +1122,7 @@ pub struct HeaderArgs<'a> {
     pub proof: Option<flatbuffers::WIPOffset<Bytes<'a >>>,
     pub uncles_hash: Option<&'a  H256>,
     pub uncles_count: u32,
+    pub dao: Option<flatbuffers::WIPOffset<Bytes<'a >>>,
 }
 impl<'a> Default for HeaderArgs<'a> {
     #[inline]
@@ -1127,6 +1140,7 @@ impl<'a> Default for HeaderArgs<'a> {
             proof: None,
             uncles_hash: None,
             uncles_count: 0,
+            dao: None,
         }
     }
 }
@@ -1184,6 +1198,10 @@ impl<'a: 'b, 'b> HeaderBuilder<'a, 'b> {
     self.fbb_.push_slot::<u32>(Header::VT_UNCLES_COUNT, uncles_count, 0);
   }
   #[inline]
+  pub fn add_dao(&mut self, dao: flatbuffers::WIPOffset<Bytes<'b >>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<Bytes>>(Header::VT_DAO, dao);
+  }
+  #[inline]
   pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>) -> HeaderBuilder<'a, 'b> {
     let start = _fbb.start_table();
     HeaderBuilder {
@@ -2103,6 +2121,26 @@ impl<'a> RelayMessage<'a> {
     }
   }
 
+  #[inline]
+  #[allow(non_snake_case)]
+  pub fn payload_as_send_compact_block(&'a self) -> Option<SendCompactBlock> {
+    if self.payload_type() == RelayPayload::SendCompactBlock {
+      self.payload().map(|u| SendCompactBlock::init_from_table(u))
+    } else {
+      None
+    }
+  }
+
+  #[inline]
+  #[allow(non_snake_case)]
+  pub fn payload_as_block_announcement(&'a self) -> Option<BlockAnnouncement> {
+    if self.payload_type() == RelayPayload::BlockAnnouncement {
+      self.payload().map(|u| BlockAnnouncement::init_from_table(u))
+    } else {
+      None
+    }
+  }
+
 }
 
 pub struct RelayMessageArgs {
@@ -2401,11 +2439,13 @@ impl<'a> RelayTransaction<'a> {
       let mut builder = RelayTransactionBuilder::new(_fbb);
       builder.add_cycles(args.cycles);
       if let Some(x) = args.transaction { builder.add_transaction(x); }
+      builder.add_stem_hops(args.stem_hops);
       builder.finish()
     }
 
     pub const VT_CYCLES: flatbuffers::VOffsetT = 4;
     pub const VT_TRANSACTION: flatbuffers::VOffsetT = 6;
+    pub const VT_STEM_HOPS: flatbuffers::VOffsetT = 8;
 
   #[inline]
   pub fn cycles(&self) -> u64 {
@@ -2415,11 +2455,16 @@ impl<'a> RelayTransaction<'a> {
   pub fn transaction(&self) -> Option<Transaction<'a>> {
     self._tab.get::<flatbuffers::ForwardsUOffset<Transaction<'a>>>(RelayTransaction::VT_TRANSACTION, None)
   }
+  #[inline]
+  pub fn stem_hops(&self) -> u8 {
+    self._tab.get::<u8>(RelayTransaction::VT_STEM_HOPS, Some(0)).unwrap()
+  }
 }
 
 pub struct RelayTransactionArgs<'a> {
     pub cycles: u64,
     pub transaction: Option<flatbuffers::WIPOffset<Transaction<'a >>>,
+    pub stem_hops: u8,
 }
 impl<'a> Default for RelayTransactionArgs<'a> {
     #[inline]
@@ -2427,6 +2472,7 @@ impl<'a> Default for RelayTransactionArgs<'a> {
         RelayTransactionArgs {
             cycles: 0,
             transaction: None,
+            stem_hops: 0,
         }
     }
 }
@@ -2444,6 +2490,10 @@ impl<'a: 'b, 'b> RelayTransactionBuilder<'a, 'b> {
     self.fbb_.push_slot_always::<flatbuffers::WIPOffset<Transaction>>(RelayTransaction::VT_TRANSACTION, transaction);
   }
   #[inline]
+  pub fn add_stem_hops(&mut self, stem_hops: u8) {
+    self.fbb_.push_slot::<u8>(RelayTransaction::VT_STEM_HOPS, stem_hops, 0);
+  }
+  #[inline]
   pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>) -> RelayTransactionBuilder<'a, 'b> {
     let start = _fbb.start_table();
     RelayTransactionBuilder {
@@ -2575,6 +2625,8 @@ impl<'a> BlockTransactions<'a> {
         _fbb: &'mut_bldr mut flatbuffers::FlatBufferBuilder<'bldr>,
         args: &'args BlockTransactionsArgs<'args>) -> flatbuffers::WIPOffset<BlockTransactions<'bldr>> {
       let mut builder = BlockTransactionsBuilder::new(_fbb);
+      builder.add_total(args.total);
+      builder.add_sequence(args.sequence);
       if let Some(x) = args.transactions { builder.add_transactions(x); }
       if let Some(x) = args.block_hash { builder.add_block_hash(x); }
       builder.finish()
@@ -2582,6 +2634,8 @@ impl<'a> BlockTransactions<'a> {
 
     pub const VT_BLOCK_HASH: flatbuffers::VOffsetT = 4;
     pub const VT_TRANSACTIONS: flatbuffers::VOffsetT = 6;
+    pub const VT_SEQUENCE: flatbuffers::VOffsetT = 8;
+    pub const VT_TOTAL: flatbuffers::VOffsetT = 10;
 
   #[inline]
   pub fn block_hash(&self) -> Option<&'a H256> {
@@ -2591,11 +2645,21 @@ impl<'a> BlockTransactions<'a> {
   pub fn transactions(&self) -> Option<flatbuffers::Vector<flatbuffers::ForwardsUOffset<Transaction<'a>>>> {
     self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<flatbuffers::ForwardsUOffset<Transaction<'a>>>>>(BlockTransactions::VT_TRANSACTIONS, None)
   }
+  #[inline]
+  pub fn sequence(&self) -> u32 {
+    self._tab.get::<u32>(BlockTransactions::VT_SEQUENCE, Some(0)).unwrap()
+  }
+  #[inline]
+  pub fn total(&self) -> u32 {
+    self._tab.get::<u32>(BlockTransactions::VT_TOTAL, Some(0)).unwrap()
+  }
 }
 
 pub struct BlockTransactionsArgs<'a> {
     pub block_hash: Option<&'a  H256>,
     pub transactions: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a , flatbuffers::ForwardsUOffset<Transaction<'a >>>>>,
+    pub sequence: u32,
+    pub total: u32,
 }
 impl<'a> Default for BlockTransactionsArgs<'a> {
     #[inline]
@@ -2603,6 +2667,8 @@ impl<'a> Default for BlockTransactionsArgs<'a> {
         BlockTransactionsArgs {
             block_hash: None,
             transactions: None,
+            sequence: 0,
+            total: 0,
         }
     }
 }
@@ -2620,6 +2686,14 @@ impl<'a: 'b, 'b> BlockTransactionsBuilder<'a, 'b> {
     self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(BlockTransactions::VT_TRANSACTIONS, transactions);
   }
   #[inline]
+  pub fn add_sequence(&mut self, sequence: u32) {
+    self.fbb_.push_slot::<u32>(BlockTransactions::VT_SEQUENCE, sequence, 0);
+  }
+  #[inline]
+  pub fn add_total(&mut self, total: u32) {
+    self.fbb_.push_slot::<u32>(BlockTransactions::VT_TOTAL, total, 0);
+  }
+  #[inline]
   pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>) -> BlockTransactionsBuilder<'a, 'b> {
     let start = _fbb.start_table();
     BlockTransactionsBuilder {
@@ -2798,6 +2872,158 @@ impl<'a: 'b, 'b> BlockProposalBuilder<'a, 'b> {
   }
 }
 
+pub enum SendCompactBlockOffset {}
+#[derive(Copy, Clone, Debug, PartialEq)]
+
+pub struct SendCompactBlock<'a> {
+  pub _tab: flatbuffers::Table<'a>,
+}
+
+impl<'a> flatbuffers::Follow<'a> for SendCompactBlock<'a> {
+    type Inner = SendCompactBlock<'a>;
+    #[inline]
+    fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+        Self {
+            _tab: flatbuffers::Table { buf: buf, loc: loc },
+        }
+    }
+}
+
+impl<'a> SendCompactBlock<'a> {
+    #[inline]
+    pub fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
+        SendCompactBlock {
+            _tab: table,
+        }
+    }
+    #[allow(unused_mut)]
+    pub fn create<'bldr: 'args, 'args: 'mut_bldr, 'mut_bldr>(
+        _fbb: &'mut_bldr mut flatbuffers::FlatBufferBuilder<'bldr>,
+        args: &'args SendCompactBlockArgs) -> flatbuffers::WIPOffset<SendCompactBlock<'bldr>> {
+      let mut builder = SendCompactBlockBuilder::new(_fbb);
+      builder.add_high_bandwidth(args.high_bandwidth);
+      builder.finish()
+    }
+
+    pub const VT_HIGH_BANDWIDTH: flatbuffers::VOffsetT = 4;
+
+  #[inline]
+  pub fn high_bandwidth(&self) -> bool {
+    self._tab.get::<bool>(SendCompactBlock::VT_HIGH_BANDWIDTH, Some(false)).unwrap()
+  }
+}
+
+pub struct SendCompactBlockArgs {
+    pub high_bandwidth: bool,
+}
+impl<'a> Default for SendCompactBlockArgs {
+    #[inline]
+    fn default() -> Self {
+        SendCompactBlockArgs {
+            high_bandwidth: false,
+        }
+    }
+}
+pub struct SendCompactBlockBuilder<'a: 'b, 'b> {
+  fbb_: &'b mut flatbuffers::FlatBufferBuilder<'a>,
+  start_: flatbuffers::WIPOffset<flatbuffers::TableUnfinishedWIPOffset>,
+}
+impl<'a: 'b, 'b> SendCompactBlockBuilder<'a, 'b> {
+  #[inline]
+  pub fn add_high_bandwidth(&mut self, high_bandwidth: bool) {
+    self.fbb_.push_slot::<bool>(SendCompactBlock::VT_HIGH_BANDWIDTH, high_bandwidth, false);
+  }
+  #[inline]
+  pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>) -> SendCompactBlockBuilder<'a, 'b> {
+    let start = _fbb.start_table();
+    SendCompactBlockBuilder {
+      fbb_: _fbb,
+      start_: start,
+    }
+  }
+  #[inline]
+  pub fn finish(self) -> flatbuffers::WIPOffset<SendCompactBlock<'a>> {
+    let o = self.fbb_.end_table(self.start_);
+    flatbuffers::WIPOffset::new(o.value())
+  }
+}
+
+pub enum BlockAnnouncementOffset {}
+#[derive(Copy, Clone, Debug, PartialEq)]
+
+pub struct BlockAnnouncement<'a> {
+  pub _tab: flatbuffers::Table<'a>,
+}
+
+impl<'a> flatbuffers::Follow<'a> for BlockAnnouncement<'a> {
+    type Inner = BlockAnnouncement<'a>;
+    #[inline]
+    fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+        Self {
+            _tab: flatbuffers::Table { buf: buf, loc: loc },
+        }
+    }
+}
+
+impl<'a> BlockAnnouncement<'a> {
+    #[inline]
+    pub fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
+        BlockAnnouncement {
+            _tab: table,
+        }
+    }
+    #[allow(unused_mut)]
+    pub fn create<'bldr: 'args, 'args: 'mut_bldr, 'mut_bldr>(
+        _fbb: &'mut_bldr mut flatbuffers::FlatBufferBuilder<'bldr>,
+        args: &'args BlockAnnouncementArgs<'args>) -> flatbuffers::WIPOffset<BlockAnnouncement<'bldr>> {
+      let mut builder = BlockAnnouncementBuilder::new(_fbb);
+      if let Some(x) = args.block_hash { builder.add_block_hash(x); }
+      builder.finish()
+    }
+
+    pub const VT_BLOCK_HASH: flatbuffers::VOffsetT = 4;
+
+  #[inline]
+  pub fn block_hash(&self) -> Option<&'a H256> {
+    self._tab.get::<H256>(BlockAnnouncement::VT_BLOCK_HASH, None)
+  }
+}
+
+pub struct BlockAnnouncementArgs<'a> {
+    pub block_hash: Option<&'a  H256>,
+}
+impl<'a> Default for BlockAnnouncementArgs<'a> {
+    #[inline]
+    fn default() -> Self {
+        BlockAnnouncementArgs {
+            block_hash: None,
+        }
+    }
+}
+pub struct BlockAnnouncementBuilder<'a: 'b, 'b> {
+  fbb_: &'b mut flatbuffers::FlatBufferBuilder<'a>,
+  start_: flatbuffers::WIPOffset<flatbuffers::TableUnfinishedWIPOffset>,
+}
+impl<'a: 'b, 'b> BlockAnnouncementBuilder<'a, 'b> {
+  #[inline]
+  pub fn add_block_hash(&mut self, block_hash: &'b  H256) {
+    self.fbb_.push_slot_always::<&H256>(BlockAnnouncement::VT_BLOCK_HASH, block_hash);
+  }
+  #[inline]
+  pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>) -> BlockAnnouncementBuilder<'a, 'b> {
+    let start = _fbb.start_table();
+    BlockAnnouncementBuilder {
+      fbb_: _fbb,
+      start_: start,
+    }
+  }
+  #[inline]
+  pub fn finish(self) -> flatbuffers::WIPOffset<BlockAnnouncement<'a>> {
+    let o = self.fbb_.end_table(self.start_);
+    flatbuffers::WIPOffset::new(o.value())
+  }
+}
+
 pub enum SetFilterOffset {}
 #[derive(Copy, Clone, Debug, PartialEq)]
 
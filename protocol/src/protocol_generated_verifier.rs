@@ -239,6 +239,82 @@ pub mod ckb {
             }
         }
 
+        impl<'a> Verify for reader::BlockAnnouncement<'a> {
+            fn verify(&self) -> Result {
+                let tab = self._tab;
+                let buf = tab.buf;
+                let buf_len = buf.len();
+
+                if tab.loc > MAX_OFFSET_LOC || tab.loc + flatbuffers::SIZE_SOFFSET > buf_len {
+                    return Err(Error::OutOfBounds);
+                }
+
+                let vtab_loc = {
+                    let soffset_slice = &buf[tab.loc..];
+                    let soffset = flatbuffers::read_scalar::<flatbuffers::SOffsetT>(soffset_slice);
+                    if soffset >= 0 {
+                        tab.loc.checked_sub(soffset as usize)
+                    } else {
+                        soffset
+                            .checked_neg()
+                            .and_then(|foffset| tab.loc.checked_add(foffset as usize))
+                    }
+                }
+                .ok_or(Error::OutOfBounds)?;
+                if vtab_loc
+                    .checked_add(flatbuffers::SIZE_VOFFSET + flatbuffers::SIZE_VOFFSET)
+                    .filter(|loc| *loc <= buf_len)
+                    .is_none()
+                {
+                    return Err(Error::OutOfBounds);
+                }
+
+                let vtab = tab.vtable();
+                let vtab_num_bytes = vtab.num_bytes();
+                let object_inline_num_bytes = vtab.object_inline_num_bytes();
+                if vtab_num_bytes < flatbuffers::SIZE_VOFFSET + flatbuffers::SIZE_VOFFSET
+                    || object_inline_num_bytes < flatbuffers::SIZE_SOFFSET
+                {
+                    return Err(Error::OutOfBounds);
+                }
+                if vtab_loc
+                    .checked_add(vtab_num_bytes)
+                    .filter(|loc| *loc <= buf_len)
+                    .is_none()
+                {
+                    return Err(Error::OutOfBounds);
+                }
+                if tab
+                    .loc
+                    .checked_add(object_inline_num_bytes)
+                    .filter(|loc| *loc <= buf_len)
+                    .is_none()
+                {
+                    return Err(Error::OutOfBounds);
+                }
+
+                for i in 0..vtab.num_fields() {
+                    let voffset = vtab.get_field(i) as usize;
+                    if (voffset > 0 && voffset < flatbuffers::SIZE_SOFFSET)
+                        || voffset >= object_inline_num_bytes
+                    {
+                        return Err(Error::OutOfBounds);
+                    }
+                }
+
+                if Self::VT_BLOCK_HASH as usize + flatbuffers::SIZE_VOFFSET
+                    <= vtab_num_bytes
+                {
+                    let voffset = vtab.get(Self::VT_BLOCK_HASH) as usize;
+                    if voffset > 0 && object_inline_num_bytes - voffset < 32 {
+                        return Err(Error::OutOfBounds);
+                    }
+                }
+
+                Ok(())
+            }
+        }
+
         impl<'a> Verify for reader::BlockProposal<'a> {
             fn verify(&self) -> Result {
                 let tab = self._tab;
@@ -414,6 +490,24 @@ pub mod ckb {
                     }
                 }
 
+                if Self::VT_SEQUENCE as usize + flatbuffers::SIZE_VOFFSET
+                    <= vtab_num_bytes
+                {
+                    let voffset = vtab.get(Self::VT_SEQUENCE) as usize;
+                    if voffset > 0 && object_inline_num_bytes - voffset < 4 {
+                        return Err(Error::OutOfBounds);
+                    }
+                }
+
+                if Self::VT_TOTAL as usize + flatbuffers::SIZE_VOFFSET
+                    <= vtab_num_bytes
+                {
+                    let voffset = vtab.get(Self::VT_TOTAL) as usize;
+                    if voffset > 0 && object_inline_num_bytes - voffset < 4 {
+                        return Err(Error::OutOfBounds);
+                    }
+                }
+
                 Ok(())
             }
         }
@@ -1634,6 +1728,21 @@ pub mod ckb {
                     }
                 }
 
+                if Self::VT_DAO as usize + flatbuffers::SIZE_VOFFSET
+                    <= vtab_num_bytes
+                {
+                    let voffset = vtab.get(Self::VT_DAO) as usize;
+                    if voffset > 0 {
+                        if voffset + 4 > object_inline_num_bytes {
+                            return Err(Error::OutOfBounds);
+                        }
+
+                        if let Some(f) = self.dao() {
+                            f.verify()?;
+                        }
+                    }
+                }
+
                 Ok(())
             }
         }
@@ -2106,6 +2215,14 @@ pub mod ckb {
                                 .payload_as_block_proposal()
                                 .ok_or(Error::UnmatchedUnion)?
                                 .verify()?,
+                            reader::RelayPayload::SendCompactBlock => self
+                                .payload_as_send_compact_block()
+                                .ok_or(Error::UnmatchedUnion)?
+                                .verify()?,
+                            reader::RelayPayload::BlockAnnouncement => self
+                                .payload_as_block_announcement()
+                                .ok_or(Error::UnmatchedUnion)?
+                                .verify()?,
                             reader::RelayPayload::NONE => return Err(Error::UnmatchedUnion),
                         }
                     }
@@ -2202,6 +2319,15 @@ pub mod ckb {
                     }
                 }
 
+                if Self::VT_STEM_HOPS as usize + flatbuffers::SIZE_VOFFSET
+                    <= vtab_num_bytes
+                {
+                    let voffset = vtab.get(Self::VT_STEM_HOPS) as usize;
+                    if voffset > 0 && object_inline_num_bytes - voffset < 1 {
+                        return Err(Error::OutOfBounds);
+                    }
+                }
+
                 Ok(())
             }
         }
@@ -2300,6 +2426,82 @@ pub mod ckb {
             }
         }
 
+        impl<'a> Verify for reader::SendCompactBlock<'a> {
+            fn verify(&self) -> Result {
+                let tab = self._tab;
+                let buf = tab.buf;
+                let buf_len = buf.len();
+
+                if tab.loc > MAX_OFFSET_LOC || tab.loc + flatbuffers::SIZE_SOFFSET > buf_len {
+                    return Err(Error::OutOfBounds);
+                }
+
+                let vtab_loc = {
+                    let soffset_slice = &buf[tab.loc..];
+                    let soffset = flatbuffers::read_scalar::<flatbuffers::SOffsetT>(soffset_slice);
+                    if soffset >= 0 {
+                        tab.loc.checked_sub(soffset as usize)
+                    } else {
+                        soffset
+                            .checked_neg()
+                            .and_then(|foffset| tab.loc.checked_add(foffset as usize))
+                    }
+                }
+                .ok_or(Error::OutOfBounds)?;
+                if vtab_loc
+                    .checked_add(flatbuffers::SIZE_VOFFSET + flatbuffers::SIZE_VOFFSET)
+                    .filter(|loc| *loc <= buf_len)
+                    .is_none()
+                {
+                    return Err(Error::OutOfBounds);
+                }
+
+                let vtab = tab.vtable();
+                let vtab_num_bytes = vtab.num_bytes();
+                let object_inline_num_bytes = vtab.object_inline_num_bytes();
+                if vtab_num_bytes < flatbuffers::SIZE_VOFFSET + flatbuffers::SIZE_VOFFSET
+                    || object_inline_num_bytes < flatbuffers::SIZE_SOFFSET
+                {
+                    return Err(Error::OutOfBounds);
+                }
+                if vtab_loc
+                    .checked_add(vtab_num_bytes)
+                    .filter(|loc| *loc <= buf_len)
+                    .is_none()
+                {
+                    return Err(Error::OutOfBounds);
+                }
+                if tab
+                    .loc
+                    .checked_add(object_inline_num_bytes)
+                    .filter(|loc| *loc <= buf_len)
+                    .is_none()
+                {
+                    return Err(Error::OutOfBounds);
+                }
+
+                for i in 0..vtab.num_fields() {
+                    let voffset = vtab.get_field(i) as usize;
+                    if (voffset > 0 && voffset < flatbuffers::SIZE_SOFFSET)
+                        || voffset >= object_inline_num_bytes
+                    {
+                        return Err(Error::OutOfBounds);
+                    }
+                }
+
+                if Self::VT_HIGH_BANDWIDTH as usize + flatbuffers::SIZE_VOFFSET
+                    <= vtab_num_bytes
+                {
+                    let voffset = vtab.get(Self::VT_HIGH_BANDWIDTH) as usize;
+                    if voffset > 0 && object_inline_num_bytes - voffset < 1 {
+                        return Err(Error::OutOfBounds);
+                    }
+                }
+
+                Ok(())
+            }
+        }
+
         impl<'a> Verify for reader::SetFilter<'a> {
             fn verify(&self) -> Result {
                 let tab = self._tab;
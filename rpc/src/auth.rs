@@ -0,0 +1,186 @@
+use crate::config::AuthConfig;
+use jsonrpc_http_server::hyper::header::{HeaderValue, AUTHORIZATION};
+use jsonrpc_http_server::hyper::{Body, Method, Request, StatusCode};
+use jsonrpc_http_server::{RequestMiddleware, RequestMiddlewareAction, Response};
+use jsonrpc_ws_server::{ws, MiddlewareAction};
+
+const UNAUTHORIZED_MESSAGE: &str = "Provide the RPC credentials in the Authorization header\n";
+
+/// Turn down the HTTP requests and the WebSocket handshakes without one of the configured
+/// credentials with `401 Unauthorized`. The CORS preflight requests carry no credentials,
+/// they are let through.
+pub(crate) struct Authenticator {
+    // The accepted values of the `Authorization` header
+    credentials: Vec<String>,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig) -> Self {
+        let mut credentials = Vec::new();
+        if let Some(ref token) = config.token {
+            credentials.push(format!("Bearer {}", token));
+        }
+        if let (Some(ref username), Some(ref password)) = (&config.username, &config.password) {
+            credentials.push(format!(
+                "Basic {}",
+                base64::encode(&format!("{}:{}", username, password))
+            ));
+        }
+        Authenticator { credentials }
+    }
+
+    fn is_authorized(&self, value: Option<&[u8]>) -> bool {
+        match value {
+            Some(value) => self
+                .credentials
+                .iter()
+                .any(|credential| constant_time_eq(credential.as_bytes(), value)),
+            None => false,
+        }
+    }
+}
+
+impl RequestMiddleware for Authenticator {
+    fn on_request(&self, request: Request<Body>) -> RequestMiddlewareAction {
+        let authorization = request
+            .headers()
+            .get(AUTHORIZATION)
+            .map(HeaderValue::as_bytes);
+        if *request.method() == Method::OPTIONS || self.is_authorized(authorization) {
+            RequestMiddlewareAction::Proceed {
+                should_continue_on_invalid_cors: false,
+                request,
+            }
+        } else {
+            Response {
+                code: StatusCode::UNAUTHORIZED,
+                content_type: HeaderValue::from_static("text/plain; charset=utf-8"),
+                content: UNAUTHORIZED_MESSAGE.to_string(),
+            }
+            .into()
+        }
+    }
+}
+
+// The WebSocket server calls it once per connection on the handshake, the subscriptions can't
+// be reached without the credentials either
+impl jsonrpc_ws_server::RequestMiddleware for Authenticator {
+    fn process(&self, request: &ws::Request) -> MiddlewareAction {
+        let authorization = request.header("authorization").map(Vec::as_slice);
+        if self.is_authorized(authorization) {
+            MiddlewareAction::Proceed
+        } else {
+            let mut response = ws::Response::new(
+                401,
+                "Unauthorized",
+                UNAUTHORIZED_MESSAGE.as_bytes().to_vec(),
+            );
+            response
+                .headers_mut()
+                .push(("Connection".to_owned(), b"close".to_vec()));
+            MiddlewareAction::Respond {
+                response,
+                validate_origin: false,
+                validate_hosts: false,
+            }
+        }
+    }
+}
+
+// Compare the credentials without leaking how many leading bytes match through the timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+
+    fn authenticator() -> Authenticator {
+        Authenticator::new(&AuthConfig {
+            token: Some("secret".to_string()),
+            username: Some("ckb".to_string()),
+            password: Some("passwd".to_string()),
+        })
+    }
+
+    fn http_request(method: Method, authorization: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder();
+        builder.method(method).uri("http://127.0.0.1:8114/");
+        if let Some(authorization) = authorization {
+            builder.header(AUTHORIZATION, authorization);
+        }
+        builder.body(Body::empty()).expect("build request")
+    }
+
+    fn http_status(action: RequestMiddlewareAction) -> Option<StatusCode> {
+        match action {
+            RequestMiddlewareAction::Proceed { .. } => None,
+            RequestMiddlewareAction::Respond { response, .. } => {
+                Some(response.wait().expect("response").status())
+            }
+        }
+    }
+
+    fn ws_request(authorization: Option<&str>) -> ws::Request {
+        let mut raw = "GET / HTTP/1.1\r\n\
+                       Host: 127.0.0.1:18114\r\n\
+                       Connection: Upgrade\r\n\
+                       Upgrade: websocket\r\n\
+                       Sec-WebSocket-Version: 13\r\n\
+                       Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n"
+            .to_string();
+        if let Some(authorization) = authorization {
+            raw.push_str(&format!("Authorization: {}\r\n", authorization));
+        }
+        raw.push_str("\r\n");
+        ws::Request::parse(raw.as_bytes())
+            .expect("parse request")
+            .expect("complete request")
+    }
+
+    #[test]
+    fn test_http_without_credentials() {
+        let authenticator = authenticator();
+        let action = authenticator.on_request(http_request(Method::POST, None));
+        assert_eq!(http_status(action), Some(StatusCode::UNAUTHORIZED));
+
+        let action = authenticator.on_request(http_request(Method::POST, Some("Bearer wrong")));
+        assert_eq!(http_status(action), Some(StatusCode::UNAUTHORIZED));
+    }
+
+    #[test]
+    fn test_http_bearer_and_basic() {
+        let authenticator = authenticator();
+        let action = authenticator.on_request(http_request(Method::POST, Some("Bearer secret")));
+        assert_eq!(http_status(action), None);
+
+        // base64("ckb:passwd")
+        let action =
+            authenticator.on_request(http_request(Method::POST, Some("Basic Y2tiOnBhc3N3ZA==")));
+        assert_eq!(http_status(action), None);
+
+        let action = authenticator.on_request(http_request(Method::POST, Some("Basic secret")));
+        assert_eq!(http_status(action), Some(StatusCode::UNAUTHORIZED));
+    }
+
+    #[test]
+    fn test_http_cors_preflight() {
+        let action = authenticator().on_request(http_request(Method::OPTIONS, None));
+        assert_eq!(http_status(action), None);
+    }
+
+    #[test]
+    fn test_ws_handshake() {
+        let authenticator = authenticator();
+        match authenticator.process(&ws_request(None)) {
+            MiddlewareAction::Respond { response, .. } => assert_eq!(response.status(), 401),
+            MiddlewareAction::Proceed => panic!("handshake without credentials proceeds"),
+        }
+        match authenticator.process(&ws_request(Some("Bearer secret"))) {
+            MiddlewareAction::Proceed => {}
+            MiddlewareAction::Respond { .. } => panic!("handshake with the token is turned down"),
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Clone, Debug, Copy, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Module {
@@ -7,18 +8,82 @@ pub enum Module {
     Miner,
     Pool,
     Trace,
+    Experiment,
     IntegrationTest,
 }
 
+// The tables must come last to be serializable by toml, keep `auth` and `method_limits` at
+// the tail.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Config {
     pub listen_address: String,
+    /// The subscriptions are served over WebSocket on this address when it is set
+    #[serde(default)]
+    pub ws_listen_address: Option<String>,
     pub max_request_body_size: usize,
     pub threads: Option<usize>,
     pub modules: Vec<Module>,
+    /// The origins allowed by CORS, `*` allows any and `null` the local files, both are allowed
+    /// if not set
+    #[serde(default)]
+    pub cors_allowed_origins: Option<Vec<String>>,
+    /// Reject the batches holding more calls, no limit if not set
+    #[serde(default)]
+    pub max_batch_size: Option<usize>,
+    /// The maximum number of concurrent calls of a method, the other methods are unlimited
+    #[serde(default)]
+    pub method_limits: HashMap<String, usize>,
+    /// Require the HTTP requests and the WebSocket handshakes to carry one of the credentials,
+    /// anyone may call if not set
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+}
+
+/// The credentials are passed in the `Authorization` header
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// Accepted as `Bearer <token>`
+    pub token: Option<String>,
+    /// Accepted as `Basic <base64 of username:password>`
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl AuthConfig {
+    /// A table without credentials would turn down every request, a username without a
+    /// password or the other way round would be ignored
+    pub fn validate(&self) -> Result<(), String> {
+        if self.token.as_ref().map_or(false, String::is_empty) {
+            return Err("rpc.auth.token is empty".to_string());
+        }
+        match (&self.username, &self.password) {
+            (Some(_), None) => {
+                return Err("rpc.auth.username is set without a password".to_string())
+            }
+            (None, Some(_)) => {
+                return Err("rpc.auth.password is set without a username".to_string())
+            }
+            (Some(username), Some(_)) if username.is_empty() || username.contains(':') => {
+                return Err("rpc.auth.username is empty or contains a colon".to_string());
+            }
+            _ => {}
+        }
+        if self.token.is_none() && self.username.is_none() {
+            return Err("rpc.auth sets neither a token nor a username and password".to_string());
+        }
+        Ok(())
+    }
 }
 
 impl Config {
+    /// Check the settings which can't be checked by the deserialization
+    pub fn validate(&self) -> Result<(), String> {
+        match self.auth {
+            Some(ref auth) => auth.validate(),
+            None => Ok(()),
+        }
+    }
+
     pub(crate) fn net_enable(&self) -> bool {
         self.modules.contains(&Module::Net)
     }
@@ -39,7 +104,42 @@ impl Config {
         self.modules.contains(&Module::Trace)
     }
 
+    pub(crate) fn experiment_enable(&self) -> bool {
+        self.modules.contains(&Module::Experiment)
+    }
+
     pub(crate) fn integration_test_enable(&self) -> bool {
         self.modules.contains(&Module::IntegrationTest)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth(token: Option<&str>, username: Option<&str>, password: Option<&str>) -> AuthConfig {
+        AuthConfig {
+            token: token.map(ToString::to_string),
+            username: username.map(ToString::to_string),
+            password: password.map(ToString::to_string),
+        }
+    }
+
+    #[test]
+    fn test_auth_validate() {
+        assert!(auth(Some("secret"), None, None).validate().is_ok());
+        assert!(auth(None, Some("ckb"), Some("passwd")).validate().is_ok());
+        assert!(auth(Some("secret"), Some("ckb"), Some("passwd"))
+            .validate()
+            .is_ok());
+
+        assert!(auth(None, None, None).validate().is_err());
+        assert!(auth(Some(""), None, None).validate().is_err());
+        assert!(auth(None, Some("ckb"), None).validate().is_err());
+        assert!(auth(Some("secret"), None, Some("passwd"))
+            .validate()
+            .is_err());
+        assert!(auth(None, Some(""), Some("passwd")).validate().is_err());
+        assert!(auth(None, Some("ck:b"), Some("passwd")).validate().is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use crate::error::RPCError;
+use ckb_core::BlockNumber;
+use ckb_shared::{chain_state::ChainState, shared::Shared, store::ChainStore};
+use ckb_traits::ChainProvider;
+use faster_hex::{hex_decode, hex_encode};
+use jsonrpc_core::{Error, Result};
+use numext_fixed_hash::H256;
+
+const CURSOR_SIZE: usize = 32 + 8 + 8 + 4 + 4;
+
+/// Position of the next entry of a paginated query, along with the tip the first page was
+/// served at. Clients get it as an opaque hex string and pass it back to fetch the next page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Cursor {
+    pub tip_hash: H256,
+    pub tip_number: BlockNumber,
+    pub block_number: BlockNumber,
+    pub tx_index: u32,
+    pub output_index: u32,
+}
+
+impl Cursor {
+    /// Cursor of the first page, starting at `block_number` with the current tip as snapshot
+    pub fn start<CS: ChainStore>(chain_state: &ChainState<CS>, block_number: BlockNumber) -> Self {
+        Cursor {
+            tip_hash: chain_state.tip_hash().clone(),
+            tip_number: chain_state.tip_number(),
+            block_number,
+            tx_index: 0,
+            output_index: 0,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut bytes = Vec::with_capacity(CURSOR_SIZE);
+        bytes.extend_from_slice(self.tip_hash.as_bytes());
+        bytes.extend_from_slice(&self.tip_number.to_be_bytes());
+        bytes.extend_from_slice(&self.block_number.to_be_bytes());
+        bytes.extend_from_slice(&self.tx_index.to_be_bytes());
+        bytes.extend_from_slice(&self.output_index.to_be_bytes());
+        let mut buffer = vec![0u8; CURSOR_SIZE * 2];
+        hex_encode(&bytes, &mut buffer).expect("hex encode cursor");
+        String::from_utf8(buffer).expect("hex string")
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self> {
+        if cursor.len() != CURSOR_SIZE * 2 {
+            return Err(Error::invalid_params("malformed cursor"));
+        }
+        let mut bytes = [0u8; CURSOR_SIZE];
+        hex_decode(cursor.as_bytes(), &mut bytes)
+            .map_err(|_| Error::invalid_params("malformed cursor"))?;
+
+        let mut tip_number = [0u8; 8];
+        tip_number.copy_from_slice(&bytes[32..40]);
+        let mut block_number = [0u8; 8];
+        block_number.copy_from_slice(&bytes[40..48]);
+        let mut tx_index = [0u8; 4];
+        tx_index.copy_from_slice(&bytes[48..52]);
+        let mut output_index = [0u8; 4];
+        output_index.copy_from_slice(&bytes[52..56]);
+        Ok(Cursor {
+            tip_hash: H256::from_slice(&bytes[0..32])
+                .map_err(|_| Error::invalid_params("malformed cursor"))?,
+            tip_number: u64::from_be_bytes(tip_number),
+            block_number: u64::from_be_bytes(block_number),
+            tx_index: u32::from_be_bytes(tx_index),
+            output_index: u32::from_be_bytes(output_index),
+        })
+    }
+
+    /// Fail with `RPCError::Reorged` if the snapshot tip left the main chain, entries the
+    /// cursor points past may have changed, the client has to restart from the first page
+    pub fn validate<CS: ChainStore>(&self, shared: &Shared<CS>) -> Result<()> {
+        if shared.block_hash(self.tip_number).as_ref() == Some(&self.tip_hash) {
+            Ok(())
+        } else {
+            Err(RPCError::custom(
+                RPCError::Reorged,
+                format!(
+                    "chain reorganized since block {:#x}, restart pagination",
+                    self.tip_hash
+                ),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_db::MemoryKeyValueDB;
+    use ckb_shared::shared::SharedBuilder;
+    use jsonrpc_core::ErrorCode;
+
+    fn new_cursor() -> Cursor {
+        Cursor {
+            tip_hash: H256::from_slice(&[1u8; 32]).unwrap(),
+            tip_number: 42,
+            block_number: 7,
+            tx_index: 3,
+            output_index: u32::max_value(),
+        }
+    }
+
+    #[test]
+    fn test_encode_and_decode() {
+        let cursor = new_cursor();
+        let encoded = cursor.encode();
+        assert_eq!(encoded.len(), CURSOR_SIZE * 2);
+        assert_eq!(Cursor::decode(&encoded), Ok(cursor));
+    }
+
+    #[test]
+    fn test_decode_malformed_cursor() {
+        let encoded = new_cursor().encode();
+        assert!(Cursor::decode("").is_err());
+        assert!(Cursor::decode(&encoded[2..]).is_err());
+        assert!(Cursor::decode(&format!("{}00", encoded)).is_err());
+        let not_hex = format!("zz{}", &encoded[2..]);
+        assert_eq!(
+            Cursor::decode(&not_hex).unwrap_err().code,
+            ErrorCode::InvalidParams
+        );
+    }
+
+    #[test]
+    fn test_validate_reorged_cursor() {
+        let shared = SharedBuilder::<MemoryKeyValueDB>::new().build().unwrap();
+        let mut cursor = Cursor::start(&shared.chain_state(), 0);
+        assert!(cursor.validate(&shared).is_ok());
+
+        // the snapshot tip is no longer in the main chain
+        cursor.tip_hash = H256::from_slice(&[1u8; 32]).unwrap();
+        assert_eq!(
+            cursor.validate(&shared).unwrap_err().code,
+            ErrorCode::ServerError(RPCError::Reorged as i64)
+        );
+        // nor is any block at its height
+        cursor.tip_number = 1;
+        assert!(cursor.validate(&shared).is_err());
+    }
+}
@@ -1,8 +1,18 @@
-use jsonrpc_core::{Error, ErrorCode};
+use jsonrpc_core::{Error, ErrorCode, Value};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum RPCError {
     Invalid = -3,
+    /// The chain reorganized while paginating, restart from the first page
+    Reorged = -4,
+    /// The block body is deleted by the pruning mode
+    Pruned = -5,
+    /// The batch holds more calls than `max_batch_size`
+    BatchTooLarge = -6,
+    /// The method already runs as many calls as its limit in `method_limits`
+    MethodBusy = -7,
+    /// The work_id wasn't issued by `get_block_template`, or the tip has moved past its parent
+    UnknownWork = -8,
 }
 
 impl RPCError {
@@ -13,4 +23,12 @@ impl RPCError {
             data: None,
         }
     }
+
+    pub fn custom_with_data(err: RPCError, message: String, data: Value) -> Error {
+        Error {
+            code: ErrorCode::ServerError(err as i64),
+            message,
+            data: Some(data),
+        }
+    }
 }
@@ -1,5 +1,8 @@
+mod auth;
 mod config;
+mod cursor;
 mod error;
+mod limits;
 mod module;
 mod server;
 
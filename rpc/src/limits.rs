@@ -0,0 +1,220 @@
+use crate::config::Config;
+use crate::error::RPCError;
+use futures::future::{self, Either, Future};
+use jsonrpc_core::{Call, Id, Metadata, Middleware, Output, Request, Response, Version};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+type FutureResponse = Box<dyn Future<Item = Option<Response>, Error = ()> + Send>;
+type FutureOutput = Box<dyn Future<Item = Option<Output>, Error = ()> + Send>;
+
+/// Bound the calls of a batch and the concurrent calls of the limited methods, the rejected
+/// requests fail with `RPCError::BatchTooLarge` or `RPCError::MethodBusy`.
+pub(crate) struct RequestLimits {
+    max_batch_size: Option<usize>,
+    methods: HashMap<String, MethodLimit>,
+}
+
+struct MethodLimit {
+    limit: usize,
+    running: Arc<AtomicUsize>,
+}
+
+// Held while the call runs, frees its slot on drop
+struct Permit(Arc<AtomicUsize>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl MethodLimit {
+    fn acquire(&self) -> Option<Permit> {
+        if self.running.fetch_add(1, Ordering::SeqCst) < self.limit {
+            Some(Permit(Arc::clone(&self.running)))
+        } else {
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            None
+        }
+    }
+}
+
+impl RequestLimits {
+    pub fn new(config: &Config) -> Self {
+        let methods = config
+            .method_limits
+            .iter()
+            .map(|(method, limit)| {
+                (
+                    method.to_owned(),
+                    MethodLimit {
+                        limit: *limit,
+                        running: Arc::new(AtomicUsize::new(0)),
+                    },
+                )
+            })
+            .collect();
+        RequestLimits {
+            max_batch_size: config.max_batch_size,
+            methods,
+        }
+    }
+}
+
+impl<M: Metadata> Middleware<M> for RequestLimits {
+    type Future = FutureResponse;
+    type CallFuture = FutureOutput;
+
+    fn on_request<F, X>(&self, request: Request, meta: M, next: F) -> Either<Self::Future, X>
+    where
+        F: FnOnce(Request, M) -> X + Send,
+        X: Future<Item = Option<Response>, Error = ()> + Send + 'static,
+    {
+        match (&request, self.max_batch_size) {
+            (Request::Batch(calls), Some(max_batch_size)) if calls.len() > max_batch_size => {
+                let error = RPCError::custom_with_data(
+                    RPCError::BatchTooLarge,
+                    format!("batch of {} calls exceeds {}", calls.len(), max_batch_size),
+                    json!({ "max_batch_size": max_batch_size }),
+                );
+                let output = Output::from(Err(error), Id::Null, Some(Version::V2));
+                Either::A(Box::new(future::ok(Some(Response::Single(output)))))
+            }
+            _ => Either::B(next(request, meta)),
+        }
+    }
+
+    fn on_call<F, X>(&self, call: Call, meta: M, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: FnOnce(Call, M) -> X + Send,
+        X: Future<Item = Option<Output>, Error = ()> + Send + 'static,
+    {
+        let method_call = match call {
+            Call::MethodCall(method_call) => method_call,
+            call => return Either::B(next(call, meta)),
+        };
+        let limit = match self.methods.get(&method_call.method) {
+            Some(limit) => limit,
+            None => return Either::B(next(Call::MethodCall(method_call), meta)),
+        };
+        match limit.acquire() {
+            Some(permit) => {
+                let output = next(Call::MethodCall(method_call), meta);
+                Either::A(Box::new(output.then(move |output| {
+                    drop(permit);
+                    output
+                })))
+            }
+            None => {
+                let error = RPCError::custom_with_data(
+                    RPCError::MethodBusy,
+                    format!(
+                        "{} is running {} calls already",
+                        method_call.method, limit.limit
+                    ),
+                    json!({ "method": method_call.method, "limit": limit.limit }),
+                );
+                let output = Output::from(Err(error), method_call.id, method_call.jsonrpc);
+                Either::A(Box::new(future::ok(Some(output))))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::sync::oneshot;
+    use jsonrpc_core::{MetaIoHandler, Params, Value};
+    use std::sync::Mutex;
+
+    fn limited_io(
+        max_batch_size: Option<usize>,
+        method_limit: usize,
+    ) -> (
+        MetaIoHandler<(), RequestLimits>,
+        Arc<Mutex<Vec<oneshot::Sender<()>>>>,
+    ) {
+        let mut method_limits = HashMap::new();
+        method_limits.insert("slow".to_owned(), method_limit);
+        let config = Config {
+            listen_address: "127.0.0.1:8114".to_owned(),
+            ws_listen_address: None,
+            max_request_body_size: 10 * 1024 * 1024,
+            threads: None,
+            modules: Vec::new(),
+            cors_allowed_origins: None,
+            max_batch_size,
+            method_limits,
+            auth: None,
+        };
+        let mut io = MetaIoHandler::with_middleware(RequestLimits::new(&config));
+        io.add_method("fast", |_: Params| Ok(Value::Bool(true)));
+        // each call runs until it's released through its sender
+        let running = Arc::new(Mutex::new(Vec::new()));
+        let senders = Arc::clone(&running);
+        io.add_method("slow", move |_: Params| {
+            let (sender, receiver) = oneshot::channel();
+            senders.lock().unwrap().push(sender);
+            receiver
+                .map(|_| Value::Bool(true))
+                .map_err(|_| jsonrpc_core::Error::internal_error())
+        });
+        (io, running)
+    }
+
+    fn request(method: &str, id: u64) -> String {
+        json!({ "jsonrpc": "2.0", "method": method, "params": [], "id": id }).to_string()
+    }
+
+    fn error_code(response: &str) -> Option<i64> {
+        let response: serde_json::Value = serde_json::from_str(response).expect("parse response");
+        response["error"]["code"].as_i64()
+    }
+
+    #[test]
+    fn test_reject_large_batch() {
+        let (io, _) = limited_io(Some(2), 1);
+        let batch = |size| {
+            let calls: Vec<String> = (0..size).map(|id| request("fast", id)).collect();
+            format!("[{}]", calls.join(","))
+        };
+
+        let response = io.handle_request_sync(&batch(2), ()).expect("response");
+        assert!(response.starts_with('['));
+        let response = io.handle_request_sync(&batch(3), ()).expect("response");
+        assert_eq!(error_code(&response), Some(RPCError::BatchTooLarge as i64));
+    }
+
+    #[test]
+    fn test_limit_concurrent_calls() {
+        let (io, running) = limited_io(None, 1);
+
+        let first = io.handle_request(&request("slow", 1), ());
+        let response = io
+            .handle_request_sync(&request("slow", 2), ())
+            .expect("response");
+        assert_eq!(error_code(&response), Some(RPCError::MethodBusy as i64));
+        // the other methods are not limited
+        let response = io
+            .handle_request_sync(&request("fast", 3), ())
+            .expect("response");
+        assert_eq!(error_code(&response), None);
+
+        // the slot is freed once the running call finishes
+        let sender = running.lock().unwrap().pop().expect("running call");
+        sender.send(()).expect("release call");
+        let response = first.wait().expect("first call").expect("response");
+        assert_eq!(error_code(&response), None);
+
+        let second = io.handle_request(&request("slow", 4), ());
+        assert_eq!(running.lock().unwrap().len(), 1);
+        drop(second);
+        // a dropped call frees its slot as well
+        let _third = io.handle_request(&request("slow", 5), ());
+        assert_eq!(running.lock().unwrap().len(), 2);
+    }
+}
@@ -1,15 +1,23 @@
+use crate::cursor::Cursor;
+use crate::error::RPCError;
 use ckb_core::cell::CellProvider;
-use ckb_core::{transaction::ProposalShortId, BlockNumber};
+use ckb_core::transaction::{CellOutput, ProposalShortId};
+use ckb_core::BlockNumber;
 use ckb_shared::{shared::Shared, store::ChainStore};
 use ckb_traits::ChainProvider;
 use jsonrpc_core::{Error, Result};
 use jsonrpc_derive::rpc;
 use jsonrpc_types::{
-    Block, CellOutputWithOutPoint, CellWithStatus, Header, OutPoint, TransactionWithStatus,
+    Block, BlockEconomicState, CellOutputWithOutPoint, CellWithStatus, CellsPage, Header, OutPoint,
+    ReorgRecord, TransactionWithStatus,
 };
 use numext_fixed_hash::H256;
+use std::cmp::min;
 use std::convert::TryInto;
 
+const MAX_REORG_HISTORY_LIMIT: u64 = 100;
+const MAX_CELLS_PAGE_LIMIT: u64 = 1000;
+
 #[rpc]
 pub trait ChainRpc {
     #[rpc(name = "get_block")]
@@ -32,40 +40,101 @@ pub trait ChainRpc {
         _to: String,
     ) -> Result<Vec<CellOutputWithOutPoint>>;
 
+    // Paginated get_cells_by_lock_hash, pass the `next_cursor` of the previous page to get
+    // the next one
+    #[rpc(name = "get_cells_by_lock_hash_page")]
+    fn get_cells_by_lock_hash_page(
+        &self,
+        _lock_hash: H256,
+        _from: String,
+        _to: String,
+        _limit: String,
+        _cursor: Option<String>,
+    ) -> Result<CellsPage>;
+
     #[rpc(name = "get_live_cell")]
     fn get_live_cell(&self, _out_point: OutPoint) -> Result<CellWithStatus>;
 
     #[rpc(name = "get_tip_block_number")]
     fn get_tip_block_number(&self) -> Result<String>;
+
+    // Reorgs are indexed from 0 in the order they happened
+    #[rpc(name = "get_reorg_history")]
+    fn get_reorg_history(&self, _from: String, _limit: String) -> Result<Vec<ReorgRecord>>;
+
+    #[rpc(name = "get_block_economic_state")]
+    fn get_block_economic_state(&self, _hash: H256) -> Result<Option<BlockEconomicState>>;
 }
 
 pub(crate) struct ChainRpcImpl<CS> {
     pub shared: Shared<CS>,
 }
 
+impl<CS: ChainStore> ChainRpcImpl<CS> {
+    // A committed transaction whose index entry was dropped by the retention, the cell set
+    // still knows its block number, so it is read from the block body
+    fn get_pruned_transaction(&self, hash: &H256) -> Option<TransactionWithStatus> {
+        let number = {
+            let chain_state = self.shared.chain_state();
+            let cutoff = self
+                .shared
+                .store_config()
+                .tx_index_cutoff(chain_state.tip_number())?;
+            let number = chain_state.cell_set().get(hash)?.block_number();
+            if number >= cutoff {
+                return None;
+            }
+            number
+        };
+        let block_hash = self.shared.block_hash(number)?;
+        self.shared
+            .block_body(&block_hash)?
+            .into_iter()
+            .find(|tx| &tx.hash() == hash)
+            .map(|tx| TransactionWithStatus::with_pruned(tx, block_hash))
+    }
+
+    // Whether the body of the main chain block at `number` may be deleted by the pruning mode
+    fn is_pruned(&self, number: BlockNumber) -> bool {
+        self.shared
+            .store()
+            .get_pruned_height()
+            .map_or(false, |pruned_height| number < pruned_height)
+    }
+}
+
 impl<CS: ChainStore + 'static> ChainRpc for ChainRpcImpl<CS> {
     fn get_block(&self, hash: H256) -> Result<Option<Block>> {
-        Ok(self.shared.block(&hash).as_ref().map(Into::into))
+        if let Some(block) = self.shared.block(&hash) {
+            return Ok(Some((&block).into()));
+        }
+        match self.shared.block_header(&hash) {
+            Some(ref header) if self.is_pruned(header.number()) => Err(RPCError::custom(
+                RPCError::Pruned,
+                format!("block {:#x} is pruned", hash),
+            )),
+            _ => Ok(None),
+        }
     }
 
     fn get_transaction(&self, hash: H256) -> Result<Option<TransactionWithStatus>> {
         let id = ProposalShortId::from_tx_hash(&hash);
 
         let tx = {
-            let chan_state = self.shared.chain_state().lock();
-
-            let tx_pool = chan_state.tx_pool();
+            let tx_pool = self.shared.tx_pool().lock();
             tx_pool
                 .get_tx_from_staging(&id)
                 .map(TransactionWithStatus::with_proposed)
                 .or_else(|| tx_pool.get_tx(&id).map(TransactionWithStatus::with_pending))
         };
 
-        Ok(tx.or_else(|| {
-            self.shared
-                .get_transaction(&hash)
-                .map(|(tx, block_hash)| TransactionWithStatus::with_committed(tx, block_hash))
-        }))
+        Ok(tx
+            .or_else(|| {
+                self.shared
+                    .get_transaction(&hash)
+                    .map(|(tx, block_hash)| TransactionWithStatus::with_committed(tx, block_hash))
+            })
+            .or_else(|| self.get_pruned_transaction(&hash)))
     }
 
     fn get_block_hash(&self, number: String) -> Result<Option<H256>> {
@@ -77,10 +146,10 @@ impl<CS: ChainStore + 'static> ChainRpc for ChainRpcImpl<CS> {
     }
 
     fn get_tip_header(&self) -> Result<Header> {
-        Ok(self.shared.chain_state().lock().tip_header().into())
+        Ok(self.shared.chain_state().tip_header().into())
     }
 
-    // TODO: we need to build a proper index instead of scanning every time
+    // Read from the lock index if it's enabled, otherwise the blocks are scanned
     fn get_cells_by_lock_hash(
         &self,
         lock_hash: H256,
@@ -88,19 +157,42 @@ impl<CS: ChainStore + 'static> ChainRpc for ChainRpcImpl<CS> {
         to: String,
     ) -> Result<Vec<CellOutputWithOutPoint>> {
         let mut result = Vec::new();
-        let chain_state = self.shared.chain_state().lock();
+        let chain_state = self.shared.chain_state();
         let from = from
             .parse::<BlockNumber>()
             .map_err(|_| Error::parse_error())?;
         let to = to
             .parse::<BlockNumber>()
             .map_err(|_| Error::parse_error())?;
+        if self.shared.store_config().lock_index {
+            let mut cells: Vec<_> = self
+                .shared
+                .store()
+                .get_lock_cells(&lock_hash)
+                .into_iter()
+                .filter(|(_, cell)| cell.block_number >= from && cell.block_number <= to)
+                .collect();
+            cells.sort_by_key(|(_, cell)| cell.block_number);
+            return Ok(cells
+                .into_iter()
+                .map(|(out_point, cell)| CellOutputWithOutPoint {
+                    out_point: OutPoint {
+                        tx_hash: out_point.tx_hash,
+                        index: out_point.index,
+                    },
+                    capacity: cell.capacity.to_string(),
+                    lock: cell.lock.into(),
+                })
+                .collect());
+        }
         for block_number in from..=to {
             if let Some(block_hash) = self.shared.block_hash(block_number) {
-                let block = self
-                    .shared
-                    .block(&block_hash)
-                    .ok_or_else(Error::internal_error)?;
+                // the pruned blocks have no live cells
+                let block = match self.shared.block(&block_hash) {
+                    Some(block) => block,
+                    None if self.is_pruned(block_number) => continue,
+                    None => return Err(Error::internal_error()),
+                };
                 for transaction in block.transactions() {
                     let transaction_meta = chain_state
                         .cell_set()
@@ -108,14 +200,11 @@ impl<CS: ChainStore + 'static> ChainRpc for ChainRpcImpl<CS> {
                         .ok_or_else(Error::internal_error)?;
                     for (i, output) in transaction.outputs().iter().enumerate() {
                         if output.lock.hash() == lock_hash && (!transaction_meta.is_dead(i)) {
-                            result.push(CellOutputWithOutPoint {
-                                out_point: OutPoint {
-                                    tx_hash: transaction.hash().clone(),
-                                    index: i as u32,
-                                },
-                                capacity: output.capacity.to_string(),
-                                lock: output.lock.clone().into(),
-                            });
+                            result.push(cell_output_with_out_point(
+                                &transaction.hash(),
+                                i as u32,
+                                output,
+                            ));
                         }
                     }
                 }
@@ -124,16 +213,171 @@ impl<CS: ChainStore + 'static> ChainRpc for ChainRpcImpl<CS> {
         Ok(result)
     }
 
+    fn get_cells_by_lock_hash_page(
+        &self,
+        lock_hash: H256,
+        from: String,
+        to: String,
+        limit: String,
+        cursor: Option<String>,
+    ) -> Result<CellsPage> {
+        let from = from
+            .parse::<BlockNumber>()
+            .map_err(|_| Error::parse_error())?;
+        let to = to
+            .parse::<BlockNumber>()
+            .map_err(|_| Error::parse_error())?;
+        let limit = min(
+            limit.parse::<u64>().map_err(|_| Error::parse_error())?,
+            MAX_CELLS_PAGE_LIMIT,
+        ) as usize;
+        if limit == 0 {
+            return Err(Error::invalid_params("limit should be greater than 0"));
+        }
+
+        let chain_state = self.shared.chain_state();
+        let mut cursor = match cursor {
+            Some(cursor) => {
+                let cursor = Cursor::decode(&cursor)?;
+                cursor.validate(&self.shared)?;
+                cursor
+            }
+            None => Cursor::start(&chain_state, from),
+        };
+        // blocks above the snapshot tip are left out, so later pages see the same chain
+        let to = min(to, cursor.tip_number);
+
+        let mut cells = Vec::new();
+        while cursor.block_number <= to {
+            let block = match self
+                .shared
+                .block_hash(cursor.block_number)
+                .and_then(|block_hash| self.shared.block(&block_hash))
+            {
+                Some(block) => block,
+                // the pruned blocks have no live cells
+                None if self.is_pruned(cursor.block_number) => {
+                    cursor.tx_index = 0;
+                    cursor.output_index = 0;
+                    cursor.block_number += 1;
+                    continue;
+                }
+                None => return Err(Error::internal_error()),
+            };
+            for (tx_index, transaction) in block
+                .transactions()
+                .iter()
+                .enumerate()
+                .skip(cursor.tx_index as usize)
+            {
+                let transaction_meta = chain_state
+                    .cell_set()
+                    .get(&transaction.hash())
+                    .ok_or_else(Error::internal_error)?;
+                for (i, output) in transaction
+                    .outputs()
+                    .iter()
+                    .enumerate()
+                    .skip(cursor.output_index as usize)
+                {
+                    if cells.len() >= limit {
+                        cursor.tx_index = tx_index as u32;
+                        cursor.output_index = i as u32;
+                        return Ok(CellsPage {
+                            cells,
+                            next_cursor: Some(cursor.encode()),
+                        });
+                    }
+                    if output.lock.hash() == lock_hash && (!transaction_meta.is_dead(i)) {
+                        cells.push(cell_output_with_out_point(
+                            &transaction.hash(),
+                            i as u32,
+                            output,
+                        ));
+                    }
+                }
+                cursor.output_index = 0;
+            }
+            cursor.tx_index = 0;
+            cursor.block_number += 1;
+        }
+        Ok(CellsPage {
+            cells,
+            next_cursor: None,
+        })
+    }
+
     fn get_live_cell(&self, out_point: OutPoint) -> Result<CellWithStatus> {
         Ok(self
             .shared
             .chain_state()
-            .lock()
             .get_cell_status(&(out_point.try_into().map_err(|_| Error::parse_error())?))
             .into())
     }
 
     fn get_tip_block_number(&self) -> Result<String> {
-        Ok(self.shared.chain_state().lock().tip_number().to_string())
+        Ok(self.shared.chain_state().tip_number().to_string())
+    }
+
+    fn get_reorg_history(&self, from: String, limit: String) -> Result<Vec<ReorgRecord>> {
+        let from = from.parse::<u64>().map_err(|_| Error::parse_error())?;
+        let limit = limit.parse::<u64>().map_err(|_| Error::parse_error())?;
+        let store = self.shared.store();
+        let to = min(
+            store.get_reorg_count(),
+            from.saturating_add(min(limit, MAX_REORG_HISTORY_LIMIT)),
+        );
+        (from..to)
+            .map(|index| {
+                store
+                    .get_reorg_record(index)
+                    .map(|record| ReorgRecord::new(index, record))
+                    .ok_or_else(Error::internal_error)
+            })
+            .collect()
+    }
+
+    fn get_block_economic_state(&self, hash: H256) -> Result<Option<BlockEconomicState>> {
+        let header = match self.shared.block_header(&hash) {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        let chain_state = self.shared.chain_state();
+        let number = header.number();
+        let cellbase_capacity = match self.shared.block_body(&hash) {
+            Some(transactions) => Some(
+                transactions
+                    .first()
+                    .ok_or_else(Error::internal_error)?
+                    .outputs_capacity()
+                    .map_err(|_| Error::internal_error())?,
+            ),
+            None => None,
+        };
+        let max_issuance = chain_state
+            .max_issuance(number)
+            .ok_or_else(Error::internal_error)?;
+        Ok(Some(BlockEconomicState {
+            number: number.to_string(),
+            block_reward: chain_state.block_reward(number).to_string(),
+            fees: chain_state.block_fees(&hash).map(|fees| fees.to_string()),
+            cellbase_capacity: cellbase_capacity.map(|capacity| capacity.to_string()),
+            max_issuance: max_issuance.to_string(),
+        }))
+    }
+}
+
+fn cell_output_with_out_point(
+    tx_hash: &H256,
+    index: u32,
+    output: &CellOutput,
+) -> CellOutputWithOutPoint {
+    CellOutputWithOutPoint {
+        out_point: OutPoint {
+            tx_hash: tx_hash.clone(),
+            index,
+        },
+        capacity: output.capacity.to_string(),
+        lock: output.lock.clone().into(),
     }
 }
@@ -0,0 +1,139 @@
+use crate::error::RPCError;
+use ckb_core::transaction::Transaction as CoreTransaction;
+use ckb_core::Cycle;
+use ckb_shared::shared::Shared;
+use ckb_shared::store::ChainStore;
+use ckb_traits::ChainProvider;
+use ckb_verification::{ScriptRole, ScriptVerifier};
+use jsonrpc_core::{Error, Result};
+use jsonrpc_derive::rpc;
+use jsonrpc_types::{DryRunResult, ScriptResult, Transaction};
+use std::convert::TryInto;
+
+#[rpc]
+pub trait ExperimentRpc {
+    // Resolved against the tip, or the pool too if `with_pool` is true, the transaction is
+    // neither added to the pool nor relayed
+    // curl -d '{"id": 2, "jsonrpc": "2.0", "method":"dry_run_transaction","params": [{"version":0, "deps":[], "inputs":[], "outputs":[], "witnesses":[]}, true]}' -H 'content-type:application/json' 'http://localhost:8114'
+    #[rpc(name = "dry_run_transaction")]
+    fn dry_run_transaction(
+        &self,
+        _tx: Transaction,
+        _with_pool: Option<bool>,
+    ) -> Result<DryRunResult>;
+}
+
+pub(crate) struct ExperimentRpcImpl<CS> {
+    pub shared: Shared<CS>,
+}
+
+impl<CS: ChainStore + 'static> ExperimentRpc for ExperimentRpcImpl<CS> {
+    fn dry_run_transaction(
+        &self,
+        tx: Transaction,
+        with_pool: Option<bool>,
+    ) -> Result<DryRunResult> {
+        let tx: CoreTransaction = tx.try_into().map_err(|_| Error::parse_error())?;
+        let rtx = {
+            let tx_pool = self.shared.tx_pool().lock();
+            let chain_state = self.shared.chain_state();
+            chain_state.resolve_tx_for_dry_run(&tx_pool, &tx, with_pool.unwrap_or(false))
+        }
+        .map_err(|err| RPCError::custom(RPCError::Invalid, err.to_string()))?;
+
+        // the scripts run without holding the locks
+        let max_cycles = self.shared.consensus().max_block_cycles();
+        let results = ScriptVerifier::new(&rtx).verify_each(max_cycles);
+        let cycles = results
+            .iter()
+            .map(|(_, result)| result.as_ref().ok())
+            .sum::<Option<Cycle>>();
+        let scripts = results
+            .into_iter()
+            .map(|(role, result)| {
+                let (script, index) = match role {
+                    ScriptRole::InputLock(index) => ("input_lock", index),
+                    ScriptRole::OutputType(index) => ("output_type", index),
+                };
+                let (cycles, error) = match result {
+                    Ok(cycles) => (Some(cycles.to_string()), None),
+                    Err(err) => (None, Some(err.to_string())),
+                };
+                ScriptResult {
+                    script: script.to_string(),
+                    index: index.to_string(),
+                    cycles,
+                    error,
+                }
+            })
+            .collect();
+        Ok(DryRunResult {
+            cycles: cycles.map(|cycles| cycles.to_string()),
+            scripts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_core::script::Script;
+    use ckb_core::transaction::{CellInput, CellOutput, OutPoint, TransactionBuilder};
+    use ckb_core::{capacity_bytes, Capacity};
+    use ckb_db::MemoryKeyValueDB;
+    use ckb_shared::shared::SharedBuilder;
+    use numext_fixed_hash::H256;
+
+    #[test]
+    fn test_dry_run_transaction() {
+        let shared = SharedBuilder::<MemoryKeyValueDB>::new().build().unwrap();
+        let rpc = ExperimentRpcImpl {
+            shared: shared.clone(),
+        };
+        let parent = TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(H256::zero(), 0), 0, vec![]))
+            .output(CellOutput::new(
+                capacity_bytes!(5000),
+                vec![],
+                Script::always_success(),
+                None,
+            ))
+            .build();
+        shared
+            .tx_pool()
+            .lock()
+            .enqueue_tx(None, Capacity::zero(), parent.clone());
+        // the input lock passes, the type script can't be found
+        let tx = TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(parent.hash(), 0), 0, vec![]))
+            .output(CellOutput::new(
+                capacity_bytes!(1000),
+                vec![],
+                Script::always_success(),
+                Some(Script::new(vec![], H256::zero())),
+            ))
+            .build();
+
+        // the input is only known to the pool
+        assert!(rpc
+            .dry_run_transaction(Transaction::from(&tx), None)
+            .is_err());
+        let result = rpc
+            .dry_run_transaction(Transaction::from(&tx), Some(true))
+            .unwrap();
+        assert_eq!(result.cycles, None);
+        assert_eq!(result.scripts.len(), 2);
+        assert_eq!(result.scripts[0].script, "input_lock");
+        assert_eq!(result.scripts[0].cycles, Some("0".to_string()));
+        assert_eq!(result.scripts[0].error, None);
+        assert_eq!(result.scripts[1].script, "output_type");
+        assert_eq!(result.scripts[1].index, "0");
+        assert_eq!(result.scripts[1].cycles, None);
+        assert!(result.scripts[1].error.is_some());
+        // nothing is added to the pool
+        assert!(!shared
+            .tx_pool()
+            .lock()
+            .contains_proposal_id(&tx.proposal_short_id()));
+    }
+}
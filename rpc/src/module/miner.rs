@@ -1,3 +1,4 @@
+use crate::error::RPCError;
 use ckb_chain::chain::ChainController;
 use ckb_core::block::Block as CoreBlock;
 use ckb_core::Cycle;
@@ -7,7 +8,9 @@ use ckb_protocol::RelayMessage;
 use ckb_shared::{shared::Shared, store::ChainStore};
 use ckb_sync::NetworkProtocol;
 use ckb_traits::ChainProvider;
-use ckb_verification::{HeaderResolverWrapper, HeaderVerifier, Verifier};
+use ckb_verification::{
+    Error as VerifyError, HeaderResolver, HeaderResolverWrapper, HeaderVerifier, PowError, Verifier,
+};
 use faketime::unix_time_as_millis;
 use flatbuffers::FlatBufferBuilder;
 use jsonrpc_core::{Error, Result};
@@ -28,11 +31,12 @@ pub trait MinerRpc {
         cycles_limit: Option<String>,
         bytes_limit: Option<String>,
         max_version: Option<u32>,
+        proposals_limit: Option<String>,
     ) -> Result<BlockTemplate>;
 
-    // curl -d '{"id": 2, "jsonrpc": "2.0", "method":"submit_block","params": [{"header":{}, "uncles":[], "transactions":[], "proposals":[]}]}' -H 'content-type:application/json' 'http://localhost:8114'
+    // curl -d '{"id": 2, "jsonrpc": "2.0", "method":"submit_block","params": ["1", {"header":{}, "uncles":[], "transactions":[], "proposals":[]}]}' -H 'content-type:application/json' 'http://localhost:8114'
     #[rpc(name = "submit_block")]
-    fn submit_block(&self, _work_id: String, _data: Block) -> Result<Option<H256>>;
+    fn submit_block(&self, work_id: String, data: Block) -> Result<Option<H256>>;
 }
 
 pub(crate) struct MinerRpcImpl<CS> {
@@ -48,6 +52,7 @@ impl<CS: ChainStore + 'static> MinerRpc for MinerRpcImpl<CS> {
         cycles_limit: Option<String>,
         bytes_limit: Option<String>,
         max_version: Option<u32>,
+        proposals_limit: Option<String>,
     ) -> Result<BlockTemplate> {
         let cycles_limit = match cycles_limit {
             Some(c) => Some(c.parse::<Cycle>().map_err(|_| Error::parse_error())?),
@@ -57,22 +62,54 @@ impl<CS: ChainStore + 'static> MinerRpc for MinerRpcImpl<CS> {
             Some(b) => Some(b.parse::<u64>().map_err(|_| Error::parse_error())?),
             None => None,
         };
+        let proposals_limit = match proposals_limit {
+            Some(p) => Some(p.parse::<u64>().map_err(|_| Error::parse_error())?),
+            None => None,
+        };
 
         self.block_assembler
-            .get_block_template(cycles_limit, bytes_limit, max_version)
+            .get_block_template(cycles_limit, bytes_limit, max_version, proposals_limit)
             .map_err(|_| Error::internal_error())
     }
 
-    fn submit_block(&self, _work_id: String, data: Block) -> Result<Option<H256>> {
+    fn submit_block(&self, work_id: String, data: Block) -> Result<Option<H256>> {
+        let parent_hash = self
+            .block_assembler
+            .get_work_parent_hash(&work_id)
+            .ok_or_else(|| {
+                RPCError::custom(
+                    RPCError::UnknownWork,
+                    format!("work {} is unknown or stale", work_id),
+                )
+            })?;
         let block: Arc<CoreBlock> = Arc::new(data.try_into().map_err(|_| Error::parse_error())?);
+        if block.header().parent_hash() != &parent_hash {
+            return Err(RPCError::custom(
+                RPCError::Invalid,
+                format!(
+                    "block doesn't extend {:#x} of work {}",
+                    parent_hash, work_id
+                ),
+            ));
+        }
         let resolver = HeaderResolverWrapper::new(block.header(), self.shared.clone());
-        let header_verify_ret = {
-            let chain_state = self.shared.chain_state().lock();
-            let header_verifier = HeaderVerifier::new(
-                &*chain_state,
-                Arc::clone(&self.shared.consensus().pow_engine()),
-            );
+        // the seal is checked in the verification pool, the rest needs the chain state
+        let pow_engine = self.shared.consensus().pow_engine();
+        let seal_valid = self
+            .shared
+            .verification_pool()
+            .install(|| pow_engine.verify_header(block.header()));
+        let header_verify_ret = if seal_valid {
+            // the parent may be on a side branch, walk its own ancestors for the median time
+            let median_time_context = self
+                .shared
+                .branch_median_time_context(resolver.parent().unwrap_or_else(|| block.header()));
+            let header_verifier = HeaderVerifier::new(median_time_context, Arc::clone(&pow_engine))
+                .dao_activation_number(self.shared.consensus().dao_activation_number())
+                .pow_verified();
             header_verifier.verify(&resolver)
+        } else {
+            Err(VerifyError::Pow(PowError::InvalidProof))
         };
         if header_verify_ret.is_ok() {
             let ret = self.chain.process_block(Arc::clone(&block));
@@ -88,7 +125,7 @@ impl<CS: ChainStore + 'static> MinerRpc for MinerRpcImpl<CS> {
                     .broadcast(NetworkProtocol::RELAY.into(), data);
                 Ok(Some(block.header().hash().clone()))
             } else {
-                let chain_state = self.shared.chain_state().lock();
+                let chain_state = self.shared.chain_state();
                 error!(target: "rpc", "submit_block process_block {:?}", ret);
                 error!(target: "rpc", "proposal table {}", serde_json::to_string(chain_state.proposal_ids().all()).unwrap());
                 Ok(None)
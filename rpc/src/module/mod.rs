@@ -1,13 +1,17 @@
 mod chain;
+mod experiment;
 mod miner;
 mod net;
 mod pool;
+mod subscription;
 mod test;
 mod trace;
 
 pub(crate) use self::chain::{ChainRpc, ChainRpcImpl};
+pub(crate) use self::experiment::{ExperimentRpc, ExperimentRpcImpl};
 pub(crate) use self::miner::{MinerRpc, MinerRpcImpl};
 pub(crate) use self::net::{NetworkRpc, NetworkRpcImpl};
 pub(crate) use self::pool::{PoolRpc, PoolRpcImpl};
+pub(crate) use self::subscription::{SubscriptionRpc, SubscriptionRpcImpl};
 pub(crate) use self::test::{IntegrationTestRpc, IntegrationTestRpcImpl};
 pub(crate) use self::trace::{TraceRpc, TraceRpcImpl};
@@ -1,8 +1,13 @@
 use build_info::{get_version, Version};
 use ckb_network::NetworkController;
+use ckb_shared::store::ChainStore;
+use ckb_sync::SyncSharedState;
+use faketime::unix_time_as_millis;
+use fnv::FnvHashMap;
 use jsonrpc_core::Result;
 use jsonrpc_derive::rpc;
-use jsonrpc_types::{Node, NodeAddress};
+use jsonrpc_types::{BannedAddress, ChainTip, Node, NodeAddress, SyncState};
+use std::sync::Arc;
 
 const MAX_ADDRS: usize = 50;
 
@@ -15,17 +20,36 @@ pub trait NetworkRpc {
     // curl -d '{"id": 2, "jsonrpc": "2.0", "method":"get_peers","params": []}' -H 'content-type:application/json' 'http://localhost:8114'
     #[rpc(name = "get_peers")]
     fn get_peers(&self) -> Result<Vec<Node>>;
+
+    // curl -d '{"id": 2, "jsonrpc": "2.0", "method":"sync_state","params": []}' -H 'content-type:application/json' 'http://localhost:8114'
+    #[rpc(name = "sync_state")]
+    fn sync_state(&self) -> Result<SyncState>;
+
+    // curl -d '{"id": 2, "jsonrpc": "2.0", "method":"get_banned_addresses","params": []}' -H 'content-type:application/json' 'http://localhost:8114'
+    #[rpc(name = "get_banned_addresses")]
+    fn get_banned_addresses(&self) -> Result<Vec<BannedAddress>>;
+
+    // curl -d '{"id": 2, "jsonrpc": "2.0", "method":"get_fork_info","params": []}' -H 'content-type:application/json' 'http://localhost:8114'
+    #[rpc(name = "get_fork_info")]
+    fn get_fork_info(&self) -> Result<Vec<ChainTip>>;
 }
 
-pub(crate) struct NetworkRpcImpl {
+pub(crate) struct NetworkRpcImpl<CS> {
     pub network_controller: NetworkController,
+    pub sync_shared_state: Arc<SyncSharedState<CS>>,
 }
 
-impl NetworkRpc for NetworkRpcImpl {
+impl<CS: ChainStore + 'static> NetworkRpc for NetworkRpcImpl<CS> {
     fn local_node_info(&self) -> Result<Node> {
+        let shared = self.sync_shared_state.shared();
+        let tip_number = shared.chain_state().tip_number();
         Ok(Node {
             version: get_version!().to_string(),
             is_outbound: None,
+            tx_index_cutoff: shared
+                .store_config()
+                .tx_index_cutoff(tip_number)
+                .map(|cutoff| cutoff.to_string()),
             node_id: self.network_controller.node_id(),
             addresses: self
                 .network_controller
@@ -42,6 +66,7 @@ impl NetworkRpc for NetworkRpcImpl {
             .into_iter()
             .map(|(peer_id, peer, addresses)| Node {
                 is_outbound: Some(peer.is_outbound()),
+                tx_index_cutoff: None,
                 version: peer
                     .identify_info
                     .map(|info| info.client_version)
@@ -58,4 +83,83 @@ impl NetworkRpc for NetworkRpcImpl {
             })
             .collect())
     }
+
+    fn sync_state(&self) -> Result<SyncState> {
+        let progress = self.sync_shared_state.sync_progress();
+        Ok(SyncState {
+            ibd: progress.is_initial_block_download,
+            best_known_block_number: progress.header_tip.to_string(),
+            tip_block_number: progress.block_tip.to_string(),
+            progress: progress.percentage(),
+        })
+    }
+
+    fn get_banned_addresses(&self) -> Result<Vec<BannedAddress>> {
+        Ok(self
+            .network_controller
+            .list_banned()
+            .into_iter()
+            .map(|banned| BannedAddress {
+                address: banned.ip.to_string(),
+                ban_until: (banned.ban_until.as_secs() * 1000).to_string(),
+                ban_reason: banned.reason,
+                created_at: (banned.created_at.as_secs() * 1000).to_string(),
+            })
+            .collect())
+    }
+
+    fn get_fork_info(&self) -> Result<Vec<ChainTip>> {
+        let (tip_hash, tip_number, tip_total_difficulty) = {
+            let chain_state = self.sync_shared_state.chain_state();
+            (
+                chain_state.tip_hash(),
+                chain_state.tip_number(),
+                chain_state.total_difficulty().clone(),
+            )
+        };
+        let node_ids: FnvHashMap<_, _> = self
+            .network_controller
+            .connected_peers()
+            .into_iter()
+            .map(|(peer_id, peer, _)| (peer.session_id, peer_id.to_base58()))
+            .collect();
+        let now = unix_time_as_millis();
+
+        let mut tips = vec![ChainTip {
+            hash: tip_hash.clone(),
+            number: tip_number.to_string(),
+            total_difficulty: tip_total_difficulty,
+            branch_length: "0".to_string(),
+            age: "0".to_string(),
+            first_seen_peer: None,
+            is_main_chain: true,
+        }];
+        for tip in self.sync_shared_state.chain_tips() {
+            let first_seen_peer = node_ids.get(&tip.first_seen_peer).cloned();
+            let age = now.saturating_sub(tip.first_seen_at).to_string();
+            if tip.hash == tip_hash {
+                tips[0].age = age;
+                tips[0].first_seen_peer = first_seen_peer;
+                continue;
+            }
+            let fork_point = match self.sync_shared_state.fork_point(&tip.hash) {
+                Some(fork_point) => fork_point,
+                None => continue,
+            };
+            // a main chain block behind the tip, not a competing one
+            if fork_point.hash() == tip.hash {
+                continue;
+            }
+            tips.push(ChainTip {
+                hash: tip.hash,
+                number: tip.number.to_string(),
+                total_difficulty: tip.total_difficulty,
+                branch_length: (tip.number - fork_point.number()).to_string(),
+                age,
+                first_seen_peer,
+                is_main_chain: false,
+            });
+        }
+        Ok(tips)
+    }
 }
@@ -1,15 +1,21 @@
 use crate::error::RPCError;
 use ckb_core::transaction::Transaction as CoreTransaction;
-use ckb_network::NetworkController;
+use ckb_core::Cycle;
+use ckb_network::{NetworkController, PeerIndex};
 use ckb_protocol::RelayMessage;
+use ckb_shared::chain_state::ChainState;
 use ckb_shared::shared::Shared;
 use ckb_shared::store::ChainStore;
-use ckb_sync::NetworkProtocol;
+use ckb_shared::tx_pool::{CellResolution as CoreCellResolution, TxPool, TxStatus};
+use ckb_shared::tx_verify_queue::TxVerification;
+use ckb_sync::{NetworkProtocol, RELAY_FEATURE_STEM};
 use flatbuffers::FlatBufferBuilder;
 use jsonrpc_core::{Error, Result};
 use jsonrpc_derive::rpc;
-use jsonrpc_types::Transaction;
+use jsonrpc_types::{CellResolution, Transaction, TransactionStatus, TxRejection};
 use numext_fixed_hash::H256;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
 use std::convert::TryInto;
 
 #[rpc]
@@ -17,33 +23,164 @@ pub trait PoolRpc {
     // curl -d '{"id": 2, "jsonrpc": "2.0", "method":"send_transaction","params": [{"version":2, "deps":[], "inputs":[], "outputs":[]}]}' -H 'content-type:application/json' 'http://localhost:8114'
     #[rpc(name = "send_transaction")]
     fn send_transaction(&self, _tx: Transaction) -> Result<H256>;
+
+    // curl -d '{"id": 2, "jsonrpc": "2.0", "method":"estimate_fee_rate","params": ["3"]}' -H 'content-type:application/json' 'http://localhost:8114'
+    #[rpc(name = "estimate_fee_rate")]
+    fn estimate_fee_rate(&self, _target_blocks: String) -> Result<Option<String>>;
+
+    // curl -d '{"id": 2, "jsonrpc": "2.0", "method":"get_transaction_rejection","params": ["0xa093b2e820f3f2202a6802314ece2eee3f863b177b3abe11bf16b1588152d31b"]}' -H 'content-type:application/json' 'http://localhost:8114'
+    #[rpc(name = "get_transaction_rejection")]
+    fn get_transaction_rejection(&self, _hash: H256) -> Result<Option<TxRejection>>;
+
+    // curl -d '{"id": 2, "jsonrpc": "2.0", "method":"get_transaction_status","params": ["0xa093b2e820f3f2202a6802314ece2eee3f863b177b3abe11bf16b1588152d31b"]}' -H 'content-type:application/json' 'http://localhost:8114'
+    #[rpc(name = "get_transaction_status")]
+    fn get_transaction_status(&self, _hash: H256) -> Result<Option<TransactionStatus>>;
 }
 
 pub(crate) struct PoolRpcImpl<CS> {
     pub network_controller: NetworkController,
     pub shared: Shared<CS>,
+    pub stem_hops: u8,
 }
 
 impl<CS: ChainStore + 'static> PoolRpc for PoolRpcImpl<CS> {
     fn send_transaction(&self, tx: Transaction) -> Result<H256> {
         let tx: CoreTransaction = tx.try_into().map_err(|_| Error::parse_error())?;
 
-        let result = {
-            let chain_state = self.shared.chain_state().lock();
-            chain_state.add_tx_to_pool(tx.clone())
-        };
+        // no lock is held while waiting for the scripts
+        let result = self
+            .shared
+            .add_local_tx_to_pool(tx.clone())
+            .and_then(TxVerification::wait)
+            .map_err(|err| {
+                if err.is_unresolvable() {
+                    let tx_pool = self.shared.tx_pool().lock();
+                    let chain_state = self.shared.chain_state();
+                    (err, Some(cell_resolutions(&chain_state, &tx_pool, &tx)))
+                } else {
+                    (err, None)
+                }
+            });
 
         match result {
             Ok(cycles) => {
-                let fbb = &mut FlatBufferBuilder::new();
-                let message = RelayMessage::build_transaction(fbb, &tx, cycles);
+                self.relay_transaction(&tx, cycles);
+                Ok(tx.hash())
+            }
+            Err((e, Some(resolutions))) => Err(RPCError::custom_with_data(
+                RPCError::Invalid,
+                e.to_string(),
+                serde_json::to_value(resolutions).expect("serialize cell resolutions"),
+            )),
+            Err((e, None)) => Err(RPCError::custom(RPCError::Invalid, e.to_string())),
+        }
+    }
+
+    fn estimate_fee_rate(&self, target_blocks: String) -> Result<Option<String>> {
+        let target_blocks = target_blocks
+            .parse::<usize>()
+            .map_err(|_| Error::parse_error())?;
+        Ok(self
+            .shared
+            .tx_pool()
+            .lock()
+            .estimate_fee_rate(target_blocks)
+            .map(|fee_rate| fee_rate.to_string()))
+    }
+
+    fn get_transaction_rejection(&self, hash: H256) -> Result<Option<TxRejection>> {
+        Ok(self
+            .shared
+            .tx_pool()
+            .lock()
+            .get_rejected(&hash)
+            .map(|rejected| TxRejection {
+                reason: rejected.reason.to_string(),
+                bad_tx: rejected.reason.is_bad_tx(),
+                timestamp: rejected.timestamp.to_string(),
+            }))
+    }
+
+    fn get_transaction_status(&self, hash: H256) -> Result<Option<TransactionStatus>> {
+        Ok(self.shared.tx_status(&hash).map(|status| {
+            let (status, block_hash) = match status {
+                TxStatus::Pending => ("pending", None),
+                TxStatus::Proposed => ("proposed", None),
+                TxStatus::Committed { block_hash } => ("committed", Some(block_hash)),
+                TxStatus::Reorged { block_hash } => ("reorged", Some(block_hash)),
+            };
+            TransactionStatus {
+                status: status.to_string(),
+                block_hash,
+            }
+        }))
+    }
+}
+
+impl<CS: ChainStore + 'static> PoolRpcImpl<CS> {
+    // Send the transaction to one random outbound peer supporting stem relay, fall back to
+    // broadcast if stem relay is disabled or there is no outbound peer. If the stem gets
+    // lost the transaction is still broadcast by the local transactions rebroadcast.
+    fn relay_transaction(&self, tx: &CoreTransaction, cycles: Cycle) {
+        let stem_peer = if self.stem_hops > 0 {
+            let outbound_peers: Vec<PeerIndex> = self
+                .network_controller
+                .connected_peers()
+                .into_iter()
+                .filter(|(_, peer, _)| {
+                    peer.is_outbound()
+                        && peer
+                            .has_protocol_feature(NetworkProtocol::RELAY.into(), RELAY_FEATURE_STEM)
+                })
+                .map(|(_, peer, _)| peer.session_id)
+                .collect();
+            outbound_peers.choose(&mut thread_rng()).cloned()
+        } else {
+            None
+        };
+
+        let fbb = &mut FlatBufferBuilder::new();
+        match stem_peer {
+            Some(peer) => {
+                let message =
+                    RelayMessage::build_stem_transaction(fbb, tx, cycles, self.stem_hops - 1);
+                fbb.finish(message, None);
+                self.network_controller.send_message_to(
+                    peer,
+                    NetworkProtocol::RELAY.into(),
+                    fbb.finished_data().into(),
+                );
+            }
+            None => {
+                let message = RelayMessage::build_transaction(fbb, tx, cycles);
                 fbb.finish(message, None);
-                let data = fbb.finished_data().into();
                 self.network_controller
-                    .broadcast(NetworkProtocol::RELAY.into(), data);
-                Ok(tx.hash())
+                    .broadcast(NetworkProtocol::RELAY.into(), fbb.finished_data().into());
             }
-            Err(e) => Err(RPCError::custom(RPCError::Invalid, e.to_string())),
         }
     }
 }
+
+pub(crate) fn cell_resolutions<CS: ChainStore>(
+    chain_state: &ChainState<CS>,
+    tx_pool: &TxPool,
+    tx: &CoreTransaction,
+) -> Vec<CellResolution> {
+    chain_state
+        .cell_resolution_details(tx_pool, tx)
+        .into_iter()
+        .map(|detail| {
+            let (status, spent_by) = match detail.resolution {
+                CoreCellResolution::Live => ("live", None),
+                CoreCellResolution::Dead(spent_by) => ("dead", spent_by),
+                CoreCellResolution::Unknown => ("unknown", None),
+            };
+            CellResolution {
+                out_point: detail.out_point.into(),
+                dep: detail.is_dep,
+                status: status.to_string(),
+                spent_by,
+            }
+        })
+        .collect()
+}
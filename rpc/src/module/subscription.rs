@@ -0,0 +1,283 @@
+use ckb_core::transaction::ProposalShortId;
+use ckb_notify::{ChainEvent, NotifyController};
+use ckb_shared::shared::Shared;
+use ckb_shared::store::ChainStore;
+use ckb_shared::tx_pool::TxPoolEvent;
+use ckb_util::RwLock;
+use crossbeam_channel::select;
+use futures::executor::{self, Notify};
+use futures::Async;
+use jsonrpc_core::{Error, Result};
+use jsonrpc_derive::rpc;
+use jsonrpc_pubsub::typed::{Sink, Subscriber};
+use jsonrpc_pubsub::{Session, SubscriptionId};
+use jsonrpc_types::{ChainFork, Header, Transaction};
+use log::warn;
+use numext_fixed_hash::H256;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::thread;
+
+const SUBSCRIBER_NAME: &str = "RpcSubscription";
+
+// The notifications are polled once on the publisher thread, nothing waits for the wakeups
+struct NoopNotify;
+
+impl Notify for NoopNotify {
+    fn notify(&self, _id: usize) {}
+}
+
+static NOOP_NOTIFY: NoopNotify = NoopNotify;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+    /// The header of every new tip
+    NewTipHeader,
+    /// Every transaction accepted into the pool
+    NewTransaction,
+    /// The replaced main chain blocks when the tip moves to another fork
+    Fork,
+}
+
+// The notifications are the JSON encoded `Header`, `Transaction` and `ChainFork`
+#[rpc]
+pub trait SubscriptionRpc {
+    type Metadata;
+
+    // ws://localhost:18114 {"id": 2, "jsonrpc": "2.0", "method": "subscribe", "params": ["new_tip_header"]}
+    #[pubsub(subscription = "subscribe", subscribe, name = "subscribe")]
+    fn subscribe(&self, meta: Self::Metadata, subscriber: Subscriber<String>, topic: Topic);
+
+    #[pubsub(subscription = "subscribe", unsubscribe, name = "unsubscribe")]
+    fn unsubscribe(&self, meta: Option<Self::Metadata>, id: SubscriptionId) -> Result<bool>;
+}
+
+// The sinks are kept along with the session which subscribed, only it can unsubscribe them
+type Subscribers = HashMap<Topic, HashMap<SubscriptionId, (Weak<Session>, Sink<String>)>>;
+
+pub(crate) struct SubscriptionRpcImpl {
+    subscribers: Arc<RwLock<Subscribers>>,
+}
+
+impl SubscriptionRpcImpl {
+    /// Push the chain and pool events to the subscribers from a background thread
+    pub fn new<CS: ChainStore + 'static>(notify: &NotifyController, shared: Shared<CS>) -> Self {
+        let chain_events = notify.subscribe_chain_event(SUBSCRIBER_NAME);
+        let pool_events = shared.tx_pool().lock().subscribe_events(SUBSCRIBER_NAME);
+        let subscribers = Arc::new(RwLock::new(Subscribers::default()));
+
+        let publisher = Arc::clone(&subscribers);
+        thread::Builder::new()
+            .name(SUBSCRIBER_NAME.to_string())
+            .spawn(move || loop {
+                select! {
+                    recv(chain_events) -> msg => match msg {
+                        Ok(event) => publish_chain_event(&publisher, &event),
+                        Err(_) => break,
+                    },
+                    recv(pool_events) -> msg => match msg {
+                        Ok(TxPoolEvent::Added(tx_hash)) => {
+                            publish_new_transaction(&publisher, &shared, &tx_hash)
+                        }
+                        Ok(_) => {}
+                        Err(_) => break,
+                    },
+                }
+            })
+            .expect("Start subscription publisher failed");
+
+        SubscriptionRpcImpl { subscribers }
+    }
+}
+
+fn publish_chain_event(subscribers: &RwLock<Subscribers>, event: &ChainEvent) {
+    let ChainEvent::Reorg {
+        attached,
+        detached,
+        common_ancestor,
+    } = event;
+    if let Some(tip) = attached.last() {
+        publish(
+            subscribers,
+            Topic::NewTipHeader,
+            &Header::from(tip.header()),
+        );
+    }
+    if !detached.is_empty() {
+        let fork = ChainFork {
+            attached: attached.iter().map(|block| block.header().into()).collect(),
+            detached: detached.iter().map(|block| block.header().into()).collect(),
+            common_ancestor: common_ancestor.clone(),
+        };
+        publish(subscribers, Topic::Fork, &fork);
+    }
+}
+
+fn publish_new_transaction<CS: ChainStore>(
+    subscribers: &RwLock<Subscribers>,
+    shared: &Shared<CS>,
+    tx_hash: &H256,
+) {
+    let tx = shared
+        .tx_pool()
+        .lock()
+        .get_tx(&ProposalShortId::from_tx_hash(tx_hash));
+    if let Some(tx) = tx {
+        publish(subscribers, Topic::NewTransaction, &Transaction::from(&tx));
+    }
+}
+
+// The sinks of the closed sessions are dropped, so are the ones of the clients which don't
+// read their notifications fast enough, one slow client can't hold back the others.
+fn publish<T: Serialize>(subscribers: &RwLock<Subscribers>, topic: Topic, message: &T) {
+    let mut subscribers = subscribers.write();
+    let sinks = match subscribers.get_mut(&topic) {
+        Some(sinks) if !sinks.is_empty() => sinks,
+        _ => return,
+    };
+    let message = match serde_json::to_string(message) {
+        Ok(message) => message,
+        Err(err) => {
+            warn!(target: "rpc", "failed to encode the {:?} notification: {}", topic, err);
+            return;
+        }
+    };
+    sinks.retain(|id, (_, sink)| {
+        let mut send = executor::spawn(sink.notify(Ok(message.clone())));
+        match send.poll_future_notify(&&NOOP_NOTIFY, 0) {
+            Ok(Async::Ready(_)) => true,
+            Ok(Async::NotReady) => {
+                warn!(
+                    target: "rpc",
+                    "drop the {:?} subscription {:?}, the client falls behind", topic, id
+                );
+                false
+            }
+            Err(_) => false,
+        }
+    });
+}
+
+// 128 random bits, so that the subscription ids of the other clients can't be guessed
+fn new_subscription_id() -> SubscriptionId {
+    SubscriptionId::String(format!("{:#x}", rand::random::<u128>()))
+}
+
+impl SubscriptionRpc for SubscriptionRpcImpl {
+    type Metadata = Arc<Session>;
+
+    fn subscribe(&self, meta: Self::Metadata, subscriber: Subscriber<String>, topic: Topic) {
+        let id = new_subscription_id();
+        if let Ok(sink) = subscriber.assign_id(id.clone()) {
+            self.subscribers
+                .write()
+                .entry(topic)
+                .or_insert_with(HashMap::new)
+                .insert(id, (Arc::downgrade(&meta), sink));
+        }
+    }
+
+    // `meta` is None when the session is closed and drops its subscriptions
+    fn unsubscribe(&self, meta: Option<Self::Metadata>, id: SubscriptionId) -> Result<bool> {
+        let is_owner = |owner: &Weak<Session>| match meta {
+            Some(ref session) => owner
+                .upgrade()
+                .map_or(false, |owner| Arc::ptr_eq(&owner, session)),
+            None => true,
+        };
+        let mut removed = 0;
+        for sinks in self.subscribers.write().values_mut() {
+            if sinks.get(&id).map_or(false, |(owner, _)| is_owner(owner)) {
+                sinks.remove(&id);
+                removed += 1;
+            }
+        }
+        if removed > 0 {
+            Ok(true)
+        } else {
+            Err(Error::invalid_params("invalid subscription id"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::sync::mpsc;
+    use futures::{Future, Stream};
+
+    fn new_session() -> Arc<Session> {
+        let (sender, _) = mpsc::channel(1);
+        Arc::new(Session::new(sender))
+    }
+
+    fn subscribe(
+        rpc: &SubscriptionRpcImpl,
+        session: &Arc<Session>,
+        topic: Topic,
+    ) -> (SubscriptionId, mpsc::Receiver<String>) {
+        let (subscriber, id, notifications) = Subscriber::new_test("subscribe");
+        rpc.subscribe(Arc::clone(session), subscriber, topic);
+        (id.wait().unwrap().unwrap(), notifications)
+    }
+
+    fn new_rpc() -> SubscriptionRpcImpl {
+        SubscriptionRpcImpl {
+            subscribers: Arc::new(RwLock::new(Subscribers::default())),
+        }
+    }
+
+    #[test]
+    fn test_subscription_ids_are_random() {
+        let rpc = new_rpc();
+        let session = new_session();
+        let (id1, _notifications1) = subscribe(&rpc, &session, Topic::NewTipHeader);
+        let (id2, _notifications2) = subscribe(&rpc, &session, Topic::NewTipHeader);
+        assert_ne!(id1, id2);
+        match id1 {
+            SubscriptionId::String(id) => assert!(id.len() > 20),
+            _ => panic!("subscription id should be a hex string"),
+        }
+    }
+
+    #[test]
+    fn test_unsubscribe_by_the_owner_only() {
+        let rpc = new_rpc();
+        let owner = new_session();
+        let (id, _notifications) = subscribe(&rpc, &owner, Topic::Fork);
+
+        assert!(rpc.unsubscribe(Some(new_session()), id.clone()).is_err());
+        assert_eq!(
+            rpc.unsubscribe(Some(Arc::clone(&owner)), id.clone()),
+            Ok(true)
+        );
+        assert!(rpc.unsubscribe(Some(owner), id.clone()).is_err());
+
+        // the closed sessions drop their subscriptions without the metadata
+        let (id, _notifications) = subscribe(&rpc, &new_session(), Topic::Fork);
+        assert_eq!(rpc.unsubscribe(None, id), Ok(true));
+    }
+
+    #[test]
+    fn test_slow_subscriber_is_dropped() {
+        let rpc = new_rpc();
+        let session = new_session();
+        let (fast_id, fast) = subscribe(&rpc, &session, Topic::NewTransaction);
+        let (slow_id, _slow) = subscribe(&rpc, &session, Topic::NewTransaction);
+
+        let mut fast = fast.wait();
+        for i in 0..3 {
+            publish(&rpc.subscribers, Topic::NewTransaction, &i);
+            let notification = fast.next().unwrap().unwrap();
+            assert!(notification.contains(&format!("\"result\":\"{}\"", i)));
+        }
+
+        let subscribers = rpc.subscribers.read();
+        let sinks = &subscribers[&Topic::NewTransaction];
+        assert!(sinks.contains_key(&fast_id));
+        assert!(!sinks.contains_key(&slow_id));
+    }
+}
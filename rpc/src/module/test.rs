@@ -1,4 +1,5 @@
 use ckb_core::transaction::Transaction as CoreTransaction;
+use ckb_core::Capacity;
 use ckb_network::NetworkController;
 use ckb_shared::shared::Shared;
 use ckb_shared::store::ChainStore;
@@ -34,9 +35,15 @@ impl<CS: ChainStore + 'static> IntegrationTestRpc for IntegrationTestRpcImpl<CS>
 
     fn enqueue_test_transaction(&self, tx: Transaction) -> Result<H256> {
         let tx: CoreTransaction = tx.try_into().map_err(|_| Error::parse_error())?;
-        let mut chain_state = self.shared.chain_state().lock();
         let tx_hash = tx.hash();
-        chain_state.mut_tx_pool().enqueue_tx(None, tx);
+        let mut tx_pool = self.shared.tx_pool().lock();
+        let chain_state = self.shared.chain_state();
+        // the pool evicts and replaces by fee rate, an unknown fee counts as zero
+        let fee = chain_state
+            .resolve_tx_from_pending_and_staging(&tx, &tx_pool)
+            .fee()
+            .unwrap_or_else(|_| Capacity::zero());
+        tx_pool.enqueue_tx(None, fee, tx);
         Ok(tx_hash)
     }
 }
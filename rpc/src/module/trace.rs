@@ -1,10 +1,14 @@
+use super::pool::cell_resolutions;
 use ckb_core::transaction::Transaction as CoreTransaction;
 use ckb_network::NetworkController;
+use ckb_shared::block_stage::LATENCY_BUCKETS;
 use ckb_shared::shared::Shared;
 use ckb_shared::store::ChainStore;
+use ckb_shared::tx_verify_queue::TxVerification;
 use jsonrpc_core::{Error, Result};
 use jsonrpc_derive::rpc;
-use jsonrpc_types::{Transaction, TxTrace};
+use jsonrpc_types::{BlockStageLatency, CellResolution, Transaction, TxTrace};
+use log::debug;
 use numext_fixed_hash::H256;
 use std::convert::TryInto;
 
@@ -15,6 +19,12 @@ pub trait TraceRpc {
 
     #[rpc(name = "get_transaction_trace")]
     fn get_transaction_trace(&self, _hash: H256) -> Result<Option<Vec<TxTrace>>>;
+
+    #[rpc(name = "get_cell_resolutions")]
+    fn get_cell_resolutions(&self, _tx: Transaction) -> Result<Vec<CellResolution>>;
+
+    #[rpc(name = "get_block_stage_latencies")]
+    fn get_block_stage_latencies(&self) -> Result<Vec<BlockStageLatency>>;
 }
 
 pub(crate) struct TraceRpcImpl<CS> {
@@ -26,14 +36,51 @@ impl<CS: ChainStore + 'static> TraceRpc for TraceRpcImpl<CS> {
     fn trace_transaction(&self, tx: Transaction) -> Result<H256> {
         let tx: CoreTransaction = tx.try_into().map_err(|_| Error::parse_error())?;
         let tx_hash = tx.hash();
-        let mut chain_state = self.shared.chain_state().lock();
-        chain_state.mut_tx_pool().trace_tx(tx);
+        self.shared.tx_pool().lock().trace_tx(&tx_hash);
+        // a rejection is recorded in the trace too
+        let result = self
+            .shared
+            .add_local_tx_to_pool(tx)
+            .and_then(TxVerification::wait);
+        if let Err(err) = result {
+            debug!(target: "rpc", "traced tx {:#x} is not added: {}", tx_hash, err);
+        }
         Ok(tx_hash)
     }
 
     fn get_transaction_trace(&self, hash: H256) -> Result<Option<Vec<TxTrace>>> {
-        let chain_state = self.shared.chain_state().lock();
-        let tx_pool = chain_state.tx_pool();
+        let tx_pool = self.shared.tx_pool().lock();
         Ok(tx_pool.get_tx_traces(&hash).cloned())
     }
+
+    fn get_cell_resolutions(&self, tx: Transaction) -> Result<Vec<CellResolution>> {
+        let tx: CoreTransaction = tx.try_into().map_err(|_| Error::parse_error())?;
+        let tx_pool = self.shared.tx_pool().lock();
+        let chain_state = self.shared.chain_state();
+        Ok(cell_resolutions(&chain_state, &tx_pool, &tx))
+    }
+
+    fn get_block_stage_latencies(&self) -> Result<Vec<BlockStageLatency>> {
+        let bucket_bounds: Vec<String> = LATENCY_BUCKETS
+            .iter()
+            .map(|bound| bound.to_string())
+            .collect();
+        Ok(self
+            .shared
+            .block_stage_metrics()
+            .snapshot()
+            .into_iter()
+            .map(|(stage, histogram)| BlockStageLatency {
+                stage: format!("{:?}", stage),
+                bucket_bounds: bucket_bounds.clone(),
+                buckets: histogram
+                    .buckets
+                    .iter()
+                    .map(|count| count.to_string())
+                    .collect(),
+                count: histogram.count.to_string(),
+                total_millis: histogram.total_millis.to_string(),
+            })
+            .collect())
+    }
 }
@@ -1,20 +1,28 @@
+use crate::auth::Authenticator;
 use crate::config::Config;
+use crate::limits::RequestLimits;
 use crate::module::{
-    ChainRpc, ChainRpcImpl, IntegrationTestRpc, IntegrationTestRpcImpl, MinerRpc, MinerRpcImpl,
-    NetworkRpc, NetworkRpcImpl, PoolRpc, PoolRpcImpl, TraceRpc, TraceRpcImpl,
+    ChainRpc, ChainRpcImpl, ExperimentRpc, ExperimentRpcImpl, IntegrationTestRpc,
+    IntegrationTestRpcImpl, MinerRpc, MinerRpcImpl, NetworkRpc, NetworkRpcImpl, PoolRpc,
+    PoolRpcImpl, SubscriptionRpc, SubscriptionRpcImpl, TraceRpc, TraceRpcImpl,
 };
 use ckb_chain::chain::ChainController;
 use ckb_miner::BlockAssemblerController;
 use ckb_network::NetworkController;
+use ckb_notify::NotifyController;
 use ckb_shared::shared::Shared;
 use ckb_shared::store::ChainStore;
-use jsonrpc_core::IoHandler;
+use ckb_sync::SyncSharedState;
+use jsonrpc_core::MetaIoHandler;
 use jsonrpc_http_server::{Server, ServerBuilder};
-use jsonrpc_server_utils::cors::AccessControlAllowOrigin;
+use jsonrpc_pubsub::{PubSubHandler, Session};
+use jsonrpc_server_utils::cors::{AccessControlAllowOrigin, Origin};
 use jsonrpc_server_utils::hosts::DomainsValidation;
+use std::sync::Arc;
 
 pub struct RpcServer {
     server: Server,
+    ws_server: Option<jsonrpc_ws_server::Server>,
 }
 
 impl RpcServer {
@@ -24,11 +32,14 @@ impl RpcServer {
         shared: Shared<CS>,
         chain: ChainController,
         block_assembler: BlockAssemblerController,
+        sync_shared_state: Arc<SyncSharedState<CS>>,
+        notify: &NotifyController,
     ) -> RpcServer
     where
         CS: ChainStore,
     {
-        let mut io = IoHandler::new();
+        let mut io: MetaIoHandler<(), RequestLimits> =
+            MetaIoHandler::with_middleware(RequestLimits::new(&config));
 
         if config.chain_enable() {
             io.extend_with(
@@ -44,6 +55,7 @@ impl RpcServer {
                 PoolRpcImpl {
                     network_controller: network_controller.clone(),
                     shared: shared.clone(),
+                    stem_hops: sync_shared_state.stem_hops(),
                 }
                 .to_delegate(),
             );
@@ -65,6 +77,7 @@ impl RpcServer {
             io.extend_with(
                 NetworkRpcImpl {
                     network_controller: network_controller.clone(),
+                    sync_shared_state,
                 }
                 .to_delegate(),
             );
@@ -80,23 +93,77 @@ impl RpcServer {
             );
         }
 
+        if config.experiment_enable() {
+            io.extend_with(
+                ExperimentRpcImpl {
+                    shared: shared.clone(),
+                }
+                .to_delegate(),
+            );
+        }
+
         if config.integration_test_enable() {
             io.extend_with(
                 IntegrationTestRpcImpl {
                     network_controller,
-                    shared,
+                    shared: shared.clone(),
                 }
                 .to_delegate(),
             );
         }
 
-        let server = ServerBuilder::new(io)
-            .cors(DomainsValidation::AllowOnly(vec![
+        let ws_server = config.ws_listen_address.as_ref().map(|ws_listen_address| {
+            let mut pubsub_io = PubSubHandler::new(MetaIoHandler::default());
+            pubsub_io.extend_with(SubscriptionRpcImpl::new(notify, shared).to_delegate());
+            let mut builder = jsonrpc_ws_server::ServerBuilder::with_meta_extractor(
+                pubsub_io,
+                |context: &jsonrpc_ws_server::RequestContext| {
+                    Arc::new(Session::new(context.sender()))
+                },
+            );
+            // `*` allows any origin, the handshakes are checked against the others
+            if let Some(origins) = config
+                .cors_allowed_origins
+                .as_ref()
+                .filter(|origins| !origins.iter().any(|origin| origin == "*"))
+            {
+                builder = builder.allowed_origins(DomainsValidation::AllowOnly(
+                    origins
+                        .iter()
+                        .map(|origin| Origin::from(origin.as_str()))
+                        .collect(),
+                ));
+            }
+            if let Some(ref auth) = config.auth {
+                builder = builder.request_middleware(Authenticator::new(auth));
+            }
+            builder
+                .start(
+                    &ws_listen_address
+                        .parse()
+                        .expect("config ws_listen_address parsed"),
+                )
+                .expect("Jsonrpc WebSocket initialize")
+        });
+
+        let cors = match config.cors_allowed_origins {
+            Some(ref origins) => origins
+                .iter()
+                .map(|origin| AccessControlAllowOrigin::from(origin.as_str()))
+                .collect(),
+            None => vec![
                 AccessControlAllowOrigin::Null,
                 AccessControlAllowOrigin::Any,
-            ]))
+            ],
+        };
+        let mut builder = ServerBuilder::new(io)
+            .cors(DomainsValidation::AllowOnly(cors))
             .threads(config.threads.unwrap_or_else(num_cpus::get))
-            .max_request_body_size(config.max_request_body_size)
+            .max_request_body_size(config.max_request_body_size);
+        if let Some(ref auth) = config.auth {
+            builder = builder.request_middleware(Authenticator::new(auth));
+        }
+        let server = builder
             .start_http(
                 &config
                     .listen_address
@@ -105,10 +172,13 @@ impl RpcServer {
             )
             .expect("Jsonrpc initialize");
 
-        RpcServer { server }
+        RpcServer { server, ws_server }
     }
 
     pub fn close(self) {
-        self.server.close()
+        self.server.close();
+        if let Some(ws_server) = self.ws_server {
+            ws_server.close();
+        }
     }
 }
@@ -3,6 +3,7 @@ mod syscalls;
 mod verify;
 
 use ckb_vm::Error as VMInternalError;
+use std::fmt;
 
 pub use crate::verify::TransactionScriptsVerifier;
 
@@ -15,3 +16,19 @@ pub enum ScriptError {
     VMError(VMInternalError),
     ExceededMaximumCycles,
 }
+
+/// The script of a transaction, by the index of the cell it guards
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum ScriptRole {
+    InputLock(usize),
+    OutputType(usize),
+}
+
+impl fmt::Display for ScriptRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptRole::InputLock(index) => write!(f, "input {}", index),
+            ScriptRole::OutputType(index) => write!(f, "output {}", index),
+        }
+    }
+}
@@ -1,10 +1,11 @@
 use crate::{
     cost_model::instruction_cycles,
     syscalls::{build_tx, Debugger, LoadCell, LoadCellByField, LoadInputByField, LoadTx},
-    ScriptError,
+    ScriptError, ScriptRole,
 };
 use ckb_core::cell::ResolvedTransaction;
 use ckb_core::script::{Script, ALWAYS_SUCCESS_HASH};
+use ckb_core::script_cache::ScriptHashCache;
 use ckb_core::transaction::{CellInput, CellOutput};
 use ckb_core::Cycle;
 use ckb_vm::{DefaultCoreMachine, DefaultMachineBuilder, SparseMemory, SupportMachine};
@@ -29,11 +30,26 @@ pub struct TransactionScriptsVerifier<'a> {
 
 impl<'a> TransactionScriptsVerifier<'a> {
     pub fn new(rtx: &'a ResolvedTransaction) -> TransactionScriptsVerifier<'a> {
+        Self::build(rtx, None)
+    }
+
+    /// Same as `new`, but the hashes of the dep cells are looked up in `hash_cache`
+    pub fn with_hash_cache(
+        rtx: &'a ResolvedTransaction,
+        hash_cache: &ScriptHashCache,
+    ) -> TransactionScriptsVerifier<'a> {
+        Self::build(rtx, Some(hash_cache))
+    }
+
+    fn build(
+        rtx: &'a ResolvedTransaction,
+        hash_cache: Option<&ScriptHashCache>,
+    ) -> TransactionScriptsVerifier<'a> {
         let dep_cells: Vec<&'a CellOutput> = rtx
             .dep_cells
             .iter()
             .map(|cell| {
-                &cell
+                &*cell
                     .get_live_output()
                     .expect("already verifies that all dep cells are valid")
                     .cell_output
@@ -43,7 +59,7 @@ impl<'a> TransactionScriptsVerifier<'a> {
             .input_cells
             .iter()
             .map(|cell| {
-                &cell
+                &*cell
                     .get_live_output()
                     .expect("already verifies that all input cells are valid")
                     .cell_output
@@ -59,9 +75,18 @@ impl<'a> TransactionScriptsVerifier<'a> {
             .map(|(idx, wit)| (idx as u32, &wit[..]))
             .collect();
 
-        let binary_index: FnvHashMap<H256, &'a [u8]> = dep_cells
+        let binary_index: FnvHashMap<H256, &'a [u8]> = rtx
+            .transaction
+            .deps()
             .iter()
-            .map(|dep_cell| (dep_cell.data_hash(), &dep_cell.data[..]))
+            .zip(dep_cells.iter())
+            .map(|(out_point, dep_cell)| {
+                let data_hash = match hash_cache {
+                    Some(hash_cache) => hash_cache.data_hash(out_point, dep_cell),
+                    None => dep_cell.data_hash(),
+                };
+                (data_hash, &dep_cell.data[..])
+            })
             .collect();
 
         let mut tx_builder = FlatBufferBuilder::new();
@@ -123,25 +148,47 @@ impl<'a> TransactionScriptsVerifier<'a> {
         current_input: Option<&'a CellInput>,
         max_cycles: Cycle,
     ) -> Result<Cycle, ScriptError> {
+        let (cycles, result) = self.run_script(
+            script,
+            prefix,
+            current_cell,
+            witness,
+            current_input,
+            max_cycles,
+        );
+        result.map(|()| cycles)
+    }
+
+    // Run the script and return the cycles it took, whether it passed or not
+    fn run_script(
+        &self,
+        script: &Script,
+        prefix: &str,
+        current_cell: &'a CellOutput,
+        witness: Option<&&'a [Vec<u8>]>,
+        current_input: Option<&'a CellInput>,
+        max_cycles: Cycle,
+    ) -> (Cycle, Result<(), ScriptError>) {
         if script.code_hash == ALWAYS_SUCCESS_HASH {
-            return Ok(0);
+            return (0, Ok(()));
         }
+        let script_binary = match self.extract_script(script) {
+            Ok(script_binary) => script_binary,
+            Err(err) => return (0, Err(err)),
+        };
         let mut args = vec![b"verify".to_vec()];
-        self.extract_script(script).and_then(|script_binary| {
-            args.extend_from_slice(&script.args.as_slice());
-            if let Some(ref input) = current_input {
-                args.extend_from_slice(&input.args.as_slice());
-            }
-            if let Some(witness) = witness {
-                args.extend_from_slice(&witness);
-            }
+        args.extend_from_slice(&script.args.as_slice());
+        if let Some(ref input) = current_input {
+            args.extend_from_slice(&input.args.as_slice());
+        }
+        if let Some(witness) = witness {
+            args.extend_from_slice(&witness);
+        }
 
-            let core_machine =
-                DefaultCoreMachine::<u64, SparseMemory<u64>>::new_with_max_cycles(max_cycles);
-            let mut machine =
-                DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::new(
-                    core_machine,
-                )
+        let core_machine =
+            DefaultCoreMachine::<u64, SparseMemory<u64>>::new_with_max_cycles(max_cycles);
+        let machine =
+            DefaultMachineBuilder::<DefaultCoreMachine<u64, SparseMemory<u64>>>::new(core_machine)
                 .instruction_cycle_func(Box::new(instruction_cycles))
                 .syscall(Box::new(self.build_load_tx()))
                 .syscall(Box::new(self.build_load_cell(current_cell)))
@@ -149,15 +196,17 @@ impl<'a> TransactionScriptsVerifier<'a> {
                 .syscall(Box::new(self.build_load_input_by_field(current_input)))
                 .syscall(Box::new(Debugger::new(prefix)))
                 .build()
-                .load_program(script_binary, &args)
-                .map_err(ScriptError::VMError)?;
-            let code = machine.interpret().map_err(ScriptError::VMError)?;
-            if code == 0 {
-                Ok(machine.cycles())
-            } else {
-                Err(ScriptError::ValidationFailure(code))
-            }
-        })
+                .load_program(script_binary, &args);
+        let mut machine = match machine {
+            Ok(machine) => machine,
+            Err(err) => return (0, Err(ScriptError::VMError(err))),
+        };
+        let result = match machine.interpret() {
+            Ok(0) => Ok(()),
+            Ok(code) => Err(ScriptError::ValidationFailure(code)),
+            Err(err) => Err(ScriptError::VMError(err)),
+        };
+        (machine.cycles(), result)
     }
 
     pub fn verify(&self, max_cycles: Cycle) -> Result<Cycle, ScriptError> {
@@ -196,6 +245,57 @@ impl<'a> TransactionScriptsVerifier<'a> {
         }
         Ok(cycles)
     }
+
+    /// Run every script, even after one fails, each with the cycles the previous ones left,
+    /// failed scripts are charged the cycles they ran too. Unlike `verify`, the result of every
+    /// script is returned, for debugging the scripts.
+    pub fn verify_each(&self, max_cycles: Cycle) -> Vec<(ScriptRole, Result<Cycle, ScriptError>)> {
+        let input_locks = self
+            .inputs
+            .iter()
+            .zip(self.input_cells.iter())
+            .enumerate()
+            .map(|(i, (input, input_cell))| {
+                let witness = self.witnesses.get(&(i as u32));
+                (
+                    ScriptRole::InputLock(i),
+                    &input_cell.lock,
+                    *input_cell,
+                    witness,
+                    Some(*input),
+                )
+            });
+        let output_types = self.outputs.iter().enumerate().filter_map(|(i, output)| {
+            output
+                .type_
+                .as_ref()
+                .map(|type_| (ScriptRole::OutputType(i), type_, *output, None, None))
+        });
+
+        let mut cycles: Cycle = 0;
+        let mut results = Vec::new();
+        for (role, script, cell, witness, input) in input_locks.chain(output_types) {
+            let prefix = format!("Transaction {}, {}", self.hash, role);
+            let (cycle, result) = self.run_script(
+                script,
+                &prefix,
+                cell,
+                witness,
+                input,
+                max_cycles.saturating_sub(cycles),
+            );
+            cycles = cycles.saturating_add(cycle);
+            let result = result.and_then(|()| {
+                if cycles > max_cycles {
+                    Err(ScriptError::ExceededMaximumCycles)
+                } else {
+                    Ok(cycle)
+                }
+            });
+            results.push((role, result));
+        }
+        results
+    }
 }
 
 #[cfg(test)]
@@ -212,6 +312,7 @@ mod tests {
     use std::fs::File;
     use std::io::{Read, Write};
     use std::path::Path;
+    use std::sync::Arc;
 
     fn open_cell_verify() -> File {
         File::open(Path::new(env!("CARGO_MANIFEST_DIR")).join("../script/testdata/verify")).unwrap()
@@ -220,12 +321,12 @@ mod tests {
     #[test]
     fn check_always_success_hash() {
         let dummy_cell = CellMeta {
-            cell_output: CellOutput::new(
+            cell_output: Arc::new(CellOutput::new(
                 capacity_bytes!(100),
                 vec![],
                 Script::always_success(),
                 None,
-            ),
+            )),
             block_number: Some(1),
             cellbase: false,
         };
@@ -242,6 +343,10 @@ mod tests {
         let verifier = TransactionScriptsVerifier::new(&rtx);
 
         assert!(verifier.verify(0).is_ok());
+        assert_eq!(
+            verifier.verify_each(0),
+            vec![(ScriptRole::InputLock(0), Ok(0))]
+        );
     }
 
     #[test]
@@ -276,12 +381,12 @@ mod tests {
         let code_hash: H256 = (&blake2b_256(&buffer)).into();
         let dep_out_point = OutPoint::new(H256::from_trimmed_hex_str("123").unwrap(), 8);
         let dep_cell = CellMeta {
-            cell_output: CellOutput::new(
+            cell_output: Arc::new(CellOutput::new(
                 Capacity::bytes(buffer.len()).unwrap(),
                 buffer,
                 Script::default(),
                 None,
-            ),
+            )),
             block_number: Some(1),
             cellbase: false,
         };
@@ -296,7 +401,7 @@ mod tests {
             .build();
 
         let dummy_cell = CellMeta {
-            cell_output: CellOutput::new(capacity_bytes!(100), vec![], script, None),
+            cell_output: Arc::new(CellOutput::new(capacity_bytes!(100), vec![], script, None)),
             block_number: Some(1),
             cellbase: false,
         };
@@ -310,6 +415,13 @@ mod tests {
         let verifier = TransactionScriptsVerifier::new(&rtx);
 
         assert!(verifier.verify(100_000_000).is_ok());
+
+        // the dep is found by the cached hash the second time
+        let hash_cache = ScriptHashCache::new(1);
+        for _ in 0..2 {
+            let verifier = TransactionScriptsVerifier::with_hash_cache(&rtx, &hash_cache);
+            assert!(verifier.verify(100_000_000).is_ok());
+        }
     }
 
     #[test]
@@ -344,12 +456,12 @@ mod tests {
         let code_hash: H256 = (&blake2b_256(&buffer)).into();
         let dep_out_point = OutPoint::new(H256::from_trimmed_hex_str("123").unwrap(), 8);
         let dep_cell = CellMeta {
-            cell_output: CellOutput::new(
+            cell_output: Arc::new(CellOutput::new(
                 Capacity::bytes(buffer.len()).unwrap(),
                 buffer,
                 Script::default(),
                 None,
-            ),
+            )),
             block_number: Some(1),
             cellbase: false,
         };
@@ -364,7 +476,7 @@ mod tests {
             .build();
 
         let dummy_cell = CellMeta {
-            cell_output: CellOutput::new(capacity_bytes!(100), vec![], script, None),
+            cell_output: Arc::new(CellOutput::new(capacity_bytes!(100), vec![], script, None)),
             block_number: Some(1),
             cellbase: false,
         };
@@ -414,12 +526,12 @@ mod tests {
         let code_hash: H256 = (&blake2b_256(&buffer)).into();
         let dep_out_point = OutPoint::new(H256::from_trimmed_hex_str("123").unwrap(), 8);
         let dep_cell = CellMeta {
-            cell_output: CellOutput::new(
+            cell_output: Arc::new(CellOutput::new(
                 Capacity::bytes(buffer.len()).unwrap(),
                 buffer,
                 Script::default(),
                 None,
-            ),
+            )),
             block_number: Some(1),
             cellbase: false,
         };
@@ -434,7 +546,7 @@ mod tests {
             .build();
 
         let dummy_cell = CellMeta {
-            cell_output: CellOutput::new(capacity_bytes!(100), vec![], script, None),
+            cell_output: Arc::new(CellOutput::new(capacity_bytes!(100), vec![], script, None)),
             block_number: Some(1),
             cellbase: false,
         };
@@ -491,7 +603,7 @@ mod tests {
             .build();
 
         let dummy_cell = CellMeta {
-            cell_output: CellOutput::new(capacity_bytes!(100), vec![], script, None),
+            cell_output: Arc::new(CellOutput::new(capacity_bytes!(100), vec![], script, None)),
             block_number: Some(1),
             cellbase: false,
         };
@@ -537,12 +649,12 @@ mod tests {
 
         let input = CellInput::new(OutPoint::null(), 0, vec![]);
         let dummy_cell = CellMeta {
-            cell_output: CellOutput::new(
+            cell_output: Arc::new(CellOutput::new(
                 capacity_bytes!(100),
                 vec![],
                 Script::always_success(),
                 None,
-            ),
+            )),
             block_number: Some(1),
             cellbase: false,
         };
@@ -557,12 +669,12 @@ mod tests {
 
         let dep_out_point = OutPoint::new(H256::from_trimmed_hex_str("123").unwrap(), 8);
         let dep_cell = CellMeta {
-            cell_output: CellOutput::new(
+            cell_output: Arc::new(CellOutput::new(
                 Capacity::bytes(buffer.len()).unwrap(),
                 buffer,
                 Script::default(),
                 None,
-            ),
+            )),
             block_number: Some(1),
             cellbase: false,
         };
@@ -616,12 +728,12 @@ mod tests {
 
         let input = CellInput::new(OutPoint::null(), 0, vec![]);
         let dummy_cell = CellMeta {
-            cell_output: CellOutput::new(
+            cell_output: Arc::new(CellOutput::new(
                 capacity_bytes!(100),
                 vec![],
                 Script::always_success(),
                 None,
-            ),
+            )),
             block_number: Some(1),
             cellbase: false,
         };
@@ -636,12 +748,12 @@ mod tests {
 
         let dep_out_point = OutPoint::new(H256::from_trimmed_hex_str("123").unwrap(), 8);
         let dep_cell = CellMeta {
-            cell_output: CellOutput::new(
+            cell_output: Arc::new(CellOutput::new(
                 Capacity::bytes(buffer.len()).unwrap(),
                 buffer,
                 Script::default(),
                 None,
-            ),
+            )),
             block_number: Some(1),
             cellbase: false,
         };
@@ -0,0 +1,152 @@
+use ckb_util::Mutex;
+use fnv::FnvHashMap;
+use log::trace;
+use numext_fixed_hash::H256;
+use std::time::{Duration, Instant};
+
+/// Upper bounds in milliseconds of the latency histogram buckets, the last bucket counts
+/// everything slower
+pub const LATENCY_BUCKETS: [u64; 8] = [1, 5, 10, 50, 100, 500, 1000, 5000];
+
+/// Stages a block passes from receipt to relay
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlockStage {
+    /// Decode the received message into a block
+    Decode,
+    /// Verify the header, including the PoW
+    HeaderVerify,
+    /// Verify the block body against the chain
+    BlockVerify,
+    /// Write the block and indexes to the store
+    Commit,
+    /// Update the tip and the transaction pool
+    TipUpdate,
+    /// Announce the accepted block to peers
+    Announce,
+}
+
+pub const BLOCK_STAGES: [BlockStage; 6] = [
+    BlockStage::Decode,
+    BlockStage::HeaderVerify,
+    BlockStage::BlockVerify,
+    BlockStage::Commit,
+    BlockStage::TipUpdate,
+    BlockStage::Announce,
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// Samples per bucket of `LATENCY_BUCKETS`, plus the overflow bucket
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub total_millis: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: vec![0; LATENCY_BUCKETS.len() + 1],
+            count: 0,
+            total_millis: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn observe(&mut self, millis: u64) {
+        let index = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| millis <= *bound)
+            .unwrap_or_else(|| LATENCY_BUCKETS.len());
+        self.buckets[index] += 1;
+        self.count += 1;
+        self.total_millis = self.total_millis.saturating_add(millis);
+    }
+}
+
+/// Latency histograms of the block processing stages, so a propagation slowdown can be
+/// attributed to a stage. Every sample is also logged with the block hash under the
+/// `block_stage` target.
+#[derive(Debug, Default)]
+pub struct BlockStageMetrics {
+    histograms: Mutex<FnvHashMap<BlockStage, LatencyHistogram>>,
+}
+
+impl BlockStageMetrics {
+    pub fn record(&self, stage: BlockStage, block_hash: &H256, elapsed: Duration) {
+        let millis = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+        trace!(target: "block_stage", "block {:#x} {:?} took {} ms", block_hash, stage, millis);
+        self.histograms
+            .lock()
+            .entry(stage)
+            .or_insert_with(Default::default)
+            .observe(millis);
+    }
+
+    /// Time a stage until the returned span is dropped
+    pub fn span(&self, stage: BlockStage, block_hash: &H256) -> StageSpan {
+        StageSpan {
+            metrics: self,
+            stage,
+            block_hash: block_hash.clone(),
+            started_at: Instant::now(),
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<(BlockStage, LatencyHistogram)> {
+        let histograms = self.histograms.lock();
+        BLOCK_STAGES
+            .iter()
+            .map(|stage| (*stage, histograms.get(stage).cloned().unwrap_or_default()))
+            .collect()
+    }
+}
+
+pub struct StageSpan<'a> {
+    metrics: &'a BlockStageMetrics,
+    stage: BlockStage,
+    block_hash: H256,
+    started_at: Instant,
+}
+
+impl<'a> Drop for StageSpan<'a> {
+    fn drop(&mut self) {
+        self.metrics
+            .record(self.stage, &self.block_hash, self.started_at.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut histogram = LatencyHistogram::default();
+        histogram.observe(0);
+        histogram.observe(1);
+        histogram.observe(7);
+        histogram.observe(6000);
+        assert_eq!(histogram.buckets, vec![2, 0, 1, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(histogram.count, 4);
+        assert_eq!(histogram.total_millis, 6008);
+    }
+
+    #[test]
+    fn test_span_records_on_drop() {
+        let metrics = BlockStageMetrics::default();
+        {
+            let _span = metrics.span(BlockStage::Commit, &H256::zero());
+        }
+        metrics.record(BlockStage::Decode, &H256::zero(), Duration::from_millis(20));
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.len(), BLOCK_STAGES.len());
+        for (stage, histogram) in snapshot {
+            match stage {
+                BlockStage::Decode => assert_eq!(histogram.buckets[3], 1),
+                BlockStage::Commit => assert_eq!(histogram.count, 1),
+                _ => assert_eq!(histogram.count, 0),
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use ckb_core::block::Block;
+use ckb_core::header::{BlockNumber, Header};
+use ckb_core::uncle::UncleBlock;
+use ckb_traits::ChainProvider;
+use ckb_verification::excluded_uncles;
+use fnv::{FnvHashMap, FnvHashSet};
+use numext_fixed_hash::H256;
+use numext_fixed_uint::U256;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+pub const MAX_CANDIDATE_UNCLES: usize = 42;
+
+/// Stale blocks near the tip, which the next blocks may include as uncles
+#[derive(Default, Debug)]
+pub struct CandidateUncles {
+    blocks: FnvHashMap<H256, Arc<Block>>,
+    by_number: BTreeMap<BlockNumber, FnvHashSet<H256>>,
+}
+
+impl CandidateUncles {
+    pub fn new() -> Self {
+        CandidateUncles::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn contains(&self, hash: &H256) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    /// Record a stale block, the oldest candidate is dropped when the pool is full.
+    /// Returns false if the block is known or older than all the candidates of a full pool.
+    pub fn insert(&mut self, block: Arc<Block>) -> bool {
+        let hash = block.header().hash();
+        let number = block.header().number();
+        if self.blocks.contains_key(&hash) {
+            return false;
+        }
+        if self.blocks.len() >= MAX_CANDIDATE_UNCLES {
+            let oldest = match self.by_number.iter().next() {
+                Some((oldest_number, hashes)) if *oldest_number < number => {
+                    hashes.iter().next().cloned()
+                }
+                _ => return false,
+            };
+            if let Some(oldest) = oldest {
+                self.remove(&oldest);
+            }
+        }
+        self.by_number
+            .entry(number)
+            .or_insert_with(FnvHashSet::default)
+            .insert(hash.clone());
+        self.blocks.insert(hash, block);
+        true
+    }
+
+    pub fn remove(&mut self, hash: &H256) -> Option<Arc<Block>> {
+        let block = self.blocks.remove(hash)?;
+        let number = block.header().number();
+        let emptied = match self.by_number.get_mut(&number) {
+            Some(hashes) => hashes.remove(hash) && hashes.is_empty(),
+            None => false,
+        };
+        if emptied {
+            self.by_number.remove(&number);
+        }
+        Some(block)
+    }
+
+    /// Remove the candidates too deep to be uncles of the blocks after `tip_number`
+    pub fn prune(&mut self, tip_number: BlockNumber, max_uncles_age: u64) {
+        let expired: Vec<H256> = self
+            .by_number
+            .range(..=tip_number.saturating_sub(max_uncles_age))
+            .flat_map(|(_, hashes)| hashes.iter().cloned())
+            .collect();
+        for hash in expired {
+            self.remove(&hash);
+        }
+    }
+
+    /// The uncles for the block after `tip` whose difficulty is `difficulty`, oldest first.
+    ///
+    /// The candidates which can never be included on this chain are removed.
+    pub fn select<CP: ChainProvider>(
+        &mut self,
+        provider: &CP,
+        tip: &Header,
+        difficulty: &U256,
+    ) -> Vec<UncleBlock> {
+        let consensus = provider.consensus();
+        let max_uncles_age = consensus.max_uncles_age() as u64;
+        let max_uncles_num = consensus.max_uncles_num();
+        self.prune(tip.number(), max_uncles_age);
+
+        let excluded = excluded_uncles(provider, &tip.hash(), max_uncles_age);
+        let number = tip.number() + 1;
+        let difficulty_epoch = number / consensus.difficulty_adjustment_interval();
+
+        let mut uncles = Vec::with_capacity(max_uncles_num);
+        let mut bad_uncles = Vec::new();
+        for block in self
+            .by_number
+            .values()
+            .flatten()
+            .map(|hash| &self.blocks[hash])
+        {
+            if uncles.len() == max_uncles_num {
+                break;
+            }
+            let header = block.header();
+            // uncle must be same difficulty epoch with candidate
+            let uncle_difficulty_epoch =
+                header.number() / consensus.difficulty_adjustment_interval();
+            if header.difficulty() != difficulty
+                || uncle_difficulty_epoch != difficulty_epoch
+                || excluded.contains(&header.hash())
+            {
+                bad_uncles.push(header.hash());
+            } else if header.number() < number {
+                uncles.push(UncleBlock {
+                    header: header.to_owned(),
+                    proposals: block.proposals().to_vec(),
+                });
+            }
+        }
+        for hash in bad_uncles {
+            self.remove(&hash);
+        }
+        uncles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_core::block::BlockBuilder;
+    use ckb_core::header::HeaderBuilder;
+
+    fn build_block(number: BlockNumber, nonce: u64) -> Arc<Block> {
+        Arc::new(
+            BlockBuilder::default()
+                .header(HeaderBuilder::default().number(number).nonce(nonce).build())
+                .build(),
+        )
+    }
+
+    #[test]
+    fn test_prune_by_depth() {
+        let mut candidates = CandidateUncles::new();
+        for number in 1..=10 {
+            assert!(candidates.insert(build_block(number, 0)));
+        }
+        assert!(!candidates.insert(build_block(10, 0)));
+
+        candidates.prune(10, 6);
+        assert_eq!(candidates.len(), 6);
+        assert!(!candidates.contains(&build_block(4, 0).header().hash()));
+        assert!(candidates.contains(&build_block(5, 0).header().hash()));
+    }
+
+    #[test]
+    fn test_drop_oldest_when_full() {
+        let mut candidates = CandidateUncles::new();
+        for nonce in 0..MAX_CANDIDATE_UNCLES as u64 {
+            assert!(candidates.insert(build_block(5 + nonce % 2, nonce)));
+        }
+        assert!(!candidates.insert(build_block(5, 100)));
+        assert!(candidates.insert(build_block(6, 100)));
+        assert_eq!(candidates.len(), MAX_CANDIDATE_UNCLES);
+        assert_eq!(candidates.by_number[&5].len(), MAX_CANDIDATE_UNCLES / 2 - 1);
+    }
+}
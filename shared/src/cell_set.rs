@@ -1,9 +1,10 @@
 use ckb_core::block::Block;
 use ckb_core::transaction::OutPoint;
 use ckb_core::transaction_meta::TransactionMeta;
-use fnv::{FnvHashMap, FnvHashSet};
+use fnv::{FnvHashMap, FnvHashSet, FnvHasher};
 use numext_fixed_hash::H256;
 use serde_derive::{Deserialize, Serialize};
+use std::hash::BuildHasherDefault;
 
 #[derive(Default, Clone, Deserialize, Serialize)]
 pub struct CellSetDiff {
@@ -38,9 +39,17 @@ impl CellSetDiff {
     }
 }
 
+/// Entries of the cell set changed by a `CellSetDiff`, applied both to the persisted cell set
+/// and to the one in memory
+pub type CellSetChanges = FnvHashMap<H256, Option<TransactionMeta>>;
+
+/// A persistent map, the clones share the unchanged entries and a change only copies the nodes
+/// on the path to the entry
+pub(crate) type CellSetMap = im::HashMap<H256, TransactionMeta, BuildHasherDefault<FnvHasher>>;
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct CellSetOverlay<'a> {
-    origin: &'a FnvHashMap<H256, TransactionMeta>,
+    origin: &'a CellSetMap,
     new: FnvHashMap<H256, TransactionMeta>,
     removed: FnvHashSet<H256>,
 }
@@ -55,15 +64,17 @@ impl<'a> CellSetOverlay<'a> {
     }
 }
 
+/// Cloning is cheap, the snapshots of the chain state share the cell set and moving to a new
+/// tip copies only the changed entries
 #[derive(Default, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct CellSet {
-    pub(crate) inner: FnvHashMap<H256, TransactionMeta>,
+    pub(crate) inner: CellSetMap,
 }
 
 impl CellSet {
     pub fn new() -> Self {
         CellSet {
-            inner: FnvHashMap::default(),
+            inner: CellSetMap::default(),
         }
     }
 
@@ -141,36 +152,68 @@ impl CellSet {
         }
     }
 
-    fn mark_live(&mut self, o: &OutPoint) {
-        if let Some(meta) = self.inner.get_mut(&o.tx_hash) {
-            meta.unset_dead(o.index as usize);
+    /// The entries changed by `diff`, `None` for the removed ones
+    pub fn changes(&self, diff: &CellSetDiff) -> CellSetChanges {
+        let mut changes = CellSetChanges::default();
+
+        for hash in &diff.old_outputs {
+            changes.insert(hash.clone(), None);
+        }
+
+        for old_input in &diff.old_inputs {
+            let meta = changes
+                .entry(old_input.tx_hash.clone())
+                .or_insert_with(|| self.inner.get(&old_input.tx_hash).cloned());
+            if let Some(meta) = meta {
+                meta.unset_dead(old_input.index as usize);
+            }
         }
-    }
 
-    pub fn update(&mut self, diff: CellSetDiff) {
-        let CellSetDiff {
-            old_inputs,
-            old_outputs,
-            new_inputs,
-            new_outputs,
-        } = diff;
+        for (hash, (number, cellbase, len)) in &diff.new_outputs {
+            let meta = if *cellbase {
+                TransactionMeta::new_cellbase(*number, *len)
+            } else {
+                TransactionMeta::new(*number, *len)
+            };
+            changes.insert(hash.clone(), Some(meta));
+        }
 
-        old_outputs.iter().for_each(|h| {
-            self.remove(h);
-        });
+        for new_input in &diff.new_inputs {
+            let meta = changes
+                .entry(new_input.tx_hash.clone())
+                .or_insert_with(|| self.inner.get(&new_input.tx_hash).cloned());
+            if let Some(meta) = meta {
+                meta.set_dead(new_input.index as usize);
+            }
+        }
 
-        old_inputs.iter().for_each(|o| {
-            self.mark_live(o);
-        });
+        changes
+    }
 
-        new_outputs
+    /// The changes replacing a saved cell set which has the entries of `tx_hashes`
+    pub fn replacing(&self, tx_hashes: Vec<H256>) -> CellSetChanges {
+        let mut changes: CellSetChanges = tx_hashes
             .into_iter()
-            .for_each(|(hash, (number, cellbase, len))| {
-                self.insert(hash, number, cellbase, len);
-            });
+            .map(|tx_hash| (tx_hash, None))
+            .collect();
+        changes.extend(
+            self.inner
+                .iter()
+                .map(|(tx_hash, meta)| (tx_hash.clone(), Some(meta.clone()))),
+        );
+        changes
+    }
 
-        new_inputs.iter().for_each(|o| {
-            self.mark_dead(o);
-        });
+    pub fn apply(&mut self, changes: CellSetChanges) {
+        for (hash, meta) in changes {
+            match meta {
+                Some(meta) => {
+                    self.inner.insert(hash, meta);
+                }
+                None => {
+                    self.inner.remove(&hash);
+                }
+            }
+        }
     }
 }
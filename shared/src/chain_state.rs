@@ -1,47 +1,91 @@
-use crate::cell_set::{CellSet, CellSetDiff, CellSetOverlay};
+use crate::cell_set::{CellSet, CellSetChanges, CellSetDiff, CellSetOverlay};
 use crate::error::SharedError;
-use crate::store::ChainStore;
-use crate::tx_pool::types::PoolEntry;
-use crate::tx_pool::{PoolError, TxPool, TxPoolConfig};
+use crate::store::{ChainStore, StoreBatch, StoreConfig};
+use crate::tx_pool::types::{fee_rate, PoolEntry};
+use crate::tx_pool::{
+    CellResolution, CellResolutionDetail, PersistedTx, PoolError, TxPool, TxPoolEvent, TxStatus,
+};
 use crate::tx_proposal_table::TxProposalTable;
 use ckb_chain_spec::consensus::{Consensus, ProposalWindow};
 use ckb_core::block::Block;
 #[allow(unused_imports)] // incorrect lint
 use ckb_core::cell::CellProvider;
 use ckb_core::cell::{
-    resolve_transaction, CellStatus, LiveCell, OverlayCellProvider, ResolvedTransaction,
+    resolve_cells, CellStatus, LiveCell, OverlayCellProvider, ResolvedTransaction,
+    UnresolvableError,
 };
+use ckb_core::extras::LockCell;
 use ckb_core::header::{BlockNumber, Header};
+use ckb_core::script_cache::ScriptHashCache;
 use ckb_core::transaction::CellOutput;
 use ckb_core::transaction::{OutPoint, ProposalShortId, Transaction};
-use ckb_core::Cycle;
+use ckb_core::{Capacity, Cycle};
 use ckb_traits::BlockMedianTimeContext;
-use ckb_verification::{PoolTransactionVerifier, TransactionVerifier};
+use ckb_util::Mutex;
+use ckb_verification::{
+    Error as VerificationError, PoolTransactionVerifier, SizeVerifier, TransactionVerifier,
+    VerificationPool,
+};
 use fnv::{FnvHashMap, FnvHashSet};
-use log::{error, trace};
+use log::{error, info, trace};
+use lru_cache::LruCache;
 use numext_fixed_hash::H256;
 use numext_fixed_uint::U256;
-use std::cell::{Ref, RefCell};
 use std::sync::Arc;
 
-#[derive(Debug, Clone)]
+const BLOCK_FEES_CACHE_SIZE: usize = 1024;
+// blocks backfilled into the transaction index per write batch
+const TX_INDEX_BACKFILL_BATCH: BlockNumber = 1000;
+
+/// Snapshot of the chain at the tip, it is never changed once published by `Shared`. The chain
+/// service clones the snapshot, updates it, and swaps it in while holding the tx pool lock.
+/// The proposal table is shared between snapshots and copied on write, the cell set is a
+/// persistent map whose snapshots share the unchanged entries.
+#[derive(Debug)]
 pub struct ChainState<CS> {
     store: Arc<CS>,
     tip_header: Header,
     total_difficulty: U256,
     pub(crate) cell_set: CellSet,
-    proposal_ids: TxProposalTable,
-    // interior mutability for immutable borrow proposal_ids
-    tx_pool: RefCell<TxPool>,
+    proposal_ids: Arc<TxProposalTable>,
     consensus: Arc<Consensus>,
+    verification_pool: VerificationPool,
+    // tx hash => cycles of the scripts, shared with `Shared`. The cycles only depend on the
+    // transaction and the cells its hash pins down, so the entries stay valid across reorgs.
+    txs_verify_cache: Arc<Mutex<LruCache<H256, Cycle>>>,
+    // the cells holding script binaries, shared with `Shared`
+    script_hash_cache: Arc<ScriptHashCache>,
+    // block hash => fees of its transactions, shared between snapshots
+    block_fees_cache: Arc<Mutex<LruCache<H256, Capacity>>>,
+}
+
+// https://github.com/rust-lang/rust/issues/40754
+impl<CS: ChainStore> ::std::clone::Clone for ChainState<CS> {
+    fn clone(&self) -> Self {
+        ChainState {
+            store: Arc::clone(&self.store),
+            tip_header: self.tip_header.clone(),
+            total_difficulty: self.total_difficulty.clone(),
+            cell_set: self.cell_set.clone(),
+            proposal_ids: Arc::clone(&self.proposal_ids),
+            consensus: Arc::clone(&self.consensus),
+            verification_pool: self.verification_pool.clone(),
+            txs_verify_cache: Arc::clone(&self.txs_verify_cache),
+            script_hash_cache: Arc::clone(&self.script_hash_cache),
+            block_fees_cache: Arc::clone(&self.block_fees_cache),
+        }
+    }
 }
 
 impl<CS: ChainStore> ChainState<CS> {
     pub fn init(
         store: &Arc<CS>,
         consensus: Arc<Consensus>,
-        tx_pool_config: TxPoolConfig,
+        verification_pool: VerificationPool,
+        txs_verify_cache: Arc<Mutex<LruCache<H256, Cycle>>>,
+        script_hash_cache: Arc<ScriptHashCache>,
     ) -> Result<Self, SharedError> {
+        store.repair_tip().map_err(SharedError::DB)?;
         // check head in store or save the genesis block as head
         let tip_header = {
             let genesis = consensus.genesis_block();
@@ -72,13 +116,11 @@ impl<CS: ChainStore> ChainState<CS> {
             }
         }?;
 
-        let tx_pool = TxPool::new(tx_pool_config);
-
         let tip_number = tip_header.number();
         let proposal_window = consensus.tx_proposal_window();
         let proposal_ids = Self::init_proposal_ids(&store, proposal_window, tip_number);
 
-        let cell_set = Self::init_cell_set(&store, tip_number);
+        let cell_set = Self::init_cell_set(&store, &tip_header)?;
 
         let total_difficulty = store
             .get_block_ext(&tip_header.hash())
@@ -89,12 +131,171 @@ impl<CS: ChainStore> ChainState<CS> {
             tip_header,
             total_difficulty,
             cell_set,
-            proposal_ids,
-            tx_pool: RefCell::new(tx_pool),
+            proposal_ids: Arc::new(proposal_ids),
             consensus,
+            verification_pool,
+            txs_verify_cache,
+            script_hash_cache,
+            block_fees_cache: Arc::new(Mutex::new(LruCache::new(BLOCK_FEES_CACHE_SIZE))),
         })
     }
 
+    /// Backfill the transaction index if it doesn't cover the main chain, which is the case
+    /// for the databases written before it or while it was disabled. Fully spent
+    /// transactions below the retention cutoff are left out.
+    pub(crate) fn init_tx_index(&self, store_config: &StoreConfig) -> Result<(), SharedError> {
+        if store_config.disable_tx_index {
+            let mut batch = self.store.new_batch().map_err(SharedError::DB)?;
+            batch.set_tx_indexed(false).map_err(SharedError::DB)?;
+            return batch.commit().map_err(SharedError::DB);
+        }
+        if self.store.is_tx_indexed() {
+            return Ok(());
+        }
+
+        let tip_number = self.tip_number();
+        info!(target: "shared", "backfill the transaction index to block {}", tip_number);
+        let cutoff = store_config.tx_index_cutoff(tip_number).unwrap_or(0);
+        let mut batch = self.store.new_batch().map_err(SharedError::DB)?;
+        for number in 0..=tip_number {
+            let hash = self.store.get_block_hash(number).ok_or_else(|| {
+                SharedError::InvalidData(format!("main chain block {} is not indexed", number))
+            })?;
+            // the pruned blocks are fully spent
+            let txs = match self.store.get_block_body(&hash) {
+                Some(txs) => txs,
+                None => continue,
+            };
+            for (index, tx) in txs.iter().enumerate() {
+                let tx_hash = tx.hash();
+                let live = self
+                    .cell_set
+                    .get(&tx_hash)
+                    .map_or(false, |meta| !meta.is_all_dead());
+                if number < cutoff && !live {
+                    continue;
+                }
+                if let Some(address) = self.store.get_block_transaction_address(&hash, index) {
+                    batch
+                        .insert_transaction_address(&tx_hash, &address)
+                        .map_err(SharedError::DB)?;
+                }
+            }
+            if (number + 1) % TX_INDEX_BACKFILL_BATCH == 0 {
+                batch.commit().map_err(SharedError::DB)?;
+                batch = self.store.new_batch().map_err(SharedError::DB)?;
+            }
+        }
+        batch.set_tx_indexed(true).map_err(SharedError::DB)?;
+        batch.commit().map_err(SharedError::DB)
+    }
+
+    /// Build the lock index from the cell set if it doesn't match the main chain, which is
+    /// the case when it was just enabled or disabled for a while
+    pub(crate) fn init_lock_index(&self, store_config: &StoreConfig) -> Result<(), SharedError> {
+        if !store_config.lock_index {
+            let mut batch = self.store.new_batch().map_err(SharedError::DB)?;
+            batch.set_lock_indexed(false).map_err(SharedError::DB)?;
+            return batch.commit().map_err(SharedError::DB);
+        }
+        if self.store.is_lock_indexed() {
+            return Ok(());
+        }
+
+        info!(target: "shared", "build the lock index to block {}", self.tip_number());
+        let mut batch = self.store.new_batch().map_err(SharedError::DB)?;
+        for (out_point, cell) in self.store.get_all_lock_cells() {
+            batch
+                .delete_lock_cell(&cell.lock.hash(), &out_point)
+                .map_err(SharedError::DB)?;
+        }
+        for (tx_hash, meta) in &self.cell_set.inner {
+            // the blocks below an imported snapshot are not stored
+            let tx = match self
+                .store
+                .get_transaction_in_block(tx_hash, meta.block_number())
+            {
+                Some(tx) => tx,
+                None => continue,
+            };
+            for (index, output) in tx.outputs().iter().enumerate() {
+                if meta.is_dead(index) {
+                    continue;
+                }
+                let cell = LockCell {
+                    capacity: output.capacity,
+                    lock: output.lock.clone(),
+                    block_number: meta.block_number(),
+                };
+                batch
+                    .insert_lock_cell(&OutPoint::new(tx_hash.clone(), index as u32), &cell)
+                    .map_err(SharedError::DB)?;
+            }
+        }
+        batch.set_lock_indexed(true).map_err(SharedError::DB)?;
+        batch.commit().map_err(SharedError::DB)
+    }
+
+    /// Save the pending and staging transactions, they are added back to the pool on the
+    /// next start
+    pub fn persist_tx_pool(&self, tx_pool: &TxPool) -> Result<(), SharedError> {
+        // parents should be added back before their children, staging transactions come
+        // first and pending ones in the order they arrived
+        let mut pending: Vec<&PoolEntry> = tx_pool.pending.inner.values().collect();
+        pending.sort_by_key(|entry| entry.timestamp);
+        let txs: Vec<PersistedTx> = tx_pool
+            .staging
+            .txs_iter()
+            .chain(pending)
+            .map(|entry| PersistedTx {
+                transaction: entry.transaction.clone(),
+                cycles: entry.cycles,
+                timestamp: entry.timestamp,
+            })
+            .collect();
+        let mut batch = self.store.new_batch().map_err(SharedError::DB)?;
+        batch
+            .insert_tx_pool_snapshot(&txs)
+            .map_err(SharedError::DB)?;
+        batch.commit().map_err(SharedError::DB)?;
+        info!(target: "tx_pool", "persisted {} transactions", txs.len());
+        Ok(())
+    }
+
+    /// Validate and add back the transactions saved by `persist_tx_pool`, the ones committed
+    /// or conflicting with the chain in the meantime are dropped
+    pub(crate) fn reload_tx_pool(&self, tx_pool: &mut TxPool) -> Result<(), SharedError> {
+        let txs = match self.store.get_tx_pool_snapshot() {
+            Some(txs) => txs,
+            None => return Ok(()),
+        };
+        let total = txs.len();
+        let mut reloaded = 0;
+        for persisted in txs {
+            let tx_hash = persisted.transaction.hash();
+            if self.cell_set.get(&tx_hash).is_some()
+                || self.store.get_transaction_address(&tx_hash).is_some()
+            {
+                continue;
+            }
+            let short_id = persisted.transaction.proposal_short_id();
+            match self.add_tx_with_cycles(tx_pool, persisted.transaction, persisted.cycles) {
+                Ok(_) => {
+                    tx_pool.set_timestamp(&short_id, persisted.timestamp);
+                    reloaded += 1;
+                }
+                Err(err) => {
+                    trace!(target: "tx_pool", "drop persisted tx {:#x}: {:?}", tx_hash, err);
+                }
+            }
+        }
+        info!(target: "tx_pool", "reloaded {} of {} persisted transactions", reloaded, total);
+
+        let mut batch = self.store.new_batch().map_err(SharedError::DB)?;
+        batch.delete_tx_pool_snapshot().map_err(SharedError::DB)?;
+        batch.commit().map_err(SharedError::DB)
+    }
+
     fn init_proposal_ids(
         store: &CS,
         proposal_window: ProposalWindow,
@@ -122,12 +323,25 @@ impl<CS: ChainStore> ChainState<CS> {
         proposal_ids
     }
 
-    fn init_cell_set(store: &CS, number: u64) -> CellSet {
+    /// Load the cell set saved with the tip, or rebuild it from the blocks and save it
+    fn init_cell_set(store: &CS, tip_header: &Header) -> Result<CellSet, SharedError> {
+        let tip_hash = tip_header.hash();
+        if let Some(cell_set) = store.get_cell_set(&tip_hash) {
+            return Ok(cell_set);
+        }
+
+        info!(target: "shared", "rebuild the cell set to block {}", tip_header.number());
         let mut cell_set = CellSet::new();
 
-        for n in 0..=number {
+        for n in 0..=tip_header.number() {
             let hash = store.get_block_hash(n).unwrap();
-            for tx in store.get_block_body(&hash).unwrap() {
+            let txs = store.get_block_body(&hash).ok_or_else(|| {
+                SharedError::InvalidData(format!(
+                    "block {} is pruned, the cell set can't be rebuilt",
+                    n
+                ))
+            })?;
+            for tx in txs {
                 let inputs = tx.input_pts();
                 let output_len = tx.outputs().len();
 
@@ -139,7 +353,14 @@ impl<CS: ChainStore> ChainState<CS> {
             }
         }
 
-        cell_set
+        let changes = cell_set.replacing(store.get_cell_set_tx_hashes());
+        let mut batch = store.new_batch().map_err(SharedError::DB)?;
+        batch
+            .update_cell_set(&changes, &tip_hash)
+            .map_err(SharedError::DB)?;
+        batch.commit().map_err(SharedError::DB)?;
+
+        Ok(cell_set)
     }
 
     pub fn tip_number(&self) -> BlockNumber {
@@ -175,12 +396,12 @@ impl<CS: ChainStore> ChainState<CS> {
     }
 
     pub fn insert_proposal_ids(&mut self, block: &Block) {
-        self.proposal_ids
+        Arc::make_mut(&mut self.proposal_ids)
             .insert(block.header().number(), block.union_proposal_ids());
     }
 
     pub fn remove_proposal_ids(&mut self, block: &Block) {
-        self.proposal_ids.remove(block.header().number());
+        Arc::make_mut(&mut self.proposal_ids).remove(block.header().number());
     }
 
     pub fn get_proposal_ids_iter(&self) -> impl Iterator<Item = &ProposalShortId> {
@@ -188,29 +409,126 @@ impl<CS: ChainStore> ChainState<CS> {
     }
 
     pub fn proposal_ids_finalize(&mut self, number: BlockNumber) -> FnvHashSet<ProposalShortId> {
-        self.proposal_ids.finalize(number)
+        Arc::make_mut(&mut self.proposal_ids).finalize(number)
     }
 
-    pub fn update_tip(&mut self, header: Header, total_difficulty: U256, txo_diff: CellSetDiff) {
+    /// Move to the new tip, `cell_set_changes` must be saved in the batch of the tip header
+    pub fn update_tip(
+        &mut self,
+        header: Header,
+        total_difficulty: U256,
+        cell_set_changes: CellSetChanges,
+    ) {
         self.tip_header = header;
         self.total_difficulty = total_difficulty;
-        self.cell_set.update(txo_diff);
+        self.cell_set.apply(cell_set_changes);
+    }
+
+    /// Verify and add the transaction, the scripts are run while the pool is locked. Use
+    /// `Shared::add_tx_to_pool` to verify them in background.
+    pub fn add_tx_to_pool(
+        &self,
+        tx_pool: &mut TxPool,
+        tx: Transaction,
+    ) -> Result<Cycle, PoolError> {
+        self.accept_tx(tx_pool, tx, None)
+    }
+
+    /// Add a transaction, tracked by the fee estimator, whose scripts are verified again
+    /// unless `cycles` is known
+    pub(crate) fn accept_tx(
+        &self,
+        tx_pool: &mut TxPool,
+        tx: Transaction,
+        cycles: Option<Cycle>,
+    ) -> Result<Cycle, PoolError> {
+        let tx_hash = tx.hash();
+        let short_id = tx.proposal_short_id();
+        let cycles = self.add_tx_with_cycles(tx_pool, tx, cycles)?;
+        let fee_rate = tx_pool
+            .pending
+            .get(&short_id)
+            .or_else(|| tx_pool.staging.get(&short_id))
+            .map(PoolEntry::fee_rate);
+        if let Some(fee_rate) = fee_rate {
+            tx_pool
+                .fee_estimator
+                .track_tx(tx_hash.clone(), fee_rate, self.tip_number());
+        }
+        tx_pool.notify(TxPoolEvent::Added(tx_hash));
+        Ok(cycles)
     }
 
-    pub fn add_tx_to_pool(&self, tx: Transaction) -> Result<Cycle, PoolError> {
-        let mut tx_pool = self.tx_pool.borrow_mut();
+    /// Scripts are not run again when the cycles of the transaction are known
+    fn add_tx_with_cycles(
+        &self,
+        tx_pool: &mut TxPool,
+        tx: Transaction,
+        cycles: Option<Cycle>,
+    ) -> Result<Cycle, PoolError> {
+        SizeVerifier::new(&tx, &self.consensus)
+            .verify()
+            .map_err(PoolError::InvalidTx)?;
         let short_id = tx.proposal_short_id();
-        let rtx = self.resolve_tx_from_pending_and_staging(&tx, &tx_pool);
+        let rtx = self.resolve_tx_from_pending_and_staging(&tx, tx_pool);
 
-        self.verify_rtx(&rtx, None).map(|cycles| {
-            if self.contains_proposal_id(&short_id) {
-                // if tx is proposed, we resolve from staging, verify again
-                self.staging_tx_and_descendants(&mut tx_pool, Some(cycles), tx);
-            } else {
-                tx_pool.enqueue_tx(Some(cycles), tx);
+        let cycles = match self.verify_rtx(&rtx, cycles) {
+            Err(err) if err.is_immature() => {
+                self.delay_tx(tx_pool, cycles, tx);
+                return Err(err);
             }
-            cycles
-        })
+            ret => ret?,
+        };
+        let fee = Self::resolved_fee(&rtx);
+        if self.contains_proposal_id(&short_id) {
+            // if tx is proposed, we resolve from staging, verify again
+            self.staging_tx_and_descendants(tx_pool, Some(cycles), tx);
+        } else {
+            tx_pool.replace_pending(&tx, fee)?;
+            tx_pool.enqueue_tx(Some(cycles), fee, tx);
+        }
+        // the pool evicts the lowest fee rate transactions when it is full, which may be this one
+        if tx_pool.contains_proposal_id(&short_id) {
+            Ok(cycles)
+        } else {
+            Err(PoolError::OverCapacity)
+        }
+    }
+
+    /// Checks run before the scripts are queued for verification, the pool policy included,
+    /// returns the resolved input and dep cells for the scripts
+    pub(crate) fn verify_tx_without_script(
+        &self,
+        tx_pool: &TxPool,
+        tx: &Transaction,
+    ) -> Result<(Vec<CellStatus>, Vec<CellStatus>), PoolError> {
+        let policy = &tx_pool.config.policy;
+        let tx_size = tx.serialized_size();
+        if tx_size > policy.max_tx_size {
+            return Err(PoolError::ExceededMaxTxSize(policy.max_tx_size));
+        }
+        SizeVerifier::new(tx, &self.consensus)
+            .verify()
+            .map_err(PoolError::InvalidTx)?;
+        let rtx = self.resolve_tx_from_pending_and_staging(tx, tx_pool);
+        self.verify_rtx_inputs(&rtx)?;
+        if fee_rate(Self::resolved_fee(&rtx), tx_size) < policy.min_fee_rate {
+            return Err(PoolError::LowFeeRate(policy.min_fee_rate));
+        }
+        TransactionVerifier::new(
+            &rtx,
+            &self,
+            self.tip_number(),
+            self.consensus().cellbase_maturity,
+        )
+        .verify_without_script()
+        .map_err(PoolError::InvalidTx)?;
+        Ok((rtx.input_cells, rtx.dep_cells))
+    }
+
+    /// Fee of a resolved transaction, inputs which are not live count as zero
+    fn resolved_fee(rtx: &ResolvedTransaction) -> Capacity {
+        rtx.fee().unwrap_or_else(|_| Capacity::zero())
     }
 
     pub fn resolve_tx_from_pending_and_staging<'a>(
@@ -222,7 +540,7 @@ impl<CS: ChainStore> ChainState<CS> {
         let pending_and_staging_provider =
             OverlayCellProvider::new(&tx_pool.pending, &staging_provider);
         let mut seen_inputs = FnvHashSet::default();
-        resolve_transaction(tx, &mut seen_inputs, &pending_and_staging_provider)
+        resolve_cells(tx, &mut seen_inputs, &pending_and_staging_provider)
     }
 
     pub fn resolve_tx_from_staging<'a>(
@@ -232,48 +550,85 @@ impl<CS: ChainStore> ChainState<CS> {
     ) -> ResolvedTransaction<'a> {
         let cell_provider = OverlayCellProvider::new(&tx_pool.staging, self);
         let mut seen_inputs = FnvHashSet::default();
-        resolve_transaction(tx, &mut seen_inputs, &cell_provider)
+        resolve_cells(tx, &mut seen_inputs, &cell_provider)
     }
 
-    // FIXME: we may need redesign orphan pool, this is not short-circuiting
-    fn verify_rtx_inputs(&self, rtx: &ResolvedTransaction) -> Result<(), PoolError> {
-        let mut unknowns = Vec::new();
-        let inputs = rtx.transaction.input_pts();
-        let deps = rtx.transaction.dep_pts();
-        for (cs, input) in rtx.input_cells.iter().zip(inputs.iter()) {
-            match cs {
-                CellStatus::Unknown => {
-                    unknowns.push(input.clone());
-                }
-                CellStatus::Dead => {
-                    return Err(PoolError::Conflict);
-                }
-                CellStatus::Live(LiveCell::Null) => {
-                    return Err(PoolError::NullInput);
+    /// Explain how every input and dep of the transaction resolves, so that the caller
+    /// can tell a double spend from a missing or not yet confirmed cell.
+    pub fn cell_resolution_details(
+        &self,
+        tx_pool: &TxPool,
+        tx: &Transaction,
+    ) -> Vec<CellResolutionDetail> {
+        let rtx = self.resolve_tx_from_pending_and_staging(tx, tx_pool);
+        let inputs = rtx
+            .input_cells
+            .iter()
+            .zip(tx.input_pts().into_iter())
+            .map(|(cs, out_point)| (cs, out_point, false));
+        let deps = rtx
+            .dep_cells
+            .iter()
+            .zip(tx.dep_pts().into_iter())
+            .map(|(cs, out_point)| (cs, out_point, true));
+        inputs
+            .chain(deps)
+            .map(|(cs, out_point, is_dep)| {
+                let resolution = match cs {
+                    CellStatus::Live(_) => CellResolution::Live,
+                    CellStatus::Dead => CellResolution::Dead(
+                        tx_pool.staging.spent_by(&out_point).map(Transaction::hash),
+                    ),
+                    CellStatus::Unknown => CellResolution::Unknown,
+                };
+                CellResolutionDetail {
+                    out_point,
+                    is_dep,
+                    resolution,
                 }
-                _ => {}
-            }
-        }
+            })
+            .collect()
+    }
 
-        for (cs, dep) in rtx.dep_cells.iter().zip(deps.iter()) {
-            match cs {
-                CellStatus::Unknown => {
-                    unknowns.push(dep.clone());
-                }
-                CellStatus::Dead => {
-                    return Err(PoolError::Conflict);
-                }
-                CellStatus::Live(LiveCell::Null) => {
-                    return Err(PoolError::NullInput);
-                }
-                _ => {}
-            }
-        }
+    /// Resolve the transaction against the tip, or the pool too if `with_pool`, and run the
+    /// checks but the scripts, without adding it to the pool. The scripts are left to the
+    /// caller, which doesn't have to hold the locks while they run.
+    pub fn resolve_tx_for_dry_run<'a>(
+        &self,
+        tx_pool: &TxPool,
+        tx: &'a Transaction,
+        with_pool: bool,
+    ) -> Result<ResolvedTransaction<'a>, PoolError> {
+        SizeVerifier::new(tx, &self.consensus)
+            .verify()
+            .map_err(PoolError::InvalidTx)?;
+        let rtx = if with_pool {
+            self.resolve_tx_from_pending_and_staging(tx, tx_pool)
+        } else {
+            let mut seen_inputs = FnvHashSet::default();
+            resolve_cells(tx, &mut seen_inputs, self)
+        };
+        self.verify_rtx_inputs(&rtx)?;
+        TransactionVerifier::new(
+            &rtx,
+            &self,
+            self.tip_number(),
+            self.consensus().cellbase_maturity,
+        )
+        .verify_without_script()
+        .map_err(PoolError::InvalidTx)?;
+        Ok(rtx)
+    }
 
-        if !unknowns.is_empty() {
-            return Err(PoolError::UnknownInputs(unknowns));
+    fn verify_rtx_inputs(&self, rtx: &ResolvedTransaction) -> Result<(), PoolError> {
+        let null_input = |cell_status: &CellStatus| match cell_status {
+            CellStatus::Live(LiveCell::Null) => true,
+            _ => false,
+        };
+        if rtx.cells_iter().any(null_input) {
+            return Err(PoolError::NullInput);
         }
-        Ok(())
+        rtx.check_resolvable().map_err(PoolError::Unresolvable)
     }
 
     pub(crate) fn verify_rtx(
@@ -283,6 +638,13 @@ impl<CS: ChainStore> ChainState<CS> {
     ) -> Result<Cycle, PoolError> {
         self.verify_rtx_inputs(rtx)?;
 
+        let tx_hash = rtx.transaction.hash();
+        let cycles = cycles.or_else(|| {
+            self.txs_verify_cache
+                .lock()
+                .get_mut(&tx_hash)
+                .map(|cycles| *cycles)
+        });
         match cycles {
             Some(cycles) => {
                 PoolTransactionVerifier::new(
@@ -303,8 +665,9 @@ impl<CS: ChainStore> ChainState<CS> {
                     self.tip_number(),
                     self.consensus().cellbase_maturity,
                 )
-                .verify(max_cycles)
+                .verify_in_pool(max_cycles, &self.verification_pool)
                 .map_err(PoolError::InvalidTx)?;
+                self.txs_verify_cache.lock().insert(tx_hash, cycles);
                 Ok(cycles)
             }
         }
@@ -321,7 +684,7 @@ impl<CS: ChainStore> ChainState<CS> {
                     trace!(target: "tx_pool", "staging tx {:x} failed {:?}", tx_hash, ret);
                 }
             } else {
-                tx_pool.enqueue_tx(entry.cycles, entry.transaction);
+                tx_pool.enqueue_tx(entry.cycles, entry.fee, entry.transaction);
             }
         }
     }
@@ -336,20 +699,29 @@ impl<CS: ChainStore> ChainState<CS> {
         let tx_hash = tx.hash();
 
         let rtx = self.resolve_tx_from_staging(&tx, tx_pool);
+        let fee = Self::resolved_fee(&rtx);
 
         match self.verify_rtx(&rtx, cycles) {
-            Err(PoolError::Conflict) => {
+            Err(PoolError::Unresolvable(UnresolvableError::Unknown { inputs, deps })) => {
+                let unknowns = inputs.iter().chain(deps.iter()).cloned().collect();
+                tx_pool.add_orphan(cycles, fee, tx, unknowns);
+                Err(PoolError::Unresolvable(UnresolvableError::Unknown {
+                    inputs,
+                    deps,
+                }))
+            }
+            Err(err @ PoolError::Unresolvable(_)) => {
                 tx_pool
                     .conflict
-                    .insert(short_id, PoolEntry::new(tx, 0, cycles));
-                Err(PoolError::Conflict)
+                    .insert(short_id, PoolEntry::new(tx, 0, cycles, fee));
+                Err(err)
             }
-            Err(PoolError::UnknownInputs(unknowns)) => {
-                tx_pool.add_orphan(cycles, tx, unknowns.clone());
-                Err(PoolError::UnknownInputs(unknowns))
+            Err(ref err) if err.is_immature() => {
+                self.delay_tx(tx_pool, cycles, tx);
+                Err(err.clone())
             }
             Ok(cycles) => {
-                tx_pool.add_staging(cycles, tx);
+                tx_pool.add_staging(cycles, fee, tx);
                 Ok(cycles)
             }
             Err(e) => {
@@ -359,6 +731,43 @@ impl<CS: ChainStore> ChainState<CS> {
         }
     }
 
+    /// Keep the transaction, which spends immature cellbases or whose since conditions are
+    /// not met, until the tip may make it valid
+    pub(crate) fn delay_tx(&self, tx_pool: &mut TxPool, cycles: Option<Cycle>, tx: Transaction) {
+        let rtx = self.resolve_tx_from_pending_and_staging(&tx, tx_pool);
+        let maturity = PoolTransactionVerifier::new(
+            &rtx,
+            &self,
+            self.tip_number(),
+            self.consensus().cellbase_maturity,
+        )
+        .maturity();
+        let fee = Self::resolved_fee(&rtx);
+        tx_pool.add_delayed(cycles, fee, tx, maturity);
+    }
+
+    /// Check the delayed transactions which may be mature at the tip again, the proposed ones
+    /// are staged and the others are added to the pending queue
+    fn promote_mature_delayed(&self, tx_pool: &mut TxPool) {
+        let tip_number = self.tip_number();
+        let median_time = self
+            .block_median_time(tip_number.saturating_sub(1))
+            .unwrap_or(0);
+        for entry in tx_pool.remove_mature_delayed(tip_number, median_time) {
+            let tx_hash = entry.transaction.hash();
+            let ret = if self.contains_proposal_id(&entry.transaction.proposal_short_id()) {
+                self.staging_tx(tx_pool, entry.cycles, entry.transaction.clone())
+                    .map(|cycles| {
+                        self.try_staging_orphan_by_ancestor(tx_pool, &entry.transaction);
+                        cycles
+                    })
+            } else {
+                self.accept_tx(tx_pool, entry.transaction, entry.cycles)
+            };
+            trace!(target: "tx_pool", "promote delayed tx {:x}: {:?}", tx_hash, ret);
+        }
+    }
+
     pub(crate) fn staging_tx_and_descendants(
         &self,
         tx_pool: &mut TxPool,
@@ -377,72 +786,185 @@ impl<CS: ChainStore> ChainState<CS> {
 
     pub fn update_tx_pool_for_reorg<'a>(
         &self,
+        tx_pool: &mut TxPool,
         detached_blocks: impl Iterator<Item = &'a Block>,
         attached_blocks: impl Iterator<Item = &'a Block>,
         detached_proposal_id: impl Iterator<Item = &'a ProposalShortId>,
     ) {
-        let mut tx_pool = self.tx_pool.borrow_mut();
-
         let mut detached = FnvHashSet::default();
         let mut attached = FnvHashSet::default();
+        // hashes of the detached blocks by the hashes of their transactions
+        let mut detached_from = FnvHashMap::default();
+        // positions of the detached transactions in the chain, parents come first
+        let mut detached_positions = FnvHashMap::default();
 
         for blk in detached_blocks {
+            for (index, tx) in blk.transactions().iter().enumerate().skip(1) {
+                detached_from.insert(tx.hash(), blk.header().hash());
+                detached_positions.insert(tx.hash(), (blk.header().number(), index));
+            }
             detached.extend(blk.transactions().iter().skip(1).cloned())
         }
 
         for blk in attached_blocks {
+            tx_pool.trace_attached_block(blk);
+            tx_pool.fee_estimator.process_block(
+                blk.header().number(),
+                blk.transactions().iter().skip(1).map(Transaction::hash),
+            );
+            for tx in blk.transactions().iter().skip(1) {
+                tx_pool.set_tx_status(
+                    tx.hash(),
+                    TxStatus::Committed {
+                        block_hash: blk.header().hash(),
+                    },
+                );
+            }
             attached.extend(blk.transactions().iter().skip(1).cloned())
         }
 
-        let retain: Vec<Transaction> = detached.difference(&attached).cloned().collect();
+        let mut retain: Vec<Transaction> = detached.difference(&attached).cloned().collect();
+        // the parents are added back before their children, so the fees of the children
+        // are known
+        retain.sort_by_key(|tx| detached_positions.get(&tx.hash()).cloned());
 
         tx_pool.remove_expired(detached_proposal_id);
         tx_pool.remove_committed_txs_from_staging(attached.iter());
 
         for tx in retain {
+            // the transaction is added back to the pool, it is not a new one
+            if let Some(block_hash) = detached_from.remove(&tx.hash()) {
+                tx_pool.set_tx_status(tx.hash(), TxStatus::Reorged { block_hash });
+            }
             if self.contains_proposal_id(&tx.proposal_short_id()) {
-                self.staging_tx_and_descendants(&mut tx_pool, None, tx);
+                self.staging_tx_and_descendants(tx_pool, None, tx);
             } else {
-                tx_pool.enqueue_tx(None, tx);
+                // the inputs are live again or in the pool, the fee is known as it was
+                // before the reorg
+                let fee =
+                    Self::resolved_fee(&self.resolve_tx_from_pending_and_staging(&tx, tx_pool));
+                tx_pool.enqueue_tx(None, fee, tx);
             }
         }
 
         for tx in &attached {
-            self.try_staging_orphan_by_ancestor(&mut tx_pool, tx);
+            self.try_staging_orphan_by_ancestor(tx_pool, tx);
         }
 
         for id in self.get_proposal_ids_iter() {
             if let Some(entry) = tx_pool.remove_pending_and_conflict(id) {
-                self.staging_tx_and_descendants(&mut tx_pool, entry.cycles, entry.transaction);
+                self.staging_tx_and_descendants(tx_pool, entry.cycles, entry.transaction);
             }
         }
+
+        self.promote_mature_delayed(tx_pool);
+
+        // the scripts of queued transactions whose inputs are spent or gone are not worth running
+        let pool: &TxPool = tx_pool;
+        let invalidated: Vec<ProposalShortId> = pool
+            .verifying
+            .iter()
+            .filter(|(_, entry)| {
+                let rtx = self.resolve_tx_from_pending_and_staging(&entry.transaction, pool);
+                self.verify_rtx_inputs(&rtx).is_err()
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in invalidated {
+            tx_pool.cancel_verifying(&id);
+        }
     }
 
-    pub fn get_last_txs_updated_at(&self) -> u64 {
-        self.tx_pool.borrow().last_txs_updated_at
+    /// Status of the transaction in the pool or the main chain, `None` if it is unknown
+    pub fn tx_status(&self, tx_pool: &TxPool, tx_hash: &H256) -> Option<TxStatus> {
+        tx_pool.get_tx_status(tx_hash).or_else(|| {
+            self.store
+                .get_transaction_address(tx_hash)
+                .map(|address| TxStatus::Committed {
+                    block_hash: address.block_hash,
+                })
+        })
     }
 
-    pub fn get_proposal_and_staging_txs(
-        &self,
-        max_prop: usize,
-        max_tx: usize,
-    ) -> (Vec<ProposalShortId>, Vec<PoolEntry>) {
-        let tx_pool = self.tx_pool.borrow();
-        let proposal = tx_pool.pending.fetch(max_prop);
-        let staging_txs = tx_pool.staging.get_txs(max_tx);
-        (proposal, staging_txs)
+    pub fn consensus(&self) -> Arc<Consensus> {
+        Arc::clone(&self.consensus)
     }
 
-    pub fn tx_pool(&self) -> Ref<TxPool> {
-        self.tx_pool.borrow()
+    /// Reward of the block `number` by the consensus, the fees excluded
+    pub fn block_reward(&self, _number: BlockNumber) -> Capacity {
+        // TODO: block reward calculation algorithm
+        self.consensus.initial_block_reward()
     }
 
-    pub fn mut_tx_pool(&mut self) -> &mut TxPool {
-        self.tx_pool.get_mut()
+    /// The most capacity issued up to the block `number`: the outputs of the genesis block and
+    /// the full rewards of the blocks after it. The fees only move capacity around, and what
+    /// the cellbases leave unclaimed is burnt but not subtracted.
+    pub fn max_issuance(&self, number: BlockNumber) -> Option<Capacity> {
+        let genesis = self
+            .consensus
+            .genesis_block()
+            .transactions()
+            .iter()
+            .try_fold(Capacity::zero(), |total, tx| {
+                total.safe_add(tx.outputs_capacity().ok()?).ok()
+            })?;
+        // every block has the same reward until the reward schedule is parameterized
+        let rewards = self.block_reward(number).as_u64().checked_mul(number)?;
+        genesis.safe_add(Capacity::shannons(rewards)).ok()
     }
 
-    pub fn consensus(&self) -> Arc<Consensus> {
-        Arc::clone(&self.consensus)
+    /// The most the cellbase of the block `number` may claim, `fees` are the fees of the
+    /// other transactions of the block
+    pub fn expected_cellbase_capacity(
+        &self,
+        number: BlockNumber,
+        fees: Capacity,
+    ) -> Result<Capacity, VerificationError> {
+        Ok(self.block_reward(number).safe_add(fees)?)
+    }
+
+    /// Fees of the transactions of the stored block `block_hash`, `None` if the block or a
+    /// cell it spends is not stored
+    pub fn block_fees(&self, block_hash: &H256) -> Option<Capacity> {
+        if let Some(fees) = self.block_fees_cache.lock().get_mut(block_hash) {
+            return Some(*fees);
+        }
+        let txs = self.store.get_block_body(block_hash)?;
+        let outputs: FnvHashMap<H256, &[CellOutput]> =
+            txs.iter().map(|tx| (tx.hash(), tx.outputs())).collect();
+        let mut fees = Capacity::zero();
+        for tx in txs.iter().filter(|tx| !tx.is_cellbase()) {
+            let mut inputs_capacity = Capacity::zero();
+            for out_point in tx.input_pts() {
+                let index = out_point.index as usize;
+                let capacity = match outputs.get(&out_point.tx_hash) {
+                    Some(outputs) => outputs.get(index).map(|output| output.capacity),
+                    None => self
+                        .store
+                        .get_cell_output(&out_point.tx_hash, out_point.index)
+                        .or_else(|| {
+                            self.store
+                                .get_transaction(&out_point.tx_hash)
+                                .and_then(|(tx, _)| tx.outputs().get(index).cloned())
+                        })
+                        .map(|output| output.capacity),
+                }?;
+                inputs_capacity = inputs_capacity.safe_add(capacity).ok()?;
+            }
+            let outputs_capacity = tx.outputs_capacity().ok()?;
+            if inputs_capacity > outputs_capacity {
+                fees = fees
+                    .safe_add(inputs_capacity.safe_sub(outputs_capacity).ok()?)
+                    .ok()?;
+            }
+        }
+        self.insert_block_fees(block_hash.clone(), fees);
+        Some(fees)
+    }
+
+    /// Remember the fees of a block computed while verifying it
+    pub fn insert_block_fees(&self, block_hash: H256, fees: Capacity) {
+        self.block_fees_cache.lock().insert(block_hash, fees);
     }
 
     pub fn new_cell_set_overlay<'a>(
@@ -454,15 +976,30 @@ impl<CS: ChainStore> ChainState<CS> {
             overlay: self.cell_set.new_overlay(diff),
             store: Arc::clone(&self.store),
             outputs,
+            script_hash_cache: &self.script_hash_cache,
         }
     }
 }
 
+/// Fees of the resolved transactions of a block, the cellbase excluded. Inputs which are not
+/// live count as zero.
+pub fn transactions_fee(resolved: &[ResolvedTransaction]) -> Result<Capacity, VerificationError> {
+    let fees = resolved
+        .iter()
+        .filter(|rtx| !rtx.transaction.is_cellbase())
+        .map(ResolvedTransaction::fee)
+        .try_fold(Capacity::zero(), |acc, rhs| {
+            rhs.and_then(|x| acc.safe_add(x))
+        })?;
+    Ok(fees)
+}
+
 #[allow(dead_code)] // incorrect lint
 pub struct ChainCellSetOverlay<'a, CS> {
     pub(crate) overlay: CellSetOverlay<'a>,
     pub(crate) store: Arc<CS>,
     pub(crate) outputs: &'a FnvHashMap<H256, &'a [CellOutput]>,
+    pub(crate) script_hash_cache: &'a ScriptHashCache,
 }
 
 #[cfg(not(test))]
@@ -473,12 +1010,18 @@ impl<CS: ChainStore> CellProvider for ChainState<CS> {
                 if tx_meta.is_dead(out_point.index as usize) {
                     CellStatus::Dead
                 } else {
-                    let (tx, _) = self
-                        .store
-                        .get_transaction(&out_point.tx_hash)
+                    let output = self
+                        .script_hash_cache
+                        .cell(out_point, || {
+                            self.store.get_cell_output_in_block(
+                                &out_point.tx_hash,
+                                out_point.index,
+                                tx_meta.block_number(),
+                            )
+                        })
                         .expect("store should be consistent with cell_set");
                     CellStatus::live_output(
-                        tx.outputs()[out_point.index as usize].clone(),
+                        output,
                         Some(tx_meta.block_number()),
                         tx_meta.is_cellbase(),
                     )
@@ -497,16 +1040,17 @@ impl<'a, CS: ChainStore> CellProvider for ChainCellSetOverlay<'a, CS> {
                 if tx_meta.is_dead(out_point.index as usize) {
                     CellStatus::Dead
                 } else {
-                    let output = self
-                        .outputs
-                        .get(&out_point.tx_hash)
-                        .map(|outputs| outputs[out_point.index as usize].clone())
-                        .or_else(|| {
-                            self.store
-                                .get_transaction(&out_point.tx_hash)
-                                .map(|(tx, _)| tx.outputs()[out_point.index as usize].clone())
-                        })
-                        .expect("store should be consistent with cell_set");
+                    let output = match self.outputs.get(&out_point.tx_hash) {
+                        Some(outputs) => Some(Arc::new(outputs[out_point.index as usize].clone())),
+                        None => self.script_hash_cache.cell(out_point, || {
+                            self.store.get_cell_output_in_block(
+                                &out_point.tx_hash,
+                                out_point.index,
+                                tx_meta.block_number(),
+                            )
+                        }),
+                    }
+                    .expect("store should be consistent with cell_set");
 
                     CellStatus::live_output(
                         output,
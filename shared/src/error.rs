@@ -11,4 +11,6 @@ pub enum SharedError {
     InvalidData(String),
     #[fail(display = "DB error: {}", _0)]
     DB(DBError),
+    #[fail(display = "InvalidConfig: {}", _0)]
+    InvalidConfig(String),
 }
@@ -0,0 +1,171 @@
+//! Fee rate estimation from the confirmation delay of the transactions accepted into the pool.
+//!
+//! Transactions are bucketed by fee rate when they enter the pool, and the number of blocks
+//! until they are committed is recorded in their bucket. The estimate for a target is the
+//! lowest fee rate whose bucket, and every bucket above it, had most transactions committed
+//! within the target.
+
+use ckb_core::header::BlockNumber;
+use fnv::FnvHashMap;
+use numext_fixed_hash::H256;
+
+/// Estimates are made for confirmation targets up to this many blocks, transactions not
+/// committed in time are counted as failures of their bucket
+pub const MAX_CONFIRM_BLOCKS: usize = 48;
+/// Upper bound of the first bucket in shannons per 1000 bytes
+const MIN_BUCKET_FEE_RATE: u64 = 1000;
+const MAX_BUCKET_FEE_RATE: u64 = 100_000_000;
+/// Older blocks weigh less, the weight halves about every 350 blocks
+const DECAY: f64 = 0.998;
+/// Consecutive buckets are grouped until they have this many samples
+const SUFFICIENT_SAMPLES: f64 = 10.0;
+/// Part of the transactions which must be committed within the target
+const SUCCESS_THRESHOLD: f64 = 0.85;
+
+#[derive(Debug, Clone)]
+pub struct FeeEstimator {
+    /// Lower bounds of the buckets
+    bucket_bounds: Vec<u64>,
+    /// `confirmed[t][b]` is the transactions of bucket `b` committed within `t + 1` blocks
+    confirmed: Vec<Vec<f64>>,
+    /// Transactions of each bucket which are committed or given up
+    total: Vec<f64>,
+    /// Tracked transactions with the tip number when they entered the pool and their bucket
+    tracked: FnvHashMap<H256, (BlockNumber, usize)>,
+}
+
+impl Default for FeeEstimator {
+    fn default() -> Self {
+        let mut bucket_bounds = vec![0];
+        let mut bound = MIN_BUCKET_FEE_RATE;
+        while bound <= MAX_BUCKET_FEE_RATE {
+            bucket_bounds.push(bound);
+            bound = bound * 3 / 2;
+        }
+        let buckets = bucket_bounds.len();
+        FeeEstimator {
+            bucket_bounds,
+            confirmed: vec![vec![0.0; buckets]; MAX_CONFIRM_BLOCKS],
+            total: vec![0.0; buckets],
+            tracked: FnvHashMap::default(),
+        }
+    }
+}
+
+impl FeeEstimator {
+    pub fn new() -> Self {
+        FeeEstimator::default()
+    }
+
+    fn bucket_index(&self, fee_rate: u64) -> usize {
+        match self.bucket_bounds.binary_search(&fee_rate) {
+            Ok(index) => index,
+            Err(index) => index - 1,
+        }
+    }
+
+    /// Start tracking a transaction accepted into the pool when the tip is `tip_number`
+    pub fn track_tx(&mut self, tx_hash: H256, fee_rate: u64, tip_number: BlockNumber) {
+        let bucket = self.bucket_index(fee_rate);
+        self.tracked.insert(tx_hash, (tip_number, bucket));
+    }
+
+    /// Record the tracked transactions committed in the new tip block `number`
+    pub fn process_block(&mut self, number: BlockNumber, tx_hashes: impl Iterator<Item = H256>) {
+        for counts in self.confirmed.iter_mut() {
+            counts.iter_mut().for_each(|count| *count *= DECAY);
+        }
+        self.total.iter_mut().for_each(|count| *count *= DECAY);
+
+        for tx_hash in tx_hashes {
+            if let Some((entered, bucket)) = self.tracked.remove(&tx_hash) {
+                let blocks = number.saturating_sub(entered).max(1) as usize;
+                self.total[bucket] += 1.0;
+                for counts in self.confirmed.iter_mut().skip(blocks - 1) {
+                    counts[bucket] += 1.0;
+                }
+            }
+        }
+
+        let total = &mut self.total;
+        self.tracked.retain(|_, (entered, bucket)| {
+            if number.saturating_sub(*entered) > MAX_CONFIRM_BLOCKS as u64 {
+                total[*bucket] += 1.0;
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// The lowest fee rate in shannons per 1000 bytes to get a transaction committed within
+    /// `target_blocks`, `None` if there isn't enough data
+    pub fn estimate_fee_rate(&self, target_blocks: usize) -> Option<u64> {
+        if target_blocks == 0 {
+            return None;
+        }
+        let confirmed = &self.confirmed[target_blocks.min(MAX_CONFIRM_BLOCKS) - 1];
+        let mut estimate = None;
+        let mut group_confirmed = 0.0;
+        let mut group_total = 0.0;
+        for bucket in (0..self.bucket_bounds.len()).rev() {
+            group_confirmed += confirmed[bucket];
+            group_total += self.total[bucket];
+            if group_total < SUFFICIENT_SAMPLES {
+                continue;
+            }
+            if group_confirmed / group_total < SUCCESS_THRESHOLD {
+                break;
+            }
+            estimate = Some(self.bucket_bounds[bucket]);
+            group_confirmed = 0.0;
+            group_total = 0.0;
+        }
+        estimate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::iter;
+
+    fn tx_hash(i: u64) -> H256 {
+        H256::from_trimmed_hex_str(&format!("{:x}", i)).unwrap()
+    }
+
+    #[test]
+    fn test_estimate_fee_rate() {
+        let mut estimator = FeeEstimator::new();
+        assert_eq!(estimator.estimate_fee_rate(1), None);
+
+        // high fee rate transactions are committed in the next block, low fee rate ones
+        // are committed after 10 blocks
+        for i in 0..20 {
+            estimator.track_tx(tx_hash(i), 5000, 0);
+            estimator.track_tx(tx_hash(100 + i), 1000, 0);
+        }
+        estimator.process_block(1, (0..20).map(tx_hash));
+        for number in 2..10 {
+            estimator.process_block(number, iter::empty());
+        }
+        estimator.process_block(10, (100..120).map(tx_hash));
+
+        assert_eq!(estimator.estimate_fee_rate(1), Some(3375));
+        assert_eq!(estimator.estimate_fee_rate(10), Some(1000));
+        assert_eq!(estimator.estimate_fee_rate(0), None);
+    }
+
+    #[test]
+    fn test_give_up_unconfirmed() {
+        let mut estimator = FeeEstimator::new();
+        for i in 0..20 {
+            estimator.track_tx(tx_hash(i), 1000, 0);
+        }
+        for number in 1..=(MAX_CONFIRM_BLOCKS as u64 + 1) {
+            estimator.process_block(number, iter::empty());
+        }
+        assert!(estimator.tracked.is_empty());
+        assert_eq!(estimator.estimate_fee_rate(MAX_CONFIRM_BLOCKS), None);
+    }
+}
@@ -7,21 +7,28 @@
 //! - [Chain](chain::chain::Chain) represent a struct which
 //!   implement `ChainProvider`
 
+pub mod block_stage;
+pub mod candidate_uncles;
 pub mod cell_set;
 pub mod chain_state;
 pub mod error;
+pub mod fee_estimator;
 mod flat_serializer;
+pub mod median_time;
 pub mod shared;
+pub mod snapshot;
 pub mod store;
+pub mod store_verifier;
 pub mod tx_pool;
 mod tx_proposal_table;
+pub mod tx_verify_queue;
 
 #[cfg(test)]
 mod tests;
 
 use ckb_db::Col;
 
-pub const COLUMNS: u32 = 9;
+pub const COLUMNS: u32 = 14;
 pub const COLUMN_INDEX: Col = 0;
 pub const COLUMN_BLOCK_HEADER: Col = 1;
 pub const COLUMN_BLOCK_BODY: Col = 2;
@@ -31,3 +38,8 @@ pub const COLUMN_TRANSACTION_ADDR: Col = 5;
 pub const COLUMN_EXT: Col = 6;
 pub const COLUMN_BLOCK_TRANSACTION_ADDRESSES: Col = 7;
 pub const COLUMN_BLOCK_PROPOSAL_IDS: Col = 8;
+pub const COLUMN_REORG: Col = 9;
+pub const COLUMN_CELL_SET: Col = 10;
+pub const COLUMN_SKIP_HASH: Col = 11;
+pub const COLUMN_LOCK_CELLS: Col = 12;
+pub const COLUMN_CELL_OUTPUTS: Col = 13;
@@ -0,0 +1,105 @@
+use crate::shared::Shared;
+use crate::store::ChainStore;
+use ckb_core::block::Block;
+use ckb_core::header::{BlockNumber, Header};
+use ckb_traits::BlockMedianTimeContext;
+use fnv::FnvHashMap;
+use numext_fixed_hash::H256;
+use std::cmp;
+
+/// Median time context of the branch ending at `tip`.
+///
+/// Ancestors are resolved by walking parent hashes rather than canonical block numbers,
+/// so a block on a side branch is checked against the timestamps of its own ancestors.
+/// Blocks which are not stored yet, e.g. the attached blocks of a fork, are supplied
+/// by `with_blocks`.
+pub struct BranchMedianTimeContext<'a, CS> {
+    shared: &'a Shared<CS>,
+    tip_hash: H256,
+    tip_number: BlockNumber,
+    pending: FnvHashMap<H256, (u64, H256)>,
+}
+
+impl<'a, CS: ChainStore> BranchMedianTimeContext<'a, CS> {
+    pub fn new(shared: &'a Shared<CS>, tip: &Header) -> Self {
+        BranchMedianTimeContext {
+            shared,
+            tip_hash: tip.hash(),
+            tip_number: tip.number(),
+            pending: FnvHashMap::default(),
+        }
+    }
+
+    pub fn with_blocks(mut self, blocks: &[Block]) -> Self {
+        self.pending.extend(blocks.iter().map(|block| {
+            let header = block.header();
+            (
+                header.hash(),
+                (header.timestamp(), header.parent_hash().to_owned()),
+            )
+        }));
+        self
+    }
+
+    fn timestamp_and_parent(&self, hash: &H256) -> Option<(u64, H256)> {
+        self.pending
+            .get(hash)
+            .cloned()
+            .or_else(|| self.shared.header_timestamp_and_parent(hash))
+    }
+
+    fn is_main_chain(&self, hash: &H256, number: BlockNumber) -> bool {
+        !self.pending.contains_key(hash)
+            && self.shared.store().get_block_hash(number).as_ref() == Some(hash)
+    }
+
+    /// hash of the ancestor with `number` on this branch
+    fn ancestor_hash(&self, number: BlockNumber) -> Option<H256> {
+        if number > self.tip_number {
+            return None;
+        }
+        let mut hash = self.tip_hash.clone();
+        let mut current = self.tip_number;
+        while current > number {
+            // below the fork point the branch is the main chain, look it up by number
+            if self.is_main_chain(&hash, current) {
+                return self.shared.store().get_block_hash(number);
+            }
+            hash = self.timestamp_and_parent(&hash)?.1;
+            current -= 1;
+        }
+        Some(hash)
+    }
+}
+
+impl<'a, CS: ChainStore> BlockMedianTimeContext for BranchMedianTimeContext<'a, CS> {
+    fn median_block_count(&self) -> u64 {
+        self.shared.consensus().median_time_block_count() as u64
+    }
+
+    fn timestamp(&self, block_number: BlockNumber) -> Option<u64> {
+        self.ancestor_hash(block_number)
+            .and_then(|hash| self.timestamp_and_parent(&hash))
+            .map(|(timestamp, _)| timestamp)
+    }
+
+    fn ancestor_timestamps(&self, block_number: BlockNumber) -> Vec<u64> {
+        // same window as the default implementation, `block_number - count ..= block_number`
+        let count = cmp::min(self.median_block_count() + 1, block_number + 1);
+        let mut hash = match self.ancestor_hash(block_number) {
+            Some(hash) => hash,
+            None => return Vec::new(),
+        };
+        let mut timestamps = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            match self.timestamp_and_parent(&hash) {
+                Some((timestamp, parent_hash)) => {
+                    timestamps.push(timestamp);
+                    hash = parent_hash;
+                }
+                None => break,
+            }
+        }
+        timestamps
+    }
+}
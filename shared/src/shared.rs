@@ -1,27 +1,51 @@
+use crate::block_stage::BlockStageMetrics;
+use crate::candidate_uncles::CandidateUncles;
 use crate::chain_state::ChainState;
 use crate::error::SharedError;
+use crate::median_time::BranchMedianTimeContext;
+use crate::snapshot::StateSnapshot;
 use crate::store::ChainKVStore;
 use crate::store::ChainStore;
-use crate::tx_pool::TxPoolConfig;
+use crate::store::StoreBatch;
+use crate::store::StoreConfig;
+use crate::tx_pool::{PoolError, TxPool, TxPoolConfig, TxStatus};
+use crate::tx_verify_queue::{TxVerification, TxVerifyQueue};
 use crate::{COLUMNS, COLUMN_BLOCK_HEADER};
 use ckb_chain_spec::consensus::Consensus;
 use ckb_core::block::Block;
 use ckb_core::extras::BlockExt;
 use ckb_core::header::{BlockNumber, Header};
+use ckb_core::script_cache::ScriptHashCache;
 use ckb_core::transaction::{Capacity, ProposalShortId, Transaction};
 use ckb_core::uncle::UncleBlock;
-use ckb_db::{CacheDB, DBConfig, KeyValueDB, MemoryKeyValueDB, RocksDB};
+use ckb_core::Cycle;
+use ckb_db::{BackendDB, CacheDB, DBConfig, KeyValueDB, MemoryKeyValueDB};
 use ckb_traits::ChainProvider;
-use ckb_util::Mutex;
+use ckb_util::{Mutex, RwLock};
+use ckb_verification::{VerificationConfig, VerificationPool};
+use lru_cache::LruCache;
 use numext_fixed_hash::H256;
 use numext_fixed_uint::U256;
 use std::sync::Arc;
 
+const HEADER_TIMESTAMPS_CACHE_SIZE: usize = 4096;
+const SCRIPT_HASH_CACHE_SIZE: usize = 1024;
+
 #[derive(Debug)]
 pub struct Shared<CS> {
     store: Arc<CS>,
-    chain_state: Arc<Mutex<ChainState<CS>>>,
+    chain_state: Arc<RwLock<Arc<ChainState<CS>>>>,
+    tx_pool: Arc<Mutex<TxPool>>,
+    pub(crate) tx_verify_queue: TxVerifyQueue,
+    txs_verify_cache: Arc<Mutex<LruCache<H256, Cycle>>>,
+    script_hash_cache: Arc<ScriptHashCache>,
+    candidate_uncles: Arc<Mutex<CandidateUncles>>,
     consensus: Arc<Consensus>,
+    verification_pool: VerificationPool,
+    store_config: Arc<StoreConfig>,
+    // header hash => (timestamp, parent hash), used to walk header chains by hash
+    header_timestamps: Arc<Mutex<LruCache<H256, (u64, H256)>>>,
+    block_stage_metrics: Arc<BlockStageMetrics>,
 }
 
 // https://github.com/rust-lang/rust/issues/40754
@@ -30,7 +54,16 @@ impl<CS: ChainStore> ::std::clone::Clone for Shared<CS> {
         Shared {
             store: Arc::clone(&self.store),
             chain_state: Arc::clone(&self.chain_state),
+            tx_pool: Arc::clone(&self.tx_pool),
+            tx_verify_queue: self.tx_verify_queue.clone(),
+            txs_verify_cache: Arc::clone(&self.txs_verify_cache),
+            script_hash_cache: Arc::clone(&self.script_hash_cache),
+            candidate_uncles: Arc::clone(&self.candidate_uncles),
             consensus: Arc::clone(&self.consensus),
+            verification_pool: self.verification_pool.clone(),
+            store_config: Arc::clone(&self.store_config),
+            header_timestamps: Arc::clone(&self.header_timestamps),
+            block_stage_metrics: Arc::clone(&self.block_stage_metrics),
         }
     }
 }
@@ -40,29 +73,214 @@ impl<CS: ChainStore> Shared<CS> {
         store: CS,
         consensus: Consensus,
         tx_pool_config: TxPoolConfig,
+        verification_pool: VerificationPool,
+        store_config: StoreConfig,
     ) -> Result<Self, SharedError> {
         let store = Arc::new(store);
         let consensus = Arc::new(consensus);
-        let chain_state = Arc::new(Mutex::new(ChainState::init(
+        let txs_verify_cache = Arc::new(Mutex::new(LruCache::new(
+            tx_pool_config.txs_verify_cache_size,
+        )));
+        let script_hash_cache = Arc::new(ScriptHashCache::new(SCRIPT_HASH_CACHE_SIZE));
+        let chain_state = ChainState::init(
             &store,
             Arc::clone(&consensus),
-            tx_pool_config,
-        )?));
+            verification_pool.clone(),
+            Arc::clone(&txs_verify_cache),
+            Arc::clone(&script_hash_cache),
+        )?;
+        chain_state.init_tx_index(&store_config)?;
+        chain_state.init_lock_index(&store_config)?;
+        let max_tx_cycles = tx_pool_config.policy.max_tx_cycles;
+        let mut tx_pool = TxPool::new(tx_pool_config);
+        chain_state.reload_tx_pool(&mut tx_pool)?;
+
+        let chain_state = Arc::new(RwLock::new(Arc::new(chain_state)));
+        let tx_pool = Arc::new(Mutex::new(tx_pool));
+        let tx_verify_queue = TxVerifyQueue::start(
+            verification_pool.clone(),
+            consensus.max_block_cycles(),
+            max_tx_cycles,
+            Arc::clone(&txs_verify_cache),
+            Arc::clone(&script_hash_cache),
+            Arc::clone(&chain_state),
+            Arc::clone(&tx_pool),
+        );
 
         Ok(Shared {
             store,
             chain_state,
+            tx_pool,
+            tx_verify_queue,
+            txs_verify_cache,
+            script_hash_cache,
+            candidate_uncles: Arc::new(Mutex::new(CandidateUncles::new())),
             consensus,
+            verification_pool,
+            store_config: Arc::new(store_config),
+            header_timestamps: Arc::new(Mutex::new(LruCache::new(HEADER_TIMESTAMPS_CACHE_SIZE))),
+            block_stage_metrics: Arc::new(BlockStageMetrics::default()),
         })
     }
 
-    pub fn chain_state(&self) -> &Mutex<ChainState<CS>> {
-        &self.chain_state
+    /// Snapshot of the chain at the tip, it stays the same while the tip moves on
+    pub fn chain_state(&self) -> Arc<ChainState<CS>> {
+        Arc::clone(&self.chain_state.read())
+    }
+
+    /// Publish a new snapshot, the chain service calls it while holding the tx pool lock
+    pub fn set_chain_state(&self, chain_state: Arc<ChainState<CS>>) {
+        *self.chain_state.write() = chain_state;
+    }
+
+    /// When the pool is updated against a snapshot, lock the pool first and load the
+    /// snapshot after, so that the snapshot is not replaced in between
+    pub fn tx_pool(&self) -> &Mutex<TxPool> {
+        &self.tx_pool
+    }
+
+    /// Take the snapshot of the state at the tip, see `StateSnapshot`
+    pub fn export_snapshot(&self) -> StateSnapshot {
+        let chain_state = self.chain_state();
+        StateSnapshot::new(
+            &*self.store,
+            &self.consensus,
+            chain_state.tip_header(),
+            chain_state.cell_set(),
+        )
+    }
+
+    /// Import a snapshot into a database which only has the genesis block, `checkpoint` is
+    /// the trusted hash of the snapshot tip. The chain state is reloaded at the new tip.
+    pub fn import_snapshot(
+        &self,
+        snapshot: &StateSnapshot,
+        checkpoint: &H256,
+    ) -> Result<(), SharedError> {
+        let _tx_pool = self.tx_pool.lock();
+        snapshot.import(&*self.store, &self.consensus, checkpoint)?;
+        let chain_state = ChainState::init(
+            &self.store,
+            Arc::clone(&self.consensus),
+            self.verification_pool.clone(),
+            Arc::clone(&self.txs_verify_cache),
+            Arc::clone(&self.script_hash_cache),
+        )?;
+        // the lock index only has the genesis cells, it is built again at the new tip
+        let mut batch = self.store.new_batch().map_err(SharedError::DB)?;
+        batch.set_lock_indexed(false).map_err(SharedError::DB)?;
+        batch.commit().map_err(SharedError::DB)?;
+        chain_state.init_lock_index(&self.store_config)?;
+        self.set_chain_state(Arc::new(chain_state));
+        Ok(())
+    }
+
+    /// Status of the transaction in the pool or the main chain, see `TxStatus`
+    pub fn tx_status(&self, tx_hash: &H256) -> Option<TxStatus> {
+        let tx_pool = self.tx_pool.lock();
+        self.chain_state().tx_status(&tx_pool, tx_hash)
+    }
+
+    /// Check the transaction against the tip and the pool, then queue its scripts for
+    /// verification, it is added to the pool once they pass. Returns without waiting for
+    /// the scripts, no lock is held while they run. Fails with `PoolError::Full` if
+    /// `max_verifying_size` transactions are queued already.
+    pub fn add_tx_to_pool(&self, tx: Transaction) -> Result<TxVerification, PoolError> {
+        self.queue_tx(tx, false)
+    }
+
+    /// Same as `add_tx_to_pool`, the transaction is remembered for rebroadcasting once added
+    pub fn add_local_tx_to_pool(&self, tx: Transaction) -> Result<TxVerification, PoolError> {
+        self.queue_tx(tx, true)
+    }
+
+    fn queue_tx(&self, tx: Transaction, local: bool) -> Result<TxVerification, PoolError> {
+        let mut tx_pool = self.tx_pool.lock();
+        if tx_pool.contains_proposal_id(&tx.proposal_short_id()) {
+            return Err(PoolError::Duplicate);
+        }
+        let tx_hash = tx.hash();
+        if let Some(err) = tx_pool.recently_rejected_bad_tx(&tx_hash) {
+            return Err(err);
+        }
+        // the scripts queued for verification are bounded, nothing pushes back otherwise
+        if tx_pool.is_verifying_full() {
+            return Err(PoolError::Full);
+        }
+        let chain_state = self.chain_state();
+        let (input_cells, dep_cells) = match chain_state.verify_tx_without_script(&tx_pool, &tx) {
+            Ok(cells) => cells,
+            Err(err) => {
+                if err.is_immature() {
+                    // the scripts are run once it matures
+                    chain_state.delay_tx(&mut tx_pool, None, tx);
+                } else {
+                    tx_pool.add_rejected(tx_hash, &err);
+                }
+                return Err(err);
+            }
+        };
+        let cancelled = tx_pool.add_verifying(tx.clone());
+        Ok(self
+            .tx_verify_queue
+            .push(tx, input_cells, dep_cells, local, cancelled))
+    }
+
+    /// Cycles of the transactions whose scripts passed, keyed by tx hash
+    pub fn txs_verify_cache(&self) -> &Mutex<LruCache<H256, Cycle>> {
+        &self.txs_verify_cache
+    }
+
+    /// Cells holding the script binaries and their data hashes, shared by the resolved
+    /// transactions and the script verifications
+    pub fn script_hash_cache(&self) -> &Arc<ScriptHashCache> {
+        &self.script_hash_cache
+    }
+
+    /// Stale blocks near the tip, the uncles of the assembled blocks are selected from them
+    pub fn candidate_uncles(&self) -> &Mutex<CandidateUncles> {
+        &self.candidate_uncles
     }
 
     pub fn store(&self) -> &Arc<CS> {
         &self.store
     }
+
+    pub fn store_config(&self) -> &StoreConfig {
+        &self.store_config
+    }
+
+    /// Thread pool to run expensive verifications in
+    pub fn verification_pool(&self) -> &VerificationPool {
+        &self.verification_pool
+    }
+
+    /// Latencies of the stages blocks pass from receipt to relay
+    pub fn block_stage_metrics(&self) -> &BlockStageMetrics {
+        &self.block_stage_metrics
+    }
+
+    /// Timestamp and parent hash of a stored header, cached since median time
+    /// calculation walks the same recent headers for every block
+    pub fn header_timestamp_and_parent(&self, hash: &H256) -> Option<(u64, H256)> {
+        if let Some(entry) = self.header_timestamps.lock().get_mut(hash) {
+            return Some(entry.clone());
+        }
+        let entry = self
+            .store
+            .get_header(hash)
+            .map(|header| (header.timestamp(), header.parent_hash().to_owned()))?;
+        self.header_timestamps
+            .lock()
+            .insert(hash.clone(), entry.clone());
+        Some(entry)
+    }
+
+    /// Median time context walking the ancestors of `tip` by parent hashes,
+    /// which is also correct when `tip` is not on the main chain
+    pub fn branch_median_time_context(&self, tip: &Header) -> BranchMedianTimeContext<CS> {
+        BranchMedianTimeContext::new(self, tip)
+    }
 }
 
 impl<CS: ChainStore> ChainProvider for Shared<CS> {
@@ -110,9 +328,8 @@ impl<CS: ChainStore> ChainProvider for Shared<CS> {
         self.store.get_transaction_address(hash).is_some()
     }
 
-    fn block_reward(&self, _block_number: BlockNumber) -> Capacity {
-        // TODO: block reward calculation algorithm
-        self.consensus.initial_block_reward()
+    fn block_reward(&self, block_number: BlockNumber) -> Capacity {
+        self.chain_state().block_reward(block_number)
     }
 
     fn get_ancestor(&self, base: &H256, number: BlockNumber) -> Option<Header> {
@@ -128,24 +345,7 @@ impl<CS: ChainStore> ChainProvider for Shared<CS> {
         }
 
         // if base in the fork
-        if let Some(header) = self.block_header(base) {
-            let mut n_number = header.number();
-            let mut index_walk = header;
-            if number > n_number {
-                return None;
-            }
-
-            while n_number > number {
-                if let Some(header) = self.block_header(&index_walk.parent_hash()) {
-                    index_walk = header;
-                    n_number -= 1;
-                } else {
-                    return None;
-                }
-            }
-            return Some(index_walk);
-        }
-        None
+        self.store.get_ancestor(base, number)
     }
 
     // T_interval = L / C_m
@@ -202,6 +402,8 @@ pub struct SharedBuilder<DB: KeyValueDB> {
     db: Option<DB>,
     consensus: Option<Consensus>,
     tx_pool_config: Option<TxPoolConfig>,
+    verification_config: Option<VerificationConfig>,
+    store_config: Option<StoreConfig>,
 }
 
 impl<DB: KeyValueDB> Default for SharedBuilder<DB> {
@@ -210,6 +412,8 @@ impl<DB: KeyValueDB> Default for SharedBuilder<DB> {
             db: None,
             consensus: None,
             tx_pool_config: None,
+            verification_config: None,
+            store_config: None,
         }
     }
 }
@@ -220,18 +424,20 @@ impl SharedBuilder<MemoryKeyValueDB> {
             db: Some(MemoryKeyValueDB::open(COLUMNS as usize)),
             consensus: None,
             tx_pool_config: None,
+            verification_config: None,
+            store_config: None,
         }
     }
 }
 
-impl SharedBuilder<CacheDB<RocksDB>> {
+impl SharedBuilder<CacheDB<BackendDB>> {
     pub fn new() -> Self {
         Default::default()
     }
 
     pub fn db(mut self, config: &DBConfig) -> Self {
         self.db = Some(CacheDB::new(
-            RocksDB::open(config, COLUMNS),
+            BackendDB::open(config, COLUMNS),
             &[(COLUMN_BLOCK_HEADER, 4096)],
         ));
         self
@@ -251,10 +457,38 @@ impl<DB: KeyValueDB> SharedBuilder<DB> {
         self
     }
 
+    pub fn verification_config(mut self, config: VerificationConfig) -> Self {
+        self.verification_config = Some(config);
+        self
+    }
+
+    pub fn store_config(mut self, config: StoreConfig) -> Self {
+        self.store_config = Some(config);
+        self
+    }
+
     pub fn build(self) -> Result<Shared<ChainKVStore<DB>>, SharedError> {
         let store = ChainKVStore::new(self.db.unwrap());
         let consensus = self.consensus.unwrap_or_else(Consensus::default);
         let tx_pool_config = self.tx_pool_config.unwrap_or_else(Default::default);
-        Shared::init(store, consensus, tx_pool_config)
+        let verification_pool =
+            VerificationPool::shared(&self.verification_config.unwrap_or_else(Default::default));
+        let store_config = self.store_config.unwrap_or_else(Default::default);
+        if let Some(depth) = store_config.block_pruning_depth {
+            let min_depth = StoreConfig::min_block_pruning_depth(&consensus);
+            if depth < min_depth {
+                return Err(SharedError::InvalidConfig(format!(
+                    "block_pruning_depth {} is less than the minimum {}",
+                    depth, min_depth
+                )));
+            }
+        }
+        Shared::init(
+            store,
+            consensus,
+            tx_pool_config,
+            verification_pool,
+            store_config,
+        )
     }
 }
@@ -0,0 +1,252 @@
+//! State snapshots letting a fresh node start at a checkpoint instead of replaying the chain.
+//!
+//! A snapshot has the headers from the genesis to its tip, the cell set at the tip, and the
+//! blocks the node needs to go on from there: the blocks of the proposal window and the blocks
+//! of the transactions which still have live cells. The older blocks are not imported, only
+//! their headers.
+//!
+//! The cell set is not committed in the headers, it is trusted as part of the checkpoint. The
+//! importer checks that the tip is the checkpoint given by the operator, that the headers link
+//! up to the genesis of the consensus with valid seals, and that the blocks match the headers.
+
+use crate::cell_set::CellSet;
+use crate::error::SharedError;
+use crate::store::{ChainStore, StoreBatch};
+use bincode::{deserialize_from, serialize_into};
+use ckb_chain_spec::consensus::Consensus;
+use ckb_core::block::Block;
+use ckb_core::extras::BlockExt;
+use ckb_core::header::{BlockNumber, Header, HEADER_VERSION};
+use ckb_core::transaction_meta::TransactionMeta;
+use ckb_traits::skip_number;
+use ckb_verification::{MerkleRootVerifier, PowBatchVerifier};
+use fnv::FnvHashSet;
+use numext_fixed_hash::H256;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    /// Headers after the genesis up to the tip
+    pub headers: Vec<Header>,
+    /// Blocks of the proposal window ending at the tip and blocks with live cells, by number
+    pub blocks: Vec<Block>,
+    /// Cell set at the tip
+    pub cell_set: CellSet,
+}
+
+fn invalid_snapshot(reason: String) -> SharedError {
+    SharedError::InvalidData(format!("invalid snapshot: {}", reason))
+}
+
+/// The first block number of the proposal window ending at `tip_number`, the genesis block is
+/// never part of a snapshot
+fn proposal_window_start(consensus: &Consensus, tip_number: BlockNumber) -> BlockNumber {
+    tip_number
+        .saturating_sub(consensus.tx_proposal_window().start())
+        .max(1)
+}
+
+impl StateSnapshot {
+    /// Take the snapshot of the main chain ending at `tip_header` whose cell set is `cell_set`
+    pub fn new<CS: ChainStore>(
+        store: &CS,
+        consensus: &Consensus,
+        tip_header: &Header,
+        cell_set: &CellSet,
+    ) -> Self {
+        let tip_number = tip_header.number();
+        let headers: Vec<Header> = (1..=tip_number)
+            .map(|number| {
+                store
+                    .get_block_hash(number)
+                    .and_then(|hash| store.get_header(&hash))
+                    .expect("main chain header should be stored")
+            })
+            .collect();
+
+        let mut numbers: BTreeSet<BlockNumber> =
+            (proposal_window_start(consensus, tip_number)..=tip_number).collect();
+        numbers.extend(
+            cell_set
+                .inner
+                .values()
+                .filter(|meta| !meta.is_all_dead())
+                .map(TransactionMeta::block_number)
+                .filter(|number| *number > 0),
+        );
+        let blocks = numbers
+            .into_iter()
+            .map(|number| {
+                store
+                    .get_block(&headers[number as usize - 1].hash())
+                    .expect("blocks of a snapshot should be stored")
+            })
+            .collect();
+
+        StateSnapshot {
+            headers,
+            blocks,
+            cell_set: cell_set.clone(),
+        }
+    }
+
+    pub fn tip_header(&self) -> Option<&Header> {
+        self.headers.last()
+    }
+
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<(), SharedError> {
+        serialize_into(writer, self).map_err(|err| {
+            SharedError::InvalidData(format!("failed to write the snapshot: {}", err))
+        })
+    }
+
+    pub fn read_from<R: Read>(reader: R) -> Result<Self, SharedError> {
+        deserialize_from(reader).map_err(|err| invalid_snapshot(err.to_string()))
+    }
+
+    /// Check the snapshot against the consensus, `checkpoint` is the trusted tip hash
+    pub fn verify(&self, consensus: &Consensus, checkpoint: &H256) -> Result<(), SharedError> {
+        let tip_header = self
+            .tip_header()
+            .ok_or_else(|| invalid_snapshot("no headers".to_owned()))?;
+        if &tip_header.hash() != checkpoint {
+            return Err(invalid_snapshot(format!(
+                "the tip {:#x} is not the checkpoint {:#x}",
+                tip_header.hash(),
+                checkpoint
+            )));
+        }
+
+        let mut parent_hash = consensus.genesis_hash().to_owned();
+        for (index, header) in self.headers.iter().enumerate() {
+            if header.version() != HEADER_VERSION
+                || header.number() != index as BlockNumber + 1
+                || header.parent_hash() != &parent_hash
+            {
+                return Err(invalid_snapshot(format!(
+                    "header {} does not follow {:#x}",
+                    index + 1,
+                    parent_hash
+                )));
+            }
+            parent_hash = header.hash();
+        }
+        PowBatchVerifier::new(&self.headers, &consensus.pow_engine())
+            .verify()
+            .map_err(|(index, err)| invalid_snapshot(format!("header {}: {:?}", index + 1, err)))?;
+
+        let mut numbers = FnvHashSet::default();
+        let mut tx_hashes: FnvHashSet<H256> = consensus
+            .genesis_block()
+            .transactions()
+            .iter()
+            .map(|tx| tx.hash())
+            .collect();
+        for block in &self.blocks {
+            let number = block.header().number();
+            let header = number
+                .checked_sub(1)
+                .and_then(|index| self.headers.get(index as usize))
+                .ok_or_else(|| invalid_snapshot(format!("block {} has no header", number)))?;
+            if header.hash() != block.header().hash() {
+                return Err(invalid_snapshot(format!(
+                    "block {} does not match its header",
+                    number
+                )));
+            }
+            MerkleRootVerifier::new()
+                .verify(block)
+                .map_err(|err| invalid_snapshot(format!("block {}: {:?}", number, err)))?;
+            numbers.insert(number);
+            tx_hashes.extend(block.transactions().iter().map(|tx| tx.hash()));
+        }
+
+        let tip_number = tip_header.number();
+        if let Some(number) = (proposal_window_start(consensus, tip_number)..=tip_number)
+            .find(|number| !numbers.contains(number))
+        {
+            return Err(invalid_snapshot(format!(
+                "block {} of the proposal window is missing",
+                number
+            )));
+        }
+        if let Some(tx_hash) = self
+            .cell_set
+            .inner
+            .iter()
+            .filter(|(_, meta)| !meta.is_all_dead())
+            .map(|(tx_hash, _)| tx_hash)
+            .find(|tx_hash| !tx_hashes.contains(tx_hash))
+        {
+            return Err(invalid_snapshot(format!(
+                "transaction {:#x} with live cells is missing",
+                tx_hash
+            )));
+        }
+        Ok(())
+    }
+
+    /// Verify the snapshot and save it to `store`, which must only have the genesis block
+    pub fn import<CS: ChainStore>(
+        &self,
+        store: &CS,
+        consensus: &Consensus,
+        checkpoint: &H256,
+    ) -> Result<(), SharedError> {
+        self.verify(consensus, checkpoint)?;
+        match store.get_tip_header() {
+            Some(ref header) if header.number() == 0 => {}
+            _ => {
+                return Err(SharedError::InvalidData(
+                    "a snapshot can only be imported into a database with the genesis block only"
+                        .to_owned(),
+                ));
+            }
+        }
+        let genesis_ext = store
+            .get_block_ext(consensus.genesis_hash())
+            .ok_or_else(|| SharedError::InvalidData("failed to get block_ext".to_owned()))?;
+
+        let mut batch = store.new_batch().map_err(SharedError::DB)?;
+        let mut total_difficulty = genesis_ext.total_difficulty;
+        let mut total_uncles_count = genesis_ext.total_uncles_count;
+        for header in &self.headers {
+            total_difficulty = total_difficulty + header.difficulty();
+            total_uncles_count += u64::from(header.uncles_count());
+            let ext = BlockExt {
+                received_at: header.timestamp(),
+                total_difficulty: total_difficulty.clone(),
+                total_uncles_count,
+                txs_verified: Some(true),
+            };
+            let skip_hash = match skip_number(header.number()) {
+                0 => consensus.genesis_hash().to_owned(),
+                skip => self.headers[skip as usize - 1].hash(),
+            };
+            batch.insert_header(header).map_err(SharedError::DB)?;
+            batch
+                .insert_skip_hash(&header.hash(), &skip_hash)
+                .map_err(SharedError::DB)?;
+            batch
+                .insert_block_ext(&header.hash(), &ext)
+                .map_err(SharedError::DB)?;
+            batch.attach_header(header).map_err(SharedError::DB)?;
+        }
+        for block in &self.blocks {
+            batch.insert_block(block).map_err(SharedError::DB)?;
+            batch.attach_block(block).map_err(SharedError::DB)?;
+        }
+
+        let tip_header = self.tip_header().expect("verified snapshot has headers");
+        let changes = self.cell_set.replacing(store.get_cell_set_tx_hashes());
+        batch
+            .update_cell_set(&changes, &tip_header.hash())
+            .map_err(SharedError::DB)?;
+        batch
+            .insert_tip_header(tip_header)
+            .map_err(SharedError::DB)?;
+        batch.commit().map_err(SharedError::DB)
+    }
+}
@@ -1,21 +1,132 @@
+use crate::cell_set::{CellSet, CellSetChanges};
 use crate::flat_serializer::{serialize as flat_serialize, serialized_addresses, Address};
+use crate::tx_pool::PersistedTx;
 use crate::{
     COLUMN_BLOCK_BODY, COLUMN_BLOCK_HEADER, COLUMN_BLOCK_PROPOSAL_IDS,
-    COLUMN_BLOCK_TRANSACTION_ADDRESSES, COLUMN_BLOCK_UNCLE, COLUMN_EXT, COLUMN_INDEX, COLUMN_META,
+    COLUMN_BLOCK_TRANSACTION_ADDRESSES, COLUMN_BLOCK_UNCLE, COLUMN_CELL_OUTPUTS, COLUMN_CELL_SET,
+    COLUMN_EXT, COLUMN_INDEX, COLUMN_LOCK_CELLS, COLUMN_META, COLUMN_REORG, COLUMN_SKIP_HASH,
     COLUMN_TRANSACTION_ADDR,
 };
 use bincode::{deserialize, serialize};
+use ckb_chain_spec::consensus::Consensus;
 use ckb_core::block::{Block, BlockBuilder};
-use ckb_core::extras::{BlockExt, TransactionAddress};
+use ckb_core::extras::{BlockExt, LockCell, ReorgRecord, TransactionAddress};
 use ckb_core::header::{BlockNumber, Header, HeaderBuilder};
-use ckb_core::transaction::{ProposalShortId, Transaction, TransactionBuilder};
+use ckb_core::transaction::{
+    CellOutput, OutPoint, ProposalShortId, Transaction, TransactionBuilder,
+};
+use ckb_core::transaction_meta::TransactionMeta;
 use ckb_core::uncle::UncleBlock;
 use ckb_db::{Col, DbBatch, Error, KeyValueDB};
+use ckb_traits::HeaderProvider;
+use log::warn;
 use numext_fixed_hash::H256;
 use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use std::cmp;
 use std::ops::Range;
 
+/// The blocks are kept at least this deep below the tip when pruning, a fork reaching below
+/// the pruned height can't be switched to.
+pub const MIN_BLOCK_PRUNING_DEPTH: BlockNumber = 100;
+
 const META_TIP_HEADER_KEY: &[u8] = b"TIP_HEADER";
+const META_REORG_COUNT_KEY: &[u8] = b"REORG_COUNT";
+const META_TX_POOL_KEY: &[u8] = b"TX_POOL";
+const META_CELL_SET_TIP_KEY: &[u8] = b"CELL_SET_TIP";
+const META_PRUNED_HEIGHT_KEY: &[u8] = b"PRUNED_HEIGHT";
+const META_TX_INDEXED_KEY: &[u8] = b"TX_INDEXED";
+const META_LOCK_INDEXED_KEY: &[u8] = b"LOCK_INDEXED";
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StoreConfig {
+    /// Index the transactions of the most recent N blocks only, the whole chain when unset.
+    ///
+    /// Transactions which still have live outputs stay indexed until they are fully spent.
+    pub tx_index_retention: Option<BlockNumber>,
+    /// Delete the bodies, uncles and proposals of the blocks deeper than N below the tip,
+    /// the whole chain is kept when unset. The headers are kept, so are the blocks which
+    /// still have live outputs.
+    ///
+    /// It must be deeper than any reorg, the uncles age and the proposal window, the blocks
+    /// are read again when they are detached, see `min_block_pruning_depth`.
+    pub block_pruning_depth: Option<BlockNumber>,
+    /// Stop indexing the transactions by hash, the cells are then looked up in the block
+    /// bodies and only the pool transactions are found by hash. The entries already written
+    /// are kept, the index is backfilled when it is enabled again.
+    #[serde(default)]
+    pub disable_tx_index: bool,
+    /// Index the live cells by the hash of their lock script, which `get_cells_by_lock_hash`
+    /// then reads instead of scanning the blocks. The index is built on startup when enabled.
+    #[serde(default)]
+    pub lock_index: bool,
+}
+
+impl StoreConfig {
+    /// The lowest block number whose transactions are still indexed when the tip is `tip_number`
+    pub fn tx_index_cutoff(&self, tip_number: BlockNumber) -> Option<BlockNumber> {
+        self.tx_index_retention
+            .map(|retention| (tip_number + 1).saturating_sub(retention))
+    }
+
+    /// The shallowest pruning depth allowed by the consensus
+    pub fn min_block_pruning_depth(consensus: &Consensus) -> BlockNumber {
+        cmp::max(
+            cmp::max(
+                consensus.max_uncles_age() as BlockNumber,
+                consensus.tx_proposal_window().start(),
+            ),
+            MIN_BLOCK_PRUNING_DEPTH,
+        )
+    }
+
+    /// The lowest block number whose body is still kept when the tip is `tip_number`
+    pub fn block_pruning_cutoff(&self, tip_number: BlockNumber) -> Option<BlockNumber> {
+        self.block_pruning_depth
+            .map(|depth| (tip_number + 1).saturating_sub(depth))
+    }
+}
+
+// lock hash | tx hash | output index in big endian, so the cells of a lock are adjacent
+fn lock_cell_key(lock_hash: &H256, out_point: &OutPoint) -> Vec<u8> {
+    let mut key = Vec::with_capacity(68);
+    key.extend_from_slice(lock_hash.as_bytes());
+    key.extend_from_slice(out_point.tx_hash.as_bytes());
+    key.extend_from_slice(&out_point.index.to_be_bytes());
+    key
+}
+
+/// Where the outputs of a main chain transaction are in the body of its block
+#[derive(Serialize, Deserialize)]
+struct CellOutputsAddress {
+    block_hash: H256,
+    outputs: Vec<Address>,
+}
+
+// The addresses of the outputs of `tx` serialized at `offset`. The outputs follow the version,
+// the deps, the inputs and the length of the outputs.
+fn cell_output_addresses(tx: &Transaction, offset: usize) -> Vec<Address> {
+    let outputs_offset = offset
+        + bincode::serialized_size(&(tx.version(), tx.deps(), tx.inputs(), 0u64))
+            .expect("serialized size should be ok") as usize;
+    serialized_addresses(tx.outputs().iter())
+        .expect("serialize addresses should be ok")
+        .into_iter()
+        .map(|address| Address {
+            offset: outputs_offset + address.offset,
+            length: address.length,
+        })
+        .collect()
+}
+
+fn lock_cell_out_point(key: &[u8]) -> OutPoint {
+    let mut index = [0u8; 4];
+    index.copy_from_slice(&key[64..68]);
+    OutPoint::new(
+        H256::from_slice(&key[32..64]).expect("db safe access"),
+        u32::from_be_bytes(index),
+    )
+}
 
 pub struct ChainKVStore<T> {
     db: T,
@@ -35,10 +146,26 @@ impl<T: KeyValueDB> ChainKVStore<T> {
             .partial_read(col, key, range)
             .expect("db operation should be ok")
     }
+
+    fn traverse_lock_cells(&self, prefix: &[u8]) -> Vec<(OutPoint, LockCell)> {
+        let mut cells = Vec::new();
+        self.db
+            .traverse_prefix(COLUMN_LOCK_CELLS, prefix, |key, value| {
+                let out_point = lock_cell_out_point(key);
+                cells.push((
+                    out_point,
+                    deserialize(value).expect("deserialize lock cell"),
+                ));
+                Ok(())
+            })
+            .expect("db operation should be ok");
+        cells
+    }
 }
 
-/// Store interface by chain
-pub trait ChainStore: Sync + Send {
+/// Store interface by chain, the headers are linked by the skip pointers inserted with
+/// `insert_skip_hash`
+pub trait ChainStore: HeaderProvider + Sync + Send {
     /// Batch handle
     type Batch: StoreBatch;
     /// New a store batch handle
@@ -59,6 +186,10 @@ pub trait ChainStore: Sync + Send {
 
     /// Init by genesis
     fn init(&self, genesis: &Block) -> Result<(), Error>;
+    /// Roll the tip back to the latest main chain block which is fully stored and drop the
+    /// index above it, a block is written in one batch but older databases may have a
+    /// partially applied one. Returns the new tip if the store was repaired.
+    fn repair_tip(&self) -> Result<Option<Header>, Error>;
     /// Get block header hash by block number
     fn get_block_hash(&self, number: BlockNumber) -> Option<H256>;
     /// Get block number by block header hash
@@ -69,19 +200,108 @@ pub trait ChainStore: Sync + Send {
     fn get_transaction(&self, h: &H256) -> Option<(Transaction, H256)>;
     /// Get commit transaction address by it's hash
     fn get_transaction_address(&self, hash: &H256) -> Option<TransactionAddress>;
+    /// Get the transaction committed in the main chain block `number`, it is searched in the
+    /// block body when the transaction index doesn't have it
+    fn get_transaction_in_block(&self, hash: &H256, number: BlockNumber) -> Option<Transaction>;
+    /// Get an output of a main chain transaction without loading the whole transaction, it is
+    /// read from the block body at the address stored by `attach_block`
+    fn get_cell_output(&self, tx_hash: &H256, index: u32) -> Option<CellOutput>;
+    /// Get an output of the transaction committed in the main chain block `number`, the
+    /// transaction is loaded instead when its output addresses aren't stored
+    fn get_cell_output_in_block(
+        &self,
+        tx_hash: &H256,
+        index: u32,
+        number: BlockNumber,
+    ) -> Option<CellOutput>;
+    /// Whether the transaction index covers the whole main chain, see
+    /// `StoreConfig::disable_tx_index`
+    fn is_tx_indexed(&self) -> bool;
+    /// Get the live cells locked by `lock_hash`, ordered by their out points, see
+    /// `StoreConfig::lock_index`
+    fn get_lock_cells(&self, lock_hash: &H256) -> Vec<(OutPoint, LockCell)>;
+    /// Get all the cells of the lock index
+    fn get_all_lock_cells(&self) -> Vec<(OutPoint, LockCell)>;
+    /// Whether the lock index matches the main chain
+    fn is_lock_indexed(&self) -> bool;
+    /// Get the address of the `index`th transaction of a block, from the block body rather
+    /// than the transaction index
+    fn get_block_transaction_address(
+        &self,
+        block_hash: &H256,
+        index: usize,
+    ) -> Option<TransactionAddress>;
+    /// Get the number of recorded reorgs
+    fn get_reorg_count(&self) -> u64;
+    /// Get a reorg record by its index, records are indexed from 0 in the order they happened
+    fn get_reorg_record(&self, index: u64) -> Option<ReorgRecord>;
+    /// Get the transaction pool saved on the last shutdown
+    fn get_tx_pool_snapshot(&self) -> Option<Vec<PersistedTx>>;
+    /// Get the saved cell set if it was saved with the tip `tip_hash` and can be decoded
+    fn get_cell_set(&self, tip_hash: &H256) -> Option<CellSet>;
+    /// Get the transaction hashes of the saved cell set, whatever tip it was saved with
+    fn get_cell_set_tx_hashes(&self) -> Vec<H256>;
+    /// Get the number below which the blocks without live outputs are pruned, see
+    /// `StoreConfig::block_pruning_depth`
+    fn get_pruned_height(&self) -> Option<BlockNumber>;
 }
 
 pub trait StoreBatch {
     fn insert_block(&mut self, block: &Block) -> Result<(), Error>;
+    /// Insert the header without the block, for the blocks before an imported snapshot
+    fn insert_header(&mut self, header: &Header) -> Result<(), Error>;
+    /// Insert the skip pointer of the block `block_hash`, see `ckb_traits::skip_number`
+    fn insert_skip_hash(&mut self, block_hash: &H256, skip_hash: &H256) -> Result<(), Error>;
     fn insert_block_ext(&mut self, block_hash: &H256, ext: &BlockExt) -> Result<(), Error>;
     fn insert_tip_header(&mut self, header: &Header) -> Result<(), Error>;
+    /// Insert the reorg record at `index`, which must be the current reorg count
+    fn insert_reorg_record(&mut self, index: u64, record: &ReorgRecord) -> Result<(), Error>;
+    /// Save the transaction pool, replacing the previous snapshot
+    fn insert_tx_pool_snapshot(&mut self, txs: &[PersistedTx]) -> Result<(), Error>;
+    fn delete_tx_pool_snapshot(&mut self) -> Result<(), Error>;
+    /// Apply the changes to the saved cell set, which then matches the tip `tip_hash`
+    fn update_cell_set(&mut self, changes: &CellSetChanges, tip_hash: &H256) -> Result<(), Error>;
+    /// Delete the body, uncles and proposals of the block, its header is kept
+    fn prune_block(&mut self, block_hash: &H256) -> Result<(), Error>;
+    /// Delete the addresses of the outputs of the transaction stored by `attach_block`
+    fn delete_cell_outputs(&mut self, tx_hash: &H256) -> Result<(), Error>;
+    fn insert_pruned_height(&mut self, number: BlockNumber) -> Result<(), Error>;
+    /// Mark whether the transaction index covers the whole main chain
+    fn set_tx_indexed(&mut self, indexed: bool) -> Result<(), Error>;
+    fn insert_lock_cell(&mut self, out_point: &OutPoint, cell: &LockCell) -> Result<(), Error>;
+    fn delete_lock_cell(&mut self, lock_hash: &H256, out_point: &OutPoint) -> Result<(), Error>;
+    /// Mark whether the lock index matches the main chain
+    fn set_lock_indexed(&mut self, indexed: bool) -> Result<(), Error>;
 
     fn attach_block(&mut self, block: &Block) -> Result<(), Error>;
+    /// Index the header in the main chain, `attach_block` also indexes the transactions
+    fn attach_header(&mut self, header: &Header) -> Result<(), Error>;
     fn detach_block(&mut self, block: &Block) -> Result<(), Error>;
+    /// Remove the header from the main chain index, `detach_block` also removes the
+    /// transactions
+    fn detach_header(&mut self, header: &Header) -> Result<(), Error>;
+
+    fn insert_transaction_address(
+        &mut self,
+        tx_hash: &H256,
+        address: &TransactionAddress,
+    ) -> Result<(), Error>;
+    fn delete_transaction_address(&mut self, tx_hash: &H256) -> Result<(), Error>;
 
     fn commit(self) -> Result<(), Error>;
 }
 
+impl<T: KeyValueDB> HeaderProvider for ChainKVStore<T> {
+    fn header(&self, hash: &H256) -> Option<Header> {
+        self.get_header(hash)
+    }
+
+    fn skip_hash(&self, hash: &H256) -> Option<H256> {
+        self.get(COLUMN_SKIP_HASH, hash.as_bytes())
+            .map(|raw| H256::from_slice(&raw[..]).expect("db safe access"))
+    }
+}
+
 impl<T: KeyValueDB> ChainStore for ChainKVStore<T> {
     type Batch = DefaultStoreBatch<T::Batch>;
 
@@ -92,23 +312,23 @@ impl<T: KeyValueDB> ChainStore for ChainKVStore<T> {
     }
 
     fn get_block(&self, h: &H256) -> Option<Block> {
-        self.get_header(h).map(|header| {
-            let transactions = self
-                .get_block_body(h)
-                .expect("block transactions must be stored");
-            let uncles = self
-                .get_block_uncles(h)
-                .expect("block uncles must be stored");
-            let proposals = self
-                .get_block_proposal_txs_ids(h)
-                .expect("block proposal_ids must be stored");
+        let header = self.get_header(h)?;
+        // only the headers are stored for the blocks before an imported snapshot
+        let transactions = self.get_block_body(h)?;
+        let uncles = self
+            .get_block_uncles(h)
+            .expect("block uncles must be stored");
+        let proposals = self
+            .get_block_proposal_txs_ids(h)
+            .expect("block proposal_ids must be stored");
+        Some(
             BlockBuilder::default()
                 .header(header)
                 .uncles(uncles)
                 .transactions(transactions)
                 .proposals(proposals)
-                .build()
-        })
+                .build(),
+        )
     }
 
     fn get_header(&self, h: &H256) -> Option<Header> {
@@ -184,6 +404,63 @@ impl<T: KeyValueDB> ChainStore for ChainKVStore<T> {
         batch.commit()
     }
 
+    fn repair_tip(&self) -> Result<Option<Header>, Error> {
+        let tip_hash = match self.get(COLUMN_META, META_TIP_HEADER_KEY) {
+            Some(raw) => H256::from_slice(&raw[..]).expect("db safe access"),
+            None => return Ok(None),
+        };
+        let tip = self
+            .get_header(&tip_hash)
+            .ok_or_else(|| Error::DBError(format!("tip header {:#x} not found", tip_hash)))?;
+        let is_complete = |header: &Header| {
+            let hash = header.hash();
+            self.get_block_hash(header.number()).as_ref() == Some(&hash)
+                && self.get_block_ext(&hash).is_some()
+                && self.get_block_body(&hash).is_some()
+        };
+
+        let mut header = tip.clone();
+        while !is_complete(&header) {
+            if header.is_genesis() {
+                return Err(Error::DBError("genesis block is incomplete".to_owned()));
+            }
+            header = self.get_header(header.parent_hash()).ok_or_else(|| {
+                Error::DBError(format!("header {:#x} not found", header.parent_hash()))
+            })?;
+        }
+
+        let mut batch = self.new_batch()?;
+        let mut repaired = header != tip;
+        // the index of a main chain switch which didn't update the tip
+        let mut number = header.number() + 1;
+        while let Some(hash) = self.get_block_hash(number) {
+            match self.get_block(&hash) {
+                Some(block) => batch.detach_block(&block)?,
+                None => {
+                    let detached = self
+                        .get_header(&hash)
+                        .ok_or_else(|| Error::DBError(format!("header {:#x} not found", hash)))?;
+                    batch.detach_header(&detached)?;
+                }
+            }
+            repaired = true;
+            number += 1;
+        }
+        if !repaired {
+            return Ok(None);
+        }
+
+        warn!(
+            target: "chain",
+            "repair the partially applied blocks, roll the tip back from {} to {}",
+            tip.number(),
+            header.number()
+        );
+        batch.insert_tip_header(&header)?;
+        batch.commit()?;
+        Ok(Some(header))
+    }
+
     fn get_block_hash(&self, number: BlockNumber) -> Option<H256> {
         self.get(COLUMN_INDEX, &number.to_le_bytes())
             .map(|raw| H256::from_slice(&raw[..]).expect("db safe access"))
@@ -220,6 +497,131 @@ impl<T: KeyValueDB> ChainStore for ChainKVStore<T> {
         self.get(COLUMN_TRANSACTION_ADDR, h.as_bytes())
             .map(|raw| deserialize(&raw[..]).unwrap())
     }
+
+    fn get_transaction_in_block(&self, hash: &H256, number: BlockNumber) -> Option<Transaction> {
+        self.get_transaction(hash).map(|(tx, _)| tx).or_else(|| {
+            let block_hash = self.get_block_hash(number)?;
+            self.get_block_body(&block_hash)?
+                .into_iter()
+                .find(|tx| &tx.hash() == hash)
+        })
+    }
+
+    fn get_cell_output(&self, tx_hash: &H256, index: u32) -> Option<CellOutput> {
+        let address: CellOutputsAddress = self
+            .get(COLUMN_CELL_OUTPUTS, tx_hash.as_bytes())
+            .map(|raw| deserialize(&raw[..]).expect("deserialize cell outputs address"))?;
+        let output = address.outputs.get(index as usize)?;
+        self.partial_get(
+            COLUMN_BLOCK_BODY,
+            address.block_hash.as_bytes(),
+            &(output.offset..(output.offset + output.length)),
+        )
+        .map(|raw| deserialize(&raw[..]).expect("deserialize cell output"))
+    }
+
+    fn get_cell_output_in_block(
+        &self,
+        tx_hash: &H256,
+        index: u32,
+        number: BlockNumber,
+    ) -> Option<CellOutput> {
+        self.get_cell_output(tx_hash, index).or_else(|| {
+            self.get_transaction_in_block(tx_hash, number)?
+                .outputs()
+                .get(index as usize)
+                .cloned()
+        })
+    }
+
+    fn is_tx_indexed(&self) -> bool {
+        self.get(COLUMN_META, META_TX_INDEXED_KEY).is_some()
+    }
+
+    fn get_lock_cells(&self, lock_hash: &H256) -> Vec<(OutPoint, LockCell)> {
+        self.traverse_lock_cells(lock_hash.as_bytes())
+    }
+
+    fn get_all_lock_cells(&self) -> Vec<(OutPoint, LockCell)> {
+        self.traverse_lock_cells(&[])
+    }
+
+    fn is_lock_indexed(&self) -> bool {
+        self.get(COLUMN_META, META_LOCK_INDEXED_KEY).is_some()
+    }
+
+    fn get_block_transaction_address(
+        &self,
+        block_hash: &H256,
+        index: usize,
+    ) -> Option<TransactionAddress> {
+        self.get(COLUMN_BLOCK_TRANSACTION_ADDRESSES, block_hash.as_bytes())
+            .and_then(|serialized_addresses| {
+                let addresses: Vec<Address> =
+                    deserialize(&serialized_addresses).expect("deserialize address should be ok");
+                addresses.get(index).map(|address| TransactionAddress {
+                    block_hash: block_hash.to_owned(),
+                    offset: address.offset,
+                    length: address.length,
+                })
+            })
+    }
+
+    fn get_reorg_count(&self) -> u64 {
+        self.get(COLUMN_META, META_REORG_COUNT_KEY)
+            .map(|raw| deserialize(&raw[..]).unwrap())
+            .unwrap_or(0)
+    }
+
+    fn get_reorg_record(&self, index: u64) -> Option<ReorgRecord> {
+        self.get(COLUMN_REORG, &index.to_be_bytes())
+            .map(|raw| deserialize(&raw[..]).unwrap())
+    }
+
+    fn get_tx_pool_snapshot(&self) -> Option<Vec<PersistedTx>> {
+        self.get(COLUMN_META, META_TX_POOL_KEY)
+            .map(|raw| deserialize(&raw[..]).unwrap())
+    }
+
+    fn get_cell_set(&self, tip_hash: &H256) -> Option<CellSet> {
+        if self.get(COLUMN_META, META_CELL_SET_TIP_KEY)? != tip_hash.as_bytes() {
+            return None;
+        }
+        let mut cell_set = CellSet::new();
+        let ret = self.db.traverse(COLUMN_CELL_SET, |key, value| {
+            let tx_hash = H256::from_slice(key)
+                .map_err(|err| Error::DBError(format!("invalid cell set key: {:?}", err)))?;
+            let meta: TransactionMeta = deserialize(value)
+                .map_err(|err| Error::DBError(format!("invalid cell set entry: {}", err)))?;
+            cell_set.inner.insert(tx_hash, meta);
+            Ok(())
+        });
+        match ret {
+            Ok(()) => Some(cell_set),
+            Err(err) => {
+                warn!(target: "shared", "failed to load the saved cell set: {}", err);
+                None
+            }
+        }
+    }
+
+    fn get_cell_set_tx_hashes(&self) -> Vec<H256> {
+        let mut tx_hashes = Vec::new();
+        self.db
+            .traverse(COLUMN_CELL_SET, |key, _| {
+                if let Ok(tx_hash) = H256::from_slice(key) {
+                    tx_hashes.push(tx_hash);
+                }
+                Ok(())
+            })
+            .expect("db operation should be ok");
+        tx_hashes
+    }
+
+    fn get_pruned_height(&self) -> Option<BlockNumber> {
+        self.get(COLUMN_META, META_PRUNED_HEIGHT_KEY)
+            .map(|raw| deserialize(&raw[..]).unwrap())
+    }
 }
 
 pub struct DefaultStoreBatch<B> {
@@ -263,6 +665,19 @@ impl<B: DbBatch> StoreBatch for DefaultStoreBatch<B> {
             COLUMN_BLOCK_TRANSACTION_ADDRESSES,
             hash.as_bytes(),
             &block_addresses,
+        )?;
+        Ok(())
+    }
+
+    fn insert_header(&mut self, header: &Header) -> Result<(), Error> {
+        self.insert_serialize(COLUMN_BLOCK_HEADER, header.hash().as_bytes(), header)
+    }
+
+    fn insert_skip_hash(&mut self, block_hash: &H256, skip_hash: &H256) -> Result<(), Error> {
+        self.insert_raw(
+            COLUMN_SKIP_HASH,
+            block_hash.as_bytes(),
+            skip_hash.as_bytes(),
         )
     }
 
@@ -281,9 +696,19 @@ impl<B: DbBatch> StoreBatch for DefaultStoreBatch<B> {
                 length: addresses[id].length,
             };
             self.insert_serialize(COLUMN_TRANSACTION_ADDR, tx.hash().as_bytes(), &address)?;
+            // a cell is read from the block body without loading its transaction
+            let outputs_address = CellOutputsAddress {
+                block_hash: hash.clone(),
+                outputs: cell_output_addresses(tx, addresses[id].offset),
+            };
+            self.insert_serialize(COLUMN_CELL_OUTPUTS, tx.hash().as_bytes(), &outputs_address)?;
         }
+        self.attach_header(block.header())
+    }
 
-        let number = block.header().number().to_le_bytes();
+    fn attach_header(&mut self, header: &Header) -> Result<(), Error> {
+        let hash = header.hash();
+        let number = header.number().to_le_bytes();
         self.insert_raw(COLUMN_INDEX, &number, hash.as_bytes())?;
         self.insert_raw(COLUMN_INDEX, hash.as_bytes(), &number)
     }
@@ -291,15 +716,102 @@ impl<B: DbBatch> StoreBatch for DefaultStoreBatch<B> {
     fn detach_block(&mut self, block: &Block) -> Result<(), Error> {
         for tx in block.transactions() {
             self.delete(COLUMN_TRANSACTION_ADDR, tx.hash().as_bytes())?;
+            self.delete_cell_outputs(&tx.hash())?;
         }
-        self.delete(COLUMN_INDEX, &block.header().number().to_le_bytes())?;
-        self.delete(COLUMN_INDEX, block.header().hash().as_bytes())
+        self.detach_header(block.header())
+    }
+
+    fn detach_header(&mut self, header: &Header) -> Result<(), Error> {
+        self.delete(COLUMN_INDEX, &header.number().to_le_bytes())?;
+        self.delete(COLUMN_INDEX, header.hash().as_bytes())
+    }
+
+    fn insert_transaction_address(
+        &mut self,
+        tx_hash: &H256,
+        address: &TransactionAddress,
+    ) -> Result<(), Error> {
+        self.insert_serialize(COLUMN_TRANSACTION_ADDR, tx_hash.as_bytes(), address)
+    }
+
+    fn delete_transaction_address(&mut self, tx_hash: &H256) -> Result<(), Error> {
+        self.delete(COLUMN_TRANSACTION_ADDR, tx_hash.as_bytes())
     }
 
     fn insert_tip_header(&mut self, h: &Header) -> Result<(), Error> {
         self.insert_raw(COLUMN_META, META_TIP_HEADER_KEY, h.hash().as_bytes())
     }
 
+    fn insert_reorg_record(&mut self, index: u64, record: &ReorgRecord) -> Result<(), Error> {
+        self.insert_serialize(COLUMN_REORG, &index.to_be_bytes(), record)?;
+        self.insert_serialize(COLUMN_META, META_REORG_COUNT_KEY, &(index + 1))
+    }
+
+    fn insert_tx_pool_snapshot(&mut self, txs: &[PersistedTx]) -> Result<(), Error> {
+        self.insert_serialize(COLUMN_META, META_TX_POOL_KEY, txs)
+    }
+
+    fn delete_tx_pool_snapshot(&mut self) -> Result<(), Error> {
+        self.delete(COLUMN_META, META_TX_POOL_KEY)
+    }
+
+    fn update_cell_set(&mut self, changes: &CellSetChanges, tip_hash: &H256) -> Result<(), Error> {
+        for (tx_hash, meta) in changes {
+            match meta {
+                Some(meta) => {
+                    self.insert_serialize(COLUMN_CELL_SET, tx_hash.as_bytes(), meta)?;
+                    // the outputs of the spent transactions are no longer read, a reorg
+                    // bringing them back falls back to loading the transaction
+                    if meta.is_all_dead() {
+                        self.delete_cell_outputs(tx_hash)?;
+                    }
+                }
+                None => self.delete(COLUMN_CELL_SET, tx_hash.as_bytes())?,
+            }
+        }
+        self.insert_raw(COLUMN_META, META_CELL_SET_TIP_KEY, tip_hash.as_bytes())
+    }
+
+    fn prune_block(&mut self, block_hash: &H256) -> Result<(), Error> {
+        self.delete(COLUMN_BLOCK_BODY, block_hash.as_bytes())?;
+        self.delete(COLUMN_BLOCK_TRANSACTION_ADDRESSES, block_hash.as_bytes())?;
+        self.delete(COLUMN_BLOCK_UNCLE, block_hash.as_bytes())?;
+        self.delete(COLUMN_BLOCK_PROPOSAL_IDS, block_hash.as_bytes())
+    }
+
+    fn delete_cell_outputs(&mut self, tx_hash: &H256) -> Result<(), Error> {
+        self.delete(COLUMN_CELL_OUTPUTS, tx_hash.as_bytes())
+    }
+
+    fn insert_pruned_height(&mut self, number: BlockNumber) -> Result<(), Error> {
+        self.insert_serialize(COLUMN_META, META_PRUNED_HEIGHT_KEY, &number)
+    }
+
+    fn set_tx_indexed(&mut self, indexed: bool) -> Result<(), Error> {
+        if indexed {
+            self.insert_raw(COLUMN_META, META_TX_INDEXED_KEY, &[])
+        } else {
+            self.delete(COLUMN_META, META_TX_INDEXED_KEY)
+        }
+    }
+
+    fn insert_lock_cell(&mut self, out_point: &OutPoint, cell: &LockCell) -> Result<(), Error> {
+        let key = lock_cell_key(&cell.lock.hash(), out_point);
+        self.insert_serialize(COLUMN_LOCK_CELLS, &key, cell)
+    }
+
+    fn delete_lock_cell(&mut self, lock_hash: &H256, out_point: &OutPoint) -> Result<(), Error> {
+        self.delete(COLUMN_LOCK_CELLS, &lock_cell_key(lock_hash, out_point))
+    }
+
+    fn set_lock_indexed(&mut self, indexed: bool) -> Result<(), Error> {
+        if indexed {
+            self.insert_raw(COLUMN_META, META_LOCK_INDEXED_KEY, &[])
+        } else {
+            self.delete(COLUMN_META, META_LOCK_INDEXED_KEY)
+        }
+    }
+
     fn commit(self) -> Result<(), Error> {
         self.inner.commit()
     }
@@ -311,7 +823,11 @@ mod tests {
     use super::*;
     use crate::store::StoreBatch;
     use ckb_chain_spec::consensus::Consensus;
+    use ckb_core::script::Script;
+    use ckb_core::transaction::CellInput;
+    use ckb_core::Capacity;
     use ckb_db::{DBConfig, RocksDB};
+    use ckb_traits::skip_number;
     use tempfile;
 
     fn setup_db(prefix: &str, columns: u32) -> RocksDB {
@@ -376,6 +892,367 @@ mod tests {
         assert_eq!(ext, store.get_block_ext(&hash).unwrap());
     }
 
+    #[test]
+    fn save_and_get_reorg_record() {
+        let db = setup_db("save_and_get_reorg_record", COLUMNS);
+        let store = ChainKVStore::new(db);
+        assert_eq!(store.get_reorg_count(), 0);
+
+        let records: Vec<_> = (0..2u64)
+            .map(|i| ReorgRecord {
+                timestamp: 1000 + i,
+                depth: i + 1,
+                attached: i + 2,
+                fork_number: 10 * i,
+                old_tip_hash: H256::zero(),
+                new_tip_hash: H256::zero(),
+                duration: 5,
+            })
+            .collect();
+        for record in &records {
+            let mut batch = store.new_batch().unwrap();
+            batch
+                .insert_reorg_record(store.get_reorg_count(), record)
+                .unwrap();
+            batch.commit().unwrap();
+        }
+        assert_eq!(store.get_reorg_count(), 2);
+        assert_eq!(store.get_reorg_record(0), Some(records[0].clone()));
+        assert_eq!(store.get_reorg_record(1), Some(records[1].clone()));
+        assert_eq!(store.get_reorg_record(2), None);
+    }
+
+    #[test]
+    fn save_and_delete_tx_pool_snapshot() {
+        let db = setup_db("save_and_delete_tx_pool_snapshot", COLUMNS);
+        let store = ChainKVStore::new(db);
+        assert_eq!(store.get_tx_pool_snapshot(), None);
+
+        let txs = vec![PersistedTx {
+            transaction: TransactionBuilder::default().build(),
+            cycles: Some(100),
+            timestamp: 1000,
+        }];
+        let mut batch = store.new_batch().unwrap();
+        batch.insert_tx_pool_snapshot(&txs).unwrap();
+        batch.commit().unwrap();
+        assert_eq!(store.get_tx_pool_snapshot(), Some(txs));
+
+        let mut batch = store.new_batch().unwrap();
+        batch.delete_tx_pool_snapshot().unwrap();
+        batch.commit().unwrap();
+        assert_eq!(store.get_tx_pool_snapshot(), None);
+    }
+
+    #[test]
+    fn save_and_update_cell_set() {
+        let db = setup_db("save_and_update_cell_set", COLUMNS);
+        let store = ChainKVStore::new(db);
+        let tip_hash = H256::from_trimmed_hex_str("1").unwrap();
+        let next_tip_hash = H256::from_trimmed_hex_str("2").unwrap();
+        let tx_hash = H256::from_trimmed_hex_str("3").unwrap();
+        assert_eq!(store.get_cell_set(&tip_hash), None);
+
+        let mut cell_set = CellSet::new();
+        cell_set.insert(tx_hash.clone(), 1, false, 3);
+        cell_set.insert(H256::zero(), 0, true, 1);
+        let changes: CellSetChanges = cell_set
+            .inner
+            .iter()
+            .map(|(tx_hash, meta)| (tx_hash.clone(), Some(meta.clone())))
+            .collect();
+        let mut batch = store.new_batch().unwrap();
+        batch.update_cell_set(&changes, &tip_hash).unwrap();
+        batch.commit().unwrap();
+        assert_eq!(store.get_cell_set(&tip_hash), Some(cell_set.clone()));
+        assert_eq!(store.get_cell_set(&next_tip_hash), None);
+
+        let mut meta = cell_set.get(&tx_hash).cloned().unwrap();
+        meta.set_dead(2);
+        let mut changes = CellSetChanges::default();
+        changes.insert(tx_hash, Some(meta));
+        changes.insert(H256::zero(), None);
+        let mut batch = store.new_batch().unwrap();
+        batch.update_cell_set(&changes, &next_tip_hash).unwrap();
+        batch.commit().unwrap();
+        cell_set.apply(changes);
+        assert_eq!(store.get_cell_set(&tip_hash), None);
+        assert_eq!(store.get_cell_set(&next_tip_hash), Some(cell_set));
+        assert_eq!(store.get_cell_set_tx_hashes().len(), 1);
+    }
+
+    fn insert_header_with_skip_hash<T: KeyValueDB>(store: &ChainKVStore<T>, header: &Header) {
+        let mut batch = store.new_batch().unwrap();
+        batch.insert_header(header).unwrap();
+        if let Some(skip) = store.get_ancestor(header.parent_hash(), skip_number(header.number())) {
+            batch
+                .insert_skip_hash(&header.hash(), &skip.hash())
+                .unwrap();
+        }
+        batch.commit().unwrap();
+    }
+
+    #[test]
+    fn get_ancestor_by_skip_hash() {
+        let db = setup_db("get_ancestor_by_skip_hash", COLUMNS);
+        let store = ChainKVStore::new(db);
+        let genesis = HeaderBuilder::default().build();
+        insert_header_with_skip_hash(&store, &genesis);
+
+        let mut main = vec![genesis];
+        for number in 1..=100 {
+            let header = HeaderBuilder::default()
+                .number(number)
+                .parent_hash(main.last().unwrap().hash())
+                .build();
+            insert_header_with_skip_hash(&store, &header);
+            main.push(header);
+        }
+        let mut fork = main[..=60].to_vec();
+        for number in 61..=80 {
+            let header = HeaderBuilder::default()
+                .number(number)
+                .parent_hash(fork.last().unwrap().hash())
+                .nonce(1)
+                .build();
+            insert_header_with_skip_hash(&store, &header);
+            fork.push(header);
+        }
+
+        assert_eq!(
+            store.skip_hash(&main[100].hash()),
+            Some(main[skip_number(100) as usize].hash())
+        );
+        assert_eq!(
+            store.get_ancestor(&main[100].hash(), 37),
+            Some(main[37].clone())
+        );
+        assert_eq!(
+            store.get_ancestor(&main[100].hash(), 0),
+            Some(main[0].clone())
+        );
+        assert_eq!(
+            store.get_ancestor(&fork[80].hash(), 70),
+            Some(fork[70].clone())
+        );
+        assert_eq!(store.get_ancestor(&main[10].hash(), 11), None);
+
+        assert_eq!(
+            store.latest_common_ancestor(&main[100], &fork[80]),
+            Some(main[60].clone())
+        );
+        assert_eq!(
+            store.latest_common_ancestor(&fork[80], &main[61]),
+            Some(main[60].clone())
+        );
+        assert_eq!(
+            store.latest_common_ancestor(&main[50], &fork[80]),
+            Some(main[50].clone())
+        );
+    }
+
+    #[test]
+    fn delete_and_restore_transaction_address() {
+        let db = setup_db("delete_and_restore_transaction_address", COLUMNS);
+        let store = ChainKVStore::new(db);
+        let block = BlockBuilder::default()
+            .transaction(TransactionBuilder::default().build())
+            .transaction(TransactionBuilder::default().version(1).build())
+            .build();
+        let hash = block.header().hash();
+        let tx_hash = block.transactions()[1].hash();
+
+        let mut batch = store.new_batch().unwrap();
+        batch.insert_block(&block).unwrap();
+        batch.attach_block(&block).unwrap();
+        batch.commit().unwrap();
+        let address = store.get_transaction_address(&tx_hash).unwrap();
+        assert_eq!(
+            store.get_block_transaction_address(&hash, 1),
+            Some(address.clone())
+        );
+        assert_eq!(store.get_block_transaction_address(&hash, 2), None);
+
+        let mut batch = store.new_batch().unwrap();
+        batch.delete_transaction_address(&tx_hash).unwrap();
+        batch.commit().unwrap();
+        assert!(store.get_transaction(&tx_hash).is_none());
+
+        let mut batch = store.new_batch().unwrap();
+        batch
+            .insert_transaction_address(&tx_hash, &address)
+            .unwrap();
+        batch.commit().unwrap();
+        assert_eq!(
+            store.get_transaction(&tx_hash),
+            Some((block.transactions()[1].clone(), hash))
+        );
+    }
+
+    #[test]
+    fn get_transaction_in_block_without_index() {
+        let db = setup_db("get_transaction_in_block_without_index", COLUMNS);
+        let store = ChainKVStore::new(db);
+        let block = BlockBuilder::default()
+            .transaction(TransactionBuilder::default().build())
+            .transaction(TransactionBuilder::default().version(1).build())
+            .build();
+        let tx = block.transactions()[1].clone();
+
+        let mut batch = store.new_batch().unwrap();
+        batch.insert_block(&block).unwrap();
+        batch.attach_header(block.header()).unwrap();
+        batch.commit().unwrap();
+        assert!(store.get_transaction(&tx.hash()).is_none());
+        assert_eq!(store.get_transaction_in_block(&tx.hash(), 0), Some(tx));
+        assert_eq!(store.get_transaction_in_block(&H256::zero(), 0), None);
+
+        assert!(!store.is_tx_indexed());
+        let mut batch = store.new_batch().unwrap();
+        batch.set_tx_indexed(true).unwrap();
+        batch.commit().unwrap();
+        assert!(store.is_tx_indexed());
+        let mut batch = store.new_batch().unwrap();
+        batch.set_tx_indexed(false).unwrap();
+        batch.commit().unwrap();
+        assert!(!store.is_tx_indexed());
+    }
+
+    #[test]
+    fn save_and_delete_cell_outputs() {
+        let db = setup_db("save_and_delete_cell_outputs", COLUMNS);
+        let store = ChainKVStore::new(db);
+        let output = |capacity| {
+            CellOutput::new(
+                Capacity::bytes(capacity).unwrap(),
+                vec![capacity as u8],
+                Script::default(),
+                None,
+            )
+        };
+        let cellbase = TransactionBuilder::default()
+            .input(CellInput::new_cellbase_input(0))
+            .output(output(5))
+            .build();
+        let tx = TransactionBuilder::default()
+            .dep(OutPoint::new(cellbase.hash(), 0))
+            .input(CellInput::new(
+                OutPoint::new(H256::zero(), 3),
+                0,
+                vec![vec![7]],
+            ))
+            .output(output(10))
+            .output(output(20))
+            .witness(vec![vec![1, 2]])
+            .build();
+        let block = BlockBuilder::default()
+            .transaction(cellbase.clone())
+            .transaction(tx.clone())
+            .build();
+
+        let mut batch = store.new_batch().unwrap();
+        batch.insert_block(&block).unwrap();
+        batch.commit().unwrap();
+        // only the main chain outputs are addressed
+        assert_eq!(store.get_cell_output(&tx.hash(), 1), None);
+
+        let mut batch = store.new_batch().unwrap();
+        batch.attach_block(&block).unwrap();
+        batch.commit().unwrap();
+        assert_eq!(store.get_cell_output(&cellbase.hash(), 0), Some(output(5)));
+        assert_eq!(store.get_cell_output(&tx.hash(), 0), Some(output(10)));
+        assert_eq!(store.get_cell_output(&tx.hash(), 1), Some(output(20)));
+        assert_eq!(store.get_cell_output(&tx.hash(), 2), None);
+
+        let mut batch = store.new_batch().unwrap();
+        batch.delete_cell_outputs(&tx.hash()).unwrap();
+        batch.commit().unwrap();
+        assert_eq!(store.get_cell_output(&tx.hash(), 0), None);
+        // loaded from the block body
+        assert_eq!(
+            store.get_cell_output_in_block(&tx.hash(), 0, 0),
+            Some(output(10))
+        );
+    }
+
+    #[test]
+    fn save_and_delete_lock_cells() {
+        let db = setup_db("save_and_delete_lock_cells", COLUMNS);
+        let store = ChainKVStore::new(db);
+        let lock = Script::always_success();
+        let other_lock = Script::default();
+        let cell = |lock: &Script, block_number| LockCell {
+            capacity: Capacity::bytes(10).unwrap(),
+            lock: lock.clone(),
+            block_number,
+        };
+        let out_points: Vec<OutPoint> = (0..3)
+            .map(|index| OutPoint::new(H256::from_slice(&[index as u8; 32]).unwrap(), index))
+            .collect();
+
+        let mut batch = store.new_batch().unwrap();
+        batch
+            .insert_lock_cell(&out_points[2], &cell(&lock, 1))
+            .unwrap();
+        batch
+            .insert_lock_cell(&out_points[0], &cell(&lock, 2))
+            .unwrap();
+        batch
+            .insert_lock_cell(&out_points[1], &cell(&other_lock, 3))
+            .unwrap();
+        batch.commit().unwrap();
+        assert_eq!(
+            store.get_lock_cells(&lock.hash()),
+            vec![
+                (out_points[0].clone(), cell(&lock, 2)),
+                (out_points[2].clone(), cell(&lock, 1)),
+            ]
+        );
+        assert_eq!(store.get_all_lock_cells().len(), 3);
+
+        let mut batch = store.new_batch().unwrap();
+        batch
+            .delete_lock_cell(&lock.hash(), &out_points[0])
+            .unwrap();
+        batch.commit().unwrap();
+        assert_eq!(
+            store.get_lock_cells(&lock.hash()),
+            vec![(out_points[2].clone(), cell(&lock, 1))]
+        );
+        assert_eq!(
+            store.get_lock_cells(&other_lock.hash()),
+            vec![(out_points[1].clone(), cell(&other_lock, 3))]
+        );
+    }
+
+    #[test]
+    fn tx_index_cutoff() {
+        let config = StoreConfig::default();
+        assert_eq!(config.tx_index_cutoff(100), None);
+
+        let config = StoreConfig {
+            tx_index_retention: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(config.tx_index_cutoff(5), Some(0));
+        assert_eq!(config.tx_index_cutoff(9), Some(0));
+        assert_eq!(config.tx_index_cutoff(10), Some(1));
+        assert_eq!(config.tx_index_cutoff(100), Some(91));
+    }
+
+    #[test]
+    fn block_pruning_cutoff() {
+        let config = StoreConfig::default();
+        assert_eq!(config.block_pruning_cutoff(100), None);
+
+        let config = StoreConfig {
+            block_pruning_depth: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(config.block_pruning_cutoff(9), Some(0));
+        assert_eq!(config.block_pruning_cutoff(100), Some(91));
+    }
+
     #[test]
     fn index_store() {
         let tmp_dir = tempfile::Builder::new()
@@ -406,4 +1283,51 @@ mod tests {
 
         assert_eq!(block.header(), &store.get_tip_header().unwrap());
     }
+
+    #[test]
+    fn repair_partially_applied_tip() {
+        let db = setup_db("repair_partially_applied_tip", COLUMNS);
+        let store = ChainKVStore::new(db);
+        let consensus = Consensus::default();
+        let genesis = consensus.genesis_block();
+        store.init(&genesis).unwrap();
+        assert_eq!(store.repair_tip(), Ok(None));
+
+        let mut blocks = Vec::new();
+        let mut parent = genesis.header().clone();
+        for _ in 0..2 {
+            let block = BlockBuilder::default()
+                .header(
+                    HeaderBuilder::default()
+                        .parent_hash(parent.hash())
+                        .number(parent.number() + 1)
+                        .build(),
+                )
+                .build();
+            parent = block.header().clone();
+            blocks.push(block);
+        }
+        let ext = store.get_block_ext(&genesis.header().hash()).unwrap();
+        let mut batch = store.new_batch().unwrap();
+        batch.insert_block(&blocks[0]).unwrap();
+        batch
+            .insert_block_ext(&blocks[0].header().hash(), &ext)
+            .unwrap();
+        batch.attach_block(&blocks[0]).unwrap();
+        batch.insert_tip_header(blocks[0].header()).unwrap();
+        batch.commit().unwrap();
+
+        // the second block is indexed as the tip without its block ext
+        let mut batch = store.new_batch().unwrap();
+        batch.insert_block(&blocks[1]).unwrap();
+        batch.attach_block(&blocks[1]).unwrap();
+        batch.insert_tip_header(blocks[1].header()).unwrap();
+        batch.commit().unwrap();
+
+        assert_eq!(store.repair_tip(), Ok(Some(blocks[0].header().clone())));
+        assert_eq!(store.get_tip_header().as_ref(), Some(blocks[0].header()));
+        assert_eq!(store.get_block_hash(2), None);
+        assert_eq!(store.get_block_number(&blocks[1].header().hash()), None);
+        assert_eq!(store.repair_tip(), Ok(None));
+    }
 }
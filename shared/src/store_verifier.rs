@@ -0,0 +1,319 @@
+use crate::cell_set::CellSet;
+use crate::error::SharedError;
+use crate::store::{ChainStore, StoreBatch, StoreConfig};
+use ckb_core::block::BlockBuilder;
+use ckb_core::extras::BlockExt;
+use ckb_core::header::BlockNumber;
+use ckb_verification::MerkleRootVerifier;
+use numext_fixed_hash::H256;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreInconsistency {
+    /// The main chain index has no block at this number below the tip
+    MissingBlockHash(BlockNumber),
+    MissingHeader(BlockNumber, H256),
+    /// The header indexed at this number has another number or hash, or doesn't link to the
+    /// main chain block below
+    BrokenLink(BlockNumber, H256),
+    /// The block isn't indexed back to its number
+    MissingBlockNumber(BlockNumber, H256),
+    /// The body is missing while the blocks below it have theirs
+    MissingBody(BlockNumber, H256),
+    /// The roots committed in the header don't match the stored block
+    InvalidCommitment(BlockNumber, H256),
+    MissingBlockExt(BlockNumber, H256),
+    /// The total difficulty or uncles count doesn't add up from the parent
+    InvalidBlockExt(BlockNumber, H256),
+    /// The transaction index misses the transaction or points it to another block
+    InvalidTxIndex(BlockNumber, H256),
+    /// The saved cell set doesn't match the one rebuilt from the main chain
+    InvalidCellSet,
+}
+
+impl StoreInconsistency {
+    /// Whether `StoreVerifier` can fix it from the rest of the store
+    pub fn is_repairable(&self) -> bool {
+        match self {
+            StoreInconsistency::MissingBlockNumber(..)
+            | StoreInconsistency::MissingBlockExt(..)
+            | StoreInconsistency::InvalidBlockExt(..)
+            | StoreInconsistency::InvalidTxIndex(..)
+            | StoreInconsistency::InvalidCellSet => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for StoreInconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreInconsistency::MissingBlockHash(number) => {
+                write!(f, "block {} is not indexed", number)
+            }
+            StoreInconsistency::MissingHeader(number, hash) => {
+                write!(f, "header of block {} {:#x} is missing", number, hash)
+            }
+            StoreInconsistency::BrokenLink(number, hash) => write!(
+                f,
+                "block {} {:#x} doesn't link to the main chain",
+                number, hash
+            ),
+            StoreInconsistency::MissingBlockNumber(number, hash) => {
+                write!(f, "block {} {:#x} is not indexed by its hash", number, hash)
+            }
+            StoreInconsistency::MissingBody(number, hash) => {
+                write!(f, "body of block {} {:#x} is missing", number, hash)
+            }
+            StoreInconsistency::InvalidCommitment(number, hash) => write!(
+                f,
+                "block {} {:#x} doesn't match the roots in its header",
+                number, hash
+            ),
+            StoreInconsistency::MissingBlockExt(number, hash) => {
+                write!(f, "ext of block {} {:#x} is missing", number, hash)
+            }
+            StoreInconsistency::InvalidBlockExt(number, hash) => {
+                write!(f, "ext of block {} {:#x} has wrong totals", number, hash)
+            }
+            StoreInconsistency::InvalidTxIndex(number, tx_hash) => write!(
+                f,
+                "transaction {:#x} of block {} is not indexed",
+                tx_hash, number
+            ),
+            StoreInconsistency::InvalidCellSet => {
+                write!(f, "the saved cell set doesn't match the main chain")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StoreReport {
+    /// The number of the stored tip
+    pub tip_number: BlockNumber,
+    pub inconsistencies: Vec<StoreInconsistency>,
+    /// The cell set can only be checked when the bodies of the whole main chain are stored
+    pub cell_set_checked: bool,
+    /// Whether the repairable inconsistencies were fixed
+    pub repaired: bool,
+}
+
+impl StoreReport {
+    pub fn is_consistent(&self) -> bool {
+        self.inconsistencies.is_empty()
+    }
+}
+
+/// Walk the main chain from the genesis to the stored tip, checking the headers link up, the
+/// bodies match their headers, and the block exts, transaction index and saved cell set agree
+/// with the blocks.
+///
+/// The bodies below a snapshot or the pruned height are not stored, so a missing body is only
+/// reported above a block which has its body.
+pub struct StoreVerifier<'a, CS> {
+    store: &'a CS,
+    store_config: &'a StoreConfig,
+    repair: bool,
+}
+
+impl<'a, CS: ChainStore> StoreVerifier<'a, CS> {
+    pub fn new(store: &'a CS, store_config: &'a StoreConfig) -> Self {
+        StoreVerifier {
+            store,
+            store_config,
+            repair: false,
+        }
+    }
+
+    /// Fix the repairable inconsistencies, see `StoreInconsistency::is_repairable`
+    pub fn repair(mut self, repair: bool) -> Self {
+        self.repair = repair;
+        self
+    }
+
+    pub fn verify(&self) -> Result<StoreReport, SharedError> {
+        let store = self.store;
+        let tip_header = match store.get_tip_header() {
+            Some(header) => header,
+            None => return Ok(StoreReport::default()),
+        };
+        let tip_number = tip_header.number();
+        let pruned_height = store.get_pruned_height().unwrap_or(0);
+        let tx_index_cutoff = if store.is_tx_indexed() {
+            Some(self.store_config.tx_index_cutoff(tip_number).unwrap_or(0))
+        } else {
+            None
+        };
+
+        let mut inconsistencies = Vec::new();
+        let mut batch = store.new_batch().map_err(SharedError::DB)?;
+        let mut cell_set = Some(CellSet::new());
+        let mut has_body = false;
+        let mut parent: Option<(H256, BlockExt)> = None;
+        let mut completed = false;
+        for number in 0..=tip_number {
+            let hash = match store.get_block_hash(number) {
+                Some(hash) => hash,
+                None => {
+                    inconsistencies.push(StoreInconsistency::MissingBlockHash(number));
+                    break;
+                }
+            };
+            let header = match store.get_header(&hash) {
+                Some(header) => header,
+                None => {
+                    inconsistencies.push(StoreInconsistency::MissingHeader(number, hash));
+                    break;
+                }
+            };
+            let linked = match parent {
+                Some((ref parent_hash, _)) => header.parent_hash() == parent_hash,
+                None => true,
+            };
+            if header.hash() != hash || header.number() != number || !linked {
+                inconsistencies.push(StoreInconsistency::BrokenLink(number, hash));
+                break;
+            }
+            if store.get_block_number(&hash) != Some(number) {
+                inconsistencies.push(StoreInconsistency::MissingBlockNumber(number, hash.clone()));
+                batch.attach_header(&header).map_err(SharedError::DB)?;
+            }
+
+            let (total_difficulty, total_uncles_count) = match parent {
+                Some((_, ref parent_ext)) => (
+                    parent_ext.total_difficulty.clone() + header.difficulty(),
+                    parent_ext.total_uncles_count + u64::from(header.uncles_count()),
+                ),
+                None => (
+                    header.difficulty().clone(),
+                    u64::from(header.uncles_count()),
+                ),
+            };
+            let ext = match store.get_block_ext(&hash) {
+                Some(ref ext)
+                    if ext.total_difficulty == total_difficulty
+                        && ext.total_uncles_count == total_uncles_count =>
+                {
+                    ext.clone()
+                }
+                stored => {
+                    let ext = match stored {
+                        Some(ext) => {
+                            inconsistencies
+                                .push(StoreInconsistency::InvalidBlockExt(number, hash.clone()));
+                            BlockExt {
+                                total_difficulty,
+                                total_uncles_count,
+                                ..ext
+                            }
+                        }
+                        None => {
+                            inconsistencies
+                                .push(StoreInconsistency::MissingBlockExt(number, hash.clone()));
+                            BlockExt {
+                                received_at: header.timestamp(),
+                                total_difficulty,
+                                total_uncles_count,
+                                txs_verified: Some(true),
+                            }
+                        }
+                    };
+                    batch
+                        .insert_block_ext(&hash, &ext)
+                        .map_err(SharedError::DB)?;
+                    ext
+                }
+            };
+
+            // a body without its uncles or proposals is as good as missing
+            let block = store.get_block_body(&hash).and_then(|transactions| {
+                Some(
+                    BlockBuilder::default()
+                        .header(header.clone())
+                        .uncles(store.get_block_uncles(&hash)?)
+                        .transactions(transactions)
+                        .proposals(store.get_block_proposal_txs_ids(&hash)?)
+                        .build(),
+                )
+            });
+            match block {
+                Some(block) => {
+                    has_body = true;
+                    if MerkleRootVerifier::new().verify(&block).is_err() {
+                        inconsistencies
+                            .push(StoreInconsistency::InvalidCommitment(number, hash.clone()));
+                    }
+                    for (index, tx) in block.transactions().iter().enumerate() {
+                        let tx_hash = tx.hash();
+                        let cutoff = match tx_index_cutoff {
+                            Some(cutoff) => cutoff,
+                            None => break,
+                        };
+                        let indexed = match store.get_transaction_address(&tx_hash) {
+                            Some(address) => address.block_hash == hash,
+                            // the spent transactions below the retention cutoff are dropped
+                            None => number < cutoff,
+                        };
+                        if !indexed {
+                            inconsistencies
+                                .push(StoreInconsistency::InvalidTxIndex(number, tx_hash.clone()));
+                            if let Some(address) = store.get_block_transaction_address(&hash, index)
+                            {
+                                batch
+                                    .insert_transaction_address(&tx_hash, &address)
+                                    .map_err(SharedError::DB)?;
+                            }
+                        }
+                    }
+                    if let Some(ref mut cell_set) = cell_set {
+                        for tx in block.transactions() {
+                            for out_point in tx.input_pts() {
+                                cell_set.mark_dead(&out_point);
+                            }
+                            cell_set.insert(
+                                tx.hash(),
+                                number,
+                                tx.is_cellbase(),
+                                tx.outputs().len(),
+                            );
+                        }
+                    }
+                }
+                None => {
+                    if has_body && number >= pruned_height {
+                        inconsistencies.push(StoreInconsistency::MissingBody(number, hash.clone()));
+                    }
+                    cell_set = None;
+                }
+            }
+
+            parent = Some((hash, ext));
+            completed = number == tip_number;
+        }
+
+        let tip_hash = tip_header.hash();
+        let cell_set = cell_set.filter(|_| completed);
+        let cell_set_checked = cell_set.is_some();
+        if let Some(cell_set) = cell_set {
+            if store.get_cell_set(&tip_hash).as_ref() != Some(&cell_set) {
+                inconsistencies.push(StoreInconsistency::InvalidCellSet);
+                let changes = cell_set.replacing(store.get_cell_set_tx_hashes());
+                batch
+                    .update_cell_set(&changes, &tip_hash)
+                    .map_err(SharedError::DB)?;
+            }
+        }
+
+        let repaired = self.repair && !inconsistencies.is_empty();
+        if repaired {
+            batch.commit().map_err(SharedError::DB)?;
+        }
+        Ok(StoreReport {
+            tip_number,
+            inconsistencies,
+            cell_set_checked,
+            repaired,
+        })
+    }
+}
@@ -3,13 +3,14 @@ use crate::{
     shared::{Shared, SharedBuilder},
     store::ChainKVStore,
 };
-use ckb_core::cell::{resolve_transaction, CellStatus, LiveCell};
+use ckb_core::cell::{resolve_cells, CellStatus, LiveCell};
 use ckb_core::transaction::Transaction;
 use ckb_db::memorydb::MemoryKeyValueDB;
 use fnv::{FnvHashMap, FnvHashSet};
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
+use std::sync::Arc;
 
 fn cell_set() -> CellSet {
     let file = File::open(
@@ -44,8 +45,8 @@ fn new_shared() -> Shared<ChainKVStore<MemoryKeyValueDB>> {
 #[test]
 fn case_no1() {
     let shared = new_shared();
-    let mut chain_state = shared.chain_state().lock();
-    chain_state.cell_set = cell_set();
+    let mut chain_state = (*shared.chain_state()).clone();
+    chain_state.cell_set = Arc::new(cell_set());
 
     let cell_set_diff = cell_set_diff();
     let outputs = FnvHashMap::default();
@@ -56,7 +57,7 @@ fn case_no1() {
 
     let mut seen = FnvHashSet::default();
     //Outpoint::null should be live
-    let rtx0 = resolve_transaction(&transcations[0], &mut seen, &cell_set_overlay);
+    let rtx0 = resolve_cells(&transcations[0], &mut seen, &cell_set_overlay);
     assert_eq!(rtx0.input_cells[0], CellStatus::Live(LiveCell::Null));
 
     // let out_point = transcations[1].inputs()[0].previous_output.clone();
@@ -64,15 +65,31 @@ fn case_no1() {
     // cell A (0x8aa8799cd6ad56dd6929fd6ac05f5cab6a5339562297abb619839ab2da519f35, 0)
     // A is dead in old fork
     let mut seen_inputs = seen.clone();
-    let rtx1 = resolve_transaction(&transcations[1], &mut seen_inputs, &*chain_state);
+    let rtx1 = resolve_cells(&transcations[1], &mut seen_inputs, &chain_state);
     assert_eq!(rtx1.input_cells[0], CellStatus::Dead);
 
     // A include in cell_set_diff old_inputs
     // A is live in cell_set_overlay
     let mut seen_inputs = seen.clone();
-    let rtx1_overlay = resolve_transaction(&transcations[1], &mut seen_inputs, &cell_set_overlay);
+    let rtx1_overlay = resolve_cells(&transcations[1], &mut seen_inputs, &cell_set_overlay);
     assert_eq!(
         rtx1_overlay.input_cells[0],
         CellStatus::Live(LiveCell::Null)
     );
 }
+
+#[test]
+fn case_no1_apply_to_clone() {
+    let cell_set = cell_set();
+    let changes = cell_set.changes(&cell_set_diff());
+    assert!(!changes.is_empty());
+
+    let mut next = cell_set.clone();
+    next.apply(changes.clone());
+
+    // the original is left as it is, the clone has the changes
+    assert_eq!(cell_set, self::cell_set());
+    for (tx_hash, meta) in changes {
+        assert_eq!(next.get(&tx_hash), meta.as_ref());
+    }
+}
@@ -4,12 +4,13 @@ use crate::{
     store::ChainKVStore,
 };
 use ckb_core::block::Block;
-use ckb_core::cell::resolve_transaction;
+use ckb_core::cell::resolve_cells;
 use ckb_db::memorydb::MemoryKeyValueDB;
 use fnv::FnvHashSet;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
+use std::sync::Arc;
 
 fn block() -> Block {
     let file =
@@ -35,15 +36,15 @@ fn new_shared() -> Shared<ChainKVStore<MemoryKeyValueDB>> {
 fn case_no2() {
     let block = block();
     let shared = new_shared();
-    let mut chain_state = shared.chain_state().lock();
-    chain_state.cell_set = cell_set();
+    let mut chain_state = (*shared.chain_state()).clone();
+    chain_state.cell_set = Arc::new(cell_set());
     let mut seen_inputs = FnvHashSet::default();
 
     // dep status
     assert!(block
         .transactions()
         .iter()
-        .map(|tx| resolve_transaction(tx, &mut seen_inputs, &*chain_state).dep_cells)
+        .map(|tx| resolve_cells(tx, &mut seen_inputs, &chain_state).dep_cells)
         .flatten()
         .all(|status| status.is_live()));
 }
@@ -1,14 +1,30 @@
+use crate::cell_set::CellSet;
 use crate::chain_state::{ChainCellSetOverlay, ChainState};
+use crate::snapshot::StateSnapshot;
+use crate::store_verifier::{StoreInconsistency, StoreVerifier};
+use crate::tx_pool::{PersistedTx, PoolError, TxPool, TxPoolConfig, TxPoolEvent, TxStatus};
 use crate::{
+    error::SharedError,
     shared::{Shared, SharedBuilder},
-    store::{ChainKVStore, ChainStore, StoreBatch},
+    store::{ChainKVStore, ChainStore, StoreBatch, StoreConfig, MIN_BLOCK_PRUNING_DEPTH},
 };
+use ckb_chain_spec::consensus::Consensus;
 use ckb_core::cell::CellProvider;
 use ckb_core::cell::CellStatus;
-use ckb_core::transaction::OutPoint;
-use ckb_core::{block::BlockBuilder, header::HeaderBuilder};
+use ckb_core::extras::BlockExt;
+use ckb_core::script::Script;
+use ckb_core::transaction::{CellInput, CellOutput, OutPoint, Transaction, TransactionBuilder};
+use ckb_core::{
+    block::{Block, BlockBuilder},
+    capacity_bytes,
+    header::HeaderBuilder,
+    Capacity,
+};
 use ckb_db::{KeyValueDB, MemoryKeyValueDB};
 use ckb_traits::BlockMedianTimeContext;
+use numext_fixed_hash::H256;
+use std::iter;
+use std::sync::atomic::Ordering;
 
 // Mock CellProvider
 #[cfg(test)]
@@ -76,7 +92,7 @@ where
 #[test]
 fn test_block_median_time() {
     let shared = new_shared();
-    let chain_state = shared.chain_state().lock();
+    let chain_state = shared.chain_state();
     assert_eq!((&*chain_state).block_median_time(0), Some(0));
     let now = faketime::unix_time_as_millis();
     insert_block_timestamps(shared.store(), &[now]);
@@ -93,3 +109,627 @@ fn test_block_median_time() {
         17
     );
 }
+
+#[test]
+fn test_branch_median_time() {
+    let shared = new_shared();
+    let genesis = shared.store().get_tip_header().expect("tip");
+    insert_block_timestamps(shared.store(), &(1..=22).collect::<Vec<_>>());
+
+    // a side branch forked from genesis, half of it stored but not attached
+    let mut parent = genesis;
+    let mut blocks = Vec::new();
+    for timestamp in 101..=122 {
+        let header = HeaderBuilder::default()
+            .timestamp(timestamp)
+            .parent_hash(parent.hash())
+            .number(parent.number() + 1)
+            .build();
+        parent = header.clone();
+        blocks.push(BlockBuilder::default().header(header).build());
+    }
+    let (stored, pending) = blocks.split_at(11);
+    let mut batch = shared.store().new_batch().unwrap();
+    for b in stored {
+        batch.insert_block(b).unwrap();
+    }
+    batch.commit().unwrap();
+
+    let branch = shared
+        .branch_median_time_context(&parent)
+        .with_blocks(pending);
+    assert_eq!(branch.block_median_time(22), Some(117));
+    assert_eq!(branch.block_median_time(11), Some(106));
+
+    let main_tip = shared
+        .store()
+        .get_block_hash(22)
+        .and_then(|hash| shared.store().get_header(&hash))
+        .expect("main tip");
+    let chain_state = shared.chain_state();
+    assert_eq!(
+        shared
+            .branch_median_time_context(&main_tip)
+            .block_median_time(22),
+        (&*chain_state).block_median_time(22)
+    );
+}
+
+#[test]
+fn test_cancel_queued_tx() {
+    let shared = new_shared();
+    let tx = TransactionBuilder::default().build();
+    let short_id = tx.proposal_short_id();
+    let cancelled = shared.tx_pool().lock().add_verifying(tx.clone());
+    assert!(shared.tx_pool().lock().contains_proposal_id(&short_id));
+
+    shared.tx_pool().lock().cancel_verifying(&short_id);
+    let verification = shared
+        .tx_verify_queue
+        .push(tx, Vec::new(), Vec::new(), false, cancelled);
+    assert_eq!(verification.wait(), Err(PoolError::Cancelled));
+    assert!(!shared.tx_pool().lock().contains_proposal_id(&short_id));
+}
+
+#[test]
+fn test_refuse_tx_when_verifying_full() {
+    let shared = new_shared();
+    shared.tx_pool().lock().config.max_verifying_size = 1;
+    let queued_tx = TransactionBuilder::default().build();
+    shared.tx_pool().lock().add_verifying(queued_tx.clone());
+    assert!(shared.tx_pool().lock().is_verifying_full());
+
+    let tx = TransactionBuilder::default()
+        .input(CellInput::new(OutPoint::new(H256::zero(), 0), 0, vec![]))
+        .build();
+    assert_eq!(
+        shared.add_tx_to_pool(tx.clone()).unwrap_err(),
+        PoolError::Full
+    );
+    // the refused transaction is not remembered as rejected
+    assert!(shared.tx_pool().lock().rejected.get(&tx.hash()).is_none());
+
+    shared
+        .tx_pool()
+        .lock()
+        .remove_verifying(&queued_tx.proposal_short_id());
+    assert!(!shared.tx_pool().lock().is_verifying_full());
+}
+
+#[test]
+fn test_cancel_queued_tx_on_reorg() {
+    let shared = new_shared();
+    let spending_tx = TransactionBuilder::default()
+        .input(CellInput::new(OutPoint::new(H256::zero(), 0), 0, vec![]))
+        .build();
+    let other_tx = TransactionBuilder::default().build();
+    let spending_cancelled = shared.tx_pool().lock().add_verifying(spending_tx.clone());
+    let other_cancelled = shared.tx_pool().lock().add_verifying(other_tx.clone());
+
+    // the spent cell is gone after the reorg, the queued scripts are not worth running
+    {
+        let mut tx_pool = shared.tx_pool().lock();
+        shared.chain_state().update_tx_pool_for_reorg(
+            &mut tx_pool,
+            iter::empty(),
+            iter::empty(),
+            iter::empty(),
+        );
+    }
+    let verification = shared.tx_verify_queue.push(
+        spending_tx,
+        Vec::new(),
+        Vec::new(),
+        false,
+        spending_cancelled,
+    );
+    assert_eq!(verification.wait(), Err(PoolError::Cancelled));
+    assert!(!other_cancelled.load(Ordering::SeqCst));
+    assert!(shared
+        .tx_pool()
+        .lock()
+        .contains_proposal_id(&other_tx.proposal_short_id()));
+}
+
+#[test]
+fn test_reject_recently_rejected_bad_tx() {
+    let shared = new_shared();
+    // no inputs nor outputs
+    let tx = TransactionBuilder::default().build();
+    let err = shared.add_tx_to_pool(tx.clone()).unwrap_err();
+    assert!(err.is_bad_tx());
+
+    let rejected = shared.tx_pool().lock().get_rejected(&tx.hash()).cloned();
+    assert_eq!(rejected.map(|rejected| rejected.reason), Some(err.clone()));
+    assert_eq!(shared.add_tx_to_pool(tx).unwrap_err(), err);
+}
+
+#[test]
+fn test_tx_pool_events() {
+    let shared = new_shared();
+    let events = shared.tx_pool().lock().subscribe_events("test");
+    let tx = TransactionBuilder::default().build();
+    let err = shared.add_tx_to_pool(tx.clone()).unwrap_err();
+    assert_eq!(
+        events.try_recv(),
+        Ok(TxPoolEvent::Rejected {
+            tx_hash: tx.hash(),
+            reason: err,
+        })
+    );
+    assert!(events.try_recv().is_err());
+}
+
+#[test]
+fn test_tx_pool_policy() {
+    let mut tx_pool_config = TxPoolConfig::default();
+    tx_pool_config.policy.max_tx_size = 10;
+    let shared = SharedBuilder::<MemoryKeyValueDB>::new()
+        .tx_pool_config(tx_pool_config)
+        .build()
+        .unwrap();
+    let tx = TransactionBuilder::default().build();
+    assert_eq!(
+        shared.add_tx_to_pool(tx.clone()).unwrap_err(),
+        PoolError::ExceededMaxTxSize(10)
+    );
+    // refused by the policy only, verified again when submitted again
+    assert!(shared.tx_pool().lock().get_rejected(&tx.hash()).is_some());
+    assert_eq!(
+        shared.tx_pool().lock().recently_rejected_bad_tx(&tx.hash()),
+        None
+    );
+}
+
+#[test]
+fn test_export_and_import_snapshot() {
+    let shared = new_shared();
+    let store = shared.store();
+    let mut parent = store.get_tip_header().unwrap();
+    let mut ext = store.get_block_ext(&parent.hash()).unwrap();
+    // the blocks are stored directly, the cell set of the chain state still ends at the genesis
+    let mut cell_set = shared.chain_state().cell_set().clone();
+    let output = CellOutput::new(capacity_bytes!(100), vec![], Script::default(), None);
+    let mut cellbases = Vec::new();
+    let mut batch = store.new_batch().unwrap();
+    for i in 1..=20 {
+        let number = parent.number() + 1;
+        let mut transactions = Vec::new();
+        if number % 5 == 0 {
+            transactions.push(
+                TransactionBuilder::default()
+                    .input(CellInput::new_cellbase_input(number))
+                    .output(output.clone())
+                    .build(),
+            );
+        }
+        // spends the cellbase of block 15
+        if number == 16 {
+            transactions.push(
+                TransactionBuilder::default()
+                    .input(CellInput::new(
+                        OutPoint::new(cellbases[2].clone(), 0),
+                        0,
+                        vec![],
+                    ))
+                    .output(output.clone())
+                    .build(),
+            );
+        }
+        for tx in &transactions {
+            for o in tx.input_pts() {
+                cell_set.mark_dead(&o);
+            }
+            cell_set.insert(tx.hash(), number, tx.is_cellbase(), tx.outputs().len());
+            if tx.is_cellbase() {
+                cellbases.push(tx.hash());
+            }
+        }
+        let block = BlockBuilder::default()
+            .transactions(transactions)
+            .with_header_builder(
+                HeaderBuilder::default()
+                    .timestamp(parent.timestamp() + i)
+                    .parent_hash(parent.hash())
+                    .number(number),
+            );
+        ext = BlockExt {
+            total_difficulty: ext.total_difficulty + block.header().difficulty(),
+            ..ext
+        };
+        batch.insert_block(&block).unwrap();
+        batch
+            .insert_block_ext(&block.header().hash(), &ext)
+            .unwrap();
+        batch.attach_block(&block).unwrap();
+        parent = block.header().clone();
+    }
+    batch.insert_tip_header(&parent).unwrap();
+    batch.commit().unwrap();
+
+    let snapshot = StateSnapshot::new(
+        &**store,
+        shared.chain_state().consensus().as_ref(),
+        &parent,
+        &cell_set,
+    );
+    let mut encoded = Vec::new();
+    snapshot.write_to(&mut encoded).unwrap();
+    let snapshot = StateSnapshot::read_from(&encoded[..]).unwrap();
+    // the blocks of the proposal window, from 10 to 20, and block 5 holding a live cellbase
+    assert_eq!(snapshot.headers.len(), 20);
+    assert_eq!(snapshot.blocks.len(), 12);
+    assert_eq!(snapshot.cell_set, cell_set);
+
+    let imported = new_shared();
+    let block_1_hash = store.get_block_hash(1).unwrap();
+    assert!(imported.import_snapshot(&snapshot, &block_1_hash).is_err());
+    imported.import_snapshot(&snapshot, &parent.hash()).unwrap();
+    assert_eq!(imported.chain_state().tip_header(), &parent);
+    assert_eq!(
+        imported.chain_state().total_difficulty(),
+        &ext.total_difficulty
+    );
+    assert_eq!(
+        imported.store().get_block_hash(1),
+        Some(block_1_hash.clone())
+    );
+    assert!(imported.store().get_header(&block_1_hash).is_some());
+    assert!(imported.store().get_block(&block_1_hash).is_none());
+    assert!(imported.store().get_block(&parent.hash()).is_some());
+    assert!(imported
+        .store()
+        .get_block(&store.get_block_hash(5).unwrap())
+        .is_some());
+    assert_eq!(imported.chain_state().cell_set(), &cell_set);
+    assert_eq!(
+        imported.store().get_cell_set(&parent.hash()),
+        Some(cell_set)
+    );
+    assert_eq!(
+        imported
+            .chain_state()
+            .cell_set()
+            .is_dead(&OutPoint::new(cellbases[2].clone(), 0)),
+        Some(true)
+    );
+    // only a database with the genesis block only accepts a snapshot
+    assert!(imported.import_snapshot(&snapshot, &parent.hash()).is_err());
+}
+
+#[test]
+fn test_block_fees() {
+    let shared = new_shared();
+    let output = |capacity: Capacity| CellOutput::new(capacity, vec![], Script::default(), None);
+    let tx_a = TransactionBuilder::default()
+        .input(CellInput::new(OutPoint::new(H256::zero(), 0), 0, vec![]))
+        .output(output(capacity_bytes!(100)))
+        .build();
+    let tx_b = TransactionBuilder::default()
+        .input(CellInput::new(OutPoint::new(tx_a.hash(), 0), 0, vec![]))
+        .output(output(capacity_bytes!(60)))
+        .build();
+    // spends an output of the same block
+    let tx_c = TransactionBuilder::default()
+        .input(CellInput::new(OutPoint::new(tx_b.hash(), 0), 0, vec![]))
+        .output(output(capacity_bytes!(50)))
+        .build();
+    let block_1 = BlockBuilder::default()
+        .transaction(tx_a)
+        .with_header_builder(HeaderBuilder::default().number(1));
+    let block_2 = BlockBuilder::default()
+        .transaction(tx_b)
+        .transaction(tx_c)
+        .with_header_builder(HeaderBuilder::default().number(2));
+    let mut batch = shared.store().new_batch().unwrap();
+    for block in &[&block_1, &block_2] {
+        batch.insert_block(block).unwrap();
+        batch.attach_block(block).unwrap();
+    }
+    batch.commit().unwrap();
+
+    let chain_state = shared.chain_state();
+    assert_eq!(
+        chain_state.block_fees(&block_2.header().hash()),
+        Some(capacity_bytes!(50))
+    );
+    // the cell spent by block 1 is unknown
+    assert_eq!(chain_state.block_fees(&block_1.header().hash()), None);
+    assert_eq!(
+        chain_state
+            .expected_cellbase_capacity(2, capacity_bytes!(50))
+            .unwrap(),
+        chain_state
+            .block_reward(2)
+            .safe_add(capacity_bytes!(50))
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_max_issuance() {
+    let tx = TransactionBuilder::default()
+        .output(CellOutput::new(
+            capacity_bytes!(1000),
+            vec![],
+            Script::default(),
+            None,
+        ))
+        .build();
+    let genesis = BlockBuilder::default()
+        .transaction(tx)
+        .with_header_builder(HeaderBuilder::default());
+    let consensus = Consensus::default()
+        .set_genesis_block(genesis)
+        .set_initial_block_reward(capacity_bytes!(50));
+    let shared = SharedBuilder::<MemoryKeyValueDB>::new()
+        .consensus(consensus)
+        .build()
+        .unwrap();
+
+    let chain_state = shared.chain_state();
+    assert_eq!(chain_state.max_issuance(0), Some(capacity_bytes!(1000)));
+    assert_eq!(chain_state.max_issuance(3), Some(capacity_bytes!(1150)));
+    assert_eq!(chain_state.max_issuance(u64::max_value()), None);
+}
+
+#[test]
+fn test_tx_status_after_reorg() {
+    let shared = new_shared();
+    let cellbase = TransactionBuilder::default().build();
+    let tx = TransactionBuilder::default()
+        .input(CellInput::new(OutPoint::new(H256::zero(), 0), 0, vec![]))
+        .build();
+    let block_a = BlockBuilder::default()
+        .transaction(cellbase.clone())
+        .transaction(tx.clone())
+        .with_header_builder(HeaderBuilder::default().number(1));
+    let block_b = BlockBuilder::default()
+        .transaction(cellbase)
+        .with_header_builder(HeaderBuilder::default().number(1).timestamp(1));
+    assert_eq!(shared.tx_status(&tx.hash()), None);
+
+    let chain_state = shared.chain_state();
+    let reorg = |detached: Option<&Block>, attached: &Block| {
+        let mut tx_pool = shared.tx_pool().lock();
+        chain_state.update_tx_pool_for_reorg(
+            &mut tx_pool,
+            detached.into_iter(),
+            iter::once(attached),
+            iter::empty(),
+        );
+    };
+    reorg(None, &block_a);
+    assert_eq!(
+        shared.tx_status(&tx.hash()),
+        Some(TxStatus::Committed {
+            block_hash: block_a.header().hash()
+        })
+    );
+
+    // back in the pool, not as a new transaction
+    reorg(Some(&block_a), &block_b);
+    assert!(shared
+        .tx_pool()
+        .lock()
+        .contains_proposal_id(&tx.proposal_short_id()));
+    assert_eq!(
+        shared.tx_status(&tx.hash()),
+        Some(TxStatus::Reorged {
+            block_hash: block_a.header().hash()
+        })
+    );
+
+    reorg(Some(&block_b), &block_a);
+    assert_eq!(
+        shared.tx_status(&tx.hash()),
+        Some(TxStatus::Committed {
+            block_hash: block_a.header().hash()
+        })
+    );
+}
+
+#[test]
+fn test_backfill_tx_index() {
+    let tx = TransactionBuilder::default()
+        .input(CellInput::new(OutPoint::null(), 0, Default::default()))
+        .output(CellOutput::new(
+            capacity_bytes!(100),
+            vec![],
+            Script::default(),
+            None,
+        ))
+        .build();
+    let genesis = BlockBuilder::default()
+        .transaction(tx.clone())
+        .with_header_builder(HeaderBuilder::default());
+    let shared = SharedBuilder::<MemoryKeyValueDB>::new()
+        .consensus(Consensus::default().set_genesis_block(genesis))
+        .store_config(StoreConfig {
+            disable_tx_index: true,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let store = shared.store();
+    assert!(!store.is_tx_indexed());
+
+    // as if written before the index
+    let mut batch = store.new_batch().unwrap();
+    batch.delete_transaction_address(&tx.hash()).unwrap();
+    batch.commit().unwrap();
+    assert!(store.get_transaction(&tx.hash()).is_none());
+    assert_eq!(
+        store.get_transaction_in_block(&tx.hash(), 0),
+        Some(tx.clone())
+    );
+
+    shared
+        .chain_state()
+        .init_tx_index(&StoreConfig::default())
+        .unwrap();
+    assert!(store.is_tx_indexed());
+    assert_eq!(
+        store.get_transaction(&tx.hash()).map(|(tx, _)| tx),
+        Some(tx)
+    );
+}
+
+#[test]
+fn test_verify_and_repair_store() {
+    let tx = TransactionBuilder::default()
+        .input(CellInput::new(OutPoint::null(), 0, Default::default()))
+        .output(CellOutput::new(
+            capacity_bytes!(100),
+            vec![],
+            Script::default(),
+            None,
+        ))
+        .build();
+    let genesis = BlockBuilder::default()
+        .transaction(tx.clone())
+        .with_header_builder(HeaderBuilder::default());
+    let genesis_hash = genesis.header().hash();
+    let shared = SharedBuilder::<MemoryKeyValueDB>::new()
+        .consensus(Consensus::default().set_genesis_block(genesis))
+        .build()
+        .unwrap();
+    let store = shared.store();
+    let store_config = StoreConfig::default();
+    let verify = |repair| {
+        StoreVerifier::new(&**store, &store_config)
+            .repair(repair)
+            .verify()
+            .unwrap()
+    };
+
+    let report = verify(false);
+    assert!(report.is_consistent());
+    assert!(report.cell_set_checked);
+
+    let mut batch = store.new_batch().unwrap();
+    let ext = store.get_block_ext(&genesis_hash).unwrap();
+    batch
+        .insert_block_ext(
+            &genesis_hash,
+            &BlockExt {
+                total_uncles_count: 5,
+                ..ext
+            },
+        )
+        .unwrap();
+    batch.delete_transaction_address(&tx.hash()).unwrap();
+    let changes = CellSet::new().replacing(store.get_cell_set_tx_hashes());
+    batch.update_cell_set(&changes, &genesis_hash).unwrap();
+    batch.commit().unwrap();
+
+    let inconsistencies = vec![
+        StoreInconsistency::InvalidBlockExt(0, genesis_hash.clone()),
+        StoreInconsistency::InvalidTxIndex(0, tx.hash()),
+        StoreInconsistency::InvalidCellSet,
+    ];
+    let report = verify(false);
+    assert_eq!(report.inconsistencies, inconsistencies);
+    assert!(!report.repaired);
+    assert_eq!(verify(false).inconsistencies, inconsistencies);
+
+    let report = verify(true);
+    assert_eq!(report.inconsistencies, inconsistencies);
+    assert!(report.repaired);
+    assert!(verify(false).is_consistent());
+    assert_eq!(
+        store
+            .get_block_ext(&genesis_hash)
+            .unwrap()
+            .total_uncles_count,
+        0
+    );
+}
+
+#[test]
+fn test_block_pruning_depth_minimum() {
+    let build = |depth| {
+        SharedBuilder::<MemoryKeyValueDB>::new()
+            .store_config(StoreConfig {
+                block_pruning_depth: Some(depth),
+                ..Default::default()
+            })
+            .build()
+    };
+    let min_depth = StoreConfig::min_block_pruning_depth(&Consensus::default());
+    assert_eq!(min_depth, MIN_BLOCK_PRUNING_DEPTH);
+    assert!(build(min_depth).is_ok());
+    match build(3) {
+        Err(SharedError::InvalidConfig(_)) => {}
+        _ => panic!("a pruning depth of 3 should be rejected"),
+    }
+}
+
+#[test]
+fn test_persist_and_reload_tx_pool() {
+    let output = |capacity| CellOutput::new(capacity, vec![], Script::default(), None);
+    let genesis_tx = TransactionBuilder::default()
+        .output(output(capacity_bytes!(1000)))
+        .build();
+    let genesis = BlockBuilder::default()
+        .transaction(genesis_tx.clone())
+        .with_header_builder(HeaderBuilder::default());
+    let shared = SharedBuilder::<MemoryKeyValueDB>::new()
+        .consensus(
+            Consensus::default()
+                .set_genesis_block(genesis)
+                .set_cellbase_maturity(0),
+        )
+        .build()
+        .unwrap();
+    let spend = |tx_hash, capacity| {
+        TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(tx_hash, 0), 0, vec![]))
+            .output(output(capacity))
+            .build()
+    };
+    let parent = spend(genesis_tx.hash(), capacity_bytes!(900));
+    let child = spend(parent.hash(), capacity_bytes!(800));
+    // double spends the parent with a lower fee
+    let conflict = spend(genesis_tx.hash(), capacity_bytes!(950));
+    let persisted = |transaction, timestamp| PersistedTx {
+        transaction,
+        cycles: Some(100),
+        timestamp,
+    };
+    let persisted_parent = persisted(parent.clone(), 1000);
+    let persisted_child = persisted(child.clone(), 2000);
+    let store = shared.store();
+    let mut batch = store.new_batch().unwrap();
+    batch
+        .insert_tx_pool_snapshot(&[
+            // committed since it was saved
+            persisted(genesis_tx.clone(), 0),
+            persisted_parent.clone(),
+            persisted_child.clone(),
+            persisted(conflict.clone(), 3000),
+        ])
+        .unwrap();
+    batch.commit().unwrap();
+
+    let chain_state = shared.chain_state();
+    let mut tx_pool = TxPool::new(TxPoolConfig::default());
+    chain_state.reload_tx_pool(&mut tx_pool).unwrap();
+    assert_eq!(store.get_tx_pool_snapshot(), None);
+    assert!(!tx_pool.contains_proposal_id(&genesis_tx.proposal_short_id()));
+    assert!(!tx_pool.contains_proposal_id(&conflict.proposal_short_id()));
+    let timestamp = |tx: &Transaction| {
+        tx_pool
+            .get_pending_entry(&tx.proposal_short_id())
+            .map(|entry| entry.timestamp)
+    };
+    assert_eq!(timestamp(&parent), Some(1000));
+    assert_eq!(timestamp(&child), Some(2000));
+
+    // saved again parents first
+    chain_state.persist_tx_pool(&tx_pool).unwrap();
+    assert_eq!(
+        store.get_tx_pool_snapshot(),
+        Some(vec![persisted_parent, persisted_child])
+    );
+}
@@ -2,9 +2,13 @@ pub mod pool;
 pub mod trace;
 pub mod types;
 
+mod delayed;
 mod orphan;
 mod pending;
 mod staging;
 
 pub use self::pool::TxPool;
-pub use self::types::{PoolEntry, PoolError, TxPoolConfig};
+pub use self::types::{
+    CellResolution, CellResolutionDetail, PersistedTx, PoolEntry, PoolError, RejectedTx,
+    TxPoolConfig, TxPoolEvent, TxPoolPolicy, TxStatus, VerifyingEntry,
+};
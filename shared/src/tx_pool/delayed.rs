@@ -0,0 +1,203 @@
+use crate::tx_pool::types::PoolEntry;
+use ckb_core::header::BlockNumber;
+use ckb_core::transaction::{ProposalShortId, Transaction};
+use ckb_core::{Capacity, Cycle};
+use ckb_verification::Maturity;
+use fnv::{FnvHashMap, FnvHashSet};
+use std::collections::BTreeMap;
+
+/// Transactions spending immature cellbases or whose since conditions are not met yet. They
+/// wait for the tip number first, then for the median time, and are checked again once both
+/// are reached.
+#[derive(Default, Debug, Clone)]
+pub(crate) struct DelayedPool {
+    pub(crate) vertices: FnvHashMap<ProposalShortId, (PoolEntry, Maturity)>,
+    /// Transactions waiting for the tip number
+    by_number: BTreeMap<BlockNumber, FnvHashSet<ProposalShortId>>,
+    /// Transactions whose tip number is reached, waiting for the median time
+    by_median_time: BTreeMap<u64, FnvHashSet<ProposalShortId>>,
+}
+
+impl DelayedPool {
+    pub(crate) fn new() -> Self {
+        DelayedPool::default()
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub(crate) fn get(&self, id: &ProposalShortId) -> Option<&PoolEntry> {
+        self.vertices.get(id).map(|(entry, _)| entry)
+    }
+
+    pub(crate) fn get_tx(&self, id: &ProposalShortId) -> Option<&Transaction> {
+        self.get(id).map(|x| &x.transaction)
+    }
+
+    pub(crate) fn contains_key(&self, id: &ProposalShortId) -> bool {
+        self.vertices.contains_key(id)
+    }
+
+    pub(crate) fn add_tx(
+        &mut self,
+        cycles: Option<Cycle>,
+        fee: Capacity,
+        tx: Transaction,
+        maturity: Maturity,
+    ) {
+        let short_id = tx.proposal_short_id();
+        self.remove(&short_id);
+        self.by_number
+            .entry(maturity.block_number)
+            .or_insert_with(FnvHashSet::default)
+            .insert(short_id);
+        self.vertices
+            .insert(short_id, (PoolEntry::new(tx, 0, cycles, fee), maturity));
+    }
+
+    pub(crate) fn remove(&mut self, id: &ProposalShortId) -> Option<PoolEntry> {
+        let (entry, maturity) = self.vertices.remove(id)?;
+        remove_from_index(&mut self.by_number, maturity.block_number, id);
+        remove_from_index(&mut self.by_median_time, maturity.median_time, id);
+        Some(entry)
+    }
+
+    /// Remove the transactions which may be mature at the tip `tip_number`, whose median
+    /// time checked by since is `median_time`
+    pub(crate) fn remove_ready(
+        &mut self,
+        tip_number: BlockNumber,
+        median_time: u64,
+    ) -> Vec<PoolEntry> {
+        let reached: Vec<BlockNumber> = self
+            .by_number
+            .range(..=tip_number)
+            .map(|(number, _)| *number)
+            .collect();
+        for number in reached {
+            for id in self.by_number.remove(&number).unwrap_or_default() {
+                if let Some((_, maturity)) = self.vertices.get(&id) {
+                    self.by_median_time
+                        .entry(maturity.median_time)
+                        .or_insert_with(FnvHashSet::default)
+                        .insert(id);
+                }
+            }
+        }
+
+        let ready: Vec<u64> = self
+            .by_median_time
+            .range(..=median_time)
+            .map(|(median_time, _)| *median_time)
+            .collect();
+        let by_median_time = &mut self.by_median_time;
+        let vertices = &mut self.vertices;
+        ready
+            .into_iter()
+            .flat_map(|median_time| by_median_time.remove(&median_time).unwrap_or_default())
+            .filter_map(|id| vertices.remove(&id))
+            .map(|(entry, _)| entry)
+            .collect()
+    }
+
+    /// Remove the transaction which has the longest to wait
+    pub(crate) fn evict(&mut self) -> Option<PoolEntry> {
+        let id = self
+            .by_number
+            .values()
+            .next_back()
+            .or_else(|| self.by_median_time.values().next_back())
+            .and_then(|ids| ids.iter().next())
+            .cloned()?;
+        self.remove(&id)
+    }
+}
+
+fn remove_from_index<K: Ord>(
+    index: &mut BTreeMap<K, FnvHashSet<ProposalShortId>>,
+    key: K,
+    id: &ProposalShortId,
+) {
+    let emptied = match index.get_mut(&key) {
+        Some(ids) => ids.remove(id) && ids.is_empty(),
+        None => false,
+    };
+    if emptied {
+        index.remove(&key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_core::transaction::{CellInput, OutPoint, TransactionBuilder};
+    use numext_fixed_hash::H256;
+
+    fn build_tx(index: u32) -> Transaction {
+        TransactionBuilder::default()
+            .input(CellInput::new(
+                OutPoint::new(H256::zero(), index),
+                0,
+                vec![],
+            ))
+            .build()
+    }
+
+    #[test]
+    fn test_remove_ready() {
+        let mut pool = DelayedPool::new();
+        let by_number = build_tx(0);
+        let by_median_time = build_tx(1);
+        pool.add_tx(
+            None,
+            Capacity::zero(),
+            by_number.clone(),
+            Maturity {
+                block_number: 10,
+                median_time: 0,
+            },
+        );
+        pool.add_tx(
+            None,
+            Capacity::zero(),
+            by_median_time.clone(),
+            Maturity {
+                block_number: 5,
+                median_time: 1000,
+            },
+        );
+
+        assert!(pool.remove_ready(9, 999).is_empty());
+        let ready = pool.remove_ready(10, 999);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].transaction, by_number);
+
+        let ready = pool.remove_ready(10, 1000);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].transaction, by_median_time);
+        assert_eq!(pool.capacity(), 0);
+        assert!(pool.by_number.is_empty());
+        assert!(pool.by_median_time.is_empty());
+    }
+
+    #[test]
+    fn test_evict_longest_waiting() {
+        let mut pool = DelayedPool::new();
+        for (index, block_number) in [3, 30, 10].iter().enumerate() {
+            pool.add_tx(
+                None,
+                Capacity::zero(),
+                build_tx(index as u32),
+                Maturity {
+                    block_number: *block_number,
+                    median_time: 0,
+                },
+            );
+        }
+        let evicted = pool.evict().expect("evict");
+        assert_eq!(evicted.transaction, build_tx(1));
+        assert_eq!(pool.capacity(), 2);
+        assert!(!pool.by_number.contains_key(&30));
+    }
+}
@@ -2,8 +2,9 @@
 
 use crate::tx_pool::types::PoolEntry;
 use ckb_core::transaction::{OutPoint, ProposalShortId, Transaction};
-use ckb_core::Cycle;
+use ckb_core::{Capacity, Cycle};
 use fnv::FnvHashMap;
+use rand::{thread_rng, Rng};
 use std::collections::hash_map;
 use std::collections::VecDeque;
 use std::iter::ExactSizeIterator;
@@ -13,6 +14,8 @@ use std::iter::ExactSizeIterator;
 pub(crate) struct OrphanPool {
     pub(crate) vertices: FnvHashMap<ProposalShortId, PoolEntry>,
     pub(crate) edges: FnvHashMap<OutPoint, Vec<ProposalShortId>>,
+    /// Total serialized size of the orphan transactions
+    pub(crate) total_size: usize,
 }
 
 impl OrphanPool {
@@ -24,6 +27,10 @@ impl OrphanPool {
         self.vertices.len()
     }
 
+    pub(crate) fn total_size(&self) -> usize {
+        self.total_size
+    }
+
     pub(crate) fn get(&self, id: &ProposalShortId) -> Option<&PoolEntry> {
         self.vertices.get(id)
     }
@@ -44,30 +51,64 @@ impl OrphanPool {
     pub(crate) fn add_tx(
         &mut self,
         cycles: Option<Cycle>,
+        fee: Capacity,
         tx: Transaction,
         unknown: impl ExactSizeIterator<Item = OutPoint>,
     ) {
         let short_id = tx.proposal_short_id();
-        let entry = PoolEntry::new(tx, unknown.len(), cycles);
+        let entry = PoolEntry::new(tx, unknown.len(), cycles, fee);
         for out_point in unknown {
             let edge = self.edges.entry(out_point).or_insert_with(Vec::new);
             edge.push(short_id);
         }
-        self.vertices.insert(short_id, entry);
+        self.total_size += entry.size;
+        if let Some(old) = self.vertices.insert(short_id, entry) {
+            self.total_size -= old.size;
+        }
     }
 
-    pub(crate) fn recursion_remove(&mut self, id: &ProposalShortId) {
+    pub(crate) fn recursion_remove(&mut self, id: &ProposalShortId) -> Vec<PoolEntry> {
+        let mut txs = Vec::new();
         let mut queue: VecDeque<ProposalShortId> = VecDeque::new();
         queue.push_back(id.clone());
         while let Some(id) = queue.pop_front() {
             if let Some(entry) = self.vertices.remove(&id) {
+                self.total_size -= entry.size;
                 for outpoint in entry.transaction.output_pts() {
                     if let Some(ids) = self.edges.remove(&outpoint) {
                         queue.extend(ids);
                     }
                 }
+                txs.push(entry);
             }
         }
+        txs
+    }
+
+    /// Remove a random transaction along with the orphans spending it. The fee of an orphan
+    /// is unknown until its inputs are resolved, so it can't tell which one to keep.
+    pub(crate) fn evict(&mut self) -> Vec<PoolEntry> {
+        if self.vertices.is_empty() {
+            return Vec::new();
+        }
+        let index = thread_rng().gen_range(0, self.vertices.len());
+        let id = *self.vertices.keys().nth(index).expect("index is in range");
+        self.recursion_remove(&id)
+    }
+
+    /// Remove the orphans which have been waiting for their parents longer than `max_age`
+    /// milliseconds, along with the orphans spending them
+    pub(crate) fn remove_expired(&mut self, now: u64, max_age: u64) -> Vec<PoolEntry> {
+        let expired: Vec<ProposalShortId> = self
+            .vertices
+            .iter()
+            .filter(|(_, entry)| now.saturating_sub(entry.timestamp) > max_age)
+            .map(|(id, _)| *id)
+            .collect();
+        expired
+            .iter()
+            .flat_map(|id| self.recursion_remove(id))
+            .collect()
     }
 
     pub(crate) fn remove_by_ancestor(&mut self, tx: &Transaction) -> Vec<PoolEntry> {
@@ -90,6 +131,7 @@ impl OrphanPool {
 
                             if refs_count == 0 {
                                 let tx = o.remove();
+                                self.total_size -= tx.size;
                                 queue.push_back(tx.transaction.output_pts());
                                 txs.push(tx);
                             }
@@ -122,6 +164,8 @@ mod tests {
     use ckb_core::Capacity;
     use numext_fixed_hash::H256;
 
+    const MOCK_FEE: Capacity = Capacity::zero();
+
     fn build_tx(inputs: Vec<(H256, u32)>, outputs_len: usize) -> Transaction {
         TransactionBuilder::default()
             .inputs(
@@ -162,9 +206,9 @@ mod tests {
 
         let tx4 = build_tx(vec![(tx3_hash, 0)], 1);
 
-        pool.add_tx(None, tx2.clone(), tx1.output_pts().into_iter());
-        pool.add_tx(None, tx3.clone(), tx2.output_pts().into_iter());
-        pool.add_tx(None, tx4.clone(), tx3.output_pts().into_iter());
+        pool.add_tx(None, MOCK_FEE, tx2.clone(), tx1.output_pts().into_iter());
+        pool.add_tx(None, MOCK_FEE, tx3.clone(), tx2.output_pts().into_iter());
+        pool.add_tx(None, MOCK_FEE, tx4.clone(), tx3.output_pts().into_iter());
 
         assert!(pool.contains(&tx2));
         assert!(pool.contains(&tx3));
@@ -194,8 +238,8 @@ mod tests {
 
         let tx4 = build_tx(vec![(tx3_hash, 0)], 1);
 
-        pool.add_tx(None, tx3.clone(), tx2.output_pts().into_iter());
-        pool.add_tx(None, tx4.clone(), tx3.output_pts().into_iter());
+        pool.add_tx(None, MOCK_FEE, tx3.clone(), tx2.output_pts().into_iter());
+        pool.add_tx(None, MOCK_FEE, tx4.clone(), tx3.output_pts().into_iter());
 
         assert!(pool.contains(&tx3));
 
@@ -229,9 +273,9 @@ mod tests {
 
         let tx4 = build_tx(vec![(tx3_hash, 0)], 1);
 
-        pool.add_tx(None, tx2.clone(), tx1.output_pts().into_iter());
-        pool.add_tx(None, tx3.clone(), tx2.output_pts().into_iter());
-        pool.add_tx(None, tx4.clone(), tx3.output_pts().into_iter());
+        pool.add_tx(None, MOCK_FEE, tx2.clone(), tx1.output_pts().into_iter());
+        pool.add_tx(None, MOCK_FEE, tx3.clone(), tx2.output_pts().into_iter());
+        pool.add_tx(None, MOCK_FEE, tx4.clone(), tx3.output_pts().into_iter());
 
         assert!(pool.contains(&tx2));
         assert!(pool.contains(&tx3));
@@ -245,4 +289,30 @@ mod tests {
         assert!(!pool.contains(&tx3));
         assert!(!pool.contains(&tx4));
     }
+
+    #[test]
+    fn test_orphan_pool_remove_expired() {
+        let mut pool = OrphanPool::new();
+
+        let tx1 = build_tx(vec![(H256::zero(), 0)], 1);
+        let tx2 = build_tx(vec![(tx1.hash(), 0)], 1);
+        let tx3 = build_tx(vec![(tx2.hash(), 0)], 1);
+
+        pool.add_tx(None, MOCK_FEE, tx2.clone(), tx1.output_pts().into_iter());
+        pool.add_tx(None, MOCK_FEE, tx3.clone(), tx2.output_pts().into_iter());
+        let added_at = pool.get(&tx2.proposal_short_id()).unwrap().timestamp;
+
+        assert!(pool.remove_expired(added_at + 1000, 1000).is_empty());
+        assert_eq!(pool.capacity(), 2);
+
+        let txs: Vec<_> = pool
+            .remove_expired(added_at + 1001, 1000)
+            .into_iter()
+            .map(|e| e.transaction)
+            .collect();
+        assert_eq!(txs.len(), 2);
+        assert!(txs.contains(&tx2) && txs.contains(&tx3));
+        assert_eq!(pool.capacity(), 0);
+        assert_eq!(pool.total_size(), 0);
+    }
 }
@@ -3,28 +3,82 @@
 use crate::tx_pool::types::PoolEntry;
 use ckb_core::cell::{CellProvider, CellStatus};
 use ckb_core::transaction::{OutPoint, ProposalShortId, Transaction};
-use ckb_core::Cycle;
-use fnv::FnvHashMap;
+use ckb_core::{Capacity, Cycle};
+use fnv::{FnvHashMap, FnvHashSet};
+use std::collections::VecDeque;
 
 #[derive(Default, Debug, Clone)]
 pub(crate) struct PendingQueue {
     pub(crate) inner: FnvHashMap<ProposalShortId, PoolEntry>,
+    /// Total serialized size of the queued transactions
+    pub(crate) total_size: usize,
+    /// Queued transactions spending the out point
+    pub(crate) spent: FnvHashMap<OutPoint, FnvHashSet<ProposalShortId>>,
+    /// Queued transactions depending on the out point
+    pub(crate) deps: FnvHashMap<OutPoint, FnvHashSet<ProposalShortId>>,
+}
+
+fn link(
+    edges: &mut FnvHashMap<OutPoint, FnvHashSet<ProposalShortId>>,
+    out_point: OutPoint,
+    id: ProposalShortId,
+) {
+    edges
+        .entry(out_point)
+        .or_insert_with(FnvHashSet::default)
+        .insert(id);
+}
+
+fn unlink(
+    edges: &mut FnvHashMap<OutPoint, FnvHashSet<ProposalShortId>>,
+    out_point: &OutPoint,
+    id: &ProposalShortId,
+) {
+    let empty = match edges.get_mut(out_point) {
+        Some(ids) => {
+            ids.remove(id);
+            ids.is_empty()
+        }
+        None => false,
+    };
+    if empty {
+        edges.remove(out_point);
+    }
 }
 
 impl PendingQueue {
     pub fn new() -> Self {
-        PendingQueue {
-            inner: FnvHashMap::default(),
-        }
+        PendingQueue::default()
     }
 
     pub fn size(&self) -> usize {
         self.inner.len()
     }
 
-    pub(crate) fn add_tx(&mut self, cycles: Option<Cycle>, tx: Transaction) -> Option<PoolEntry> {
+    pub(crate) fn total_size(&self) -> usize {
+        self.total_size
+    }
+
+    pub(crate) fn add_tx(
+        &mut self,
+        cycles: Option<Cycle>,
+        fee: Capacity,
+        tx: Transaction,
+    ) -> Option<PoolEntry> {
         let short_id = tx.proposal_short_id();
-        self.inner.insert(short_id, PoolEntry::new(tx, 0, cycles))
+        for i in tx.input_pts() {
+            link(&mut self.spent, i, short_id);
+        }
+        for d in tx.dep_pts() {
+            link(&mut self.deps, d, short_id);
+        }
+        let entry = PoolEntry::new(tx, 0, cycles, fee);
+        self.total_size += entry.size;
+        let old = self.inner.insert(short_id, entry);
+        if let Some(ref old) = old {
+            self.total_size -= old.size;
+        }
+        old
     }
 
     pub(crate) fn contains_key(&self, id: &ProposalShortId) -> bool {
@@ -40,7 +94,60 @@ impl PendingQueue {
     }
 
     pub(crate) fn remove(&mut self, id: &ProposalShortId) -> Option<PoolEntry> {
-        self.inner.remove(id)
+        let entry = self.inner.remove(id);
+        if let Some(ref entry) = entry {
+            self.total_size -= entry.size;
+            for i in entry.transaction.input_pts() {
+                unlink(&mut self.spent, &i, id);
+            }
+            for d in entry.transaction.dep_pts() {
+                unlink(&mut self.deps, &d, id);
+            }
+        }
+        entry
+    }
+
+    /// Queued transactions which spend any input of the transaction
+    pub(crate) fn conflicts(&self, tx: &Transaction) -> FnvHashSet<ProposalShortId> {
+        let short_id = tx.proposal_short_id();
+        tx.input_pts()
+            .iter()
+            .filter_map(|i| self.spent.get(i))
+            .flat_map(|ids| ids.iter().cloned())
+            .filter(|id| *id != short_id)
+            .collect()
+    }
+
+    /// The transactions and all queued transactions spending or depending on their outputs
+    pub(crate) fn with_descendants(
+        &self,
+        ids: impl Iterator<Item = ProposalShortId>,
+    ) -> FnvHashSet<ProposalShortId> {
+        let mut descendants = FnvHashSet::default();
+        let mut queue: VecDeque<ProposalShortId> = ids.collect();
+        while let Some(id) = queue.pop_front() {
+            if !descendants.insert(id) {
+                continue;
+            }
+            if let Some(entry) = self.inner.get(&id) {
+                for o in entry.transaction.output_pts() {
+                    let spent = self.spent.get(&o).into_iter().flatten();
+                    let deps = self.deps.get(&o).into_iter().flatten();
+                    queue.extend(spent.chain(deps).cloned());
+                }
+            }
+        }
+        descendants
+    }
+
+    /// Remove the transaction with the lowest fee rate
+    pub(crate) fn evict(&mut self) -> Option<PoolEntry> {
+        let id = self
+            .inner
+            .iter()
+            .min_by_key(|(_, entry)| entry.fee_rate())
+            .map(|(id, _)| *id)?;
+        self.remove(&id)
     }
 
     pub(crate) fn fetch(&self, n: usize) -> Vec<ProposalShortId> {
@@ -60,3 +167,62 @@ impl CellProvider for PendingQueue {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_core::script::Script;
+    use ckb_core::transaction::{CellInput, CellOutput, TransactionBuilder};
+    use numext_fixed_hash::H256;
+
+    fn build_tx(inputs: Vec<(H256, u32)>, outputs_len: usize) -> Transaction {
+        TransactionBuilder::default()
+            .inputs(
+                inputs
+                    .into_iter()
+                    .map(|(txid, index)| {
+                        CellInput::new(OutPoint::new(txid, index), 0, Default::default())
+                    })
+                    .collect(),
+            )
+            .outputs(
+                (0..outputs_len)
+                    .map(|i| {
+                        CellOutput::new(
+                            Capacity::bytes(i + 1).unwrap(),
+                            Vec::new(),
+                            Script::default(),
+                            None,
+                        )
+                    })
+                    .collect(),
+            )
+            .build()
+    }
+
+    #[test]
+    fn test_conflicts_and_descendants() {
+        let tx1 = build_tx(vec![(H256::zero(), 0)], 1);
+        let tx2 = build_tx(vec![(tx1.hash(), 0)], 1);
+        let tx3 = build_tx(vec![(H256::zero(), 1)], 1);
+        let double_spend = build_tx(vec![(H256::zero(), 0), (H256::zero(), 2)], 1);
+
+        let mut queue = PendingQueue::new();
+        for tx in &[&tx1, &tx2, &tx3] {
+            queue.add_tx(None, Capacity::zero(), (*tx).clone());
+        }
+
+        let conflicts = queue.conflicts(&double_spend);
+        assert_eq!(conflicts.len(), 1);
+        assert!(conflicts.contains(&tx1.proposal_short_id()));
+
+        let replaced = queue.with_descendants(conflicts.into_iter());
+        assert_eq!(replaced.len(), 2);
+        assert!(replaced.contains(&tx1.proposal_short_id()));
+        assert!(replaced.contains(&tx2.proposal_short_id()));
+
+        queue.remove(&tx1.proposal_short_id());
+        assert!(queue.conflicts(&double_spend).is_empty());
+        assert!(queue.spent.get(&OutPoint::new(H256::zero(), 0)).is_none());
+    }
+}
@@ -1,16 +1,32 @@
 //! Top-level Pool type, methods, and tests
 use super::trace::TxTraceMap;
-use super::types::{PoolEntry, TxPoolConfig};
+use super::types::{
+    fee_rate, PoolEntry, PoolError, RejectedTx, TxPoolConfig, TxPoolEvent, TxStatus, VerifyingEntry,
+};
+use crate::fee_estimator::FeeEstimator;
+use crate::tx_pool::delayed::DelayedPool;
 use crate::tx_pool::orphan::OrphanPool;
 use crate::tx_pool::pending::PendingQueue;
 use crate::tx_pool::staging::StagingPool;
+use ckb_core::block::Block;
+use ckb_core::header::BlockNumber;
 use ckb_core::transaction::{OutPoint, ProposalShortId, Transaction};
-use ckb_core::Cycle;
+use ckb_core::{Capacity, Cycle};
+use ckb_verification::Maturity;
+use crossbeam_channel::{self, Receiver, Sender, TrySendError};
 use faketime::unix_time_as_millis;
+use fnv::{FnvHashMap, FnvHashSet};
 use jsonrpc_types::TxTrace;
-use log::trace;
+use log::{trace, warn};
 use lru_cache::LruCache;
 use numext_fixed_hash::H256;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// Events a subscriber may lag behind, further events are dropped for it
+const EVENT_CHANNEL_SIZE: usize = 1024;
+/// Transactions whose commit or reorg is remembered
+const TX_STATUSES_SIZE: usize = 10_000;
 
 #[derive(Debug, Clone)]
 pub struct TxPool {
@@ -21,18 +37,34 @@ pub struct TxPool {
     pub(crate) staging: StagingPool,
     /// Orphans in the pool
     pub(crate) orphan: OrphanPool,
+    /// Transactions waiting to mature
+    pub(crate) delayed: DelayedPool,
     /// cache for conflict transaction
     pub(crate) conflict: LruCache<ProposalShortId, PoolEntry>,
     /// trace record map
     pub(crate) trace: TxTraceMap,
     /// last txs updated timestamp
     pub(crate) last_txs_updated_at: u64,
+    /// locally submitted transactions with the submitted timestamp
+    pub(crate) local_txs: FnvHashMap<ProposalShortId, u64>,
+    /// confirmation delays of the accepted transactions
+    pub(crate) fee_estimator: FeeEstimator,
+    /// Transactions whose scripts are queued for verification
+    pub(crate) verifying: FnvHashMap<ProposalShortId, VerifyingEntry>,
+    /// Recently rejected transactions, bad ones are rejected again without verification
+    pub(crate) rejected: LruCache<H256, RejectedTx>,
+    /// Transactions recently committed in or reorged out of the main chain, only the
+    /// `Committed` and `Reorged` statuses are recorded
+    pub(crate) tx_statuses: LruCache<H256, TxStatus>,
+    /// subscriber name => sender of the pool events
+    pub(crate) event_subscribers: FnvHashMap<String, Sender<TxPoolEvent>>,
 }
 
 impl TxPool {
     pub fn new(config: TxPoolConfig) -> TxPool {
         let cache_size = config.max_cache_size;
         let trace_size = config.trace.unwrap_or(0);
+        let rejected_size = config.max_rejected_size;
         let last_txs_updated_at = 0u64;
 
         TxPool {
@@ -40,24 +72,195 @@ impl TxPool {
             pending: PendingQueue::new(),
             staging: StagingPool::new(),
             orphan: OrphanPool::new(),
+            delayed: DelayedPool::new(),
             conflict: LruCache::new(cache_size),
             last_txs_updated_at,
             trace: TxTraceMap::new(trace_size),
+            local_txs: FnvHashMap::default(),
+            fee_estimator: FeeEstimator::new(),
+            verifying: FnvHashMap::default(),
+            rejected: LruCache::new(rejected_size),
+            tx_statuses: LruCache::new(TX_STATUSES_SIZE),
+            event_subscribers: FnvHashMap::default(),
         }
     }
 
     // enqueue_tx inserts a new transaction into the non-verifiable transaction queue.
-    pub fn enqueue_tx(&mut self, cycles: Option<Cycle>, tx: Transaction) -> bool {
-        self.pending.add_tx(cycles, tx).is_none()
+    pub fn enqueue_tx(&mut self, cycles: Option<Cycle>, fee: Capacity, tx: Transaction) -> bool {
+        if self.config.trace_enable() {
+            self.trace
+                .add_pending(&tx.hash(), "tx added to pending queue");
+        }
+        let ret = self.pending.add_tx(cycles, fee, tx).is_none();
+        self.limit_pending();
+        ret
     }
 
-    // trace_tx basically same as enqueue_tx, but additional register a trace.
-    pub fn trace_tx(&mut self, tx: Transaction) -> bool {
+    /// Start recording the events of the transaction, which is then submitted as any other,
+    /// see `get_tx_traces`
+    pub fn trace_tx(&mut self, tx_hash: &H256) {
+        if self.config.trace_enable() {
+            self.trace.received(tx_hash, "tx received");
+        }
+    }
+
+    pub(crate) fn trace_verified(&mut self, tx_hash: &H256, cycles: Cycle) {
         if self.config.trace_enable() {
             self.trace
-                .add_pending(&tx.hash(), "unknown tx, insert to pending queue");
+                .verified(tx_hash, format!("scripts verified, {} cycles", cycles));
+        }
+    }
+
+    /// Record the traced transactions the attached block proposes or commits
+    pub(crate) fn trace_attached_block(&mut self, block: &Block) {
+        if !self.config.trace_enable() {
+            return;
+        }
+        let header = block.header();
+        let block_info = format!("block {} {:#x}", header.number(), header.hash());
+        for id in block.union_proposal_ids() {
+            if let Some(tx) = self.get_tx(&id) {
+                self.trace
+                    .proposed(&tx.hash(), format!("proposed in {}", block_info));
+            }
+        }
+        for tx in block.transactions().iter().skip(1) {
+            self.trace
+                .committed(&tx.hash(), format!("committed in {}", block_info));
+        }
+    }
+
+    pub fn mark_local_tx(&mut self, id: ProposalShortId) {
+        self.local_txs.insert(id, unix_time_as_millis());
+    }
+
+    pub fn remove_local_tx(&mut self, id: &ProposalShortId) -> bool {
+        self.local_txs.remove(id).is_some()
+    }
+
+    /// Locally submitted transactions still in pending or staging pool, with the submitted
+    /// timestamp. Marks of transactions which have left the pool are dropped.
+    pub fn local_txs(&mut self) -> Vec<(u64, PoolEntry)> {
+        let pending = &self.pending;
+        let staging = &self.staging;
+        let mut entries = Vec::new();
+        self.local_txs.retain(|id, submitted_at| {
+            match pending.get(id).or_else(|| staging.get(id)) {
+                Some(entry) => {
+                    entries.push((*submitted_at, entry.clone()));
+                    true
+                }
+                None => false,
+            }
+        });
+        entries
+    }
+
+    /// Entries added back from a persisted pool keep their original creation time
+    pub(crate) fn set_timestamp(&mut self, id: &ProposalShortId, timestamp: u64) {
+        let entry = match self.pending.inner.get_mut(id) {
+            Some(entry) => Some(entry),
+            None => self.staging.vertices.get_mut(id),
+        };
+        if let Some(entry) = entry {
+            entry.timestamp = timestamp;
+        }
+    }
+
+    /// Receive the pool events from now on, a subscriber registered with the same name is
+    /// replaced. Events are dropped for a subscriber lagging too far behind.
+    pub fn subscribe_events<S: ToString>(&mut self, name: S) -> Receiver<TxPoolEvent> {
+        let (sender, receiver) = crossbeam_channel::bounded(EVENT_CHANNEL_SIZE);
+        self.event_subscribers.insert(name.to_string(), sender);
+        receiver
+    }
+
+    pub(crate) fn notify(&mut self, event: TxPoolEvent) {
+        self.event_subscribers
+            .retain(|name, sender| match sender.try_send(event.clone()) {
+                Ok(_) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!(target: "tx_pool", "subscriber {} lags behind, drop {:?}", name, event);
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+
+    /// Remember why the transaction was rejected, errors which are not rejections are ignored
+    pub(crate) fn add_rejected(&mut self, tx_hash: H256, reason: &PoolError) {
+        if reason.is_rejection() {
+            trace!(target: "tx_pool", "tx {:x} rejected: {:?}", tx_hash, reason);
+            if self.config.trace_enable() {
+                self.trace.rejected(&tx_hash, reason);
+            }
+            self.rejected.insert(
+                tx_hash.clone(),
+                RejectedTx {
+                    reason: reason.clone(),
+                    timestamp: unix_time_as_millis(),
+                },
+            );
+            self.notify(TxPoolEvent::Rejected {
+                tx_hash,
+                reason: reason.clone(),
+            });
+        }
+    }
+
+    pub fn get_rejected(&self, tx_hash: &H256) -> Option<&RejectedTx> {
+        self.rejected.get(tx_hash)
+    }
+
+    /// The error of a bad transaction rejected recently, it is not worth verifying again
+    pub(crate) fn recently_rejected_bad_tx(&self, tx_hash: &H256) -> Option<PoolError> {
+        self.rejected
+            .get(tx_hash)
+            .filter(|rejected| rejected.reason.is_bad_tx())
+            .map(|rejected| rejected.reason.clone())
+    }
+
+    pub(crate) fn set_tx_status(&mut self, tx_hash: H256, status: TxStatus) {
+        self.tx_statuses.insert(tx_hash, status);
+    }
+
+    /// Status of the transaction if it is in the pool or recently committed. A reorged
+    /// transaction stays `Reorged` until it is committed again or leaves the pool.
+    pub fn get_tx_status(&self, tx_hash: &H256) -> Option<TxStatus> {
+        let id = ProposalShortId::from_tx_hash(tx_hash);
+        let is_tx = |tx: &Transaction| &tx.hash() == tx_hash;
+        let pool_status = if self
+            .staging
+            .get(&id)
+            .filter(|entry| is_tx(&entry.transaction))
+            .is_some()
+        {
+            Some(TxStatus::Proposed)
+        } else if self
+            .pending
+            .get(&id)
+            .or_else(|| self.orphan.get(&id))
+            .or_else(|| self.delayed.get(&id))
+            .map(|entry| &entry.transaction)
+            .or_else(|| self.verifying.get(&id).map(|entry| &entry.transaction))
+            .filter(|tx| is_tx(tx))
+            .is_some()
+        {
+            Some(TxStatus::Pending)
+        } else {
+            None
+        };
+        match self.tx_statuses.get(tx_hash) {
+            Some(TxStatus::Committed { block_hash }) => Some(TxStatus::Committed {
+                block_hash: block_hash.clone(),
+            }),
+            Some(TxStatus::Reorged { block_hash }) if pool_status.is_some() => {
+                Some(TxStatus::Reorged {
+                    block_hash: block_hash.clone(),
+                })
+            }
+            _ => pool_status,
         }
-        self.pending.add_tx(None, tx).is_none()
     }
 
     pub fn get_tx_traces(&self, hash: &H256) -> Option<&Vec<TxTrace>> {
@@ -67,6 +270,7 @@ impl TxPool {
     pub(crate) fn add_orphan(
         &mut self,
         cycles: Option<Cycle>,
+        fee: Capacity,
         tx: Transaction,
         unknowns: Vec<OutPoint>,
     ) {
@@ -77,32 +281,209 @@ impl TxPool {
                 format!("orphan tx, unknown inputs/deps {:?}", unknowns),
             );
         }
-        self.orphan.add_tx(cycles, tx, unknowns.into_iter());
+        self.orphan.add_tx(cycles, fee, tx, unknowns.into_iter());
+        self.remove_expired_orphans();
+        while self.orphan.capacity() > self.config.max_orphan_size
+            || self.orphan.total_size() > self.config.max_orphan_bytes
+        {
+            let entries = self.orphan.evict();
+            self.trace_evicted(&entries, "orphan pool is full");
+        }
+    }
+
+    /// Keep the transaction until the tip reaches `maturity`, see `remove_mature_delayed`
+    pub(crate) fn add_delayed(
+        &mut self,
+        cycles: Option<Cycle>,
+        fee: Capacity,
+        tx: Transaction,
+        maturity: Maturity,
+    ) {
+        trace!(target: "tx_pool", "add_delayed {:#x} {:?}", tx.hash(), maturity);
+        if self.config.trace_enable() {
+            self.trace.delayed(
+                &tx.hash(),
+                format!(
+                    "tx immature, wait for block {} and median time {}",
+                    maturity.block_number, maturity.median_time
+                ),
+            );
+        }
+        self.delayed.add_tx(cycles, fee, tx, maturity);
+        while self.delayed.capacity() > self.config.max_delayed_size {
+            match self.delayed.evict() {
+                Some(entry) => self.trace_evicted(&[entry], "delayed pool is full"),
+                None => break,
+            }
+        }
     }
 
-    pub(crate) fn add_staging(&mut self, cycles: Cycle, tx: Transaction) {
+    /// Remove the delayed transactions which may be mature at the tip `tip_number`, whose
+    /// median time checked by since is `median_time`. They must be checked again.
+    pub(crate) fn remove_mature_delayed(
+        &mut self,
+        tip_number: BlockNumber,
+        median_time: u64,
+    ) -> Vec<PoolEntry> {
+        self.delayed.remove_ready(tip_number, median_time)
+    }
+
+    pub(crate) fn add_staging(&mut self, cycles: Cycle, fee: Capacity, tx: Transaction) {
         trace!(target: "tx_pool", "add_staging {:#x}", tx.hash());
         if self.config.trace_enable() {
             self.trace.staged(&tx.hash(), "tx staged".to_string());
         }
         self.touch_last_txs_updated_at();
-        self.staging.add_tx(cycles, tx);
+        self.staging.add_tx(cycles, fee, tx);
+        while self.staging.capacity() > self.config.max_pool_size
+            || self.staging.total_size() > self.config.max_pool_bytes
+        {
+            match self.staging.evict() {
+                Some(entries) => self.trace_evicted(&entries, "staging pool is full"),
+                None => break,
+            }
+        }
+    }
+
+    /// Replace-by-fee: remove the pending transactions which double spend the inputs of `tx`,
+    /// along with their descendants, when `tx` pays a higher fee rate than each of them by
+    /// at least `rbf_fee_rate_increment` and no less fee than all of them together.
+    /// Does nothing unless `enable_rbf` is set.
+    pub(crate) fn replace_pending(
+        &mut self,
+        tx: &Transaction,
+        fee: Capacity,
+    ) -> Result<Vec<PoolEntry>, PoolError> {
+        if !self.config.enable_rbf {
+            return Ok(Vec::new());
+        }
+        let conflicts = self.pending.conflicts(tx);
+        if conflicts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let replaced = self.pending.with_descendants(conflicts.iter().cloned());
+        if tx
+            .input_pts()
+            .iter()
+            .chain(tx.dep_pts().iter())
+            .any(|o| replaced.contains(&ProposalShortId::from_tx_hash(&o.tx_hash)))
+        {
+            return Err(PoolError::ReplacementSpendsReplaced);
+        }
+
+        let required_fee_rate = conflicts
+            .iter()
+            .filter_map(|id| self.pending.get(id))
+            .map(PoolEntry::fee_rate)
+            .max()
+            .unwrap_or(0)
+            .saturating_add(self.config.rbf_fee_rate_increment);
+        let replaced_fee = replaced
+            .iter()
+            .filter_map(|id| self.pending.get(id))
+            .map(|entry| entry.fee.as_u64())
+            .fold(0u64, u64::saturating_add);
+        if fee_rate(fee, tx.serialized_size()) < required_fee_rate || fee.as_u64() < replaced_fee {
+            return Err(PoolError::ReplacementFeeTooLow(required_fee_rate));
+        }
+        if self.evicted_after_replacing(tx, fee, &replaced) {
+            return Err(PoolError::OverCapacity);
+        }
+
+        let entries: Vec<PoolEntry> = replaced
+            .iter()
+            .filter_map(|id| self.pending.remove(id))
+            .collect();
+        let reason = format!("replaced by {:#x}", tx.hash());
+        self.trace_evicted(&entries, &reason);
+        Ok(entries)
+    }
+
+    // Whether `limit_pending` would evict `tx` once it replaces `replaced`, the replaced
+    // transactions are kept then
+    fn evicted_after_replacing(
+        &self,
+        tx: &Transaction,
+        fee: Capacity,
+        replaced: &FnvHashSet<ProposalShortId>,
+    ) -> bool {
+        let tx_size = tx.serialized_size();
+        let tx_fee_rate = fee_rate(fee, tx_size);
+        let replaced_entries: Vec<&PoolEntry> = replaced
+            .iter()
+            .filter_map(|id| self.pending.get(id))
+            .collect();
+        let mut size = self.pending.size() + 1 - replaced_entries.len();
+        let mut total_size = self.pending.total_size() + tx_size
+            - replaced_entries
+                .iter()
+                .map(|entry| entry.size)
+                .sum::<usize>();
+        let mut remaining: Vec<(u64, usize)> = self
+            .pending
+            .inner
+            .iter()
+            .filter(|(id, _)| !replaced.contains(id))
+            .map(|(_, entry)| (entry.fee_rate(), entry.size))
+            .collect();
+        remaining.sort();
+        let mut remaining = remaining.into_iter();
+        while size > self.config.max_pending_size || total_size > self.config.max_pending_bytes {
+            match remaining.next() {
+                Some((fee_rate, entry_size)) if fee_rate < tx_fee_rate => {
+                    size -= 1;
+                    total_size -= entry_size;
+                }
+                _ => return true,
+            }
+        }
+        false
+    }
+
+    /// Drop the orphans whose parents haven't arrived in `max_orphan_age`
+    pub fn remove_expired_orphans(&mut self) {
+        let entries = self
+            .orphan
+            .remove_expired(unix_time_as_millis(), self.config.max_orphan_age);
+        self.trace_evicted(&entries, "orphan expired");
+    }
+
+    /// Evict the lowest fee rate transactions until the pending queue fits in its limits
+    fn limit_pending(&mut self) {
+        while self.pending.size() > self.config.max_pending_size
+            || self.pending.total_size() > self.config.max_pending_bytes
+        {
+            match self.pending.evict() {
+                Some(entry) => self.trace_evicted(&[entry], "pending queue is full"),
+                None => break,
+            }
+        }
+    }
+
+    fn trace_evicted(&mut self, entries: &[PoolEntry], reason: &str) {
+        for entry in entries {
+            trace!(
+                target: "tx_pool",
+                "evicted {:#x}, {}",
+                entry.transaction.hash(),
+                reason
+            );
+            if self.config.trace_enable() {
+                self.trace.evicted(&entry.transaction.hash(), reason);
+            }
+            self.notify(TxPoolEvent::Evicted {
+                tx_hash: entry.transaction.hash(),
+                reason: reason.to_string(),
+            });
+        }
     }
 
     pub(crate) fn remove_pending_and_conflict(
         &mut self,
         id: &ProposalShortId,
     ) -> Option<PoolEntry> {
-        self.pending
-            .remove(id)
-            .or_else(|| self.conflict.remove(id))
-            .map(|entry| {
-                if self.config.trace_enable() {
-                    self.trace
-                        .proposed(&entry.transaction.hash(), format!("{:?} proposed", id));
-                }
-                entry
-            })
+        self.pending.remove(id).or_else(|| self.conflict.remove(id))
     }
 
     pub(crate) fn capacity(&self) -> usize {
@@ -113,15 +494,63 @@ impl TxPool {
         self.last_txs_updated_at = unix_time_as_millis();
     }
 
+    pub fn get_last_txs_updated_at(&self) -> u64 {
+        self.last_txs_updated_at
+    }
+
+    /// Staging transactions are returned in topology, packages with higher ancestor fee rate first
+    pub fn get_proposal_and_staging_txs(
+        &self,
+        max_prop: usize,
+        max_tx: usize,
+    ) -> (Vec<ProposalShortId>, Vec<PoolEntry>) {
+        let proposal = self.pending.fetch(max_prop);
+        let staging_txs = self.staging.get_txs(max_tx);
+        (proposal, staging_txs)
+    }
+
+    /// Lowest fee rate in shannons per 1000 bytes for a transaction to be committed within
+    /// `target_blocks`, estimated from the pool transactions committed in recent blocks
+    pub fn estimate_fee_rate(&self, target_blocks: usize) -> Option<u64> {
+        self.fee_estimator.estimate_fee_rate(target_blocks)
+    }
+
     pub fn staging_txs_iter(&self) -> impl Iterator<Item = &PoolEntry> {
         self.staging.txs_iter()
     }
 
+    pub(crate) fn add_verifying(&mut self, tx: Transaction) -> Arc<AtomicBool> {
+        let entry = VerifyingEntry::new(tx);
+        let cancelled = Arc::clone(&entry.cancelled);
+        self.verifying
+            .insert(entry.transaction.proposal_short_id(), entry);
+        cancelled
+    }
+
+    /// Whether `max_verifying_size` transactions are queued for script verification already
+    pub fn is_verifying_full(&self) -> bool {
+        self.verifying.len() >= self.config.max_verifying_size
+    }
+
+    pub(crate) fn remove_verifying(&mut self, id: &ProposalShortId) -> Option<VerifyingEntry> {
+        self.verifying.remove(id)
+    }
+
+    /// Drop a queued transaction, its verification job is skipped if not started yet
+    pub(crate) fn cancel_verifying(&mut self, id: &ProposalShortId) {
+        if let Some(entry) = self.verifying.remove(id) {
+            trace!(target: "tx_pool", "cancel verifying {:#x}", entry.transaction.hash());
+            entry.cancel();
+        }
+    }
+
     pub fn contains_proposal_id(&self, id: &ProposalShortId) -> bool {
-        self.pending.contains_key(id)
+        self.verifying.contains_key(id)
+            || self.pending.contains_key(id)
             || self.conflict.contains_key(id)
             || self.staging.contains_key(id)
             || self.orphan.contains_key(id)
+            || self.delayed.contains_key(id)
     }
 
     pub fn get_tx(&self, id: &ProposalShortId) -> Option<Transaction> {
@@ -129,10 +558,16 @@ impl TxPool {
             .get_tx(id)
             .or_else(|| self.staging.get_tx(id))
             .or_else(|| self.orphan.get_tx(id))
+            .or_else(|| self.delayed.get_tx(id))
             .or_else(|| self.conflict.get(id).map(|e| &e.transaction))
             .cloned()
     }
 
+    /// The entry of a transaction waiting to be proposed
+    pub fn get_pending_entry(&self, id: &ProposalShortId) -> Option<&PoolEntry> {
+        self.pending.get(id)
+    }
+
     pub fn get_tx_from_staging(&self, id: &ProposalShortId) -> Option<Transaction> {
         self.staging.get_tx(id).cloned()
     }
@@ -149,8 +584,8 @@ impl TxPool {
         for tx in txs {
             let hash = tx.hash();
             trace!(target: "tx_pool", "committed {:#x}", hash);
-            if self.config.trace_enable() {
-                self.trace.committed(&hash, "tx committed".to_string());
+            if self.staging.contains_key(&tx.proposal_short_id()) {
+                self.notify(TxPoolEvent::Committed(hash));
             }
             self.staging.remove_committed_tx(tx);
         }
@@ -163,10 +598,126 @@ impl TxPool {
                     for entry in entries {
                         self.trace
                             .expired(&entry.transaction.hash(), "tx proposal expired".to_string());
-                        self.enqueue_tx(entry.cycles, entry.transaction);
+                        self.enqueue_tx(entry.cycles, entry.fee, entry.transaction);
                     }
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_core::transaction::{CellInput, TransactionBuilder};
+
+    fn build_tx(inputs: Vec<OutPoint>, version: u32) -> Transaction {
+        TransactionBuilder::default()
+            .version(version)
+            .inputs(
+                inputs
+                    .into_iter()
+                    .map(|out_point| CellInput::new(out_point, 0, Default::default()))
+                    .collect(),
+            )
+            .build()
+    }
+
+    fn rbf_pool() -> TxPool {
+        TxPool::new(TxPoolConfig {
+            enable_rbf: true,
+            rbf_fee_rate_increment: 1000,
+            ..Default::default()
+        })
+    }
+
+    // The lowest fee paying at least `fee_rate`
+    fn fee_at_rate(tx: &Transaction, fee_rate: u64) -> Capacity {
+        let size = tx.serialized_size() as u64;
+        Capacity::shannons((fee_rate * size + 999) / 1000)
+    }
+
+    fn out_point(index: u32) -> OutPoint {
+        OutPoint::new(H256::zero(), index)
+    }
+
+    #[test]
+    fn test_replace_pending() {
+        let mut pool = rbf_pool();
+        let replaced = build_tx(vec![out_point(0)], 0);
+        let child = build_tx(vec![OutPoint::new(replaced.hash(), 0)], 0);
+        pool.enqueue_tx(None, fee_at_rate(&replaced, 2000), replaced.clone());
+        pool.enqueue_tx(None, fee_at_rate(&child, 2000), child.clone());
+
+        // the fee rate must exceed the replaced one by the increment
+        let replacement = build_tx(vec![out_point(0)], 1);
+        assert!(pool
+            .replace_pending(&replacement, fee_at_rate(&replacement, 2500))
+            .is_err());
+        assert_eq!(pool.pending.size(), 2);
+
+        // the replacement can't spend what it replaces
+        let spending_replaced = build_tx(vec![out_point(0), OutPoint::new(replaced.hash(), 1)], 1);
+        assert_eq!(
+            pool.replace_pending(&spending_replaced, fee_at_rate(&spending_replaced, 10000))
+                .unwrap_err(),
+            PoolError::ReplacementSpendsReplaced
+        );
+
+        let entries = pool
+            .replace_pending(&replacement, fee_at_rate(&replacement, 10000))
+            .expect("replace");
+        let ids: FnvHashSet<ProposalShortId> = entries
+            .iter()
+            .map(|entry| entry.transaction.proposal_short_id())
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&replaced.proposal_short_id()));
+        assert!(ids.contains(&child.proposal_short_id()));
+        assert_eq!(pool.pending.size(), 0);
+    }
+
+    #[test]
+    fn test_replace_pending_disabled() {
+        let mut pool = rbf_pool();
+        pool.config.enable_rbf = false;
+        pool.enqueue_tx(None, Capacity::zero(), build_tx(vec![out_point(0)], 0));
+
+        let replacement = build_tx(vec![out_point(0)], 1);
+        let entries = pool
+            .replace_pending(&replacement, fee_at_rate(&replacement, 10000))
+            .expect("replace");
+        assert!(entries.is_empty());
+        assert_eq!(pool.pending.size(), 1);
+    }
+
+    #[test]
+    fn test_keep_replaced_if_replacement_evicted() {
+        let mut pool = rbf_pool();
+        let replaced = build_tx(vec![out_point(0)], 0);
+        let other = build_tx(vec![out_point(1)], 0);
+        pool.config.max_pending_bytes = replaced.serialized_size() + other.serialized_size();
+        pool.enqueue_tx(None, fee_at_rate(&replaced, 1000), replaced.clone());
+        pool.enqueue_tx(None, fee_at_rate(&other, 5000), other.clone());
+
+        // larger than the replaced transaction, the pool is over its bytes limit after the
+        // replacement and the replacement has the lowest fee rate
+        let replacement = build_tx(vec![out_point(0), out_point(2)], 1);
+        assert_eq!(
+            pool.replace_pending(&replacement, fee_at_rate(&replacement, 3000))
+                .unwrap_err(),
+            PoolError::OverCapacity
+        );
+        assert!(pool.pending.contains_key(&replaced.proposal_short_id()));
+
+        // the other transaction is evicted instead
+        let entries = pool
+            .replace_pending(&replacement, fee_at_rate(&replacement, 6000))
+            .expect("replace");
+        assert_eq!(entries.len(), 1);
+        assert!(!pool.pending.contains_key(&replaced.proposal_short_id()));
+        pool.enqueue_tx(None, fee_at_rate(&replacement, 6000), replacement.clone());
+        assert!(pool.pending.contains_key(&replacement.proposal_short_id()));
+        assert!(!pool.pending.contains_key(&other.proposal_short_id()));
+    }
+}
@@ -3,9 +3,10 @@
 use crate::tx_pool::types::PoolEntry;
 use ckb_core::cell::{CellProvider, CellStatus};
 use ckb_core::transaction::{CellOutput, OutPoint, ProposalShortId, Transaction};
-use ckb_core::Cycle;
+use ckb_core::{Capacity, Cycle};
 use fnv::{FnvHashMap, FnvHashSet};
 use linked_hash_map::LinkedHashMap;
+use std::cmp::{max, Reverse};
 use std::hash::Hash;
 
 #[derive(Default, Debug, Clone)]
@@ -95,6 +96,8 @@ impl<K: Hash + Eq, V: Copy + Eq + Hash> Edges<K, V> {
 pub struct StagingPool {
     pub(crate) vertices: LinkedHashMap<ProposalShortId, PoolEntry>,
     pub(crate) edges: Edges<OutPoint, ProposalShortId>,
+    /// Total serialized size of the staged transactions
+    pub(crate) total_size: usize,
 }
 
 impl CellProvider for StagingPool {
@@ -122,6 +125,10 @@ impl StagingPool {
         self.vertices.len()
     }
 
+    pub fn total_size(&self) -> usize {
+        self.total_size
+    }
+
     pub fn contains_key(&self, id: &ProposalShortId) -> bool {
         self.vertices.contains_key(id)
     }
@@ -134,6 +141,15 @@ impl StagingPool {
         self.get(id).map(|x| &x.transaction)
     }
 
+    /// Get the staging transaction which spends the out point
+    pub fn spent_by(&self, o: &OutPoint) -> Option<&Transaction> {
+        self.edges
+            .get_inner(o)
+            .and_then(Option::as_ref)
+            .or_else(|| self.edges.get_outer(o).and_then(Option::as_ref))
+            .and_then(|id| self.get_tx(id))
+    }
+
     pub fn get_output(&self, o: &OutPoint) -> Option<CellOutput> {
         self.vertices
             .get(&ProposalShortId::from_tx_hash(&o.tx_hash))
@@ -142,6 +158,8 @@ impl StagingPool {
 
     pub fn remove_vertex(&mut self, id: &ProposalShortId, rtxs: &mut Vec<PoolEntry>) {
         if let Some(x) = self.vertices.remove(id) {
+            self.total_size -= x.size;
+            self.unlink_package(id, &x);
             let tx = &x.transaction;
             let inputs = tx.input_pts();
             let outputs = tx.output_pts();
@@ -185,7 +203,32 @@ impl StagingPool {
         }
     }
 
-    pub fn add_tx(&mut self, cycles: Cycle, tx: Transaction) {
+    /// Remove the transaction with the lowest fee rate along with its descendants, a
+    /// transaction whose descendants pay for it is scored by the descendants fee rate
+    pub fn evict(&mut self) -> Option<Vec<PoolEntry>> {
+        let id = self
+            .vertices
+            .iter()
+            .min_by_key(|(_, entry)| max(entry.fee_rate(), entry.descendants_fee_rate()))
+            .map(|(id, _)| *id)?;
+        self.remove(&id)
+    }
+
+    /// Drop the links between the removed entry and its staged ancestors and descendants
+    fn unlink_package(&mut self, id: &ProposalShortId, entry: &PoolEntry) {
+        for ancestor in &entry.ancestors {
+            if let Some(x) = self.vertices.get_mut(ancestor) {
+                x.remove_descendant(id, entry);
+            }
+        }
+        for descendant in &entry.descendants {
+            if let Some(x) = self.vertices.get_mut(descendant) {
+                x.remove_ancestor(id, entry);
+            }
+        }
+    }
+
+    pub fn add_tx(&mut self, cycles: Cycle, fee: Capacity, tx: Transaction) {
         let inputs = tx.input_pts();
         let outputs = tx.output_pts();
         let deps = tx.dep_pts();
@@ -193,6 +236,7 @@ impl StagingPool {
         let id = tx.proposal_short_id();
 
         let mut count: usize = 0;
+        let mut parents = FnvHashSet::default();
 
         for i in inputs {
             let mut flag = true;
@@ -200,6 +244,7 @@ impl StagingPool {
                 *x = Some(id);
                 count += 1;
                 flag = false;
+                parents.insert(ProposalShortId::from_tx_hash(&i.tx_hash));
             }
 
             if flag {
@@ -210,6 +255,7 @@ impl StagingPool {
         for d in deps {
             if self.edges.contains_key(&d) {
                 count += 1;
+                parents.insert(ProposalShortId::from_tx_hash(&d.tx_hash));
             }
             self.edges.insert_deps(d, id);
         }
@@ -218,8 +264,27 @@ impl StagingPool {
             self.edges.mark_inpool(o);
         }
 
-        self.vertices
-            .insert(id, PoolEntry::new(tx, count, Some(cycles)));
+        let mut entry = PoolEntry::new(tx, count, Some(cycles), fee);
+        let mut ancestors = FnvHashSet::default();
+        for parent in &parents {
+            if let Some(x) = self.vertices.get(parent) {
+                ancestors.insert(*parent);
+                ancestors.extend(x.ancestors.iter().cloned());
+            }
+        }
+        for ancestor in &ancestors {
+            if let Some(x) = self.vertices.get(ancestor) {
+                entry.add_ancestor(*ancestor, x);
+            }
+        }
+        for ancestor in &ancestors {
+            if let Some(x) = self.vertices.get_mut(ancestor) {
+                x.add_descendant(id, &entry);
+            }
+        }
+
+        self.total_size += entry.size;
+        self.vertices.insert(id, entry);
     }
 
     pub fn remove_committed_tx(&mut self, tx: &Transaction) {
@@ -228,7 +293,9 @@ impl StagingPool {
         let deps = tx.dep_pts();
         let id = tx.proposal_short_id();
 
-        if self.vertices.remove(&id).is_some() {
+        if let Some(entry) = self.vertices.remove(&id) {
+            self.total_size -= entry.size;
+            self.unlink_package(&id, &entry);
             for o in outputs {
                 if let Some(cid) = self.edges.remove_inner(&o) {
                     self.dec_ref(&cid);
@@ -270,13 +337,49 @@ impl StagingPool {
         }
     }
 
-    /// Get n transactions in topology
+    /// Get n transactions in topology. Packages of a transaction and its staged ancestors
+    /// are taken by ancestor fee rate, higher first, so a parent paying a low fee is mined
+    /// together with a child paying for it.
     pub fn get_txs(&self, n: usize) -> Vec<PoolEntry> {
-        self.vertices
-            .front_n(n)
+        let entries: Vec<(&ProposalShortId, &PoolEntry)> = self.vertices.iter().collect();
+        let indexes: FnvHashMap<&ProposalShortId, usize> = entries
             .iter()
-            .map(|x| x.1.clone())
-            .collect()
+            .enumerate()
+            .map(|(index, (id, _))| (*id, index))
+            .collect();
+        let mut candidates: Vec<usize> = (0..entries.len()).collect();
+        // ties are broken by the staging order
+        candidates.sort_by_key(|index| (Reverse(entries[*index].1.ancestors_fee_rate()), *index));
+
+        let mut selected = FnvHashSet::default();
+        let mut txs = Vec::with_capacity(n.min(entries.len()));
+        for index in candidates {
+            if txs.len() >= n {
+                break;
+            }
+            let (id, entry) = entries[index];
+            if selected.contains(id) {
+                continue;
+            }
+            // ancestors are always staged before their descendants
+            let mut package: Vec<usize> = entry
+                .ancestors
+                .iter()
+                .filter(|ancestor| !selected.contains(*ancestor))
+                .filter_map(|ancestor| indexes.get(ancestor).cloned())
+                .collect();
+            package.sort();
+            package.push(index);
+            if txs.len() + package.len() > n {
+                continue;
+            }
+            for index in package {
+                let (id, entry) = entries[index];
+                selected.insert(*id);
+                txs.push(entry.clone());
+            }
+        }
+        txs
     }
 
     pub fn txs_iter(&self) -> impl Iterator<Item = &PoolEntry> {
@@ -330,6 +433,7 @@ mod tests {
     }
 
     pub const MOCK_CYCLES: Cycle = 0;
+    pub const MOCK_FEE: Capacity = Capacity::zero();
 
     #[test]
     fn test_add_entry() {
@@ -341,8 +445,8 @@ mod tests {
         let id1 = tx1.proposal_short_id();
         let id2 = tx2.proposal_short_id();
 
-        pool.add_tx(MOCK_CYCLES, tx1.clone());
-        pool.add_tx(MOCK_CYCLES, tx2.clone());
+        pool.add_tx(MOCK_CYCLES, MOCK_FEE, tx1.clone());
+        pool.add_tx(MOCK_CYCLES, MOCK_FEE, tx2.clone());
 
         assert_eq!(pool.vertices.len(), 2);
         assert_eq!(pool.edges.inner_len(), 2);
@@ -358,6 +462,117 @@ mod tests {
         assert_eq!(pool.get(&id2).unwrap().refs_count, 0);
     }
 
+    #[test]
+    fn test_spent_by() {
+        let tx1 = build_tx(vec![(H256::zero(), 1)], 1);
+        let tx1_hash = tx1.hash().clone();
+        let tx2 = build_tx(vec![(tx1_hash.clone(), 0)], 1);
+
+        let mut pool = StagingPool::new();
+        pool.add_tx(MOCK_CYCLES, MOCK_FEE, tx1.clone());
+        pool.add_tx(MOCK_CYCLES, MOCK_FEE, tx2.clone());
+
+        assert_eq!(pool.spent_by(&OutPoint::new(H256::zero(), 1)), Some(&tx1));
+        assert_eq!(pool.spent_by(&OutPoint::new(tx1_hash, 0)), Some(&tx2));
+        assert_eq!(pool.spent_by(&OutPoint::new(tx2.hash(), 0)), None);
+    }
+
+    #[test]
+    fn test_get_txs_by_ancestors_fee_rate() {
+        let tx1 = build_tx(vec![(H256::zero(), 1)], 1);
+        let tx2 = build_tx(vec![(H256::zero(), 2)], 1);
+        let tx3 = build_tx(vec![(tx1.hash().clone(), 0)], 1);
+
+        let mut pool = StagingPool::new();
+        pool.add_tx(MOCK_CYCLES, Capacity::shannons(100), tx1.clone());
+        pool.add_tx(MOCK_CYCLES, Capacity::shannons(200), tx2.clone());
+        pool.add_tx(MOCK_CYCLES, Capacity::shannons(10000), tx3.clone());
+
+        // tx3 pays for its parent tx1
+        let txs: Vec<_> = pool.get_txs(3).into_iter().map(|e| e.transaction).collect();
+        assert_eq!(txs, vec![tx1.clone(), tx3.clone(), tx2.clone()]);
+
+        // the package of tx3 doesn't fit
+        let txs: Vec<_> = pool.get_txs(1).into_iter().map(|e| e.transaction).collect();
+        assert_eq!(txs, vec![tx2]);
+    }
+
+    #[test]
+    fn test_package() {
+        let tx1 = build_tx(vec![(H256::zero(), 1)], 1);
+        let tx2 = build_tx(vec![(tx1.hash().clone(), 0)], 1);
+        let tx3 = build_tx(vec![(tx2.hash().clone(), 0)], 1);
+        let id1 = tx1.proposal_short_id();
+        let id2 = tx2.proposal_short_id();
+        let id3 = tx3.proposal_short_id();
+
+        let mut pool = StagingPool::new();
+        pool.add_tx(1, Capacity::shannons(100), tx1.clone());
+        pool.add_tx(2, Capacity::shannons(200), tx2.clone());
+        pool.add_tx(4, Capacity::shannons(400), tx3.clone());
+
+        let entry1 = pool.get(&id1).unwrap();
+        assert!(entry1.ancestors.is_empty());
+        assert_eq!(entry1.descendants.len(), 2);
+        assert_eq!(entry1.descendants_fee, Capacity::shannons(700));
+        assert_eq!(entry1.descendants_cycles, 7);
+
+        let entry3 = pool.get(&id3).unwrap();
+        assert_eq!(entry3.ancestors.len(), 2);
+        assert_eq!(entry3.ancestors_fee, Capacity::shannons(700));
+        assert_eq!(entry3.ancestors_cycles, 7);
+        assert_eq!(
+            entry3.ancestors_size,
+            tx1.serialized_size() + tx2.serialized_size() + tx3.serialized_size()
+        );
+
+        pool.remove_committed_tx(&tx1);
+        let entry2 = pool.get(&id2).unwrap();
+        assert!(entry2.ancestors.is_empty());
+        assert_eq!(entry2.ancestors_fee, Capacity::shannons(200));
+        assert_eq!(entry2.descendants_fee, Capacity::shannons(600));
+
+        pool.remove(&id3);
+        let entry2 = pool.get(&id2).unwrap();
+        assert!(entry2.descendants.is_empty());
+        assert_eq!(entry2.descendants_fee, Capacity::shannons(200));
+        assert_eq!(entry2.descendants_cycles, 2);
+    }
+
+    #[test]
+    fn test_evict() {
+        let tx1 = build_tx(vec![(H256::zero(), 1)], 1);
+        let tx2 = build_tx(vec![(H256::zero(), 2)], 1);
+        let tx3 = build_tx(vec![(tx1.hash().clone(), 0)], 1);
+
+        let mut pool = StagingPool::new();
+        pool.add_tx(MOCK_CYCLES, Capacity::shannons(100), tx1.clone());
+        pool.add_tx(MOCK_CYCLES, Capacity::shannons(200), tx2.clone());
+        pool.add_tx(MOCK_CYCLES, Capacity::shannons(10000), tx3.clone());
+        let total_size = pool.total_size();
+
+        // tx1 is kept for the fee paid by tx3
+        let evicted: Vec<_> = pool
+            .evict()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.transaction)
+            .collect();
+        assert_eq!(evicted, vec![tx2.clone()]);
+        assert_eq!(pool.capacity(), 2);
+        assert_eq!(pool.total_size(), total_size - tx2.serialized_size());
+
+        let evicted: Vec<_> = pool
+            .evict()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.transaction)
+            .collect();
+        assert_eq!(evicted, vec![tx1, tx3]);
+        assert_eq!(pool.capacity(), 0);
+        assert_eq!(pool.total_size(), 0);
+    }
+
     #[test]
     fn test_add_roots() {
         let tx1 = build_tx(vec![(H256::zero(), 1), (H256::zero(), 2)], 1);
@@ -374,8 +589,8 @@ mod tests {
         let id1 = tx1.proposal_short_id();
         let id2 = tx2.proposal_short_id();
 
-        pool.add_tx(MOCK_CYCLES, tx1.clone());
-        pool.add_tx(MOCK_CYCLES, tx2.clone());
+        pool.add_tx(MOCK_CYCLES, MOCK_FEE, tx1.clone());
+        pool.add_tx(MOCK_CYCLES, MOCK_FEE, tx2.clone());
 
         assert_eq!(pool.get(&id1).unwrap().refs_count, 0);
         assert_eq!(pool.get(&id2).unwrap().refs_count, 0);
@@ -423,11 +638,11 @@ mod tests {
 
         let mut pool = StagingPool::new();
 
-        pool.add_tx(MOCK_CYCLES, tx1.clone());
-        pool.add_tx(MOCK_CYCLES, tx2.clone());
-        pool.add_tx(MOCK_CYCLES, tx3.clone());
-        pool.add_tx(MOCK_CYCLES, tx4.clone());
-        pool.add_tx(MOCK_CYCLES, tx5.clone());
+        pool.add_tx(MOCK_CYCLES, MOCK_FEE, tx1.clone());
+        pool.add_tx(MOCK_CYCLES, MOCK_FEE, tx2.clone());
+        pool.add_tx(MOCK_CYCLES, MOCK_FEE, tx3.clone());
+        pool.add_tx(MOCK_CYCLES, MOCK_FEE, tx4.clone());
+        pool.add_tx(MOCK_CYCLES, MOCK_FEE, tx5.clone());
 
         assert_eq!(pool.get(&id1).unwrap().refs_count, 0);
         assert_eq!(pool.get(&id3).unwrap().refs_count, 1);
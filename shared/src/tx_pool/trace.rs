@@ -29,12 +29,13 @@ impl TxTraceMap {
         }
     }
 
-    pub fn add_pending<S: ToString>(&mut self, hash: &H256, info: S) {
+    /// Start tracing the transaction, the events of the others are not recorded
+    pub fn received<S: ToString>(&mut self, hash: &H256, info: S) {
         self.inner
             .entry(hash.clone())
             .or_insert_with(Vec::new)
             .push(TxTrace::new(
-                Action::AddPending,
+                Action::Received,
                 info.to_string(),
                 unix_time_as_millis(),
             ));
@@ -44,11 +45,16 @@ impl TxTraceMap {
         self.inner.get(hash)
     }
 
+    define_method!(verified, Action::Verified);
+    define_method!(add_pending, Action::AddPending);
     define_method!(proposed, Action::Proposed);
     define_method!(staged, Action::Staged);
     define_method!(add_orphan, Action::AddOrphan);
+    define_method!(delayed, Action::Delayed);
     define_method!(expired, Action::Expired);
     define_method!(committed, Action::Committed);
+    define_method!(evicted, Action::Evicted);
+    define_method!(rejected, Action::Rejected);
 }
 
 #[cfg(test)]
@@ -65,12 +71,16 @@ mod tests {
         let faketime_file = faketime::millis_tempfile(9102).expect("create faketime file");
         faketime::enable(&faketime_file);
 
+        map.received(&tx_hash, "received");
+        map.verified(&tx_hash, "verified");
         map.add_pending(&tx_hash, "pending");
         map.proposed(&tx_hash, "proposed");
         map.staged(&tx_hash, "staged");
         map.add_orphan(&tx_hash, "add_orphan");
         map.expired(&tx_hash, "expired");
         map.committed(&tx_hash, "committed");
+        map.evicted(&tx_hash, "evicted");
+        map.rejected(&tx_hash, "rejected");
 
         let traces = map.get(&tx_hash);
 
@@ -78,12 +88,16 @@ mod tests {
             format!("{:?}", traces),
             concat!(
                 "Some([",
+                "{ action: Received, info: received, time: 9102 }, ",
+                "{ action: Verified, info: verified, time: 9102 }, ",
                 "{ action: AddPending, info: pending, time: 9102 }, ",
                 "{ action: Proposed, info: proposed, time: 9102 }, ",
                 "{ action: Staged, info: staged, time: 9102 }, ",
                 "{ action: AddOrphan, info: add_orphan, time: 9102 }, ",
                 "{ action: Expired, info: expired, time: 9102 }, ",
-                "{ action: Committed, info: committed, time: 9102 }",
+                "{ action: Committed, info: committed, time: 9102 }, ",
+                "{ action: Evicted, info: evicted, time: 9102 }, ",
+                "{ action: Rejected, info: rejected, time: 9102 }",
                 "])"
             ),
         );
@@ -1,14 +1,20 @@
 //! The primary module containing the implementations of the transaction pool
 //! and its top-level members.
 
+use ckb_core::cell::UnresolvableError;
 use ckb_core::transaction::OutPoint;
-use ckb_core::transaction::Transaction;
-use ckb_core::Cycle;
+use ckb_core::transaction::{ProposalShortId, Transaction};
+use ckb_core::{Capacity, Cycle};
 use ckb_verification::TransactionError;
 use failure::Fail;
+use faketime::unix_time_as_millis;
+use fnv::FnvHashSet;
+use numext_fixed_hash::H256;
 use serde_derive::{Deserialize, Serialize};
 use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 /// Transaction pool configuration
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -20,6 +26,102 @@ pub struct TxPoolConfig {
     pub max_cache_size: usize,
     pub max_pending_size: usize,
     pub trace: Option<usize>,
+    /// Maximum total serialized size of the staging pool in bytes
+    #[serde(default = "default_max_pool_bytes")]
+    pub max_pool_bytes: usize,
+    /// Maximum total serialized size of the orphan pool in bytes, random orphans are
+    /// evicted when it is exceeded
+    #[serde(default = "default_max_orphan_bytes")]
+    pub max_orphan_bytes: usize,
+    /// Orphans whose parents don't arrive within this many milliseconds are dropped
+    #[serde(default = "default_max_orphan_age")]
+    pub max_orphan_age: u64,
+    /// Maximum total serialized size of the pending queue in bytes
+    #[serde(default = "default_max_pending_bytes")]
+    pub max_pending_bytes: usize,
+    /// Allow a transaction to replace the pending transactions it double spends by paying
+    /// a higher fee rate
+    #[serde(default)]
+    pub enable_rbf: bool,
+    /// Fee rate in shannons per 1000 bytes a replacement must pay above the replaced
+    /// transactions
+    #[serde(default = "default_rbf_fee_rate_increment")]
+    pub rbf_fee_rate_increment: u64,
+    /// Number of transaction cycles cached to skip running the same scripts again
+    #[serde(default = "default_txs_verify_cache_size")]
+    pub txs_verify_cache_size: usize,
+    /// Number of recently rejected transactions remembered with the rejection reason
+    #[serde(default = "default_max_rejected_size")]
+    pub max_rejected_size: usize,
+    /// Maximum number of transactions waiting for immature cellbases or since conditions,
+    /// the ones with the longest to wait are evicted beyond it
+    #[serde(default = "default_max_delayed_size")]
+    pub max_delayed_size: usize,
+    /// Maximum number of transactions whose scripts are queued or running, the others are
+    /// refused until the verifications catch up
+    #[serde(default = "default_max_verifying_size")]
+    pub max_verifying_size: usize,
+    /// Transactions violating the policy are rejected before their scripts are run
+    #[serde(default)]
+    pub policy: TxPoolPolicy,
+}
+
+/// Limits of the transactions accepted into the pool, on top of the consensus rules
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TxPoolPolicy {
+    /// Maximum cycles the scripts of a transaction may take
+    pub max_tx_cycles: Cycle,
+    /// Maximum serialized size of a transaction in bytes
+    pub max_tx_size: usize,
+    /// Minimum fee rate in shannons per 1000 bytes
+    pub min_fee_rate: u64,
+}
+
+impl Default for TxPoolPolicy {
+    fn default() -> Self {
+        TxPoolPolicy {
+            max_tx_cycles: 100_000_000,
+            max_tx_size: 512 * 1024,
+            min_fee_rate: 0,
+        }
+    }
+}
+
+fn default_max_pool_bytes() -> usize {
+    20 * 1024 * 1024
+}
+
+fn default_max_orphan_bytes() -> usize {
+    5 * 1024 * 1024
+}
+
+fn default_max_orphan_age() -> u64 {
+    20 * 60 * 1000
+}
+
+fn default_max_pending_bytes() -> usize {
+    20 * 1024 * 1024
+}
+
+fn default_rbf_fee_rate_increment() -> u64 {
+    1000
+}
+
+fn default_txs_verify_cache_size() -> usize {
+    100_000
+}
+
+fn default_max_rejected_size() -> usize {
+    10_000
+}
+
+fn default_max_delayed_size() -> usize {
+    10_000
+}
+
+fn default_max_verifying_size() -> usize {
+    1000
 }
 
 impl Default for TxPoolConfig {
@@ -31,6 +133,17 @@ impl Default for TxPoolConfig {
             max_cache_size: 1000,
             max_pending_size: 10000,
             trace: Some(100),
+            max_pool_bytes: default_max_pool_bytes(),
+            max_orphan_bytes: default_max_orphan_bytes(),
+            max_orphan_age: default_max_orphan_age(),
+            max_pending_bytes: default_max_pending_bytes(),
+            enable_rbf: false,
+            rbf_fee_rate_increment: default_rbf_fee_rate_increment(),
+            txs_verify_cache_size: default_txs_verify_cache_size(),
+            max_rejected_size: default_max_rejected_size(),
+            max_delayed_size: default_max_delayed_size(),
+            max_verifying_size: default_max_verifying_size(),
+            policy: TxPoolPolicy::default(),
         }
     }
 }
@@ -47,8 +160,9 @@ impl TxPoolConfig {
 pub enum PoolError {
     /// An invalid pool entry caused by underlying tx validation error
     InvalidTx(TransactionError),
-    /// CellStatus Conflict
-    Conflict,
+    /// An input or dep is spent or unknown, a transaction with unknown cells is kept as an
+    /// orphan
+    Unresolvable(UnresolvableError),
     /// Transaction pool is over capacity, can't accept more transactions
     OverCapacity,
     /// tx_pool don't accept cellbase-like tx
@@ -59,8 +173,21 @@ pub enum PoolError {
     InvalidBlockNumber,
     /// Duplicate tx
     Duplicate,
-    /// Tx contains unknown inputs
-    UnknownInputs(Vec<OutPoint>),
+    /// Tx double spends pending txs but doesn't pay enough to replace them, carries the
+    /// minimum fee rate required
+    ReplacementFeeTooLow(u64),
+    /// Tx spends or depends on the outputs of a pending tx it would replace
+    ReplacementSpendsReplaced,
+    /// Script verification is dropped since a reorg spent or removed the tx inputs
+    Cancelled,
+    /// Tx is larger than `max_tx_size` of the pool policy, carries the limit
+    ExceededMaxTxSize(usize),
+    /// Tx scripts take more than `max_tx_cycles` of the pool policy, carries the limit
+    ExceededMaxTxCycles(Cycle),
+    /// Tx pays less than `min_fee_rate` of the pool policy, carries the minimum fee rate
+    LowFeeRate(u64),
+    /// Too many transactions are being verified, the tx can be submitted again later
+    Full,
 }
 
 impl PoolError {
@@ -73,6 +200,50 @@ impl PoolError {
             _ => false,
         }
     }
+
+    /// Whether the transaction is turned down, as opposed to being already known, waiting for
+    /// its inputs or to mature, or dropped by a reorg
+    pub fn is_rejection(&self) -> bool {
+        match self {
+            PoolError::Duplicate
+            | PoolError::Unresolvable(UnresolvableError::Unknown { .. })
+            | PoolError::Cancelled
+            | PoolError::Full => false,
+            _ => !self.is_immature(),
+        }
+    }
+
+    /// Whether the transaction is kept in the delayed pool until it matures
+    pub fn is_immature(&self) -> bool {
+        match self {
+            PoolError::InvalidTx(err) => err.is_immature(),
+            _ => false,
+        }
+    }
+
+    /// Whether the error is caused by inputs or deps which can't be resolved as live cells
+    pub fn is_unresolvable(&self) -> bool {
+        match self {
+            PoolError::Unresolvable(_) | PoolError::NullInput => true,
+            _ => false,
+        }
+    }
+}
+
+/// Status of a transaction input or dep resolved against the chain and the pool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CellResolution {
+    Live,
+    /// The cell is spent, carries the spending transaction hash if it is in the pool
+    Dead(Option<H256>),
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CellResolutionDetail {
+    pub out_point: OutPoint,
+    pub is_dep: bool,
+    pub resolution: CellResolution,
 }
 
 impl fmt::Display for PoolError {
@@ -81,6 +252,79 @@ impl fmt::Display for PoolError {
     }
 }
 
+/// A transaction whose scripts are queued for verification, it is not in the pool yet
+#[derive(Debug, Clone)]
+pub struct VerifyingEntry {
+    pub transaction: Transaction,
+    /// Shared with the verification job, which skips or drops its result once set
+    pub cancelled: Arc<AtomicBool>,
+}
+
+impl VerifyingEntry {
+    pub fn new(transaction: Transaction) -> Self {
+        VerifyingEntry {
+            transaction,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Changes of the pool broadcast to the subscribers of `TxPool::subscribe_events`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TxPoolEvent {
+    /// Accepted into the pending queue or the staging pool
+    Added(H256),
+    /// Committed in a new tip block and removed from the staging pool
+    Committed(H256),
+    /// Dropped from the pool before being committed
+    Evicted {
+        tx_hash: H256,
+        reason: String,
+    },
+    Rejected {
+        tx_hash: H256,
+        reason: PoolError,
+    },
+}
+
+/// Why and when a transaction was rejected
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedTx {
+    pub reason: PoolError,
+    pub timestamp: u64,
+}
+
+/// Where a transaction is on its way from the pool to the main chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxStatus {
+    /// In the pool, not proposed yet or waiting for its inputs or to mature
+    Pending,
+    /// In the staging pool, ready to be committed
+    Proposed,
+    /// Committed in the main chain block
+    Committed { block_hash: H256 },
+    /// Committed in the block which is detached from the main chain, the transaction is back
+    /// in the pool
+    Reorged { block_hash: H256 },
+}
+
+/// A pending or staging transaction saved across restarts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PersistedTx {
+    pub transaction: Transaction,
+    pub cycles: Option<Cycle>,
+    pub timestamp: u64,
+}
+
+/// Fee rate in shannons per 1000 bytes
+pub(crate) fn fee_rate(fee: Capacity, size: usize) -> u64 {
+    fee.as_u64().saturating_mul(1000) / size as u64
+}
+
 /// An entry in the transaction pool.
 #[derive(Debug, Clone)]
 pub struct PoolEntry {
@@ -90,17 +334,105 @@ pub struct PoolEntry {
     pub refs_count: usize,
     /// Cycles
     pub cycles: Option<Cycle>,
+    /// Fee paid by the transaction, zero if the inputs have not been resolved
+    pub fee: Capacity,
+    /// Serialized size of the transaction in bytes
+    pub size: usize,
+    /// Time the entry was created, in milliseconds
+    pub timestamp: u64,
+    /// In pool transactions whose outputs this transaction spends or depends on, directly
+    /// or indirectly
+    pub ancestors: FnvHashSet<ProposalShortId>,
+    /// In pool transactions spending or depending on the outputs of this transaction,
+    /// directly or indirectly
+    pub descendants: FnvHashSet<ProposalShortId>,
+    /// Fee, size and cycles of the transaction and all its ancestors
+    pub ancestors_fee: Capacity,
+    pub ancestors_size: usize,
+    pub ancestors_cycles: Cycle,
+    /// Fee, size and cycles of the transaction and all its descendants
+    pub descendants_fee: Capacity,
+    pub descendants_size: usize,
+    pub descendants_cycles: Cycle,
 }
 
 impl PoolEntry {
     /// Create new transaction pool entry
-    pub fn new(tx: Transaction, count: usize, cycles: Option<Cycle>) -> PoolEntry {
+    pub fn new(tx: Transaction, count: usize, cycles: Option<Cycle>, fee: Capacity) -> PoolEntry {
+        let size = tx.serialized_size();
+        let own_cycles = cycles.unwrap_or(0);
         PoolEntry {
             transaction: tx,
             refs_count: count,
             cycles,
+            fee,
+            size,
+            timestamp: unix_time_as_millis(),
+            ancestors: FnvHashSet::default(),
+            descendants: FnvHashSet::default(),
+            ancestors_fee: fee,
+            ancestors_size: size,
+            ancestors_cycles: own_cycles,
+            descendants_fee: fee,
+            descendants_size: size,
+            descendants_cycles: own_cycles,
         }
     }
+
+    /// Fee rate in shannons per 1000 bytes
+    pub fn fee_rate(&self) -> u64 {
+        fee_rate(self.fee, self.size)
+    }
+
+    /// Fee rate of the package made of the transaction and its ancestors
+    pub fn ancestors_fee_rate(&self) -> u64 {
+        fee_rate(self.ancestors_fee, self.ancestors_size)
+    }
+
+    /// Fee rate of the package made of the transaction and its descendants
+    pub fn descendants_fee_rate(&self) -> u64 {
+        fee_rate(self.descendants_fee, self.descendants_size)
+    }
+
+    pub(crate) fn add_ancestor(&mut self, id: ProposalShortId, ancestor: &PoolEntry) {
+        if self.ancestors.insert(id) {
+            self.ancestors_fee = add_fee(self.ancestors_fee, ancestor.fee);
+            self.ancestors_size += ancestor.size;
+            self.ancestors_cycles += ancestor.cycles.unwrap_or(0);
+        }
+    }
+
+    pub(crate) fn remove_ancestor(&mut self, id: &ProposalShortId, ancestor: &PoolEntry) {
+        if self.ancestors.remove(id) {
+            self.ancestors_fee = sub_fee(self.ancestors_fee, ancestor.fee);
+            self.ancestors_size -= ancestor.size;
+            self.ancestors_cycles -= ancestor.cycles.unwrap_or(0);
+        }
+    }
+
+    pub(crate) fn add_descendant(&mut self, id: ProposalShortId, descendant: &PoolEntry) {
+        if self.descendants.insert(id) {
+            self.descendants_fee = add_fee(self.descendants_fee, descendant.fee);
+            self.descendants_size += descendant.size;
+            self.descendants_cycles += descendant.cycles.unwrap_or(0);
+        }
+    }
+
+    pub(crate) fn remove_descendant(&mut self, id: &ProposalShortId, descendant: &PoolEntry) {
+        if self.descendants.remove(id) {
+            self.descendants_fee = sub_fee(self.descendants_fee, descendant.fee);
+            self.descendants_size -= descendant.size;
+            self.descendants_cycles -= descendant.cycles.unwrap_or(0);
+        }
+    }
+}
+
+fn add_fee(a: Capacity, b: Capacity) -> Capacity {
+    Capacity::shannons(a.as_u64().saturating_add(b.as_u64()))
+}
+
+fn sub_fee(a: Capacity, b: Capacity) -> Capacity {
+    Capacity::shannons(a.as_u64().saturating_sub(b.as_u64()))
 }
 
 impl Hash for PoolEntry {
@@ -1,15 +1,23 @@
 use ckb_chain_spec::consensus::ProposalWindow;
 use ckb_core::header::BlockNumber;
 use ckb_core::transaction::ProposalShortId;
-use fnv::FnvHashSet;
+use fnv::{FnvHashMap, FnvHashSet};
 use log::trace;
 use std::collections::BTreeMap;
-use std::ops::Bound;
+use std::mem;
 
+/// Proposals of the blocks in the proposal window. The union of the window is maintained as
+/// blocks are inserted and removed, `finalize` only visits the blocks entering or leaving
+/// the window.
 #[derive(Debug, PartialEq, Clone, Eq)]
 pub struct TxProposalTable {
     pub(crate) table: BTreeMap<BlockNumber, FnvHashSet<ProposalShortId>>,
-    pub(crate) set: FnvHashSet<ProposalShortId>,
+    /// Ids proposed in the window with the number of window blocks proposing them
+    pub(crate) set: FnvHashMap<ProposalShortId, usize>,
+    /// First and last block numbers of the window as of the last `finalize`
+    pub(crate) window: Option<(BlockNumber, BlockNumber)>,
+    /// Ids which left the window since the last `finalize`
+    pub(crate) removed: FnvHashSet<ProposalShortId>,
     pub(crate) proposal_window: ProposalWindow,
 }
 
@@ -17,58 +25,123 @@ impl TxProposalTable {
     pub fn new(proposal_window: ProposalWindow) -> Self {
         TxProposalTable {
             proposal_window,
-            set: FnvHashSet::default(),
+            set: FnvHashMap::default(),
+            window: None,
+            removed: FnvHashSet::default(),
             table: BTreeMap::default(),
         }
     }
 
+    fn in_window(&self, number: BlockNumber) -> bool {
+        self.window
+            .map_or(false, |(start, end)| number >= start && number <= end)
+    }
+
     // If the TABLE did not have this value present, true is returned.
     // If the TABLE did have this value present, false is returned
     pub fn insert(&mut self, number: BlockNumber, ids: FnvHashSet<ProposalShortId>) -> bool {
-        self.table.insert(number, ids).is_none()
+        let in_window = self.in_window(number);
+        if in_window {
+            add_ids(&mut self.set, &mut self.removed, &ids);
+        }
+        match self.table.insert(number, ids) {
+            Some(old) => {
+                if in_window {
+                    remove_ids(&mut self.set, &mut self.removed, &old);
+                }
+                false
+            }
+            None => true,
+        }
     }
 
     pub fn remove(&mut self, number: BlockNumber) -> Option<FnvHashSet<ProposalShortId>> {
-        self.table.remove(&number)
+        let ids = self.table.remove(&number)?;
+        if self.in_window(number) {
+            remove_ids(&mut self.set, &mut self.removed, &ids);
+        }
+        Some(ids)
     }
 
     pub fn contains(&self, id: &ProposalShortId) -> bool {
-        self.set.contains(id)
+        self.set.contains_key(id)
     }
 
     pub fn get_ids_iter(&self) -> impl Iterator<Item = &ProposalShortId> {
-        self.set.iter()
+        self.set.keys()
     }
 
     pub fn all(&self) -> &BTreeMap<BlockNumber, FnvHashSet<ProposalShortId>> {
         &self.table
     }
 
+    /// Move the window to the tip `number`, returns the ids which left the window since the
+    /// last call, including the ids of the removed blocks
     pub fn finalize(&mut self, number: BlockNumber) -> FnvHashSet<ProposalShortId> {
         let proposal_start = number.saturating_sub(self.proposal_window.start()) + 1;
         let proposal_end = number.saturating_sub(self.proposal_window.end()) + 1;
-
-        let mut left = self.table.split_off(&proposal_start);
-        ::std::mem::swap(&mut self.table, &mut left);
-
-        trace!(target: "chain", "[proposal_finalize] table {:?}", self.table);
-        let new_ids = self
+        let old_window = self.window.replace((proposal_start, proposal_end));
+
+        let in_new_window = |n: &BlockNumber| *n >= proposal_start && *n <= proposal_end;
+        let leaving: Vec<BlockNumber> = match old_window {
+            Some((start, end)) => self
+                .table
+                .range(start..=end)
+                .map(|(n, _)| *n)
+                .filter(|n| !in_new_window(n))
+                .collect(),
+            None => Vec::new(),
+        };
+        let entering: Vec<BlockNumber> = self
             .table
-            .range((Bound::Unbounded, Bound::Included(&proposal_end)))
-            .map(|pair| pair.1)
-            .cloned()
-            .flatten()
+            .range(proposal_start..=proposal_end)
+            .map(|(n, _)| *n)
+            .filter(|n| old_window.map_or(true, |(start, end)| *n < start || *n > end))
             .collect();
+        for n in leaving {
+            remove_ids(&mut self.set, &mut self.removed, &self.table[&n]);
+        }
+        for n in entering {
+            add_ids(&mut self.set, &mut self.removed, &self.table[&n]);
+        }
 
-        let removed_ids: FnvHashSet<ProposalShortId> =
-            self.set.difference(&new_ids).cloned().collect();
+        let left = self.table.split_off(&proposal_start);
+        self.table = left;
+
+        let removed_ids = mem::replace(&mut self.removed, FnvHashSet::default());
         trace!(target: "chain", "[proposal_finalize] number {} proposal_start {}----proposal_end {}", number , proposal_start, proposal_end);
-        trace!(target: "chain", "[proposal_finalize] number {} new_ids {:?}----removed_ids {:?}", number, new_ids, removed_ids);
-        self.set = new_ids;
+        trace!(target: "chain", "[proposal_finalize] number {} removed_ids {:?}", number, removed_ids);
         removed_ids
     }
 }
 
+fn add_ids(
+    set: &mut FnvHashMap<ProposalShortId, usize>,
+    removed: &mut FnvHashSet<ProposalShortId>,
+    ids: &FnvHashSet<ProposalShortId>,
+) {
+    for id in ids {
+        *set.entry(*id).or_insert(0) += 1;
+        removed.remove(id);
+    }
+}
+
+fn remove_ids(
+    set: &mut FnvHashMap<ProposalShortId, usize>,
+    removed: &mut FnvHashSet<ProposalShortId>,
+    ids: &FnvHashSet<ProposalShortId>,
+) {
+    for id in ids {
+        if let Some(count) = set.get_mut(id) {
+            *count -= 1;
+            if *count == 0 {
+                set.remove(id);
+                removed.insert(*id);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -95,4 +168,29 @@ mod tests {
         assert!(table.finalize(12).is_empty());
         assert!(!table.contains(&id));
     }
+
+    #[test]
+    fn test_remove_in_window() {
+        let window = ProposalWindow(2, 10);
+        let mut table = TxProposalTable::new(window);
+        let id1 = ProposalShortId::new([1; 10]);
+        let id2 = ProposalShortId::new([2; 10]);
+        table.insert(1, vec![id1, id2].into_iter().collect());
+        table.insert(2, vec![id2].into_iter().collect());
+        assert!(table.finalize(3).is_empty());
+        assert!(table.contains(&id1));
+        assert!(table.contains(&id2));
+
+        // a reorg replaces the block 2 and removes the block 1
+        table.insert(2, vec![id1].into_iter().collect());
+        assert!(table.contains(&id1));
+        assert!(table.contains(&id2));
+        table.remove(1);
+        assert!(table.contains(&id1));
+        assert!(!table.contains(&id2));
+
+        let removed: FnvHashSet<ProposalShortId> = vec![id2].into_iter().collect();
+        assert_eq!(table.finalize(3), removed);
+        assert_eq!(table.get_ids_iter().collect::<Vec<_>>(), vec![&id1]);
+    }
 }
@@ -0,0 +1,209 @@
+//! Background script verification of the transactions submitted to the pool.
+//!
+//! The cheap checks are done by the submitter while it holds the pool lock, the scripts are
+//! then run in the verification pool without any lock, and the results are added to the pool
+//! by a dedicated thread. The verification pool threads never wait for the pool lock, the
+//! chain service holds it while it runs verifications in the same thread pool.
+
+use crate::chain_state::ChainState;
+use crate::store::ChainStore;
+use crate::tx_pool::{PoolError, TxPool};
+use ckb_core::cell::{CellStatus, ResolvedTransaction};
+use ckb_core::script_cache::ScriptHashCache;
+use ckb_core::transaction::Transaction;
+use ckb_core::Cycle;
+use ckb_script::ScriptError;
+use ckb_util::{Mutex, RwLock};
+use ckb_verification::{ScriptVerifier, TransactionError, VerificationPool};
+use crossbeam_channel::{self, Receiver, Sender, TryRecvError};
+use log::trace;
+use lru_cache::LruCache;
+use numext_fixed_hash::H256;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Result of a transaction queued by `Shared::add_tx_to_pool`
+#[derive(Debug)]
+pub struct TxVerification {
+    receiver: Receiver<Result<Cycle, PoolError>>,
+}
+
+impl TxVerification {
+    /// Wait until the scripts are verified and the transaction is added to the pool or rejected
+    pub fn wait(self) -> Result<Cycle, PoolError> {
+        self.receiver.recv().unwrap_or(Err(PoolError::Cancelled))
+    }
+
+    /// The result if the transaction is already added to the pool or rejected, without
+    /// waiting for the scripts
+    pub fn try_wait(&self) -> Option<Result<Cycle, PoolError>> {
+        match self.receiver.try_recv() {
+            Ok(ret) => Some(ret),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(PoolError::Cancelled)),
+        }
+    }
+}
+
+struct VerifiedTx {
+    transaction: Transaction,
+    result: Result<Cycle, PoolError>,
+    local: bool,
+    cancelled: Arc<AtomicBool>,
+    reply: Sender<Result<Cycle, PoolError>>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TxVerifyQueue {
+    verification_pool: VerificationPool,
+    max_cycles: Cycle,
+    /// `max_tx_cycles` of the pool policy, scripts are aborted beyond it
+    max_tx_cycles: Cycle,
+    txs_verify_cache: Arc<Mutex<LruCache<H256, Cycle>>>,
+    script_hash_cache: Arc<ScriptHashCache>,
+    verified_sender: Sender<VerifiedTx>,
+}
+
+impl TxVerifyQueue {
+    /// Start the thread adding the verified transactions, it exits once the queue is dropped
+    pub(crate) fn start<CS: ChainStore + 'static>(
+        verification_pool: VerificationPool,
+        max_cycles: Cycle,
+        max_tx_cycles: Cycle,
+        txs_verify_cache: Arc<Mutex<LruCache<H256, Cycle>>>,
+        script_hash_cache: Arc<ScriptHashCache>,
+        chain_state: Arc<RwLock<Arc<ChainState<CS>>>>,
+        tx_pool: Arc<Mutex<TxPool>>,
+    ) -> Self {
+        let (verified_sender, verified_receiver) = crossbeam_channel::unbounded();
+        thread::Builder::new()
+            .name("TxVerifyQueue".to_string())
+            .spawn(move || {
+                for verified in verified_receiver.iter() {
+                    let reply = verified.reply.clone();
+                    let ret = add_verified_tx(&chain_state, &tx_pool, verified);
+                    let _ = reply.send(ret);
+                }
+            })
+            .expect("Start TxVerifyQueue failed");
+        TxVerifyQueue {
+            verification_pool,
+            max_cycles,
+            max_tx_cycles,
+            txs_verify_cache,
+            script_hash_cache,
+            verified_sender,
+        }
+    }
+
+    /// Run the scripts of `tx`, which passed the other checks against `input_cells` and
+    /// `dep_cells`, unless its cycles are cached. The job is skipped if `cancelled` is set
+    /// before it starts.
+    pub(crate) fn push(
+        &self,
+        tx: Transaction,
+        input_cells: Vec<CellStatus>,
+        dep_cells: Vec<CellStatus>,
+        local: bool,
+        cancelled: Arc<AtomicBool>,
+    ) -> TxVerification {
+        let (reply, receiver) = crossbeam_channel::bounded(1);
+        let queue = self.clone();
+        self.verification_pool.spawn(move || {
+            let result = if cancelled.load(Ordering::SeqCst) {
+                Err(PoolError::Cancelled)
+            } else {
+                queue.verify_scripts(&tx, input_cells, dep_cells)
+            };
+            let _ = queue.verified_sender.send(VerifiedTx {
+                transaction: tx,
+                result,
+                local,
+                cancelled,
+                reply,
+            });
+        });
+        TxVerification { receiver }
+    }
+
+    fn verify_scripts(
+        &self,
+        tx: &Transaction,
+        input_cells: Vec<CellStatus>,
+        dep_cells: Vec<CellStatus>,
+    ) -> Result<Cycle, PoolError> {
+        let max_cycles = self.max_cycles.min(self.max_tx_cycles);
+        let tx_hash = tx.hash();
+        let cached = self
+            .txs_verify_cache
+            .lock()
+            .get_mut(&tx_hash)
+            .map(|cycles| *cycles);
+        let cycles = match cached {
+            Some(cycles) => cycles,
+            None => {
+                let rtx = ResolvedTransaction {
+                    transaction: tx,
+                    input_cells,
+                    dep_cells,
+                };
+                match ScriptVerifier::with_hash_cache(&rtx, &self.script_hash_cache)
+                    .verify(max_cycles)
+                {
+                    Ok(cycles) => {
+                        self.txs_verify_cache.lock().insert(tx_hash, cycles);
+                        cycles
+                    }
+                    // the transaction may still fit in a block, only this node refuses it
+                    Err(TransactionError::ScriptFailure(ScriptError::ExceededMaximumCycles))
+                        if self.max_tx_cycles < self.max_cycles =>
+                    {
+                        return Err(PoolError::ExceededMaxTxCycles(self.max_tx_cycles));
+                    }
+                    Err(err) => return Err(PoolError::InvalidTx(err)),
+                }
+            }
+        };
+        if cycles > self.max_tx_cycles {
+            Err(PoolError::ExceededMaxTxCycles(self.max_tx_cycles))
+        } else {
+            Ok(cycles)
+        }
+    }
+}
+
+fn add_verified_tx<CS: ChainStore>(
+    chain_state: &RwLock<Arc<ChainState<CS>>>,
+    tx_pool: &Mutex<TxPool>,
+    verified: VerifiedTx,
+) -> Result<Cycle, PoolError> {
+    let mut tx_pool = tx_pool.lock();
+    // cancelled entries are already removed, the id may be queued again since
+    if verified.cancelled.load(Ordering::SeqCst) {
+        return Err(PoolError::Cancelled);
+    }
+    let short_id = verified.transaction.proposal_short_id();
+    let tx_hash = verified.transaction.hash();
+    tx_pool.remove_verifying(&short_id);
+    // the tip may have moved on while the scripts ran, the inputs are resolved again
+    let transaction = verified.transaction;
+    if let Ok(cycles) = verified.result {
+        tx_pool.trace_verified(&tx_hash, cycles);
+    }
+    let ret = verified.result.and_then(|cycles| {
+        let chain_state = Arc::clone(&chain_state.read());
+        chain_state.accept_tx(&mut tx_pool, transaction, Some(cycles))
+    });
+    trace!(target: "tx_pool", "add verified tx {:?}: {:?}", short_id, ret);
+    match ret {
+        Ok(_) => {
+            tx_pool.rejected.remove(&tx_hash);
+            if verified.local {
+                tx_pool.mark_local_tx(short_id);
+            }
+        }
+        Err(ref err) => tx_pool.add_rejected(tx_hash, err),
+    }
+    ret
+}
@@ -21,6 +21,7 @@ pub(crate) const POW_SPACING: u64 = 15 * 1000; //15s
 
 pub(crate) const MAX_BLOCK_CYCLES: Cycle = 100_000_000;
 pub(crate) const MAX_BLOCK_BYTES: u64 = 10_000_000; // 10mb
+pub(crate) const MAX_TX_BYTES: u64 = 1_000_000; // 1mb
 pub(crate) const BLOCK_VERSION: u32 = 0;
 
 #[derive(Clone, PartialEq, Debug, Eq, Copy)]
@@ -59,8 +60,17 @@ pub struct Consensus {
     pub max_block_cycles: Cycle,
     // Maximum number of bytes to use for the entire block
     pub max_block_bytes: u64,
+    // Maximum serialized size of a single non-cellbase transaction
+    pub max_tx_bytes: u64,
+    // Maximum count of inputs of a single non-cellbase transaction, unlimited if None
+    pub max_tx_inputs: Option<usize>,
+    // Maximum count of outputs of a single non-cellbase transaction, unlimited if None
+    pub max_tx_outputs: Option<usize>,
     // block version number supported
     pub block_version: Version,
+    // The dao field of the header is filled from this block number on, it stays empty
+    // forever if None
+    pub dao_activation_number: Option<BlockNumber>,
 }
 
 // genesis difficulty should not be zero
@@ -85,7 +95,11 @@ impl Default for Consensus {
             median_time_block_count: MEDIAN_TIME_BLOCK_COUNT,
             max_block_cycles: MAX_BLOCK_CYCLES,
             max_block_bytes: MAX_BLOCK_BYTES,
+            max_tx_bytes: MAX_TX_BYTES,
+            max_tx_inputs: None,
+            max_tx_outputs: None,
             block_version: BLOCK_VERSION,
+            dao_activation_number: None,
         }
     }
 }
@@ -117,11 +131,31 @@ impl Consensus {
         self
     }
 
+    pub fn set_max_tx_bytes(mut self, max_tx_bytes: u64) -> Self {
+        self.max_tx_bytes = max_tx_bytes;
+        self
+    }
+
+    pub fn set_max_tx_inputs(mut self, max_tx_inputs: Option<usize>) -> Self {
+        self.max_tx_inputs = max_tx_inputs;
+        self
+    }
+
+    pub fn set_max_tx_outputs(mut self, max_tx_outputs: Option<usize>) -> Self {
+        self.max_tx_outputs = max_tx_outputs;
+        self
+    }
+
     pub fn set_cellbase_maturity(mut self, cellbase_maturity: BlockNumber) -> Self {
         self.cellbase_maturity = cellbase_maturity;
         self
     }
 
+    pub fn set_dao_activation_number(mut self, dao_activation_number: Option<BlockNumber>) -> Self {
+        self.dao_activation_number = dao_activation_number;
+        self
+    }
+
     pub fn genesis_block(&self) -> &Block {
         &self.genesis_block
     }
@@ -174,10 +208,26 @@ impl Consensus {
         self.max_block_bytes
     }
 
+    pub fn max_tx_bytes(&self) -> u64 {
+        self.max_tx_bytes
+    }
+
+    pub fn max_tx_inputs(&self) -> Option<usize> {
+        self.max_tx_inputs
+    }
+
+    pub fn max_tx_outputs(&self) -> Option<usize> {
+        self.max_tx_outputs
+    }
+
     pub fn block_version(&self) -> Version {
         self.block_version
     }
 
+    pub fn dao_activation_number(&self) -> Option<BlockNumber> {
+        self.dao_activation_number
+    }
+
     pub fn tx_proposal_window(&self) -> ProposalWindow {
         self.tx_proposal_window
     }
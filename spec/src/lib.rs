@@ -9,7 +9,7 @@
 //! we must put nested config struct in the tail to make it serializable,
 //! details https://docs.rs/toml/0.5.0/toml/ser/index.html
 
-use crate::consensus::Consensus;
+use crate::consensus::{Consensus, MAX_TX_BYTES};
 use ckb_core::block::Block;
 use ckb_core::block::BlockBuilder;
 use ckb_core::header::HeaderBuilder;
@@ -32,6 +32,14 @@ pub mod consensus;
 pub struct ChainSpec {
     pub resource: Resource,
     pub name: String,
+    /// Multiaddrs with the `/p2p/` suffix, dialed when the peer store is empty
+    pub bootnodes: Vec<String>,
+    /// Domains queried for seed peers
+    pub dns_seeds: Vec<String>,
+    /// Public keys of the dns seeders, see `NetworkConfig::dns_seed_pubkeys`
+    pub dns_seed_pubkeys: Vec<String>,
+    /// Peers below these p2p protocol versions are disconnected
+    pub min_protocol_versions: Vec<MinProtocolVersion>,
     pub genesis: Genesis,
     pub params: Params,
     pub system_cells: Vec<Resource>,
@@ -42,6 +50,14 @@ pub struct ChainSpec {
 #[derive(Serialize, Deserialize)]
 pub struct ChainSpecConfig {
     pub name: String,
+    #[serde(default)]
+    pub bootnodes: Vec<String>,
+    #[serde(default)]
+    pub dns_seeds: Vec<String>,
+    #[serde(default)]
+    pub dns_seed_pubkeys: Vec<String>,
+    #[serde(default)]
+    pub min_protocol_versions: Vec<MinProtocolVersion>,
     pub genesis: Genesis,
     pub params: Params,
     pub system_cells: Vec<SystemCell>,
@@ -53,6 +69,18 @@ pub struct Params {
     pub initial_block_reward: Capacity,
     pub max_block_cycles: Cycle,
     pub cellbase_maturity: BlockNumber,
+    #[serde(default = "default_max_tx_bytes")]
+    pub max_tx_bytes: u64,
+    #[serde(default)]
+    pub max_tx_inputs: Option<usize>,
+    #[serde(default)]
+    pub max_tx_outputs: Option<usize>,
+    #[serde(default)]
+    pub dao_activation_number: Option<BlockNumber>,
+}
+
+fn default_max_tx_bytes() -> u64 {
+    MAX_TX_BYTES
 }
 
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
@@ -72,6 +100,14 @@ pub struct Seal {
     pub proof: Vec<u8>,
 }
 
+/// The lowest version of the p2p protocol `protocol_id` peers must speak,
+/// raised on custom networks to coordinate protocol upgrades
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct MinProtocolVersion {
+    pub protocol_id: usize,
+    pub version: String,
+}
+
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct SystemCell {
     pub path: PathBuf,
@@ -150,6 +186,10 @@ impl ChainSpec {
             resource,
             system_cells: system_cells_result?,
             name: spec_config.name,
+            bootnodes: spec_config.bootnodes,
+            dns_seeds: spec_config.dns_seeds,
+            dns_seed_pubkeys: spec_config.dns_seed_pubkeys,
+            min_protocol_versions: spec_config.min_protocol_versions,
             genesis: spec_config.genesis,
             params: spec_config.params,
             pow: spec_config.pow,
@@ -219,6 +259,10 @@ impl ChainSpec {
             .set_cellbase_maturity(self.params.cellbase_maturity)
             .set_initial_block_reward(self.params.initial_block_reward)
             .set_max_block_cycles(self.params.max_block_cycles)
+            .set_max_tx_bytes(self.params.max_tx_bytes)
+            .set_max_tx_inputs(self.params.max_tx_inputs)
+            .set_max_tx_outputs(self.params.max_tx_outputs)
+            .set_dao_activation_number(self.params.dao_activation_number)
             .set_pow(self.pow.clone());
 
         Ok(consensus)
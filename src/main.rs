@@ -30,6 +30,21 @@ fn run_app() -> Result<(), ExitCode> {
         (cli::CMD_PROF, Some(matches)) => subcommand::profile(setup.prof(&matches)?),
         (cli::CMD_EXPORT, Some(matches)) => subcommand::export(setup.export(&matches)?),
         (cli::CMD_IMPORT, Some(matches)) => subcommand::import(setup.import(&matches)?),
+        (cli::CMD_EXPORT_ADDRESSES, Some(matches)) => {
+            subcommand::export_addresses(setup.export_addresses(&matches)?)
+        }
+        (cli::CMD_IMPORT_ADDRESSES, Some(matches)) => {
+            subcommand::import_addresses(setup.import_addresses(&matches)?)
+        }
+        (cli::CMD_EXPORT_SNAPSHOT, Some(matches)) => {
+            subcommand::export_snapshot(setup.export_snapshot(&matches)?)
+        }
+        (cli::CMD_IMPORT_SNAPSHOT, Some(matches)) => {
+            subcommand::import_snapshot(setup.import_snapshot(&matches)?)
+        }
+        (cli::CMD_VERIFY_STORE, Some(matches)) => {
+            subcommand::verify_store(setup.verify_store(&matches)?)
+        }
         _ => unreachable!(),
     }
 }
@@ -0,0 +1,34 @@
+use ckb_app_config::{ExitCode, ExportAddressesArgs, ImportAddressesArgs};
+use ckb_network::peer_store::SqlitePeerStore;
+
+fn open_peer_store(path: String) -> Result<SqlitePeerStore, ExitCode> {
+    SqlitePeerStore::file(path).map_err(|err| {
+        eprintln!("Open peer store error: {:?}", err);
+        ExitCode::Failure
+    })
+}
+
+pub fn export_addresses(args: ExportAddressesArgs) -> Result<(), ExitCode> {
+    let peer_store = open_peer_store(args.config.peer_store_path().to_string_lossy().to_string())?;
+    let count = peer_store.export_addresses(&args.target).map_err(|err| {
+        eprintln!("Export addresses error: {}", err);
+        ExitCode::Failure
+    })?;
+    println!("Exported {} addresses to {}", count, args.target.display());
+    Ok(())
+}
+
+pub fn import_addresses(args: ImportAddressesArgs) -> Result<(), ExitCode> {
+    let mut peer_store =
+        open_peer_store(args.config.peer_store_path().to_string_lossy().to_string())?;
+    let count = peer_store.import_addresses(&args.source).map_err(|err| {
+        eprintln!("Import addresses error: {}", err);
+        ExitCode::Failure
+    })?;
+    println!(
+        "Imported {} new addresses from {}",
+        count,
+        args.source.display()
+    );
+    Ok(())
+}
@@ -1,10 +1,10 @@
 use ckb_app_config::{ExitCode, ExportArgs};
-use ckb_db::{CacheDB, RocksDB};
+use ckb_db::{BackendDB, CacheDB};
 use ckb_instrument::Export;
 use ckb_shared::shared::SharedBuilder;
 
 pub fn export(args: ExportArgs) -> Result<(), ExitCode> {
-    let shared = SharedBuilder::<CacheDB<RocksDB>>::default()
+    let shared = SharedBuilder::<CacheDB<BackendDB>>::default()
         .consensus(args.consensus)
         .db(&args.config.db)
         .build()
@@ -13,6 +13,7 @@ pub fn export(args: ExportArgs) -> Result<(), ExitCode> {
             ExitCode::Failure
         })?;
     Export::new(shared, args.format, args.target)
+        .range(args.from, args.to)
         .execute()
         .map_err(|err| {
             eprintln!("Export error: {:?}", err);
@@ -1,3 +1,4 @@
+mod addresses;
 pub mod cli;
 mod export;
 mod import;
@@ -5,10 +6,15 @@ mod init;
 mod miner;
 mod prof;
 mod run;
+mod snapshot;
+mod verify_store;
 
+pub use self::addresses::{export_addresses, import_addresses};
 pub use self::export::export;
 pub use self::import::import;
 pub use self::init::init;
 pub use self::miner::miner;
 pub use self::prof::profile;
 pub use self::run::run;
+pub use self::snapshot::{export_snapshot, import_snapshot};
+pub use self::verify_store::verify_store;
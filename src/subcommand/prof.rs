@@ -1,6 +1,6 @@
 use ckb_app_config::{ExitCode, ProfArgs};
 use ckb_chain::chain::ChainBuilder;
-use ckb_db::{CacheDB, DBConfig, RocksDB};
+use ckb_db::{BackendDB, CacheDB, DBConfig};
 use ckb_notify::NotifyService;
 use ckb_shared::shared::{Shared, SharedBuilder};
 use ckb_shared::store::ChainStore;
@@ -8,7 +8,7 @@ use log::info;
 use std::sync::Arc;
 
 pub fn profile(args: ProfArgs) -> Result<(), ExitCode> {
-    let shared = SharedBuilder::<CacheDB<RocksDB>>::default()
+    let shared = SharedBuilder::<CacheDB<BackendDB>>::default()
         .consensus(args.consensus.clone())
         .db(&args.config.db)
         .tx_pool_config(args.config.tx_pool.clone())
@@ -19,11 +19,11 @@ pub fn profile(args: ProfArgs) -> Result<(), ExitCode> {
         })?;
 
     let tmp_dir = tempfile::Builder::new().tempdir().unwrap();
-    let tmp_shared = SharedBuilder::<CacheDB<RocksDB>>::default()
+    let tmp_shared = SharedBuilder::<CacheDB<BackendDB>>::default()
         .consensus(args.consensus)
         .db(&DBConfig {
             path: tmp_dir.as_ref().to_path_buf(),
-            options: None,
+            ..Default::default()
         })
         .tx_pool_config(args.config.tx_pool)
         .build()
@@ -33,7 +33,7 @@ pub fn profile(args: ProfArgs) -> Result<(), ExitCode> {
         })?;
 
     let from = std::cmp::max(1, args.from);
-    let to = std::cmp::min(shared.chain_state().lock().tip_number(), args.to);
+    let to = std::cmp::min(shared.chain_state().tip_number(), args.to);
     info!("start profling, re-process blocks {}..{}:", from, to);
     let now = std::time::Instant::now();
     let tx_count = profile_block_process(shared, tmp_shared, from, to);
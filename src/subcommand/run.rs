@@ -1,25 +1,30 @@
 use crate::helper::{deadlock_detection, wait_for_exit};
 use ckb_app_config::{ExitCode, RunArgs};
 use ckb_chain::chain::{ChainBuilder, ChainController};
-use ckb_db::{CacheDB, RocksDB};
+use ckb_db::{BackendDB, CacheDB};
 use ckb_miner::BlockAssembler;
 use ckb_network::{CKBProtocol, NetworkService, NetworkState};
 use ckb_notify::{NotifyController, NotifyService};
 use ckb_rpc::RpcServer;
 use ckb_shared::shared::{Shared, SharedBuilder};
 use ckb_shared::store::ChainStore;
-use ckb_sync::{NetTimeProtocol, NetworkProtocol, Relayer, SyncSharedState, Synchronizer};
+use ckb_sync::{
+    NetTimeProtocol, NetworkProtocol, Relayer, SyncSharedState, Synchronizer, RELAY_FEATURE_STEM,
+};
 use ckb_traits::chain_provider::ChainProvider;
-use log::info;
+use log::{error, info};
 use std::sync::Arc;
 
 pub fn run(args: RunArgs) -> Result<(), ExitCode> {
     deadlock_detection();
 
-    let shared = SharedBuilder::<CacheDB<RocksDB>>::default()
+    let warm_up_depth = args.config.verification.warm_up_depth;
+    let shared = SharedBuilder::<CacheDB<BackendDB>>::default()
         .consensus(args.consensus)
         .db(&args.config.db)
         .tx_pool_config(args.config.tx_pool)
+        .verification_config(args.config.verification)
+        .store_config(args.config.store)
         .build()
         .map_err(|err| {
             eprintln!("Run error: {:?}", err);
@@ -28,7 +33,7 @@ pub fn run(args: RunArgs) -> Result<(), ExitCode> {
 
     let notify = NotifyService::default().start(Some("notify"));
 
-    let chain_controller = setup_chain(shared.clone(), notify.clone());
+    let chain_controller = setup_chain(shared.clone(), notify.clone(), warm_up_depth)?;
     info!(target: "main", "chain genesis hash: {:#x}", shared.genesis_hash());
 
     let block_assembler = BlockAssembler::new(shared.clone(), args.config.block_assembler);
@@ -37,7 +42,12 @@ pub fn run(args: RunArgs) -> Result<(), ExitCode> {
     let network_state = Arc::new(
         NetworkState::from_config(args.config.network).expect("Init network state failed"),
     );
-    let sync_shared_state = Arc::new(SyncSharedState::new(shared.clone()));
+    let max_future_block_time = args.config.sync.max_future_block_time;
+    let sync_shared_state = Arc::new(
+        SyncSharedState::new(shared.clone())
+            .with_max_future_block_time(max_future_block_time)
+            .with_stem_hops(args.config.relay.stem_hops),
+    );
     let synchronizer = Synchronizer::new(
         chain_controller.clone(),
         Arc::clone(&sync_shared_state),
@@ -46,10 +56,11 @@ pub fn run(args: RunArgs) -> Result<(), ExitCode> {
 
     let relayer = Relayer::new(
         chain_controller.clone(),
-        sync_shared_state,
+        Arc::clone(&sync_shared_state),
         synchronizer.peers(),
+        args.config.relay,
     );
-    let net_timer = NetTimeProtocol::default();
+    let net_timer = NetTimeProtocol::with_tolerant_offset(max_future_block_time);
 
     let protocols = vec![
         CKBProtocol::new(
@@ -62,10 +73,11 @@ pub fn run(args: RunArgs) -> Result<(), ExitCode> {
         CKBProtocol::new(
             "rel".to_string(),
             NetworkProtocol::RELAY.into(),
-            &["1".to_string()][..],
+            &["1".to_string(), "2".to_string()][..],
             move || Box::new(relayer.clone()),
             Arc::clone(&network_state),
-        ),
+        )
+        .with_features("2", RELAY_FEATURE_STEM),
         CKBProtocol::new(
             "tim".to_string(),
             NetworkProtocol::TIME.into(),
@@ -81,9 +93,11 @@ pub fn run(args: RunArgs) -> Result<(), ExitCode> {
     let rpc_server = RpcServer::new(
         args.config.rpc,
         network_controller,
-        shared,
+        shared.clone(),
         chain_controller,
         block_assembler_controller,
+        sync_shared_state,
+        &notify,
     );
 
     wait_for_exit();
@@ -92,13 +106,27 @@ pub fn run(args: RunArgs) -> Result<(), ExitCode> {
 
     rpc_server.close();
     info!(target: "main", "Jsonrpc shutdown");
+
+    let tx_pool = shared.tx_pool().lock();
+    if let Err(err) = shared.chain_state().persist_tx_pool(&tx_pool) {
+        error!(target: "main", "Failed to persist the transaction pool: {}", err);
+    }
     Ok(())
 }
 
 fn setup_chain<CS: ChainStore + 'static>(
     shared: Shared<CS>,
     notify: NotifyController,
-) -> ChainController {
+    warm_up_depth: u64,
+) -> Result<ChainController, ExitCode> {
     let chain_service = ChainBuilder::new(shared, notify).build();
-    chain_service.start(Some("ChainService"))
+    chain_service.verify_tip(warm_up_depth).map_err(|err| {
+        eprintln!(
+            "Stored chain does not pass verification, the database may be corrupted \
+             or the chain spec changed: {}",
+            err
+        );
+        ExitCode::Failure
+    })?;
+    Ok(chain_service.start(Some("ChainService")))
 }
@@ -0,0 +1,65 @@
+use ckb_app_config::{ExitCode, ExportSnapshotArgs, ImportSnapshotArgs};
+use ckb_db::{BackendDB, CacheDB};
+use ckb_shared::shared::SharedBuilder;
+use ckb_shared::snapshot::StateSnapshot;
+use numext_fixed_hash::H256;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+pub fn export_snapshot(args: ExportSnapshotArgs) -> Result<(), ExitCode> {
+    let shared = SharedBuilder::<CacheDB<BackendDB>>::default()
+        .consensus(args.consensus)
+        .db(&args.config.db)
+        .build()
+        .map_err(|err| {
+            eprintln!("Export snapshot error: {:?}", err);
+            ExitCode::Failure
+        })?;
+    let snapshot = shared.export_snapshot();
+    let file = File::create(&args.target)?;
+    snapshot.write_to(BufWriter::new(file)).map_err(|err| {
+        eprintln!("Export snapshot error: {}", err);
+        ExitCode::Failure
+    })?;
+    if let Some(tip_header) = snapshot.tip_header() {
+        println!(
+            "Exported the snapshot at block {} {:#x} to {}",
+            tip_header.number(),
+            tip_header.hash(),
+            args.target.display()
+        );
+    }
+    Ok(())
+}
+
+pub fn import_snapshot(args: ImportSnapshotArgs) -> Result<(), ExitCode> {
+    let checkpoint = H256::from_hex_str(&args.checkpoint).map_err(|err| {
+        eprintln!("Invalid checkpoint {}: {:?}", args.checkpoint, err);
+        ExitCode::Cli
+    })?;
+    let file = File::open(&args.source)?;
+    let snapshot = StateSnapshot::read_from(BufReader::new(file)).map_err(|err| {
+        eprintln!("Import snapshot error: {}", err);
+        ExitCode::Failure
+    })?;
+    let shared = SharedBuilder::<CacheDB<BackendDB>>::default()
+        .consensus(args.consensus)
+        .db(&args.config.db)
+        .build()
+        .map_err(|err| {
+            eprintln!("Import snapshot error: {:?}", err);
+            ExitCode::Failure
+        })?;
+    shared
+        .import_snapshot(&snapshot, &checkpoint)
+        .map_err(|err| {
+            eprintln!("Import snapshot error: {}", err);
+            ExitCode::Failure
+        })?;
+    println!(
+        "Imported the snapshot at block {} {:#x}",
+        shared.chain_state().tip_number(),
+        checkpoint
+    );
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use ckb_app_config::{ExitCode, VerifyStoreArgs};
+use ckb_db::{BackendDB, CacheDB};
+use ckb_shared::store::ChainKVStore;
+use ckb_shared::store_verifier::StoreVerifier;
+use ckb_shared::COLUMNS;
+
+pub fn verify_store(args: VerifyStoreArgs) -> Result<(), ExitCode> {
+    // opened without `SharedBuilder`, which repairs the tip and rebuilds the indexes on startup
+    let db = CacheDB::new(BackendDB::open(&args.config.db, COLUMNS), &[]);
+    let store = ChainKVStore::new(db);
+    let report = StoreVerifier::new(&store, &args.config.store)
+        .repair(args.repair)
+        .verify()
+        .map_err(|err| {
+            eprintln!("Verify store error: {}", err);
+            ExitCode::Failure
+        })?;
+
+    for inconsistency in &report.inconsistencies {
+        let status = if report.repaired && inconsistency.is_repairable() {
+            "repaired"
+        } else if inconsistency.is_repairable() {
+            "repairable"
+        } else {
+            "unrepairable"
+        };
+        println!("{}: {}", status, inconsistency);
+    }
+    if !report.cell_set_checked {
+        println!("The cell set is not checked because some block bodies are not stored");
+    }
+    if report.is_consistent() {
+        println!("The store is consistent to block {}", report.tip_number);
+        Ok(())
+    } else {
+        println!(
+            "Found {} inconsistencies to block {}",
+            report.inconsistencies.len(),
+            report.tip_number
+        );
+        if report.repaired
+            && report
+                .inconsistencies
+                .iter()
+                .all(|inconsistency| inconsistency.is_repairable())
+        {
+            Ok(())
+        } else {
+            Err(ExitCode::Failure)
+        }
+    }
+}
@@ -1,14 +1,71 @@
+use ckb_core::BlockNumber;
+use ckb_verification::ALLOWED_FUTURE_BLOCKTIME;
 use serde_derive::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
     pub orphan_block_limit: usize,
+    /// Orphan blocks whose parent is this many blocks below the best known header are discarded
+    #[serde(default = "default_max_orphan_block_age")]
+    pub max_orphan_block_age: BlockNumber,
+    /// Headers timestamped more than this many milliseconds ahead of the local clock are
+    /// parked rather than accepted, a network time offset above it is reported as clock skew
+    #[serde(default = "default_max_future_block_time")]
+    pub max_future_block_time: u64,
+}
+
+fn default_max_orphan_block_age() -> BlockNumber {
+    1024
+}
+
+fn default_max_future_block_time() -> u64 {
+    ALLOWED_FUTURE_BLOCKTIME
 }
 
 impl Config {
     pub fn default() -> Self {
         Config {
             orphan_block_limit: 1024,
+            max_orphan_block_age: default_max_orphan_block_age(),
+            max_future_block_time: default_max_future_block_time(),
+        }
+    }
+}
+
+/// Relay policy of the transactions from peers, the ones which don't satisfy it are neither
+/// accepted into the pool nor relayed to other peers. Transactions submitted through RPC are
+/// not checked against it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RelayConfig {
+    /// Minimum fee rate to relay a transaction, in shannons per 1000 bytes
+    pub min_fee_rate: u64,
+    /// Maximum serialized transaction size in bytes
+    pub max_tx_size: usize,
+    /// Maximum transactions a peer may announce to us per second, 0 means unlimited
+    pub max_tx_announce_rate: u32,
+    /// Locally submitted transactions are passed along this many single random outbound
+    /// peers before being broadcast, which hides their origin from peers listening to
+    /// the broadcast, 0 disables the stem phase
+    pub stem_hops: u8,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        RelayConfig {
+            min_fee_rate: 0,
+            max_tx_size: 512 * 1024,
+            max_tx_announce_rate: 100,
+            stem_hops: 0,
+        }
+    }
+}
+
+impl RelayConfig {
+    pub fn is_fee_rate_acceptable(&self, fee: u64, tx_size: usize) -> bool {
+        if tx_size == 0 {
+            return true;
         }
+        fee.saturating_mul(1000) / tx_size as u64 >= self.min_fee_rate
     }
 }
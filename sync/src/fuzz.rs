@@ -0,0 +1,130 @@
+//! Entry points for fuzzing, arbitrary bytes are decoded and processed as a sync or relay
+//! message received from a peer, with a network context which sends nothing.
+
+use crate::{Config, NetworkProtocol, RelayConfig, Relayer, SyncSharedState, Synchronizer};
+use ckb_chain::chain::ChainBuilder;
+use ckb_db::memorydb::MemoryKeyValueDB;
+use ckb_network::{
+    Behaviour, CKBProtocolContext, Peer, PeerIndex, PeerInfo, ProtocolId, TargetSession,
+};
+use ckb_notify::{NotifyController, NotifyService};
+use ckb_protocol::{get_root, RelayMessage, SyncMessage};
+use ckb_shared::shared::SharedBuilder;
+use ckb_shared::store::ChainKVStore;
+use std::sync::Arc;
+use std::time::Duration;
+
+const FUZZ_PEER: usize = 0;
+
+pub struct FuzzNetworkContext {
+    protocol_id: ProtocolId,
+}
+
+impl FuzzNetworkContext {
+    pub fn new(protocol_id: ProtocolId) -> Self {
+        FuzzNetworkContext { protocol_id }
+    }
+}
+
+impl CKBProtocolContext for FuzzNetworkContext {
+    fn set_notify(&self, _interval: Duration, _token: u64) {}
+    fn send_message(&self, _proto_id: ProtocolId, _peer_index: PeerIndex, _data: bytes::Bytes) {}
+    fn send_message_to(&self, _peer_index: PeerIndex, _data: bytes::Bytes) {}
+    fn filter_broadcast(&self, _target: TargetSession, _data: bytes::Bytes) {}
+    fn disconnect(&self, _peer_index: PeerIndex) {}
+    fn get_peer(&self, _peer_index: PeerIndex) -> Option<Peer> {
+        None
+    }
+    fn peer_info(&self, _peer_index: PeerIndex) -> Option<PeerInfo> {
+        None
+    }
+    fn connected_peers(&self) -> Vec<PeerIndex> {
+        vec![FUZZ_PEER.into()]
+    }
+    fn report_peer(&self, _peer_index: PeerIndex, _behaviour: Behaviour) {}
+    fn ban_peer(&self, _peer_index: PeerIndex, _timeout: Duration) {}
+    fn protocol_id(&self) -> ProtocolId {
+        self.protocol_id
+    }
+}
+
+/// A synchronizer and a relayer on an in-memory chain which only has the genesis block
+pub struct FuzzTarget {
+    synchronizer: Synchronizer<ChainKVStore<MemoryKeyValueDB>>,
+    relayer: Relayer<ChainKVStore<MemoryKeyValueDB>>,
+    sync_nc: FuzzNetworkContext,
+    relay_nc: FuzzNetworkContext,
+    // keep the notify service running
+    _notify: NotifyController,
+}
+
+impl Default for FuzzTarget {
+    fn default() -> Self {
+        let shared = SharedBuilder::<MemoryKeyValueDB>::new()
+            .build()
+            .expect("build shared");
+        let notify = NotifyService::default().start::<&str>(None);
+        let chain_controller = ChainBuilder::new(shared.clone(), notify.clone())
+            .build()
+            .start::<&str>(None);
+        let sync_shared_state = Arc::new(SyncSharedState::new(shared));
+        let synchronizer = Synchronizer::new(
+            chain_controller.clone(),
+            Arc::clone(&sync_shared_state),
+            Config::default(),
+        );
+        let relayer = Relayer::new(
+            chain_controller,
+            sync_shared_state,
+            synchronizer.peers(),
+            RelayConfig::default(),
+        );
+        FuzzTarget {
+            synchronizer,
+            relayer,
+            sync_nc: FuzzNetworkContext::new(NetworkProtocol::SYNC.into()),
+            relay_nc: FuzzNetworkContext::new(NetworkProtocol::RELAY.into()),
+            _notify: notify,
+        }
+    }
+}
+
+impl FuzzTarget {
+    pub fn sync_message(&self, data: &[u8]) {
+        if let Ok(message) = get_root::<SyncMessage>(data) {
+            let _ = self
+                .synchronizer
+                .try_process(&self.sync_nc, FUZZ_PEER.into(), message);
+        }
+    }
+
+    pub fn relay_message(&self, data: &[u8]) {
+        if let Ok(message) = get_root::<RelayMessage>(data) {
+            let _ = self
+                .relayer
+                .try_process(&self.relay_nc, FUZZ_PEER.into(), message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_core::transaction::TransactionBuilder;
+    use flatbuffers::FlatBufferBuilder;
+
+    #[test]
+    fn test_fuzz_target() {
+        let target = FuzzTarget::default();
+        for data in &[&[][..], &[0u8; 4][..], &[0xffu8; 64][..]] {
+            target.sync_message(data);
+            target.relay_message(data);
+        }
+
+        let fbb = &mut FlatBufferBuilder::new();
+        let tx = TransactionBuilder::default().build();
+        let message = RelayMessage::build_transaction(fbb, &tx, 0);
+        fbb.finish(message, None);
+        target.relay_message(fbb.finished_data());
+    }
+}
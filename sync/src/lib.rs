@@ -4,6 +4,8 @@
 //! https://github.com/nervosnetwork/rfcs/tree/master/rfcs/0000-block-sync-protocol
 
 mod config;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
 mod net_time_checker;
 mod relayer;
 mod synchronizer;
@@ -12,11 +14,11 @@ mod types;
 #[cfg(test)]
 mod tests;
 
-pub use crate::config::Config;
+pub use crate::config::{Config, RelayConfig};
 pub use crate::net_time_checker::NetTimeProtocol;
 pub use crate::relayer::Relayer;
 pub use crate::synchronizer::Synchronizer;
-pub use crate::types::SyncSharedState;
+pub use crate::types::{ChainTip, SyncProgress, SyncSharedState};
 use std::time::Duration;
 
 pub const MAX_HEADERS_LEN: usize = 2_000;
@@ -25,12 +27,14 @@ pub const MAX_SCHEDULED_LEN: usize = 4 * 1024;
 pub const MAX_BLOCKS_TO_ANNOUNCE: usize = 8;
 pub const MAX_UNCONNECTING_HEADERS: usize = 10;
 pub const MAX_BLOCKS_IN_TRANSIT_PER_PEER: usize = 16;
+// Download headers from this many peers concurrently while in IBD
+pub const MAX_HEADERS_SYNC_PEERS_IN_IBD: usize = 3;
 pub const MAX_TIP_AGE: u64 = 60 * 60 * 1000;
 pub const STALE_RELAY_AGE_LIMIT: u64 = 30 * 24 * 60 * 60 * 1000;
 pub const BLOCK_DOWNLOAD_WINDOW: u64 = 1024;
 pub const PER_FETCH_BLOCK_LIMIT: usize = 128;
 
-use ckb_network::ProtocolId;
+use ckb_network::{ProtocolFeatures, ProtocolId};
 
 pub enum NetworkProtocol {
     SYNC = 100,
@@ -38,6 +42,10 @@ pub enum NetworkProtocol {
     TIME = 102,
 }
 
+/// Relay protocol feature, the peer forwards transactions with stem hops to a single peer
+/// instead of broadcasting them, see `RelayConfig::stem_hops`. Supported since relay version 2.
+pub const RELAY_FEATURE_STEM: ProtocolFeatures = 1;
+
 impl Into<ProtocolId> for NetworkProtocol {
     fn into(self) -> ProtocolId {
         (self as usize).into()
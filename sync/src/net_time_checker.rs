@@ -74,12 +74,15 @@ impl Default for NetTimeChecker {
 /// Collect time offset samples from network peers and send notify to user if offset is too large
 pub struct NetTimeProtocol {
     checker: RwLock<NetTimeChecker>,
+    /// Whether the clock skew has been reported already
+    skew_reported: bool,
 }
 
 impl Clone for NetTimeProtocol {
     fn clone(&self) -> Self {
         NetTimeProtocol {
             checker: RwLock::new(self.checker.read().clone()),
+            skew_reported: self.skew_reported,
         }
     }
 }
@@ -91,14 +94,24 @@ impl NetTimeProtocol {
             max_samples,
             tolerant_offset,
         ));
-        NetTimeProtocol { checker }
+        NetTimeProtocol {
+            checker,
+            skew_reported: false,
+        }
+    }
+
+    pub fn with_tolerant_offset(tolerant_offset: u64) -> Self {
+        Self::new(MIN_SAMPLES, MAX_SAMPLES, tolerant_offset)
     }
 }
 
 impl Default for NetTimeProtocol {
     fn default() -> Self {
         let checker = RwLock::new(NetTimeChecker::default());
-        NetTimeProtocol { checker }
+        NetTimeProtocol {
+            checker,
+            skew_reported: false,
+        }
     }
 }
 
@@ -149,8 +162,18 @@ impl CKBProtocolHandler for NetTimeProtocol {
         let mut net_time_checker = self.checker.write();
         debug!(target: "network", "new net time offset sample {}ms", offset);
         net_time_checker.add_sample(offset);
-        if let Err(offset) = net_time_checker.check() {
-            warn!(target: "network", "Please check your computer's local clock({}ms offset from network peers), If your clock is wrong, it may cause unexpected errors.", offset);
+        match net_time_checker.check() {
+            Err(offset) => {
+                warn!(target: "network", "Please check your computer's local clock({}ms offset from network peers), If your clock is wrong, it may cause unexpected errors.", offset);
+                if !self.skew_reported {
+                    self.skew_reported = true;
+                    sentry::capture_message(
+                        &format!("local clock is {}ms offset from network peers", offset),
+                        sentry::Level::Warning,
+                    );
+                }
+            }
+            Ok(()) => self.skew_reported = false,
         }
     }
 }
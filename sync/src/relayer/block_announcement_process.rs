@@ -0,0 +1,54 @@
+use crate::relayer::Relayer;
+use ckb_network::{CKBProtocolContext, PeerIndex};
+use ckb_protocol::{cast, BlockAnnouncement};
+use ckb_shared::store::ChainStore;
+use failure::Error as FailureError;
+use log::debug;
+use numext_fixed_hash::H256;
+use std::convert::TryInto;
+
+pub struct BlockAnnouncementProcess<'a, CS> {
+    message: &'a BlockAnnouncement<'a>,
+    relayer: &'a Relayer<CS>,
+    nc: &'a CKBProtocolContext,
+    peer: PeerIndex,
+}
+
+impl<'a, CS: ChainStore> BlockAnnouncementProcess<'a, CS> {
+    pub fn new(
+        message: &'a BlockAnnouncement,
+        relayer: &'a Relayer<CS>,
+        nc: &'a CKBProtocolContext,
+        peer: PeerIndex,
+    ) -> Self {
+        BlockAnnouncementProcess {
+            message,
+            relayer,
+            nc,
+            peer,
+        }
+    }
+
+    pub fn execute(self) -> Result<(), FailureError> {
+        let block_hash: H256 = cast!(self.message.block_hash())?.try_into()?;
+        self.relayer
+            .peers
+            .known_blocks
+            .lock()
+            .insert(self.peer, block_hash.clone());
+
+        if self.relayer.is_block_known(&block_hash) {
+            debug!(target: "relay", "announced block {:#x} already known", block_hash);
+            return Ok(());
+        }
+
+        // low bandwidth mode, pull the announced block through headers sync
+        debug!(target: "relay", "peer {} announces block {:#x}, send_getheaders_to_peer", self.peer, block_hash);
+        self.relayer.shared.send_getheaders_to_peer(
+            self.nc,
+            self.peer,
+            self.relayer.shared.chain_state().tip_header(),
+        );
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use crate::relayer::Relayer;
+use ckb_core::transaction::Transaction;
 use ckb_protocol::{cast, BlockProposal, FlatbuffersVectorIterator};
 use ckb_shared::store::ChainStore;
 use failure::Error as FailureError;
@@ -16,12 +17,17 @@ impl<'a, CS: ChainStore> BlockProposalProcess<'a, CS> {
     }
 
     pub fn execute(self) -> Result<(), FailureError> {
-        let chain_state = self.relayer.shared.chain_state().lock();
         let txs = FlatbuffersVectorIterator::new(cast!(self.message.transactions())?);
         for tx in txs {
-            let ret = chain_state.add_tx_to_pool(TryInto::try_into(tx)?);
-            if ret.is_err() {
-                warn!(target: "relay", "BlockProposal add_tx_to_pool error {:?}", ret)
+            let tx: Transaction = TryInto::try_into(tx)?;
+            self.relayer
+                .state
+                .inflight_proposals
+                .lock()
+                .remove(&tx.proposal_short_id());
+            // the scripts are verified in background, the result is not waited for
+            if let Err(err) = self.relayer.shared.shared().add_tx_to_pool(tx) {
+                warn!(target: "relay", "BlockProposal add_tx_to_pool error {:?}", err)
             }
         }
         Ok(())
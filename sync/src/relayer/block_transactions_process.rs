@@ -1,9 +1,13 @@
-use crate::relayer::Relayer;
+use crate::relayer::{Relayer, MAX_BLOCK_TRANSACTIONS_BUFFERED_BLOCKS};
 use ckb_core::transaction::Transaction;
 use ckb_network::{CKBProtocolContext, PeerIndex};
 use ckb_protocol::{cast, BlockTransactions, FlatbuffersVectorIterator};
 use ckb_shared::store::ChainStore;
 use failure::Error as FailureError;
+use fnv::FnvHashMap;
+use log::debug;
+use numext_fixed_hash::H256;
+use std::cmp;
 use std::convert::TryInto;
 use std::sync::Arc;
 
@@ -30,7 +34,83 @@ impl<'a, CS: ChainStore> BlockTransactionsProcess<'a, CS> {
     }
 
     pub fn execute(self) -> Result<(), FailureError> {
-        let block_hash = cast!(self.message.block_hash())?.try_into()?;
+        let block_hash: H256 = cast!(self.message.block_hash())?.try_into()?;
+        // only the transactions of the short ids are requested
+        let max_transactions = match self
+            .relayer
+            .state
+            .pending_compact_blocks
+            .lock()
+            .get(&block_hash)
+        {
+            Some(compact_block) => compact_block.short_ids.len(),
+            None => return Ok(()),
+        };
+
+        // peers unaware of chunking leave both fields unset
+        let total = cmp::max(self.message.total(), 1);
+        let sequence = self.message.sequence();
+        // every chunk of a split response holds at least one transaction
+        if sequence >= total || (total > 1 && total as usize > max_transactions) {
+            debug!(target: "relay", "invalid block transactions chunk {}/{} of {:#x}", sequence + 1, total, block_hash);
+            cast!(None::<()>)?;
+        }
+        let transactions: Vec<Transaction> =
+            FlatbuffersVectorIterator::new(cast!(self.message.transactions())?)
+                .map(TryInto::try_into)
+                .collect::<Result<_, FailureError>>()?;
+        if transactions.len() > max_transactions || (total > 1 && transactions.is_empty()) {
+            cast!(None::<()>)?;
+        }
+
+        let transactions = if total == 1 {
+            transactions
+        } else {
+            let mut block_transactions_chunks = self.relayer.state.block_transactions_chunks.lock();
+            {
+                // drop partial responses of blocks no longer pending
+                let pending_compact_blocks = self.relayer.state.pending_compact_blocks.lock();
+                block_transactions_chunks
+                    .retain(|(_, hash), _| pending_compact_blocks.contains_key(hash));
+            }
+            // the chunks of different peers answering the same request are never mixed
+            let key = (self.peer, block_hash.clone());
+            let chunks = block_transactions_chunks
+                .entry(key.clone())
+                .or_insert_with(|| BlockTransactionsChunks::new(total));
+            if chunks.total != total {
+                cast!(None::<()>)?;
+            }
+            chunks.insert(sequence, transactions);
+            // a peer answers few blocks at once, and the missing transactions of a block fit
+            // in the block
+            let max_buffered_bytes = self.relayer.shared.consensus().max_block_bytes() as usize
+                * MAX_BLOCK_TRANSACTIONS_BUFFERED_BLOCKS;
+            let buffered_bytes: usize = block_transactions_chunks
+                .iter()
+                .filter(|((peer, _), _)| *peer == self.peer)
+                .map(|(_, chunks)| chunks.bytes)
+                .sum();
+            if buffered_bytes > max_buffered_bytes
+                || block_transactions_chunks[&key].transactions_count() > max_transactions
+            {
+                debug!(target: "relay", "peer {} buffered too many block transactions", self.peer);
+                block_transactions_chunks.retain(|(peer, _), _| *peer != self.peer);
+                cast!(None::<()>)?;
+            }
+            let chunks = &block_transactions_chunks[&key];
+            if (chunks.chunks.len() as u32) < total {
+                debug!(target: "relay", "received block transactions chunk {}/{} of {:#x}", sequence + 1, total, block_hash);
+                return Ok(());
+            }
+            let transactions = block_transactions_chunks
+                .remove(&key)
+                .expect("chunks inserted above")
+                .concat();
+            block_transactions_chunks.retain(|(_, hash), _| hash != &block_hash);
+            transactions
+        };
+
         if let Some(compact_block) = self
             .relayer
             .state
@@ -38,22 +118,64 @@ impl<'a, CS: ChainStore> BlockTransactionsProcess<'a, CS> {
             .lock()
             .remove(&block_hash)
         {
-            let transactions: Result<Vec<Transaction>, FailureError> =
-                FlatbuffersVectorIterator::new(cast!(self.message.transactions())?)
-                    .map(TryInto::try_into)
-                    .collect();
-
             let ret = {
-                let chain_state = self.relayer.shared.chain_state().lock();
+                let tx_pool = self.relayer.shared.tx_pool().lock();
                 self.relayer
-                    .reconstruct_block(&chain_state, &compact_block, transactions?)
+                    .reconstruct_block(&tx_pool, &compact_block, transactions)
             };
 
             if let Ok(block) = ret {
-                self.relayer
-                    .accept_block(self.nc, self.peer, &Arc::new(block));
+                if let Err(err) = self
+                    .relayer
+                    .accept_block(self.nc, self.peer, &Arc::new(block))
+                {
+                    if err.is_misbehavior() {
+                        return Err(err.into());
+                    }
+                }
             }
         }
         Ok(())
     }
 }
+
+/// Chunks of a `BlockTransactions` response received so far
+pub struct BlockTransactionsChunks {
+    total: u32,
+    chunks: FnvHashMap<u32, Vec<Transaction>>,
+    /// Serialized size of the transactions in `chunks`
+    bytes: usize,
+}
+
+impl BlockTransactionsChunks {
+    fn new(total: u32) -> Self {
+        BlockTransactionsChunks {
+            total,
+            chunks: FnvHashMap::default(),
+            bytes: 0,
+        }
+    }
+
+    fn insert(&mut self, sequence: u32, transactions: Vec<Transaction>) {
+        self.bytes += transactions
+            .iter()
+            .map(Transaction::serialized_size)
+            .sum::<usize>();
+        if let Some(replaced) = self.chunks.insert(sequence, transactions) {
+            self.bytes -= replaced
+                .iter()
+                .map(Transaction::serialized_size)
+                .sum::<usize>();
+        }
+    }
+
+    fn transactions_count(&self) -> usize {
+        self.chunks.values().map(Vec::len).sum()
+    }
+
+    fn concat(self) -> Vec<Transaction> {
+        let mut chunks: Vec<_> = self.chunks.into_iter().collect();
+        chunks.sort_by_key(|(sequence, _)| *sequence);
+        chunks.into_iter().flat_map(|(_, txs)| txs).collect()
+    }
+}
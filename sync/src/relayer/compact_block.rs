@@ -1,8 +1,10 @@
+use ckb_core::block::Block;
 use ckb_core::header::Header;
-use ckb_core::transaction::{IndexTransaction, ProposalShortId};
+use ckb_core::transaction::{IndexTransaction, ProposalShortId, Transaction};
 use ckb_core::uncle::UncleBlock;
 use ckb_protocol::{self, cast, FlatbuffersVectorIterator};
 use failure::Error as FailureError;
+use std::collections::HashSet;
 use std::convert::{TryFrom, TryInto};
 
 pub type ShortTransactionID = [u8; 6];
@@ -37,7 +39,7 @@ impl<'a> TryFrom<ckb_protocol::CompactBlock<'a>> for CompactBlock {
             .map(TryInto::try_into)
             .collect();
 
-        Ok(CompactBlock {
+        let compact_block = CompactBlock {
             header: header.try_into()?,
             nonce: b.nonce(),
             short_ids: cast!(FlatbuffersVectorIterator::new(short_ids)
@@ -50,6 +52,100 @@ impl<'a> TryFrom<ckb_protocol::CompactBlock<'a>> for CompactBlock {
             prefilled_transactions: prefilled_transactions?,
             uncles: uncles?,
             proposals: proposals?,
-        })
+        };
+        // prefilled transactions are spliced by index when reconstructing the block
+        if !compact_block.has_valid_prefilled_indexes() {
+            cast!(None::<()>)?;
+        }
+        Ok(compact_block)
+    }
+}
+
+impl CompactBlock {
+    /// Total number of transactions in the block, prefilled or referenced by short id
+    pub fn txs_len(&self) -> usize {
+        self.prefilled_transactions.len() + self.short_ids.len()
+    }
+
+    /// Prefilled indexes must be strictly increasing and point inside the block
+    pub fn has_valid_prefilled_indexes(&self) -> bool {
+        let txs_len = self.txs_len();
+        self.prefilled_transactions
+            .iter()
+            .try_fold(None, |prev: Option<usize>, pt| {
+                if pt.index >= txs_len || prev.map_or(false, |prev| pt.index <= prev) {
+                    None
+                } else {
+                    Some(Some(pt.index))
+                }
+            })
+            .is_some()
+    }
+}
+
+/// Select the transactions to prefill when announcing `block`.
+///
+/// The cellbase is always prefilled, any other transaction is prefilled when
+/// `is_recent` reports it was received too recently to be in peers' pools,
+/// which saves peers a `GetBlockTransactions` round trip.
+pub fn prefilled_transactions_indexes<F>(block: &Block, is_recent: F) -> HashSet<usize>
+where
+    F: Fn(&Transaction) -> bool,
+{
+    block
+        .transactions()
+        .iter()
+        .enumerate()
+        .filter(|(index, tx)| *index == 0 || is_recent(tx))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_core::block::BlockBuilder;
+    use ckb_core::header::HeaderBuilder;
+    use ckb_core::transaction::{CellInput, TransactionBuilder};
+
+    fn new_tx(number: u64) -> Transaction {
+        TransactionBuilder::default()
+            .input(CellInput::new_cellbase_input(number))
+            .build()
+    }
+
+    fn new_compact_block(indexes: &[usize], short_ids_len: usize) -> CompactBlock {
+        CompactBlock {
+            header: HeaderBuilder::default().build(),
+            uncles: Vec::new(),
+            nonce: 0,
+            short_ids: vec![[0u8; 6]; short_ids_len],
+            prefilled_transactions: indexes
+                .iter()
+                .map(|index| IndexTransaction {
+                    index: *index,
+                    transaction: new_tx(*index as u64),
+                })
+                .collect(),
+            proposals: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_prefilled_indexes_validation() {
+        assert!(new_compact_block(&[0, 2], 1).has_valid_prefilled_indexes());
+        assert!(!new_compact_block(&[0, 3], 1).has_valid_prefilled_indexes());
+        assert!(!new_compact_block(&[2, 0], 1).has_valid_prefilled_indexes());
+        assert!(!new_compact_block(&[0, 0], 1).has_valid_prefilled_indexes());
+    }
+
+    #[test]
+    fn test_prefilled_transactions_indexes() {
+        let recent = new_tx(2);
+        let block = BlockBuilder::default()
+            .transactions(vec![new_tx(0), new_tx(1), recent.clone()])
+            .build();
+        let indexes = prefilled_transactions_indexes(&block, |tx| tx.hash() == recent.hash());
+        assert_eq!(indexes, [0, 2].iter().cloned().collect());
     }
 }
@@ -1,8 +1,10 @@
 use super::compact_block::CompactBlock;
+use crate::relayer::send_queue::SendPriority;
 use crate::relayer::Relayer;
 use ckb_core::{header::Header, BlockNumber};
 use ckb_network::{CKBProtocolContext, PeerIndex};
 use ckb_protocol::{CompactBlock as FbsCompactBlock, RelayMessage};
+use ckb_shared::block_stage::BlockStage;
 use ckb_shared::shared::Shared;
 use ckb_shared::store::ChainStore;
 use ckb_traits::{BlockMedianTimeContext, ChainProvider};
@@ -14,6 +16,7 @@ use log::debug;
 use numext_fixed_hash::H256;
 use std::convert::TryInto;
 use std::sync::Arc;
+use std::time::Instant;
 
 pub struct CompactBlockProcess<'a, CS> {
     message: &'a FbsCompactBlock<'a>,
@@ -38,8 +41,11 @@ impl<'a, CS: ChainStore> CompactBlockProcess<'a, CS> {
     }
 
     pub fn execute(self) -> Result<(), FailureError> {
+        let started_at = Instant::now();
         let compact_block: CompactBlock = (*self.message).try_into()?;
         let block_hash = compact_block.header.hash();
+        let block_stage_metrics = self.relayer.shared.shared().block_stage_metrics();
+        block_stage_metrics.record(BlockStage::Decode, &block_hash, started_at.elapsed());
         if let Some(parent_header_view) = self
             .relayer
             .shared
@@ -63,7 +69,7 @@ impl<'a, CS: ChainStore> CompactBlockProcess<'a, CS> {
             self.relayer.shared.send_getheaders_to_peer(
                 self.nc,
                 self.peer,
-                self.relayer.shared.chain_state().lock().tip_header(),
+                self.relayer.shared.chain_state().tip_header(),
             );
             return Ok(());
         }
@@ -85,24 +91,36 @@ impl<'a, CS: ChainStore> CompactBlockProcess<'a, CS> {
                         shared: self.relayer.shared.shared(),
                     },
                     Arc::clone(&self.relayer.shared.consensus().pow_engine()),
-                );
-                match header_verifier.verify(&resolver) {
+                )
+                .allowed_future_blocktime(self.relayer.shared.max_future_block_time())
+                .dao_activation_number(self.relayer.shared.consensus().dao_activation_number());
+                let header_verify_ret = {
+                    let _span = block_stage_metrics.span(BlockStage::HeaderVerify, &block_hash);
+                    header_verifier.verify(&resolver)
+                };
+                match header_verify_ret {
                     Ok(_) => {
                         let ret = {
-                            let chain_state = self.relayer.shared.chain_state().lock();
+                            let chain_state = self.relayer.shared.chain_state();
                             self.relayer.request_proposal_txs(
                                 &chain_state,
-                                self.nc,
                                 self.peer,
                                 &compact_block,
                             );
+                            let tx_pool = self.relayer.shared.tx_pool().lock();
                             self.relayer
-                                .reconstruct_block(&chain_state, &compact_block, Vec::new())
+                                .reconstruct_block(&tx_pool, &compact_block, Vec::new())
                         };
                         match ret {
                             Ok(block) => {
-                                self.relayer
-                                    .accept_block(self.nc, self.peer, &Arc::new(block))
+                                if let Err(err) =
+                                    self.relayer
+                                        .accept_block(self.nc, self.peer, &Arc::new(block))
+                                {
+                                    if err.is_misbehavior() {
+                                        return Err(err.into());
+                                    }
+                                }
                             }
                             Err(missing) => {
                                 missing_indexes = missing;
@@ -117,6 +135,10 @@ impl<'a, CS: ChainStore> CompactBlockProcess<'a, CS> {
                 }
             } else {
                 debug!(target: "relay", "Already processed compact block {}", block_hash);
+                if pending_compact_blocks.contains_key(&block_hash) {
+                    self.relayer
+                        .add_proposal_alternates(self.peer, &compact_block);
+                }
             }
         }
         if !missing_indexes.is_empty() {
@@ -130,8 +152,8 @@ impl<'a, CS: ChainStore> CompactBlockProcess<'a, CS> {
                     .collect::<Vec<_>>(),
             );
             fbb.finish(message, None);
-            self.nc
-                .send_message_to(self.peer, fbb.finished_data().into());
+            self.relayer
+                .send_message_to(SendPriority::High, self.peer, fbb.finished_data().into());
         }
         Ok(())
     }
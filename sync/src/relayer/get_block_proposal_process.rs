@@ -1,5 +1,6 @@
+use crate::relayer::send_queue::SendPriority;
 use crate::relayer::Relayer;
-use ckb_network::{CKBProtocolContext, PeerIndex};
+use ckb_network::PeerIndex;
 use ckb_protocol::{cast, GetBlockProposal, RelayMessage};
 use ckb_shared::store::ChainStore;
 use failure::Error as FailureError;
@@ -9,20 +10,13 @@ use std::convert::TryInto;
 pub struct GetBlockProposalProcess<'a, CS> {
     message: &'a GetBlockProposal<'a>,
     relayer: &'a Relayer<CS>,
-    nc: &'a CKBProtocolContext,
     peer: PeerIndex,
 }
 
 impl<'a, CS: ChainStore> GetBlockProposalProcess<'a, CS> {
-    pub fn new(
-        message: &'a GetBlockProposal,
-        relayer: &'a Relayer<CS>,
-        nc: &'a CKBProtocolContext,
-        peer: PeerIndex,
-    ) -> Self {
+    pub fn new(message: &'a GetBlockProposal, relayer: &'a Relayer<CS>, peer: PeerIndex) -> Self {
         GetBlockProposalProcess {
             message,
-            nc,
             relayer,
             peer,
         }
@@ -33,8 +27,7 @@ impl<'a, CS: ChainStore> GetBlockProposalProcess<'a, CS> {
         let proposals = cast!(self.message.proposals())?;
 
         let transactions = {
-            let chain_state = self.relayer.shared.chain_state().lock();
-            let tx_pool = chain_state.tx_pool();
+            let tx_pool = self.relayer.shared.tx_pool().lock();
 
             let proposals = proposals
                 .iter()
@@ -59,8 +52,8 @@ impl<'a, CS: ChainStore> GetBlockProposalProcess<'a, CS> {
         let message = RelayMessage::build_block_proposal(fbb, &transactions);
         fbb.finish(message, None);
 
-        self.nc
-            .send_message_to(self.peer, fbb.finished_data().into());
+        self.relayer
+            .send_message_to(SendPriority::High, self.peer, fbb.finished_data().into());
         Ok(())
     }
 }
@@ -1,5 +1,7 @@
-use crate::relayer::Relayer;
-use ckb_network::{CKBProtocolContext, PeerIndex};
+use crate::relayer::send_queue::SendPriority;
+use crate::relayer::{Relayer, MAX_BLOCK_TRANSACTIONS_CHUNK_SIZE};
+use ckb_core::transaction::Transaction;
+use ckb_network::PeerIndex;
 use ckb_protocol::{cast, GetBlockTransactions, RelayMessage};
 use ckb_shared::store::ChainStore;
 use failure::Error as FailureError;
@@ -10,7 +12,6 @@ use std::convert::TryInto;
 pub struct GetBlockTransactionsProcess<'a, CS> {
     message: &'a GetBlockTransactions<'a>,
     relayer: &'a Relayer<CS>,
-    nc: &'a CKBProtocolContext,
     peer: PeerIndex,
 }
 
@@ -18,12 +19,10 @@ impl<'a, CS: ChainStore> GetBlockTransactionsProcess<'a, CS> {
     pub fn new(
         message: &'a GetBlockTransactions,
         relayer: &'a Relayer<CS>,
-        nc: &'a CKBProtocolContext,
         peer: PeerIndex,
     ) -> Self {
         GetBlockTransactionsProcess {
             message,
-            nc,
             relayer,
             peer,
         }
@@ -42,14 +41,79 @@ impl<'a, CS: ChainStore> GetBlockTransactionsProcess<'a, CS> {
                 .filter_map(|i| block.transactions().get(*i as usize).cloned())
                 .map(Into::into)
                 .collect::<Vec<_>>();
-            let fbb = &mut FlatBufferBuilder::new();
-            let message = RelayMessage::build_block_transactions(fbb, &block_hash, &transactions);
-            fbb.finish(message, None);
+            let chunks = split_into_chunks(transactions, MAX_BLOCK_TRANSACTIONS_CHUNK_SIZE);
+            let total = chunks.len() as u32;
+            for (sequence, chunk) in chunks.iter().enumerate() {
+                let fbb = &mut FlatBufferBuilder::new();
+                let message = RelayMessage::build_block_transactions_chunk(
+                    fbb,
+                    &block_hash,
+                    chunk,
+                    sequence as u32,
+                    total,
+                );
+                fbb.finish(message, None);
 
-            self.nc
-                .send_message_to(self.peer, fbb.finished_data().into());
+                self.relayer.send_message_to(
+                    SendPriority::High,
+                    self.peer,
+                    fbb.finished_data().into(),
+                );
+            }
         }
 
         Ok(())
     }
 }
+
+/// Split transactions into chunks of at most `max_chunk_size` serialized bytes, a
+/// transaction larger than the limit gets a chunk of its own. There is always at
+/// least one chunk, so an empty response is still answered.
+fn split_into_chunks(
+    transactions: Vec<Transaction>,
+    max_chunk_size: usize,
+) -> Vec<Vec<Transaction>> {
+    let mut chunks: Vec<Vec<Transaction>> = vec![Vec::new()];
+    let mut chunk_size = 0;
+    for tx in transactions {
+        let tx_size = tx.serialized_size();
+        if chunk_size + tx_size > max_chunk_size && chunk_size > 0 {
+            chunks.push(Vec::new());
+            chunk_size = 0;
+        }
+        chunk_size += tx_size;
+        chunks.last_mut().expect("chunks is not empty").push(tx);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_core::transaction::{CellInput, TransactionBuilder};
+
+    #[test]
+    fn test_split_into_chunks() {
+        let txs: Vec<Transaction> = (0..5)
+            .map(|i| {
+                TransactionBuilder::default()
+                    .input(CellInput::new_cellbase_input(i))
+                    .build()
+            })
+            .collect();
+        let tx_size = txs[0].serialized_size();
+
+        assert_eq!(split_into_chunks(Vec::new(), tx_size).len(), 1);
+        assert_eq!(split_into_chunks(txs.clone(), tx_size * 5).len(), 1);
+
+        let chunks = split_into_chunks(txs.clone(), tx_size * 2);
+        assert_eq!(
+            chunks.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![2, 2, 1]
+        );
+        assert_eq!(chunks.concat(), txs);
+
+        // a transaction larger than the limit is sent alone
+        assert_eq!(split_into_chunks(txs, tx_size - 1).len(), 5);
+    }
+}
@@ -1,46 +1,141 @@
+mod block_announcement_process;
 mod block_proposal_process;
 mod block_transactions_process;
 pub mod compact_block;
 mod compact_block_process;
 mod get_block_proposal_process;
 mod get_block_transactions_process;
+mod send_compact_block_process;
+pub mod send_queue;
 mod transaction_process;
 
+use self::block_announcement_process::BlockAnnouncementProcess;
 use self::block_proposal_process::BlockProposalProcess;
-use self::block_transactions_process::BlockTransactionsProcess;
-use self::compact_block::CompactBlock;
+use self::block_transactions_process::{BlockTransactionsChunks, BlockTransactionsProcess};
+use self::compact_block::{prefilled_transactions_indexes, CompactBlock};
 use self::compact_block_process::CompactBlockProcess;
 use self::get_block_proposal_process::GetBlockProposalProcess;
 use self::get_block_transactions_process::GetBlockTransactionsProcess;
-use self::transaction_process::TransactionProcess;
+use self::send_compact_block_process::SendCompactBlockProcess;
+use self::send_queue::{SendPriority, SendQueue, SendTarget};
+use self::transaction_process::{RelayedTx, TransactionProcess};
+use crate::config::RelayConfig;
 use crate::relayer::compact_block::ShortTransactionID;
+use crate::synchronizer::block_pool::OrphanBlockPool;
 use crate::types::{Peers, SyncSharedState};
-use crate::BAD_MESSAGE_BAN_TIME;
+use crate::{BAD_MESSAGE_BAN_TIME, RELAY_FEATURE_STEM};
+use bytes::Bytes;
 use ckb_chain::chain::ChainController;
 use ckb_core::block::{Block, BlockBuilder};
 use ckb_core::transaction::{ProposalShortId, Transaction};
 use ckb_core::uncle::UncleBlock;
+use ckb_core::{BlockNumber, Cycle};
 use ckb_network::{CKBProtocolContext, CKBProtocolHandler, PeerIndex};
 use ckb_protocol::{
     cast, get_root, short_transaction_id, short_transaction_id_keys, RelayMessage, RelayPayload,
 };
+use ckb_shared::block_stage::BlockStage;
 use ckb_shared::chain_state::ChainState;
+use ckb_shared::error::SharedError;
 use ckb_shared::store::ChainStore;
+use ckb_shared::tx_pool::{PoolError, TxPool};
+use ckb_shared::tx_verify_queue::TxVerification;
 use ckb_util::Mutex;
+use ckb_verification::Error as VerifyError;
 use failure::Error as FailureError;
+use failure::Fail;
 use faketime::unix_time_as_millis;
 use flatbuffers::FlatBufferBuilder;
 use fnv::{FnvHashMap, FnvHashSet};
-use log::{debug, info};
+use log::{debug, info, warn};
 use lru_cache::LruCache;
 use numext_fixed_hash::H256;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use std::collections::hash_map::Entry;
 use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::Duration;
 
 pub const TX_PROPOSAL_TOKEN: u64 = 0;
+pub const TX_REBROADCAST_TOKEN: u64 = 1;
+pub const SEND_QUEUE_FLUSH_TOKEN: u64 = 2;
+pub const PENDING_COMPACT_BLOCKS_TOKEN: u64 = 3;
+pub const ORPHAN_EXPIRY_TOKEN: u64 = 4;
 pub const MAX_RELAY_PEERS: usize = 128;
 pub const TX_FILTER_SIZE: usize = 50000;
+pub const INVALID_BLOCKS_SIZE: usize = 1024;
+// Window used to count transaction announcements for the per-peer rate limit
+pub const TX_ANNOUNCE_RATE_WINDOW: u64 = 1000;
+// Locally submitted transactions are rebroadcast with exponential backoff, starting
+// from this interval, until they leave the pool or reach the TTL
+pub const TX_REBROADCAST_INTERVAL: u64 = 30 * 1000;
+pub const TX_REBROADCAST_TTL: u64 = 24 * 60 * 60 * 1000;
+// Transactions received within this window are prefilled in announced compact blocks,
+// they are unlikely to have reached peers' pools yet
+pub const RECENT_TX_PREFILL_WINDOW: u64 = 5 * 1000;
+pub const RECENT_TXS_SIZE: usize = 1024;
+// Number of peers asked to push new blocks to us as compact blocks without announcement
+pub const MAX_HIGH_BANDWIDTH_PEERS: usize = 3;
+// BlockTransactions responses are split into chunks of this many serialized bytes,
+// well below the p2p frame limit
+pub const MAX_BLOCK_TRANSACTIONS_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+// Partial BlockTransactions responses buffered for a peer are capped at this many times the
+// maximum block size
+pub const MAX_BLOCK_TRANSACTIONS_BUFFERED_BLOCKS: usize = 2;
+// Transaction relays are spread over several flushes, so blocks and proposals queued
+// meanwhile go out first
+pub const MAX_LOW_PRIORITY_SENDS_PER_FLUSH: usize = 256;
+// Proposal transactions not received in time are requested from another peer
+pub const PROPOSAL_REQUEST_TIMEOUT: u64 = 5 * 1000;
+pub const MAX_RELAY_ORPHAN_BLOCKS: usize = 64;
+// Stem hops requested by peers are capped, so a transaction can't be held in the stem phase
+// for long
+pub const MAX_STEM_HOPS: u8 = 10;
+// A transaction forwarded in the stem phase is broadcast by ourselves if we don't see it
+// being broadcast within this time
+pub const STEM_EMBARGO_TIMEOUT: u64 = 30 * 1000;
+
+#[derive(Debug, Fail)]
+pub enum AcceptBlockError {
+    /// The block is stored, marked invalid, or being downloaded by the synchronizer
+    #[fail(display = "AlreadyKnown")]
+    AlreadyKnown,
+    /// The parent block is not stored yet, the block is kept as an orphan
+    #[fail(display = "ParentUnknown")]
+    ParentUnknown,
+    /// The block fails verification, the peer sending it is responsible
+    #[fail(display = "Invalid: {}", _0)]
+    Invalid(FailureError),
+    /// The block can not be stored because of a local failure, it may be accepted later
+    #[fail(display = "Internal: {}", _0)]
+    Internal(FailureError),
+}
+
+impl AcceptBlockError {
+    pub(crate) fn from_process_block_error(err: FailureError) -> Self {
+        if let Some(verify_error) = err.downcast_ref::<VerifyError>() {
+            return match verify_error {
+                VerifyError::UnknownParent(_) => AcceptBlockError::ParentUnknown,
+                _ => AcceptBlockError::Invalid(err),
+            };
+        }
+        match err.downcast_ref::<SharedError>() {
+            Some(SharedError::InvalidTransaction(_)) | Some(SharedError::InvalidParentBlock) => {
+                AcceptBlockError::Invalid(err)
+            }
+            _ => AcceptBlockError::Internal(err),
+        }
+    }
+
+    /// Whether the peer sending the block should be punished
+    pub fn is_misbehavior(&self) -> bool {
+        match self {
+            AcceptBlockError::Invalid(_) => true,
+            _ => false,
+        }
+    }
+}
 
 pub struct Relayer<CS> {
     chain: ChainController,
@@ -48,6 +143,7 @@ pub struct Relayer<CS> {
     state: Arc<RelayState>,
     // TODO refactor shared Peers struct with Synchronizer
     peers: Arc<Peers>,
+    pub(crate) config: Arc<RelayConfig>,
 }
 
 impl<CS: ChainStore> Clone for Relayer<CS> {
@@ -57,6 +153,7 @@ impl<CS: ChainStore> Clone for Relayer<CS> {
             shared: Arc::clone(&self.shared),
             state: Arc::clone(&self.state),
             peers: Arc::clone(&self.peers),
+            config: Arc::clone(&self.config),
         }
     }
 }
@@ -66,16 +163,18 @@ impl<CS: ChainStore> Relayer<CS> {
         chain: ChainController,
         shared: Arc<SyncSharedState<CS>>,
         peers: Arc<Peers>,
+        config: RelayConfig,
     ) -> Self {
         Relayer {
             chain,
             shared,
             state: Arc::new(RelayState::default()),
             peers,
+            config: Arc::new(config),
         }
     }
 
-    fn try_process(
+    pub(crate) fn try_process(
         &self,
         nc: &CKBProtocolContext,
         peer: PeerIndex,
@@ -92,6 +191,10 @@ impl<CS: ChainStore> Relayer<CS> {
                 .execute()?;
             }
             RelayPayload::RelayTransaction => {
+                if is_block_relay_only(nc, peer) {
+                    debug!(target: "relay", "ignore transaction from block relay only peer {}", peer);
+                    return Ok(());
+                }
                 TransactionProcess::new(
                     &cast!(message.payload_as_relay_transaction())?,
                     self,
@@ -104,7 +207,6 @@ impl<CS: ChainStore> Relayer<CS> {
                 GetBlockTransactionsProcess::new(
                     &cast!(message.payload_as_get_block_transactions())?,
                     self,
-                    nc,
                     peer,
                 )
                 .execute()?;
@@ -122,7 +224,6 @@ impl<CS: ChainStore> Relayer<CS> {
                 GetBlockProposalProcess::new(
                     &cast!(message.payload_as_get_block_proposal())?,
                     self,
-                    nc,
                     peer,
                 )
                 .execute()?;
@@ -131,6 +232,23 @@ impl<CS: ChainStore> Relayer<CS> {
                 BlockProposalProcess::new(&cast!(message.payload_as_block_proposal())?, self)
                     .execute()?;
             }
+            RelayPayload::SendCompactBlock => {
+                SendCompactBlockProcess::new(
+                    &cast!(message.payload_as_send_compact_block())?,
+                    self,
+                    peer,
+                )
+                .execute()?;
+            }
+            RelayPayload::BlockAnnouncement => {
+                BlockAnnouncementProcess::new(
+                    &cast!(message.payload_as_block_announcement())?,
+                    self,
+                    nc,
+                    peer,
+                )
+                .execute()?;
+            }
             RelayPayload::NONE => {
                 cast!(None)?;
             }
@@ -147,62 +265,254 @@ impl<CS: ChainStore> Relayer<CS> {
     pub fn request_proposal_txs(
         &self,
         chain_state: &ChainState<CS>,
-        nc: &CKBProtocolContext,
         peer: PeerIndex,
         block: &CompactBlock,
     ) {
+        // the transactions received would be refused
+        if self.shared.tx_pool().lock().is_verifying_full() {
+            debug!(target: "relay", "too many transactions are being verified, skip requesting proposals");
+            return;
+        }
+        let deadline = unix_time_as_millis() + PROPOSAL_REQUEST_TIMEOUT;
+        let block_number = block.header.number();
         let mut inflight = self.state.inflight_proposals.lock();
-        let unknown_ids = block
+        let mut unknown_ids = Vec::new();
+        for id in block
             .proposals
             .iter()
             .chain(block.uncles.iter().flat_map(UncleBlock::proposals))
-            .filter(|x| !chain_state.contains_proposal_id(x) && inflight.insert(**x))
-            .cloned()
-            .collect::<Vec<_>>();
+            .filter(|id| !chain_state.contains_proposal_id(id))
+        {
+            match inflight.entry(*id) {
+                Entry::Occupied(mut entry) => entry.get_mut().add_alternate(peer),
+                Entry::Vacant(entry) => {
+                    entry.insert(InflightProposal::new(peer, block_number, deadline));
+                    unknown_ids.push(*id);
+                }
+            }
+        }
 
         if !unknown_ids.is_empty() {
             let fbb = &mut FlatBufferBuilder::new();
-            let message =
-                RelayMessage::build_get_block_proposal(fbb, block.header.number(), &unknown_ids);
+            let message = RelayMessage::build_get_block_proposal(fbb, block_number, &unknown_ids);
+            fbb.finish(message, None);
+
+            self.send_message_to(SendPriority::High, peer, fbb.finished_data().into());
+        }
+    }
+
+    // The peer relays a block we are already fetching proposals for, remember it as a
+    // fallback in case the requested peer stalls
+    pub(crate) fn add_proposal_alternates(&self, peer: PeerIndex, block: &CompactBlock) {
+        let mut inflight = self.state.inflight_proposals.lock();
+        for id in block
+            .proposals
+            .iter()
+            .chain(block.uncles.iter().flat_map(UncleBlock::proposals))
+        {
+            if let Some(proposal) = inflight.get_mut(id) {
+                proposal.add_alternate(peer);
+            }
+        }
+    }
+
+    // Drop fulfilled proposal requests, re-request expired ones from an alternate peer
+    fn retry_inflight_proposals(&self) {
+        let now = unix_time_as_millis();
+        let mut retries: FnvHashMap<(PeerIndex, BlockNumber), Vec<ProposalShortId>> =
+            FnvHashMap::default();
+        {
+            let tx_pool = self.shared.tx_pool().lock();
+            let chain_state = self.shared.chain_state();
+            let mut inflight = self.state.inflight_proposals.lock();
+            inflight.retain(|id, proposal| {
+                if chain_state.contains_proposal_id(id) || tx_pool.get_tx(id).is_some() {
+                    return false;
+                }
+                if now < proposal.deadline {
+                    return true;
+                }
+                match proposal.switch_to_alternate(now + PROPOSAL_REQUEST_TIMEOUT) {
+                    Some(peer) => {
+                        retries
+                            .entry((peer, proposal.block_number))
+                            .or_insert_with(Vec::new)
+                            .push(*id);
+                        true
+                    }
+                    None => false,
+                }
+            });
+        }
+
+        for ((peer, block_number), ids) in retries {
+            debug!(target: "relay", "re-request {} proposal transactions from peer {}", ids.len(), peer);
+            let fbb = &mut FlatBufferBuilder::new();
+            let message = RelayMessage::build_get_block_proposal(fbb, block_number, &ids);
             fbb.finish(message, None);
+            self.send_message_to(SendPriority::High, peer, fbb.finished_data().into());
+        }
+    }
 
-            nc.send_message_to(peer, fbb.finished_data().into());
+    // Cheap checks before verification, the same block is usually relayed by many peers
+    // simultaneously, or is being downloaded by the synchronizer already
+    fn is_block_known(&self, block_hash: &H256) -> bool {
+        if self.shared.block_header(block_hash).is_some() {
+            return true;
         }
+        if self.state.invalid_blocks.lock().contains_key(block_hash) {
+            return true;
+        }
+        self.peers
+            .blocks_inflight
+            .read()
+            .values()
+            .any(|inflight| inflight.contains(block_hash))
     }
 
-    pub fn accept_block(&self, nc: &CKBProtocolContext, peer: PeerIndex, block: &Arc<Block>) {
+    pub(crate) fn mark_recent_tx(&self, tx_hash: H256) {
+        self.state
+            .recent_txs
+            .lock()
+            .insert(tx_hash, unix_time_as_millis());
+    }
+
+    fn compact_block_prefilled_indexes(&self, block: &Block) -> HashSet<usize> {
+        let now = unix_time_as_millis();
+        let recent_txs = self.state.recent_txs.lock();
+        prefilled_transactions_indexes(block, |tx| {
+            recent_txs.get(&tx.hash()).map_or(false, |received_at| {
+                now.saturating_sub(*received_at) < RECENT_TX_PREFILL_WINDOW
+            })
+        })
+    }
+
+    /// Accept the block and the orphan blocks waiting for it
+    pub fn accept_block(
+        &self,
+        nc: &CKBProtocolContext,
+        peer: PeerIndex,
+        block: &Arc<Block>,
+    ) -> Result<(), AcceptBlockError> {
+        self.accept_single_block(nc, peer, block)?;
+
+        let orphan_blocks = self
+            .state
+            .orphan_blocks
+            .remove_blocks_by_parent(&block.header().hash());
+        for orphan_block in orphan_blocks {
+            let orphan_block = Arc::new(orphan_block);
+            if let Err(err) = self.accept_single_block(nc, peer, &orphan_block) {
+                debug!(
+                    target: "relay",
+                    "accept orphan block {:#x} error {}",
+                    orphan_block.header().hash(),
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn accept_single_block(
+        &self,
+        nc: &CKBProtocolContext,
+        peer: PeerIndex,
+        block: &Arc<Block>,
+    ) -> Result<(), AcceptBlockError> {
+        if self.is_block_known(&block.header().hash()) {
+            debug!(target: "relay", "[block_relay] block {:#x} already known", block.header().hash());
+            return Err(AcceptBlockError::AlreadyKnown);
+        }
+        if self
+            .shared
+            .block_header(&block.header().parent_hash())
+            .is_none()
+        {
+            debug!(target: "relay", "[block_relay] block {:#x} parent unknown", block.header().hash());
+            if self.state.orphan_blocks.len() < MAX_RELAY_ORPHAN_BLOCKS {
+                self.state.orphan_blocks.insert(block.as_ref().clone());
+            }
+            return Err(AcceptBlockError::ParentUnknown);
+        }
+
         let ret = self.chain.process_block(Arc::clone(&block));
 
         if ret.is_ok() {
             debug!(target: "relay", "[block_relay] relayer accept_block {} {}", block.header().hash(), unix_time_as_millis());
             let block_hash = block.header().hash();
             self.shared.remove_header_view(&block_hash);
-            let fbb = &mut FlatBufferBuilder::new();
-            let message = RelayMessage::build_compact_block(fbb, block, &HashSet::new());
-            fbb.finish(message, None);
-
-            let mut known_blocks = self.peers.known_blocks.lock();
-            let selected_peers: Vec<PeerIndex> = nc
-                .connected_peers()
-                .into_iter()
-                .filter(|target_peer| {
-                    known_blocks.insert(*target_peer, block_hash.clone()) && (peer != *target_peer)
+            let _span = self
+                .shared
+                .shared()
+                .block_stage_metrics()
+                .span(BlockStage::Announce, &block_hash);
+            let selected_peers: Vec<PeerIndex> = {
+                let mut known_blocks = self.peers.known_blocks.lock();
+                nc.connected_peers()
+                    .into_iter()
+                    .filter(|target_peer| {
+                        known_blocks.insert(*target_peer, block_hash.clone())
+                            && (peer != *target_peer)
+                    })
+                    .take(MAX_RELAY_PEERS)
+                    .collect()
+            };
+            // peers which never told us their mode are treated as high bandwidth ones
+            let (high_bandwidth_peers, low_bandwidth_peers): (Vec<PeerIndex>, Vec<PeerIndex>) = {
+                let compact_block_modes = self.state.compact_block_modes.lock();
+                selected_peers.into_iter().partition(|target_peer| {
+                    compact_block_modes
+                        .get(target_peer)
+                        .cloned()
+                        .unwrap_or(true)
                 })
-                .take(MAX_RELAY_PEERS)
-                .collect();
+            };
 
-            // TODO: use filter broadcast
-            for target_peer in selected_peers {
-                nc.send_message_to(target_peer, fbb.finished_data().into());
+            if !high_bandwidth_peers.is_empty() {
+                let fbb = &mut FlatBufferBuilder::new();
+                let prefilled_indexes = self.compact_block_prefilled_indexes(block);
+                let message = RelayMessage::build_compact_block(fbb, block, &prefilled_indexes);
+                fbb.finish(message, None);
+                self.broadcast_to(
+                    SendPriority::High,
+                    high_bandwidth_peers,
+                    fbb.finished_data().into(),
+                );
+            }
+            if !low_bandwidth_peers.is_empty() {
+                let fbb = &mut FlatBufferBuilder::new();
+                let message = RelayMessage::build_block_announcement(fbb, &block_hash);
+                fbb.finish(message, None);
+                self.broadcast_to(
+                    SendPriority::High,
+                    low_bandwidth_peers,
+                    fbb.finished_data().into(),
+                );
             }
+            Ok(())
         } else {
             debug!(target: "relay", "accept_block verify error {:?}", ret);
+            let err = AcceptBlockError::from_process_block_error(ret.unwrap_err());
+            match err {
+                AcceptBlockError::Invalid(_) => {
+                    self.state
+                        .invalid_blocks
+                        .lock()
+                        .insert(block.header().hash(), ());
+                }
+                AcceptBlockError::Internal(ref internal) => {
+                    warn!(target: "relay", "accept_block {:#x} internal error {}", block.header().hash(), internal);
+                }
+                _ => {}
+            }
+            Err(err)
         }
     }
 
     pub fn reconstruct_block(
         &self,
-        chain_state: &ChainState<CS>,
+        tx_pool: &TxPool,
         compact_block: &CompactBlock,
         transactions: Vec<Transaction>,
     ) -> Result<Block, Vec<usize>> {
@@ -217,36 +527,32 @@ impl<CS: ChainStore> Relayer<CS> {
             })
             .collect();
 
-        {
-            let tx_pool = chain_state.tx_pool();
-            let iter = tx_pool.staging_txs_iter().filter_map(|entry| {
-                let short_id = short_transaction_id(key0, key1, &entry.transaction.witness_hash());
-                if compact_block.short_ids.contains(&short_id) {
-                    Some((short_id, entry.transaction.clone()))
-                } else {
-                    None
-                }
-            });
-            txs_map.extend(iter);
-        }
+        let iter = tx_pool.staging_txs_iter().filter_map(|entry| {
+            let short_id = short_transaction_id(key0, key1, &entry.transaction.witness_hash());
+            if compact_block.short_ids.contains(&short_id) {
+                Some((short_id, entry.transaction.clone()))
+            } else {
+                None
+            }
+        });
+        txs_map.extend(iter);
 
-        let txs_len = compact_block.prefilled_transactions.len() + compact_block.short_ids.len();
-        let mut block_transactions: Vec<Option<Transaction>> = Vec::with_capacity(txs_len);
+        let mut block_transactions: Vec<Option<Transaction>> = vec![None; compact_block.txs_len()];
 
-        let short_ids_iter = &mut compact_block.short_ids.iter();
-        // fill transactions gap
-        compact_block.prefilled_transactions.iter().for_each(|pt| {
-            let gap = pt.index - block_transactions.len();
-            if gap > 0 {
-                short_ids_iter
-                    .take(gap)
-                    .for_each(|short_id| block_transactions.push(txs_map.remove(short_id)));
+        // splice prefilled transactions at their indexes
+        for pt in &compact_block.prefilled_transactions {
+            if let Some(slot) = block_transactions.get_mut(pt.index) {
+                *slot = Some(pt.transaction.clone());
             }
-            block_transactions.push(Some(pt.transaction.clone()));
-        });
+        }
 
-        // append remain transactions
-        short_ids_iter.for_each(|short_id| block_transactions.push(txs_map.remove(short_id)));
+        // fill the gaps with short id transactions in order
+        let mut short_ids_iter = compact_block.short_ids.iter();
+        for slot in block_transactions.iter_mut().filter(|slot| slot.is_none()) {
+            if let Some(short_id) = short_ids_iter.next() {
+                *slot = txs_map.remove(short_id);
+            }
+        }
 
         let missing = block_transactions.iter().any(Option::is_none);
 
@@ -273,13 +579,12 @@ impl<CS: ChainStore> Relayer<CS> {
         }
     }
 
-    fn prune_tx_proposal_request(&self, nc: &CKBProtocolContext) {
+    fn prune_tx_proposal_request(&self) {
         let mut pending_proposals_request = self.state.pending_proposals_request.lock();
         let mut peer_txs = FnvHashMap::default();
         let mut remove_ids = Vec::new();
         {
-            let chain_state = self.shared.chain_state().lock();
-            let tx_pool = chain_state.tx_pool();
+            let tx_pool = self.shared.tx_pool().lock();
             for (id, peer_indexs) in pending_proposals_request.iter() {
                 if let Some(tx) = tx_pool.get_tx(id) {
                     for peer_index in peer_indexs {
@@ -300,18 +605,292 @@ impl<CS: ChainStore> Relayer<CS> {
             let message =
                 RelayMessage::build_block_proposal(fbb, &txs.into_iter().collect::<Vec<_>>());
             fbb.finish(message, None);
-            nc.send_message_to(peer_index, fbb.finished_data().into());
+            self.send_message_to(SendPriority::High, peer_index, fbb.finished_data().into());
         }
     }
 
+    // A pending compact block is stale once it's stored (usually downloaded by the
+    // synchronizer meanwhile) or the tip has moved past its height, stop waiting for its
+    // transactions. Responses to outstanding GetBlockTransactions requests are ignored
+    // as the block is no longer pending.
+    fn reconcile_pending_compact_blocks(&self) {
+        let tip_number = self.shared.tip_header().number();
+        let pending_hashes: FnvHashSet<H256> = {
+            let mut pending_compact_blocks = self.state.pending_compact_blocks.lock();
+            pending_compact_blocks.retain(|hash, compact_block| {
+                let stale = compact_block.header.number() <= tip_number
+                    || self.shared.block_header(hash).is_some();
+                if stale {
+                    debug!(target: "relay", "drop stale pending compact block {:#x}", hash);
+                }
+                !stale
+            });
+            pending_compact_blocks.keys().cloned().collect()
+        };
+        self.state
+            .block_transactions_chunks
+            .lock()
+            .retain(|(_, hash), _| pending_hashes.contains(hash));
+        self.state.orphan_blocks.remove_stale_blocks(tip_number);
+    }
+
+    fn rebroadcast_local_txs(&self, nc: &CKBProtocolContext) {
+        let now = unix_time_as_millis();
+        let local_txs = self.shared.tx_pool().lock().local_txs();
+
+        let mut expired = Vec::new();
+        let mut alive = FnvHashSet::default();
+        let connected_peers: Vec<PeerIndex> = nc
+            .connected_peers()
+            .into_iter()
+            .filter(|peer| !is_block_relay_only(nc, *peer))
+            .collect();
+        // the transactions sent in the stem phase are left to the stem peers until their
+        // embargoes expire, we only broadcast them if the stem is lost
+        let first_interval = if self.shared.stem_hops() > 0 {
+            STEM_EMBARGO_TIMEOUT + TX_REBROADCAST_INTERVAL
+        } else {
+            TX_REBROADCAST_INTERVAL
+        };
+        let mut tx_rebroadcast = self.state.tx_rebroadcast.lock();
+        let mut known_txs = self.peers.known_txs.lock();
+        for (submitted_at, entry) in local_txs {
+            let id = entry.transaction.proposal_short_id();
+            if now.saturating_sub(submitted_at) > TX_REBROADCAST_TTL {
+                expired.push(id);
+                continue;
+            }
+            alive.insert(id);
+
+            let (next_at, interval) = tx_rebroadcast
+                .entry(id)
+                .or_insert((submitted_at + first_interval, TX_REBROADCAST_INTERVAL));
+            if now < *next_at {
+                continue;
+            }
+            *interval = interval.saturating_mul(2);
+            *next_at = now.saturating_add(*interval);
+
+            let cycles = match entry.cycles {
+                Some(cycles) => cycles,
+                None => continue,
+            };
+            // only peers which haven't heard about the transaction yet
+            let tx_hash = entry.transaction.hash();
+            let selected_peers: Vec<PeerIndex> = connected_peers
+                .iter()
+                .filter(|peer| known_txs.insert(**peer, tx_hash.clone()))
+                .take(MAX_RELAY_PEERS)
+                .cloned()
+                .collect();
+            if selected_peers.is_empty() {
+                continue;
+            }
+
+            debug!(target: "relay", "rebroadcast local tx {:#x} to {} peers", tx_hash, selected_peers.len());
+            let fbb = &mut FlatBufferBuilder::new();
+            let message = RelayMessage::build_transaction(fbb, &entry.transaction, cycles);
+            fbb.finish(message, None);
+            self.broadcast_to(
+                SendPriority::Low,
+                selected_peers,
+                fbb.finished_data().into(),
+            );
+        }
+        tx_rebroadcast.retain(|id, _| alive.contains(id));
+
+        if !expired.is_empty() {
+            let mut tx_pool = self.shared.tx_pool().lock();
+            for id in expired {
+                tx_pool.remove_local_tx(&id);
+            }
+        }
+    }
+
+    /// Broadcast a relay transaction message to connected peers which don't know the
+    /// transaction yet, except `from`
+    pub(crate) fn broadcast_transaction(
+        &self,
+        nc: &CKBProtocolContext,
+        tx_hash: &H256,
+        from: Option<PeerIndex>,
+        data: Bytes,
+    ) {
+        let selected_peers: Vec<PeerIndex> = {
+            let mut known_txs = self.peers.known_txs.lock();
+            nc.connected_peers()
+                .into_iter()
+                .filter(|target_peer| {
+                    !is_block_relay_only(nc, *target_peer)
+                        && known_txs.insert(*target_peer, tx_hash.clone())
+                        && (from != Some(*target_peer))
+                })
+                .take(MAX_RELAY_PEERS)
+                .collect()
+        };
+        self.broadcast_to(SendPriority::Low, selected_peers, data);
+    }
+
+    /// Forward a transaction in the stem phase to one random outbound peer other than `from`
+    /// which supports `RELAY_FEATURE_STEM`,
+    /// `fluff_data` is broadcast by ourselves if the transaction isn't broadcast by anyone
+    /// before the embargo expires. Return false if there is no peer to forward to.
+    pub(crate) fn relay_stem_transaction(
+        &self,
+        nc: &CKBProtocolContext,
+        from: PeerIndex,
+        tx: &Transaction,
+        cycles: Cycle,
+        stem_hops: u8,
+        fluff_data: Bytes,
+    ) -> bool {
+        let outbound_peers: Vec<PeerIndex> = nc
+            .connected_peers()
+            .into_iter()
+            .filter(|peer| {
+                *peer != from
+                    && nc
+                        .get_peer(*peer)
+                        .map(|peer| {
+                            peer.is_outbound()
+                                && !peer.is_block_relay_only
+                                && peer.has_protocol_feature(nc.protocol_id(), RELAY_FEATURE_STEM)
+                        })
+                        .unwrap_or(false)
+            })
+            .collect();
+        let target_peer = match outbound_peers.choose(&mut thread_rng()) {
+            Some(peer) => *peer,
+            None => return false,
+        };
+
+        let tx_hash = tx.hash();
+        self.peers
+            .known_txs
+            .lock()
+            .insert(target_peer, tx_hash.clone());
+        let fbb = &mut FlatBufferBuilder::new();
+        let message = RelayMessage::build_stem_transaction(fbb, tx, cycles, stem_hops);
+        fbb.finish(message, None);
+        self.send_message_to(SendPriority::Low, target_peer, fbb.finished_data().into());
+
+        let deadline = unix_time_as_millis() + STEM_EMBARGO_TIMEOUT;
+        self.state
+            .stem_embargo
+            .lock()
+            .insert(tx_hash, (deadline, fluff_data));
+        true
+    }
+
+    // Broadcast the stem transactions nobody broadcast in time, the next stem peer may
+    // have dropped them
+    fn fluff_expired_stem_txs(&self, nc: &CKBProtocolContext) {
+        let now = unix_time_as_millis();
+        let expired: Vec<(H256, Bytes)> = {
+            let mut stem_embargo = self.state.stem_embargo.lock();
+            let expired_hashes: Vec<H256> = stem_embargo
+                .iter()
+                .filter(|(_, (deadline, _))| *deadline <= now)
+                .map(|(tx_hash, _)| tx_hash.clone())
+                .collect();
+            expired_hashes
+                .into_iter()
+                .filter_map(|tx_hash| {
+                    stem_embargo
+                        .remove(&tx_hash)
+                        .map(|(_, data)| (tx_hash, data))
+                })
+                .collect()
+        };
+        for (tx_hash, data) in expired {
+            debug!(target: "relay", "stem embargo of tx {:#x} expired, broadcast it", tx_hash);
+            self.broadcast_transaction(nc, &tx_hash, None, data);
+        }
+    }
+
+    // Relay the transactions whose scripts are verified, the others are checked again on
+    // the next flush
+    fn finish_verified_txs(&self, nc: &CKBProtocolContext) {
+        let finished: Vec<(RelayedTx, Result<Cycle, PoolError>)> = {
+            let mut verifying_txs = self.state.verifying_txs.lock();
+            let mut finished = Vec::new();
+            let mut index = 0;
+            while index < verifying_txs.len() {
+                match verifying_txs[index].1.try_wait() {
+                    Some(result) => {
+                        let (relayed, _) = verifying_txs.swap_remove(index);
+                        finished.push((relayed, result));
+                    }
+                    None => index += 1,
+                }
+            }
+            finished
+        };
+        for (relayed, result) in finished {
+            relayed.finish(self, nc, result);
+        }
+    }
+
+    /// Queue a message to a single peer, it's written on the next flush of the send queue
+    pub(crate) fn send_message_to(&self, priority: SendPriority, peer: PeerIndex, data: Bytes) {
+        self.state
+            .send_queue
+            .lock()
+            .push(priority, SendTarget::Peer(peer), data);
+    }
+
+    /// Queue a message to several peers, it's written on the next flush of the send queue
+    pub(crate) fn broadcast_to(&self, priority: SendPriority, peers: Vec<PeerIndex>, data: Bytes) {
+        self.state
+            .send_queue
+            .lock()
+            .push(priority, SendTarget::Peers(peers), data);
+    }
+
+    fn flush_send_queue(&self, nc: &CKBProtocolContext) {
+        self.state
+            .send_queue
+            .lock()
+            .flush(nc, MAX_LOW_PRIORITY_SENDS_PER_FLUSH);
+    }
+
     pub fn peers(&self) -> Arc<Peers> {
         Arc::clone(&self.peers)
     }
+
+    // Count one more transaction announced by peer, return false if the peer exceeds
+    // the configured announce rate in current window
+    pub(crate) fn check_tx_announce_rate(&self, peer: PeerIndex) -> bool {
+        let max_rate = self.config.max_tx_announce_rate;
+        if max_rate == 0 {
+            return true;
+        }
+        let now = unix_time_as_millis();
+        let mut tx_announce_counter = self.state.tx_announce_counter.lock();
+        let (window_start, count) = tx_announce_counter.entry(peer).or_insert((now, 0));
+        if now.saturating_sub(*window_start) >= TX_ANNOUNCE_RATE_WINDOW {
+            *window_start = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= max_rate
+    }
+}
+
+// Transactions are not relayed with peers in block relay only slots
+fn is_block_relay_only(nc: &CKBProtocolContext, peer: PeerIndex) -> bool {
+    nc.get_peer(peer)
+        .map(|peer| peer.is_block_relay_only)
+        .unwrap_or(false)
 }
 
 impl<CS: ChainStore> CKBProtocolHandler for Relayer<CS> {
     fn init(&mut self, nc: Box<dyn CKBProtocolContext>) {
         nc.set_notify(Duration::from_millis(100), TX_PROPOSAL_TOKEN);
+        nc.set_notify(Duration::from_secs(5), TX_REBROADCAST_TOKEN);
+        nc.set_notify(Duration::from_millis(100), SEND_QUEUE_FLUSH_TOKEN);
+        nc.set_notify(Duration::from_secs(1), PENDING_COMPACT_BLOCKS_TOKEN);
+        nc.set_notify(Duration::from_secs(60), ORPHAN_EXPIRY_TOKEN);
     }
 
     fn received(
@@ -331,45 +910,749 @@ impl<CS: ChainStore> CKBProtocolHandler for Relayer<CS> {
 
         debug!(target: "relay", "msg {:?}", msg.payload_type());
         self.process(nc.as_ref(), peer_index, msg);
+        self.flush_send_queue(nc.as_ref());
     }
 
-    fn connected(
-        &mut self,
-        _nc: Box<dyn CKBProtocolContext>,
-        peer_index: PeerIndex,
-        version: &str,
-    ) {
+    fn connected(&mut self, nc: Box<dyn CKBProtocolContext>, peer_index: PeerIndex, version: &str) {
         info!(target: "relay", "RelayProtocol({}).connected peer={}", version, peer_index);
-        // do nothing
+        let high_bandwidth = {
+            let mut high_bandwidth_peers = self.state.high_bandwidth_peers.lock();
+            if high_bandwidth_peers.len() < MAX_HIGH_BANDWIDTH_PEERS {
+                high_bandwidth_peers.insert(peer_index);
+                true
+            } else {
+                false
+            }
+        };
+        let fbb = &mut FlatBufferBuilder::new();
+        let message = RelayMessage::build_send_compact_block(fbb, high_bandwidth);
+        fbb.finish(message, None);
+        self.send_message_to(SendPriority::High, peer_index, fbb.finished_data().into());
+        self.flush_send_queue(nc.as_ref());
     }
 
     fn disconnected(&mut self, _nc: Box<dyn CKBProtocolContext>, peer_index: PeerIndex) {
         info!(target: "relay", "RelayProtocol.disconnected peer={}", peer_index);
-        // TODO
+        self.state.tx_announce_counter.lock().remove(&peer_index);
+        self.state.compact_block_modes.lock().remove(&peer_index);
+        self.state.high_bandwidth_peers.lock().remove(&peer_index);
+        self.state
+            .block_transactions_chunks
+            .lock()
+            .retain(|(peer, _), _| *peer != peer_index);
     }
 
     fn notify(&mut self, nc: Box<dyn CKBProtocolContext>, token: u64) {
         match token {
-            TX_PROPOSAL_TOKEN => self.prune_tx_proposal_request(nc.as_ref()),
+            TX_PROPOSAL_TOKEN => {
+                self.prune_tx_proposal_request();
+                self.retry_inflight_proposals();
+            }
+            TX_REBROADCAST_TOKEN => {
+                self.rebroadcast_local_txs(nc.as_ref());
+                self.fluff_expired_stem_txs(nc.as_ref());
+            }
+            SEND_QUEUE_FLUSH_TOKEN => self.finish_verified_txs(nc.as_ref()),
+            PENDING_COMPACT_BLOCKS_TOKEN => self.reconcile_pending_compact_blocks(),
+            ORPHAN_EXPIRY_TOKEN => self.shared.tx_pool().lock().remove_expired_orphans(),
             _ => unreachable!(),
         }
+        self.flush_send_queue(nc.as_ref());
+    }
+}
+
+/// A proposal transaction requested from `peer`, to be re-requested from one of the
+/// `alternates` relaying the same block once `deadline` passes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InflightProposal {
+    pub peer: PeerIndex,
+    pub block_number: BlockNumber,
+    pub deadline: u64,
+    pub alternates: Vec<PeerIndex>,
+}
+
+impl InflightProposal {
+    pub fn new(peer: PeerIndex, block_number: BlockNumber, deadline: u64) -> Self {
+        InflightProposal {
+            peer,
+            block_number,
+            deadline,
+            alternates: Vec::new(),
+        }
+    }
+
+    pub fn add_alternate(&mut self, peer: PeerIndex) {
+        if peer != self.peer && !self.alternates.contains(&peer) {
+            self.alternates.push(peer);
+        }
+    }
+
+    // Move the request to the next alternate peer, return None if there is no one left
+    pub fn switch_to_alternate(&mut self, deadline: u64) -> Option<PeerIndex> {
+        if self.alternates.is_empty() {
+            return None;
+        }
+        self.peer = self.alternates.remove(0);
+        self.deadline = deadline;
+        Some(self.peer)
     }
 }
 
 pub struct RelayState {
     pub pending_compact_blocks: Mutex<FnvHashMap<H256, CompactBlock>>,
-    pub inflight_proposals: Mutex<FnvHashSet<ProposalShortId>>,
+    pub inflight_proposals: Mutex<FnvHashMap<ProposalShortId, InflightProposal>>,
     pub pending_proposals_request: Mutex<FnvHashMap<ProposalShortId, FnvHashSet<PeerIndex>>>,
     pub tx_filter: Mutex<LruCache<H256, ()>>,
+    pub tx_announce_counter: Mutex<FnvHashMap<PeerIndex, (u64, u32)>>,
+    pub invalid_blocks: Mutex<LruCache<H256, ()>>,
+    pub tx_rebroadcast: Mutex<FnvHashMap<ProposalShortId, (u64, u64)>>,
+    pub recent_txs: Mutex<LruCache<H256, u64>>,
+    /// Compact block modes requested by peers, `true` for high bandwidth
+    pub compact_block_modes: Mutex<FnvHashMap<PeerIndex, bool>>,
+    /// Peers asked to push compact blocks to us
+    pub high_bandwidth_peers: Mutex<FnvHashSet<PeerIndex>>,
+    /// Chunks of the `BlockTransactions` responses received so far, by peer and block hash
+    pub block_transactions_chunks: Mutex<FnvHashMap<(PeerIndex, H256), BlockTransactionsChunks>>,
+    pub send_queue: Mutex<SendQueue>,
+    /// Transactions forwarded in the stem phase, with the deadline to broadcast them and the
+    /// relay message to broadcast
+    pub stem_embargo: Mutex<FnvHashMap<H256, (u64, Bytes)>>,
+    /// Relayed transactions whose scripts are being verified
+    pub verifying_txs: Mutex<Vec<(RelayedTx, TxVerification)>>,
+    /// Relayed blocks whose parent is not stored yet
+    pub orphan_blocks: OrphanBlockPool,
 }
 
 impl Default for RelayState {
     fn default() -> Self {
         RelayState {
             pending_compact_blocks: Mutex::new(FnvHashMap::default()),
-            inflight_proposals: Mutex::new(FnvHashSet::default()),
+            inflight_proposals: Mutex::new(FnvHashMap::default()),
             pending_proposals_request: Mutex::new(FnvHashMap::default()),
             tx_filter: Mutex::new(LruCache::new(TX_FILTER_SIZE)),
+            tx_announce_counter: Mutex::new(FnvHashMap::default()),
+            invalid_blocks: Mutex::new(LruCache::new(INVALID_BLOCKS_SIZE)),
+            tx_rebroadcast: Mutex::new(FnvHashMap::default()),
+            recent_txs: Mutex::new(LruCache::new(RECENT_TXS_SIZE)),
+            compact_block_modes: Mutex::new(FnvHashMap::default()),
+            high_bandwidth_peers: Mutex::new(FnvHashSet::default()),
+            block_transactions_chunks: Mutex::new(FnvHashMap::default()),
+            send_queue: Mutex::new(SendQueue::default()),
+            stem_embargo: Mutex::new(FnvHashMap::default()),
+            verifying_txs: Mutex::new(Vec::new()),
+            orphan_blocks: OrphanBlockPool::with_capacity(MAX_RELAY_ORPHAN_BLOCKS),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_chain::chain::ChainBuilder;
+    use ckb_chain_spec::consensus::Consensus;
+    use ckb_core::header::{Header, HeaderBuilder};
+    use ckb_core::script::Script;
+    use ckb_core::transaction::{CellInput, CellOutput, OutPoint, TransactionBuilder};
+    use ckb_core::{capacity_bytes, Capacity};
+    use ckb_db::memorydb::MemoryKeyValueDB;
+    use ckb_network::{
+        multiaddr::ToMultiaddr, Behaviour, Peer, PeerId, PeerInfo, ProtocolId, SessionType,
+        TargetSession,
+    };
+    use ckb_notify::{NotifyController, NotifyService};
+    use ckb_protocol::{get_root, RelayMessage};
+    use ckb_shared::shared::SharedBuilder;
+    use ckb_shared::store::ChainKVStore;
+    use ckb_shared::tx_pool::TxPoolConfig;
+    use ckb_traits::ChainProvider;
+    use std::convert::TryInto;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct DummyNetworkContext {
+        peers: FnvHashMap<PeerIndex, Peer>,
+        sent: Mutex<Vec<(PeerIndex, Bytes)>>,
+    }
+
+    impl DummyNetworkContext {
+        fn add_peer(&mut self, peer: usize, session_type: SessionType, stem: bool) {
+            let mut peer_info = Peer::new(
+                peer.into(),
+                session_type,
+                PeerId::random(),
+                "/ip4/127.0.0.1".to_multiaddr().expect("parse multiaddr"),
+                false,
+            );
+            if stem {
+                peer_info
+                    .protocol_features
+                    .insert(self.protocol_id(), RELAY_FEATURE_STEM);
+            }
+            self.peers.insert(peer.into(), peer_info);
+        }
+
+        // The relay transactions sent since last call, with their stem hops
+        fn take_sent(&self) -> Vec<(PeerIndex, H256, u8)> {
+            self.sent
+                .lock()
+                .drain(..)
+                .map(|(peer, data)| {
+                    let message = get_root::<RelayMessage>(&data)
+                        .payload_as_relay_transaction()
+                        .expect("relay transaction");
+                    let (tx, _): (Transaction, Cycle) = message.try_into().expect("decode");
+                    (peer, tx.hash(), message.stem_hops())
+                })
+                .collect()
+        }
+    }
+
+    impl CKBProtocolContext for DummyNetworkContext {
+        fn set_notify(&self, _interval: Duration, _token: u64) {}
+        fn send_message(&self, _proto_id: ProtocolId, peer_index: PeerIndex, data: Bytes) {
+            self.send_message_to(peer_index, data);
+        }
+        fn send_message_to(&self, peer_index: PeerIndex, data: Bytes) {
+            self.sent.lock().push((peer_index, data));
+        }
+        fn filter_broadcast(&self, target: TargetSession, data: Bytes) {
+            match target {
+                TargetSession::Single(peer) => self.send_message_to(peer, data),
+                TargetSession::Multi(peers) => {
+                    for peer in peers {
+                        self.send_message_to(peer, data.clone());
+                    }
+                }
+                TargetSession::All => {
+                    for peer in self.peers.keys() {
+                        self.send_message_to(*peer, data.clone());
+                    }
+                }
+            }
+        }
+        fn disconnect(&self, _peer_index: PeerIndex) {}
+        fn get_peer(&self, peer_index: PeerIndex) -> Option<Peer> {
+            self.peers.get(&peer_index).cloned()
+        }
+        fn peer_info(&self, peer_index: PeerIndex) -> Option<PeerInfo> {
+            self.get_peer(peer_index)
+                .map(|peer| PeerInfo::new(&peer, None))
+        }
+        fn connected_peers(&self) -> Vec<PeerIndex> {
+            self.peers.keys().cloned().collect()
+        }
+        fn report_peer(&self, _peer_index: PeerIndex, _behaviour: Behaviour) {}
+        fn ban_peer(&self, _peer_index: PeerIndex, _timeout: Duration) {}
+        fn protocol_id(&self) -> ProtocolId {
+            crate::NetworkProtocol::RELAY.into()
+        }
+    }
+
+    fn gen_relayer(stem_hops: u8) -> (Relayer<ChainKVStore<MemoryKeyValueDB>>, NotifyController) {
+        gen_relayer_with_config(stem_hops, Default::default(), Default::default())
+    }
+
+    fn gen_relayer_with_config(
+        stem_hops: u8,
+        tx_pool_config: TxPoolConfig,
+        relay_config: RelayConfig,
+    ) -> (Relayer<ChainKVStore<MemoryKeyValueDB>>, NotifyController) {
+        gen_relayer_with_consensus(stem_hops, tx_pool_config, relay_config, Default::default())
+    }
+
+    fn gen_relayer_with_consensus(
+        stem_hops: u8,
+        tx_pool_config: TxPoolConfig,
+        relay_config: RelayConfig,
+        consensus: Consensus,
+    ) -> (Relayer<ChainKVStore<MemoryKeyValueDB>>, NotifyController) {
+        let shared = SharedBuilder::<MemoryKeyValueDB>::new()
+            .consensus(consensus)
+            .tx_pool_config(tx_pool_config)
+            .build()
+            .unwrap();
+        let notify = NotifyService::default().start::<&str>(None);
+        let chain_controller = ChainBuilder::new(shared.clone(), notify.clone())
+            .verification(false)
+            .build()
+            .start::<&str>(None);
+        let sync_shared_state = SyncSharedState::new(shared).with_stem_hops(stem_hops);
+        let relayer = Relayer::new(
+            chain_controller,
+            Arc::new(sync_shared_state),
+            Arc::new(Default::default()),
+            relay_config,
+        );
+        (relayer, notify)
+    }
+
+    fn gen_tx(index: u64) -> Transaction {
+        TransactionBuilder::default()
+            .input(CellInput::new_cellbase_input(index))
+            .build()
+    }
+
+    fn transaction_data(tx: &Transaction, stem_hops: u8) -> Bytes {
+        let fbb = &mut FlatBufferBuilder::new();
+        let message = RelayMessage::build_stem_transaction(fbb, tx, 0, stem_hops);
+        fbb.finish(message, None);
+        fbb.finished_data().into()
+    }
+
+    // Append a block to the chain of the relayer
+    fn process_block(
+        relayer: &Relayer<ChainKVStore<MemoryKeyValueDB>>,
+        parent: &Header,
+        transactions: Vec<Transaction>,
+        proposals: Vec<ProposalShortId>,
+    ) -> Block {
+        let number = parent.number() + 1;
+        let cellbase = TransactionBuilder::default()
+            .input(CellInput::new_cellbase_input(number))
+            .outputs(
+                (0..4)
+                    .map(|_| {
+                        CellOutput::new(
+                            capacity_bytes!(50),
+                            Vec::new(),
+                            Script::always_success(),
+                            None,
+                        )
+                    })
+                    .collect(),
+            )
+            .build();
+        let difficulty = relayer
+            .shared
+            .shared()
+            .calculate_difficulty(parent)
+            .unwrap();
+        let header_builder = HeaderBuilder::default()
+            .parent_hash(parent.hash().clone())
+            .number(number)
+            .timestamp(parent.timestamp() + 1)
+            .difficulty(difficulty);
+        let block = BlockBuilder::default()
+            .transaction(cellbase)
+            .transactions(transactions)
+            .proposals(proposals)
+            .with_header_builder(header_builder);
+        relayer
+            .chain
+            .process_block(Arc::new(block.clone()))
+            .expect("process block");
+        block
+    }
+
+    fn process_transaction(
+        relayer: &Relayer<ChainKVStore<MemoryKeyValueDB>>,
+        nc: &DummyNetworkContext,
+        peer: usize,
+        tx: &Transaction,
+    ) {
+        let data = transaction_data(tx, 0);
+        let message = get_root::<RelayMessage>(&data)
+            .payload_as_relay_transaction()
+            .unwrap();
+        TransactionProcess::new(&message, relayer, nc, peer.into())
+            .execute()
+            .expect("process transaction");
+    }
+
+    // Whether the transaction passed the relay policy and is taken by the pool, it may
+    // still be rejected there
+    fn is_submitted_to_pool(
+        relayer: &Relayer<ChainKVStore<MemoryKeyValueDB>>,
+        tx: &Transaction,
+    ) -> bool {
+        let tx_pool = relayer.shared.tx_pool().lock();
+        tx_pool.contains_proposal_id(&tx.proposal_short_id())
+            || tx_pool.get_rejected(&tx.hash()).is_some()
+    }
+
+    fn block_transactions_data(
+        block_hash: &H256,
+        transactions: &[Transaction],
+        sequence: u32,
+        total: u32,
+    ) -> Bytes {
+        let fbb = &mut FlatBufferBuilder::new();
+        let message = RelayMessage::build_block_transactions_chunk(
+            fbb,
+            block_hash,
+            transactions,
+            sequence,
+            total,
+        );
+        fbb.finish(message, None);
+        fbb.finished_data().into()
+    }
+
+    #[test]
+    fn test_reject_unbounded_block_transactions_chunks() {
+        let (relayer, _notify) = gen_relayer(0);
+        let nc = DummyNetworkContext::default();
+        let block_hash = H256::zero();
+        relayer.state.pending_compact_blocks.lock().insert(
+            block_hash.clone(),
+            CompactBlock {
+                header: Default::default(),
+                uncles: Vec::new(),
+                nonce: 0,
+                short_ids: vec![[0; 6], [1; 6]],
+                prefilled_transactions: Vec::new(),
+                proposals: Vec::new(),
+            },
+        );
+        let process = |transactions: &[Transaction], sequence: u32, total: u32| {
+            let data = block_transactions_data(&block_hash, transactions, sequence, total);
+            let message = get_root::<RelayMessage>(&data)
+                .payload_as_block_transactions()
+                .unwrap();
+            BlockTransactionsProcess::new(&message, &relayer, &nc, 0.into()).execute()
+        };
+
+        // more chunks than the missing transactions
+        assert!(process(&[gen_tx(0)], 0, u32::max_value()).is_err());
+        assert!(process(&[gen_tx(0)], 0, 3).is_err());
+        // an empty chunk of a split response
+        assert!(process(&[], 0, 2).is_err());
+        assert!(relayer.state.block_transactions_chunks.lock().is_empty());
+
+        assert!(process(&[gen_tx(0)], 0, 2).is_ok());
+        assert_eq!(relayer.state.block_transactions_chunks.lock().len(), 1);
+        // resending a chunk replaces it
+        assert!(process(&[gen_tx(1)], 0, 2).is_ok());
+        // more transactions than the missing ones
+        assert!(process(&[gen_tx(2), gen_tx(3)], 1, 2).is_err());
+        assert!(relayer.state.block_transactions_chunks.lock().is_empty());
+    }
+
+    #[test]
+    fn test_relay_stem_transaction() {
+        let (relayer, _notify) = gen_relayer(2);
+        let mut nc = DummyNetworkContext::default();
+        nc.add_peer(0, SessionType::Outbound, true);
+        nc.add_peer(1, SessionType::Inbound, true);
+        nc.add_peer(2, SessionType::Outbound, false);
+
+        // the stem is only passed to outbound peers supporting it, other than the sender
+        let tx = gen_tx(0);
+        let fluff_data = transaction_data(&tx, 0);
+        assert!(!relayer.relay_stem_transaction(&nc, 0.into(), &tx, 0, 1, fluff_data.clone()));
+        assert!(relayer.state.stem_embargo.lock().is_empty());
+
+        assert!(relayer.relay_stem_transaction(&nc, 1.into(), &tx, 0, 1, fluff_data));
+        relayer.flush_send_queue(&nc);
+        assert_eq!(nc.take_sent(), vec![(0.into(), tx.hash(), 1)]);
+        assert!(relayer.state.stem_embargo.lock().contains_key(&tx.hash()));
+    }
+
+    #[cfg(not(disable_faketime))]
+    #[test]
+    fn test_fluff_expired_stem_txs() {
+        let faketime_file = faketime::millis_tempfile(0).expect("create faketime file");
+        faketime::enable(&faketime_file);
+
+        let (relayer, _notify) = gen_relayer(2);
+        let mut nc = DummyNetworkContext::default();
+        for peer in 0..3 {
+            nc.add_peer(peer, SessionType::Outbound, true);
+        }
+
+        let tx = gen_tx(0);
+        assert!(relayer.relay_stem_transaction(&nc, 0.into(), &tx, 0, 1, transaction_data(&tx, 0)));
+        relayer.flush_send_queue(&nc);
+        let stem_peer = nc.take_sent()[0].0;
+
+        // nobody broadcast the transaction before the embargo expires
+        faketime::write_millis(&faketime_file, STEM_EMBARGO_TIMEOUT - 1).expect("write millis");
+        relayer.fluff_expired_stem_txs(&nc);
+        relayer.flush_send_queue(&nc);
+        assert!(nc.take_sent().is_empty());
+
+        faketime::write_millis(&faketime_file, STEM_EMBARGO_TIMEOUT).expect("write millis");
+        relayer.fluff_expired_stem_txs(&nc);
+        relayer.flush_send_queue(&nc);
+        let sent = nc.take_sent();
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|(peer, hash, stem_hops)| *peer != stem_peer
+            && hash == &tx.hash()
+            && *stem_hops == 0));
+        assert!(relayer.state.stem_embargo.lock().is_empty());
+    }
+
+    #[test]
+    fn test_fluff_clears_stem_embargo() {
+        let (relayer, _notify) = gen_relayer(2);
+        let mut nc = DummyNetworkContext::default();
+        for peer in 0..2 {
+            nc.add_peer(peer, SessionType::Outbound, true);
+        }
+
+        let tx = gen_tx(0);
+        assert!(relayer.relay_stem_transaction(&nc, 0.into(), &tx, 0, 1, transaction_data(&tx, 0)));
+
+        // still in the stem phase, the embargo is kept
+        let data = transaction_data(&tx, 1);
+        let message = get_root::<RelayMessage>(&data)
+            .payload_as_relay_transaction()
+            .unwrap();
+        let _ = TransactionProcess::new(&message, &relayer, &nc, 1.into()).execute();
+        assert!(relayer.state.stem_embargo.lock().contains_key(&tx.hash()));
+
+        // somebody is broadcasting it
+        let data = transaction_data(&tx, 0);
+        let message = get_root::<RelayMessage>(&data)
+            .payload_as_relay_transaction()
+            .unwrap();
+        let _ = TransactionProcess::new(&message, &relayer, &nc, 1.into()).execute();
+        assert!(relayer.state.stem_embargo.lock().is_empty());
+    }
+
+    #[test]
+    fn test_discard_relayed_tx_when_verifying_full() {
+        let mut tx_pool_config = TxPoolConfig::default();
+        tx_pool_config.max_verifying_size = 0;
+        let (relayer, _notify) = gen_relayer_with_config(0, tx_pool_config, Default::default());
+        let mut nc = DummyNetworkContext::default();
+        nc.add_peer(0, SessionType::Outbound, true);
+
+        let tx = gen_tx(0);
+        let data = transaction_data(&tx, 0);
+        let message = get_root::<RelayMessage>(&data)
+            .payload_as_relay_transaction()
+            .unwrap();
+        assert!(TransactionProcess::new(&message, &relayer, &nc, 0.into())
+            .execute()
+            .is_ok());
+        // not marked as known, so it is taken when relayed again later
+        assert!(!relayer.state.tx_filter.lock().contains_key(&tx.hash()));
+        assert!(relayer.state.verifying_txs.lock().is_empty());
+
+        // the proposals are not requested either
+        let block = CompactBlock {
+            header: Default::default(),
+            uncles: Vec::new(),
+            nonce: 0,
+            short_ids: Vec::new(),
+            prefilled_transactions: Vec::new(),
+            proposals: vec![tx.proposal_short_id()],
+        };
+        relayer.request_proposal_txs(&relayer.shared.chain_state(), 0.into(), &block);
+        relayer.flush_send_queue(&nc);
+        assert!(nc.sent.lock().is_empty());
+        assert!(relayer.state.inflight_proposals.lock().is_empty());
+    }
+
+    #[test]
+    fn test_fee_rate_acceptable() {
+        let config = RelayConfig {
+            min_fee_rate: 1000,
+            ..Default::default()
+        };
+        assert!(config.is_fee_rate_acceptable(100, 100));
+        assert!(config.is_fee_rate_acceptable(101, 100));
+        assert!(!config.is_fee_rate_acceptable(99, 100));
+        assert!(config.is_fee_rate_acceptable(0, 0));
+        assert!(config.is_fee_rate_acceptable(u64::max_value(), 1));
+    }
+
+    #[test]
+    fn test_discard_relayed_tx_under_min_fee_rate() {
+        let parent = TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(H256::zero(), 0), 0, vec![]))
+            .output(CellOutput::new(
+                capacity_bytes!(5000),
+                vec![],
+                Script::always_success(),
+                None,
+            ))
+            .build();
+        let tx = TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(parent.hash(), 0), 0, vec![]))
+            .output(CellOutput::new(
+                capacity_bytes!(1000),
+                vec![],
+                Script::always_success(),
+                None,
+            ))
+            .build();
+
+        for &(min_fee_rate, accepted) in &[(u64::max_value(), false), (0, true)] {
+            let relay_config = RelayConfig {
+                min_fee_rate,
+                ..Default::default()
+            };
+            let (relayer, _notify) = gen_relayer_with_config(0, Default::default(), relay_config);
+            let mut nc = DummyNetworkContext::default();
+            nc.add_peer(0, SessionType::Outbound, true);
+            relayer
+                .shared
+                .tx_pool()
+                .lock()
+                .enqueue_tx(None, Capacity::zero(), parent.clone());
+
+            process_transaction(&relayer, &nc, 0, &tx);
+            assert_eq!(is_submitted_to_pool(&relayer, &tx), accepted);
+        }
+    }
+
+    #[test]
+    fn test_discard_relayed_tx_over_max_size() {
+        let tx = gen_tx(0);
+        let tx_size = {
+            let fbb = &mut FlatBufferBuilder::new();
+            let message = RelayMessage::build_transaction(fbb, &tx, 0);
+            fbb.finish(message, None);
+            fbb.finished_data().len()
+        };
+
+        for &(max_tx_size, accepted) in &[(tx_size - 1, false), (tx_size, true)] {
+            let relay_config = RelayConfig {
+                max_tx_size,
+                ..Default::default()
+            };
+            let (relayer, _notify) = gen_relayer_with_config(0, Default::default(), relay_config);
+            let mut nc = DummyNetworkContext::default();
+            nc.add_peer(0, SessionType::Outbound, true);
+
+            process_transaction(&relayer, &nc, 0, &tx);
+            assert_eq!(is_submitted_to_pool(&relayer, &tx), accepted);
+        }
+    }
+
+    #[cfg(not(disable_faketime))]
+    #[test]
+    fn test_discard_relayed_txs_over_announce_rate() {
+        let faketime_file = faketime::millis_tempfile(0).expect("create faketime file");
+        faketime::enable(&faketime_file);
+
+        let relay_config = RelayConfig {
+            max_tx_announce_rate: 2,
+            ..Default::default()
+        };
+        let (relayer, _notify) = gen_relayer_with_config(0, Default::default(), relay_config);
+        let mut nc = DummyNetworkContext::default();
+        nc.add_peer(0, SessionType::Outbound, true);
+        nc.add_peer(1, SessionType::Outbound, true);
+        let is_known = |tx: &Transaction| relayer.state.tx_filter.lock().contains_key(&tx.hash());
+
+        for i in 0..3 {
+            process_transaction(&relayer, &nc, 0, &gen_tx(i));
+        }
+        assert!(is_known(&gen_tx(0)));
+        assert!(is_known(&gen_tx(1)));
+        assert!(!is_known(&gen_tx(2)));
+
+        // the rate is counted per peer
+        process_transaction(&relayer, &nc, 1, &gen_tx(3));
+        assert!(is_known(&gen_tx(3)));
+
+        // and reset in the next window
+        faketime::write_millis(&faketime_file, TX_ANNOUNCE_RATE_WINDOW).expect("write millis");
+        process_transaction(&relayer, &nc, 0, &gen_tx(2));
+        assert!(is_known(&gen_tx(2)));
+        faketime::write_millis(&faketime_file, 0).expect("write millis");
+    }
+
+    #[cfg(not(disable_faketime))]
+    #[test]
+    fn test_rebroadcast_local_txs_until_committed() {
+        let faketime_file = faketime::millis_tempfile(0).expect("create faketime file");
+        faketime::enable(&faketime_file);
+
+        let consensus = Consensus::default().set_cellbase_maturity(0);
+        let (relayer, _notify) =
+            gen_relayer_with_consensus(0, Default::default(), Default::default(), consensus);
+        let mut nc = DummyNetworkContext::default();
+        nc.add_peer(0, SessionType::Outbound, true);
+        nc.add_peer(1, SessionType::Outbound, true);
+
+        let genesis = relayer.shared.tip_header();
+        let block = process_block(&relayer, &genesis, Vec::new(), Vec::new());
+        let tx = TransactionBuilder::default()
+            .input(CellInput::new(
+                OutPoint::new(block.transactions()[0].hash(), 0),
+                0,
+                vec![],
+            ))
+            .output(CellOutput::new(
+                capacity_bytes!(50),
+                Vec::new(),
+                Script::always_success(),
+                None,
+            ))
+            .build();
+        let id = tx.proposal_short_id();
+        {
+            let mut tx_pool = relayer.shared.tx_pool().lock();
+            relayer
+                .shared
+                .chain_state()
+                .add_tx_to_pool(&mut tx_pool, tx.clone())
+                .expect("add tx to pool");
+            tx_pool.mark_local_tx(id);
+        }
+
+        faketime::write_millis(&faketime_file, TX_REBROADCAST_INTERVAL).expect("write millis");
+        relayer.rebroadcast_local_txs(&nc);
+        relayer.flush_send_queue(&nc);
+        let sent = nc.take_sent();
+        assert_eq!(sent.len(), 2);
+        assert!(sent
+            .iter()
+            .all(|(_, hash, stem_hops)| hash == &tx.hash() && *stem_hops == 0));
+        assert!(relayer.state.tx_rebroadcast.lock().contains_key(&id));
+
+        // proposed and then committed
+        let block = process_block(&relayer, block.header(), Vec::new(), vec![id]);
+        process_block(&relayer, block.header(), vec![tx.clone()], Vec::new());
+
+        // a newly connected peer doesn't know the transaction, it is not sent anyway
+        nc.add_peer(2, SessionType::Outbound, true);
+        faketime::write_millis(&faketime_file, TX_REBROADCAST_INTERVAL * 4).expect("write millis");
+        relayer.rebroadcast_local_txs(&nc);
+        relayer.flush_send_queue(&nc);
+        assert!(nc.take_sent().is_empty());
+        assert!(relayer.state.tx_rebroadcast.lock().is_empty());
+        assert!(relayer.shared.tx_pool().lock().local_txs().is_empty());
+        faketime::write_millis(&faketime_file, 0).expect("write millis");
+    }
+
+    #[cfg(not(disable_faketime))]
+    #[test]
+    fn test_rebroadcast_local_stem_txs_after_embargo() {
+        let faketime_file = faketime::millis_tempfile(0).expect("create faketime file");
+        faketime::enable(&faketime_file);
+
+        for &stem_hops in &[0, 2] {
+            let (relayer, _notify) = gen_relayer(stem_hops);
+            let mut nc = DummyNetworkContext::default();
+            nc.add_peer(0, SessionType::Outbound, true);
+
+            let tx = gen_tx(0);
+            {
+                let mut tx_pool = relayer.shared.tx_pool().lock();
+                tx_pool.enqueue_tx(Some(0), Capacity::zero(), tx.clone());
+                tx_pool.mark_local_tx(tx.proposal_short_id());
+            }
+            let first_rebroadcast_at = if stem_hops > 0 {
+                STEM_EMBARGO_TIMEOUT + TX_REBROADCAST_INTERVAL
+            } else {
+                TX_REBROADCAST_INTERVAL
+            };
+
+            faketime::write_millis(&faketime_file, first_rebroadcast_at - 1).expect("write millis");
+            relayer.rebroadcast_local_txs(&nc);
+            relayer.flush_send_queue(&nc);
+            assert!(nc.take_sent().is_empty());
+
+            faketime::write_millis(&faketime_file, first_rebroadcast_at).expect("write millis");
+            relayer.rebroadcast_local_txs(&nc);
+            relayer.flush_send_queue(&nc);
+            assert_eq!(nc.take_sent(), vec![(0.into(), tx.hash(), 0)]);
+            faketime::write_millis(&faketime_file, 0).expect("write millis");
         }
     }
 }
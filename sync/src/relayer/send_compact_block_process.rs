@@ -0,0 +1,33 @@
+use crate::relayer::Relayer;
+use ckb_network::PeerIndex;
+use ckb_protocol::SendCompactBlock;
+use ckb_shared::store::ChainStore;
+use failure::Error as FailureError;
+use log::debug;
+
+pub struct SendCompactBlockProcess<'a, CS> {
+    message: &'a SendCompactBlock<'a>,
+    relayer: &'a Relayer<CS>,
+    peer: PeerIndex,
+}
+
+impl<'a, CS: ChainStore> SendCompactBlockProcess<'a, CS> {
+    pub fn new(message: &'a SendCompactBlock, relayer: &'a Relayer<CS>, peer: PeerIndex) -> Self {
+        SendCompactBlockProcess {
+            message,
+            relayer,
+            peer,
+        }
+    }
+
+    pub fn execute(self) -> Result<(), FailureError> {
+        let high_bandwidth = self.message.high_bandwidth();
+        debug!(target: "relay", "peer {} asks for high bandwidth mode: {}", self.peer, high_bandwidth);
+        self.relayer
+            .state
+            .compact_block_modes
+            .lock()
+            .insert(self.peer, high_bandwidth);
+        Ok(())
+    }
+}
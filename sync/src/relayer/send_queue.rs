@@ -0,0 +1,181 @@
+use bytes::Bytes;
+use ckb_network::{CKBProtocolContext, PeerIndex, TargetSession};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::mem;
+
+/// Max messages waiting in the queue, once full the oldest of the lowest priority is dropped
+pub(crate) const MAX_QUEUED_MESSAGES: usize = 4096;
+
+/// Relay messages are sent in priority order, so blocks and proposals never wait behind
+/// bulk transaction relays
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SendPriority {
+    Low,
+    High,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendTarget {
+    Peer(PeerIndex),
+    Peers(Vec<PeerIndex>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct QueuedMessage {
+    priority: SendPriority,
+    sequence: u64,
+    target: SendTarget,
+    data: Bytes,
+}
+
+impl Ord for QueuedMessage {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // higher priority first, FIFO within the same priority
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl PartialOrd for QueuedMessage {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Default)]
+pub struct SendQueue {
+    queue: BinaryHeap<QueuedMessage>,
+    sequence: u64,
+}
+
+impl SendQueue {
+    pub fn push(&mut self, priority: SendPriority, target: SendTarget, data: Bytes) {
+        self.sequence += 1;
+        self.queue.push(QueuedMessage {
+            priority,
+            sequence: self.sequence,
+            target,
+            data,
+        });
+        if self.queue.len() > MAX_QUEUED_MESSAGES {
+            self.drop_oldest_of_lowest_priority();
+        }
+    }
+
+    fn drop_oldest_of_lowest_priority(&mut self) {
+        let mut messages = mem::replace(&mut self.queue, BinaryHeap::new()).into_vec();
+        if let Some(index) = messages
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, message)| (message.priority, message.sequence))
+            .map(|(index, _)| index)
+        {
+            messages.swap_remove(index);
+        }
+        self.queue = BinaryHeap::from(messages);
+    }
+
+    /// Send the queued messages, high priority ones first. At most `max_low_priority`
+    /// low priority messages are sent, the rest wait for the next flush.
+    pub fn flush(&mut self, nc: &CKBProtocolContext, max_low_priority: usize) {
+        for (target, data) in self.pop_sendable(max_low_priority) {
+            match target {
+                SendTarget::Peer(peer) => nc.send_message_to(peer, data),
+                SendTarget::Peers(peers) => nc.filter_broadcast(TargetSession::Multi(peers), data),
+            }
+        }
+    }
+
+    fn pop_sendable(&mut self, max_low_priority: usize) -> Vec<(SendTarget, Bytes)> {
+        let mut sendable = Vec::new();
+        let mut low_priority_count = 0;
+        while let Some(message) = self.queue.peek() {
+            if message.priority == SendPriority::Low {
+                if low_priority_count >= max_low_priority {
+                    break;
+                }
+                low_priority_count += 1;
+            }
+            let message = self.queue.pop().expect("peeked above");
+            sendable.push((message.target, message.data));
+        }
+        sendable
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pop_sendable_in_priority_order() {
+        let mut queue = SendQueue::default();
+        queue.push(SendPriority::Low, SendTarget::Peer(1), Bytes::from("tx1"));
+        queue.push(SendPriority::Low, SendTarget::Peer(1), Bytes::from("tx2"));
+        queue.push(
+            SendPriority::High,
+            SendTarget::Peer(1),
+            Bytes::from("block"),
+        );
+        queue.push(SendPriority::Low, SendTarget::Peer(1), Bytes::from("tx3"));
+        queue.push(
+            SendPriority::High,
+            SendTarget::Peers(vec![1, 2]),
+            Bytes::from("proposal"),
+        );
+
+        let data: Vec<Bytes> = queue
+            .pop_sendable(2)
+            .into_iter()
+            .map(|(_, data)| data)
+            .collect();
+        assert_eq!(
+            data,
+            vec![
+                Bytes::from("block"),
+                Bytes::from("proposal"),
+                Bytes::from("tx1"),
+                Bytes::from("tx2"),
+            ]
+        );
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn test_drop_oldest_low_priority_when_full() {
+        let mut queue = SendQueue::default();
+        queue.push(
+            SendPriority::High,
+            SendTarget::Peer(1),
+            Bytes::from("block"),
+        );
+        for i in 0..MAX_QUEUED_MESSAGES {
+            queue.push(
+                SendPriority::Low,
+                SendTarget::Peer(1),
+                Bytes::from(i.to_string()),
+            );
+        }
+        assert_eq!(queue.len(), MAX_QUEUED_MESSAGES);
+
+        let data: Vec<Bytes> = queue
+            .pop_sendable(2)
+            .into_iter()
+            .map(|(_, data)| data)
+            .collect();
+        assert_eq!(
+            data,
+            vec![Bytes::from("block"), Bytes::from("1"), Bytes::from("2")]
+        );
+    }
+}
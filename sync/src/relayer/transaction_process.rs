@@ -1,13 +1,16 @@
 use crate::relayer::Relayer;
-use crate::relayer::MAX_RELAY_PEERS;
+use crate::relayer::MAX_STEM_HOPS;
+use bytes::Bytes;
 use ckb_core::{transaction::Transaction, Cycle};
-use ckb_network::{CKBProtocolContext, PeerIndex, TargetSession};
+use ckb_network::{CKBProtocolContext, PeerIndex};
 use ckb_protocol::{RelayMessage, RelayTransaction as FbsRelayTransaction};
 use ckb_shared::store::ChainStore;
+use ckb_shared::tx_pool::PoolError;
 use failure::Error as FailureError;
 use flatbuffers::FlatBufferBuilder;
 use log::debug;
 use numext_fixed_hash::H256;
+use std::cmp;
 use std::convert::TryInto;
 use std::time::Duration;
 
@@ -38,45 +41,137 @@ impl<'a, CS: ChainStore> TransactionProcess<'a, CS> {
     pub fn execute(self) -> Result<(), FailureError> {
         let (tx, relay_cycles): (Transaction, Cycle) = (*self.message).try_into()?;
         let tx_hash = tx.hash();
+        let stem_hops = cmp::min(self.message.stem_hops(), MAX_STEM_HOPS);
+
+        if stem_hops == 0 {
+            // the transaction is being broadcast, no need to broadcast it on our own
+            self.relayer.state.stem_embargo.lock().remove(&tx_hash);
+        }
+
+        if !self.relayer.check_tx_announce_rate(self.peer) {
+            debug!(target: "relay", "peer {} exceeds the transaction announce rate, discarding {:#x}", self.peer, tx_hash);
+            return Ok(());
+        }
+
+        // not marked as known, the transaction is taken once the verifications catch up
+        if self.relayer.shared.tx_pool().lock().is_verifying_full() {
+            debug!(target: "relay", "discarding transaction {:#x}, too many transactions are being verified", tx_hash);
+            return Ok(());
+        }
 
         if self.already_known(tx_hash.clone()) {
             debug!(target: "relay", "discarding already known transaction {:#x}", tx_hash);
             return Ok(());
         }
 
-        let tx_result = {
-            let chain_state = self.relayer.shared.chain_state().lock();
-            chain_state.add_tx_to_pool(tx.clone())
+        let fbb = &mut FlatBufferBuilder::new();
+        let message = RelayMessage::build_transaction(fbb, &tx, relay_cycles);
+        fbb.finish(message, None);
+        let tx_size = fbb.finished_data().len();
+        if tx_size > self.relayer.config.max_tx_size {
+            debug!(target: "relay", "discarding transaction {:#x}, size {} exceeds {}", tx_hash, tx_size, self.relayer.config.max_tx_size);
+            return Ok(());
+        }
+
+        {
+            let tx_pool = self.relayer.shared.tx_pool().lock();
+            let chain_state = self.relayer.shared.chain_state();
+            let fee = {
+                let rtx = chain_state.resolve_tx_from_pending_and_staging(&tx, &tx_pool);
+                if rtx.is_fully_resolved() {
+                    rtx.fee().ok()
+                } else {
+                    None
+                }
+            };
+            // the fee of a transaction with unknown inputs can't be calculated yet
+            if let Some(fee) = fee {
+                if !self
+                    .relayer
+                    .config
+                    .is_fee_rate_acceptable(fee.as_u64(), tx_size)
+                {
+                    debug!(target: "relay", "discarding transaction {:#x}, fee {} is too low for size {}", tx_hash, fee.as_u64(), tx_size);
+                    return Ok(());
+                }
+            }
+        }
+        // the scripts are verified in background, the result is handled once they finish
+        // without blocking the message handler
+        let verified = RelayedTx {
+            peer: self.peer,
+            tx: tx.clone(),
+            relay_cycles,
+            stem_hops,
+            data: fbb.finished_data().into(),
         };
-        // disconnect peer if cycles mismatch
-        match tx_result {
-            Ok(cycles) if cycles == relay_cycles => {
-                let mut known_txs = self.relayer.peers.known_txs.lock();
-                let selected_peers: Vec<PeerIndex> = self
-                    .nc
-                    .connected_peers()
-                    .into_iter()
-                    .filter(|target_peer| {
-                        known_txs.insert(*target_peer, tx_hash.clone())
-                            && (self.peer != *target_peer)
-                    })
-                    .take(MAX_RELAY_PEERS)
-                    .collect();
+        match self.relayer.shared.shared().add_tx_to_pool(tx) {
+            Ok(verification) => self
+                .relayer
+                .state
+                .verifying_txs
+                .lock()
+                .push((verified, verification)),
+            Err(PoolError::Full) => {
+                self.relayer.state.tx_filter.lock().remove(&tx_hash);
+            }
+            Err(err) => verified.finish(self.relayer, self.nc, Err(err)),
+        }
+
+        Ok(())
+    }
+
+    fn already_known(&self, hash: H256) -> bool {
+        let mut tx_filter = self.relayer.state.tx_filter.lock();
+        tx_filter.insert(hash, ()).is_some()
+    }
+}
+
+/// A relayed transaction whose scripts are verified in background
+pub struct RelayedTx {
+    peer: PeerIndex,
+    tx: Transaction,
+    relay_cycles: Cycle,
+    stem_hops: u8,
+    /// The relay transaction message of `tx` with no stem hops
+    data: Bytes,
+}
 
-                let fbb = &mut FlatBufferBuilder::new();
-                let message = RelayMessage::build_transaction(fbb, &tx, cycles);
-                fbb.finish(message, None);
-                let data = fbb.finished_data().into();
-                self.nc
-                    .filter_broadcast(TargetSession::Multi(selected_peers), data);
+impl RelayedTx {
+    /// Relay the transaction once it's added to the pool, or punish the peer which relayed
+    /// an invalid one
+    pub fn finish<CS: ChainStore>(
+        self,
+        relayer: &Relayer<CS>,
+        nc: &CKBProtocolContext,
+        result: Result<Cycle, PoolError>,
+    ) {
+        let tx_hash = self.tx.hash();
+        // disconnect peer if cycles mismatch
+        match result {
+            Ok(cycles) if cycles == self.relay_cycles => {
+                relayer.mark_recent_tx(tx_hash.clone());
+                // a stem transaction is broadcast here if there is no peer to pass it to
+                if self.stem_hops == 0
+                    || !relayer.relay_stem_transaction(
+                        nc,
+                        self.peer,
+                        &self.tx,
+                        cycles,
+                        self.stem_hops - 1,
+                        self.data.clone(),
+                    )
+                {
+                    relayer.broadcast_transaction(nc, &tx_hash, Some(self.peer), self.data);
+                }
             }
             Ok(cycles) => {
                 debug!(
                     target: "relay",
                     "peer {} relay wrong cycles tx: {:?} real cycles {} wrong cycles {}",
-                    self.peer, tx, cycles, relay_cycles,
+                    self.peer, self.tx, cycles, self.relay_cycles,
                 );
-                self.nc.ban_peer(self.peer, DEFAULT_BAN_TIME);
+                nc.ban_peer(self.peer, DEFAULT_BAN_TIME);
             }
             Err(err) => {
                 if err.is_bad_tx() {
@@ -84,22 +179,15 @@ impl<'a, CS: ChainStore> TransactionProcess<'a, CS> {
                     sentry::capture_message(
                         &format!(
                             "ban peer {} {:?}, reason: relay invalid tx: {:?}, error: {:?}",
-                            self.peer, DEFAULT_BAN_TIME, tx, err
+                            self.peer, DEFAULT_BAN_TIME, self.tx, err
                         ),
                         sentry::Level::Info,
                     );
-                    self.nc.ban_peer(self.peer, DEFAULT_BAN_TIME);
+                    nc.ban_peer(self.peer, DEFAULT_BAN_TIME);
                 } else {
                     debug!(target: "relay", "peer {} relay a conflict or missing input tx: {:?}, error: {:?}", self.peer, tx_hash, err);
                 }
             }
         }
-
-        Ok(())
-    }
-
-    fn already_known(&self, hash: H256) -> bool {
-        let mut tx_filter = self.relayer.state.tx_filter.lock();
-        tx_filter.insert(hash, ()).is_some()
     }
 }
@@ -7,6 +7,7 @@ use crate::{
 use ckb_core::header::Header;
 use ckb_network::PeerIndex;
 use ckb_shared::store::ChainStore;
+use ckb_traits::HeaderProvider;
 use ckb_util::try_option;
 use faketime::unix_time_as_millis;
 use log::{debug, trace};
@@ -27,7 +28,7 @@ where
 {
     pub fn new(synchronizer: Synchronizer<CS>, peer: PeerIndex) -> Self {
         let (tip_header, total_difficulty) = {
-            let chain_state = synchronizer.shared.chain_state().lock();
+            let chain_state = synchronizer.shared.chain_state();
             (
                 chain_state.tip_header().clone(),
                 chain_state.total_difficulty().clone(),
@@ -180,7 +181,6 @@ where
 
         {
             let mut guard = self.synchronizer.peers.blocks_inflight.write();
-            let inflight = guard.get_mut(&self.peer).expect("inflight already init");
 
             while n_height < max_height && v_fetch.len() < PER_FETCH_BLOCK_LIMIT {
                 n_height += 1;
@@ -190,6 +190,19 @@ where
                     .get_ancestor(&best_known_header.hash(), n_height)?;
                 let to_fetch_hash = to_fetch.hash();
 
+                // Blocks are downloaded in parallel from several peers, skip the ones
+                // already requested from another peer
+                let inflight_elsewhere = guard.iter().any(|(peer, inflight)| {
+                    *peer != self.peer && inflight.contains(&to_fetch_hash)
+                });
+                if inflight_elsewhere {
+                    continue;
+                }
+
+                let inflight = guard.get_mut(&self.peer).expect("inflight already init");
+                if inflight.len() >= MAX_BLOCKS_IN_TRANSIT_PER_PEER {
+                    break;
+                }
                 let block_status = self.synchronizer.get_block_status(&to_fetch_hash);
                 if block_status == BlockStatus::VALID_MASK
                     && inflight.insert(to_fetch_hash.clone().clone())
@@ -1,4 +1,5 @@
 use ckb_core::block::Block;
+use ckb_core::BlockNumber;
 use ckb_util::RwLock;
 use fnv::{FnvHashMap, FnvHashSet};
 use numext_fixed_hash::H256;
@@ -47,8 +48,29 @@ impl OrphanBlockPool {
         removed
     }
 
+    /// Remove orphan blocks whose parent number is below `min_parent_number`,
+    /// together with their descendants in the pool
+    pub fn remove_stale_blocks(&self, min_parent_number: BlockNumber) -> Vec<Block> {
+        let stale_parents: Vec<ParentHash> = self
+            .blocks
+            .read()
+            .iter()
+            .filter(|(_, blocks)| {
+                blocks
+                    .iter()
+                    .any(|block| block.header().number().saturating_sub(1) < min_parent_number)
+            })
+            .map(|(parent_hash, _)| parent_hash.clone())
+            .collect();
+        stale_parents
+            .iter()
+            .flat_map(|parent_hash| self.remove_blocks_by_parent(parent_hash))
+            .collect()
+    }
+
+    /// The number of orphan blocks, not of the parents they wait for
     pub fn len(&self) -> usize {
-        self.blocks.read().len()
+        self.blocks.read().values().map(FnvHashSet::len).sum()
     }
 
     pub fn is_empty(&self) -> bool {
@@ -102,4 +124,56 @@ mod tests {
         let block: HashSet<Block> = HashSet::from_iter(blocks.into_iter());
         assert_eq!(orphan, block)
     }
+
+    #[test]
+    fn test_remove_stale_blocks() {
+        let consensus = Consensus::default();
+        let pool = OrphanBlockPool::with_capacity(10);
+        let mut parent = consensus.genesis_block().header().clone();
+        let mut blocks: Vec<Block> = Vec::new();
+        for _ in 0..3 {
+            let new_block = gen_block(&parent);
+            parent = new_block.header().clone();
+            blocks.push(new_block);
+        }
+        // an unrelated orphan far above the others
+        let mut high_parent = parent.clone();
+        for _ in 0..10 {
+            high_parent = gen_block(&high_parent).header().clone();
+        }
+        let high_block = gen_block(&high_parent);
+
+        pool.insert(blocks[1].clone());
+        pool.insert(blocks[2].clone());
+        pool.insert(high_block.clone());
+
+        let removed = pool.remove_stale_blocks(5);
+        let removed: HashSet<Block> = HashSet::from_iter(removed.into_iter());
+        let expected: HashSet<Block> = HashSet::from_iter(blocks[1..].iter().cloned());
+        assert_eq!(removed, expected);
+        assert!(pool.contains(&high_block));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn test_len_counts_blocks() {
+        let consensus = Consensus::default();
+        let pool = OrphanBlockPool::with_capacity(10);
+        let genesis = consensus.genesis_block().header().clone();
+        let block = gen_block(&genesis);
+        // a sibling waiting for the same parent
+        let sibling = BlockBuilder::default()
+            .header(
+                HeaderBuilder::default()
+                    .parent_hash(genesis.hash().clone())
+                    .number(1)
+                    .nonce(genesis.nonce() + 2)
+                    .build(),
+            )
+            .build();
+        pool.insert(block.clone());
+        pool.insert(sibling);
+        pool.insert(gen_block(block.header()));
+        assert_eq!(pool.len(), 3);
+    }
 }
@@ -2,10 +2,12 @@ use crate::synchronizer::Synchronizer;
 use ckb_core::block::Block;
 use ckb_network::{CKBProtocolContext, PeerIndex};
 use ckb_protocol::Block as PBlock;
+use ckb_shared::block_stage::BlockStage;
 use ckb_shared::store::ChainStore;
 use failure::Error as FailureError;
 use log::debug;
 use std::convert::TryInto;
+use std::time::Instant;
 
 pub struct BlockProcess<'a, CS: ChainStore + 'a> {
     message: &'a PBlock<'a>,
@@ -31,7 +33,17 @@ where
     }
 
     pub fn execute(self) -> Result<(), FailureError> {
+        let started_at = Instant::now();
         let block: Block = (*self.message).try_into()?;
+        self.synchronizer
+            .shared
+            .shared()
+            .block_stage_metrics()
+            .record(
+                BlockStage::Decode,
+                &block.header().hash(),
+                started_at.elapsed(),
+            );
         debug!(target: "sync", "BlockProcess received block {} {:x}", block.header().number(), block.header().hash());
 
         self.synchronizer.peers.block_received(self.peer, &block);
@@ -35,9 +35,19 @@ where
     pub fn execute(self) -> Result<(), FailureError> {
         let block_hashes = cast!(self.message.block_hashes())?;
 
+        let store = self.synchronizer.shared.store();
+        let pruned_height = store.get_pruned_height().unwrap_or(0);
+
         for fbs_h256 in block_hashes {
             let block_hash = fbs_h256.try_into()?;
             debug!(target: "sync", "get_blocks {:x}", block_hash);
+            // blocks below the pruned height are only partially kept, none of them is served
+            if let Some(header) = store.get_header(&block_hash) {
+                if header.number() < pruned_height {
+                    debug!(target: "sync", "get_blocks {:x} is pruned", block_hash);
+                    continue;
+                }
+            }
             if let Some(block) = self.synchronizer.shared.get_block(&block_hash) {
                 debug!(target: "sync", "respond_block {} {:x}", block.header().number(), block.header().hash());
                 let fbb = &mut FlatBufferBuilder::new();
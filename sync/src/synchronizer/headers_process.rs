@@ -5,13 +5,18 @@ use ckb_core::{header::Header, BlockNumber};
 use ckb_network::{CKBProtocolContext, PeerIndex};
 use ckb_protocol::{cast, FlatbuffersVectorIterator, Headers};
 use ckb_shared::store::ChainStore;
-use ckb_traits::BlockMedianTimeContext;
-use ckb_verification::{Error as VerifyError, HeaderResolver, HeaderVerifier, Verifier};
+use ckb_traits::{BlockMedianTimeContext, HeaderProvider};
+use ckb_verification::{
+    Error as VerifyError, HeaderResolver, HeaderVerifier, PowBatchVerifier, TimestampError,
+    Verifier,
+};
 use failure::Error as FailureError;
 use log::{self, debug, log_enabled, warn};
 use numext_fixed_uint::U256;
+use std::cmp;
 use std::convert::TryInto;
 use std::sync::Arc;
+use std::time::Instant;
 
 pub struct HeadersProcess<'a, CS: ChainStore + 'a> {
     message: &'a Headers<'a>,
@@ -193,7 +198,10 @@ where
         let verifier = HeaderVerifier::new(
             resolver.clone(),
             Arc::clone(&self.synchronizer.shared.consensus().pow_engine()),
-        );
+        )
+        .allowed_future_blocktime(self.synchronizer.shared.max_future_block_time())
+        .dao_activation_number(self.synchronizer.shared.consensus().dao_activation_number())
+        .pow_verified();
         let acceptor =
             HeaderAcceptor::new(first, self.peer, &self.synchronizer, resolver, verifier);
         acceptor.accept()
@@ -225,6 +233,25 @@ where
             return Ok(());
         }
 
+        let started_at = Instant::now();
+        let pow_verifier =
+            PowBatchVerifier::new(&headers, &self.synchronizer.shared.consensus().pow_engine());
+        let verification_pool = self.synchronizer.shared.shared().verification_pool();
+        if let Err((index, error)) = verification_pool.install(|| pow_verifier.verify()) {
+            debug!(target: "sync", "HeadersProcess header {:x} {:?}", headers[index].hash(), error);
+            self.synchronizer.peers.misbehavior(self.peer, 100);
+            return Ok(());
+        }
+        let elapsed = started_at.elapsed().as_millis();
+        debug!(
+            target: "sync",
+            "HeadersProcess verified {} seals in {}ms, {} headers/s",
+            headers.len(),
+            elapsed,
+            headers.len() as u128 * 1000 / cmp::max(elapsed, 1),
+        );
+
+        // seals are verified above, the verifiers below only do the contextual checks
         let result = self.accept_first(&headers[0]);
         if !result.is_valid() {
             if result.misbehavior > 0 {
@@ -242,7 +269,10 @@ where
                 let verifier = HeaderVerifier::new(
                     resolver.clone(),
                     Arc::clone(&self.synchronizer.shared.consensus().pow_engine()),
-                );
+                )
+                .allowed_future_blocktime(self.synchronizer.shared.max_future_block_time())
+                .dao_activation_number(self.synchronizer.shared.consensus().dao_activation_number())
+                .pow_verified();
                 let acceptor =
                     HeaderAcceptor::new(&header, self.peer, &self.synchronizer, resolver, verifier);
                 let result = acceptor.accept();
@@ -261,7 +291,7 @@ where
 
         if log_enabled!(target: "sync", log::Level::Debug) {
             let own = self.synchronizer.shared.best_known_header();
-            let chain_state = self.synchronizer.shared.chain_state().lock();
+            let chain_state = self.synchronizer.shared.chain_state();
             let peer_state = self.synchronizer.peers.best_known_header(self.peer);
             debug!(
                 target: "sync",
@@ -404,8 +434,17 @@ where
 
         if self.non_contextual_check(&mut result).is_err() {
             debug!(target: "sync", "HeadersProcess accept {:?} non_contextual", self.header.number());
-            self.synchronizer
-                .insert_block_status(self.header.hash().clone(), BlockStatus::FAILED_MASK);
+            if result.is_too_new() {
+                // park it, the header becomes acceptable later unless our clock is wrong
+                warn!(
+                    target: "sync",
+                    "header {:x} is too far in the future, check your local clock if this keeps happening",
+                    self.header.hash()
+                );
+            } else {
+                self.synchronizer
+                    .insert_block_status(self.header.hash().clone(), BlockStatus::FAILED_MASK);
+            }
             return result;
         }
 
@@ -464,4 +503,14 @@ impl ValidationResult {
     pub fn is_valid(&self) -> bool {
         self.state == ValidationState::VALID
     }
+
+    /// Whether the header was rejected for being timestamped too far in the future
+    pub fn is_too_new(&self) -> bool {
+        match self.error {
+            Some(ValidationError::Verify(VerifyError::Timestamp(
+                TimestampError::BlockTimeTooNew { .. },
+            ))) => true,
+            _ => false,
+        }
+    }
 }
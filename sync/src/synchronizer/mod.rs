@@ -1,5 +1,5 @@
 mod block_fetcher;
-mod block_pool;
+pub(crate) mod block_pool;
 mod block_process;
 mod get_blocks_process;
 mod get_headers_process;
@@ -12,17 +12,18 @@ use self::get_blocks_process::GetBlocksProcess;
 use self::get_headers_process::GetHeadersProcess;
 use self::headers_process::HeadersProcess;
 use crate::config::Config;
+use crate::relayer::AcceptBlockError;
 use crate::types::{HeaderView, Peers, SyncSharedState};
 use crate::{
     BAD_MESSAGE_BAN_TIME, CHAIN_SYNC_TIMEOUT, EVICTION_HEADERS_RESPONSE_TIME,
     HEADERS_DOWNLOAD_TIMEOUT_BASE, HEADERS_DOWNLOAD_TIMEOUT_PER_HEADER,
-    MAX_OUTBOUND_PEERS_TO_PROTECT_FROM_DISCONNECT, POW_SPACE,
+    MAX_HEADERS_SYNC_PEERS_IN_IBD, MAX_OUTBOUND_PEERS_TO_PROTECT_FROM_DISCONNECT, POW_SPACE,
 };
 use bitflags::bitflags;
 use ckb_chain::chain::ChainController;
 use ckb_core::block::Block;
-use ckb_core::header::Header;
-use ckb_network::{CKBProtocolContext, CKBProtocolHandler, PeerIndex};
+use ckb_core::header::{BlockNumber, Header};
+use ckb_network::{CKBProtocolContext, CKBProtocolHandler, PeerIndex, PeerInfo};
 use ckb_protocol::{cast, get_root, SyncMessage, SyncPayload};
 use ckb_shared::store::ChainStore;
 use ckb_util::Mutex;
@@ -109,7 +110,7 @@ impl<CS: ChainStore> Synchronizer<CS> {
         }
     }
 
-    fn try_process(
+    pub(crate) fn try_process(
         &self,
         nc: &CKBProtocolContext,
         peer: PeerIndex,
@@ -205,6 +206,7 @@ impl<CS: ChainStore> Synchronizer<CS> {
             };
 
             self.peers.new_header_received(peer, &header_view);
+            self.shared.record_chain_tip(&header_view, peer);
             self.shared
                 .insert_header_view(header.hash().clone(), header_view);
         }
@@ -227,8 +229,15 @@ impl<CS: ChainStore> Synchronizer<CS> {
         }
     }
 
-    fn accept_block(&self, peer: PeerIndex, block: &Arc<Block>) -> Result<(), FailureError> {
-        self.chain.process_block(Arc::clone(&block))?;
+    fn accept_block(&self, peer: PeerIndex, block: &Arc<Block>) -> Result<(), AcceptBlockError> {
+        if let Err(err) = self.chain.process_block(Arc::clone(&block)) {
+            let err = AcceptBlockError::from_process_block_error(err);
+            // a block failed by a local error may be accepted later
+            if let AcceptBlockError::Invalid(_) = err {
+                self.insert_block_status(block.header().hash().clone(), BlockStatus::FAILED_VALID);
+            }
+            return Err(err);
+        }
         self.shared.remove_header_view(&block.header().hash());
         self.mark_block_stored(block.header().hash().clone());
         self.peers.set_last_common_header(peer, &block.header());
@@ -278,6 +287,12 @@ impl<CS: ChainStore> Synchronizer<CS> {
                     accept_ret.unwrap_err()
                 )
             }
+        } else if block.header().number().saturating_sub(1) < self.min_orphan_parent_number() {
+            debug!(
+                target: "sync", "[Synchronizer] discard stale orphan block {:?} {:x}",
+                block.header().number(),
+                block.header().hash()
+            );
         } else {
             debug!(
                 target: "sync", "[Synchronizer] insert_orphan_block {:?}------------{:x}",
@@ -290,6 +305,24 @@ impl<CS: ChainStore> Synchronizer<CS> {
         debug!(target: "sync", "[Synchronizer] insert_new_block finish");
     }
 
+    /// Orphan blocks whose parent is far below the best known header are either stale
+    /// forks or hostile, they won't be connected while we sync towards the best header.
+    fn min_orphan_parent_number(&self) -> BlockNumber {
+        self.shared
+            .best_known_header()
+            .number()
+            .saturating_sub(self.config.max_orphan_block_age)
+    }
+
+    pub fn evict_stale_orphan_blocks(&self) {
+        let removed = self
+            .orphan_block_pool
+            .remove_stale_blocks(self.min_orphan_parent_number());
+        if !removed.is_empty() {
+            debug!(target: "sync", "[Synchronizer] evict {} stale orphan blocks", removed.len());
+        }
+    }
+
     pub fn get_blocks_to_fetch(&self, peer: PeerIndex) -> Option<Vec<H256>> {
         BlockFetcher::new(self.clone(), peer).fetch()
     }
@@ -344,7 +377,7 @@ impl<CS: ChainStore> Synchronizer<CS> {
                     let best_known_header = best_known_headers.get(peer);
 
                     let (tip_header, local_total_difficulty) = {
-                        let chain_state = self.shared.chain_state().lock();
+                        let chain_state = self.shared.chain_state();
                         (
                             chain_state.tip_header().clone(),
                             chain_state.total_difficulty().clone(),
@@ -415,7 +448,7 @@ impl<CS: ChainStore> Synchronizer<CS> {
 
         let tip = {
             let (header, total_difficulty) = {
-                let chain_state = self.shared.chain_state().lock();
+                let chain_state = self.shared.chain_state();
                 (
                     chain_state.tip_header().clone(),
                     chain_state.total_difficulty().clone(),
@@ -432,8 +465,11 @@ impl<CS: ChainStore> Synchronizer<CS> {
             }
         };
         for peer in peers {
-            // Only sync with 1 peer if we're in IBD
-            if self.shared.is_initial_block_download() && self.n_sync.load(Ordering::Acquire) != 0 {
+            // Only sync headers with a few peers if we're in IBD, blocks are
+            // fetched from every peer whose best known header is on the best chain
+            if self.shared.is_initial_block_download()
+                && self.n_sync.load(Ordering::Acquire) >= MAX_HEADERS_SYNC_PEERS_IN_IBD
+            {
                 break;
             }
             {
@@ -452,7 +488,7 @@ impl<CS: ChainStore> Synchronizer<CS> {
     }
 
     fn find_blocks_to_fetch(&self, nc: &CKBProtocolContext) {
-        let peers: Vec<PeerIndex> = self
+        let mut peers: Vec<PeerIndex> = self
             .peers
             .state
             .read()
@@ -461,6 +497,7 @@ impl<CS: ChainStore> Synchronizer<CS> {
             .map(|(peer_id, _)| peer_id)
             .cloned()
             .collect();
+        sort_peers_for_block_fetch(nc, &mut peers);
 
         trace!(target: "sync", "poll find_blocks_to_fetch select peers");
         for peer in peers {
@@ -481,6 +518,17 @@ impl<CS: ChainStore> Synchronizer<CS> {
     }
 }
 
+// Outbound peers are chosen by us, ask them first so they get the bulk of the blocks to
+// download. Among them prefer the lowest smoothed ping, peers not pinged yet go last.
+fn sort_peers_for_block_fetch(nc: &CKBProtocolContext, peers: &mut Vec<PeerIndex>) {
+    peers.sort_by_key(|peer| {
+        let info = nc.peer_info(*peer);
+        let is_outbound = info.as_ref().map(PeerInfo::is_outbound).unwrap_or(false);
+        let rtt = info.and_then(|info| info.rtt);
+        (!is_outbound, rtt.is_none(), rtt)
+    });
+}
+
 impl<CS: ChainStore> CKBProtocolHandler for Synchronizer<CS> {
     fn init(&mut self, nc: Box<dyn CKBProtocolContext>) {
         // NOTE: 100ms is what bitcoin use.
@@ -539,6 +587,7 @@ impl<CS: ChainStore> CKBProtocolHandler for Synchronizer<CS> {
                 }
                 TIMEOUT_EVICTION_TOKEN => {
                     self.eviction(nc.as_ref());
+                    self.evict_stale_orphan_blocks();
                 }
                 _ => unreachable!(),
             }
@@ -553,7 +602,7 @@ mod tests {
     use self::block_process::BlockProcess;
     use self::headers_process::HeadersProcess;
     use super::*;
-    use crate::{SyncSharedState, MAX_TIP_AGE};
+    use crate::{SyncProgress, SyncSharedState, MAX_BLOCKS_IN_TRANSIT_PER_PEER, MAX_TIP_AGE};
     use ckb_chain::chain::ChainBuilder;
     use ckb_chain_spec::consensus::Consensus;
     use ckb_core::block::BlockBuilder;
@@ -564,8 +613,8 @@ mod tests {
     use ckb_core::Capacity;
     use ckb_db::memorydb::MemoryKeyValueDB;
     use ckb_network::{
-        multiaddr::ToMultiaddr, Behaviour, CKBProtocolContext, Peer, PeerId, PeerIndex, ProtocolId,
-        SessionType, TargetSession,
+        multiaddr::ToMultiaddr, Behaviour, CKBProtocolContext, Peer, PeerId, PeerIndex, PeerInfo,
+        ProtocolId, SessionType, TargetSession,
     };
     use ckb_notify::{NotifyController, NotifyService};
     use ckb_protocol::{Block as FbsBlock, Headers as FbsHeaders};
@@ -573,6 +622,7 @@ mod tests {
     use ckb_shared::shared::SharedBuilder;
     use ckb_shared::store::{ChainKVStore, ChainStore};
     use ckb_traits::chain_provider::ChainProvider;
+    use ckb_traits::HeaderProvider;
     use ckb_util::Mutex;
     #[cfg(not(disable_faketime))]
     use faketime;
@@ -666,6 +716,33 @@ mod tests {
             .expect("process block ok");
     }
 
+    #[test]
+    fn test_chain_tips_and_fork_point() {
+        let (chain_controller, shared, _notify) = start_chain(None, None);
+        for i in 1..5 {
+            insert_block(&chain_controller, &shared, i, i);
+        }
+        let synchronizer = gen_synchronizer(chain_controller.clone(), shared.clone());
+
+        // a branch of two headers forked from block 2
+        let fork_parent = shared.block_header(&shared.block_hash(2).unwrap()).unwrap();
+        let difficulty = shared.calculate_difficulty(&fork_parent).unwrap();
+        let fork1 = gen_block(&fork_parent, difficulty.clone(), 100);
+        let fork2 = gen_block(fork1.header(), difficulty, 101);
+        synchronizer.insert_header_view(fork1.header(), 1.into());
+        synchronizer.insert_header_view(fork2.header(), 2.into());
+
+        let tips = synchronizer.shared.chain_tips();
+        assert_eq!(tips.len(), 1);
+        assert_eq!(tips[0].hash, fork2.header().hash());
+        assert_eq!(tips[0].number, 4);
+        assert_eq!(tips[0].first_seen_peer, 2.into());
+        assert_eq!(
+            synchronizer.shared.fork_point(&fork2.header().hash()),
+            Some(fork_parent)
+        );
+    }
+
     #[test]
     fn test_locator() {
         let (chain_controller, shared, _notify) = start_chain(None, None);
@@ -683,7 +760,7 @@ mod tests {
 
         let locator = synchronizer
             .shared
-            .get_locator(shared.chain_state().lock().tip_header());
+            .get_locator(shared.chain_state().tip_header());
 
         let mut expect = Vec::new();
 
@@ -717,7 +794,7 @@ mod tests {
 
         let locator1 = synchronizer1
             .shared
-            .get_locator(shared1.chain_state().lock().tip_header());
+            .get_locator(shared1.chain_state().tip_header());
 
         let latest_common = synchronizer2
             .shared
@@ -779,7 +856,7 @@ mod tests {
         let synchronizer2 = gen_synchronizer(chain_controller2.clone(), shared2.clone());
         let locator1 = synchronizer1
             .shared
-            .get_locator(shared1.chain_state().lock().tip_header());
+            .get_locator(shared1.chain_state().tip_header());
 
         let latest_common = synchronizer2
             .shared
@@ -811,20 +888,17 @@ mod tests {
 
         let header = synchronizer
             .shared
-            .get_ancestor(&shared.chain_state().lock().tip_hash(), 100);
+            .get_ancestor(&shared.chain_state().tip_hash(), 100);
         let tip = synchronizer
             .shared
-            .get_ancestor(&shared.chain_state().lock().tip_hash(), 199);
+            .get_ancestor(&shared.chain_state().tip_hash(), 199);
         let noop = synchronizer
             .shared
-            .get_ancestor(&shared.chain_state().lock().tip_hash(), 200);
+            .get_ancestor(&shared.chain_state().tip_hash(), 200);
         assert!(tip.is_some());
         assert!(header.is_some());
         assert!(noop.is_none());
-        assert_eq!(
-            tip.unwrap(),
-            shared.chain_state().lock().tip_header().clone()
-        );
+        assert_eq!(tip.unwrap(), shared.chain_state().tip_header().clone());
         assert_eq!(
             header.unwrap(),
             shared
@@ -862,7 +936,34 @@ mod tests {
         });
         assert_eq!(
             chain1_last_block.header(),
-            shared2.chain_state().lock().tip_header()
+            shared2.chain_state().tip_header()
+        );
+    }
+
+    #[test]
+    fn test_mark_invalid_block() {
+        let shared = SharedBuilder::<MemoryKeyValueDB>::new().build().unwrap();
+        let notify = NotifyService::default().start::<&str>(None);
+        let chain_controller = ChainBuilder::new(shared.clone(), notify)
+            .build()
+            .start::<&str>(None);
+        let synchronizer = gen_synchronizer(chain_controller, shared.clone());
+
+        let parent = shared.block_header(&shared.block_hash(0).unwrap()).unwrap();
+        let difficulty = shared.calculate_difficulty(&parent).unwrap();
+        // without a cellbase
+        let block = BlockBuilder::default().with_header_builder(
+            HeaderBuilder::default()
+                .parent_hash(parent.hash().clone())
+                .timestamp(parent.timestamp() + 1)
+                .number(1)
+                .difficulty(difficulty),
+        );
+        synchronizer.insert_block_status(block.header().hash().clone(), BlockStatus::VALID_MASK);
+        synchronizer.process_new_block(0.into(), block.clone());
+        assert_eq!(
+            synchronizer.get_block_status(&block.header().hash()),
+            BlockStatus::FAILED_VALID
         );
     }
 
@@ -941,6 +1042,10 @@ mod tests {
         fn get_peer(&self, peer_index: PeerIndex) -> Option<Peer> {
             self.peers.get(&peer_index).cloned()
         }
+        fn peer_info(&self, peer_index: PeerIndex) -> Option<PeerInfo> {
+            self.get_peer(peer_index)
+                .map(|peer| PeerInfo::new(&peer, None))
+        }
         fn connected_peers(&self) -> Vec<PeerIndex> {
             unimplemented!();
         }
@@ -963,6 +1068,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_sort_peers_for_block_fetch() {
+        let mut nc = mock_network_context(4);
+        let rtts = [Some(300), Some(100), None, Some(200)];
+        for (peer, rtt) in rtts.iter().enumerate() {
+            let peer = nc.peers.get_mut(&peer.into()).expect("peer");
+            peer.rtt = rtt.map(Duration::from_millis);
+        }
+        nc.peers.get_mut(&3.into()).expect("peer").session_type = SessionType::Inbound;
+
+        let mut peers: Vec<PeerIndex> = (0..4).map(Into::into).collect();
+        sort_peers_for_block_fetch(&nc, &mut peers);
+        assert_eq!(
+            peers,
+            vec![1.into(), 0.into(), 2.into(), 3.into()] as Vec<PeerIndex>
+        );
+    }
+
     #[test]
     fn test_sync_process() {
         let _ = env_logger::try_init();
@@ -982,7 +1105,7 @@ mod tests {
 
         let locator1 = synchronizer1
             .shared
-            .get_locator(&shared1.chain_state().lock().tip_header());
+            .get_locator(&shared1.chain_state().tip_header());
 
         for i in 1..=num {
             let j = if i > 192 { i + 1 } else { i };
@@ -1075,6 +1198,124 @@ mod tests {
         );
     }
 
+    // Send the headers after the genesis of `source` to `synchronizer` from `peer`
+    fn sync_headers<CS: ChainStore>(
+        synchronizer: &Synchronizer<CS>,
+        source: &Synchronizer<CS>,
+        peer: PeerIndex,
+    ) {
+        let headers = source.shared.get_locator_response(0, &H256::zero());
+        let fbb = &mut FlatBufferBuilder::new();
+        let fbs_headers = FbsHeaders::build(fbb, &headers);
+        fbb.finish(fbs_headers, None);
+        let fbs_headers = get_root::<FbsHeaders>(fbb.finished_data());
+        HeadersProcess::new(&fbs_headers, synchronizer, peer, &mock_network_context(0))
+            .execute()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_fetch_skips_blocks_inflight_with_other_peers() {
+        let consensus = Consensus::default();
+        let (chain_controller1, shared1, _notify1) = start_chain(Some(consensus.clone()), None);
+        let (chain_controller2, shared2, _notify2) = start_chain(Some(consensus), None);
+        let num = MAX_BLOCKS_IN_TRANSIT_PER_PEER as u64 + 4;
+        for i in 1..=num {
+            insert_block(&chain_controller1, &shared1, i, i);
+        }
+
+        let synchronizer = gen_synchronizer(chain_controller2, shared2);
+        let (peer1, peer2): (PeerIndex, PeerIndex) = (1.into(), 2.into());
+        let synchronizer1 = gen_synchronizer(chain_controller1, shared1.clone());
+        sync_headers(&synchronizer, &synchronizer1, peer1);
+        sync_headers(&synchronizer, &synchronizer1, peer2);
+        let hashes: Vec<H256> = (1..=num)
+            .map(|number| shared1.block_hash(number).unwrap())
+            .collect();
+
+        // the first peer fetches until its in-flight limit
+        let blocks_to_fetch = synchronizer.get_blocks_to_fetch(peer1).unwrap();
+        assert_eq!(
+            blocks_to_fetch,
+            hashes[..MAX_BLOCKS_IN_TRANSIT_PER_PEER].to_vec()
+        );
+        assert!(synchronizer.get_blocks_to_fetch(peer1).is_none());
+
+        // the other peer skips the blocks in flight with the first one
+        let blocks_to_fetch = synchronizer.get_blocks_to_fetch(peer2).unwrap();
+        assert_eq!(
+            blocks_to_fetch,
+            hashes[MAX_BLOCKS_IN_TRANSIT_PER_PEER..].to_vec()
+        );
+        assert_eq!(
+            synchronizer.get_blocks_to_fetch(peer2).unwrap(),
+            Vec::<H256>::new()
+        );
+
+        // the blocks are fetched again once they are no longer in flight elsewhere
+        synchronizer.peers.blocks_inflight.write().remove(&peer1);
+        let blocks_to_fetch = synchronizer.get_blocks_to_fetch(peer2).unwrap();
+        assert_eq!(
+            blocks_to_fetch,
+            hashes[..MAX_BLOCKS_IN_TRANSIT_PER_PEER - 4].to_vec()
+        );
+    }
+
+    #[test]
+    fn test_sync_progress_percentage() {
+        let progress = |header_tip, block_tip| SyncProgress {
+            header_tip,
+            block_tip,
+            is_initial_block_download: false,
+        };
+        assert_eq!(progress(0, 0).percentage(), 100);
+        assert_eq!(progress(3, 0).percentage(), 0);
+        assert_eq!(progress(3, 1).percentage(), 33);
+        assert_eq!(progress(3, 3).percentage(), 100);
+        // a header tip behind the block tip never exceeds 100
+        assert_eq!(progress(3, 5).percentage(), 100);
+    }
+
+    #[test]
+    fn test_sync_progress_of_header_and_block_tips() {
+        let consensus = Consensus::default();
+        let (chain_controller1, shared1, _notify1) = start_chain(Some(consensus.clone()), None);
+        let (chain_controller2, shared2, _notify2) = start_chain(Some(consensus), None);
+        for i in 1..=8 {
+            insert_block(&chain_controller1, &shared1, i, i);
+        }
+
+        let synchronizer = gen_synchronizer(chain_controller2.clone(), shared2);
+        let progress = synchronizer.shared.sync_progress();
+        assert_eq!((progress.header_tip, progress.block_tip), (0, 0));
+        assert_eq!(progress.percentage(), 100);
+
+        // the headers are downloaded first
+        let synchronizer1 = gen_synchronizer(chain_controller1, shared1.clone());
+        sync_headers(&synchronizer, &synchronizer1, 1.into());
+        let progress = synchronizer.shared.sync_progress();
+        assert_eq!((progress.header_tip, progress.block_tip), (8, 0));
+        assert_eq!(progress.percentage(), 0);
+
+        for number in 1..=2 {
+            let block = shared1.block(&shared1.block_hash(number).unwrap()).unwrap();
+            chain_controller2
+                .process_block(Arc::new(block))
+                .expect("process block ok");
+        }
+        let progress = synchronizer.shared.sync_progress();
+        assert_eq!((progress.header_tip, progress.block_tip), (8, 2));
+        assert_eq!(progress.percentage(), 25);
+
+        // the header tip is never behind the block tip
+        synchronizer
+            .shared
+            .set_best_known_header(mock_header_view(0));
+        let progress = synchronizer.shared.sync_progress();
+        assert_eq!((progress.header_tip, progress.block_tip), (2, 2));
+        assert_eq!(progress.percentage(), 100);
+    }
+
     #[cfg(not(disable_faketime))]
     #[test]
     fn test_header_sync_timeout() {
@@ -1118,10 +1359,7 @@ mod tests {
 
         let (chain_controller, shared, _notify) = start_chain(Some(consensus), None);
 
-        assert_eq!(
-            shared.chain_state().lock().total_difficulty(),
-            &U256::from(2u64)
-        );
+        assert_eq!(shared.chain_state().total_difficulty(), &U256::from(2u64));
 
         let synchronizer = gen_synchronizer(chain_controller.clone(), shared.clone());
 
@@ -1160,7 +1398,7 @@ mod tests {
             // where we checked against our tip.
             // Either way, set a new timeout based on current tip.
             let (tip, total_difficulty) = {
-                let chain_state = shared.chain_state().lock();
+                let chain_state = shared.chain_state();
                 let header = chain_state.tip_header().clone();
                 let total_difficulty = chain_state.total_difficulty().clone();
                 (header, total_difficulty)
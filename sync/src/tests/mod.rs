@@ -1,6 +1,7 @@
 use bytes::Bytes;
 use ckb_network::{
-    Behaviour, CKBProtocolContext, CKBProtocolHandler, Peer, PeerIndex, ProtocolId, TargetSession,
+    Behaviour, CKBProtocolContext, CKBProtocolHandler, Peer, PeerIndex, PeerInfo, ProtocolId,
+    TargetSession,
 };
 use ckb_util::RwLock;
 use std::collections::HashMap;
@@ -188,6 +189,9 @@ impl CKBProtocolContext for TestNetworkContext {
     fn get_peer(&self, _peer_index: PeerIndex) -> Option<Peer> {
         None
     }
+    fn peer_info(&self, _peer_index: PeerIndex) -> Option<PeerInfo> {
+        None
+    }
     fn connected_peers(&self) -> Vec<PeerIndex> {
         self.msg_senders.keys().map(|k| k.1).collect::<Vec<_>>()
     }
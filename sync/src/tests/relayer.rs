@@ -1,4 +1,4 @@
-use crate::relayer::TX_PROPOSAL_TOKEN;
+use crate::relayer::{AcceptBlockError, TX_PROPOSAL_TOKEN};
 use crate::tests::TestNode;
 use crate::{NetworkProtocol, Relayer, SyncSharedState};
 use ckb_chain::chain::{ChainBuilder, ChainController};
@@ -11,10 +11,12 @@ use ckb_core::{capacity_bytes, Capacity};
 use ckb_db::memorydb::MemoryKeyValueDB;
 use ckb_notify::NotifyService;
 use ckb_protocol::RelayMessage;
+use ckb_shared::error::SharedError;
 use ckb_shared::shared::{Shared, SharedBuilder};
 use ckb_shared::store::ChainKVStore;
 use ckb_traits::ChainProvider;
 use ckb_util::RwLock;
+use ckb_verification::Error as VerifyError;
 use faketime::{self, unix_time_as_millis};
 use flatbuffers::get_root;
 use flatbuffers::FlatBufferBuilder;
@@ -44,9 +46,7 @@ fn relay_compact_block_with_one_tx() {
     thread::Builder::new()
         .name(thread_name)
         .spawn(move || {
-            let last_block = shared1
-                .block(&shared1.chain_state().lock().tip_hash())
-                .unwrap();
+            let last_block = shared1.block(&shared1.chain_state().tip_hash()).unwrap();
             let last_cellbase = last_block.transactions().first().unwrap();
 
             // building tx and broadcast it
@@ -65,9 +65,10 @@ fn relay_compact_block_with_one_tx() {
                 .build();
 
             {
-                let chain_state = shared1.chain_state().lock();
-                let cycles = chain_state
-                    .add_tx_to_pool(tx.clone())
+                let mut tx_pool = shared1.tx_pool().lock();
+                let cycles = shared1
+                    .chain_state()
+                    .add_tx_to_pool(&mut tx_pool, tx.clone())
                     .expect("verify relay tx");
                 let fbb = &mut FlatBufferBuilder::new();
                 let message = RelayMessage::build_transaction(fbb, &tx, cycles);
@@ -168,7 +169,7 @@ fn relay_compact_block_with_one_tx() {
     // find a solution to remove this line after pool refactoring
     thread::sleep(time::Duration::from_secs(2));
 
-    assert_eq!(shared2.chain_state().lock().tip_number(), 5);
+    assert_eq!(shared2.chain_state().tip_number(), 5);
 }
 
 #[test]
@@ -189,9 +190,7 @@ fn relay_compact_block_with_missing_indexs() {
     thread::Builder::new()
         .name(thread_name)
         .spawn(move || {
-            let last_block = shared1
-                .block(&shared1.chain_state().lock().tip_hash())
-                .unwrap();
+            let last_block = shared1.block(&shared1.chain_state().tip_hash()).unwrap();
             let last_cellbase = last_block.transactions().first().unwrap();
 
             // building 10 txs and broadcast some
@@ -216,9 +215,10 @@ fn relay_compact_block_with_missing_indexs() {
             [3, 5].iter().for_each(|i| {
                 let tx = &txs[*i];
                 let cycles = {
-                    let chain_state = shared1.chain_state().lock();
-                    chain_state
-                        .add_tx_to_pool(tx.clone())
+                    let mut tx_pool = shared1.tx_pool().lock();
+                    shared1
+                        .chain_state()
+                        .add_tx_to_pool(&mut tx_pool, tx.clone())
                         .expect("verify relay tx")
                 };
                 let fbb = &mut FlatBufferBuilder::new();
@@ -313,7 +313,34 @@ fn relay_compact_block_with_missing_indexs() {
     // Wait node2 receive transaction and block from node1
     let _ = signal_rx2.recv();
 
-    assert_eq!(shared2.chain_state().lock().tip_number(), 5);
+    assert_eq!(shared2.chain_state().tip_number(), 5);
+}
+
+#[test]
+fn accept_block_error_category() {
+    let unknown_parent = AcceptBlockError::from_process_block_error(
+        VerifyError::UnknownParent(Default::default()).into(),
+    );
+    match unknown_parent {
+        AcceptBlockError::ParentUnknown => {}
+        err => panic!("unexpected {:?}", err),
+    }
+
+    let invalid =
+        AcceptBlockError::from_process_block_error(VerifyError::CommitTransactionsRoot.into());
+    assert!(invalid.is_misbehavior());
+    let invalid_parent =
+        AcceptBlockError::from_process_block_error(SharedError::InvalidParentBlock.into());
+    assert!(invalid_parent.is_misbehavior());
+
+    let internal = AcceptBlockError::from_process_block_error(
+        SharedError::InvalidData("corrupted".to_string()).into(),
+    );
+    match internal {
+        AcceptBlockError::Internal(_) => {}
+        ref err => panic!("unexpected {:?}", err),
+    }
+    assert!(!internal.is_misbehavior());
 }
 
 fn setup_node(
@@ -384,6 +411,7 @@ fn setup_node(
         chain_controller.clone(),
         sync_shared_state,
         Arc::new(Default::default()),
+        Default::default(),
     );
 
     let mut node = TestNode::default();
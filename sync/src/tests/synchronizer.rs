@@ -56,10 +56,10 @@ fn basic_sync() {
     // Wait node1 receive block from node2
     let _ = signal_rx1.recv();
 
-    assert_eq!(shared1.chain_state().lock().tip_number(), 3);
+    assert_eq!(shared1.chain_state().tip_number(), 3);
     assert_eq!(
-        shared1.chain_state().lock().tip_number(),
-        shared2.chain_state().lock().tip_number()
+        shared1.chain_state().tip_number(),
+        shared2.chain_state().tip_number()
     );
 }
 
@@ -9,9 +9,11 @@ use ckb_protocol::SyncMessage;
 use ckb_shared::chain_state::ChainState;
 use ckb_shared::shared::Shared;
 use ckb_shared::store::ChainStore;
-use ckb_traits::ChainProvider;
+use ckb_shared::tx_pool::TxPool;
+use ckb_traits::{skip_number, ChainProvider, HeaderProvider};
 use ckb_util::Mutex;
 use ckb_util::RwLock;
+use ckb_verification::ALLOWED_FUTURE_BLOCKTIME;
 use faketime::unix_time_as_millis;
 use flatbuffers::FlatBufferBuilder;
 use fnv::{FnvHashMap, FnvHashSet};
@@ -21,8 +23,10 @@ use numext_fixed_hash::H256;
 use numext_fixed_uint::U256;
 use std::cmp;
 use std::collections::hash_map::{Entry, HashMap};
+use std::sync::Arc;
 
 const FILTER_SIZE: usize = 20000;
+const MAX_CHAIN_TIPS: usize = 64;
 
 // State used to enforce CHAIN_SYNC_TIMEOUT
 // Only in effect for outbound, non-manual connections, with
@@ -129,6 +133,10 @@ impl BlocksInflight {
         self.blocks.insert(hash)
     }
 
+    pub fn contains(&self, hash: &H256) -> bool {
+        self.blocks.contains(hash)
+    }
+
     pub fn remove(&mut self, hash: &H256) -> bool {
         self.blocks.remove(hash)
     }
@@ -228,6 +236,8 @@ pub struct HeaderView {
     inner: Header,
     total_difficulty: U256,
     total_uncles_count: u64,
+    /// Set when the view is inserted into the header map, see `ckb_traits::skip_number`
+    skip_hash: Option<H256>,
 }
 
 impl HeaderView {
@@ -236,6 +246,7 @@ impl HeaderView {
             inner,
             total_difficulty,
             total_uncles_count,
+            skip_hash: None,
         }
     }
 
@@ -264,16 +275,47 @@ impl HeaderView {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncProgress {
+    pub header_tip: BlockNumber,
+    pub block_tip: BlockNumber,
+    pub is_initial_block_download: bool,
+}
+
+impl SyncProgress {
+    /// Percentage of the best known header chain that has been fully downloaded
+    pub fn percentage(&self) -> u8 {
+        if self.header_tip == 0 {
+            return 100;
+        }
+        cmp::min(self.block_tip * 100 / self.header_tip, 100) as u8
+    }
+}
+
+/// A chain tip announced by peers, i.e. a known header without known children
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainTip {
+    pub hash: H256,
+    pub number: BlockNumber,
+    pub total_difficulty: U256,
+    /// Unix time in milliseconds when the tip was first seen
+    pub first_seen_at: u64,
+    pub first_seen_peer: PeerIndex,
+}
+
 pub struct SyncSharedState<CS> {
     shared: Shared<CS>,
     header_map: RwLock<HashMap<H256, HeaderView>>,
     best_known_header: RwLock<HeaderView>,
+    chain_tips: RwLock<FnvHashMap<H256, ChainTip>>,
+    max_future_block_time: u64,
+    stem_hops: u8,
 }
 
 impl<CS: ChainStore> SyncSharedState<CS> {
     pub fn new(shared: Shared<CS>) -> SyncSharedState<CS> {
         let (total_difficulty, header, total_uncles_count) = {
-            let chain_state = shared.chain_state().lock();
+            let chain_state = shared.chain_state();
             let block_ext = shared
                 .block_ext(&chain_state.tip_hash())
                 .expect("tip block_ext must exist");
@@ -293,15 +335,41 @@ impl<CS: ChainStore> SyncSharedState<CS> {
             shared,
             header_map,
             best_known_header,
+            chain_tips: RwLock::new(FnvHashMap::default()),
+            max_future_block_time: ALLOWED_FUTURE_BLOCKTIME,
+            stem_hops: 0,
         }
     }
 
+    /// Override how far ahead of the local clock a header timestamp may be
+    pub fn with_max_future_block_time(mut self, max_future_block_time: u64) -> Self {
+        self.max_future_block_time = max_future_block_time;
+        self
+    }
+
+    pub fn max_future_block_time(&self) -> u64 {
+        self.max_future_block_time
+    }
+
+    /// Set the number of stem hops of locally submitted transactions, see `RelayConfig`
+    pub fn with_stem_hops(mut self, stem_hops: u8) -> Self {
+        self.stem_hops = stem_hops;
+        self
+    }
+
+    pub fn stem_hops(&self) -> u8 {
+        self.stem_hops
+    }
+
     pub fn shared(&self) -> &Shared<CS> {
         &self.shared
     }
-    pub fn chain_state(&self) -> &Mutex<ChainState<CS>> {
+    pub fn chain_state(&self) -> Arc<ChainState<CS>> {
         self.shared.chain_state()
     }
+    pub fn tx_pool(&self) -> &Mutex<TxPool> {
+        self.shared.tx_pool()
+    }
     pub fn block_header(&self, hash: &H256) -> Option<Header> {
         self.shared.block_header(hash)
     }
@@ -315,17 +383,28 @@ impl<CS: ChainStore> SyncSharedState<CS> {
         self.shared.block(hash)
     }
     pub fn tip_header(&self) -> Header {
-        self.shared.chain_state().lock().tip_header().clone()
+        self.shared.chain_state().tip_header().clone()
     }
     pub fn consensus(&self) -> &Consensus {
         self.shared.consensus()
     }
     pub fn is_initial_block_download(&self) -> bool {
-        unix_time_as_millis()
-            .saturating_sub(self.shared.chain_state().lock().tip_header().timestamp())
+        unix_time_as_millis().saturating_sub(self.shared.chain_state().tip_header().timestamp())
             > MAX_TIP_AGE
     }
 
+    /// Compare the stored block tip with the best known header tip, the headers are
+    /// downloaded first during IBD so the gap between them is the remaining work.
+    pub fn sync_progress(&self) -> SyncProgress {
+        let block_tip = self.shared.chain_state().tip_number();
+        let header_tip = cmp::max(self.best_known_header().number(), block_tip);
+        SyncProgress {
+            header_tip,
+            block_tip,
+            is_initial_block_download: self.is_initial_block_download(),
+        }
+    }
+
     pub fn best_known_header(&self) -> HeaderView {
         self.best_known_header.read().clone()
     }
@@ -333,7 +412,49 @@ impl<CS: ChainStore> SyncSharedState<CS> {
         *self.best_known_header.write() = header;
     }
 
-    pub fn insert_header_view(&self, hash: H256, header: HeaderView) {
+    /// Record `header` as a chain tip replacing its parent, the peer first sent it is kept
+    pub fn record_chain_tip(&self, header: &HeaderView, peer: PeerIndex) {
+        let mut chain_tips = self.chain_tips.write();
+        chain_tips.remove(header.inner().parent_hash());
+        chain_tips.entry(header.hash()).or_insert_with(|| ChainTip {
+            hash: header.hash(),
+            number: header.number(),
+            total_difficulty: header.total_difficulty().clone(),
+            first_seen_at: unix_time_as_millis(),
+            first_seen_peer: peer,
+        });
+        if chain_tips.len() > MAX_CHAIN_TIPS {
+            // forget the tip with the least work
+            let weakest = chain_tips
+                .values()
+                .min_by(|a, b| a.total_difficulty.cmp(&b.total_difficulty))
+                .map(|tip| tip.hash.clone());
+            if let Some(hash) = weakest {
+                chain_tips.remove(&hash);
+            }
+        }
+    }
+
+    /// Known chain tips, the ones with most work first
+    pub fn chain_tips(&self) -> Vec<ChainTip> {
+        let mut chain_tips: Vec<ChainTip> = self.chain_tips.read().values().cloned().collect();
+        chain_tips.sort_by(|a, b| b.total_difficulty.cmp(&a.total_difficulty));
+        chain_tips
+    }
+
+    /// The last block of the main chain `hash` descends from, None if some header is unknown
+    pub fn fork_point(&self, hash: &H256) -> Option<Header> {
+        let mut header = self.get_header(hash)?;
+        while self.block_hash(header.number()).as_ref() != Some(&header.hash()) {
+            header = self.get_header(header.parent_hash())?;
+        }
+        Some(header)
+    }
+
+    pub fn insert_header_view(&self, hash: H256, mut header: HeaderView) {
+        header.skip_hash = self
+            .get_ancestor(header.inner().parent_hash(), skip_number(header.number()))
+            .map(|skip| skip.hash());
         self.header_map.write().insert(hash, header);
     }
     pub fn remove_header_view(&self, hash: &H256) {
@@ -361,27 +482,6 @@ impl<CS: ChainStore> SyncSharedState<CS> {
             .or_else(|| self.shared.block_header(hash))
     }
 
-    pub fn get_ancestor(&self, base: &H256, number: BlockNumber) -> Option<Header> {
-        if let Some(header) = self.get_header(base) {
-            let mut n_number = header.number();
-            let mut index_walk = header;
-            if number > n_number {
-                return None;
-            }
-
-            while n_number > number {
-                if let Some(header) = self.get_header(&index_walk.parent_hash()) {
-                    index_walk = header;
-                    n_number -= 1;
-                } else {
-                    return None;
-                }
-            }
-            return Some(index_walk);
-        }
-        None
-    }
-
     pub fn get_locator(&self, start: &Header) -> Vec<H256> {
         let mut step = 1;
         let mut locator = Vec::with_capacity(32);
@@ -417,22 +517,7 @@ impl<CS: ChainStore> SyncSharedState<CS> {
         best_known_header: &Header,
     ) -> Option<Header> {
         debug_assert!(best_known_header.number() >= last_common_header.number());
-
-        let mut m_right =
-            self.get_ancestor(&best_known_header.hash(), last_common_header.number())?;
-
-        if &m_right == last_common_header {
-            return Some(m_right);
-        }
-
-        let mut m_left = self.get_header(&last_common_header.hash())?;
-        debug_assert!(m_right.number() == m_left.number());
-
-        while m_left != m_right {
-            m_left = self.get_ancestor(&m_left.hash(), m_left.number() - 1)?;
-            m_right = self.get_ancestor(&m_right.hash(), m_right.number() - 1)?;
-        }
-        Some(m_left)
+        self.latest_common_ancestor(last_common_header, best_known_header)
     }
 
     pub fn locate_latest_common_block(
@@ -513,3 +598,16 @@ impl<CS: ChainStore> SyncSharedState<CS> {
         );
     }
 }
+
+impl<CS: ChainStore> HeaderProvider for SyncSharedState<CS> {
+    fn header(&self, hash: &H256) -> Option<Header> {
+        self.get_header(hash)
+    }
+
+    fn skip_hash(&self, hash: &H256) -> Option<H256> {
+        match self.header_map.read().get(hash) {
+            Some(header) => header.skip_hash.clone(),
+            None => self.shared.store().skip_hash(hash),
+        }
+    }
+}
@@ -50,7 +50,18 @@ impl Net {
                     .expect("invalid address")],
                 public_addresses: vec![],
                 bootnodes: vec![],
+                dns_seeds: vec![],
+                dns_seed_pubkeys: vec![],
+                network_magic: 0,
+                ignore_spec_seeds: true,
                 reserved_peers: vec![],
+                allow_outbound_same_group: false,
+                min_protocol_versions: Default::default(),
+                block_relay_only_peers: 0,
+                discovery_local_address: true,
+                max_peer_store_addrs: 16384,
+                idle_timeout_secs: 1800,
+                rate_limits: Vec::new(),
                 reserved_only: false,
                 max_peers: 1,
                 max_outbound_peers: 1,
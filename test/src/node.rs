@@ -7,6 +7,7 @@ use ckb_core::header::{HeaderBuilder, Seal};
 use ckb_core::script::Script;
 use ckb_core::transaction::{CellInput, CellOutput, OutPoint, Transaction, TransactionBuilder};
 use ckb_core::{capacity_bytes, BlockNumber, Capacity};
+use ckb_db::DBBackend;
 use jsonrpc_client_http::{HttpHandle, HttpTransport};
 use jsonrpc_types::{BlockTemplate, CellbaseTemplate};
 use log::info;
@@ -121,9 +122,10 @@ impl Node {
     // workaround: submit_pow_solution rpc doesn't working since miner is running as a standalone process
     // TODO: remove clicker pow engine and cleanup rpc
     pub fn generate_block(&self) -> H256 {
+        let (work_id, block) = self.new_block();
         let result = self
             .rpc_client()
-            .submit_block("".to_owned(), (&self.new_block()).into())
+            .submit_block(work_id, (&block).into())
             .call()
             .expect("rpc call submit_block failed");
         result.expect("submit_block result none")
@@ -172,10 +174,11 @@ impl Node {
             .expect("block")
     }
 
-    pub fn new_block(&self) -> Block {
+    /// A block built from a new template, with the work_id to submit it with
+    pub fn new_block(&self) -> (String, Block) {
         let template = self
             .rpc_client()
-            .get_block_template(None, None, None)
+            .get_block_template(None, None, None, None)
             .call()
             .expect("rpc call get_block_template failed");
 
@@ -189,6 +192,7 @@ impl Node {
             transactions, // Vec<TransactionTemplate>
             proposals,    // Vec<ProposalShortId>
             cellbase,     // CellbaseTemplate
+            work_id,
             ..
         } = template;
 
@@ -213,7 +217,7 @@ impl Node {
             .parent_hash(parent_hash)
             .seal(Seal::new(rand::random(), Vec::new()));
 
-        BlockBuilder::default()
+        let block = BlockBuilder::default()
             .uncles(
                 uncles
                     .into_iter()
@@ -236,7 +240,8 @@ impl Node {
                     .collect::<Result<_, _>>()
                     .expect("parse proposal transactions failed"),
             )
-            .with_header_builder(header_builder)
+            .with_header_builder(header_builder);
+        (work_id, block)
     }
 
     pub fn new_transaction(&self, hash: H256) -> Transaction {
@@ -283,6 +288,8 @@ impl Node {
         let mut ckb_config: CKBAppConfig =
             toml::from_slice(&fs::read(&ckb_config_path)?).expect("ckb config");
         ckb_config.chain.spec = config_path.into();
+        // the nodes are never restarted, keep their chains off the disk
+        ckb_config.db.backend = DBBackend::Memory;
         fs::write(
             &ckb_config_path,
             toml::to_string(&ckb_config).expect("ckb config serialize"),
@@ -48,6 +48,24 @@ impl Spec for MiningBasic {
             .transactions()
             .iter()
             .any(|tx| transaction_hash.eq(&tx.hash())));
+
+        info!("Block submitted with an unknown work id should be rejected");
+        let (_work_id, block) = node.new_block();
+        assert!(node
+            .rpc_client()
+            .submit_block("unknown".to_owned(), (&block).into())
+            .call()
+            .is_err());
+
+        info!("Block built from an older work should be accepted after the pool changed");
+        let (work_id, block) = node.new_block();
+        node.generate_transaction();
+        let block_hash = node
+            .rpc_client()
+            .submit_block(work_id, (&block).into())
+            .call()
+            .expect("rpc call submit_block failed");
+        assert_eq!(block_hash, Some(block.header().hash()));
     }
 
     fn num_nodes(&self) -> usize {
@@ -93,8 +93,16 @@ impl Spec for PoolTrace {
             .cloned()
             .collect();
 
-        info!("TxTrace actions should contains AddPending and Committed");
-        assert!(actions.contains(&Action::AddPending) && actions.contains(&Action::Committed));
+        info!("TxTrace actions should contains Received, Verified, AddPending, Proposed and Committed");
+        for action in &[
+            Action::Received,
+            Action::Verified,
+            Action::AddPending,
+            Action::Proposed,
+            Action::Committed,
+        ] {
+            assert!(actions.contains(action), "{:?} is not traced", action);
+        }
     }
 
     fn num_nodes(&self) -> usize {
@@ -0,0 +1,74 @@
+use ckb_core::header::{BlockNumber, Header};
+use numext_fixed_hash::H256;
+
+/// Turn the lowest one bit of `number` into zero
+fn invert_lowest_one(number: BlockNumber) -> BlockNumber {
+    number & number.saturating_sub(1)
+}
+
+/// The number of the ancestor a block at `number` keeps a skip pointer to.
+///
+/// Any number below is reached in O(log n) jumps, following the skip list of bitcoin.
+pub fn skip_number(number: BlockNumber) -> BlockNumber {
+    if number < 2 {
+        0
+    } else if number & 1 == 1 {
+        invert_lowest_one(invert_lowest_one(number - 1)) + 1
+    } else {
+        invert_lowest_one(number)
+    }
+}
+
+/// Headers linked by their parents and by skip pointers to older ancestors, see `skip_number`
+pub trait HeaderProvider {
+    fn header(&self, hash: &H256) -> Option<Header>;
+
+    /// The hash of the ancestor at `skip_number` of the block `hash`, `None` if it is not
+    /// recorded and the lookups go through the parent
+    fn skip_hash(&self, hash: &H256) -> Option<H256>;
+
+    /// The ancestor of `base` at `number`, `None` if `number` is above `base` or some header
+    /// on the way is unknown
+    fn get_ancestor(&self, base: &H256, number: BlockNumber) -> Option<Header> {
+        let mut header = self.header(base)?;
+        if number > header.number() {
+            return None;
+        }
+        while header.number() > number {
+            let current = header.number();
+            let skip = skip_number(current);
+            let skip_prev = skip_number(current - 1);
+            // only jump if the parent doesn't jump to a better position
+            let jump =
+                skip == number || (skip > number && !(skip_prev + 2 < skip && skip_prev >= number));
+            let next_hash = match self.skip_hash(&header.hash()) {
+                Some(skip_hash) if jump => skip_hash,
+                _ => header.parent_hash().to_owned(),
+            };
+            header = self.header(&next_hash)?;
+        }
+        Some(header)
+    }
+
+    /// The latest block both `a` and `b` descend from, `None` if some header on the way is
+    /// unknown
+    fn latest_common_ancestor(&self, a: &Header, b: &Header) -> Option<Header> {
+        let (mut a, mut b) = if a.number() > b.number() {
+            (self.get_ancestor(&a.hash(), b.number())?, b.to_owned())
+        } else {
+            (a.to_owned(), self.get_ancestor(&b.hash(), a.number())?)
+        };
+        while a != b {
+            // both are at the same number, the common ancestor is below their skip
+            // ancestors if those differ
+            let (a_skip, b_skip) = (self.skip_hash(&a.hash()), self.skip_hash(&b.hash()));
+            let (a_next, b_next) = match (a_skip, b_skip) {
+                (Some(a_skip), Some(b_skip)) if a_skip != b_skip => (a_skip, b_skip),
+                _ => (a.parent_hash().to_owned(), b.parent_hash().to_owned()),
+            };
+            a = self.header(&a_next)?;
+            b = self.header(&b_next)?;
+        }
+        Some(a)
+    }
+}
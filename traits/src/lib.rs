@@ -1,5 +1,7 @@
 pub mod block_median_time_context;
 pub mod chain_provider;
+pub mod header_provider;
 
 pub use crate::block_median_time_context::BlockMedianTimeContext;
 pub use crate::chain_provider::ChainProvider;
+pub use crate::header_provider::{skip_number, HeaderProvider};
@@ -16,8 +16,10 @@ use ckb_miner::MinerConfig;
 use ckb_network::NetworkConfig;
 use ckb_resource::{Resource, ResourceLocator};
 use ckb_rpc::Config as RpcConfig;
+use ckb_shared::store::StoreConfig;
 use ckb_shared::tx_pool::TxPoolConfig;
-use ckb_sync::Config as SyncConfig;
+use ckb_sync::{Config as SyncConfig, RelayConfig};
+use ckb_verification::VerificationConfig;
 use logger::Config as LogConfig;
 
 use super::sentry_config::SentryConfig;
@@ -42,12 +44,18 @@ pub struct CKBAppConfig {
     pub chain: ChainConfig,
 
     pub block_assembler: BlockAssemblerConfig,
-    #[serde(skip)]
+    #[serde(default)]
     pub db: DBConfig,
     pub network: NetworkConfig,
     pub rpc: RpcConfig,
     pub sync: SyncConfig,
     pub tx_pool: TxPoolConfig,
+    #[serde(default)]
+    pub relay: RelayConfig,
+    #[serde(default)]
+    pub verification: VerificationConfig,
+    #[serde(default)]
+    pub store: StoreConfig,
 }
 
 // change the order of fields will break integration test, see module doc.
@@ -165,6 +173,14 @@ impl CKBAppConfig {
         }
         self.db.path = mkdir(self.data_dir.join("db"))?;
         self.network.path = mkdir(self.data_dir.join("network"))?;
+        self.network.validate().map_err(|err| {
+            eprintln!("Config Error: {}", err);
+            ExitCode::Config
+        })?;
+        self.rpc.validate().map_err(|err| {
+            eprintln!("Config Error: {}", err);
+            ExitCode::Config
+        })?;
 
         Ok(self)
     }
@@ -236,6 +252,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_reject_malformed_min_protocol_version() {
+        let dir = mkdir();
+        let locator = ResourceLocator::with_root_dir(dir.path().to_path_buf()).unwrap();
+        let app_config = AppConfig::load_for_subcommand(&locator, cli::CMD_RUN)
+            .unwrap_or_else(|err| panic!(err));
+        let mut ckb_config = app_config.into_ckb().unwrap_or_else(|err| panic!(err));
+        assert!(ckb_config
+            .network
+            .set_min_protocol_versions(vec![(0, "1".to_owned()), (1, "1.x".to_owned())])
+            .is_err());
+        assert!(ckb_config.network.min_protocol_versions.is_empty());
+        ckb_config
+            .network
+            .set_min_protocol_versions(vec![(0, "1.2".to_owned())])
+            .unwrap();
+        assert_eq!(ckb_config.network.min_protocol_version(0), Some("1.2"));
+    }
+
     #[test]
     fn test_miner_toml() {
         let dir = mkdir();
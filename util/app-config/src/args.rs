@@ -2,6 +2,7 @@ use super::app_config::CKBAppConfig;
 use ckb_chain_spec::consensus::Consensus;
 use ckb_instrument::Format;
 use ckb_miner::MinerConfig;
+use ckb_network::NetworkConfig;
 use ckb_pow::PowEngine;
 use ckb_resource::ResourceLocator;
 use std::path::PathBuf;
@@ -12,6 +13,8 @@ pub struct ExportArgs {
     pub consensus: Consensus,
     pub format: Format,
     pub target: PathBuf,
+    pub from: u64,
+    pub to: Option<u64>,
 }
 
 pub struct ImportArgs {
@@ -19,6 +22,37 @@ pub struct ImportArgs {
     pub consensus: Consensus,
     pub format: Format,
     pub source: PathBuf,
+    /// Only verify the headers of the imported blocks
+    pub fast: bool,
+}
+
+pub struct ExportAddressesArgs {
+    pub config: NetworkConfig,
+    pub target: PathBuf,
+}
+
+pub struct ImportAddressesArgs {
+    pub config: NetworkConfig,
+    pub source: PathBuf,
+}
+
+pub struct ExportSnapshotArgs {
+    pub config: Box<CKBAppConfig>,
+    pub consensus: Consensus,
+    pub target: PathBuf,
+}
+
+pub struct ImportSnapshotArgs {
+    pub config: Box<CKBAppConfig>,
+    pub consensus: Consensus,
+    pub source: PathBuf,
+    /// Hex hash of the snapshot tip block
+    pub checkpoint: String,
+}
+
+pub struct VerifyStoreArgs {
+    pub config: Box<CKBAppConfig>,
+    pub repair: bool,
 }
 
 pub struct RunArgs {
@@ -6,6 +6,11 @@ pub const CMD_RUN: &str = "run";
 pub const CMD_MINER: &str = "miner";
 pub const CMD_EXPORT: &str = "export";
 pub const CMD_IMPORT: &str = "import";
+pub const CMD_EXPORT_ADDRESSES: &str = "export-addresses";
+pub const CMD_IMPORT_ADDRESSES: &str = "import-addresses";
+pub const CMD_EXPORT_SNAPSHOT: &str = "export-snapshot";
+pub const CMD_IMPORT_SNAPSHOT: &str = "import-snapshot";
+pub const CMD_VERIFY_STORE: &str = "verify-store";
 pub const CMD_INIT: &str = "init";
 pub const CMD_CLI: &str = "cli";
 pub const CMD_KEYGEN: &str = "keygen";
@@ -22,6 +27,11 @@ pub const ARG_P2P_PORT: &str = "p2p-port";
 pub const ARG_RPC_PORT: &str = "rpc-port";
 pub const ARG_FORCE: &str = "force";
 pub const ARG_LOG_TO: &str = "log-to";
+pub const ARG_CHECKPOINT: &str = "checkpoint";
+pub const ARG_FROM: &str = "from";
+pub const ARG_TO: &str = "to";
+pub const ARG_FAST: &str = "fast";
+pub const ARG_REPAIR: &str = "repair";
 
 pub fn get_matches() -> ArgMatches<'static> {
     let version = get_version!();
@@ -46,6 +56,11 @@ pub fn get_matches() -> ArgMatches<'static> {
         .subcommand(miner())
         .subcommand(export())
         .subcommand(import())
+        .subcommand(export_addresses())
+        .subcommand(import_addresses())
+        .subcommand(export_snapshot())
+        .subcommand(import_snapshot())
+        .subcommand(verify_store())
         .subcommand(cli())
         .subcommand(init())
         .subcommand(prof())
@@ -104,6 +119,20 @@ fn export() -> App<'static, 'static> {
                 .index(1)
                 .help("Specify the export target path."),
         )
+        .arg(
+            Arg::with_name(ARG_FROM)
+                .long(ARG_FROM)
+                .value_name("number")
+                .takes_value(true)
+                .help("Export the blocks from this number, the genesis by default."),
+        )
+        .arg(
+            Arg::with_name(ARG_TO)
+                .long(ARG_TO)
+                .value_name("number")
+                .takes_value(true)
+                .help("Export the blocks up to this number, the tip by default."),
+        )
 }
 
 fn import() -> App<'static, 'static> {
@@ -119,6 +148,85 @@ fn import() -> App<'static, 'static> {
                 .index(1)
                 .help("Specify the exported data path."),
         )
+        .arg(
+            Arg::with_name(ARG_FAST)
+                .long(ARG_FAST)
+                .help("Only verify the block headers, trust the rest of the exported blocks."),
+        )
+}
+
+fn export_addresses() -> App<'static, 'static> {
+    SubCommand::with_name(CMD_EXPORT_ADDRESSES)
+        .about("Export peer addresses in the peer store as JSON lines")
+        .arg(
+            Arg::with_name(ARG_TARGET)
+                .short("t")
+                .long(ARG_TARGET)
+                .value_name("path")
+                .required(true)
+                .index(1)
+                .help("Specify the export target path."),
+        )
+}
+
+fn import_addresses() -> App<'static, 'static> {
+    SubCommand::with_name(CMD_IMPORT_ADDRESSES)
+        .about("Import peer addresses exported by export-addresses into the peer store")
+        .arg(
+            Arg::with_name(ARG_SOURCE)
+                .short("s")
+                .long(ARG_SOURCE)
+                .value_name("path")
+                .required(true)
+                .index(1)
+                .help("Specify the exported addresses path."),
+        )
+}
+
+fn export_snapshot() -> App<'static, 'static> {
+    SubCommand::with_name(CMD_EXPORT_SNAPSHOT)
+        .about("Export the chain state at the tip for import-snapshot")
+        .arg(
+            Arg::with_name(ARG_TARGET)
+                .short("t")
+                .long(ARG_TARGET)
+                .value_name("path")
+                .required(true)
+                .index(1)
+                .help("Specify the export target path."),
+        )
+}
+
+fn import_snapshot() -> App<'static, 'static> {
+    SubCommand::with_name(CMD_IMPORT_SNAPSHOT)
+        .about("Import a chain state exported by export-snapshot into a new database")
+        .arg(
+            Arg::with_name(ARG_SOURCE)
+                .short("s")
+                .long(ARG_SOURCE)
+                .value_name("path")
+                .required(true)
+                .index(1)
+                .help("Specify the exported snapshot path."),
+        )
+        .arg(
+            Arg::with_name(ARG_CHECKPOINT)
+                .long(ARG_CHECKPOINT)
+                .value_name("hash")
+                .required(true)
+                .takes_value(true)
+                .help("Specify the trusted hash of the snapshot tip block, in hex."),
+        )
+}
+
+fn verify_store() -> App<'static, 'static> {
+    SubCommand::with_name(CMD_VERIFY_STORE)
+        .about("Check the database is consistent with the stored chain")
+        .arg(
+            Arg::with_name(ARG_REPAIR)
+                .long(ARG_REPAIR)
+                .help("Fix the inconsistencies which can be rebuilt from the stored blocks."),
+        )
 }
 
 fn cli() -> App<'static, 'static> {
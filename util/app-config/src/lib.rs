@@ -5,7 +5,10 @@ mod exit_code;
 mod sentry_config;
 
 pub use app_config::{AppConfig, CKBAppConfig, MinerAppConfig};
-pub use args::{ExportArgs, ImportArgs, InitArgs, MinerArgs, ProfArgs, RunArgs};
+pub use args::{
+    ExportAddressesArgs, ExportArgs, ExportSnapshotArgs, ImportAddressesArgs, ImportArgs,
+    ImportSnapshotArgs, InitArgs, MinerArgs, ProfArgs, RunArgs, VerifyStoreArgs,
+};
 pub use exit_code::ExitCode;
 
 use ckb_chain_spec::{consensus::Consensus, ChainSpec};
@@ -92,8 +95,29 @@ impl Setup {
     }
 
     pub fn run(self) -> Result<RunArgs, ExitCode> {
-        let consensus = self.consensus()?;
-        let config = self.config.into_ckb()?;
+        let spec = self.chain_spec()?;
+        let consensus = self.consensus_from(&spec)?;
+        let mut config = self.config.into_ckb()?;
+        config
+            .network
+            .merge_spec_seeds(&spec.bootnodes, &spec.dns_seeds, &spec.dns_seed_pubkeys)
+            .map_err(|err| {
+                eprintln!("Invalid bootnodes in chain spec: {:?}", err);
+                ExitCode::Config
+            })?;
+        config.network.network_magic = network_magic(&consensus);
+        config
+            .network
+            .set_min_protocol_versions(
+                spec.min_protocol_versions
+                    .iter()
+                    .map(|min| (min.protocol_id, min.version.clone()))
+                    .collect(),
+            )
+            .map_err(|err| {
+                eprintln!("Invalid min protocol versions in chain spec: {:?}", err);
+                ExitCode::Config
+            })?;
 
         Ok(RunArgs { config, consensus })
     }
@@ -128,12 +152,14 @@ impl Setup {
         let config = self.config.into_ckb()?;
         let format = value_t!(matches.value_of(cli::ARG_FORMAT), Format)?;
         let source = value_t!(matches.value_of(cli::ARG_SOURCE), PathBuf)?;
+        let fast = matches.is_present(cli::ARG_FAST);
 
         Ok(ImportArgs {
             config,
             consensus,
             format,
             source,
+            fast,
         })
     }
 
@@ -142,15 +168,90 @@ impl Setup {
         let config = self.config.into_ckb()?;
         let format = value_t!(matches.value_of(cli::ARG_FORMAT), Format)?;
         let target = value_t!(matches.value_of(cli::ARG_TARGET), PathBuf)?;
+        let from = if matches.is_present(cli::ARG_FROM) {
+            value_t!(matches.value_of(cli::ARG_FROM), u64)?
+        } else {
+            0
+        };
+        let to = if matches.is_present(cli::ARG_TO) {
+            Some(value_t!(matches.value_of(cli::ARG_TO), u64)?)
+        } else {
+            None
+        };
 
         Ok(ExportArgs {
             config,
             consensus,
             format,
             target,
+            from,
+            to,
+        })
+    }
+
+    pub fn export_addresses<'m>(
+        self,
+        matches: &ArgMatches<'m>,
+    ) -> Result<ExportAddressesArgs, ExitCode> {
+        let config = self.config.into_ckb()?.network;
+        let target = value_t!(matches.value_of(cli::ARG_TARGET), PathBuf)?;
+
+        Ok(ExportAddressesArgs { config, target })
+    }
+
+    pub fn import_addresses<'m>(
+        self,
+        matches: &ArgMatches<'m>,
+    ) -> Result<ImportAddressesArgs, ExitCode> {
+        let config = self.config.into_ckb()?.network;
+        let source = value_t!(matches.value_of(cli::ARG_SOURCE), PathBuf)?;
+
+        Ok(ImportAddressesArgs { config, source })
+    }
+
+    pub fn export_snapshot<'m>(
+        self,
+        matches: &ArgMatches<'m>,
+    ) -> Result<ExportSnapshotArgs, ExitCode> {
+        let consensus = self.consensus()?;
+        let config = self.config.into_ckb()?;
+        let target = value_t!(matches.value_of(cli::ARG_TARGET), PathBuf)?;
+
+        Ok(ExportSnapshotArgs {
+            config,
+            consensus,
+            target,
         })
     }
 
+    pub fn import_snapshot<'m>(
+        self,
+        matches: &ArgMatches<'m>,
+    ) -> Result<ImportSnapshotArgs, ExitCode> {
+        let consensus = self.consensus()?;
+        let config = self.config.into_ckb()?;
+        let source = value_t!(matches.value_of(cli::ARG_SOURCE), PathBuf)?;
+        let checkpoint = matches
+            .value_of(cli::ARG_CHECKPOINT)
+            .unwrap()
+            .trim_start_matches("0x")
+            .to_string();
+
+        Ok(ImportSnapshotArgs {
+            config,
+            consensus,
+            source,
+            checkpoint,
+        })
+    }
+
+    pub fn verify_store<'m>(self, matches: &ArgMatches<'m>) -> Result<VerifyStoreArgs, ExitCode> {
+        let config = self.config.into_ckb()?;
+        let repair = matches.is_present(cli::ARG_REPAIR);
+
+        Ok(VerifyStoreArgs { config, repair })
+    }
+
     pub fn init<'m>(matches: &ArgMatches<'m>) -> Result<InitArgs, ExitCode> {
         let locator = locator_from_matches(matches)?;
         let export_specs = matches.is_present(cli::ARG_EXPORT_SPECS);
@@ -194,7 +295,11 @@ impl Setup {
     }
 
     fn consensus(&self) -> Result<Consensus, ExitCode> {
-        let result = consensus_from_spec(&self.chain_spec()?);
+        self.consensus_from(&self.chain_spec()?)
+    }
+
+    fn consensus_from(&self, spec: &ChainSpec) -> Result<Consensus, ExitCode> {
+        let result = consensus_from_spec(spec);
 
         if let Ok(consensus) = &result {
             if self.is_sentry_enabled {
@@ -216,6 +321,13 @@ fn is_daemon(subcommand_name: &str) -> bool {
     }
 }
 
+// The first 4 bytes of the genesis hash
+fn network_magic(consensus: &Consensus) -> u32 {
+    let mut magic = [0u8; 4];
+    magic.copy_from_slice(&consensus.genesis_hash().as_bytes()[..4]);
+    u32::from_le_bytes(magic)
+}
+
 fn consensus_from_spec(spec: &ChainSpec) -> Result<Consensus, ExitCode> {
     spec.to_consensus().and_then(verify_genesis).map_err(|err| {
         eprintln!("{:?}", err);
@@ -1,11 +1,11 @@
 use crate::format::Format;
 use crate::iter::ChainIterator;
+use ckb_core::BlockNumber;
 use ckb_shared::shared::Shared;
 use ckb_shared::store::ChainStore;
 use ckb_traits::ChainProvider;
 #[cfg(feature = "progress_bar")]
 use indicatif::{ProgressBar, ProgressStyle};
-use serde_json;
 use std::error::Error;
 use std::fs;
 use std::io;
@@ -19,6 +19,10 @@ pub struct Export<CS> {
     pub shared: Shared<CS>,
     /// which format be used to export
     pub format: Format,
+    /// first block to export, the genesis by default
+    pub from: BlockNumber,
+    /// last block to export, the tip by default
+    pub to: Option<BlockNumber>,
 }
 
 impl<CS: ChainStore> Export<CS> {
@@ -27,12 +31,21 @@ impl<CS: ChainStore> Export<CS> {
             shared,
             format,
             target,
+            from: 0,
+            to: None,
         }
     }
 
+    /// Only export the blocks from `from` to `to` inclusive
+    pub fn range(mut self, from: BlockNumber, to: Option<BlockNumber>) -> Self {
+        self.from = from;
+        self.to = to;
+        self
+    }
+
     /// Returning ChainIterator dealing with blocks iterate.
     pub fn iter(&self) -> ChainIterator<CS> {
-        ChainIterator::new(self.shared.clone())
+        ChainIterator::range(self.shared.clone(), self.from, self.to)
     }
 
     /// export file name
@@ -42,39 +55,27 @@ impl<CS: ChainStore> Export<CS> {
 
     pub fn execute(self) -> Result<(), Box<Error>> {
         fs::create_dir_all(&self.target)?;
-        match self.format {
-            Format::Json => self.write_to_json(),
-            _ => Ok(()),
-        }
-    }
-
-    #[cfg(not(feature = "progress_bar"))]
-    pub fn write_to_json(self) -> Result<(), Box<Error>> {
         let f = fs::OpenOptions::new()
             .create_new(true)
             .read(true)
             .write(true)
             .open(&self.target.join(self.file_name()))?;
         let mut writer = io::BufWriter::new(f);
+        self.write_blocks(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
 
+    #[cfg(not(feature = "progress_bar"))]
+    fn write_blocks<W: Write>(&self, writer: &mut W) -> Result<(), Box<Error>> {
         for block in self.iter() {
-            let block: Block = block.into();
-            let encoded = serde_json::to_vec(&block)?;
-            writer.write_all(&encoded)?;
-            writer.write_all(b"\n")?;
+            self.format.write_block(writer, &block)?;
         }
         Ok(())
     }
 
     #[cfg(feature = "progress_bar")]
-    pub fn write_to_json(self) -> Result<(), Box<Error>> {
-        let f = fs::OpenOptions::new()
-            .create_new(true)
-            .read(true)
-            .write(true)
-            .open(&self.target.join(self.file_name()))?;
-        let mut writer = io::BufWriter::new(f);
-
+    fn write_blocks<W: Write>(&self, writer: &mut W) -> Result<(), Box<Error>> {
         let blocks_iter = self.iter();
         let progress_bar = ProgressBar::new(blocks_iter.len());
         progress_bar.set_style(
@@ -83,9 +84,7 @@ impl<CS: ChainStore> Export<CS> {
                 .progress_chars("##-"),
         );
         for block in blocks_iter {
-            let encoded = serde_json::to_vec(&block)?;
-            writer.write_all(&encoded)?;
-            writer.write_all(b"\n")?;
+            self.format.write_block(writer, &block)?;
             progress_bar.inc(1);
         }
         progress_bar.finish_with_message("done!");
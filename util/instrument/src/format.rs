@@ -1,4 +1,7 @@
+use ckb_core::block::Block;
+use std::error::Error;
 use std::fmt;
+use std::io::{BufRead, Write};
 use std::str::FromStr;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -27,3 +30,134 @@ impl FromStr for Format {
         }
     }
 }
+
+impl Format {
+    /// Write a block, json blocks are one per line, binary blocks are prefixed by the length
+    /// of their bincode encoding as a little endian u32. Returns the number of bytes written.
+    pub fn write_block<W: Write>(self, writer: &mut W, block: &Block) -> Result<usize, Box<Error>> {
+        match self {
+            Format::Json => {
+                let encoded = serde_json::to_vec(block)?;
+                writer.write_all(&encoded)?;
+                writer.write_all(b"\n")?;
+                Ok(encoded.len() + 1)
+            }
+            Format::Binary => {
+                let encoded = bincode::serialize(block)?;
+                writer.write_all(&(encoded.len() as u32).to_le_bytes())?;
+                writer.write_all(&encoded)?;
+                Ok(encoded.len() + 4)
+            }
+        }
+    }
+
+    /// Read the next block written by `write_block` and the number of bytes it took, `None` at
+    /// the end of the stream. A binary block longer than `max_block_bytes` is an error, its
+    /// length prefix is checked before allocating the buffer.
+    pub fn read_block<R: BufRead>(
+        self,
+        reader: &mut R,
+        max_block_bytes: usize,
+    ) -> Result<Option<(Block, usize)>, Box<Error>> {
+        match self {
+            Format::Json => {
+                let mut line = String::new();
+                let read = reader.read_line(&mut line)?;
+                if read == 0 {
+                    return Ok(None);
+                }
+                Ok(Some((serde_json::from_str(&line)?, read)))
+            }
+            Format::Binary => {
+                if reader.fill_buf()?.is_empty() {
+                    return Ok(None);
+                }
+                let mut len = [0u8; 4];
+                reader.read_exact(&mut len)?;
+                let len = u32::from_le_bytes(len) as usize;
+                if len > max_block_bytes {
+                    return Err(format!(
+                        "block of {} bytes exceeds the limit of {} bytes",
+                        len, max_block_bytes
+                    )
+                    .into());
+                }
+                let mut encoded = vec![0u8; len];
+                reader.read_exact(&mut encoded)?;
+                Ok(Some((bincode::deserialize(&encoded)?, encoded.len() + 4)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_core::block::BlockBuilder;
+    use ckb_core::header::HeaderBuilder;
+    use ckb_core::transaction::TransactionBuilder;
+    use std::io::Cursor;
+
+    const MAX_BLOCK_BYTES: usize = 1_000_000;
+
+    fn round_trip(format: Format) {
+        let blocks: Vec<Block> = (0..3)
+            .map(|number| {
+                BlockBuilder::default()
+                    .header(HeaderBuilder::default().number(number).build())
+                    .transaction(TransactionBuilder::default().build())
+                    .build()
+            })
+            .collect();
+        let mut buffer = Vec::new();
+        let written: usize = blocks
+            .iter()
+            .map(|block| format.write_block(&mut buffer, block).unwrap())
+            .sum();
+        assert_eq!(written, buffer.len());
+
+        let mut reader = Cursor::new(buffer);
+        for block in &blocks {
+            let (read, _) = format
+                .read_block(&mut reader, MAX_BLOCK_BYTES)
+                .unwrap()
+                .unwrap();
+            assert_eq!(&read, block);
+        }
+        assert!(format
+            .read_block(&mut reader, MAX_BLOCK_BYTES)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        round_trip(Format::Json);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        round_trip(Format::Binary);
+    }
+
+    #[test]
+    fn reject_oversized_binary_block() {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&(u32::max_value()).to_le_bytes());
+        buffer.extend_from_slice(&[0u8; 16]);
+        let mut reader = Cursor::new(buffer);
+        assert!(Format::Binary
+            .read_block(&mut reader, MAX_BLOCK_BYTES)
+            .is_err());
+
+        let block = BlockBuilder::default()
+            .transaction(TransactionBuilder::default().build())
+            .build();
+        let mut buffer = Vec::new();
+        let written = Format::Binary.write_block(&mut buffer, &block).unwrap();
+        let mut reader = Cursor::new(buffer.clone());
+        assert!(Format::Binary.read_block(&mut reader, written - 5).is_err());
+        let mut reader = Cursor::new(buffer);
+        assert!(Format::Binary.read_block(&mut reader, written - 4).is_ok());
+    }
+}
@@ -1,9 +1,12 @@
 use crate::format::Format;
 use ckb_chain::chain::ChainController;
 use ckb_core::block::Block;
+use ckb_shared::shared::Shared;
+use ckb_shared::store::ChainStore;
+use ckb_traits::ChainProvider;
+use ckb_verification::{HeaderResolver, HeaderResolverWrapper, HeaderVerifier, Verifier};
 #[cfg(feature = "progress_bar")]
 use indicatif::{ProgressBar, ProgressStyle};
-use serde_json;
 use std::error::Error;
 use std::fs;
 use std::io;
@@ -12,69 +15,227 @@ use std::path::PathBuf;
 use std::sync::Arc;
 
 /// Export block date from file to database.
-pub struct Import {
+///
+/// The header of every imported block is verified, the rest of the block is verified by the
+/// chain service unless it is built without verification to trust the exported blocks.
+pub struct Import<CS> {
     /// source file contains block data
     source: PathBuf,
+    shared: Shared<CS>,
     chain: ChainController,
     /// source file format
     format: Format,
 }
 
-impl Import {
-    pub fn new(chain: ChainController, format: Format, source: PathBuf) -> Self {
+impl<CS: ChainStore> Import<CS> {
+    pub fn new(
+        shared: Shared<CS>,
+        chain: ChainController,
+        format: Format,
+        source: PathBuf,
+    ) -> Self {
         Import {
             format,
+            shared,
             chain,
             source,
         }
     }
 
     pub fn execute(self) -> Result<(), Box<Error>> {
-        match self.format {
-            Format::Json => self.read_from_json(),
-            _ => Ok(()),
-        }
+        let f = fs::File::open(&self.source)?;
+        let mut reader = io::BufReader::new(f);
+        self.read_blocks(&mut reader)
     }
 
     #[cfg(not(feature = "progress_bar"))]
-    pub fn read_from_json(&self) -> Result<(), Box<Error>> {
-        let f = fs::File::open(&self.source)?;
-        let reader = io::BufReader::new(f);
-
-        for line in reader.lines() {
-            let s = line?;
-            let block: Arc<Block> = Arc::new(serde_json::from_str(&s)?);
-            if !block.is_genesis() {
-                self.chain
-                    .process_block(block)
-                    .expect("import occur malformation data");
-            }
+    fn read_blocks<R: BufRead>(&self, reader: &mut R) -> Result<(), Box<Error>> {
+        while let Some((block, _)) = self.format.read_block(reader, self.max_block_bytes())? {
+            self.import_block(block)?;
         }
         Ok(())
     }
 
     #[cfg(feature = "progress_bar")]
-    pub fn read_from_json(&self) -> Result<(), Box<Error>> {
+    fn read_blocks<R: BufRead>(&self, reader: &mut R) -> Result<(), Box<Error>> {
         let metadata = fs::metadata(&self.source)?;
-        let f = fs::File::open(&self.source)?;
-        let reader = io::BufReader::new(f);
         let progress_bar = ProgressBar::new(metadata.len() as u64);
         progress_bar.set_style(
             ProgressStyle::default_bar()
                 .template("[{elapsed_precise}] {bar:50.cyan/blue} {bytes:>6}/{total_bytes:6} {msg}")
                 .progress_chars("##-"),
         );
-        for line in reader.lines() {
-            let s = line?;
-            let block: Arc<Block> = Arc::new(serde_json::from_str(&s)?);
-            if !block.is_genesis() {
-                self.chain
-                    .process_block(block)
-                    .expect("import occur malformation data");
-            }
-            progress_bar.inc(s.as_bytes().len() as u64);
+        while let Some((block, read)) = self.format.read_block(reader, self.max_block_bytes())? {
+            self.import_block(block)?;
+            progress_bar.inc(read as u64);
         }
         progress_bar.finish_with_message("done!");
         Ok(())
     }
+
+    fn max_block_bytes(&self) -> usize {
+        self.shared.consensus().max_block_bytes() as usize
+    }
+
+    fn import_block(&self, block: Block) -> Result<(), Box<Error>> {
+        if block.is_genesis() {
+            return Ok(());
+        }
+        let header = block.header();
+        let resolver = HeaderResolverWrapper::new(header, self.shared.clone());
+        let parent = resolver.parent().ok_or_else(|| {
+            format!(
+                "missing parent of block {} {:x}",
+                header.number(),
+                header.hash()
+            )
+        })?;
+        let median_time_context = self.shared.branch_median_time_context(parent);
+        HeaderVerifier::new(median_time_context, self.shared.consensus().pow_engine())
+            .dao_activation_number(self.shared.consensus().dao_activation_number())
+            .verify(&resolver)
+            .map_err(|err| {
+                format!(
+                    "invalid header of block {} {:x}: {:?}",
+                    header.number(),
+                    header.hash(),
+                    err
+                )
+            })?;
+        let number = header.number();
+        let hash = header.hash();
+        self.chain
+            .process_block(Arc::new(block))
+            .map_err(|err| format!("invalid block {} {:x}: {:?}", number, hash, err))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_chain::chain::ChainBuilder;
+    use ckb_chain_spec::consensus::Consensus;
+    use ckb_core::block::BlockBuilder;
+    use ckb_core::header::{Header, HeaderBuilder};
+    use ckb_core::script::Script;
+    use ckb_core::transaction::{CellInput, CellOutput, TransactionBuilder};
+    use ckb_core::{capacity_bytes, Capacity};
+    use ckb_db::memorydb::MemoryKeyValueDB;
+    use ckb_notify::NotifyService;
+    use ckb_shared::shared::SharedBuilder;
+    use ckb_shared::store::ChainKVStore;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn start_chain(
+        verification: bool,
+    ) -> (Shared<ChainKVStore<MemoryKeyValueDB>>, ChainController) {
+        let shared = SharedBuilder::<MemoryKeyValueDB>::new()
+            .consensus(Consensus::default().set_cellbase_maturity(0))
+            .build()
+            .unwrap();
+        let notify = NotifyService::default().start::<&str>(None);
+        let chain_controller = ChainBuilder::new(shared.clone(), notify)
+            .verification(verification)
+            .build()
+            .start::<&str>(None);
+        (shared, chain_controller)
+    }
+
+    fn gen_block(parent: &Header, reward: Capacity) -> Block {
+        let number = parent.number() + 1;
+        let cellbase = TransactionBuilder::default()
+            .input(CellInput::new_cellbase_input(number))
+            .output(CellOutput::new(
+                reward,
+                vec![],
+                Script::always_success(),
+                None,
+            ))
+            .build();
+        let header_builder = HeaderBuilder::default()
+            .parent_hash(parent.hash().clone())
+            .timestamp(parent.timestamp() + 1)
+            .number(number)
+            .difficulty(parent.difficulty().clone());
+        BlockBuilder::default()
+            .transaction(cellbase)
+            .with_header_builder(header_builder)
+    }
+
+    fn export(blocks: &[Block]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        for block in blocks {
+            Format::Binary.write_block(&mut file, block).unwrap();
+        }
+        file.flush().unwrap();
+        file
+    }
+
+    fn import(
+        shared: &Shared<ChainKVStore<MemoryKeyValueDB>>,
+        chain_controller: ChainController,
+        file: &NamedTempFile,
+    ) -> Result<(), Box<Error>> {
+        Import::new(
+            shared.clone(),
+            chain_controller,
+            Format::Binary,
+            file.path().to_path_buf(),
+        )
+        .execute()
+    }
+
+    #[test]
+    fn import_exported_blocks() {
+        let (shared, chain_controller) = start_chain(true);
+        let mut blocks = vec![shared.consensus().genesis_block().clone()];
+        for _ in 0..3 {
+            let block = gen_block(blocks.last().unwrap().header(), capacity_bytes!(5000));
+            blocks.push(block);
+        }
+        let file = export(&blocks);
+
+        import(&shared, chain_controller, &file).unwrap();
+        let chain_state = shared.chain_state();
+        assert_eq!(chain_state.tip_number(), 3);
+        assert_eq!(&chain_state.tip_hash(), blocks[3].header().hash());
+    }
+
+    #[test]
+    fn fast_import_trusts_block_bodies() {
+        // the cellbase claims more than the block reward
+        let genesis = Consensus::default().genesis_block().clone();
+        let block = gen_block(genesis.header(), capacity_bytes!(5001));
+        let file = export(&[genesis, block.clone()]);
+
+        let (shared, chain_controller) = start_chain(true);
+        assert!(import(&shared, chain_controller, &file).is_err());
+        assert_eq!(shared.chain_state().tip_number(), 0);
+
+        let (shared, chain_controller) = start_chain(false);
+        import(&shared, chain_controller, &file).unwrap();
+        assert_eq!(&shared.chain_state().tip_hash(), block.header().hash());
+    }
+
+    #[test]
+    fn fast_import_verifies_headers() {
+        let genesis = Consensus::default().genesis_block().clone();
+        let block = gen_block(genesis.header(), capacity_bytes!(5000));
+        // not after the median time of the previous blocks
+        let header_builder = HeaderBuilder::default()
+            .parent_hash(genesis.header().hash().clone())
+            .timestamp(genesis.header().timestamp())
+            .number(1)
+            .difficulty(genesis.header().difficulty().clone());
+        let block = BlockBuilder::default()
+            .transactions(block.transactions().to_vec())
+            .with_header_builder(header_builder);
+        let file = export(&[genesis, block]);
+
+        let (shared, chain_controller) = start_chain(false);
+        assert!(import(&shared, chain_controller, &file).is_err());
+        assert_eq!(shared.chain_state().tip_number(), 0);
+    }
 }
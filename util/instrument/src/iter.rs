@@ -3,6 +3,7 @@ use ckb_core::BlockNumber;
 use ckb_shared::shared::Shared;
 use ckb_shared::store::ChainStore;
 use ckb_traits::ChainProvider;
+use std::cmp;
 
 // An iterator over the entries of a `Chain`.
 pub struct ChainIterator<CS> {
@@ -13,8 +14,18 @@ pub struct ChainIterator<CS> {
 
 impl<CS: ChainStore> ChainIterator<CS> {
     pub fn new(shared: Shared<CS>) -> Self {
-        let current = shared.block_hash(0).and_then(|h| shared.block(&h));
-        let tip = shared.chain_state().lock().tip_number();
+        Self::range(shared, 0, None)
+    }
+
+    /// Iterate the main chain blocks from `from` to `to` inclusive, `to` is capped at the tip
+    pub fn range(shared: Shared<CS>, from: BlockNumber, to: Option<BlockNumber>) -> Self {
+        let tip_number = shared.chain_state().tip_number();
+        let tip = to.map_or(tip_number, |to| cmp::min(to, tip_number));
+        let current = if from <= tip {
+            shared.block_hash(from).and_then(|h| shared.block(&h))
+        } else {
+            None
+        };
         ChainIterator {
             shared,
             current,
@@ -23,7 +34,10 @@ impl<CS: ChainStore> ChainIterator<CS> {
     }
 
     pub fn len(&self) -> u64 {
-        self.tip + 1
+        match self.current {
+            Some(ref b) => self.tip - b.header().number() + 1,
+            None => 0,
+        }
     }
 }
 
@@ -34,14 +48,14 @@ impl<CS: ChainStore> Iterator for ChainIterator<CS> {
         let current = self.current.take();
 
         self.current = match current {
-            Some(ref b) => {
+            Some(ref b) if b.header().number() < self.tip => {
                 if let Some(block_hash) = self.shared.block_hash(b.header().number() + 1) {
                     self.shared.block(&block_hash)
                 } else {
                     None
                 }
             }
-            None => None,
+            _ => None,
         };
         current
     }
@@ -1,5 +1,6 @@
 use crate::{BlockNumber, Bytes, Capacity, ProposalShortId};
 use ckb_core::block::{Block as CoreBlock, BlockBuilder};
+use ckb_core::extras::ReorgRecord as CoreReorgRecord;
 use ckb_core::header::{Header as CoreHeader, HeaderBuilder, Seal as CoreSeal};
 use ckb_core::script::Script as CoreScript;
 use ckb_core::transaction::{
@@ -263,6 +264,14 @@ impl TransactionWithStatus {
             transaction: (&tx).into(),
         }
     }
+
+    /// Build with pruned status
+    pub fn with_pruned(tx: CoreTransaction, hash: H256) -> Self {
+        Self {
+            tx_status: TxStatus::pruned(hash),
+            transaction: (&tx).into(),
+        }
+    }
 }
 
 /// Status for transaction
@@ -275,6 +284,8 @@ pub enum Status {
     Proposed,
     /// Transaction commit on block
     Committed,
+    /// Transaction commit on block below the transaction index cutoff
+    Pruned,
 }
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
@@ -304,6 +315,13 @@ impl TxStatus {
             block_hash: Some(hash),
         }
     }
+
+    pub fn pruned(hash: H256) -> Self {
+        Self {
+            status: Status::Pruned,
+            block_hash: Some(hash),
+        }
+    }
 }
 
 #[derive(Clone, Default, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
@@ -343,6 +361,7 @@ pub struct Header {
     pub difficulty: U256,
     pub uncles_hash: H256,
     pub uncles_count: u32,
+    pub dao: Bytes,
     pub seal: Seal,
     #[serde(skip_deserializing)]
     pub hash: H256,
@@ -361,6 +380,7 @@ impl<'a> From<&'a CoreHeader> for Header {
             difficulty: core.difficulty().clone(),
             uncles_hash: core.uncles_hash().clone(),
             uncles_count: core.uncles_count(),
+            dao: Bytes::new(core.dao().to_vec()),
             seal: core.seal().clone().into(),
             hash: core.hash().clone(),
         }
@@ -382,6 +402,7 @@ impl TryFrom<Header> for CoreHeader {
             difficulty,
             uncles_hash,
             uncles_count,
+            dao,
             seal,
             ..
         } = json;
@@ -397,6 +418,7 @@ impl TryFrom<Header> for CoreHeader {
             .difficulty(difficulty)
             .uncles_hash(uncles_hash)
             .uncles_count(uncles_count)
+            .dao(dao.into_vec())
             .seal(seal.try_into()?)
             .build())
     }
@@ -486,6 +508,56 @@ impl TryFrom<Block> for CoreBlock {
     }
 }
 
+#[derive(Clone, Default, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+pub struct ReorgRecord {
+    pub index: String,
+    pub timestamp: String,
+    pub depth: String,
+    pub attached: String,
+    pub fork_number: BlockNumber,
+    pub old_tip_hash: H256,
+    pub new_tip_hash: H256,
+    pub duration: String,
+}
+
+impl ReorgRecord {
+    pub fn new(index: u64, core: CoreReorgRecord) -> ReorgRecord {
+        ReorgRecord {
+            index: index.to_string(),
+            timestamp: core.timestamp.to_string(),
+            depth: core.depth.to_string(),
+            attached: core.attached.to_string(),
+            fork_number: core.fork_number.to_string(),
+            old_tip_hash: core.old_tip_hash,
+            new_tip_hash: core.new_tip_hash,
+            duration: core.duration.to_string(),
+        }
+    }
+}
+
+/// The main chain blocks replaced when the tip moves to another fork, `detached` from the old
+/// tip down and `attached` from the common ancestor up to the new tip
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct ChainFork {
+    pub attached: Vec<Header>,
+    pub detached: Vec<Header>,
+    pub common_ancestor: H256,
+}
+
+/// The capacity a block issues and the cellbase claims. The cellbase may claim up to
+/// `block_reward` plus `fees`, what it leaves is burnt. `fees` and `cellbase_capacity` are
+/// `None` if the cells they are computed from are not stored.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct BlockEconomicState {
+    pub number: BlockNumber,
+    pub block_reward: Capacity,
+    pub fees: Option<Capacity>,
+    pub cellbase_capacity: Option<Capacity>,
+    /// The genesis outputs plus the full rewards of the blocks up to this one, the capacity
+    /// left unclaimed by the cellbases is not subtracted
+    pub max_issuance: Capacity,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,6 +1,8 @@
 use crate::{Capacity, CellOutput, OutPoint, Script};
 use ckb_core::cell::{CellStatus, LiveCell};
+use numext_fixed_hash::H256;
 use serde_derive::{Deserialize, Serialize};
+use std::sync::Arc;
 
 // This is used as return value of get_cells_by_type_hash RPC:
 // it contains both OutPoint data used for referencing a cell, as well as
@@ -12,9 +14,20 @@ pub struct CellOutputWithOutPoint {
     pub lock: Script,
 }
 
+/// A page of cells, pass `next_cursor` back to fetch the next page, it's None on the last page
+#[derive(Serialize, Deserialize)]
+pub struct CellsPage {
+    pub cells: Vec<CellOutputWithOutPoint>,
+    pub next_cursor: Option<String>,
+}
+
+/// `block_number` and `cellbase` are only set for the live cells, wallets need them to tell
+/// whether a cellbase output is mature
 #[derive(Serialize, Deserialize)]
 pub struct CellWithStatus {
     pub cell: Option<CellOutput>,
+    pub block_number: Option<String>,
+    pub cellbase: Option<bool>,
     pub status: String,
 }
 
@@ -28,9 +41,30 @@ impl From<CellStatus> for CellWithStatus {
             CellStatus::Dead => (None, "dead"),
             CellStatus::Unknown => (None, "unknown"),
         };
+        let block_number = cell
+            .as_ref()
+            .and_then(|cell| cell.block_number)
+            .map(|number| number.to_string());
+        let cellbase = cell.as_ref().map(|cell| cell.is_cellbase());
         Self {
-            cell: cell.map(|cell| cell.cell_output.into()),
+            cell: cell.map(|cell| {
+                Arc::try_unwrap(cell.cell_output)
+                    .unwrap_or_else(|cell_output| (*cell_output).clone())
+                    .into()
+            }),
+            block_number,
+            cellbase,
             status: status.to_string(),
         }
     }
 }
+
+// This is used to explain why a transaction inputs or deps can't be resolved,
+// status is one of "live", "dead" and "unknown"
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct CellResolution {
+    pub out_point: OutPoint,
+    pub dep: bool,
+    pub status: String,
+    pub spent_by: Option<H256>,
+}
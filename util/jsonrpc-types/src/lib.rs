@@ -14,13 +14,15 @@ pub use self::block_template::{
     BlockTemplate, CellbaseTemplate, TransactionTemplate, UncleTemplate,
 };
 pub use self::blockchain::{
-    Block, CellInput, CellOutput, Header, OutPoint, Script, Seal, Transaction,
-    TransactionWithStatus, TxStatus, UncleBlock, Witness,
+    Block, BlockEconomicState, CellInput, CellOutput, ChainFork, Header, OutPoint, ReorgRecord,
+    Script, Seal, Transaction, TransactionWithStatus, TxStatus, UncleBlock, Witness,
 };
 pub use self::bytes::Bytes;
-pub use self::cell::{CellOutputWithOutPoint, CellWithStatus};
-pub use self::net::{Node, NodeAddress};
+pub use self::cell::{CellOutputWithOutPoint, CellResolution, CellWithStatus, CellsPage};
+pub use self::net::{BannedAddress, ChainTip, Node, NodeAddress, SyncState};
 pub use self::proposal_short_id::ProposalShortId;
-pub use self::trace::{Action, TxTrace};
+pub use self::trace::{
+    Action, BlockStageLatency, DryRunResult, ScriptResult, TransactionStatus, TxRejection, TxTrace,
+};
 pub use ckb_core::Version;
 pub use jsonrpc_core::types::{error, id, params, request, response, version};
@@ -1,3 +1,6 @@
+use crate::BlockNumber;
+use numext_fixed_hash::H256;
+use numext_fixed_uint::U256;
 use serde_derive::{Deserialize, Serialize};
 
 // TODO add more fields from PeerIdentifyInfo
@@ -7,6 +10,9 @@ pub struct Node {
     pub node_id: String,
     pub addresses: Vec<NodeAddress>,
     pub is_outbound: Option<bool>,
+    /// Lowest block number whose transactions are indexed, only set for the local node
+    /// when a transaction index retention is configured
+    pub tx_index_cutoff: Option<BlockNumber>,
 }
 
 #[derive(Clone, Default, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
@@ -14,3 +20,33 @@ pub struct NodeAddress {
     pub address: String,
     pub score: u8,
 }
+
+#[derive(Clone, Default, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+pub struct BannedAddress {
+    pub address: String,
+    pub ban_until: String,
+    pub ban_reason: String,
+    pub created_at: String,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+pub struct SyncState {
+    pub ibd: bool,
+    pub best_known_block_number: BlockNumber,
+    pub tip_block_number: BlockNumber,
+    pub progress: u8,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
+pub struct ChainTip {
+    pub hash: H256,
+    pub number: BlockNumber,
+    pub total_difficulty: U256,
+    /// Blocks since the main chain, 0 for the main chain tip
+    pub branch_length: BlockNumber,
+    /// Milliseconds since the tip was first seen
+    pub age: String,
+    /// Node id of the peer sent the tip first, None if it is disconnected or the tip is local
+    pub first_seen_peer: Option<String>,
+    pub is_main_chain: bool,
+}
@@ -1,14 +1,23 @@
+use crate::Cycle;
+use numext_fixed_hash::H256;
 use serde_derive::{Deserialize, Serialize};
 use std::fmt;
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Hash)]
 pub enum Action {
+    Received,
+    /// The scripts passed, the info carries the cycles
+    Verified,
     AddPending,
     Proposed,
     Staged,
     Expired,
     AddOrphan,
+    Delayed,
     Committed,
+    Evicted,
+    /// The info carries the reason
+    Rejected,
 }
 
 #[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Hash)]
@@ -43,3 +52,49 @@ impl fmt::Display for TxTrace {
         fmt::Debug::fmt(&self, f)
     }
 }
+
+/// Latency histogram of a block processing stage
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockStageLatency {
+    pub stage: String,
+    /// Upper bounds in milliseconds of the buckets, the last bucket has no bound
+    pub bucket_bounds: Vec<String>,
+    pub buckets: Vec<String>,
+    pub count: String,
+    pub total_millis: String,
+}
+
+/// Why and when a transaction was rejected by the pool, `bad_tx` tells whether it is rejected
+/// again without verification
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxRejection {
+    pub reason: String,
+    pub bad_tx: bool,
+    pub timestamp: String,
+}
+
+/// Status of a transaction: `pending`, `proposed`, `committed`, or `reorged` if the block it
+/// was committed in is detached and it is back in the pool. `block_hash` is the block it is
+/// or was committed in.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionStatus {
+    pub status: String,
+    pub block_hash: Option<H256>,
+}
+
+/// Result of a script run by `dry_run_transaction`, `script` is `input_lock` or `output_type`
+/// and `index` the index of the cell it guards. One of `cycles` and `error` is set.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScriptResult {
+    pub script: String,
+    pub index: String,
+    pub cycles: Option<Cycle>,
+    pub error: Option<String>,
+}
+
+/// `cycles` is the total of the scripts, it's only set when all of them pass
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DryRunResult {
+    pub cycles: Option<Cycle>,
+    pub scripts: Vec<ScriptResult>,
+}
@@ -1,17 +1,20 @@
 use crate::error::{CellbaseError, CommitError, Error, UnclesError};
 use crate::header_verifier::HeaderResolver;
-use crate::{TransactionVerifier, Verifier};
+use crate::{SizeVerifier, TransactionVerifier, Verifier};
 use ckb_core::cell::ResolvedTransaction;
 use ckb_core::header::Header;
+use ckb_core::script_cache::ScriptHashCache;
 use ckb_core::transaction::{Capacity, CellInput, Transaction};
 use ckb_core::Cycle;
 use ckb_core::{block::Block, BlockNumber};
 use ckb_traits::{BlockMedianTimeContext, ChainProvider};
-use fnv::FnvHashSet;
+use fnv::{FnvHashMap, FnvHashSet};
 use log::error;
+use numext_fixed_hash::H256;
 use numext_fixed_uint::U256;
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use std::collections::HashSet;
+use std::sync::Arc;
 
 //TODO: cellbase, witness
 #[derive(Clone)]
@@ -26,6 +29,8 @@ pub struct BlockVerifier<P> {
     uncles: UnclesVerifier<P>,
     // Verify the the propose-then-commit consensus rule
     commit: CommitVerifier<P>,
+    // Verify the committed transactions do not exceed the size limits
+    transactions_size: TransactionsSizeVerifier<P>,
 }
 
 impl<P> BlockVerifier<P>
@@ -39,7 +44,8 @@ where
             cellbase: CellbaseVerifier::new(provider.clone()),
             merkle_root: MerkleRootVerifier::new(),
             uncles: UnclesVerifier::new(provider.clone()),
-            commit: CommitVerifier::new(provider),
+            commit: CommitVerifier::new(provider.clone()),
+            transactions_size: TransactionsSizeVerifier::new(provider),
         }
     }
 }
@@ -51,6 +57,7 @@ impl<P: ChainProvider + Clone> Verifier for BlockVerifier<P> {
         self.cellbase.verify(target)?;
         self.duplicate.verify(target)?;
         self.merkle_root.verify(target)?;
+        self.transactions_size.verify(target)?;
         self.commit.verify(target)?;
         self.uncles.verify(target)
     }
@@ -101,6 +108,32 @@ impl<CP: ChainProvider + Clone> CellbaseVerifier<CP> {
     }
 }
 
+#[derive(Clone)]
+pub struct TransactionsSizeVerifier<CP> {
+    provider: CP,
+}
+
+impl<CP: ChainProvider + Clone> TransactionsSizeVerifier<CP> {
+    pub fn new(provider: CP) -> Self {
+        TransactionsSizeVerifier { provider }
+    }
+
+    pub fn verify(&self, block: &Block) -> Result<(), Error> {
+        let consensus = self.provider.consensus();
+        // the cellbase is bounded by the cellbase rules, index as `TransactionsVerifier` does
+        block
+            .transactions()
+            .iter()
+            .skip(1)
+            .enumerate()
+            .try_for_each(|(index, tx)| {
+                SizeVerifier::new(tx, consensus)
+                    .verify()
+                    .map_err(|e| Error::Transactions((index, e)))
+            })
+    }
+}
+
 #[derive(Clone)]
 pub struct DuplicateVerifier {}
 
@@ -254,25 +287,10 @@ impl<CP: ChainProvider + Clone> UnclesVerifier<CP> {
         // cB.p^7
         // verify uncles is not included in main chain
         // TODO: cache context
-        let mut excluded = FnvHashSet::default();
+        let mut excluded =
+            excluded_uncles(&self.provider, block.header().parent_hash(), max_uncles_age);
         let mut included = FnvHashSet::default();
         excluded.insert(block.header().hash());
-        let mut block_hash = block.header().parent_hash().clone();
-        excluded.insert(block_hash.clone());
-        for _ in 0..max_uncles_age {
-            if let Some(header) = self.provider.block_header(&block_hash) {
-                let parent_hash = header.parent_hash().clone();
-                excluded.insert(parent_hash.clone());
-                if let Some(uncles) = self.provider.uncles(&block_hash) {
-                    uncles.iter().for_each(|uncle| {
-                        excluded.insert(uncle.header.hash());
-                    });
-                };
-                block_hash = parent_hash;
-            } else {
-                break;
-            }
-        }
 
         let block_difficulty_epoch =
             block.header().number() / self.provider.consensus().difficulty_adjustment_interval();
@@ -325,38 +343,74 @@ impl<CP: ChainProvider + Clone> UnclesVerifier<CP> {
     }
 }
 
+/// Hashes which the uncles of a block on `parent_hash` must not have: the main chain blocks
+/// and the uncles they already include, as far as the uncles may be deep
+pub fn excluded_uncles<CP: ChainProvider>(
+    provider: &CP,
+    parent_hash: &H256,
+    max_uncles_age: u64,
+) -> FnvHashSet<H256> {
+    let mut excluded = FnvHashSet::default();
+    let mut block_hash = parent_hash.to_owned();
+    excluded.insert(block_hash.clone());
+    for _ in 0..max_uncles_age {
+        if let Some(header) = provider.block_header(&block_hash) {
+            let parent_hash = header.parent_hash().to_owned();
+            excluded.insert(parent_hash.clone());
+            if let Some(uncles) = provider.uncles(&block_hash) {
+                excluded.extend(uncles.iter().map(|uncle| uncle.header.hash()));
+            };
+            block_hash = parent_hash;
+        } else {
+            break;
+        }
+    }
+    excluded
+}
+
 #[derive(Clone)]
 pub struct TransactionsVerifier {
     max_cycles: Cycle,
+    script_hash_cache: Option<Arc<ScriptHashCache>>,
 }
 
 impl TransactionsVerifier {
     pub fn new(max_cycles: Cycle) -> Self {
-        TransactionsVerifier { max_cycles }
+        TransactionsVerifier {
+            max_cycles,
+            script_hash_cache: None,
+        }
+    }
+
+    /// Same as `new`, but the hashes of the dep cells are looked up in `script_hash_cache`
+    pub fn with_script_hash_cache(
+        max_cycles: Cycle,
+        script_hash_cache: Arc<ScriptHashCache>,
+    ) -> Self {
+        TransactionsVerifier {
+            max_cycles,
+            script_hash_cache: Some(script_hash_cache),
+        }
     }
 
+    /// `cellbase_capacity` is the most the cellbase may claim, the block reward plus the fees.
+    /// The scripts of the transactions in `txs_verify_cache` are not run again, their cached
+    /// cycles are used. Returns the hashes and cycles of the transactions whose scripts ran.
     pub fn verify<M>(
         &self,
         resolved: &[ResolvedTransaction],
-        block_reward: Capacity,
+        cellbase_capacity: Capacity,
         block_median_time_context: M,
         tip_number: BlockNumber,
         cellbase_maturity: BlockNumber,
-    ) -> Result<(), Error>
+        txs_verify_cache: &FnvHashMap<H256, Cycle>,
+    ) -> Result<Vec<(H256, Cycle)>, Error>
     where
         M: BlockMedianTimeContext + Sync,
     {
         // verify cellbase reward
         let cellbase = &resolved[0];
-        let fee: Capacity = resolved
-            .iter()
-            .skip(1)
-            .map(ResolvedTransaction::fee)
-            .try_fold(Capacity::zero(), |acc, rhs| {
-                rhs.and_then(|x| acc.safe_add(x))
-            })?;
-
-        if cellbase.transaction.outputs_capacity()? > block_reward.safe_add(fee)? {
+        if cellbase.transaction.outputs_capacity()? > cellbase_capacity {
             return Err(Error::Cellbase(CellbaseError::InvalidReward));
         }
         // TODO use TransactionScriptsVerifier to verify cellbase script
@@ -367,24 +421,38 @@ impl TransactionsVerifier {
             .skip(1)
             .enumerate()
             .map(|(index, tx)| {
-                TransactionVerifier::new(
+                let tx_hash = tx.transaction.hash();
+                let mut verifier = TransactionVerifier::new(
                     &tx,
                     &block_median_time_context,
                     tip_number,
                     cellbase_maturity,
-                )
-                .verify(self.max_cycles)
-                .map_err(|e| Error::Transactions((index, e)))
-                .map(|cycles| cycles)
+                );
+                if let Some(ref script_hash_cache) = self.script_hash_cache {
+                    verifier = verifier.script_hash_cache(script_hash_cache);
+                }
+                let ret = match txs_verify_cache.get(&tx_hash) {
+                    Some(cycles) => verifier
+                        .verify_without_script()
+                        .map(|_| (tx_hash, *cycles, true)),
+                    None => verifier
+                        .verify(self.max_cycles)
+                        .map(|cycles| (tx_hash, cycles, false)),
+                };
+                ret.map_err(|e| Error::Transactions((index, e)))
             })
             .collect::<Result<Vec<_>, _>>()?;
 
-        let sum: Cycle = cycles_set.iter().sum();
+        let sum: Cycle = cycles_set.iter().map(|(_, cycles, _)| cycles).sum();
 
         if sum > self.max_cycles {
             Err(Error::ExceededMaximumCycles)
         } else {
-            Ok(())
+            Ok(cycles_set
+                .into_iter()
+                .filter(|(_, _, cached)| !cached)
+                .map(|(tx_hash, cycles, _)| (tx_hash, cycles))
+                .collect())
         }
     }
 }
@@ -1,3 +1,5 @@
+use ckb_core::cell::UnresolvableError;
+use ckb_core::transaction::CapacityError;
 use ckb_core::BlockNumber;
 use ckb_script::ScriptError;
 use numext_fixed_hash::H256;
@@ -51,6 +53,8 @@ pub enum Error {
     ExceededMaximumCycles,
     /// The field version in block header is not allowed.
     Version,
+    /// The field dao in block header is invalid.
+    Dao(DaoError),
     /// Overflow when do computation for capacity.
     CapacityOverflow,
 }
@@ -127,13 +131,23 @@ pub struct NumberError {
     pub actual: u64,
 }
 
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum DaoError {
+    /// The dao field is filled before the activation number of the consensus
+    Inactive,
+    TooLarge {
+        max: usize,
+        actual: usize,
+    },
+}
+
 #[derive(Debug, PartialEq, Clone, Eq)]
 pub enum DifficultyError {
     MixMismatch { expected: U256, actual: U256 },
     AncestorNotFound,
 }
 
-#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+#[derive(Debug, PartialEq, Clone, Eq)]
 pub enum TransactionError {
     NullInput,
     NullDep,
@@ -147,25 +161,40 @@ pub enum TransactionError {
     InvalidScript,
     ScriptFailure(ScriptError),
     InvalidSignature,
-    Conflict,
-    Unknown,
+    /// An input or dep is not a live cell
+    Unresolvable(UnresolvableError),
     Version,
     /// Tx not satisfied since condition
     Immature,
     /// Invalid ValidSince flags
     InvalidValidSince,
     CellbaseImmaturity,
+    /// Serialized size exceeds `max_tx_bytes` of the consensus
+    ExceededMaximumBytes,
+    /// Inputs count exceeds `max_tx_inputs` of the consensus
+    TooManyInputs,
+    /// Outputs count exceeds `max_tx_outputs` of the consensus
+    TooManyOutputs,
 }
 
 impl TransactionError {
     /// Transaction error may be caused by different tip between peers if this method return false,
     /// Otherwise we consider the Bad Tx is constructed intendedly.
-    pub fn is_bad_tx(self) -> bool {
+    pub fn is_bad_tx(&self) -> bool {
         use TransactionError::*;
         match self {
             NullInput | NullDep | CapacityOverflow | DuplicateInputs | Empty
             | OutputsSumOverflow | InvalidScript | ScriptFailure(_) | InvalidSignature
-            | InvalidValidSince => true,
+            | InvalidValidSince | ExceededMaximumBytes | TooManyInputs | TooManyOutputs => true,
+            _ => false,
+        }
+    }
+
+    /// The transaction spends an immature cellbase or its since conditions are not met yet,
+    /// it may become valid later
+    pub fn is_immature(&self) -> bool {
+        match self {
+            TransactionError::Immature | TransactionError::CellbaseImmaturity => true,
             _ => false,
         }
     }
@@ -179,6 +208,17 @@ impl From<occupied_capacity::Error> for TransactionError {
     }
 }
 
+impl From<CapacityError> for TransactionError {
+    fn from(error: CapacityError) -> Self {
+        match error {
+            CapacityError::OutputsSumOverflow { .. } => TransactionError::OutputsSumOverflow,
+            CapacityError::Overflow | CapacityError::InsufficientCellCapacity { .. } => {
+                TransactionError::CapacityOverflow
+            }
+        }
+    }
+}
+
 impl From<occupied_capacity::Error> for Error {
     fn from(error: occupied_capacity::Error) -> Self {
         match error {
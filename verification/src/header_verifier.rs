@@ -1,11 +1,13 @@
 use super::Verifier;
-use crate::error::{DifficultyError, Error, NumberError, PowError, TimestampError};
+use crate::error::{DaoError, DifficultyError, Error, NumberError, PowError, TimestampError};
 use crate::shared::ALLOWED_FUTURE_BLOCKTIME;
-use ckb_core::header::{Header, HEADER_VERSION};
+use ckb_core::header::{Header, HEADER_VERSION, MAX_DAO_BYTES};
+use ckb_core::BlockNumber;
 use ckb_pow::PowEngine;
 use ckb_traits::BlockMedianTimeContext;
 use faketime::unix_time_as_millis;
 use numext_fixed_uint::U256;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use std::marker::PhantomData;
 use std::sync::Arc;
 
@@ -20,6 +22,9 @@ pub trait HeaderResolver {
 pub struct HeaderVerifier<T, M> {
     pub pow: Arc<dyn PowEngine>,
     block_median_time_context: M,
+    allowed_future_blocktime: u64,
+    dao_activation_number: Option<BlockNumber>,
+    verify_pow: bool,
     _phantom: PhantomData<T>,
 }
 
@@ -28,9 +33,31 @@ impl<T, M: BlockMedianTimeContext> HeaderVerifier<T, M> {
         HeaderVerifier {
             pow,
             block_median_time_context,
+            allowed_future_blocktime: ALLOWED_FUTURE_BLOCKTIME,
+            dao_activation_number: None,
+            verify_pow: true,
             _phantom: PhantomData,
         }
     }
+
+    /// Skip the PoW check, for headers whose seal is verified already, e.g. by `PowBatchVerifier`
+    pub fn pow_verified(mut self) -> Self {
+        self.verify_pow = false;
+        self
+    }
+
+    /// Override how far in the future (in milliseconds) a header timestamp may be
+    pub fn allowed_future_blocktime(mut self, allowed_future_blocktime: u64) -> Self {
+        self.allowed_future_blocktime = allowed_future_blocktime;
+        self
+    }
+
+    /// The block number from which on the header may carry the dao field, see
+    /// `Consensus::dao_activation_number`
+    pub fn dao_activation_number(mut self, dao_activation_number: Option<BlockNumber>) -> Self {
+        self.dao_activation_number = dao_activation_number;
+        self
+    }
 }
 
 impl<T: HeaderResolver, M: BlockMedianTimeContext> Verifier for HeaderVerifier<T, M> {
@@ -38,13 +65,21 @@ impl<T: HeaderResolver, M: BlockMedianTimeContext> Verifier for HeaderVerifier<T
     fn verify(&self, target: &T) -> Result<(), Error> {
         let header = target.header();
         VersionVerifier::new(header).verify()?;
+        DaoVerifier::new(header, self.dao_activation_number).verify()?;
         // POW check first
-        PowVerifier::new(header, &self.pow).verify()?;
+        if self.verify_pow {
+            PowVerifier::new(header, &self.pow).verify()?;
+        }
         let parent = target
             .parent()
             .ok_or_else(|| Error::UnknownParent(header.parent_hash().clone()))?;
         NumberVerifier::new(parent, header).verify()?;
-        TimestampVerifier::new(&self.block_median_time_context, header).verify()?;
+        TimestampVerifier::new(
+            &self.block_median_time_context,
+            header,
+            self.allowed_future_blocktime,
+        )
+        .verify()?;
         DifficultyVerifier::verify(target)?;
         Ok(())
     }
@@ -67,18 +102,55 @@ impl<'a> VersionVerifier<'a> {
     }
 }
 
+pub struct DaoVerifier<'a> {
+    header: &'a Header,
+    dao_activation_number: Option<BlockNumber>,
+}
+
+impl<'a> DaoVerifier<'a> {
+    pub fn new(header: &'a Header, dao_activation_number: Option<BlockNumber>) -> Self {
+        DaoVerifier {
+            header,
+            dao_activation_number,
+        }
+    }
+
+    pub fn verify(&self) -> Result<(), Error> {
+        let dao = self.header.dao();
+        let active = self
+            .dao_activation_number
+            .map_or(false, |activation| self.header.number() >= activation);
+        if !active && !dao.is_empty() {
+            return Err(Error::Dao(DaoError::Inactive));
+        }
+        if dao.len() > MAX_DAO_BYTES {
+            return Err(Error::Dao(DaoError::TooLarge {
+                max: MAX_DAO_BYTES,
+                actual: dao.len(),
+            }));
+        }
+        Ok(())
+    }
+}
+
 pub struct TimestampVerifier<'a, M> {
     header: &'a Header,
     block_median_time_context: &'a M,
     now: u64,
+    allowed_future_blocktime: u64,
 }
 
 impl<'a, M: BlockMedianTimeContext> TimestampVerifier<'a, M> {
-    pub fn new(block_median_time_context: &'a M, header: &'a Header) -> Self {
+    pub fn new(
+        block_median_time_context: &'a M,
+        header: &'a Header,
+        allowed_future_blocktime: u64,
+    ) -> Self {
         TimestampVerifier {
             block_median_time_context,
             header,
             now: unix_time_as_millis(),
+            allowed_future_blocktime,
         }
     }
 
@@ -102,7 +174,7 @@ impl<'a, M: BlockMedianTimeContext> TimestampVerifier<'a, M> {
                 found: self.header.timestamp(),
             }));
         }
-        let max = self.now + ALLOWED_FUTURE_BLOCKTIME;
+        let max = self.now + self.allowed_future_blocktime;
         if self.header.timestamp() > max {
             return Err(Error::Timestamp(TimestampError::BlockTimeTooNew {
                 max,
@@ -175,3 +247,34 @@ impl<'a> PowVerifier<'a> {
         }
     }
 }
+
+/// Verify the PoW seals of a batch of headers concurrently, seal verification
+/// dominates the cost of header sync and doesn't depend on any context.
+pub struct PowBatchVerifier<'a> {
+    headers: &'a [Header],
+    pow: Arc<dyn PowEngine>,
+}
+
+impl<'a> PowBatchVerifier<'a> {
+    pub fn new(headers: &'a [Header], pow: &Arc<dyn PowEngine>) -> Self {
+        PowBatchVerifier {
+            headers,
+            pow: Arc::clone(pow),
+        }
+    }
+
+    /// Returns the index of the first header with an invalid seal on failure
+    pub fn verify(&self) -> Result<(), (usize, Error)> {
+        let invalid = self
+            .headers
+            .par_iter()
+            .enumerate()
+            .filter(|(_, header)| !self.pow.verify_header(header))
+            .map(|(index, _)| index)
+            .min();
+        match invalid {
+            Some(index) => Err((index, Error::Pow(PowError::InvalidProof))),
+            None => Ok(()),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 mod block_verifier;
 mod error;
 mod header_verifier;
+mod pool;
 mod shared;
 mod transaction_verifier;
 
@@ -8,13 +9,18 @@ mod transaction_verifier;
 mod tests;
 
 pub use crate::block_verifier::{
-    BlockVerifier, HeaderResolverWrapper, MerkleRootVerifier, TransactionsVerifier,
+    excluded_uncles, BlockVerifier, HeaderResolverWrapper, MerkleRootVerifier,
+    TransactionsSizeVerifier, TransactionsVerifier,
 };
-pub use crate::error::{Error, TransactionError};
-pub use crate::header_verifier::{HeaderResolver, HeaderVerifier};
+pub use crate::error::{Error, PowError, TimestampError, TransactionError};
+pub use crate::header_verifier::{HeaderResolver, HeaderVerifier, PowBatchVerifier};
+pub use crate::pool::{VerificationConfig, VerificationPool, VerificationPoolMetrics};
+pub use crate::shared::ALLOWED_FUTURE_BLOCKTIME;
 pub use crate::transaction_verifier::{
-    InputVerifier, PoolTransactionVerifier, TransactionVerifier,
+    InputVerifier, Maturity, PoolTransactionVerifier, ScriptVerifier, SizeVerifier,
+    TransactionVerifier,
 };
+pub use ckb_script::ScriptRole;
 
 pub trait Verifier {
     type Target;
@@ -0,0 +1,156 @@
+use futures::Async;
+use lazy_static::lazy_static;
+use log::warn;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde_derive::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+lazy_static! {
+    // the pool of the process, created by the first `VerificationPool::shared`
+    static ref SHARED_POOL: Mutex<Option<VerificationPool>> = Mutex::new(None);
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VerificationConfig {
+    /// Number of verification threads, 0 means the number of CPUs
+    pub threads: usize,
+    /// Blocks below the tip re-verified on startup besides the tip itself
+    pub warm_up_depth: u64,
+}
+
+/// Snapshot of the verification pool queue
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerificationPoolMetrics {
+    pub threads: usize,
+    /// Jobs waiting for a thread
+    pub queued: usize,
+    /// Jobs being run
+    pub active: usize,
+    /// Jobs finished since startup
+    pub completed: usize,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    queued: AtomicUsize,
+    active: AtomicUsize,
+    completed: AtomicUsize,
+}
+
+/// Thread pool shared by block verification, transaction pool acceptance and block template
+/// validation. Parallel verifiers run inside it instead of the global rayon pool, so a big
+/// block can't occupy every core and starve the threads driving the network and RPC.
+#[derive(Clone, Debug)]
+pub struct VerificationPool {
+    pool: Arc<ThreadPool>,
+    counters: Arc<Counters>,
+}
+
+impl VerificationPool {
+    /// The pool of the process, every `Shared` of it verifies in the same threads. It is
+    /// sized by the config of the first caller, the threads of later configs are ignored.
+    pub fn shared(config: &VerificationConfig) -> Self {
+        let mut shared_pool = SHARED_POOL.lock().expect("lock shared verification pool");
+        match *shared_pool {
+            Some(ref pool) => {
+                if config.threads != 0 && config.threads != pool.pool.current_num_threads() {
+                    warn!(
+                        target: "verification",
+                        "verification pool is running {} threads already, ignore threads = {}",
+                        pool.pool.current_num_threads(),
+                        config.threads
+                    );
+                }
+                pool.clone()
+            }
+            None => {
+                let pool = VerificationPool::new(config);
+                *shared_pool = Some(pool.clone());
+                pool
+            }
+        }
+    }
+
+    /// A pool of its own, see `shared` for the pool of the process
+    pub fn new(config: &VerificationConfig) -> Self {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(config.threads)
+            .thread_name(|index| format!("Verification-{}", index))
+            .build()
+            .expect("build verification thread pool");
+        VerificationPool {
+            pool: Arc::new(pool),
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    /// Run `op` in the pool and wait for its result, parallel iterators in `op` are run by
+    /// the pool threads. Called from a tokio thread pool, such as the network and RPC
+    /// runtimes, the runtime is told the thread blocks, so it moves the other tasks of the
+    /// thread to another one while waiting.
+    pub fn install<OP, R>(&self, op: OP) -> R
+    where
+        OP: FnOnce() -> R + Send,
+        R: Send,
+    {
+        let mut op = Some(op);
+        // not in a tokio thread pool, or its blocking threads are all busy
+        if let Ok(Async::Ready(ret)) =
+            tokio_threadpool::blocking(|| self.install_blocking(op.take().expect("run once")))
+        {
+            return ret;
+        }
+        self.install_blocking(op.take().expect("not run by tokio"))
+    }
+
+    fn install_blocking<OP, R>(&self, op: OP) -> R
+    where
+        OP: FnOnce() -> R + Send,
+        R: Send,
+    {
+        self.counters.queued.fetch_add(1, Ordering::SeqCst);
+        let counters = &self.counters;
+        let ret = self.pool.install(|| {
+            counters.queued.fetch_sub(1, Ordering::SeqCst);
+            counters.active.fetch_add(1, Ordering::SeqCst);
+            let ret = op();
+            counters.active.fetch_sub(1, Ordering::SeqCst);
+            ret
+        });
+        self.counters.completed.fetch_add(1, Ordering::SeqCst);
+        ret
+    }
+
+    /// Run `op` in the pool without waiting for it
+    pub fn spawn<OP>(&self, op: OP)
+    where
+        OP: FnOnce() + Send + 'static,
+    {
+        self.counters.queued.fetch_add(1, Ordering::SeqCst);
+        let counters = Arc::clone(&self.counters);
+        self.pool.spawn(move || {
+            counters.queued.fetch_sub(1, Ordering::SeqCst);
+            counters.active.fetch_add(1, Ordering::SeqCst);
+            op();
+            counters.active.fetch_sub(1, Ordering::SeqCst);
+            counters.completed.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+
+    pub fn metrics(&self) -> VerificationPoolMetrics {
+        VerificationPoolMetrics {
+            threads: self.pool.current_num_threads(),
+            queued: self.counters.queued.load(Ordering::SeqCst),
+            active: self.counters.active.load(Ordering::SeqCst),
+            completed: self.counters.completed.load(Ordering::SeqCst),
+        }
+    }
+}
+
+impl Default for VerificationPool {
+    fn default() -> Self {
+        VerificationPool::shared(&VerificationConfig::default())
+    }
+}
@@ -1,11 +1,14 @@
-use super::super::block_verifier::{BlockVerifier, CellbaseVerifier};
-use super::super::error::{CellbaseError, Error as VerifyError};
+use super::super::block_verifier::{BlockVerifier, CellbaseVerifier, TransactionsVerifier};
+use super::super::error::{CellbaseError, Error as VerifyError, TransactionError};
 use super::dummy::DummyChainProvider;
 use crate::Verifier;
 use ckb_core::block::BlockBuilder;
+use ckb_core::cell::{CellStatus, ResolvedTransaction};
 use ckb_core::script::Script;
 use ckb_core::transaction::{CellInput, CellOutput, OutPoint, Transaction, TransactionBuilder};
-use ckb_core::{capacity_bytes, Capacity};
+use ckb_core::{capacity_bytes, Capacity, Cycle};
+use ckb_traits::BlockMedianTimeContext;
+use fnv::FnvHashMap;
 use numext_fixed_hash::H256;
 
 fn create_cellbase_transaction_with_capacity(capacity: Capacity) -> Transaction {
@@ -150,3 +153,65 @@ pub fn test_empty_transactions() {
         Err(VerifyError::Cellbase(CellbaseError::InvalidQuantity))
     );
 }
+
+struct FakeMedianTime;
+
+impl BlockMedianTimeContext for FakeMedianTime {
+    fn median_block_count(&self) -> u64 {
+        11
+    }
+    fn timestamp(&self, _n: u64) -> Option<u64> {
+        None
+    }
+    fn ancestor_timestamps(&self, _n: u64) -> Vec<u64> {
+        Vec::new()
+    }
+}
+
+#[test]
+pub fn test_skip_cached_scripts() {
+    let cellbase = create_cellbase_transaction();
+    let transaction = create_normal_transaction();
+    // no dep holds the lock script of the input, running it fails
+    let input = CellOutput::new(capacity_bytes!(100), Vec::new(), Script::default(), None);
+    let resolved = vec![
+        ResolvedTransaction {
+            transaction: &cellbase,
+            dep_cells: Vec::new(),
+            input_cells: Vec::new(),
+        },
+        ResolvedTransaction {
+            transaction: &transaction,
+            dep_cells: Vec::new(),
+            input_cells: vec![CellStatus::live_output(input, Some(0), false)],
+        },
+    ];
+    let verifier = TransactionsVerifier::new(1000);
+    let verify = |txs_verify_cache: &FnvHashMap<H256, Cycle>| {
+        verifier.verify(
+            &resolved,
+            capacity_bytes!(100),
+            FakeMedianTime,
+            10,
+            0,
+            txs_verify_cache,
+        )
+    };
+
+    match verify(&FnvHashMap::default()) {
+        Err(VerifyError::Transactions((0, TransactionError::ScriptFailure(_)))) => {}
+        ret => panic!("unexpected {:?}", ret),
+    }
+
+    // the cycles of a transaction verified before are used, its scripts don't run again
+    let mut txs_verify_cache = FnvHashMap::default();
+    txs_verify_cache.insert(transaction.hash(), 100);
+    assert_eq!(verify(&txs_verify_cache), Ok(Vec::new()));
+
+    // the cached cycles count against the block limit
+    txs_verify_cache.insert(transaction.hash(), 1001);
+    assert_eq!(
+        verify(&txs_verify_cache),
+        Err(VerifyError::ExceededMaximumCycles)
+    );
+}
@@ -0,0 +1,50 @@
+use super::super::error::{DaoError, Error as VerifyError};
+use super::super::header_verifier::DaoVerifier;
+use ckb_core::header::{HeaderBuilder, MAX_DAO_BYTES};
+
+#[test]
+pub fn test_empty_dao_before_activation() {
+    let header = HeaderBuilder::default().number(10).build();
+    assert_eq!(DaoVerifier::new(&header, None).verify(), Ok(()));
+    assert_eq!(DaoVerifier::new(&header, Some(11)).verify(), Ok(()));
+}
+
+#[test]
+pub fn test_filled_dao_before_activation() {
+    let header = HeaderBuilder::default().number(10).dao(vec![1; 8]).build();
+    assert_eq!(
+        DaoVerifier::new(&header, None).verify(),
+        Err(VerifyError::Dao(DaoError::Inactive))
+    );
+    assert_eq!(
+        DaoVerifier::new(&header, Some(11)).verify(),
+        Err(VerifyError::Dao(DaoError::Inactive))
+    );
+}
+
+#[test]
+pub fn test_dao_after_activation() {
+    let header = HeaderBuilder::default().number(10).dao(vec![1; 8]).build();
+    assert_eq!(DaoVerifier::new(&header, Some(10)).verify(), Ok(()));
+
+    let header = HeaderBuilder::default()
+        .number(10)
+        .dao(vec![1; MAX_DAO_BYTES])
+        .build();
+    assert_eq!(DaoVerifier::new(&header, Some(5)).verify(), Ok(()));
+}
+
+#[test]
+pub fn test_oversized_dao_after_activation() {
+    let header = HeaderBuilder::default()
+        .number(10)
+        .dao(vec![1; MAX_DAO_BYTES + 1])
+        .build();
+    assert_eq!(
+        DaoVerifier::new(&header, Some(5)).verify(),
+        Err(VerifyError::Dao(DaoError::TooLarge {
+            max: MAX_DAO_BYTES,
+            actual: MAX_DAO_BYTES + 1,
+        }))
+    );
+}
@@ -0,0 +1,72 @@
+use crate::pool::{VerificationConfig, VerificationPool, VerificationPoolMetrics};
+use futures::{future, Future};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::sync::mpsc;
+use tokio::runtime::Runtime;
+
+#[test]
+fn test_install_runs_in_pool() {
+    let pool = VerificationPool::new(&VerificationConfig {
+        threads: 2,
+        ..Default::default()
+    });
+    let thread_name = pool.install(|| std::thread::current().name().map(ToOwned::to_owned));
+    assert!(thread_name.unwrap().starts_with("Verification-"));
+
+    let sum: u64 = pool.install(|| (0..100u64).into_par_iter().sum());
+    assert_eq!(sum, 4950);
+}
+
+#[test]
+fn test_metrics() {
+    let pool = VerificationPool::new(&VerificationConfig {
+        threads: 2,
+        ..Default::default()
+    });
+    assert_eq!(
+        pool.metrics(),
+        VerificationPoolMetrics {
+            threads: 2,
+            queued: 0,
+            active: 0,
+            completed: 0,
+        }
+    );
+
+    let active = pool.install(|| pool.metrics().active);
+    assert_eq!(active, 1);
+    pool.install(|| ());
+    assert_eq!(pool.metrics().completed, 2);
+    assert_eq!(pool.metrics().queued, 0);
+}
+
+#[test]
+fn test_shared_pool() {
+    let pool = VerificationPool::shared(&VerificationConfig::default());
+    let other = VerificationPool::shared(&VerificationConfig {
+        threads: 1,
+        ..Default::default()
+    });
+    assert_eq!(pool.metrics().threads, other.metrics().threads);
+    // the jobs of one are counted by the other, other tests may run in it too
+    let completed = pool.metrics().completed;
+    other.install(|| ());
+    assert!(pool.metrics().completed > completed);
+}
+
+#[test]
+fn test_install_in_tokio_thread_pool() {
+    let pool = VerificationPool::new(&VerificationConfig {
+        threads: 2,
+        ..Default::default()
+    });
+    let mut runtime = Runtime::new().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    runtime.spawn(future::lazy(move || {
+        let sum: u64 = pool.install(|| (0..100u64).into_par_iter().sum());
+        sender.send(sum).unwrap();
+        Ok(())
+    }));
+    assert_eq!(receiver.recv(), Ok(4950));
+    runtime.shutdown_now().wait().unwrap();
+}
@@ -1,10 +1,11 @@
 use super::super::transaction_verifier::{
-    CapacityVerifier, DuplicateDepsVerifier, DuplicateInputsVerifier, EmptyVerifier,
-    MaturityVerifier, NullVerifier, ValidSinceVerifier,
+    CapacityVerifier, DuplicateDepsVerifier, DuplicateInputsVerifier, EmptyVerifier, InputVerifier,
+    Maturity, MaturityVerifier, NullVerifier, SizeVerifier, ValidSinceVerifier,
 };
 use crate::error::TransactionError;
+use ckb_chain_spec::consensus::Consensus;
 use ckb_core::cell::CellStatus;
-use ckb_core::cell::ResolvedTransaction;
+use ckb_core::cell::{ResolvedTransaction, UnresolvableError};
 use ckb_core::script::Script;
 use ckb_core::transaction::{CellInput, CellOutput, OutPoint, TransactionBuilder};
 use ckb_core::{capacity_bytes, Capacity};
@@ -24,6 +25,40 @@ pub fn test_null() {
     assert_eq!(verifier.verify().err(), Some(TransactionError::NullInput));
 }
 
+#[test]
+pub fn test_unresolvable_inputs() {
+    let out_point = |index| OutPoint::new(H256::zero(), index);
+    let transaction = TransactionBuilder::default()
+        .input(CellInput::new(out_point(0), 0, Default::default()))
+        .input(CellInput::new(out_point(1), 0, Default::default()))
+        .dep(out_point(2))
+        .build();
+    let rtx = ResolvedTransaction {
+        transaction: &transaction,
+        input_cells: vec![CellStatus::Unknown, CellStatus::live_null()],
+        dep_cells: vec![CellStatus::Unknown],
+    };
+    assert_eq!(
+        InputVerifier::new(&rtx).verify().err(),
+        Some(TransactionError::Unresolvable(UnresolvableError::Unknown {
+            inputs: vec![out_point(0)],
+            deps: vec![out_point(2)],
+        }))
+    );
+
+    let rtx = ResolvedTransaction {
+        transaction: &transaction,
+        input_cells: vec![CellStatus::Unknown, CellStatus::Dead],
+        dep_cells: vec![CellStatus::live_null()],
+    };
+    assert_eq!(
+        InputVerifier::new(&rtx).verify().err(),
+        Some(TransactionError::Unresolvable(
+            UnresolvableError::DeadInput(out_point(1))
+        ))
+    );
+}
+
 #[test]
 pub fn test_empty() {
     let transaction = TransactionBuilder::default().build();
@@ -32,6 +67,41 @@ pub fn test_empty() {
     assert_eq!(verifier.verify().err(), Some(TransactionError::Empty));
 }
 
+#[test]
+pub fn test_size_limits() {
+    let transaction = TransactionBuilder::default()
+        .input(CellInput::new(OutPoint::null(), 0, Default::default()))
+        .input(CellInput::new(OutPoint::null(), 0, Default::default()))
+        .output(CellOutput::new(
+            capacity_bytes!(50),
+            Vec::new(),
+            Script::default(),
+            None,
+        ))
+        .build();
+
+    let consensus = Consensus::default();
+    assert!(SizeVerifier::new(&transaction, &consensus).verify().is_ok());
+
+    let consensus = Consensus::default().set_max_tx_bytes(transaction.serialized_size() as u64 - 1);
+    assert_eq!(
+        SizeVerifier::new(&transaction, &consensus).verify().err(),
+        Some(TransactionError::ExceededMaximumBytes)
+    );
+
+    let consensus = Consensus::default().set_max_tx_inputs(Some(1));
+    assert_eq!(
+        SizeVerifier::new(&transaction, &consensus).verify().err(),
+        Some(TransactionError::TooManyInputs)
+    );
+
+    let consensus = Consensus::default().set_max_tx_outputs(Some(0));
+    assert_eq!(
+        SizeVerifier::new(&transaction, &consensus).verify().err(),
+        Some(TransactionError::TooManyOutputs)
+    );
+}
+
 #[test]
 pub fn test_capacity_outofbound() {
     let transaction = TransactionBuilder::default()
@@ -90,6 +160,8 @@ pub fn test_cellbase_maturity() {
         Some(TransactionError::CellbaseImmaturity)
     );
 
+    assert_eq!(verifier.mature_number(), 130);
+
     let tip_number = 130;
     let verifier = MaturityVerifier::new(&rtx, tip_number, cellbase_maturity);
 
@@ -241,6 +313,13 @@ pub fn test_since() {
     };
     let verifier = ValidSinceVerifier::new(&rtx, &median_time_context, 5);
     assert_eq!(verifier.verify().err(), Some(TransactionError::Immature));
+    assert_eq!(
+        verifier.maturity(),
+        Maturity {
+            block_number: 10,
+            median_time: 0,
+        }
+    );
     // spent after 10 height
     let verifier = ValidSinceVerifier::new(&rtx, &median_time_context, 10);
     assert!(verifier.verify().is_ok());
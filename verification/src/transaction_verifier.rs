@@ -1,13 +1,15 @@
 use crate::error::TransactionError;
+use crate::pool::VerificationPool;
+use ckb_chain_spec::consensus::Consensus;
+use ckb_core::script_cache::ScriptHashCache;
 use ckb_core::transaction::{Capacity, OutPoint, Transaction, TX_VERSION};
 use ckb_core::{
-    cell::{CellMeta, CellStatus, LiveCell, ResolvedTransaction},
+    cell::{CellMeta, CellStatus, LiveCell, ResolvedTransaction, UnresolvableError},
     BlockNumber, Cycle,
 };
-use ckb_script::TransactionScriptsVerifier;
+use ckb_script::{ScriptRole, TransactionScriptsVerifier};
 use ckb_traits::BlockMedianTimeContext;
 use lru_cache::LruCache;
-use occupied_capacity::OccupiedCapacity;
 use std::cell::RefCell;
 use std::collections::HashSet;
 
@@ -36,6 +38,27 @@ where
         self.valid_since.verify()?;
         Ok(())
     }
+
+    /// The earliest tip at which the cellbases spent are mature and the since conditions
+    /// may be met
+    pub fn maturity(&self) -> Maturity {
+        let cellbase = self.maturity.mature_number();
+        let since = self.valid_since.maturity();
+        Maturity {
+            block_number: cellbase.max(since.block_number),
+            median_time: since.median_time,
+        }
+    }
+}
+
+/// A transaction failing with `Immature` or `CellbaseImmaturity` may be valid once the tip
+/// number reaches `block_number` and the median time checked by since, the one of the block
+/// before the tip, reaches `median_time`. It is checked again then, a relative since on a
+/// cell which is not committed yet is checked again at the next tip.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Maturity {
+    pub block_number: BlockNumber,
+    pub median_time: u64,
 }
 
 pub struct TransactionVerifier<'a, M> {
@@ -75,7 +98,32 @@ where
         }
     }
 
+    /// Look up the hashes of the dep cells in `hash_cache` when running the scripts
+    pub fn script_hash_cache(mut self, hash_cache: &'a ScriptHashCache) -> Self {
+        self.script.hash_cache = Some(hash_cache);
+        self
+    }
+
     pub fn verify(&self, max_cycles: Cycle) -> Result<Cycle, TransactionError> {
+        self.verify_without_script()?;
+        let cycles = self.script.verify(max_cycles)?;
+        Ok(cycles)
+    }
+
+    /// Same as `verify`, but the scripts, the expensive part, are run in `pool`
+    pub fn verify_in_pool(
+        &self,
+        max_cycles: Cycle,
+        pool: &VerificationPool,
+    ) -> Result<Cycle, TransactionError> {
+        self.verify_without_script()?;
+        let script = &self.script;
+        let cycles = pool.install(|| script.verify(max_cycles))?;
+        Ok(cycles)
+    }
+
+    /// Everything but the scripts, cheap enough to run before queueing the scripts
+    pub fn verify_without_script(&self) -> Result<(), TransactionError> {
         self.version.verify()?;
         self.empty.verify()?;
         self.null.verify()?;
@@ -85,8 +133,7 @@ where
         self.duplicate_inputs.verify()?;
         self.duplicate_deps.verify()?;
         self.since.verify()?;
-        let cycles = self.script.verify(max_cycles)?;
-        Ok(cycles)
+        Ok(())
     }
 }
 
@@ -119,41 +166,66 @@ impl<'a> InputVerifier<'a> {
     }
 
     pub fn verify(&self) -> Result<(), TransactionError> {
-        for cs in &self.resolved_transaction.input_cells {
-            if cs.is_dead() {
-                return Err(TransactionError::Conflict);
-            } else if cs.is_unknown() {
-                return Err(TransactionError::Unknown);
-            }
-        }
-
-        for cs in &self.resolved_transaction.dep_cells {
-            if cs.is_dead() {
-                return Err(TransactionError::Conflict);
-            } else if cs.is_unknown() {
-                return Err(TransactionError::Unknown);
-            }
-        }
-        Ok(())
+        self.resolved_transaction
+            .check_resolvable()
+            .map_err(TransactionError::Unresolvable)
     }
 }
 
 pub struct ScriptVerifier<'a> {
     resolved_transaction: &'a ResolvedTransaction<'a>,
+    hash_cache: Option<&'a ScriptHashCache>,
 }
 
 impl<'a> ScriptVerifier<'a> {
     pub fn new(resolved_transaction: &'a ResolvedTransaction) -> Self {
         ScriptVerifier {
             resolved_transaction,
+            hash_cache: None,
+        }
+    }
+
+    /// Same as `new`, but the hashes of the dep cells are looked up in `hash_cache`
+    pub fn with_hash_cache(
+        resolved_transaction: &'a ResolvedTransaction,
+        hash_cache: &'a ScriptHashCache,
+    ) -> Self {
+        ScriptVerifier {
+            resolved_transaction,
+            hash_cache: Some(hash_cache),
         }
     }
 
     pub fn verify(&self, max_cycles: Cycle) -> Result<Cycle, TransactionError> {
-        TransactionScriptsVerifier::new(&self.resolved_transaction)
+        let verifier = match self.hash_cache {
+            Some(hash_cache) => {
+                TransactionScriptsVerifier::with_hash_cache(&self.resolved_transaction, hash_cache)
+            }
+            None => TransactionScriptsVerifier::new(&self.resolved_transaction),
+        };
+        verifier
             .verify(max_cycles)
             .map_err(TransactionError::ScriptFailure)
     }
+
+    /// Run every script and return the result of each, see
+    /// `TransactionScriptsVerifier::verify_each`
+    pub fn verify_each(
+        &self,
+        max_cycles: Cycle,
+    ) -> Vec<(ScriptRole, Result<Cycle, TransactionError>)> {
+        let verifier = match self.hash_cache {
+            Some(hash_cache) => {
+                TransactionScriptsVerifier::with_hash_cache(&self.resolved_transaction, hash_cache)
+            }
+            None => TransactionScriptsVerifier::new(&self.resolved_transaction),
+        };
+        verifier
+            .verify_each(max_cycles)
+            .into_iter()
+            .map(|(role, result)| (role, result.map_err(TransactionError::ScriptFailure)))
+            .collect()
+    }
 }
 
 pub struct EmptyVerifier<'a> {
@@ -174,6 +246,39 @@ impl<'a> EmptyVerifier<'a> {
     }
 }
 
+/// Bounds the serialized size and the inputs/outputs counts of a transaction by the
+/// consensus limits
+pub struct SizeVerifier<'a> {
+    transaction: &'a Transaction,
+    consensus: &'a Consensus,
+}
+
+impl<'a> SizeVerifier<'a> {
+    pub fn new(transaction: &'a Transaction, consensus: &'a Consensus) -> Self {
+        SizeVerifier {
+            transaction,
+            consensus,
+        }
+    }
+
+    pub fn verify(&self) -> Result<(), TransactionError> {
+        if self.transaction.serialized_size() as u64 > self.consensus.max_tx_bytes() {
+            return Err(TransactionError::ExceededMaximumBytes);
+        }
+        if let Some(max_inputs) = self.consensus.max_tx_inputs() {
+            if self.transaction.inputs().len() > max_inputs {
+                return Err(TransactionError::TooManyInputs);
+            }
+        }
+        if let Some(max_outputs) = self.consensus.max_tx_outputs() {
+            if self.transaction.outputs().len() > max_outputs {
+                return Err(TransactionError::TooManyOutputs);
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct MaturityVerifier<'a> {
     transaction: &'a ResolvedTransaction<'a>,
     tip_number: BlockNumber,
@@ -218,6 +323,20 @@ impl<'a> MaturityVerifier<'a> {
             Ok(())
         }
     }
+
+    /// The tip number from which all the cellbases spent or depended on are mature
+    pub fn mature_number(&self) -> BlockNumber {
+        self.transaction
+            .input_cells
+            .iter()
+            .chain(self.transaction.dep_cells.iter())
+            .filter_map(CellStatus::get_live_output)
+            .filter(|meta| meta.is_cellbase())
+            .filter_map(|meta| meta.block_number)
+            .map(|number| number + self.cellbase_maturity)
+            .max()
+            .unwrap_or(0)
+    }
 }
 
 pub struct DuplicateInputsVerifier<'a> {
@@ -307,34 +426,10 @@ impl<'a> CapacityVerifier<'a> {
             .filter_map(CellStatus::get_live_output)
             .try_fold(Capacity::zero(), |acc, meta| acc.safe_add(meta.capacity()))?;
 
-        let outputs_total = self
-            .resolved_transaction
+        self.resolved_transaction
             .transaction
-            .outputs()
-            .iter()
-            .try_fold(Capacity::zero(), |acc, output| {
-                acc.safe_add(output.capacity)
-            })?;
-
-        if inputs_total < outputs_total {
-            return Err(TransactionError::OutputsSumOverflow);
-        }
-        let of = self
-            .resolved_transaction
-            .transaction
-            .outputs()
-            .iter()
-            .any(|output| {
-                output
-                    .occupied_capacity()
-                    .map(|x| x > output.capacity)
-                    .unwrap_or(true)
-            });
-        if of {
-            Err(TransactionError::CapacityOverflow)
-        } else {
-            Ok(())
-        }
+            .check_capacity(inputs_total)
+            .map_err(|errors| errors[0].clone().into())
     }
 }
 
@@ -503,10 +598,62 @@ where
                     LiveCell::Null => continue, // do not verify null in ValidSinceVerifier
                     LiveCell::Output(meta) => meta,
                 },
-                _ => return Err(TransactionError::Conflict),
+                CellStatus::Dead => {
+                    return Err(TransactionError::Unresolvable(
+                        UnresolvableError::DeadInput(input.previous_output.clone()),
+                    ));
+                }
+                CellStatus::Unknown => {
+                    return Err(TransactionError::Unresolvable(UnresolvableError::Unknown {
+                        inputs: vec![input.previous_output.clone()],
+                        deps: vec![],
+                    }));
+                }
             };
             self.verify_relative_lock(since, cell)?;
         }
         Ok(())
     }
+
+    /// The earliest tip at which the since conditions may be met, see `Maturity`
+    pub fn maturity(&self) -> Maturity {
+        let mut maturity = Maturity::default();
+        for (cell_status, input) in self
+            .rtx
+            .input_cells
+            .iter()
+            .zip(self.rtx.transaction.inputs())
+        {
+            if input.since == 0 {
+                continue;
+            }
+            let since = ValidSince(input.since);
+            let (block_number, median_time) = if since.is_absolute() {
+                (since.block_number(), since.block_timestamp())
+            } else {
+                let cell_block_number = match cell_status {
+                    CellStatus::Live(LiveCell::Output(meta)) => meta.block_number,
+                    _ => None,
+                };
+                match cell_block_number {
+                    Some(number) => (
+                        since.block_number().map(|blocks| number + blocks),
+                        since.block_timestamp().map(|timestamp| {
+                            self.block_median_time(number.saturating_sub(1))
+                                .unwrap_or_else(|| 0)
+                                + timestamp
+                        }),
+                    ),
+                    None => (Some(self.tip_number + 1), None),
+                }
+            };
+            if let Some(block_number) = block_number {
+                maturity.block_number = maturity.block_number.max(block_number);
+            }
+            if let Some(median_time) = median_time {
+                maturity.median_time = maturity.median_time.max(median_time);
+            }
+        }
+        maturity
+    }
 }